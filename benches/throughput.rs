@@ -0,0 +1,93 @@
+//! Records/second through the parser, the engine (with and without capacity
+//! hints) and the whole pipeline, on a generated dataset.
+//!
+//! The dataset has 100000 records by default; set `BENCH_RECORDS` to measure
+//! larger inputs (e.g. `BENCH_RECORDS=10000000 cargo bench`). Compare runs
+//! with `cargo bench -- --save-baseline main` on the base branch and
+//! `cargo bench -- --baseline main` on the change; the same way,
+//! `--features fast-parse` against a baseline without it shows what the
+//! hand-rolled row parser buys.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use payment_engine::csv_handler::{read_records, write_accounts};
+use payment_engine::{process_reader, GeneratorConfig, InputRecord, PaymentEngine};
+use std::env;
+use std::hint::black_box;
+use std::io;
+
+const CLIENTS: u64 = 1_000;
+
+fn dataset() -> Vec<u8> {
+    let transactions = env::var("BENCH_RECORDS")
+        .ok()
+        .and_then(|records| records.parse().ok())
+        .unwrap_or(100_000);
+    let config = GeneratorConfig {
+        clients: CLIENTS,
+        transactions,
+        dispute_ratio: 0.05,
+        invalid_ratio: 0.01,
+        seed: 42,
+    };
+    let mut data = Vec::new();
+    config.generate(&mut data).expect("generating the dataset");
+    data
+}
+
+fn throughput(c: &mut Criterion) {
+    let data = dataset();
+    let records: Vec<InputRecord> = read_records(data.as_slice())
+        .filter_map(|record| record.parsed.ok())
+        .collect();
+    let lines = data.iter().filter(|&&b| b == b'\n').count() as u64 - 1;
+
+    let mut group = c.benchmark_group("throughput");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(lines));
+
+    group.bench_function("parse", |b| {
+        b.iter(|| read_records(black_box(data.as_slice())).count())
+    });
+
+    group.throughput(Throughput::Elements(records.len() as u64));
+    group.bench_function("engine", |b| {
+        b.iter_batched(
+            || records.clone(),
+            |records| {
+                let mut engine = PaymentEngine::new();
+                for record in records {
+                    let _ = engine.process(record);
+                }
+                engine
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("engine_with_capacity", |b| {
+        b.iter_batched(
+            || records.clone(),
+            |records| {
+                let mut engine = PaymentEngine::with_capacity(CLIENTS as usize, records.len());
+                for record in records {
+                    let _ = engine.process(record);
+                }
+                engine
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.throughput(Throughput::Elements(lines));
+    group.bench_function("end_to_end", |b| {
+        b.iter(|| {
+            let mut engine = PaymentEngine::new();
+            process_reader(black_box(data.as_slice()), &mut engine).unwrap();
+            write_accounts(&engine, io::sink()).unwrap();
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+//! Arbitrary bytes through the CSV reader and into the engine, as a partner
+//! file would go. Bad rows must come back as errors, never as panics, and
+//! whatever gets applied must leave the engine consistent.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use payment_engine::csv_handler::{read_records, write_accounts};
+use payment_engine::PaymentEngine;
+use std::io;
+
+fuzz_target!(|data: &[u8]| {
+    let mut engine = PaymentEngine::new();
+    for record in read_records(data) {
+        if let Ok(record) = record.parsed {
+            let _ = engine.process(record);
+        }
+    }
+    engine.check_invariants().unwrap();
+    write_accounts(&engine, io::sink()).unwrap();
+});
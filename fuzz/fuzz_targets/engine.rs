@@ -0,0 +1,115 @@
+//! Arbitrary record sequences through engines with arbitrary policies. No
+//! record may panic, and the invariants must hold after every one of them.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use payment_engine::models::Currency;
+use payment_engine::{
+    ClientId, InputRecord, InterestSchedule, LockedAccountPolicy, PaymentEngine, StaticRates,
+    TransactionType, TxId, UnderfundedDisputeMode,
+};
+use rust_decimal::Decimal;
+
+const RECORD_TYPES: [TransactionType; 13] = [
+    TransactionType::Deposit,
+    TransactionType::Withdrawal,
+    TransactionType::Dispute,
+    TransactionType::Resolve,
+    TransactionType::Chargeback,
+    TransactionType::Transfer,
+    TransactionType::Refund,
+    TransactionType::Auth,
+    TransactionType::Capture,
+    TransactionType::Void,
+    TransactionType::Close,
+    TransactionType::Admin,
+    TransactionType::Convert,
+];
+
+const CURRENCIES: [&str; 3] = ["", "USD", "EUR"];
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    underfunded_disputes: u8,
+    locked_deposits: u8,
+    max_disputes: u8,
+    interest: bool,
+    records: Vec<Record>,
+}
+
+/// Small client, tx and currency ranges, so records keep referring to each
+/// other.
+#[derive(Debug, Arbitrary)]
+struct Record {
+    record_type: u8,
+    client: u8,
+    tx: u8,
+    /// Mantissa and scale, so amounts with too many decimals show up too.
+    amount: Option<(i64, u8)>,
+    counterparty: Option<u8>,
+    currency: u8,
+    target_currency: Option<u8>,
+    timestamp: Option<u32>,
+    idempotency_key: Option<u8>,
+}
+
+fn currency(index: u8) -> Currency {
+    CURRENCIES[usize::from(index) % CURRENCIES.len()]
+        .parse()
+        .unwrap()
+}
+
+impl Record {
+    fn into_input_record(self) -> InputRecord {
+        InputRecord {
+            record_type: RECORD_TYPES[usize::from(self.record_type) % RECORD_TYPES.len()],
+            client_id: ClientId::from(self.client % 4),
+            tx_id: TxId::from(self.tx % 32),
+            amount: self
+                .amount
+                .map(|(mantissa, scale)| Decimal::new(mantissa, u32::from(scale % 8))),
+            counterparty_id: self.counterparty.map(|client| ClientId::from(client % 4)),
+            currency: currency(self.currency),
+            target_currency: self.target_currency.map(currency),
+            timestamp: self.timestamp.map(u64::from),
+            idempotency_key: self.idempotency_key.map(|key| (key % 8).to_string()),
+        }
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let underfunded = [
+        UnderfundedDisputeMode::Ignore,
+        UnderfundedDisputeMode::AllowNegative,
+        UnderfundedDisputeMode::Queue,
+    ];
+    let locked = [
+        LockedAccountPolicy::Accept,
+        LockedAccountPolicy::Hold,
+        LockedAccountPolicy::Reject,
+    ];
+    let (usd, eur) = (currency(1), currency(2));
+    let mut engine = PaymentEngine::new()
+        .with_underfunded_dispute_mode(underfunded[usize::from(input.underfunded_disputes) % 3])
+        .with_locked_account_policy(locked[usize::from(input.locked_deposits) % 3])
+        .with_max_disputes(input.max_disputes % 4 + 1)
+        .with_rate_provider(
+            StaticRates::new()
+                .with_rate(usd, eur, Decimal::new(9, 1))
+                .with_rate(eur, usd, Decimal::new(11, 1)),
+        );
+    if input.interest {
+        engine = engine.with_interest(InterestSchedule::new(Decimal::new(5, 0)));
+    }
+
+    for record in input.records {
+        let record = record.into_input_record();
+        let description = format!("{:?}", record);
+        let _ = engine.process(record);
+        if let Err(e) = engine.check_invariants() {
+            panic!("{} after {}", e, description);
+        }
+    }
+});
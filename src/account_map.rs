@@ -0,0 +1,118 @@
+use crate::errors::PaymentError;
+use crate::models::ClientId;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Maps the bank accounts named by bank files (IBANs or other account
+/// numbers) to the clients that own them.
+///
+/// An empty map reads account numbers as client ids, for files written with
+/// the engine's clients in mind. Clones share the table.
+#[derive(Debug, Clone, Default)]
+pub struct AccountMap {
+    clients: Arc<HashMap<String, ClientId>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountRow {
+    account: String,
+    client: ClientId,
+}
+
+impl AccountMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Assigns `account` to `client`.
+    pub fn with_account(mut self, account: &str, client: ClientId) -> Self {
+        Arc::make_mut(&mut self.clients).insert(normalize(account), client);
+        self
+    }
+
+    /// Reads an `account,client` CSV table. An account can only belong to one
+    /// client.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, PaymentError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let mut map = Self::new();
+        for row in rdr.deserialize() {
+            let AccountRow { account, client } = row?;
+            let owner = map.clients.get(&normalize(&account));
+            if owner.is_some_and(|&owner| owner != client) {
+                return Err(PaymentError::InvalidConfig(format!(
+                    "account '{}' is listed for more than one client",
+                    account
+                )));
+            }
+            map = map.with_account(&account, client);
+        }
+        Ok(map)
+    }
+
+    /// Reads an `account,client` CSV table from the file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PaymentError> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    /// The client owning `account`, or `None` if it's someone else's. Spaces
+    /// and case don't matter, as IBANs are often printed in groups.
+    pub fn client(&self, account: &str) -> Option<ClientId> {
+        if self.clients.is_empty() {
+            return account.trim().parse().ok();
+        }
+        self.clients.get(&normalize(account)).copied()
+    }
+}
+
+fn normalize(account: &str) -> String {
+    account
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("DE89370400440532013000", Some(1))]
+    #[case("de89 3704 0044 0532 0130 00", Some(1))]
+    #[case("12345", Some(2))]
+    #[case("GB29NWBK60161331926819", None)]
+    #[case("7", None)]
+    fn test_account_map(#[case] account: &str, #[case] expected: Option<ClientId>) {
+        let map = AccountMap::from_reader(
+            "account,client\nDE89 3704 0044 0532 0130 00,1\n12345,2\n".as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(map.client(account), expected);
+    }
+
+    #[rstest]
+    fn test_empty_account_map_reads_client_ids() {
+        let map = AccountMap::new();
+        assert_eq!(map.client(" 7 "), Some(7));
+        assert_eq!(map.client("DE89370400440532013000"), None);
+    }
+
+    #[rstest]
+    fn test_account_map_reads_numeric_accounts() {
+        let map = AccountMap::from_reader("account,client\n000111,1\n".as_bytes()).unwrap();
+        assert_eq!(map.client("000111"), Some(1));
+    }
+
+    #[rstest]
+    fn test_account_map_rejects_shared_accounts() {
+        let err = AccountMap::from_reader("account,client\nA1,1\na1,2\n".as_bytes()).unwrap_err();
+        assert!(matches!(err, PaymentError::InvalidConfig(_)));
+    }
+}
@@ -0,0 +1,311 @@
+use crate::models::{Account, ClientId, Currency};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Storage that keeps account balances beyond the engine's lifetime.
+///
+/// The engine works on its own copy of the accounts, loads them from the store
+/// when it's attached and writes every account back as soon as it changes, so
+/// a store only needs to look accounts up by client and currency.
+pub trait AccountStore: Debug + Send {
+    fn get(&self, key: (ClientId, Currency)) -> io::Result<Option<Account>>;
+
+    /// Inserts the account, or replaces the stored one with the same key.
+    fn upsert(&mut self, account: &Account) -> io::Result<()>;
+
+    /// Returns every stored account, in no particular order.
+    fn accounts(&self) -> io::Result<Vec<Account>>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every stored account.
+    fn clear(&mut self) -> io::Result<()>;
+}
+
+/// Keeps accounts in a `HashMap`, mostly useful for tests and embedding.
+#[derive(Debug, Default)]
+pub struct MemoryAccountStore {
+    accounts: HashMap<(ClientId, Currency), Account>,
+}
+
+impl MemoryAccountStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AccountStore for MemoryAccountStore {
+    fn get(&self, key: (ClientId, Currency)) -> io::Result<Option<Account>> {
+        Ok(self.accounts.get(&key).cloned())
+    }
+
+    fn upsert(&mut self, account: &Account) -> io::Result<()> {
+        self.accounts
+            .insert((account.client_id, account.currency), account.clone());
+        Ok(())
+    }
+
+    fn accounts(&self) -> io::Result<Vec<Account>> {
+        Ok(self.accounts.values().cloned().collect())
+    }
+
+    fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.accounts.clear();
+        Ok(())
+    }
+}
+
+/// Size of one account slot on disk.
+const SLOT_SIZE: u64 = 104;
+
+/// Keeps accounts in a file of fixed-size slots, one per account.
+///
+/// Slots are appended as accounts are first stored and rewritten in place
+/// afterwards; only the slot index (one entry per account) is kept in memory.
+/// Unlike [`DiskTxStore`](crate::tx_store::DiskTxStore), opening an existing
+/// file keeps its accounts.
+#[derive(Debug)]
+pub struct DiskAccountStore {
+    file: File,
+    slots: HashMap<(ClientId, Currency), u64>,
+}
+
+impl DiskAccountStore {
+    /// Opens the store at `path`, creating an empty one if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let size = file.metadata()?.len();
+        if size % SLOT_SIZE != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "account store has a partial slot",
+            ));
+        }
+
+        let mut store = Self {
+            file,
+            slots: HashMap::new(),
+        };
+        for (slot, account) in store.read_all()?.into_iter().enumerate() {
+            store
+                .slots
+                .insert((account.client_id, account.currency), slot as u64);
+        }
+        Ok(store)
+    }
+
+    fn read_all(&self) -> io::Result<Vec<Account>> {
+        let mut file = &self.file;
+        let count = file.metadata()?.len() / SLOT_SIZE;
+        file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(file);
+        let mut slot = [0u8; SLOT_SIZE as usize];
+        let mut accounts = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            reader.read_exact(&mut slot)?;
+            accounts.push(decode_slot(&slot)?);
+        }
+        Ok(accounts)
+    }
+}
+
+impl AccountStore for DiskAccountStore {
+    fn get(&self, key: (ClientId, Currency)) -> io::Result<Option<Account>> {
+        let Some(&slot) = self.slots.get(&key) else {
+            return Ok(None);
+        };
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(slot * SLOT_SIZE))?;
+        let mut bytes = [0u8; SLOT_SIZE as usize];
+        file.read_exact(&mut bytes)?;
+        decode_slot(&bytes).map(Some)
+    }
+
+    fn upsert(&mut self, account: &Account) -> io::Result<()> {
+        let next = self.slots.len() as u64;
+        let slot = *self
+            .slots
+            .entry((account.client_id, account.currency))
+            .or_insert(next);
+        self.file.seek(SeekFrom::Start(slot * SLOT_SIZE))?;
+        self.file.write_all(&encode_slot(account))
+    }
+
+    fn accounts(&self) -> io::Result<Vec<Account>> {
+        self.read_all()
+    }
+
+    fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.slots.clear();
+        Ok(())
+    }
+}
+
+// Slot layout: [present, flags (locked, closed, touched), client (8, LE), currency (8),
+// available, held, authorized, overdraft limit, accrued interest (16 each), padding].
+fn encode_slot(account: &Account) -> [u8; SLOT_SIZE as usize] {
+    let mut slot = [0u8; SLOT_SIZE as usize];
+    slot[0] = 1;
+    slot[1] =
+        u8::from(account.locked) | u8::from(account.closed) << 1 | u8::from(account.touched) << 2;
+    slot[2..10].copy_from_slice(&u64::from(account.client_id).to_le_bytes());
+    slot[10..18].copy_from_slice(&account.currency.to_bytes());
+    let amounts = [
+        account.available,
+        account.held,
+        account.authorized,
+        account.overdraft_limit,
+        account.accrued_interest,
+    ];
+    for (i, amount) in amounts.iter().enumerate() {
+        let start = 18 + i * 16;
+        slot[start..start + 16].copy_from_slice(&amount.serialize());
+    }
+    slot
+}
+
+fn decode_slot(slot: &[u8; SLOT_SIZE as usize]) -> io::Result<Account> {
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "corrupt account slot");
+    if slot[0] != 1 || slot[1] > 0b111 {
+        return Err(corrupt());
+    }
+    let amount = |i: usize| {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&slot[18 + i * 16..18 + (i + 1) * 16]);
+        Decimal::deserialize(bytes)
+    };
+    let mut currency = [0u8; 8];
+    currency.copy_from_slice(&slot[10..18]);
+    let mut client_id = [0u8; 8];
+    client_id.copy_from_slice(&slot[2..10]);
+    let client_id = ClientId::try_from(u64::from_le_bytes(client_id)).map_err(|_| corrupt())?;
+    Ok(Account {
+        client_id,
+        currency: Currency::from_bytes(currency),
+        available: amount(0),
+        held: amount(1),
+        authorized: amount(2),
+        locked: slot[1] & 1 != 0,
+        closed: slot[1] & 2 != 0,
+        overdraft_limit: amount(3),
+        accrued_interest: amount(4),
+        touched: slot[1] & 4 != 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    fn account(client_id: ClientId, available: Decimal) -> Account {
+        Account {
+            available,
+            ..Account::new(client_id)
+        }
+    }
+
+    fn exercise_store(store: &mut dyn AccountStore) {
+        let eur: Currency = "EUR".parse().unwrap();
+        assert!(store.is_empty());
+        assert_eq!(store.get((1, Currency::default())).unwrap(), None);
+
+        store.upsert(&account(1, dec!(1.2345))).unwrap();
+        store.upsert(&account(2, dec!(10))).unwrap();
+        let updated = Account {
+            currency: eur,
+            held: dec!(-3.5),
+            authorized: dec!(0.0001),
+            locked: true,
+            closed: true,
+            overdraft_limit: dec!(100),
+            accrued_interest: dec!(0.000001),
+            touched: true,
+            ..account(1, dec!(7))
+        };
+        store.upsert(&updated).unwrap();
+        store.upsert(&updated).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get((1, eur)).unwrap(), Some(updated.clone()));
+        assert_eq!(
+            store.get((1, Currency::default())).unwrap(),
+            Some(account(1, dec!(1.2345)))
+        );
+
+        let mut accounts = store.accounts().unwrap();
+        accounts.sort_by_key(|a| (a.client_id, a.currency));
+        assert_eq!(
+            accounts,
+            [account(1, dec!(1.2345)), updated, account(2, dec!(10))]
+        );
+
+        store.clear().unwrap();
+        assert!(store.is_empty());
+        assert_eq!(store.get((2, Currency::default())).unwrap(), None);
+    }
+
+    #[rstest]
+    fn test_memory_account_store() {
+        exercise_store(&mut MemoryAccountStore::new());
+    }
+
+    #[rstest]
+    fn test_disk_account_store() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise_store(&mut DiskAccountStore::open(dir.path().join("accounts.db")).unwrap());
+    }
+
+    #[rstest]
+    fn test_disk_account_store_survives_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.db");
+        {
+            let mut store = DiskAccountStore::open(&path).unwrap();
+            store.upsert(&account(1, dec!(5))).unwrap();
+            store.upsert(&account(2, dec!(6))).unwrap();
+            store.upsert(&account(1, dec!(7))).unwrap();
+        }
+
+        let mut store = DiskAccountStore::open(&path).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(
+            store.get((1, Currency::default())).unwrap(),
+            Some(account(1, dec!(7)))
+        );
+        store.upsert(&account(3, dec!(8))).unwrap();
+        assert_eq!(store.accounts().unwrap().len(), 3);
+    }
+
+    #[rstest]
+    fn test_disk_account_store_rejects_partial_slot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.db");
+        std::fs::write(&path, [1u8; 10]).unwrap();
+
+        let err = DiskAccountStore::open(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,68 @@
+//! Dispute aging: disputes left open longer than card schemes allow are
+//! settled at the end of a run, and what was done is reported apart from the
+//! accounts.
+
+use crate::models::{ClientId, Currency, TxId};
+use crate::output::ReportRow;
+use crate::policy::ExpiredDisputeAction;
+use rust_decimal::Decimal;
+use serde_derive::Serialize;
+
+/// A dispute settled by
+/// [`PaymentEngine::expire_disputes`](crate::engine::PaymentEngine::expire_disputes).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ExpiredDispute {
+    #[serde(rename = "tx")]
+    pub tx_id: TxId,
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub currency: Currency,
+    /// The disputed amount.
+    pub amount: Decimal,
+    /// When the dispute was filed.
+    pub opened_at: u64,
+    /// How it was settled.
+    pub action: ExpiredDisputeAction,
+}
+
+impl ReportRow for ExpiredDispute {
+    const COLUMNS: &'static [&'static str] =
+        &["tx", "client", "currency", "amount", "opened_at", "action"];
+
+    fn amounts_mut(&mut self) -> Vec<&mut Decimal> {
+        vec![&mut self.amount]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{write_report_csv, write_report_json};
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    #[rstest]
+    fn test_write_expired_disputes() {
+        let expired = [ExpiredDispute {
+            tx_id: 7,
+            client_id: 2,
+            currency: "EUR".parse().unwrap(),
+            amount: dec!(12.5),
+            opened_at: 86_400,
+            action: ExpiredDisputeAction::Chargeback,
+        }];
+        let mut csv = Vec::new();
+        write_report_csv(&expired, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "tx,client,currency,amount,opened_at,action\n7,2,EUR,12.5000,86400,chargeback\n"
+        );
+        let mut json = Vec::new();
+        write_report_json(&expired, &mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "[{\"tx\":7,\"client\":2,\"currency\":\"EUR\",\"amount\":\"12.5000\",\
+             \"opened_at\":86400,\"action\":\"chargeback\"}]\n"
+        );
+    }
+}
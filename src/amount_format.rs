@@ -0,0 +1,131 @@
+//! Localized amount spellings, as ERP and spreadsheet exports write them.
+
+use std::str::FromStr;
+
+/// How the `amount` column of the input writes numbers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AmountFormat {
+    /// Plain decimals like `1234.56`, read as they are.
+    #[default]
+    Plain,
+    /// Comma-grouped thousands and a decimal point, like `1,234.56`.
+    Us,
+    /// Point- or space-grouped thousands and a decimal comma, like
+    /// `1.234,56`, as most European ERPs export them.
+    European,
+}
+
+impl FromStr for AmountFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "plain" => Ok(AmountFormat::Plain),
+            "us" | "en" => Ok(AmountFormat::Us),
+            "eu" | "european" => Ok(AmountFormat::European),
+            other => Err(format!("unknown amount format '{}'", other)),
+        }
+    }
+}
+
+impl AmountFormat {
+    /// Rewrites `amount` as a plain decimal, or `None` if it isn't written
+    /// in this format. Grouped formats also take accounting negatives in
+    /// parentheses, like `(12.00)`. Empty amounts stay empty.
+    pub fn normalize(self, amount: &str) -> Option<String> {
+        let amount = amount.trim();
+        let (decimal, groups): (char, &[char]) = match self {
+            AmountFormat::Plain => return Some(amount.to_string()),
+            AmountFormat::Us => ('.', &[',']),
+            AmountFormat::European => (',', &['.', ' ', '\u{a0}', '\u{202f}']),
+        };
+        if amount.is_empty() {
+            return Some(String::new());
+        }
+        let (negative, amount) = match amount
+            .strip_prefix('(')
+            .and_then(|inner| inner.strip_suffix(')'))
+        {
+            Some(inner) => (true, inner.trim()),
+            None => match amount.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, amount.strip_prefix('+').unwrap_or(amount)),
+            },
+        };
+        let (whole, fraction) = match amount.split_once(decimal) {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (amount, None),
+        };
+        let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+        let mut parts = whole.split(groups);
+        let first = parts.next().filter(|first| digits(first))?;
+        let mut normalized = String::with_capacity(amount.len() + 1);
+        if negative {
+            normalized.push('-');
+        }
+        normalized.push_str(first);
+        let mut grouped = false;
+        for group in parts {
+            // Thousands come in threes after a leading group of at most three.
+            if group.len() != 3 || !digits(group) || first.len() > 3 {
+                return None;
+            }
+            grouped = true;
+            normalized.push_str(group);
+        }
+        if grouped && first.starts_with('0') {
+            return None;
+        }
+        if let Some(fraction) = fraction {
+            if !digits(fraction) {
+                return None;
+            }
+            normalized.push('.');
+            normalized.push_str(fraction);
+        }
+        Some(normalized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(AmountFormat::Plain, "1234.56", Some("1234.56"))]
+    #[case(AmountFormat::Us, "1,234.56", Some("1234.56"))]
+    #[case(AmountFormat::Us, "1234.5", Some("1234.5"))]
+    #[case(AmountFormat::Us, "(12.00)", Some("-12.00"))]
+    #[case(AmountFormat::Us, "-1,000", Some("-1000"))]
+    #[case(AmountFormat::Us, "", Some(""))]
+    #[case(AmountFormat::Us, "1,23.4", None)]
+    #[case(AmountFormat::Us, "1234,567", None)]
+    #[case(AmountFormat::Us, "1.234,56", None)]
+    #[case(AmountFormat::Us, "12.", None)]
+    #[case(AmountFormat::European, "1.234,56", Some("1234.56"))]
+    #[case(AmountFormat::European, "1 234 567,8", Some("1234567.8"))]
+    #[case(AmountFormat::European, "( 1.234,56 )", Some("-1234.56"))]
+    #[case(AmountFormat::European, "0,5", Some("0.5"))]
+    #[case(AmountFormat::European, "1,234.56", None)]
+    #[case(AmountFormat::European, "ten", None)]
+    fn test_normalize(
+        #[case] format: AmountFormat,
+        #[case] amount: &str,
+        #[case] expected: Option<&str>,
+    ) {
+        assert_eq!(format.normalize(amount).as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case("plain", Ok(AmountFormat::Plain))]
+    #[case("US", Ok(AmountFormat::Us))]
+    #[case("eu", Ok(AmountFormat::European))]
+    #[case("swiss", Err("unknown amount format 'swiss'".to_string()))]
+    fn test_amount_format_parse(
+        #[case] input: &str,
+        #[case] expected: Result<AmountFormat, String>,
+    ) {
+        assert_eq!(input.parse::<AmountFormat>(), expected);
+    }
+}
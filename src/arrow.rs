@@ -0,0 +1,236 @@
+//! Accounts and balance mutations as Arrow record batches, so analytics
+//! libraries (Polars, DataFusion, ...) can read results in process without a
+//! serialization round trip, and as Arrow IPC files for the ones that can't.
+
+use crate::audit::AuditEntry;
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::models::OutputRecord;
+use crate::output::{output_mantissa, OUTPUT_SCALE as SCALE};
+use arrow_array::{ArrayRef, BooleanArray, Decimal128Array, RecordBatch, StringArray, UInt64Array};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema};
+use rust_decimal::Decimal;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Digits of the amount columns: any `Decimal` fits once scaled to `SCALE`.
+const PRECISION: u8 = 38;
+
+/// The columns of an account batch, named like the CSV header. Client ids are
+/// `UInt64` with and without `wide-ids`, so every build has one schema.
+pub fn account_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("client", DataType::UInt64, false),
+        Field::new("currency", DataType::Utf8, false),
+        amount_field("available"),
+        amount_field("held"),
+        amount_field("total"),
+        Field::new("locked", DataType::Boolean, false),
+        Field::new("closed", DataType::Boolean, false),
+        amount_field("overdraft"),
+    ])
+}
+
+/// The columns of a batch of balance mutations, named like the audit log
+/// header.
+pub fn audit_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("tx", DataType::UInt64, false),
+        Field::new("client", DataType::UInt64, false),
+        Field::new("currency", DataType::Utf8, false),
+        Field::new("action", DataType::Utf8, false),
+        amount_field("amount"),
+        amount_field("available"),
+        amount_field("held"),
+        Field::new("locked", DataType::Boolean, false),
+    ])
+}
+
+/// One row per account, sorted by client and currency like the other
+/// formats.
+pub fn accounts_to_record_batch(engine: &PaymentEngine) -> Result<RecordBatch, PaymentError> {
+    let accounts: Vec<OutputRecord> = engine
+        .iter_accounts_sorted()
+        .map(|account| account.to_output_record())
+        .collect();
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            accounts
+                .iter()
+                .map(|account| u64::from(account.client_id))
+                .collect::<UInt64Array>(),
+        ),
+        strings(accounts.iter().map(|account| account.currency.as_str())),
+        amounts(accounts.iter().map(|account| account.available))?,
+        amounts(accounts.iter().map(|account| account.held))?,
+        amounts(accounts.iter().map(|account| account.total))?,
+        booleans(accounts.iter().map(|account| account.locked)),
+        booleans(accounts.iter().map(|account| account.closed)),
+        amounts(accounts.iter().map(|account| account.overdraft))?,
+    ];
+    Ok(RecordBatch::try_new(Arc::new(account_schema()), columns)?)
+}
+
+/// One row per entry, in the given order; `entries` is typically a client
+/// statement.
+pub fn audit_entries_to_record_batch<'a>(
+    entries: impl IntoIterator<Item = &'a AuditEntry>,
+) -> Result<RecordBatch, PaymentError> {
+    let entries: Vec<&AuditEntry> = entries.into_iter().collect();
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            entries
+                .iter()
+                .map(|entry| u64::from(entry.tx))
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            entries
+                .iter()
+                .map(|entry| u64::from(entry.client))
+                .collect::<UInt64Array>(),
+        ),
+        strings(entries.iter().map(|entry| entry.currency.as_str())),
+        strings(entries.iter().map(|entry| entry.action.as_str())),
+        amounts(entries.iter().map(|entry| entry.amount))?,
+        amounts(entries.iter().map(|entry| entry.available))?,
+        amounts(entries.iter().map(|entry| entry.held))?,
+        booleans(entries.iter().map(|entry| entry.locked)),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(audit_schema()), columns)?)
+}
+
+/// Writes `batch` to `writer` as an Arrow IPC file.
+pub fn write_ipc<W: Write>(batch: &RecordBatch, writer: W) -> Result<(), PaymentError> {
+    let mut file = FileWriter::try_new(writer, &batch.schema())?;
+    file.write(batch)?;
+    file.into_inner()?.flush()?;
+    Ok(())
+}
+
+fn amount_field(name: &str) -> Field {
+    Field::new(name, DataType::Decimal128(PRECISION, SCALE as i8), false)
+}
+
+fn amounts(values: impl Iterator<Item = Decimal>) -> Result<ArrayRef, PaymentError> {
+    let array = values
+        .map(output_mantissa)
+        .collect::<Decimal128Array>()
+        .with_precision_and_scale(PRECISION, SCALE as i8)?;
+    Ok(Arc::new(array))
+}
+
+fn strings<'a>(values: impl Iterator<Item = &'a str>) -> ArrayRef {
+    Arc::new(values.map(Some).collect::<StringArray>())
+}
+
+fn booleans(values: impl Iterator<Item = bool>) -> ArrayRef {
+    Arc::new(values.map(Some).collect::<BooleanArray>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ClientId, InputRecord, TransactionType, TxId};
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Decimal128Type, UInt64Type};
+    use arrow_ipc::reader::FileReader;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    fn record(record_type: TransactionType, client_id: ClientId, tx_id: TxId) -> InputRecord {
+        InputRecord {
+            record_type,
+            client_id,
+            tx_id,
+            amount: Some(dec!(1.5)),
+            counterparty_id: None,
+            currency: Default::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[rstest]
+    fn test_accounts_to_record_batch() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(record(TransactionType::Deposit, 2, 1))
+            .unwrap();
+        engine
+            .process(record(TransactionType::Deposit, 1, 2))
+            .unwrap();
+
+        let batch = accounts_to_record_batch(&engine).unwrap();
+        assert_eq!(batch.schema().as_ref(), &account_schema());
+        assert_eq!(batch.num_rows(), 2);
+        let clients = batch.column(0).as_primitive::<UInt64Type>();
+        assert_eq!(clients.values(), &[1, 2]);
+        let available = batch.column(2).as_primitive::<Decimal128Type>();
+        assert_eq!(available.value_as_string(0), "1.5000");
+        assert!(!batch.column(5).as_boolean().value(0));
+    }
+
+    #[rstest]
+    fn test_audit_entries_to_record_batch() {
+        let mut engine = PaymentEngine::new().with_statement_history();
+        engine
+            .process(record(TransactionType::Deposit, 1, 1))
+            .unwrap();
+        engine
+            .process(record(TransactionType::Withdrawal, 1, 2))
+            .unwrap();
+
+        let batch = audit_entries_to_record_batch(engine.statement(1).unwrap()).unwrap();
+        assert_eq!(batch.schema().as_ref(), &audit_schema());
+        let actions = batch.column(3).as_string::<i32>();
+        assert_eq!(actions.value(0), "deposit");
+        assert_eq!(actions.value(1), "withdrawal");
+        let available = batch.column(5).as_primitive::<Decimal128Type>();
+        assert_eq!(available.value_as_string(1), "0.0000");
+    }
+
+    #[rstest]
+    fn test_engine_record_batches() {
+        let mut engine = PaymentEngine::new();
+        assert!(engine.history_to_record_batch().is_none());
+        engine = engine.with_statement_history();
+        for (client_id, tx_id) in [(2, 1), (1, 2), (2, 3)] {
+            engine
+                .process(record(TransactionType::Deposit, client_id, tx_id))
+                .unwrap();
+        }
+
+        assert_eq!(
+            engine.to_record_batch().unwrap(),
+            accounts_to_record_batch(&engine).unwrap()
+        );
+        let history = engine.history_to_record_batch().unwrap().unwrap();
+        // By client, then in the order applied.
+        let txs = history.column(0).as_primitive::<UInt64Type>();
+        assert_eq!(txs.values(), &[2, 1, 3]);
+    }
+
+    #[rstest]
+    fn test_write_ipc_round_trips() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(record(TransactionType::Deposit, 1, 1))
+            .unwrap();
+        let batch = accounts_to_record_batch(&engine).unwrap();
+        let mut file = Vec::new();
+        write_ipc(&batch, &mut file).unwrap();
+
+        let batches: Vec<RecordBatch> = FileReader::try_new(Cursor::new(file), None)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(batches, [batch]);
+    }
+}
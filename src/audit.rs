@@ -0,0 +1,222 @@
+use crate::errors::PaymentError;
+use crate::models::{Account, ClientId, Currency, TransactionDirection, TransactionType, TxId};
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::Write;
+
+/// A kind of balance change the engine records, named in the audit log by
+/// its `action`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mutation {
+    Deposit,
+    Withdrawal,
+    TransferOut,
+    TransferIn,
+    Refund,
+    Auth,
+    Capture,
+    Void,
+    /// Holds the funds of a transaction moving them in the direction given.
+    Dispute(TransactionDirection),
+    Resolve(TransactionDirection),
+    Chargeback(TransactionDirection),
+    /// Closes the account, moving nothing.
+    Close,
+    /// Sets the overdraft limit, given as the amount.
+    Admin,
+    /// Locks the account of a blocked client, moving nothing.
+    Block,
+    ConvertOut,
+    ConvertIn,
+    CreditAdjustment,
+    DebitAdjustment,
+    Interest,
+    /// A fee paid by a client.
+    Fee,
+    /// A fee received by the house account.
+    FeeIncome,
+    /// Chargebacked funds received by the suspense account.
+    WriteOff,
+}
+
+impl Mutation {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Mutation::Deposit => "deposit",
+            Mutation::Withdrawal => "withdrawal",
+            Mutation::TransferOut => "transfer_out",
+            Mutation::TransferIn => "transfer_in",
+            Mutation::Refund => "refund",
+            Mutation::Auth => "auth",
+            Mutation::Capture => "capture",
+            Mutation::Void => "void",
+            Mutation::Dispute(_) => "dispute",
+            Mutation::Resolve(_) => "resolve",
+            Mutation::Chargeback(_) => "chargeback",
+            Mutation::Close => "close",
+            Mutation::Admin => "admin",
+            Mutation::Block => "block",
+            Mutation::ConvertOut => "convert_out",
+            Mutation::ConvertIn => "convert_in",
+            Mutation::CreditAdjustment => "credit_adjustment",
+            Mutation::DebitAdjustment => "debit_adjustment",
+            Mutation::Interest => "interest",
+            Mutation::Fee => "fee",
+            Mutation::FeeIncome => "fee_income",
+            Mutation::WriteOff => "write_off",
+        }
+    }
+
+    /// How much the mutation of `amount` changes the account's total,
+    /// negative if it takes funds out.
+    pub(crate) fn total_change(self, amount: Decimal) -> Decimal {
+        match self {
+            Mutation::Deposit
+            | Mutation::TransferIn
+            | Mutation::ConvertIn
+            | Mutation::CreditAdjustment
+            | Mutation::Interest
+            | Mutation::FeeIncome
+            | Mutation::WriteOff
+            // A disputed withdrawal is held on top of the balance.
+            | Mutation::Dispute(TransactionDirection::Debit) => amount,
+            Mutation::Withdrawal
+            | Mutation::TransferOut
+            | Mutation::Refund
+            | Mutation::Capture
+            | Mutation::ConvertOut
+            | Mutation::DebitAdjustment
+            | Mutation::Fee
+            | Mutation::Resolve(TransactionDirection::Debit)
+            | Mutation::Chargeback(TransactionDirection::Credit) => -amount,
+            Mutation::Auth
+            | Mutation::Void
+            | Mutation::Close
+            | Mutation::Admin
+            | Mutation::Block
+            | Mutation::Dispute(TransactionDirection::Credit)
+            | Mutation::Resolve(TransactionDirection::Credit)
+            | Mutation::Chargeback(TransactionDirection::Debit) => Decimal::ZERO,
+        }
+    }
+
+    /// The record type a fee schedule charges this mutation as. Receiving
+    /// legs, closures, admin records, adjustments and fees are free.
+    pub(crate) fn fee_type(self) -> Option<TransactionType> {
+        Some(match self {
+            Mutation::Deposit => TransactionType::Deposit,
+            Mutation::Withdrawal => TransactionType::Withdrawal,
+            Mutation::TransferOut => TransactionType::Transfer,
+            Mutation::Refund => TransactionType::Refund,
+            Mutation::Auth => TransactionType::Auth,
+            Mutation::Capture => TransactionType::Capture,
+            Mutation::Void => TransactionType::Void,
+            Mutation::Dispute(_) => TransactionType::Dispute,
+            Mutation::Resolve(_) => TransactionType::Resolve,
+            Mutation::Chargeback(_) => TransactionType::Chargeback,
+            Mutation::ConvertOut => TransactionType::Convert,
+            Mutation::TransferIn
+            | Mutation::Close
+            | Mutation::Admin
+            | Mutation::Block
+            | Mutation::ConvertIn
+            | Mutation::CreditAdjustment
+            | Mutation::DebitAdjustment
+            | Mutation::Interest
+            | Mutation::Fee
+            | Mutation::FeeIncome
+            | Mutation::WriteOff => return None,
+        })
+    }
+}
+
+/// One balance mutation, as written to the audit log and kept in client
+/// statements.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct AuditEntry {
+    pub tx: TxId,
+    pub client: ClientId,
+    #[serde(default)]
+    pub currency: Currency,
+    /// What changed the balance: `deposit`, `withdrawal`, `transfer_out`,
+    /// `transfer_in`, `refund`, `auth`, `capture`, `void`, `dispute`,
+    /// `resolve`, `chargeback`, `close` and `block` (which move nothing),
+    /// `admin` (whose amount is the new overdraft limit), `convert_out` and
+    /// `convert_in` for both sides of a currency conversion,
+    /// `credit_adjustment` and `debit_adjustment`, `interest` for posted
+    /// interest, `fee` and `fee_income` for fees paid by a client and
+    /// received by the house account, or `write_off` for chargebacked funds
+    /// received by the suspense account.
+    pub action: String,
+    pub amount: Decimal,
+    /// Balances after the mutation.
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+    /// Extra input columns of the record that made the mutation, as a JSON
+    /// object with sorted keys; empty when it had none.
+    #[serde(default)]
+    pub metadata: String,
+}
+
+impl AuditEntry {
+    /// Describes `account` right after `mutation` moved `amount` for tx `tx`.
+    pub(crate) fn new(tx: TxId, mutation: Mutation, amount: Decimal, account: &Account) -> Self {
+        let mut entry = AuditEntry {
+            tx,
+            client: account.client_id,
+            currency: account.currency,
+            action: mutation.as_str().to_string(),
+            amount,
+            available: account.available,
+            held: account.held,
+            locked: account.locked,
+            metadata: String::new(),
+        };
+        for value in [&mut entry.amount, &mut entry.available, &mut entry.held] {
+            value.rescale(4);
+        }
+        entry
+    }
+
+    /// Adds the record's extra input columns to the entry.
+    pub(crate) fn with_metadata(mut self, metadata: &HashMap<String, String>) -> Self {
+        if !metadata.is_empty() {
+            let sorted: BTreeMap<&String, &String> = metadata.iter().collect();
+            self.metadata = serde_json::to_string(&sorted).unwrap_or_default();
+        }
+        self
+    }
+}
+
+/// Append-only CSV trail of every applied balance mutation, in the order the
+/// engine applied them.
+pub(crate) struct AuditLog {
+    writer: csv::Writer<Box<dyn Write + Send>>,
+}
+
+impl AuditLog {
+    pub(crate) fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(Box::new(writer)),
+        }
+    }
+
+    pub(crate) fn record(&mut self, entry: &AuditEntry) -> Result<(), PaymentError> {
+        self.writer.serialize(entry)?;
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), PaymentError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditLog").finish_non_exhaustive()
+    }
+}
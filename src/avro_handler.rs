@@ -0,0 +1,720 @@
+//! Avro object container files, for pipelines where Avro is the common
+//! currency: transactions in, accounts and statements out.
+//!
+//! Input files are decoded with the schema they carry, so producers only have
+//! to keep the field names of [`transaction_schema`]; the fields of each
+//! record are then read by name into an [`InputRecord`].
+
+use crate::audit::AuditEntry;
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::input::{process_records, RawRecord};
+use crate::models::{InputRecord, OutputRecord, TransactionType};
+use crate::output::{output_mantissa, OUTPUT_SCALE};
+use crate::report::ProcessingReport;
+use crate::tx_ids::TxIdMap;
+use avro_schema::file::{Block, CompressedBlock};
+use avro_schema::read::fallible_streaming_iterator::FallibleStreamingIterator;
+use avro_schema::read::{read_metadata, BlockStreamingIterator};
+use avro_schema::schema::{BytesLogical, Field, FixedLogical, LongLogical, Record, Schema};
+use avro_schema::write::encode::zigzag_encode;
+use avro_schema::write::{write_block, write_metadata};
+use rust_decimal::Decimal;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::io::{Read, Write};
+use std::iter;
+use std::str::FromStr;
+
+/// Rows written per block of an output file.
+const BLOCK_ROWS: usize = 4096;
+/// Digits of written amounts: any `Decimal` fits once scaled to the output
+/// precision.
+const PRECISION: usize = 38;
+
+/// The schema of transaction files. Fields are matched by name, so files may
+/// order them differently, leave out the optional ones, or use other types
+/// that decode to the same values: an enum for `type`, an `int` for the ids,
+/// or a `decimal` or `double` for `amount`.
+pub fn transaction_schema() -> Record {
+    let optional = |schema| Schema::Union(vec![Schema::Null, schema]);
+    let mut record = Record::new(
+        "Transaction",
+        vec![
+            Field::new("type", Schema::String(None)),
+            Field::new("client", Schema::Long(None)),
+            Field::new("tx", Schema::Long(None)),
+            Field::new("amount", optional(Schema::String(None))),
+            Field::new("counterparty", optional(Schema::Long(None))),
+            Field::new("currency", optional(Schema::String(None))),
+            Field::new("to_currency", optional(Schema::String(None))),
+            Field::new(
+                "timestamp",
+                optional(Schema::Long(Some(LongLogical::TimestampMillis))),
+            ),
+            Field::new("idempotency_key", optional(Schema::String(None))),
+            Field::new("reference", optional(Schema::String(None))),
+        ],
+    );
+    record.namespace = Some("payment_engine".to_string());
+    record
+}
+
+/// The schema of account files, with the columns of the CSV output.
+pub fn account_schema() -> Record {
+    let mut record = Record::new(
+        "Account",
+        vec![
+            Field::new("client", Schema::Long(None)),
+            Field::new("currency", Schema::String(None)),
+            Field::new("available", amount_schema()),
+            Field::new("held", amount_schema()),
+            Field::new("total", amount_schema()),
+            Field::new("locked", Schema::Boolean),
+            Field::new("closed", Schema::Boolean),
+            Field::new("overdraft", amount_schema()),
+        ],
+    );
+    record.namespace = Some("payment_engine".to_string());
+    record
+}
+
+/// The schema of statement files, with the columns of the audit log.
+pub fn audit_schema() -> Record {
+    let mut record = Record::new(
+        "AuditEntry",
+        vec![
+            Field::new("tx", Schema::Long(None)),
+            Field::new("client", Schema::Long(None)),
+            Field::new("currency", Schema::String(None)),
+            Field::new("action", Schema::String(None)),
+            Field::new("amount", amount_schema()),
+            Field::new("available", amount_schema()),
+            Field::new("held", amount_schema()),
+            Field::new("locked", Schema::Boolean),
+        ],
+    );
+    record.namespace = Some("payment_engine".to_string());
+    record
+}
+
+fn amount_schema() -> Schema {
+    Schema::Bytes(Some(BytesLogical::Decimal(
+        PRECISION,
+        OUTPUT_SCALE as usize,
+    )))
+}
+
+/// Processes transactions from an Avro file.
+pub fn process_avro<R: Read>(
+    reader: R,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError> {
+    process_records(read_avro(reader), engine)
+}
+
+/// Lazily decodes the records of an Avro file. Their `line` is their 1-based
+/// position in the file; a header that can't be read is reported at line 0.
+/// Deflate and Snappy blocks are decompressed.
+pub fn read_avro<'a, R: Read + 'a>(reader: R) -> Box<dyn Iterator<Item = RawRecord> + 'a> {
+    decode_file(reader, None)
+}
+
+/// Like [`read_avro`], reading the `tx` field through `tx_ids`.
+pub fn read_avro_with_tx_ids<'a, R: Read + 'a>(
+    reader: R,
+    tx_ids: TxIdMap,
+) -> Box<dyn Iterator<Item = RawRecord> + 'a> {
+    decode_file(reader, Some(tx_ids))
+}
+
+fn decode_file<'a, R: Read + 'a>(
+    mut reader: R,
+    tx_ids: Option<TxIdMap>,
+) -> Box<dyn Iterator<Item = RawRecord> + 'a> {
+    let metadata = match read_metadata(&mut reader) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            return Box::new(iter::once(RawRecord {
+                line: 0,
+                raw: String::new(),
+                parsed: Err(PaymentError::Avro(format!("invalid header: {}", e))),
+            }))
+        }
+    };
+    Box::new(AvroRecords {
+        blocks: BlockStreamingIterator::new(reader, metadata.compression, metadata.marker),
+        fields: metadata.record.fields,
+        block: Block::default(),
+        offset: 0,
+        line: 0,
+        tx_ids,
+        done: false,
+    })
+}
+
+/// The records of an Avro file, a block at a time.
+struct AvroRecords<R: Read> {
+    blocks: BlockStreamingIterator<R>,
+    fields: Vec<Field>,
+    /// The block being decoded; `number_of_rows` counts the rows left.
+    block: Block,
+    offset: usize,
+    line: u64,
+    tx_ids: Option<TxIdMap>,
+    done: bool,
+}
+
+impl<R: Read> Iterator for AvroRecords<R> {
+    type Item = RawRecord;
+
+    fn next(&mut self) -> Option<RawRecord> {
+        while self.block.number_of_rows == 0 {
+            if self.done {
+                return None;
+            }
+            match self.blocks.next() {
+                Ok(Some(block)) => {
+                    self.block.clone_from(block);
+                    self.offset = 0;
+                }
+                Ok(None) => return None,
+                Err(e) => {
+                    // Without the block's length there's no telling where the
+                    // next one starts.
+                    self.done = true;
+                    return Some(RawRecord {
+                        line: self.line + 1,
+                        raw: String::new(),
+                        parsed: Err(PaymentError::Avro(format!("invalid block: {}", e))),
+                    });
+                }
+            }
+        }
+
+        self.line += 1;
+        self.block.number_of_rows -= 1;
+        let mut data = &self.block.data[self.offset..];
+        let decoded = decode_fields(&self.fields, &mut data);
+        self.offset = self.block.data.len() - data.len();
+        let (raw, parsed) = match decoded {
+            Ok(fields) => {
+                let raw = serde_json::to_string(&Datum::Record(fields.clone())).unwrap_or_default();
+                (raw, input_record(fields, self.tx_ids.as_ref()))
+            }
+            Err(e) => {
+                // The rest of the block can't be located; the next block
+                // starts after its sync marker.
+                self.block.number_of_rows = 0;
+                (String::new(), Err(e))
+            }
+        };
+        Some(RawRecord {
+            line: self.line,
+            raw,
+            parsed,
+        })
+    }
+}
+
+/// A decoded Avro value.
+#[derive(Debug, Clone, PartialEq)]
+enum Datum {
+    Null,
+    Boolean(bool),
+    Long(i64),
+    Double(f64),
+    Decimal(Decimal),
+    Text(String),
+    /// Fields by name, in the schema's order.
+    Record(Vec<(String, Datum)>),
+}
+
+/// The JSON form of a record, listed for the records that are skipped.
+/// Decimals are strings, so they're written exactly.
+impl Serialize for Datum {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Datum::Null => serializer.serialize_unit(),
+            Datum::Boolean(value) => serializer.serialize_bool(*value),
+            Datum::Long(value) => serializer.serialize_i64(*value),
+            Datum::Double(value) => serializer.serialize_f64(*value),
+            Datum::Decimal(value) => serializer.collect_str(value),
+            Datum::Text(value) => serializer.serialize_str(value),
+            Datum::Record(fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (name, value) in fields {
+                    map.serialize_entry(name, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+/// Decodes a record's fields, leaving out nulls so optional fields take
+/// their defaults.
+fn decode_fields(fields: &[Field], data: &mut &[u8]) -> Result<Vec<(String, Datum)>, PaymentError> {
+    let mut record = Vec::with_capacity(fields.len());
+    for field in fields {
+        let value = decode_value(&field.schema, data)?;
+        if value != Datum::Null {
+            record.push((field.name.clone(), value));
+        }
+    }
+    Ok(record)
+}
+
+fn decode_value(schema: &Schema, data: &mut &[u8]) -> Result<Datum, PaymentError> {
+    Ok(match schema {
+        Schema::Null => Datum::Null,
+        Schema::Boolean => Datum::Boolean(take(data, 1)?[0] != 0),
+        Schema::Int(_) => Datum::Long(read_long(data)?),
+        Schema::Long(logical) => {
+            let value = read_long(data)?;
+            // Timestamps are seconds throughout the engine.
+            Datum::Long(match logical {
+                Some(LongLogical::TimestampMillis | LongLogical::LocalTimestampMillis) => {
+                    value.div_euclid(1_000)
+                }
+                Some(LongLogical::TimestampMicros | LongLogical::LocalTimestampMicros) => {
+                    value.div_euclid(1_000_000)
+                }
+                _ => value,
+            })
+        }
+        Schema::Float => {
+            let bytes = take(data, 4)?.try_into().unwrap_or_default();
+            Datum::Double(f32::from_le_bytes(bytes).into())
+        }
+        Schema::Double => {
+            let bytes = take(data, 8)?.try_into().unwrap_or_default();
+            Datum::Double(f64::from_le_bytes(bytes))
+        }
+        Schema::Bytes(Some(BytesLogical::Decimal(_, scale))) => {
+            let len = read_len(data)?;
+            Datum::Decimal(decimal(take(data, len)?, *scale)?)
+        }
+        Schema::Fixed(fixed) => match fixed.logical {
+            Some(FixedLogical::Decimal(_, scale)) => {
+                Datum::Decimal(decimal(take(data, fixed.size)?, scale)?)
+            }
+            _ => return Err(unsupported(&fixed.name)),
+        },
+        Schema::Bytes(None) | Schema::String(_) => {
+            let len = read_len(data)?;
+            let bytes = take(data, len)?;
+            Datum::Text(String::from_utf8_lossy(bytes).into_owned())
+        }
+        Schema::Enum(schema) => {
+            let index = read_len(data)?;
+            match schema.symbols.get(index) {
+                Some(symbol) => Datum::Text(symbol.clone()),
+                None => return Err(PaymentError::Avro(format!("no symbol {}", index))),
+            }
+        }
+        Schema::Union(branches) => {
+            let index = read_len(data)?;
+            match branches.get(index) {
+                Some(branch) => decode_value(branch, data)?,
+                None => return Err(PaymentError::Avro(format!("no union branch {}", index))),
+            }
+        }
+        Schema::Record(record) => Datum::Record(decode_fields(&record.fields, data)?),
+        Schema::Array(_) | Schema::Map(_) => return Err(unsupported("array and map")),
+    })
+}
+
+/// Reads the fields of a transaction record into an [`InputRecord`], the
+/// `tx` through `tx_ids` if given. Fields it doesn't have are ignored.
+fn input_record(
+    fields: Vec<(String, Datum)>,
+    tx_ids: Option<&TxIdMap>,
+) -> Result<InputRecord, PaymentError> {
+    let (mut record_type, mut client_id, mut tx_id) = (None, None, None);
+    let mut record = InputRecord {
+        record_type: TransactionType::Deposit,
+        client_id: 0,
+        tx_id: 0,
+        amount: None,
+        counterparty_id: None,
+        currency: Default::default(),
+        target_currency: None,
+        timestamp: None,
+        idempotency_key: None,
+        reference: None,
+        metadata: Default::default(),
+    };
+    for (name, value) in fields {
+        let invalid = || PaymentError::Avro(format!("invalid {} field", name));
+        match (name.as_str(), &value) {
+            ("type", Datum::Text(text)) => {
+                record_type = Some(TransactionType::from_alias(text).ok_or_else(|| {
+                    PaymentError::Avro(format!("unknown transaction type '{}'", text))
+                })?)
+            }
+            ("client", Datum::Long(id)) => {
+                client_id = Some((*id).try_into().map_err(|_| invalid())?)
+            }
+            ("tx", Datum::Long(id)) => {
+                tx_id = Some(match tx_ids {
+                    Some(tx_ids) => tx_ids.map(&id.to_string())?,
+                    None => (*id).try_into().map_err(|_| invalid())?,
+                })
+            }
+            ("tx", Datum::Text(reference)) => match tx_ids {
+                Some(tx_ids) => tx_id = Some(tx_ids.map(reference)?),
+                None => return Err(invalid()),
+            },
+            ("amount", Datum::Decimal(amount)) => record.amount = Some(*amount),
+            ("amount", Datum::Long(amount)) => record.amount = Some(Decimal::from(*amount)),
+            // Through its shortest spelling, as JSON amounts are read.
+            ("amount", Datum::Double(amount)) => {
+                record.amount = Some(Decimal::from_str(&amount.to_string()).map_err(|_| invalid())?)
+            }
+            ("amount", Datum::Text(amount)) => {
+                record.amount = Some(
+                    Decimal::from_str(amount)
+                        .or_else(|_| Decimal::from_scientific(amount))
+                        .map_err(|_| invalid())?,
+                )
+            }
+            ("counterparty", Datum::Long(id)) => {
+                record.counterparty_id = Some((*id).try_into().map_err(|_| invalid())?)
+            }
+            ("currency", Datum::Text(code)) => {
+                record.currency = code.parse().map_err(|_| invalid())?
+            }
+            ("to_currency", Datum::Text(code)) => {
+                record.target_currency = Some(code.parse().map_err(|_| invalid())?)
+            }
+            ("timestamp", Datum::Long(seconds)) => {
+                record.timestamp = Some((*seconds).try_into().map_err(|_| invalid())?)
+            }
+            ("idempotency_key", Datum::Text(key)) => record.idempotency_key = Some(key.clone()),
+            ("reference", Datum::Text(reference)) => record.reference = Some(reference.clone()),
+            (
+                "type" | "client" | "tx" | "amount" | "counterparty" | "currency" | "to_currency"
+                | "timestamp" | "idempotency_key" | "reference",
+                _,
+            ) => return Err(invalid()),
+            _ => {}
+        }
+    }
+    let missing = |name| PaymentError::Avro(format!("missing field {}", name));
+    record.record_type = record_type.ok_or_else(|| missing("type"))?;
+    record.client_id = client_id.ok_or_else(|| missing("client"))?;
+    record.tx_id = tx_id.ok_or_else(|| missing("tx"))?;
+    Ok(record)
+}
+
+fn unsupported(what: &str) -> PaymentError {
+    PaymentError::Avro(format!("unsupported field type {}", what))
+}
+
+/// A decimal from its big-endian two's complement mantissa.
+fn decimal(bytes: &[u8], scale: usize) -> Result<Decimal, PaymentError> {
+    let invalid = || PaymentError::Avro("invalid decimal".to_string());
+    if bytes.is_empty() || bytes.len() > 16 {
+        return Err(invalid());
+    }
+    let fill = if bytes[0] & 0x80 == 0 { 0 } else { 0xff };
+    let mut mantissa = [fill; 16];
+    mantissa[16 - bytes.len()..].copy_from_slice(bytes);
+    let scale = u32::try_from(scale).map_err(|_| invalid())?;
+    Decimal::try_from_i128_with_scale(i128::from_be_bytes(mantissa), scale).map_err(|_| invalid())
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], PaymentError> {
+    if data.len() < len {
+        return Err(PaymentError::Avro("record ends early".to_string()));
+    }
+    let (value, rest) = data.split_at(len);
+    *data = rest;
+    Ok(value)
+}
+
+/// A zigzag-encoded variable-length `long`.
+fn read_long(data: &mut &[u8]) -> Result<i64, PaymentError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = take(data, 1)?[0];
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+        }
+    }
+    Err(PaymentError::Avro("invalid long".to_string()))
+}
+
+/// A length or index, which can't be negative.
+fn read_len(data: &mut &[u8]) -> Result<usize, PaymentError> {
+    usize::try_from(read_long(data)?).map_err(|_| PaymentError::Avro("negative length".to_string()))
+}
+
+/// Writes account states as an Avro file of [`account_schema`] records,
+/// sorted like the other formats.
+pub fn write_accounts_avro<W: Write>(
+    engine: &PaymentEngine,
+    writer: W,
+) -> Result<(), PaymentError> {
+    let accounts: Vec<OutputRecord> = engine
+        .iter_accounts_sorted()
+        .map(|account| account.to_output_record())
+        .collect();
+    write_file(account_schema(), &accounts, writer, |account, row| {
+        write_long(u64::from(account.client_id) as i64, row)?;
+        write_bytes(account.currency.as_str().as_bytes(), row)?;
+        write_amount(account.available, row)?;
+        write_amount(account.held, row)?;
+        write_amount(account.total, row)?;
+        row.push(u8::from(account.locked));
+        row.push(u8::from(account.closed));
+        write_amount(account.overdraft, row)
+    })
+}
+
+/// Writes a client statement as an Avro file of [`audit_schema`] records.
+pub fn write_statement_avro<W: Write>(
+    entries: &[AuditEntry],
+    writer: W,
+) -> Result<(), PaymentError> {
+    write_file(audit_schema(), entries, writer, |entry, row| {
+        write_long(u64::from(entry.tx) as i64, row)?;
+        write_long(u64::from(entry.client) as i64, row)?;
+        write_bytes(entry.currency.as_str().as_bytes(), row)?;
+        write_bytes(entry.action.as_bytes(), row)?;
+        write_amount(entry.amount, row)?;
+        write_amount(entry.available, row)?;
+        write_amount(entry.held, row)?;
+        row.push(u8::from(entry.locked));
+        Ok(())
+    })
+}
+
+fn write_file<T, W: Write>(
+    schema: Record,
+    rows: &[T],
+    mut writer: W,
+    encode: impl Fn(&T, &mut Vec<u8>) -> Result<(), PaymentError>,
+) -> Result<(), PaymentError> {
+    let avro = |e: avro_schema::error::Error| PaymentError::Avro(e.to_string());
+    write_metadata(&mut writer, schema, None).map_err(avro)?;
+    for chunk in rows.chunks(BLOCK_ROWS) {
+        let mut data = Vec::new();
+        for row in chunk {
+            encode(row, &mut data)?;
+        }
+        write_block(&mut writer, &CompressedBlock::new(chunk.len(), data)).map_err(avro)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_long(value: i64, row: &mut Vec<u8>) -> Result<(), PaymentError> {
+    zigzag_encode(value, row).map_err(|e| PaymentError::Avro(e.to_string()))
+}
+
+fn write_bytes(bytes: &[u8], row: &mut Vec<u8>) -> Result<(), PaymentError> {
+    write_long(bytes.len() as i64, row)?;
+    row.extend_from_slice(bytes);
+    Ok(())
+}
+
+/// An amount as a `decimal`: the shortest big-endian two's complement of its
+/// mantissa at the output precision.
+fn write_amount(amount: Decimal, row: &mut Vec<u8>) -> Result<(), PaymentError> {
+    let bytes = output_mantissa(amount).to_be_bytes();
+    let redundant = bytes
+        .windows(2)
+        .take_while(|pair| {
+            (pair[0] == 0 && pair[1] & 0x80 == 0) || (pair[0] == 0xff && pair[1] & 0x80 != 0)
+        })
+        .count();
+    write_bytes(&bytes[redundant..], row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TransactionType;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    /// An uncompressed file of `schema` holding `rows`, each already encoded.
+    fn avro_file(schema: Record, rows: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = Vec::new();
+        write_metadata(&mut file, schema, None).unwrap();
+        write_block(&mut file, &CompressedBlock::new(rows.len(), rows.concat())).unwrap();
+        file
+    }
+
+    fn transaction(record_type: &str, client: i64, tx: i64, amount: Option<&str>) -> Vec<u8> {
+        let mut row = Vec::new();
+        write_bytes(record_type.as_bytes(), &mut row).unwrap();
+        write_long(client, &mut row).unwrap();
+        write_long(tx, &mut row).unwrap();
+        match amount {
+            Some(amount) => {
+                write_long(1, &mut row).unwrap();
+                write_bytes(amount.as_bytes(), &mut row).unwrap();
+            }
+            None => write_long(0, &mut row).unwrap(),
+        }
+        // The remaining optional fields are null.
+        for _ in 0..6 {
+            write_long(0, &mut row).unwrap();
+        }
+        row
+    }
+
+    #[rstest]
+    fn test_process_avro() {
+        let file = avro_file(
+            transaction_schema(),
+            &[
+                transaction("deposit", 1, 1, Some("10.5")),
+                transaction("withdrawal", 1, 2, Some("20.0")),
+                transaction("dispute", 1, 1, None),
+            ],
+        );
+        let mut engine = PaymentEngine::new();
+        let report = process_avro(file.as_slice(), &mut engine).unwrap();
+
+        // The withdrawal is declined, not skipped.
+        assert_eq!(report.records_read, 3);
+        assert!(report.skipped.is_empty());
+        let account = engine.get_account(1, Default::default()).unwrap();
+        assert_eq!(account.available, dec!(0));
+        assert_eq!(account.held, dec!(10.5));
+    }
+
+    #[rstest]
+    fn test_read_avro_matches_fields_by_name() {
+        // Another producer's schema: fields reordered, an enum, an int id, a
+        // decimal amount and a millisecond timestamp.
+        let schema = Record::new(
+            "Payment",
+            vec![
+                Field::new("amount", Schema::Bytes(Some(BytesLogical::Decimal(10, 2)))),
+                Field::new(
+                    "type",
+                    Schema::Enum(avro_schema::schema::Enum::new(
+                        "Kind",
+                        vec!["deposit".to_string(), "withdrawal".to_string()],
+                    )),
+                ),
+                Field::new("client", Schema::Int(None)),
+                Field::new("tx", Schema::Long(None)),
+                Field::new(
+                    "timestamp",
+                    Schema::Long(Some(LongLogical::TimestampMillis)),
+                ),
+                Field::new("note", Schema::String(None)),
+            ],
+        );
+        let mut row = Vec::new();
+        write_bytes(&[0x04, 0xd2], &mut row).unwrap(); // 12.34
+        write_long(1, &mut row).unwrap(); // withdrawal
+        write_long(7, &mut row).unwrap();
+        write_long(3, &mut row).unwrap();
+        write_long(1_700_000_000_999, &mut row).unwrap();
+        write_bytes(b"ignored", &mut row).unwrap();
+
+        let records: Vec<RawRecord> = read_avro(avro_file(schema, &[row]).as_slice()).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].line, 1);
+        let record = records[0].parsed.as_ref().unwrap();
+        assert_eq!(record.record_type, TransactionType::Withdrawal);
+        assert_eq!(record.client_id, 7);
+        assert_eq!(record.tx_id, 3);
+        assert_eq!(record.amount, Some(dec!(12.34)));
+        assert_eq!(record.timestamp, Some(1_700_000_000));
+    }
+
+    #[rstest]
+    fn test_read_avro_reports_bad_records() {
+        let mut truncated = transaction("deposit", 1, 2, Some("1.0"));
+        truncated.truncate(3);
+        let file = avro_file(
+            transaction_schema(),
+            &[transaction("bogus", 1, 1, Some("1.0")), truncated],
+        );
+        let records: Vec<RawRecord> = read_avro(file.as_slice()).collect();
+        assert_eq!(records.len(), 2);
+        // The raw record is its JSON form, for the rejects file.
+        assert!(records[0].raw.contains("\"type\":\"bogus\""));
+        assert!(records[0].parsed.is_err());
+        assert!(matches!(records[1].parsed, Err(PaymentError::Avro(_))));
+
+        let records: Vec<RawRecord> = read_avro(&b"not avro"[..]).collect();
+        assert_eq!(records[0].line, 0);
+        assert!(matches!(records[0].parsed, Err(PaymentError::Avro(_))));
+    }
+
+    #[rstest]
+    fn test_write_accounts_avro_round_trips() {
+        let mut engine = PaymentEngine::new().with_statement_history();
+        let file = avro_file(
+            transaction_schema(),
+            &[
+                transaction("deposit", 2, 1, Some("10.5")),
+                transaction("deposit", 1, 2, Some("0.0001")),
+                transaction("withdrawal", 2, 3, Some("11")),
+            ],
+        );
+        process_avro(file.as_slice(), &mut engine).unwrap();
+
+        let mut output = Vec::new();
+        write_accounts_avro(&engine, &mut output).unwrap();
+        let accounts = read_values(&output);
+        assert_eq!(
+            accounts,
+            [
+                serde_json::json!({"client": 1, "currency": "", "available": "0.0001",
+                    "held": "0.0000", "total": "0.0001", "locked": false, "closed": false,
+                    "overdraft": "0.0000"}),
+                serde_json::json!({"client": 2, "currency": "", "available": "10.5000",
+                    "held": "0.0000", "total": "10.5000", "locked": false, "closed": false,
+                    "overdraft": "0.0000"}),
+            ]
+        );
+
+        let mut output = Vec::new();
+        write_statement_avro(engine.statement(2).unwrap(), &mut output).unwrap();
+        let entries = read_values(&output);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["action"], "deposit");
+    }
+
+    /// Decodes every record of an Avro file into JSON values.
+    fn read_values(mut file: &[u8]) -> Vec<serde_json::Value> {
+        let metadata = read_metadata(&mut file).unwrap();
+        let mut blocks = BlockStreamingIterator::new(file, metadata.compression, metadata.marker);
+        let mut values = Vec::new();
+        while let Some(block) = blocks.next().unwrap() {
+            let mut data = block.data.as_slice();
+            for _ in 0..block.number_of_rows {
+                let fields = decode_fields(&metadata.record.fields, &mut data).unwrap();
+                values.push(serde_json::to_value(Datum::Record(fields)).unwrap());
+            }
+        }
+        values
+    }
+
+    #[rstest]
+    #[case(dec!(0), &[0x00])]
+    #[case(dec!(0.0127), &[0x7f])]
+    #[case(dec!(0.0128), &[0x00, 0x80])]
+    #[case(dec!(-0.0001), &[0xff])]
+    #[case(dec!(-0.0129), &[0xff, 0x7f])]
+    fn test_write_amount(#[case] amount: Decimal, #[case] expected: &[u8]) {
+        let mut row = Vec::new();
+        write_amount(amount, &mut row).unwrap();
+        assert_eq!(&row[1..], expected);
+        let mut data = &row[1..];
+        assert_eq!(
+            decimal(take(&mut data, expected.len()).unwrap(), 4).unwrap(),
+            amount
+        );
+    }
+}
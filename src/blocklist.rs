@@ -0,0 +1,90 @@
+//! Sanctions screening: clients whose transactions are all rejected.
+
+use crate::account_map::AccountMap;
+use crate::errors::PaymentError;
+use crate::models::ClientId;
+use rustc_hash::FxHashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Clients barred from transacting. The engine rejects their records and
+/// locks the accounts they name. Clones share the set.
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist {
+    clients: Arc<FxHashSet<ClientId>>,
+}
+
+impl Blocklist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `client` to the blocklist.
+    pub fn with_client(mut self, client: ClientId) -> Self {
+        Arc::make_mut(&mut self.clients).insert(client);
+        self
+    }
+
+    /// Reads a blocklist of one entry per line: a client id, or an external
+    /// account `accounts` assigns to a client. Blank lines and lines
+    /// starting with `#` are ignored.
+    pub fn from_reader<R: Read>(reader: R, accounts: &AccountMap) -> Result<Self, PaymentError> {
+        let mut blocklist = Self::new();
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let entry = line.trim();
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+            let client = accounts
+                .client(entry)
+                .or_else(|| entry.parse().ok())
+                .ok_or_else(|| {
+                    PaymentError::InvalidConfig(format!(
+                        "blocklist entry '{}' is neither a client id nor a known account",
+                        entry
+                    ))
+                })?;
+            blocklist = blocklist.with_client(client);
+        }
+        Ok(blocklist)
+    }
+
+    /// Reads the blocklist at `path`.
+    pub fn load<P: AsRef<Path>>(path: P, accounts: &AccountMap) -> Result<Self, PaymentError> {
+        Self::from_reader(File::open(path)?, accounts)
+    }
+
+    pub fn contains(&self, client: ClientId) -> bool {
+        self.clients.contains(&client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_blocklist_reads_clients_and_accounts() {
+        let accounts = AccountMap::new().with_account("DE89 3704 0044 0532 0130 00", 4);
+        let blocklist = Blocklist::from_reader(
+            "# sanctioned\n 2 \n\nde89370400440532013000\n".as_bytes(),
+            &accounts,
+        )
+        .unwrap();
+        assert!(blocklist.contains(2));
+        assert!(blocklist.contains(4));
+        assert!(!blocklist.contains(1));
+    }
+
+    #[rstest]
+    fn test_blocklist_rejects_unknown_entries() {
+        let accounts = AccountMap::new().with_account("DE89370400440532013000", 4);
+        let err =
+            Blocklist::from_reader("GB29NWBK60161331926819\n".as_bytes(), &accounts).unwrap_err();
+        assert!(matches!(err, PaymentError::InvalidConfig(_)));
+    }
+}
@@ -0,0 +1,373 @@
+//! Checkpoints of long runs, so a run over a huge input that's interrupted
+//! can resume where it stopped instead of reprocessing it from the start.
+//!
+//! A checkpoint file is a JSON line giving how many records of the input
+//! were applied (and those skipped among them), followed by an engine
+//! snapshot (see [`PaymentEngine::snapshot`]). On resume, the records it
+//! covers are passed over without being applied again: CSV rows unread (see
+//! [`process_records_after_checkpoint`]), other records decoded, so ids
+//! mapped by `--tx-id-format` come out the same. Idempotency keys aren't
+//! part of a snapshot, so retries of records before the checkpoint aren't
+//! recognized.
+//!
+//! Each checkpoint is a full snapshot, so they're spaced out as the state
+//! grows: once writing one every `interval` records would write more than
+//! [`BYTES_PER_RECORD`] bytes per record, the next one waits for enough
+//! records to keep it at that.
+
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::input::{process_records_with_policy, RawRecord};
+use crate::output::write_file_atomically;
+use crate::policy::ErrorPolicy;
+use crate::report::{ProcessingReport, SkippedRecord};
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::num::NonZeroU64;
+use std::path::Path;
+
+/// Version written in checkpoints and accepted when resuming from them.
+const CHECKPOINT_VERSION: u32 = 1;
+
+/// Bytes of checkpoint written per record processed, at most, once
+/// checkpoints grow large: about what reading a CSV record takes, so
+/// checkpointing costs no more than the input itself.
+pub const BYTES_PER_RECORD: u64 = 16;
+
+/// The first line of a checkpoint file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    /// Records of the input applied or skipped before the checkpoint.
+    records: u64,
+    skipped: Vec<SkippedRecord>,
+}
+
+/// Applies `records` to the engine like [`process_records_with_policy`],
+/// writing a checkpoint to `path` every `interval` records (or less often,
+/// as snapshots grow). If `path` holds a checkpoint already, the engine is
+/// restored from it and the records it covers are passed over. The report
+/// covers the whole input, and the checkpoint is left in place for the
+/// caller to remove once the results are safe.
+pub fn process_records_with_checkpoints<I>(
+    records: I,
+    engine: &mut PaymentEngine,
+    policy: ErrorPolicy,
+    path: &Path,
+    interval: NonZeroU64,
+) -> Result<ProcessingReport, PaymentError>
+where
+    I: IntoIterator<Item = RawRecord>,
+{
+    process(records, engine, policy, path, interval, true)
+}
+
+/// Like [`process_records_with_checkpoints`], for `records` that start
+/// after the [`records_covered`] by the checkpoint at `path`, which the
+/// caller passed over, e.g. with
+/// [`InputOptions::skip_rows`](crate::input::InputOptions::skip_rows).
+pub fn process_records_after_checkpoint<I>(
+    records: I,
+    engine: &mut PaymentEngine,
+    policy: ErrorPolicy,
+    path: &Path,
+    interval: NonZeroU64,
+) -> Result<ProcessingReport, PaymentError>
+where
+    I: IntoIterator<Item = RawRecord>,
+{
+    process(records, engine, policy, path, interval, false)
+}
+
+fn process<I>(
+    records: I,
+    engine: &mut PaymentEngine,
+    policy: ErrorPolicy,
+    path: &Path,
+    interval: NonZeroU64,
+    pass_covered: bool,
+) -> Result<ProcessingReport, PaymentError>
+where
+    I: IntoIterator<Item = RawRecord>,
+{
+    let mut records = records.into_iter();
+    let mut report = match resume(path, engine)? {
+        Some(report) => {
+            if pass_covered {
+                let passed = records.by_ref().take(report.records_read as usize).count();
+                if passed as u64 != report.records_read {
+                    return Err(PaymentError::InvalidSnapshot(format!(
+                        "the checkpoint covers {} records but the inputs have {}",
+                        report.records_read, passed
+                    )));
+                }
+            }
+            tracing::info!(
+                "Resuming from {} after {} records",
+                path.display(),
+                report.records_read
+            );
+            report
+        }
+        None => ProcessingReport::default(),
+    };
+
+    let mut next = interval.get();
+    loop {
+        let chunk =
+            process_records_with_policy(records.by_ref().take(next as usize), engine, policy)?;
+        if chunk.records_read == 0 {
+            break;
+        }
+        report.records_read += chunk.records_read;
+        report.skipped.extend(chunk.skipped);
+        write_checkpoint(path, engine, &report)?;
+        if chunk.records_read < next {
+            break;
+        }
+        next = interval
+            .get()
+            .max(fs::metadata(path)?.len() / BYTES_PER_RECORD);
+    }
+    Ok(report)
+}
+
+/// The number of records the checkpoint at `path` covers, or 0 if there's
+/// no checkpoint there.
+pub fn records_covered(path: &Path) -> Result<u64, PaymentError> {
+    match File::open(path) {
+        Ok(file) => Ok(read_header(&mut BufReader::new(file))?.records),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes the state of `engine` after the records of `report` to `path`,
+/// atomically so an interruption leaves the previous checkpoint intact.
+pub fn write_checkpoint(
+    path: &Path,
+    engine: &PaymentEngine,
+    report: &ProcessingReport,
+) -> Result<(), PaymentError> {
+    let header = Header {
+        version: CHECKPOINT_VERSION,
+        records: report.records_read,
+        skipped: report.skipped.clone(),
+    };
+    write_file_atomically(path, |writer| {
+        serde_json::to_writer(&mut *writer, &header)?;
+        writer.write_all(b"\n")?;
+        engine.snapshot(&mut *writer)
+    })
+}
+
+/// Restores `engine` from the checkpoint at `path`, returning the report of
+/// the records it covers, or `None` if there's no checkpoint there.
+pub fn resume(
+    path: &Path,
+    engine: &mut PaymentEngine,
+) -> Result<Option<ProcessingReport>, PaymentError> {
+    let mut reader = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let header = read_header(&mut reader)?;
+    engine.restore(reader)?;
+    Ok(Some(ProcessingReport {
+        records_read: header.records,
+        skipped: header.skipped,
+    }))
+}
+
+fn read_header<R: BufRead>(reader: &mut R) -> Result<Header, PaymentError> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let header: Header = serde_json::from_str(&line)?;
+    if header.version != CHECKPOINT_VERSION {
+        return Err(PaymentError::InvalidSnapshot(format!(
+            "unsupported checkpoint version {} (expected {})",
+            header.version, CHECKPOINT_VERSION
+        )));
+    }
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_handler::read_records;
+    use crate::input::{process_records, read_records_with_options, InputFormat, InputOptions};
+    use crate::models::OutputRecord;
+    use crate::report::SkipKind;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+    use std::fs;
+    use std::sync::atomic::Ordering;
+
+    const INPUT: &str = "type,client,tx,amount
+deposit,1,1,10
+deposit,2,2,5
+withdrawal,1,3,lots
+deposit,1,4,1
+dispute,1,1,
+withdrawal,2,5,2
+";
+
+    fn every(records: u64) -> NonZeroU64 {
+        NonZeroU64::new(records).unwrap()
+    }
+
+    fn balances(engine: &PaymentEngine) -> Vec<OutputRecord> {
+        let mut accounts = engine.get_accounts();
+        accounts.sort_by_key(|account| account.client_id);
+        accounts
+    }
+
+    #[rstest]
+    fn test_checkpoints_resume_where_they_stopped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.checkpoint");
+
+        // A strict run stops at the bad withdrawal, after checkpointing
+        // the first two records.
+        let mut engine = PaymentEngine::new();
+        let err = process_records_with_checkpoints(
+            read_records(INPUT.as_bytes()),
+            &mut engine,
+            ErrorPolicy::FailFast,
+            &path,
+            every(2),
+        )
+        .unwrap_err();
+        assert!(matches!(err, PaymentError::AtLine { line: 4, .. }));
+
+        // The records the checkpoint covers aren't applied again, even if
+        // they no longer decode.
+        let resumed = INPUT.replacen("deposit,1,1,10", "garbled", 1);
+        let mut engine = PaymentEngine::new();
+        let report = process_records_with_checkpoints(
+            read_records(resumed.as_bytes()),
+            &mut engine,
+            ErrorPolicy::Skip,
+            &path,
+            every(2),
+        )
+        .unwrap();
+        assert_eq!(report.records_read, 6);
+        assert_eq!(report.count(SkipKind::Decode), 1);
+        assert_eq!(report.skipped[0].line, 4);
+
+        let mut uninterrupted = PaymentEngine::new();
+        process_records(read_records(INPUT.as_bytes()), &mut uninterrupted).unwrap();
+        assert_eq!(balances(&engine), balances(&uninterrupted));
+        let account = engine.get_account(1, Default::default()).unwrap();
+        assert_eq!((account.available, account.held), (dec!(1), dec!(10)));
+
+        // The last checkpoint covers the whole input.
+        let mut restored = PaymentEngine::new();
+        assert_eq!(resume(&path, &mut restored).unwrap(), Some(report));
+        assert_eq!(balances(&restored), balances(&engine));
+    }
+
+    #[rstest]
+    fn test_checkpoints_spaced_out_as_they_grow() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.checkpoint");
+        let mut input = "type,client,tx,amount\n".to_string();
+        for tx in 1..=50 {
+            input.push_str(&format!("deposit,{},{},1\n", tx, tx));
+        }
+        input.push_str("withdrawal,1,51,lots\n");
+
+        let err = process_records_with_checkpoints(
+            read_records(input.as_bytes()),
+            &mut PaymentEngine::new(),
+            ErrorPolicy::FailFast,
+            &path,
+            every(1),
+        )
+        .unwrap_err();
+        assert!(matches!(err, PaymentError::AtLine { line: 52, .. }));
+        let covered = records_covered(&path).unwrap();
+        assert!((1..50).contains(&covered), "{}", covered);
+    }
+
+    #[rstest]
+    fn test_resume_after_rows_passed_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.checkpoint");
+        assert_eq!(records_covered(&path).unwrap(), 0);
+        process_records_with_checkpoints(
+            read_records(INPUT.as_bytes()),
+            &mut PaymentEngine::new(),
+            ErrorPolicy::FailFast,
+            &path,
+            every(2),
+        )
+        .unwrap_err();
+        assert_eq!(records_covered(&path).unwrap(), 2);
+
+        let options = InputOptions::default();
+        options.skip_rows.store(2, Ordering::Relaxed);
+        let mut engine = PaymentEngine::new();
+        let report = process_records_after_checkpoint(
+            read_records_with_options(INPUT.as_bytes(), InputFormat::Csv, &options),
+            &mut engine,
+            ErrorPolicy::Skip,
+            &path,
+            every(2),
+        )
+        .unwrap();
+        assert_eq!(report.records_read, 6);
+        assert_eq!(report.skipped[0].line, 4);
+
+        let mut uninterrupted = PaymentEngine::new();
+        process_records(read_records(INPUT.as_bytes()), &mut uninterrupted).unwrap();
+        assert_eq!(balances(&engine), balances(&uninterrupted));
+    }
+
+    #[rstest]
+    fn test_resume_without_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = PaymentEngine::new();
+        assert_eq!(resume(&dir.path().join("none"), &mut engine).unwrap(), None);
+    }
+
+    #[rstest]
+    fn test_checkpoint_of_a_longer_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.checkpoint");
+        let mut engine = PaymentEngine::new();
+        let report = ProcessingReport {
+            records_read: 10,
+            skipped: Vec::new(),
+        };
+        write_checkpoint(&path, &engine, &report).unwrap();
+        let err = process_records_with_checkpoints(
+            read_records(INPUT.as_bytes()),
+            &mut engine,
+            ErrorPolicy::Skip,
+            &path,
+            every(2),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid snapshot: the checkpoint covers 10 records but the inputs have 6"
+        );
+    }
+
+    #[rstest]
+    fn test_resume_rejects_other_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.checkpoint");
+        fs::write(&path, "{\"version\":2,\"records\":0,\"skipped\":[]}\n{}").unwrap();
+        let err = resume(&path, &mut PaymentEngine::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid snapshot: unsupported checkpoint version 2 (expected 1)"
+        );
+    }
+}
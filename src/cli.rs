@@ -0,0 +1,2039 @@
+use clap::error::ErrorKind;
+use clap::{ArgGroup, CommandFactory, Parser, Subcommand};
+use payment_engine::input::InputFormat;
+use payment_engine::output::OutputFormat;
+use payment_engine::{
+    config, AmountPrecision, ClientId, ClientSet, CsvDialect, DuplicateTxPolicy, EngineConfig,
+    ErrorPolicy, ExpiredDisputeAction, GeneratorConfig, PaymentError, TxIdFormat,
+};
+use rust_decimal::Decimal;
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::str::FromStr;
+
+/// Command-line options accepted by the binary.
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    /// Input files processed in order through one engine ("-" is stdin).
+    /// Optional with `--listen` and `--watch`.
+    pub inputs: Vec<String>,
+    /// Apply the records of all inputs in timestamp order instead of input
+    /// by input (`--merge-by-timestamp`).
+    pub merge_by_timestamp: bool,
+    /// Seconds records may arrive late and still be applied in timestamp
+    /// order (`--reorder-window`).
+    pub reorder_window: Option<u64>,
+    /// Clients whose records are processed and whose accounts are written,
+    /// all of them when `None` (`--clients`).
+    pub clients: Option<ClientSet>,
+    pub input_format: InputFormat,
+    /// How the `tx` column identifies transactions (`--tx-id-format`).
+    pub tx_id_format: TxIdFormat,
+    pub output_format: OutputFormat,
+    /// Leave accounts nothing was applied to out of the output
+    /// (`--skip-empty`).
+    pub skip_empty: bool,
+    /// Destination file for the accounts; stdout when `None`.
+    pub output: Option<String>,
+    /// Number of client shards processed in parallel.
+    pub shards: NonZeroUsize,
+    /// Threads the input goes through (`--threads`): 1 parses and applies
+    /// records on the same thread, 2 parses on a thread of its own.
+    pub threads: NonZeroUsize,
+    /// Directory for disk-backed transaction stores; in memory when `None`.
+    pub tx_store_dir: Option<String>,
+    /// Keep transactions in memory in the packed encoding
+    /// (`--compact-tx-store`).
+    pub compact_tx_store: bool,
+    /// CSV file listing every skipped record and why (`--rejects`).
+    pub rejects: Option<String>,
+    /// CSV file receiving the outcome of every input record (`--results`).
+    pub results: Option<String>,
+    /// CSV or JSON file receiving the movements of every account over the
+    /// run (`--settlement`).
+    pub settlement: Option<String>,
+    /// CSV or JSON file receiving the locked accounts and open disputes at
+    /// the end of the run (`--risk-report`).
+    pub risk_report: Option<String>,
+    /// CSV or JSON file receiving the disputes settled for their age at the
+    /// end of the run (`--expired-disputes`).
+    pub expired_disputes: Option<String>,
+    /// Accounts CSV or snapshot the final accounts must match (`--verify`).
+    pub verify: Option<String>,
+    /// CSV file receiving every balance mutation (`--audit-log`).
+    pub audit_log: Option<String>,
+    /// File keeping account balances across runs (`--account-store`).
+    pub account_store: Option<String>,
+    /// Write-ahead log replayed on startup and appended to while processing
+    /// (`--wal`).
+    pub wal: Option<String>,
+    /// File the run is checkpointed to and resumed from (`--checkpoint`).
+    pub checkpoint: Option<String>,
+    /// Records between checkpoints (`--checkpoint-every`).
+    pub checkpoint_every: NonZeroU64,
+    /// Address serving the line protocol once the inputs are processed
+    /// (`--listen`): `unix:<path>` for a Unix socket, a TCP `host:port`
+    /// otherwise.
+    pub listen: Option<String>,
+    /// Directory whose new files are processed once the inputs are
+    /// (`--watch`).
+    pub watch: Option<String>,
+    /// CSV file of exchange rates quoted to `convert` records (`--rates`).
+    pub rates: Option<String>,
+    /// CSV file assigning the bank accounts of bank file input to clients
+    /// (`--accounts`).
+    pub accounts: Option<String>,
+    /// File of the clients, or accounts, whose records are all rejected
+    /// (`--blocklist`).
+    pub blocklist: Option<String>,
+    /// CSV file of client attributes loaded into the engine
+    /// (`--clients-file`).
+    pub clients_file: Option<String>,
+    /// Layout file of fixed-width input (`--layout`).
+    pub layout: Option<String>,
+    /// How CSV input is written (`--delimiter`, `--quote`, `--escape`,
+    /// `--header-alias` and `--no-header`).
+    pub csv: CsvDialect,
+    /// Engine policies: those of the `--config` file, if any, overridden by
+    /// `--overdraft-limit`, `--interest-rate`, `--interest-period`,
+    /// `--dispute-window`, `--dispute-max-age`, `--expired-dispute-action`,
+    /// `--idempotency-retention`, `--duplicate-tx`,
+    /// `--amount-precision` and `--suspense-account`.
+    pub engine: EngineConfig,
+    /// Abort on the first bad record instead of skipping it (`--strict`).
+    pub error_policy: ErrorPolicy,
+    /// Fail the run if the account totals don't add up to what the records
+    /// moved in and out (`--reconcile`).
+    pub reconcile: bool,
+    /// Fail the run if the final engine state is inconsistent
+    /// (`--check-invariants`).
+    pub check_invariants: bool,
+    /// Client whose statement is written instead of the accounts
+    /// (`statement <client>` subcommand).
+    pub statement: Option<ClientId>,
+    /// Simulate the run without writing to the account store, write-ahead
+    /// log, audit log or transaction store directory (`validate`
+    /// subcommand).
+    pub validate: bool,
+    /// Settings of the synthetic input written instead of processing any
+    /// (`generate` subcommand).
+    pub generate: Option<GeneratorConfig>,
+    /// Format the decoded inputs are rewritten in instead of processing them
+    /// (`convert` subcommand).
+    pub convert: Option<InputFormat>,
+    /// Expected and actual account files compared instead of processing
+    /// any (`diff` subcommand).
+    pub diff: Option<(String, String)>,
+    /// Print a processing summary to stderr (`--stats`).
+    pub stats: bool,
+    /// Log verbosity relative to the default (warnings): each `-v` adds a
+    /// level, `-q` drops to errors only.
+    pub verbosity: i8,
+}
+
+/// Processes payment transactions and reports the final state of every client
+/// account.
+#[derive(Debug, Parser)]
+#[command(name = "payment_engine", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Log more: -v for info, -vv for debug, -vvv for trace
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+    /// Only log errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Process the inputs and write the final accounts (the default when no
+    /// subcommand is given)
+    Process(RunArgs),
+    /// Process the inputs, then keep the engine running to serve connections,
+    /// process files dropped in a directory, or both
+    #[command(group(ArgGroup::new("serve").required(true).multiple(true).args(["listen", "watch"])))]
+    Serve {
+        /// Serve the line protocol on a TCP `host:port`, or a Unix socket with
+        /// `unix:<path>`
+        #[arg(long, value_name = "ADDR")]
+        listen: Option<String>,
+        /// Process every file that appears in this directory
+        #[arg(long, value_name = "DIR")]
+        watch: Option<String>,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Process the inputs without writing any persistent state, failing if
+    /// any record would be skipped
+    Validate(RunArgs),
+    /// Write a synthetic CSV input for load tests
+    Generate(GenerateArgs),
+    /// Rewrite the records of the inputs in another format without
+    /// processing them
+    Convert {
+        /// Format written: csv, jsonl, or msgpack and protobuf with the
+        /// features of the same names
+        #[arg(long, value_name = "FORMAT")]
+        to: String,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Process the inputs and write one client's statement instead of the
+    /// accounts
+    Statement {
+        /// Client whose balance mutations are listed
+        client: ClientId,
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Compare two account files (outputs or snapshots) and list every
+    /// balance or flag that differs, failing if any does
+    Diff {
+        /// Accounts CSV or engine snapshot expected, like a golden output
+        expected: String,
+        /// Accounts CSV or engine snapshot compared against it
+        actual: String,
+        /// Write the differences to a file instead of stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<String>,
+    },
+}
+
+/// Options shared by the subcommands that process inputs.
+#[derive(Debug, Default, clap::Args)]
+struct RunArgs {
+    /// Input files, processed in order through one engine ("-" reads stdin)
+    #[arg(value_name = "INPUT")]
+    inputs: Vec<String>,
+    /// Apply the records of all inputs in timestamp order, each input being
+    /// in order already, instead of one input after the other
+    #[arg(long)]
+    merge_by_timestamp: bool,
+    /// Apply records in timestamp order, holding them until this many
+    /// seconds of newer ones arrived; later ones are dropped as late
+    #[arg(long, value_name = "SECONDS")]
+    reorder_window: Option<u64>,
+    /// Process and write the accounts of these clients only, e.g.
+    /// 1,5,100-200
+    #[arg(long, value_name = "LIST")]
+    clients: Option<String>,
+    /// Engine policies and I/O settings (TOML, or YAML for .yaml/.yml files);
+    /// flags override them
+    #[arg(long, value_name = "PATH")]
+    config: Option<String>,
+    /// Input format: csv, jsonl, pain001, camt053, mt940, nacha, ofx, qif,
+    /// fixed (with --layout), or avro, msgpack and protobuf with the
+    /// features of the same names
+    #[arg(long, value_name = "FORMAT")]
+    input_format: Option<String>,
+    /// How the tx column identifies transactions: numeric, uuid or string
+    #[arg(long, value_name = "FORMAT")]
+    tx_id_format: Option<String>,
+    /// Output format: csv, json, jsonl, table, or arrow, avro and parquet
+    /// with the features of the same names
+    #[arg(long, value_name = "FORMAT")]
+    output_format: Option<String>,
+    /// Show locked accounts in red in --output-format table
+    #[arg(long)]
+    color: bool,
+    /// Leave out accounts that hold nothing, aren't locked and had nothing
+    /// applied to them, like those of ignored withdrawals
+    #[arg(long)]
+    skip_empty: bool,
+    /// Write the output to a file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<String>,
+    /// Spread clients over this many worker threads
+    #[arg(long, value_name = "N", value_parser = parse_shards)]
+    shards: Option<NonZeroUsize>,
+    /// 2 parses the input on a thread of its own
+    #[arg(long, value_name = "1|2", value_parser = parse_threads)]
+    threads: Option<NonZeroUsize>,
+    /// Keep transactions in on-disk indexes in this directory
+    #[arg(long, value_name = "DIR")]
+    tx_store_dir: Option<String>,
+    /// Keep transactions in memory in a packed form
+    #[arg(long)]
+    compact_tx_store: bool,
+    /// Write every skipped record and why to this CSV file
+    #[arg(long, value_name = "PATH")]
+    rejects: Option<String>,
+    /// Write the outcome of every record to this CSV file
+    #[arg(long, value_name = "PATH")]
+    results: Option<String>,
+    /// Write each account's deposits, withdrawals, net movement, disputes
+    /// and chargebacks over the run to this CSV file (JSON if it ends in
+    /// .json)
+    #[arg(long, value_name = "PATH")]
+    settlement: Option<String>,
+    /// Write the accounts left locked and the disputes still open, with the
+    /// funds they hold, to this CSV file (JSON if it ends in .json)
+    #[arg(long, value_name = "PATH")]
+    risk_report: Option<String>,
+    /// Write the disputes settled for --dispute-max-age to this CSV file
+    /// (JSON if it ends in .json)
+    #[arg(long, value_name = "PATH")]
+    expired_disputes: Option<String>,
+    /// Fail, listing the differences, unless the final accounts match this
+    /// accounts CSV or snapshot
+    #[arg(long, value_name = "PATH")]
+    verify: Option<String>,
+    /// Write every balance mutation to this CSV file
+    #[arg(long, value_name = "PATH")]
+    audit_log: Option<String>,
+    /// Keep account balances in this file across runs
+    #[arg(long, value_name = "PATH")]
+    account_store: Option<String>,
+    /// Replay and append to this write-ahead log
+    #[arg(long, value_name = "PATH")]
+    wal: Option<String>,
+    /// Checkpoint the run to this file, resuming from it if it's there; it's
+    /// removed once the accounts are written
+    #[arg(long, value_name = "PATH")]
+    checkpoint: Option<String>,
+    /// Records between checkpoints [default: 100000]
+    #[arg(long, value_name = "N")]
+    checkpoint_every: Option<NonZeroU64>,
+    /// Let every account overdraw up to this amount
+    #[arg(long, value_name = "AMOUNT", allow_negative_numbers = true, value_parser = parse_non_negative)]
+    overdraft_limit: Option<Decimal>,
+    /// CSV file of exchange rates (from,to,rate) for convert records
+    #[arg(long, value_name = "PATH")]
+    rates: Option<String>,
+    /// CSV file of bank accounts (account,client) for pain001, camt053,
+    /// mt940, nacha, ofx and qif input; without it account numbers are read
+    /// as client ids
+    #[arg(long, value_name = "PATH")]
+    accounts: Option<String>,
+    /// File of client ids, or accounts listed in --accounts, one per line,
+    /// whose records are rejected and whose accounts are locked
+    #[arg(long, value_name = "PATH")]
+    blocklist: Option<String>,
+    /// CSV file of client attributes (client,name,external_id,currency,
+    /// overdraft_limit,kyc_status), echoed next to their accounts
+    #[arg(long, value_name = "PATH")]
+    clients_file: Option<String>,
+    /// TOML or YAML file giving the columns of fixed-width input
+    #[arg(long, value_name = "PATH")]
+    layout: Option<String>,
+    /// Field delimiter of CSV input: one character, or tab
+    #[arg(long, value_name = "CHAR")]
+    delimiter: Option<String>,
+    /// Quote character of CSV input, or none to read quotes as text
+    #[arg(long, value_name = "CHAR")]
+    quote: Option<String>,
+    /// Character escaping quotes in quoted CSV fields, instead of doubling
+    /// them
+    #[arg(long, value_name = "CHAR")]
+    escape: Option<String>,
+    /// Read a CSV header name as an input column (e.g. txn_id=tx); repeatable
+    #[arg(long = "header-alias", value_name = "NAME=COLUMN")]
+    header_aliases: Vec<String>,
+    /// CSV input has no header row: its columns are type,client,tx,amount
+    /// in that order
+    #[arg(long)]
+    no_header: bool,
+    /// Keep CSV columns that aren't input columns (e.g. memo) and write
+    /// them to the audit log and statements
+    #[arg(long)]
+    capture_metadata: bool,
+    /// Read a name in the CSV type column as a transaction type (e.g.
+    /// payout=withdrawal); repeatable
+    #[arg(long = "type-alias", value_name = "NAME=TYPE")]
+    type_aliases: Vec<String>,
+    /// Only read exact transaction type names (and --type-alias ones) in CSV
+    /// input, rejecting spellings like Deposit or withdraw
+    #[arg(long)]
+    strict_types: bool,
+    /// How CSV input writes amounts: plain (1234.56), us (1,234.56) or eu
+    /// (1.234,56); us and eu also read (12.00) as negative
+    #[arg(long, value_name = "FORMAT")]
+    amount_format: Option<String>,
+    /// Pay this annual interest rate (in percent) on available balances
+    #[arg(long, value_name = "PERCENT", allow_negative_numbers = true, value_parser = parse_non_negative)]
+    interest_rate: Option<Decimal>,
+    /// Days between interest postings [default: 30]
+    #[arg(long, value_name = "DAYS", value_parser = parse_period)]
+    interest_period: Option<u32>,
+    /// Reject disputes filed more than this many days after their transaction
+    #[arg(long, value_name = "DAYS", allow_negative_numbers = true, value_parser = parse_days)]
+    dispute_window: Option<u64>,
+    /// At the end of the run, settle disputes filed more than this many
+    /// days before the newest record
+    #[arg(long, value_name = "DAYS", allow_negative_numbers = true, value_parser = parse_days)]
+    dispute_max_age: Option<u64>,
+    /// How disputes past --dispute-max-age are settled: resolve or
+    /// chargeback [default: resolve]
+    #[arg(long, value_name = "ACTION")]
+    expired_dispute_action: Option<ExpiredDisputeAction>,
+    /// Forget idempotency keys after this many days
+    #[arg(long, value_name = "DAYS", allow_negative_numbers = true, value_parser = parse_days)]
+    idempotency_retention: Option<u64>,
+    /// Records reusing a taken tx id: ignore, warn or error
+    #[arg(long, value_name = "POLICY")]
+    duplicate_tx: Option<DuplicateTxPolicy>,
+    /// Amounts with more than 4 decimal places: reject, truncate or
+    /// round-half-even
+    #[arg(long, value_name = "MODE")]
+    amount_precision: Option<AmountPrecision>,
+    /// Credit chargebacked funds to this client's account instead of
+    /// writing them off the books
+    #[arg(long, value_name = "CLIENT")]
+    suspense_account: Option<ClientId>,
+    /// Reject transactions moving more than this amount
+    #[arg(long, value_name = "AMOUNT", allow_negative_numbers = true, value_parser = parse_non_negative)]
+    max_amount: Option<Decimal>,
+    /// Reject withdrawals taking a client's withdrawals for the day past
+    /// this amount
+    #[arg(long, value_name = "AMOUNT", allow_negative_numbers = true, value_parser = parse_non_negative)]
+    max_daily_withdrawal: Option<Decimal>,
+    /// Reject a client's transactions once this many were accepted within
+    /// --velocity-window
+    #[arg(long, value_name = "N", value_parser = parse_count)]
+    max_transactions: Option<u32>,
+    /// Seconds --max-transactions counts over [default: 3600]
+    #[arg(long, value_name = "SECONDS", value_parser = parse_window)]
+    velocity_window: Option<u64>,
+    /// Stop at the first bad record instead of skipping it
+    #[arg(long)]
+    strict: bool,
+    /// Fail if the account totals don't add up to the deposits, withdrawals,
+    /// chargebacks and other flows of the records applied
+    #[arg(long)]
+    reconcile: bool,
+    /// Fail if the final engine state breaks one of its invariants; reads
+    /// every stored transaction
+    #[arg(long)]
+    check_invariants: bool,
+    /// Print a processing summary to stderr
+    #[arg(long)]
+    stats: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct GenerateArgs {
+    /// Clients the records are spread over
+    #[arg(long, value_name = "N", default_value_t = GeneratorConfig::default().clients)]
+    clients: u64,
+    /// Records written, invalid ones included
+    #[arg(long, value_name = "N", default_value_t = GeneratorConfig::default().transactions)]
+    transactions: u64,
+    /// Share of records that are disputes, resolves or chargebacks
+    #[arg(long, value_name = "RATIO", default_value_t = GeneratorConfig::default().dispute_ratio)]
+    dispute_ratio: f64,
+    /// Share of records that are malformed or rejected
+    #[arg(long, value_name = "RATIO", default_value_t = GeneratorConfig::default().invalid_ratio)]
+    invalid_ratio: f64,
+    /// The same seed and settings always give the same file
+    #[arg(long, value_name = "N", default_value_t = GeneratorConfig::default().seed)]
+    seed: u64,
+    /// Write the input to a file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<String>,
+}
+
+/// Records between checkpoints without `--checkpoint-every`.
+const DEFAULT_CHECKPOINT_INTERVAL: NonZeroU64 = NonZeroU64::new(100_000).unwrap();
+
+/// Subcommands and flags that are handled before one would be inserted.
+const SUBCOMMANDS: [&str; 12] = [
+    "process",
+    "serve",
+    "validate",
+    "generate",
+    "convert",
+    "statement",
+    "diff",
+    "help",
+    "-h",
+    "--help",
+    "-V",
+    "--version",
+];
+
+/// Parses command-line arguments (excluding the program name). Without a
+/// subcommand, the arguments are those of `process`.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Args, clap::Error> {
+    let mut args: Vec<String> = args.into_iter().collect();
+    if !args
+        .first()
+        .is_some_and(|arg| SUBCOMMANDS.contains(&arg.as_str()))
+    {
+        args.insert(0, "process".to_string());
+    }
+    let cli = Cli::try_parse_from(std::iter::once("payment_engine".to_string()).chain(args))?;
+    let verbosity = if cli.quiet {
+        -1
+    } else {
+        i8::try_from(cli.verbose).unwrap_or(i8::MAX)
+    };
+
+    let (run, listen, watch, statement, validate, convert) = match cli.command {
+        Command::Process(run) => (run, None, None, None, false, None),
+        Command::Serve { listen, watch, run } => (run, listen, watch, None, false, None),
+        Command::Validate(run) => (run, None, None, None, true, None),
+        Command::Statement { client, run } => (run, None, None, Some(client), false, None),
+        Command::Convert { to, run } => {
+            let to = convert_format(&to).map_err(|e| usage_error(ErrorKind::InvalidValue, e))?;
+            (run, None, None, None, false, Some(to))
+        }
+        Command::Generate(generate) => {
+            let config = GeneratorConfig {
+                clients: generate.clients,
+                transactions: generate.transactions,
+                dispute_ratio: generate.dispute_ratio,
+                invalid_ratio: generate.invalid_ratio,
+                seed: generate.seed,
+            };
+            config
+                .validate()
+                .map_err(|e| usage_error(ErrorKind::ValueValidation, e))?;
+            let mut args = resolve(RunArgs::default(), None, None, None, false, verbosity)?;
+            args.output = generate.output;
+            args.generate = Some(config);
+            return Ok(args);
+        }
+        Command::Diff {
+            expected,
+            actual,
+            output,
+        } => {
+            let mut args = resolve(RunArgs::default(), None, None, None, false, verbosity)?;
+            args.output = output;
+            args.diff = Some((expected, actual));
+            return Ok(args);
+        }
+    };
+    if run.inputs.is_empty() && listen.is_none() && watch.is_none() {
+        return Err(usage_error(
+            ErrorKind::MissingRequiredArgument,
+            "missing input file",
+        ));
+    }
+    let mut args = resolve(run, listen, watch, statement, validate, verbosity)?;
+    args.convert = convert;
+    Ok(args)
+}
+
+/// The format `convert --to` names, if records can be written in it.
+fn convert_format(to: &str) -> Result<InputFormat, String> {
+    match to.parse()? {
+        format @ (InputFormat::Csv | InputFormat::JsonLines) => Ok(format),
+        #[cfg(feature = "msgpack")]
+        InputFormat::MessagePack => Ok(InputFormat::MessagePack),
+        #[cfg(feature = "protobuf")]
+        InputFormat::Protobuf => Ok(InputFormat::Protobuf),
+        _ => Err(format!(
+            "can't convert records to '{}' (csv, jsonl, msgpack or protobuf)",
+            to
+        )),
+    }
+}
+
+/// Merges the options of a run with its `--config` file and checks that
+/// they can be combined.
+fn resolve(
+    run: RunArgs,
+    listen: Option<String>,
+    watch: Option<String>,
+    statement: Option<ClientId>,
+    validate: bool,
+    verbosity: i8,
+) -> Result<Args, clap::Error> {
+    let conflict = |msg: &str| Err(usage_error(ErrorKind::ArgumentConflict, msg));
+    let invalid = |msg: String| usage_error(ErrorKind::InvalidValue, msg);
+    if run.inputs.iter().filter(|input| *input == "-").count() > 1 {
+        return Err(invalid("stdin ('-') can only be read once".to_string()));
+    }
+
+    let (mut engine, io) = match &run.config {
+        Some(path) => {
+            load_config(path).map_err(|e| invalid(format!("can't load {}: {}", path, e)))?
+        }
+        None => (EngineConfig::default(), IoConfig::default()),
+    };
+    let csv = csv_dialect(&run, &io).map_err(invalid)?;
+    let input_format: InputFormat =
+        parse_or_default(run.input_format.or(io.input_format)).map_err(invalid)?;
+    let tx_id_format: TxIdFormat =
+        parse_or_default(run.tx_id_format.or(io.tx_id_format)).map_err(invalid)?;
+    let output_format = match parse_or_default(run.output_format.or(io.output_format)) {
+        Ok(OutputFormat::Table { .. }) => OutputFormat::Table {
+            color: run.color || io.color,
+        },
+        Ok(_) if run.color || io.color => {
+            return conflict("--color requires --output-format table")
+        }
+        format => format.map_err(invalid)?,
+    };
+    let clients = run
+        .clients
+        .or(io.clients)
+        .map(|clients| clients.parse::<ClientSet>())
+        .transpose()
+        .map_err(invalid)?;
+    let shards = run.shards.or(io.shards).unwrap_or(NonZeroUsize::MIN);
+    let threads = run.threads.or(io.threads).unwrap_or(NonZeroUsize::MIN);
+    if threads.get() > 2 {
+        return Err(invalid(format!(
+            "invalid thread count '{}' (1 or 2)",
+            threads
+        )));
+    }
+    let tx_store_dir = run.tx_store_dir.or(io.tx_store_dir);
+    let compact_tx_store = run.compact_tx_store || io.compact_tx_store;
+    let results = run.results.or(io.results);
+    let settlement = run.settlement.or(io.settlement);
+    let risk_report = run.risk_report.or(io.risk_report);
+    let expired_disputes = run.expired_disputes.or(io.expired_disputes);
+    let verify = run.verify.or(io.verify);
+    let audit_log = run.audit_log.or(io.audit_log);
+    let account_store = run.account_store.or(io.account_store);
+    let wal = run.wal.or(io.wal);
+    let checkpoint = run.checkpoint.or(io.checkpoint);
+    let checkpoint_every = run.checkpoint_every.or(io.checkpoint_every);
+    let error_policy = if run.strict || io.strict {
+        ErrorPolicy::FailFast
+    } else {
+        ErrorPolicy::Skip
+    };
+    engine.overdraft_limit = run.overdraft_limit.unwrap_or(engine.overdraft_limit);
+    engine.interest_rate = run.interest_rate.or(engine.interest_rate);
+    engine.interest_period_days = run.interest_period.or(engine.interest_period_days);
+    engine.dispute_window_days = run.dispute_window.or(engine.dispute_window_days);
+    engine.dispute_max_age_days = run.dispute_max_age.or(engine.dispute_max_age_days);
+    engine.expired_dispute_action = run
+        .expired_dispute_action
+        .unwrap_or(engine.expired_dispute_action);
+    engine.idempotency_retention_days = run
+        .idempotency_retention
+        .or(engine.idempotency_retention_days);
+    engine.duplicate_tx = run.duplicate_tx.unwrap_or(engine.duplicate_tx);
+    engine.amount_precision = run.amount_precision.unwrap_or(engine.amount_precision);
+    engine.suspense_account = run.suspense_account.or(engine.suspense_account);
+    engine.max_amount = run.max_amount.or(engine.max_amount);
+    engine.max_daily_withdrawal = run.max_daily_withdrawal.or(engine.max_daily_withdrawal);
+    engine.max_transactions = run.max_transactions.or(engine.max_transactions);
+    engine.velocity_window_seconds = run.velocity_window.or(engine.velocity_window_seconds);
+    if run.interest_period.is_some() && engine.interest_rate.is_none() {
+        return Err(usage_error(
+            ErrorKind::MissingRequiredArgument,
+            "--interest-period requires --interest-rate",
+        ));
+    }
+    if run.velocity_window.is_some() && engine.max_transactions.is_none() {
+        return Err(usage_error(
+            ErrorKind::MissingRequiredArgument,
+            "--velocity-window requires --max-transactions",
+        ));
+    }
+    if engine.dispute_max_age_days.is_none() {
+        let given = [
+            (
+                run.expired_dispute_action.is_some(),
+                "--expired-dispute-action",
+            ),
+            (expired_disputes.is_some(), "--expired-disputes"),
+        ];
+        if let Some((_, flag)) = given.iter().find(|(given, _)| *given) {
+            return Err(usage_error(
+                ErrorKind::MissingRequiredArgument,
+                format!("{} requires --dispute-max-age", flag),
+            ));
+        }
+    }
+
+    if checkpoint_every.is_some() && checkpoint.is_none() {
+        return Err(usage_error(
+            ErrorKind::MissingRequiredArgument,
+            "--checkpoint-every requires --checkpoint",
+        ));
+    }
+
+    let layout = run.layout.or(io.layout);
+    if input_format == InputFormat::FixedWidth && layout.is_none() {
+        return Err(usage_error(
+            ErrorKind::MissingRequiredArgument,
+            "--input-format fixed requires --layout",
+        ));
+    }
+
+    if tx_id_format != TxIdFormat::Numeric && wal.is_some() {
+        // The log holds mapped ids, which a restart would assign differently.
+        return conflict("--tx-id-format can't be combined with --wal");
+    }
+    if tx_id_format != TxIdFormat::Numeric && listen.is_some() {
+        return conflict("--tx-id-format can't be combined with --listen");
+    }
+    if output_format.is_binary() && listen.is_some() {
+        // Protocol answers are lines.
+        return conflict("--output-format arrow, avro or parquet can't be combined with --listen");
+    }
+    if !input_format.is_lines() && listen.is_some() {
+        return conflict("only csv and jsonl input can be combined with --listen");
+    }
+    if csv != CsvDialect::default() && listen.is_some() {
+        // Requests are told from queries by their commas.
+        return conflict(
+            "CSV dialect settings (--delimiter, --quote, --escape, --header-alias, --no-header, --capture-metadata, --type-alias, --strict-types and --amount-format) can't be combined with --listen",
+        );
+    }
+    if compact_tx_store && tx_store_dir.is_some() {
+        return conflict("--compact-tx-store can't be combined with --tx-store-dir");
+    }
+    if verify.is_some() && (listen.is_some() || watch.is_some()) {
+        // A served engine's accounts never reach a final state.
+        return conflict("--verify can't be combined with serve");
+    }
+    if listen.is_some() && shards.get() > 1 {
+        // Connections need one engine to apply their records to and query.
+        return conflict("--listen can't be combined with --shards");
+    }
+    if watch.is_some() && shards.get() > 1 {
+        return conflict("--watch can't be combined with --shards");
+    }
+    if results.is_some() && shards.get() > 1 {
+        // Like the audit log, the results follow the single input order.
+        return conflict("--results can't be combined with --shards");
+    }
+    if audit_log.is_some() && shards.get() > 1 {
+        // Shards apply mutations concurrently, so there's no single order to log.
+        return conflict("--audit-log can't be combined with --shards");
+    }
+    if account_store.is_some() && shards.get() > 1 {
+        // Each shard would need its own store and couldn't load the others' accounts.
+        return conflict("--account-store can't be combined with --shards");
+    }
+    if wal.is_some() && shards.get() > 1 {
+        // Like the audit log, the log needs a single order of applied records.
+        return conflict("--wal can't be combined with --shards");
+    }
+    if wal.is_some() && account_store.is_some() {
+        // Replaying the log on top of stored balances would apply it twice.
+        return conflict("--wal can't be combined with --account-store");
+    }
+    if clients.is_some() {
+        // State left by a run over some clients would be wrong for the rest.
+        let others = [
+            (wal.is_some(), "--wal"),
+            (account_store.is_some(), "--account-store"),
+            (checkpoint.is_some(), "--checkpoint"),
+            (listen.is_some() || watch.is_some(), "serve"),
+        ];
+        if let Some((_, other)) = others.iter().find(|(given, _)| *given) {
+            return conflict(&format!("--clients can't be combined with {}", other));
+        }
+    }
+    if checkpoint.is_some() {
+        // A resumed run starts from the checkpoint's state, so nothing else
+        // may keep state or write records from the start of the input.
+        let others = [
+            (shards.get() > 1, "--shards"),
+            (wal.is_some(), "--wal"),
+            (account_store.is_some(), "--account-store"),
+            (audit_log.is_some(), "--audit-log"),
+            (results.is_some(), "--results"),
+            (settlement.is_some(), "--settlement"),
+            (listen.is_some(), "--listen"),
+            (watch.is_some(), "--watch"),
+            (statement.is_some(), "statement"),
+            (validate, "validate"),
+        ];
+        if let Some((_, other)) = others.iter().find(|(given, _)| *given) {
+            return conflict(&format!("--checkpoint can't be combined with {}", other));
+        }
+    }
+    Ok(Args {
+        inputs: run.inputs,
+        merge_by_timestamp: run.merge_by_timestamp || io.merge_by_timestamp,
+        reorder_window: run.reorder_window.or(io.reorder_window),
+        clients,
+        input_format,
+        tx_id_format,
+        output_format,
+        skip_empty: run.skip_empty || io.skip_empty,
+        output: run.output.or(io.output),
+        shards,
+        threads,
+        tx_store_dir,
+        compact_tx_store,
+        rejects: run.rejects.or(io.rejects),
+        results,
+        settlement,
+        risk_report,
+        expired_disputes,
+        verify,
+        audit_log,
+        account_store,
+        wal,
+        checkpoint,
+        checkpoint_every: checkpoint_every.unwrap_or(DEFAULT_CHECKPOINT_INTERVAL),
+        listen,
+        watch,
+        rates: run.rates.or(io.rates),
+        accounts: run.accounts.or(io.accounts),
+        blocklist: run.blocklist.or(io.blocklist),
+        clients_file: run.clients_file.or(io.clients_file),
+        layout,
+        csv,
+        engine,
+        error_policy,
+        reconcile: run.reconcile || io.reconcile,
+        check_invariants: run.check_invariants || io.check_invariants,
+        statement,
+        validate,
+        generate: None,
+        convert: None,
+        diff: None,
+        stats: run.stats,
+        verbosity,
+    })
+}
+
+/// An error reported like clap's own, followed by the usage.
+fn usage_error(kind: ErrorKind, msg: impl std::fmt::Display) -> clap::Error {
+    Cli::command().error(kind, msg)
+}
+
+/// The CSV dialect of the flags, falling back to the `[io]` settings. Header
+/// and type aliases of both apply, the flags' last.
+fn csv_dialect(run: &RunArgs, io: &IoConfig) -> Result<CsvDialect, String> {
+    let mut dialect = CsvDialect::new();
+    if let Some(delimiter) = run.delimiter.as_ref().or(io.delimiter.as_ref()) {
+        dialect = dialect.with_delimiter(csv_char(delimiter)?);
+    }
+    match run.quote.as_ref().or(io.quote.as_ref()).map(String::as_str) {
+        Some("none") => dialect = dialect.with_quote(None),
+        Some(quote) => dialect = dialect.with_quote(Some(csv_char(quote)?)),
+        None => {}
+    }
+    if let Some(escape) = run.escape.as_ref().or(io.escape.as_ref()) {
+        dialect = dialect.with_escape(Some(csv_char(escape)?));
+    }
+    if run.no_header || io.no_header {
+        dialect = dialect.without_header();
+    }
+    if run.capture_metadata || io.capture_metadata {
+        dialect = dialect.with_metadata();
+    }
+    if run.strict_types || io.strict_types {
+        dialect = dialect.with_strict_types();
+    }
+    if let Some(format) = run.amount_format.as_ref().or(io.amount_format.as_ref()) {
+        dialect = dialect.with_amount_format(format.parse()?);
+    }
+    let flags = run.type_aliases.iter().map(|alias| {
+        alias
+            .split_once('=')
+            .ok_or_else(|| format!("invalid type alias '{}' (expected NAME=TYPE)", alias))
+    });
+    let config = io
+        .type_aliases
+        .iter()
+        .map(|(name, record_type)| Ok((name.as_str(), record_type.as_str())));
+    for alias in config.chain(flags) {
+        let (name, record_type) = alias?;
+        dialect = dialect.with_type_alias(name, record_type.trim().parse()?);
+    }
+    let flags = run.header_aliases.iter().map(|alias| {
+        alias
+            .split_once('=')
+            .ok_or_else(|| format!("invalid header alias '{}' (expected NAME=COLUMN)", alias))
+    });
+    let config = io
+        .header_aliases
+        .iter()
+        .map(|(name, column)| Ok((name.as_str(), column.as_str())));
+    for alias in config.chain(flags) {
+        let (name, column) = alias?;
+        dialect = dialect
+            .with_alias(name, column.trim())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(dialect)
+}
+
+/// A single ASCII character, or `tab`.
+fn csv_char(value: &str) -> Result<u8, String> {
+    match value.as_bytes() {
+        _ if value.eq_ignore_ascii_case("tab") || value == "\\t" => Ok(b'\t'),
+        [c] if c.is_ascii() => Ok(*c),
+        _ => Err(format!(
+            "invalid CSV character '{}' (expected one ASCII character)",
+            value
+        )),
+    }
+}
+
+fn parse_shards(value: &str) -> Result<NonZeroUsize, String> {
+    value
+        .parse()
+        .map_err(|_| "expected a positive number".to_string())
+}
+
+fn parse_threads(value: &str) -> Result<NonZeroUsize, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|threads: &NonZeroUsize| threads.get() <= 2)
+        .ok_or_else(|| "expected 1 or 2".to_string())
+}
+
+fn parse_non_negative(value: &str) -> Result<Decimal, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|amount: &Decimal| !amount.is_sign_negative())
+        .ok_or_else(|| "expected a non-negative number".to_string())
+}
+
+fn parse_period(value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|days: &u32| *days > 0)
+        .ok_or_else(|| "expected a positive number of days".to_string())
+}
+
+fn parse_count(value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|count: &u32| *count > 0)
+        .ok_or_else(|| "expected a positive number".to_string())
+}
+
+fn parse_window(value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|seconds: &u64| *seconds > 0)
+        .ok_or_else(|| "expected a positive number of seconds".to_string())
+}
+
+fn parse_days(value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|days: &u64| days.checked_mul(86_400).is_some())
+        .ok_or_else(|| "expected a number of days".to_string())
+}
+
+/// The `[io]` table of a `--config` file. Each setting is named after the
+/// flag it stands in for, which overrides it when given.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct IoConfig {
+    merge_by_timestamp: bool,
+    reorder_window: Option<u64>,
+    clients: Option<String>,
+    input_format: Option<String>,
+    tx_id_format: Option<String>,
+    output_format: Option<String>,
+    color: bool,
+    skip_empty: bool,
+    output: Option<String>,
+    shards: Option<NonZeroUsize>,
+    threads: Option<NonZeroUsize>,
+    tx_store_dir: Option<String>,
+    compact_tx_store: bool,
+    rejects: Option<String>,
+    results: Option<String>,
+    settlement: Option<String>,
+    risk_report: Option<String>,
+    expired_disputes: Option<String>,
+    verify: Option<String>,
+    audit_log: Option<String>,
+    account_store: Option<String>,
+    wal: Option<String>,
+    checkpoint: Option<String>,
+    checkpoint_every: Option<NonZeroU64>,
+    rates: Option<String>,
+    accounts: Option<String>,
+    blocklist: Option<String>,
+    clients_file: Option<String>,
+    layout: Option<String>,
+    delimiter: Option<String>,
+    quote: Option<String>,
+    escape: Option<String>,
+    header_aliases: BTreeMap<String, String>,
+    no_header: bool,
+    capture_metadata: bool,
+    type_aliases: BTreeMap<String, String>,
+    strict_types: bool,
+    amount_format: Option<String>,
+    strict: bool,
+    reconcile: bool,
+    check_invariants: bool,
+}
+
+/// Reads a `--config` file: engine policies at the top level, I/O settings
+/// in an `[io]` table.
+fn load_config(path: &str) -> Result<(EngineConfig, IoConfig), PaymentError> {
+    let invalid = |e: serde_json::Error| PaymentError::InvalidConfig(e.to_string());
+    let mut table: Map<String, Value> = config::read_file(path)?;
+    let io = match table.remove("io") {
+        Some(io) => serde_json::from_value(io).map_err(invalid)?,
+        None => IoConfig::default(),
+    };
+    let engine: EngineConfig = serde_json::from_value(Value::Object(table)).map_err(invalid)?;
+    engine.validate()?;
+    Ok((engine, io))
+}
+
+fn parse_or_default<T: Default + FromStr<Err = String>>(
+    value: Option<String>,
+) -> Result<T, String> {
+    value.map_or_else(|| Ok(T::default()), |value| value.parse())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use payment_engine::{
+        AmountFormat, AmountPrecision, DuplicateTxPolicy, InterestSchedule, Limits, TransactionType,
+    };
+    use rstest::rstest;
+    use std::io::Write;
+    use std::time::Duration;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        // The first line of clap's report, without the usage that follows.
+        parse_args(args.iter().map(|a| a.to_string())).map_err(|e| {
+            let report = e.to_string();
+            let line = report.lines().next().unwrap_or_default();
+            line.trim_start_matches("error: ").to_string()
+        })
+    }
+
+    #[rstest]
+    fn test_parse_args_defaults() {
+        let args = parse(&["input.csv"]).unwrap();
+        assert_eq!(args.inputs, ["input.csv"]);
+        assert_eq!(args.input_format, InputFormat::Csv);
+        assert_eq!(args.output_format, OutputFormat::Csv);
+        assert_eq!(args.output, None);
+        assert_eq!(args.shards.get(), 1);
+        assert_eq!(args.error_policy, ErrorPolicy::Skip);
+        assert!(!args.stats);
+        assert_eq!(args.statement, None);
+        assert!(!args.validate);
+        assert_eq!(args.generate, None);
+        assert_eq!(args.verbosity, 0);
+        assert_eq!(args.engine, EngineConfig::default());
+    }
+
+    #[rstest]
+    fn test_parse_args_validate() {
+        let args = parse(&["validate", "--account-store", "accounts.db", "a.csv"]).unwrap();
+        assert!(args.validate);
+        assert_eq!(args.inputs, ["a.csv"]);
+        assert_eq!(args.account_store, Some("accounts.db".to_string()));
+    }
+
+    #[rstest]
+    fn test_parse_args_generate() {
+        let args = parse(&[
+            "generate",
+            "--clients",
+            "10",
+            "--transactions",
+            "500",
+            "--dispute-ratio",
+            "0.1",
+            "--invalid-ratio",
+            "0.02",
+            "--seed",
+            "42",
+            "-o",
+            "load.csv",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.generate,
+            Some(GeneratorConfig {
+                clients: 10,
+                transactions: 500,
+                dispute_ratio: 0.1,
+                invalid_ratio: 0.02,
+                seed: 42,
+            })
+        );
+        assert!(args.inputs.is_empty());
+        assert_eq!(args.output, Some("load.csv".to_string()));
+        assert_eq!(
+            parse(&["generate"]).unwrap().generate,
+            Some(GeneratorConfig::default())
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_convert() {
+        let args = parse(&["convert", "--to", "jsonl", "-o", "a.jsonl", "a.csv"]).unwrap();
+        assert_eq!(args.convert, Some(InputFormat::JsonLines));
+        assert_eq!(args.inputs, ["a.csv"]);
+        assert_eq!(args.output, Some("a.jsonl".to_string()));
+        assert_eq!(parse(&["a.csv"]).unwrap().convert, None);
+
+        let err = parse(&["convert", "--to", "ofx", "a.csv"]).unwrap_err();
+        assert!(err.to_string().contains("can't convert records to 'ofx'"));
+        assert!(parse(&["convert", "a.csv"]).is_err());
+    }
+
+    #[rstest]
+    fn test_parse_args_diff() {
+        let args = parse(&["diff", "golden.csv", "new.csv", "-o", "diff.csv"]).unwrap();
+        assert_eq!(
+            args.diff,
+            Some(("golden.csv".to_string(), "new.csv".to_string()))
+        );
+        assert_eq!(args.output, Some("diff.csv".to_string()));
+        assert_eq!(parse(&["a.csv"]).unwrap().diff, None);
+        assert!(parse(&["diff", "golden.csv"]).is_err());
+    }
+
+    #[rstest]
+    fn test_parse_args_rejects() {
+        let args = parse(&["--rejects", "rejects.csv", "a.csv"]).unwrap();
+        assert_eq!(args.inputs, ["a.csv"]);
+        assert_eq!(args.rejects, Some("rejects.csv".to_string()));
+    }
+
+    #[rstest]
+    #[case(&["--interest-rate", "2.5"], Some(InterestSchedule::new(Decimal::new(25, 1))))]
+    #[case(
+        &["--interest-rate", "2.5", "--interest-period", "7"],
+        Some(InterestSchedule::new(Decimal::new(25, 1)).with_period_days(7))
+    )]
+    #[case(&[], None)]
+    fn test_parse_args_interest(
+        #[case] flags: &[&str],
+        #[case] expected: Option<InterestSchedule>,
+    ) {
+        let mut args = flags.to_vec();
+        args.push("a.csv");
+        assert_eq!(parse(&args).unwrap().engine.interest(), expected);
+    }
+
+    #[rstest]
+    fn test_parse_args_dispute_window() {
+        let args = parse(&["--dispute-window", "90", "a.csv"]).unwrap();
+        assert_eq!(
+            args.engine.dispute_window(),
+            Some(Duration::from_secs(90 * 86_400))
+        );
+        assert_eq!(parse(&["a.csv"]).unwrap().engine.dispute_window(), None);
+    }
+
+    #[rstest]
+    fn test_parse_args_dispute_max_age() {
+        let args = parse(&[
+            "--dispute-max-age",
+            "45",
+            "--expired-dispute-action",
+            "chargeback",
+            "--expired-disputes",
+            "expired.csv",
+            "a.csv",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.engine.dispute_max_age(),
+            Some(Duration::from_secs(45 * 86_400))
+        );
+        assert_eq!(
+            args.engine.expired_dispute_action,
+            ExpiredDisputeAction::Chargeback
+        );
+        assert_eq!(args.expired_disputes, Some("expired.csv".to_string()));
+        let args = parse(&["a.csv"]).unwrap();
+        assert_eq!(args.engine.dispute_max_age(), None);
+        assert_eq!(
+            args.engine.expired_dispute_action,
+            ExpiredDisputeAction::Resolve
+        );
+
+        let (_dir, path) = config_file("engine.toml", "dispute_max_age_days = 45\n");
+        let args = parse(&["--config", &path, "--expired-disputes", "x.json", "a.csv"]).unwrap();
+        assert_eq!(
+            args.engine.dispute_max_age(),
+            Some(Duration::from_secs(45 * 86_400))
+        );
+        assert_eq!(
+            parse(&["--expired-dispute-action", "resolve", "a.csv"]).unwrap_err(),
+            "--expired-dispute-action requires --dispute-max-age"
+        );
+        assert_eq!(
+            parse(&["--expired-disputes", "x.csv", "a.csv"]).unwrap_err(),
+            "--expired-disputes requires --dispute-max-age"
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_idempotency_retention() {
+        let args = parse(&["--idempotency-retention", "7", "a.csv"]).unwrap();
+        assert_eq!(
+            args.engine.idempotency_retention(),
+            Some(Duration::from_secs(7 * 86_400))
+        );
+        assert_eq!(
+            parse(&["a.csv"]).unwrap().engine.idempotency_retention(),
+            None
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_amount_precision() {
+        let args = parse(&["--amount-precision", "round-half-even", "a.csv"]).unwrap();
+        assert_eq!(args.engine.amount_precision, AmountPrecision::RoundHalfEven);
+        assert_eq!(
+            parse(&["a.csv"]).unwrap().engine.amount_precision,
+            AmountPrecision::Reject
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_duplicate_tx() {
+        let args = parse(&["--duplicate-tx", "warn", "a.csv"]).unwrap();
+        assert_eq!(args.engine.duplicate_tx, DuplicateTxPolicy::Warn);
+        assert_eq!(
+            parse(&["a.csv"]).unwrap().engine.duplicate_tx,
+            DuplicateTxPolicy::Ignore
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_rates() {
+        let args = parse(&["--rates", "rates.csv", "a.csv"]).unwrap();
+        assert_eq!(args.inputs, ["a.csv"]);
+        assert_eq!(args.rates, Some("rates.csv".to_string()));
+    }
+
+    #[rstest]
+    fn test_parse_args_blocklist() {
+        let args = parse(&["--blocklist", "blocked.txt", "a.csv"]).unwrap();
+        assert_eq!(args.blocklist, Some("blocked.txt".to_string()));
+        let (_dir, path) = config_file("engine.toml", "[io]\nblocklist = \"sanctions.txt\"\n");
+        let args = parse(&["--config", &path, "a.csv"]).unwrap();
+        assert_eq!(args.blocklist, Some("sanctions.txt".to_string()));
+    }
+
+    #[rstest]
+    fn test_parse_args_clients_file() {
+        let args = parse(&["--clients-file", "clients.csv", "a.csv"]).unwrap();
+        assert_eq!(args.clients_file, Some("clients.csv".to_string()));
+        assert_eq!(args.clients, None);
+    }
+
+    #[rstest]
+    fn test_parse_args_pain001() {
+        let args = parse(&[
+            "--input-format",
+            "pain001",
+            "--accounts",
+            "accounts.csv",
+            "a.xml",
+        ])
+        .unwrap();
+        assert_eq!(args.input_format, InputFormat::Pain001);
+        assert_eq!(args.accounts, Some("accounts.csv".to_string()));
+        assert_eq!(
+            parse(&["serve", "--listen", ":7000", "--input-format", "pain001"]).unwrap_err(),
+            "only csv and jsonl input can be combined with --listen"
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_layout() {
+        let args = parse(&[
+            "--input-format",
+            "fixed",
+            "--layout",
+            "layout.toml",
+            "a.txt",
+        ])
+        .unwrap();
+        assert_eq!(args.input_format, InputFormat::FixedWidth);
+        assert_eq!(args.layout, Some("layout.toml".to_string()));
+        assert_eq!(
+            parse(&["--input-format", "fixed", "a.txt"]).unwrap_err(),
+            "--input-format fixed requires --layout"
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_csv_dialect() {
+        let args = parse(&[
+            "--delimiter",
+            ";",
+            "--quote",
+            "none",
+            "--header-alias",
+            "txn_id=tx",
+            "--header-alias",
+            "customer=client",
+            "a.csv",
+        ])
+        .unwrap();
+        let expected = CsvDialect::new()
+            .with_delimiter(b';')
+            .with_quote(None)
+            .with_alias("txn_id", "tx")
+            .and_then(|dialect| dialect.with_alias("customer", "client"))
+            .unwrap();
+        assert_eq!(args.csv, expected);
+        assert_eq!(
+            parse(&["--delimiter", "tab", "a.csv"]).unwrap().csv,
+            CsvDialect::new().with_delimiter(b'\t')
+        );
+        assert_eq!(
+            parse(&["--delimiter", ";;", "a.csv"]).unwrap_err(),
+            "invalid CSV character ';;' (expected one ASCII character)"
+        );
+        assert_eq!(
+            parse(&["--header-alias", "txn_id", "a.csv"]).unwrap_err(),
+            "invalid header alias 'txn_id' (expected NAME=COLUMN)"
+        );
+        assert_eq!(
+            parse(&["serve", "--listen", ":7000", "--delimiter", ";"]).unwrap_err(),
+            "CSV dialect settings (--delimiter, --quote, --escape, --header-alias, --no-header, --capture-metadata, --type-alias, --strict-types and --amount-format) can't be combined with --listen"
+        );
+
+        let (_dir, path) = config_file(
+            "engine.toml",
+            "[io]\ndelimiter = \"|\"\nheader_aliases = { txn_id = \"tx\", memo = \"client\" }\n",
+        );
+        let args = parse(&["--config", &path, "--header-alias", "memo=amount", "a.csv"]).unwrap();
+        let expected = CsvDialect::new()
+            .with_delimiter(b'|')
+            .with_alias("txn_id", "tx")
+            .and_then(|dialect| dialect.with_alias("memo", "amount"))
+            .unwrap();
+        assert_eq!(args.csv, expected);
+        let err = parse(&["--header-alias", "memo=note", "a.csv"]).unwrap_err();
+        assert!(err.contains("unknown column 'note'"), "{}", err);
+    }
+
+    #[rstest]
+    fn test_parse_args_no_header() {
+        let args = parse(&["--no-header", "a.csv"]).unwrap();
+        assert_eq!(args.csv, CsvDialect::new().without_header());
+        assert_eq!(parse(&["a.csv"]).unwrap().csv, CsvDialect::new());
+    }
+
+    #[rstest]
+    fn test_parse_args_type_aliases() {
+        let (_dir, path) = config_file(
+            "engine.toml",
+            "[io]\nstrict_types = true\ntype_aliases = { payout = \"withdrawal\" }\n",
+        );
+        let args = parse(&["--config", &path, "--type-alias", "topup=deposit", "a.csv"]).unwrap();
+        let expected = CsvDialect::new()
+            .with_strict_types()
+            .with_type_alias("payout", TransactionType::Withdrawal)
+            .with_type_alias("topup", TransactionType::Deposit);
+        assert_eq!(args.csv, expected);
+        assert_eq!(
+            parse(&["--type-alias", "payout=send", "a.csv"]).unwrap_err(),
+            "unknown transaction type 'send'"
+        );
+        assert_eq!(
+            parse(&["--type-alias", "payout", "a.csv"]).unwrap_err(),
+            "invalid type alias 'payout' (expected NAME=TYPE)"
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_amount_format() {
+        let args = parse(&["--amount-format", "eu", "a.csv"]).unwrap();
+        let expected = CsvDialect::new().with_amount_format(AmountFormat::European);
+        assert_eq!(args.csv, expected);
+        let (_dir, path) = config_file("engine.toml", "[io]\namount_format = \"us\"\n");
+        let args = parse(&["--config", &path, "a.csv"]).unwrap();
+        assert_eq!(
+            args.csv,
+            CsvDialect::new().with_amount_format(AmountFormat::Us)
+        );
+        assert_eq!(
+            parse(&["--amount-format", "swiss", "a.csv"]).unwrap_err(),
+            "unknown amount format 'swiss'"
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_capture_metadata() {
+        let args = parse(&["--capture-metadata", "a.csv"]).unwrap();
+        assert_eq!(args.csv, CsvDialect::new().with_metadata());
+        let err = parse(&["serve", "--listen", ":7000", "--capture-metadata"]).unwrap_err();
+        assert!(err.contains("--capture-metadata"), "{}", err);
+    }
+
+    #[rstest]
+    fn test_parse_args_statement() {
+        let args = parse(&["statement", "42", "--output-format", "json", "a.csv"]).unwrap();
+        assert_eq!(args.statement, Some(42));
+        assert_eq!(args.inputs, ["a.csv"]);
+        assert_eq!(args.output_format, OutputFormat::Json);
+    }
+
+    #[rstest]
+    fn test_parse_args_audit_log() {
+        let args = parse(&["a.csv", "--audit-log", "audit.csv"]).unwrap();
+        assert_eq!(args.audit_log, Some("audit.csv".to_string()));
+    }
+
+    #[rstest]
+    fn test_parse_args_account_store() {
+        let args = parse(&["a.csv", "--account-store", "accounts.db"]).unwrap();
+        assert_eq!(args.account_store, Some("accounts.db".to_string()));
+    }
+
+    #[rstest]
+    fn test_parse_args_wal() {
+        let args = parse(&["a.csv", "--wal", "engine.wal"]).unwrap();
+        assert_eq!(args.wal, Some("engine.wal".to_string()));
+    }
+
+    #[rstest]
+    fn test_parse_args_results() {
+        let args = parse(&["a.csv", "--results", "results.csv"]).unwrap();
+        assert_eq!(args.results, Some("results.csv".to_string()));
+        assert_eq!(parse(&["a.csv"]).unwrap().results, None);
+    }
+
+    #[rstest]
+    fn test_parse_args_listen() {
+        let args = parse(&["serve", "--listen", "unix:/tmp/engine.sock"]).unwrap();
+        assert!(args.inputs.is_empty());
+        assert_eq!(args.listen, Some("unix:/tmp/engine.sock".to_string()));
+        let args = parse(&["serve", "a.csv", "--listen", "127.0.0.1:7000"]).unwrap();
+        assert_eq!(args.inputs, ["a.csv"]);
+    }
+
+    #[rstest]
+    fn test_parse_args_default_subcommand() {
+        let explicit = parse(&["process", "-v", "a.csv", "--stats"]).unwrap();
+        let implicit = parse(&["-v", "a.csv", "--stats"]).unwrap();
+        assert_eq!(explicit.inputs, implicit.inputs);
+        assert_eq!(explicit.verbosity, implicit.verbosity);
+        assert_eq!(explicit.stats, implicit.stats);
+        // A file named like a subcommand needs the explicit form.
+        assert_eq!(parse(&["process", "serve"]).unwrap().inputs, ["serve"]);
+    }
+
+    #[rstest]
+    #[case(&["--help"], ErrorKind::DisplayHelp)]
+    #[case(&["serve", "-h"], ErrorKind::DisplayHelp)]
+    #[case(&["--version"], ErrorKind::DisplayVersion)]
+    fn test_parse_args_help(#[case] args: &[&str], #[case] kind: ErrorKind) {
+        let err = parse_args(args.iter().map(|a| a.to_string())).unwrap_err();
+        assert_eq!(err.kind(), kind);
+    }
+
+    #[rstest]
+    fn test_parse_args_watch() {
+        let args = parse(&["serve", "--watch", "incoming", "--listen", ":7000"]).unwrap();
+        assert!(args.inputs.is_empty());
+        assert_eq!(args.watch, Some("incoming".to_string()));
+        assert_eq!(args.listen, Some(":7000".to_string()));
+    }
+
+    #[rstest]
+    fn test_parse_args_overdraft_limit() {
+        let args = parse(&["--overdraft-limit", "50.5", "a.csv"]).unwrap();
+        assert_eq!(args.engine.overdraft_limit, Decimal::new(505, 1));
+    }
+
+    #[rstest]
+    fn test_parse_args_config() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "duplicate_tx = \"error\"\noverdraft_limit = \"20\"\ninterest_rate = 1.5\ninterest_period_days = 7"
+        )
+        .unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let args = parse(&["--config", path, "a.csv"]).unwrap();
+        assert_eq!(args.engine.duplicate_tx, DuplicateTxPolicy::Error);
+        assert_eq!(args.engine.overdraft_limit, Decimal::new(20, 0));
+
+        // Flags override the file, wherever they appear.
+        let args = parse(&[
+            "--overdraft-limit",
+            "5",
+            "--config",
+            path,
+            "--interest-rate",
+            "3",
+            "a.csv",
+        ])
+        .unwrap();
+        assert_eq!(args.engine.duplicate_tx, DuplicateTxPolicy::Error);
+        assert_eq!(args.engine.overdraft_limit, Decimal::new(5, 0));
+        assert_eq!(
+            args.engine.interest(),
+            Some(InterestSchedule::new(Decimal::new(3, 0)).with_period_days(7))
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_config_errors() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "max_disputes = \"many\"").unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let err = parse(&["--config", path, "a.csv"]).unwrap_err();
+        assert!(err.starts_with(&format!("can't load {}: Invalid config: ", path)));
+        let err = parse(&["--config", "/nonexistent/engine.toml", "a.csv"]).unwrap_err();
+        assert!(err.starts_with("can't load /nonexistent/engine.toml: IO error"));
+    }
+
+    fn config_file(name: &str, contents: &str) -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        (dir, path.to_str().unwrap().to_string())
+    }
+
+    #[rstest]
+    #[case(
+        "engine.toml",
+        "amount_precision = \"truncate\"\n\
+         [io]\n\
+         input_format = \"jsonl\"\n\
+         output_format = \"json\"\n\
+         strict = true\n\
+         compact_tx_store = true\n\
+         shards = 2\n"
+    )]
+    #[case(
+        "engine.yaml",
+        "amount_precision: truncate\n\
+         io:\n  input_format: jsonl\n  output_format: json\n  strict: true\n  \
+         compact_tx_store: true\n  shards: 2\n"
+    )]
+    fn test_parse_args_config_io(#[case] name: &str, #[case] contents: &str) {
+        let (_dir, path) = config_file(name, contents);
+
+        let args = parse(&["--config", &path, "a.csv"]).unwrap();
+        assert_eq!(args.engine.amount_precision, AmountPrecision::Truncate);
+        assert_eq!(args.input_format, InputFormat::JsonLines);
+        assert_eq!(args.output_format, OutputFormat::Json);
+        assert_eq!(args.error_policy, ErrorPolicy::FailFast);
+        assert!(args.compact_tx_store);
+        assert_eq!(args.shards.get(), 2);
+
+        let args = parse(&[
+            "--config",
+            &path,
+            "--input-format",
+            "csv",
+            "--shards",
+            "1",
+            "a.csv",
+        ])
+        .unwrap();
+        assert_eq!(args.input_format, InputFormat::Csv);
+        assert_eq!(args.output_format, OutputFormat::Json);
+        assert_eq!(args.shards.get(), 1);
+    }
+
+    #[rstest]
+    #[case("[io]\ninput_format = \"xml\"", "unknown input format 'xml'")]
+    #[case("[io]\nthreads = 4", "invalid thread count '4' (1 or 2)")]
+    #[case(
+        "[io]\nshards = 2\nwal = \"engine.wal\"",
+        "--wal can't be combined with --shards"
+    )]
+    fn test_parse_args_config_io_errors(#[case] contents: &str, #[case] expected: &str) {
+        let (_dir, path) = config_file("engine.toml", contents);
+        assert_eq!(parse(&["--config", &path, "a.csv"]).unwrap_err(), expected);
+    }
+
+    #[rstest]
+    fn test_parse_args_config_unknown_io_setting() {
+        let (_dir, path) = config_file("engine.yml", "io:\n  listen: \":7000\"\n");
+        let err = parse(&["--config", &path, "a.csv"]).unwrap_err();
+        assert!(
+            err.contains("Invalid config: unknown field `listen`"),
+            "{}",
+            err
+        );
+    }
+
+    #[rstest]
+    #[case(&["-q", "a.csv"], -1)]
+    #[case(&["--quiet", "a.csv"], -1)]
+    #[case(&["-v", "a.csv"], 1)]
+    #[case(&["-vv", "a.csv", "--verbose"], 3)]
+    fn test_parse_args_verbosity(#[case] args: &[&str], #[case] expected: i8) {
+        let args = parse(args).unwrap();
+        assert_eq!(args.inputs, ["a.csv"]);
+        assert_eq!(args.verbosity, expected);
+    }
+
+    #[rstest]
+    fn test_parse_args_strict_and_stats() {
+        let args = parse(&["a.csv", "--strict", "--stats"]).unwrap();
+        assert_eq!(args.error_policy, ErrorPolicy::FailFast);
+        assert!(args.stats);
+    }
+
+    #[rstest]
+    fn test_parse_args_reconcile() {
+        assert!(parse(&["--reconcile", "a.csv"]).unwrap().reconcile);
+        assert!(!parse(&["a.csv"]).unwrap().reconcile);
+        let (_dir, path) = config_file("engine.toml", "[io]\nreconcile = true\n");
+        assert!(parse(&["--config", &path, "a.csv"]).unwrap().reconcile);
+    }
+
+    #[rstest]
+    fn test_parse_args_check_invariants() {
+        assert!(
+            parse(&["--check-invariants", "a.csv"])
+                .unwrap()
+                .check_invariants
+        );
+        assert!(!parse(&["a.csv"]).unwrap().check_invariants);
+        let (_dir, path) = config_file("engine.toml", "[io]\ncheck_invariants = true\n");
+        assert!(
+            parse(&["--config", &path, "a.csv"])
+                .unwrap()
+                .check_invariants
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_shards() {
+        let args = parse(&["--shards", "4", "a.csv"]).unwrap();
+        assert_eq!(args.shards.get(), 4);
+    }
+
+    #[rstest]
+    fn test_parse_args_threads() {
+        assert_eq!(parse(&["a.csv"]).unwrap().threads.get(), 1);
+        let args = parse(&["--threads", "2", "a.csv"]).unwrap();
+        assert_eq!(args.threads.get(), 2);
+    }
+
+    #[rstest]
+    fn test_parse_args_tx_store_dir() {
+        let args = parse(&["--tx-store-dir", "/tmp/store", "a.csv"]).unwrap();
+        assert_eq!(args.tx_store_dir, Some("/tmp/store".to_string()));
+    }
+
+    #[rstest]
+    fn test_parse_args_merge_by_timestamp() {
+        let args = parse(&["--merge-by-timestamp", "a.csv", "b.csv"]).unwrap();
+        assert!(args.merge_by_timestamp);
+        assert_eq!(args.inputs, ["a.csv", "b.csv"]);
+        assert!(!parse(&["a.csv"]).unwrap().merge_by_timestamp);
+        let (_dir, path) = config_file("engine.toml", "[io]\nmerge_by_timestamp = true\n");
+        assert!(
+            parse(&["--config", &path, "a.csv"])
+                .unwrap()
+                .merge_by_timestamp
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_reorder_window() {
+        let args = parse(&["--reorder-window", "30", "a.csv"]).unwrap();
+        assert_eq!(args.reorder_window, Some(30));
+        assert_eq!(parse(&["a.csv"]).unwrap().reorder_window, None);
+        assert!(parse(&["--reorder-window", "-1", "a.csv"]).is_err());
+        let (_dir, path) = config_file("engine.toml", "[io]\nreorder_window = 60\n");
+        let args = parse(&["--config", &path, "a.csv"]).unwrap();
+        assert_eq!(args.reorder_window, Some(60));
+    }
+
+    #[rstest]
+    fn test_parse_args_suspense_account() {
+        let args = parse(&["--suspense-account", "9999", "a.csv"]).unwrap();
+        assert_eq!(args.engine.suspense_account, Some(9999));
+        assert_eq!(parse(&["a.csv"]).unwrap().engine.suspense_account, None);
+        let (_dir, path) = config_file("engine.toml", "suspense_account = 1\n");
+        let args = parse(&["--config", &path, "a.csv"]).unwrap();
+        assert_eq!(args.engine.suspense_account, Some(1));
+        let args = parse(&["--config", &path, "--suspense-account", "2", "a.csv"]).unwrap();
+        assert_eq!(args.engine.suspense_account, Some(2));
+    }
+
+    #[rstest]
+    fn test_parse_args_limits() {
+        let args = parse(&[
+            "--max-amount",
+            "500",
+            "--max-daily-withdrawal",
+            "1000",
+            "--max-transactions",
+            "3",
+            "--velocity-window",
+            "60",
+            "a.csv",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.engine.limits(),
+            Some(
+                Limits::new()
+                    .with_max_amount(Decimal::from(500))
+                    .with_max_daily_withdrawal(Decimal::from(1000))
+                    .with_max_transactions(3, Duration::from_secs(60))
+            )
+        );
+        assert_eq!(parse(&["a.csv"]).unwrap().engine.limits(), None);
+        let (_dir, path) = config_file(
+            "engine.toml",
+            "max_transactions = 10
+",
+        );
+        let args = parse(&["--config", &path, "--velocity-window", "5", "a.csv"]).unwrap();
+        assert_eq!(
+            args.engine.limits(),
+            Some(Limits::new().with_max_transactions(10, Duration::from_secs(5)))
+        );
+        assert_eq!(
+            parse(&["--velocity-window", "60", "a.csv"]).unwrap_err(),
+            "--velocity-window requires --max-transactions"
+        );
+        assert!(parse(&["--max-transactions", "0", "a.csv"]).is_err());
+        assert!(parse(&["--max-amount", "-1", "a.csv"]).is_err());
+    }
+
+    #[rstest]
+    fn test_parse_args_settlement() {
+        let args = parse(&["--settlement", "settlement.csv", "a.csv"]).unwrap();
+        assert_eq!(args.settlement, Some("settlement.csv".to_string()));
+        assert_eq!(parse(&["a.csv"]).unwrap().settlement, None);
+        let (_dir, path) = config_file("engine.toml", "[io]\nsettlement = \"eod.json\"\n");
+        let args = parse(&["--config", &path, "a.csv"]).unwrap();
+        assert_eq!(args.settlement, Some("eod.json".to_string()));
+    }
+
+    #[rstest]
+    fn test_parse_args_risk_report() {
+        let args = parse(&["--risk-report", "risk.csv", "a.csv"]).unwrap();
+        assert_eq!(args.risk_report, Some("risk.csv".to_string()));
+        assert_eq!(parse(&["a.csv"]).unwrap().risk_report, None);
+        let (_dir, path) = config_file("engine.toml", "[io]\nrisk_report = \"risk.json\"\n");
+        let args = parse(&["--config", &path, "a.csv"]).unwrap();
+        assert_eq!(args.risk_report, Some("risk.json".to_string()));
+    }
+
+    #[rstest]
+    fn test_parse_args_verify() {
+        let args = parse(&["--verify", "golden.csv", "a.csv"]).unwrap();
+        assert_eq!(args.verify, Some("golden.csv".to_string()));
+        assert_eq!(parse(&["a.csv"]).unwrap().verify, None);
+        let (_dir, path) = config_file("engine.toml", "[io]\nverify = \"golden.csv\"\n");
+        let args = parse(&["--config", &path, "a.csv"]).unwrap();
+        assert_eq!(args.verify, Some("golden.csv".to_string()));
+        assert_eq!(
+            parse(&["serve", "--watch", "in", "--verify", "golden.csv"]).unwrap_err(),
+            "--verify can't be combined with serve"
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_checkpoint() {
+        let args = parse(&["--checkpoint", "run.ckpt", "a.csv"]).unwrap();
+        assert_eq!(args.checkpoint, Some("run.ckpt".to_string()));
+        assert_eq!(args.checkpoint_every, DEFAULT_CHECKPOINT_INTERVAL);
+        assert_eq!(parse(&["a.csv"]).unwrap().checkpoint, None);
+        let (_dir, path) = config_file(
+            "engine.toml",
+            "[io]\ncheckpoint = \"run.ckpt\"\ncheckpoint_every = 500\n",
+        );
+        let args = parse(&["--config", &path, "--checkpoint-every", "50", "a.csv"]).unwrap();
+        assert_eq!(args.checkpoint, Some("run.ckpt".to_string()));
+        assert_eq!(args.checkpoint_every.get(), 50);
+    }
+
+    #[rstest]
+    fn test_parse_args_compact_tx_store() {
+        let args = parse(&["--compact-tx-store", "a.csv"]).unwrap();
+        assert!(args.compact_tx_store);
+        assert!(!parse(&["a.csv"]).unwrap().compact_tx_store);
+        assert_eq!(
+            parse(&[
+                "--compact-tx-store",
+                "--tx-store-dir",
+                "/tmp/store",
+                "a.csv"
+            ])
+            .unwrap_err(),
+            "--compact-tx-store can't be combined with --tx-store-dir"
+        );
+    }
+
+    #[rstest]
+    #[case("--output")]
+    #[case("-o")]
+    fn test_parse_args_output(#[case] flag: &str) {
+        let args = parse(&[flag, "out.csv", "a.csv"]).unwrap();
+        assert_eq!(args.inputs, ["a.csv"]);
+        assert_eq!(args.output, Some("out.csv".to_string()));
+    }
+
+    #[rstest]
+    fn test_parse_args_multiple_inputs() {
+        let args = parse(&["day1.csv", "--shards", "2", "day2.csv", "-"]).unwrap();
+        assert_eq!(args.inputs, ["day1.csv", "day2.csv", "-"]);
+    }
+
+    #[rstest]
+    fn test_parse_args_clients() {
+        let args = parse(&["--clients", "1,5,100-200", "a.csv"]).unwrap();
+        assert_eq!(args.clients, Some("1,5,100-200".parse().unwrap()));
+        assert_eq!(parse(&["a.csv"]).unwrap().clients, None);
+        let (_dir, path) = config_file("engine.toml", "[io]\nclients = \"7\"\n");
+        let args = parse(&["--config", &path, "a.csv"]).unwrap();
+        assert_eq!(args.clients, Some("7".parse().unwrap()));
+
+        assert_eq!(
+            parse(&["--clients", "9-3", "a.csv"]).unwrap_err(),
+            "empty client range '9-3'"
+        );
+        assert_eq!(
+            parse(&["--clients", "1", "--wal", "run.wal", "a.csv"]).unwrap_err(),
+            "--clients can't be combined with --wal"
+        );
+        assert_eq!(
+            parse(&["serve", "--listen", "127.0.0.1:0", "--clients", "1"]).unwrap_err(),
+            "--clients can't be combined with serve"
+        );
+    }
+
+    #[rstest]
+    fn test_parse_args_skip_empty() {
+        assert!(parse(&["--skip-empty", "a.csv"]).unwrap().skip_empty);
+        assert!(!parse(&["a.csv"]).unwrap().skip_empty);
+        let (_dir, path) = config_file("engine.toml", "[io]\nskip_empty = true\n");
+        assert!(parse(&["--config", &path, "a.csv"]).unwrap().skip_empty);
+    }
+
+    #[rstest]
+    fn test_parse_args_input_format() {
+        let args = parse(&["--input-format", "jsonl", "-"]).unwrap();
+        assert_eq!(args.inputs, ["-"]);
+        assert_eq!(args.input_format, InputFormat::JsonLines);
+    }
+
+    #[rstest]
+    fn test_parse_args_tx_id_format() {
+        let args = parse(&["--tx-id-format", "uuid", "a.csv"]).unwrap();
+        assert_eq!(args.tx_id_format, TxIdFormat::Uuid);
+        assert_eq!(parse(&["a.csv"]).unwrap().tx_id_format, TxIdFormat::Numeric);
+    }
+
+    #[rstest]
+    fn test_parse_args_output_format() {
+        let args = parse(&["a.csv", "--output-format", "json"]).unwrap();
+        assert_eq!(args.inputs, ["a.csv"]);
+        assert_eq!(args.output_format, OutputFormat::Json);
+    }
+
+    #[rstest]
+    fn test_parse_args_table() {
+        let args = parse(&["a.csv", "--output-format", "table"]).unwrap();
+        assert_eq!(args.output_format, OutputFormat::Table { color: false });
+        let args = parse(&["a.csv", "--output-format", "table", "--color"]).unwrap();
+        assert_eq!(args.output_format, OutputFormat::Table { color: true });
+        let (_dir, path) = config_file(
+            "engine.toml",
+            "[io]\noutput_format = \"table\"\ncolor = true\n",
+        );
+        let args = parse(&["--config", &path, "a.csv"]).unwrap();
+        assert_eq!(args.output_format, OutputFormat::Table { color: true });
+        assert_eq!(
+            parse(&["a.csv", "--color"]).unwrap_err(),
+            "--color requires --output-format table"
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[rstest]
+    #[case("arrow", OutputFormat::Arrow)]
+    #[case("parquet", OutputFormat::Parquet)]
+    fn test_parse_args_columnar(#[case] format: &str, #[case] expected: OutputFormat) {
+        let args = parse(&["statement", "1", "a.csv", "--output-format", format]).unwrap();
+        assert_eq!(args.output_format, expected);
+        assert_eq!(
+            parse(&["serve", "--listen", ":7000", "--output-format", format]).unwrap_err(),
+            "--output-format arrow, avro or parquet can't be combined with --listen"
+        );
+    }
+
+    #[cfg(feature = "avro")]
+    #[rstest]
+    fn test_parse_args_avro() {
+        let args = parse(&[
+            "a.avro",
+            "--input-format",
+            "avro",
+            "--output-format",
+            "avro",
+        ])
+        .unwrap();
+        assert_eq!(args.input_format, InputFormat::Avro);
+        assert_eq!(args.output_format, OutputFormat::Avro);
+        assert_eq!(
+            parse(&["serve", "--listen", ":7000", "--input-format", "avro"]).unwrap_err(),
+            "only csv and jsonl input can be combined with --listen"
+        );
+    }
+
+    #[rstest]
+    #[case(&[], "missing input file")]
+    #[case(&["-", "a.csv", "-"], "stdin ('-') can only be read once")]
+    #[case(
+        &["--input-format"],
+        "a value is required for '--input-format <FORMAT>' but none was supplied"
+    )]
+    #[case(&["--input-format", "xml", "a.csv"], "unknown input format 'xml'")]
+    #[case(&["a.csv", "--output-format", "xml"], "unknown output format 'xml'")]
+    #[case(
+        &["a.csv", "--output"],
+        "a value is required for '--output <PATH>' but none was supplied"
+    )]
+    #[case(
+        &["a.csv", "--rejects"],
+        "a value is required for '--rejects <PATH>' but none was supplied"
+    )]
+    #[case(
+        &["a.csv", "--config"],
+        "a value is required for '--config <PATH>' but none was supplied"
+    )]
+    #[case(&["statement"], "the following required arguments were not provided:")]
+    #[case(
+        &["statement", "x", "a.csv"],
+        "invalid value 'x' for '<CLIENT>': invalid digit found in string"
+    )]
+    #[case(
+        &["--audit-log", "audit.csv", "--shards", "2", "a.csv"],
+        "--audit-log can't be combined with --shards"
+    )]
+    #[case(
+        &["--account-store", "accounts.db", "--shards", "2", "a.csv"],
+        "--account-store can't be combined with --shards"
+    )]
+    #[case(&["--wal", "engine.wal", "--shards", "2", "a.csv"], "--wal can't be combined with --shards")]
+    #[case(
+        &["--results", "results.csv", "--shards", "2", "a.csv"],
+        "--results can't be combined with --shards"
+    )]
+    #[case(
+        &["--wal", "engine.wal", "--account-store", "accounts.db", "a.csv"],
+        "--wal can't be combined with --account-store"
+    )]
+    #[case(
+        &["--checkpoint-every", "10", "a.csv"],
+        "--checkpoint-every requires --checkpoint"
+    )]
+    #[case(
+        &["--checkpoint", "run.ckpt", "--shards", "2", "a.csv"],
+        "--checkpoint can't be combined with --shards"
+    )]
+    #[case(
+        &["--checkpoint", "run.ckpt", "--results", "results.csv", "a.csv"],
+        "--checkpoint can't be combined with --results"
+    )]
+    #[case(
+        &["--checkpoint", "run.ckpt", "--settlement", "eod.csv", "a.csv"],
+        "--checkpoint can't be combined with --settlement"
+    )]
+    #[case(
+        &["validate", "--checkpoint", "run.ckpt", "a.csv"],
+        "--checkpoint can't be combined with validate"
+    )]
+    #[case(
+        &["serve", "--checkpoint", "run.ckpt", "--watch", "in"],
+        "--checkpoint can't be combined with --watch"
+    )]
+    #[case(&["serve"], "the following required arguments were not provided:")]
+    #[case(
+        &["serve", "--listen", ":7000", "--shards", "2"],
+        "--listen can't be combined with --shards"
+    )]
+    #[case(&["serve", "--watch", "in", "--shards", "2"], "--watch can't be combined with --shards")]
+    #[case(&["--listen", ":7000"], "unexpected argument '--listen' found")]
+    #[case(&["statement", "1", "--watch", "in"], "unexpected argument '--watch' found")]
+    #[case(&["validate", "--listen", ":7000"], "unexpected argument '--listen' found")]
+    #[case(&["--seed", "1", "a.csv"], "unexpected argument '--seed' found")]
+    #[case(&["generate", "a.csv"], "unexpected argument 'a.csv' found")]
+    #[case(
+        &["generate", "--clients", "x"],
+        "invalid value 'x' for '--clients <N>': invalid digit found in string"
+    )]
+    #[case(
+        &["generate", "--dispute-ratio", "2"],
+        "Invalid generator settings: dispute ratio must be between 0 and 1"
+    )]
+    #[case(&["--tx-id-format", "hex", "a.csv"], "unknown tx id format 'hex'")]
+    #[case(
+        &["--tx-id-format", "uuid", "--wal", "engine.wal", "a.csv"],
+        "--tx-id-format can't be combined with --wal"
+    )]
+    #[case(
+        &["serve", "--tx-id-format", "string", "--listen", ":7000"],
+        "--tx-id-format can't be combined with --listen"
+    )]
+    #[case(
+        &["--shards", "0", "a.csv"],
+        "invalid value '0' for '--shards <N>': expected a positive number"
+    )]
+    #[case(
+        &["--threads", "0", "a.csv"],
+        "invalid value '0' for '--threads <1|2>': expected 1 or 2"
+    )]
+    #[case(
+        &["--threads", "8", "a.csv"],
+        "invalid value '8' for '--threads <1|2>': expected 1 or 2"
+    )]
+    #[case(
+        &["--overdraft-limit", "-1", "a.csv"],
+        "invalid value '-1' for '--overdraft-limit <AMOUNT>': expected a non-negative number"
+    )]
+    #[case(
+        &["--overdraft-limit", "lots", "a.csv"],
+        "invalid value 'lots' for '--overdraft-limit <AMOUNT>': expected a non-negative number"
+    )]
+    #[case(
+        &["--interest-rate", "-1", "a.csv"],
+        "invalid value '-1' for '--interest-rate <PERCENT>': expected a non-negative number"
+    )]
+    #[case(
+        &["--dispute-window", "-5", "a.csv"],
+        "invalid value '-5' for '--dispute-window <DAYS>': expected a number of days"
+    )]
+    #[case(
+        &["--idempotency-retention", "x", "a.csv"],
+        "invalid value 'x' for '--idempotency-retention <DAYS>': expected a number of days"
+    )]
+    #[case(
+        &["--duplicate-tx", "skip", "a.csv"],
+        "invalid value 'skip' for '--duplicate-tx <POLICY>': unknown duplicate tx policy 'skip'"
+    )]
+    #[case(
+        &["--amount-precision", "ceil", "a.csv"],
+        "invalid value 'ceil' for '--amount-precision <MODE>': unknown amount precision 'ceil'"
+    )]
+    #[case(
+        &["--interest-rate", "1", "--interest-period", "0", "a.csv"],
+        "invalid value '0' for '--interest-period <DAYS>': expected a positive number of days"
+    )]
+    #[case(
+        &["--interest-period", "7", "a.csv"],
+        "--interest-period requires --interest-rate"
+    )]
+    #[case(&["--bogus", "a.csv"], "unexpected argument '--bogus' found")]
+    fn test_parse_args_errors(#[case] args: &[&str], #[case] expected: &str) {
+        assert_eq!(parse(args).unwrap_err(), expected);
+    }
+}
@@ -0,0 +1,225 @@
+//! Attributes of the engine's clients, like their names and KYC status, read
+//! from a clients file and echoed next to their accounts.
+
+use crate::errors::PaymentError;
+use crate::models::{ClientId, Currency};
+use rust_decimal::Decimal;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde_derive::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Where a client stands in know-your-customer checks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KycStatus {
+    Verified,
+    /// Funds may come in, but none may leave.
+    Pending,
+    /// No transactions at all, nor incoming transfers.
+    Failed,
+}
+
+impl KycStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KycStatus::Verified => "verified",
+            KycStatus::Pending => "pending",
+            KycStatus::Failed => "failed",
+        }
+    }
+}
+
+/// What the clients file says about a client. Every attribute is optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub name: Option<String>,
+    /// The client's id in other systems, like a CRM.
+    pub external_id: Option<String>,
+    /// Currency of the client's records that don't name one.
+    pub currency: Option<Currency>,
+    /// Overdraft limit the client's accounts start with, instead of the
+    /// engine's.
+    pub overdraft_limit: Option<Decimal>,
+    pub kyc_status: Option<KycStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientRow {
+    client: ClientId,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    external_id: Option<String>,
+    #[serde(default)]
+    currency: Option<Currency>,
+    #[serde(default)]
+    overdraft_limit: Option<Decimal>,
+    #[serde(default)]
+    kyc_status: Option<KycStatus>,
+}
+
+/// The attributes of some clients, by id. Clones share the table.
+#[derive(Debug, Clone, Default)]
+pub struct ClientRegistry {
+    clients: Arc<FxHashMap<ClientId, ClientInfo>>,
+}
+
+impl ClientRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `info` about `client`, replacing what was known.
+    pub fn with_client(mut self, client: ClientId, info: ClientInfo) -> Self {
+        Arc::make_mut(&mut self.clients).insert(client, info);
+        self
+    }
+
+    /// Reads a CSV table with a `client` column and any of `name`,
+    /// `external_id`, `currency`, `overdraft_limit` and `kyc_status`
+    /// (`verified`, `pending` or `failed`). A client, and an external id,
+    /// can only be listed once.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, PaymentError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let mut registry = Self::new();
+        let mut external_ids = FxHashSet::default();
+        for row in rdr.deserialize() {
+            let row: ClientRow = row?;
+            if registry.get(row.client).is_some() {
+                return Err(PaymentError::InvalidConfig(format!(
+                    "client {} is listed more than once",
+                    row.client
+                )));
+            }
+            if let Some(external_id) = &row.external_id {
+                if !external_ids.insert(external_id.clone()) {
+                    return Err(PaymentError::InvalidConfig(format!(
+                        "external id '{}' is listed for more than one client",
+                        external_id
+                    )));
+                }
+            }
+            if row
+                .overdraft_limit
+                .is_some_and(|limit| limit < Decimal::ZERO)
+            {
+                return Err(PaymentError::InvalidConfig(format!(
+                    "overdraft limit of client {} can't be negative",
+                    row.client
+                )));
+            }
+            registry = registry.with_client(
+                row.client,
+                ClientInfo {
+                    name: row.name,
+                    external_id: row.external_id,
+                    currency: row.currency,
+                    overdraft_limit: row.overdraft_limit,
+                    kyc_status: row.kyc_status,
+                },
+            );
+        }
+        Ok(registry)
+    }
+
+    /// Reads the clients file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PaymentError> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn get(&self, client: ClientId) -> Option<&ClientInfo> {
+        self.clients.get(&client)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+/// The client attributes written next to each account in the outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct ClientColumns<'a> {
+    name: Option<&'a str>,
+    external_id: Option<&'a str>,
+    kyc_status: Option<KycStatus>,
+}
+
+impl<'a> ClientColumns<'a> {
+    pub(crate) const NAMES: [&'static str; 3] = ["name", "external_id", "kyc_status"];
+
+    pub(crate) fn of(info: Option<&'a ClientInfo>) -> Self {
+        ClientColumns {
+            name: info.and_then(|info| info.name.as_deref()),
+            external_id: info.and_then(|info| info.external_id.as_deref()),
+            kyc_status: info.and_then(|info| info.kyc_status),
+        }
+    }
+
+    /// The columns as CSV fields, empty for what isn't known.
+    pub(crate) fn fields(&self) -> [&'a str; 3] {
+        [
+            self.name.unwrap_or_default(),
+            self.external_id.unwrap_or_default(),
+            self.kyc_status.map(KycStatus::as_str).unwrap_or_default(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    #[rstest]
+    fn test_client_registry_reads_attributes() {
+        let registry = ClientRegistry::from_reader(
+            "client,name,external_id,currency,overdraft_limit,kyc_status\n\
+             1,Ada Lovelace,CRM-1,eur,50,verified\n\
+             2,,,,,pending\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            registry.get(1),
+            Some(&ClientInfo {
+                name: Some("Ada Lovelace".to_string()),
+                external_id: Some("CRM-1".to_string()),
+                currency: Some("EUR".parse().unwrap()),
+                overdraft_limit: Some(dec!(50)),
+                kyc_status: Some(KycStatus::Verified),
+            })
+        );
+        assert_eq!(
+            registry.get(2),
+            Some(&ClientInfo {
+                kyc_status: Some(KycStatus::Pending),
+                ..ClientInfo::default()
+            })
+        );
+        assert_eq!(registry.get(3), None);
+    }
+
+    #[rstest]
+    fn test_client_registry_reads_some_columns() {
+        let registry = ClientRegistry::from_reader("client,name\n7,Grace\n".as_bytes()).unwrap();
+        assert_eq!(
+            registry.get(7).and_then(|info| info.name.as_deref()),
+            Some("Grace")
+        );
+    }
+
+    #[rstest]
+    #[case("client\n1\n1\n")]
+    #[case("client,external_id\n1,CRM-1\n2,CRM-1\n")]
+    #[case("client,overdraft_limit\n1,-5\n")]
+    fn test_client_registry_rejects_bad_tables(#[case] table: &str) {
+        let err = ClientRegistry::from_reader(table.as_bytes()).unwrap_err();
+        assert!(matches!(err, PaymentError::InvalidConfig(_)), "{:?}", err);
+    }
+}
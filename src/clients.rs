@@ -0,0 +1,141 @@
+//! Selections of clients, like `1,5,100-200`, to process and report only
+//! some clients of an input, e.g. to reproduce one customer's balance from a
+//! file of millions of records.
+
+use crate::input::RawRecord;
+use crate::models::ClientId;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// A set of client ids, parsed from a comma-separated list of ids and
+/// inclusive `from-to` ranges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientSet {
+    /// Sorted, disjoint and not adjacent, so lookups are a binary search.
+    ranges: Vec<RangeInclusive<ClientId>>,
+}
+
+impl ClientSet {
+    pub fn contains(&self, client_id: ClientId) -> bool {
+        let next = self
+            .ranges
+            .partition_point(|range| *range.end() < client_id);
+        self.ranges
+            .get(next)
+            .is_some_and(|range| range.contains(&client_id))
+    }
+}
+
+impl FromStr for ClientSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ranges = Vec::new();
+        for part in s.split(',').map(str::trim) {
+            let bound = |id: &str| {
+                id.trim()
+                    .parse::<ClientId>()
+                    .map_err(|_| format!("invalid client '{}' in '{}'", id.trim(), s))
+            };
+            let range = match part.split_once('-') {
+                Some((from, to)) => bound(from)?..=bound(to)?,
+                None => bound(part)?..=bound(part)?,
+            };
+            if range.is_empty() {
+                return Err(format!("empty client range '{}'", part));
+            }
+            ranges.push(range);
+        }
+        ranges.sort_unstable_by_key(|range| *range.start());
+        let mut merged: Vec<RangeInclusive<ClientId>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start().saturating_sub(1) <= *last.end() => {
+                    *last = *last.start()..=*last.end().max(range.end());
+                }
+                _ => merged.push(range),
+            }
+        }
+        Ok(ClientSet { ranges: merged })
+    }
+}
+
+impl fmt::Display for ClientSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, range) in self.ranges.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            if range.start() == range.end() {
+                write!(f, "{}", range.start())?;
+            } else {
+                write!(f, "{}-{}", range.start(), range.end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Passes on the records of the selected clients only. Records that couldn't
+/// be decoded have no client, so they're passed on to be reported as usual.
+/// Transfers from other clients are dropped with the rest of their records,
+/// so a selected client doesn't receive them.
+pub fn select_clients<I>(records: I, clients: ClientSet) -> impl Iterator<Item = RawRecord>
+where
+    I: IntoIterator<Item = RawRecord>,
+{
+    records.into_iter().filter(move |raw| {
+        raw.parsed
+            .as_ref()
+            .map_or(true, |record| clients.contains(record.client_id))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_handler::read_records;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("1,5,100-200", "1,5,100-200")]
+    #[case(" 7 , 3-4,2", "2-4,7")]
+    #[case("10-20,15-30,31", "10-31")]
+    #[case("4-4", "4")]
+    fn test_parse_client_set(#[case] input: &str, #[case] expected: &str) {
+        assert_eq!(input.parse::<ClientSet>().unwrap().to_string(), expected);
+    }
+
+    #[rstest]
+    #[case("", "invalid client '' in ''")]
+    #[case("1,x", "invalid client 'x' in '1,x'")]
+    #[case("5-", "invalid client '' in '5-'")]
+    #[case("9-3", "empty client range '9-3'")]
+    fn test_parse_client_set_errors(#[case] input: &str, #[case] expected: &str) {
+        assert_eq!(input.parse::<ClientSet>().unwrap_err(), expected);
+    }
+
+    #[rstest]
+    fn test_client_set_contains() {
+        let clients: ClientSet = "1,5,100-200".parse().unwrap();
+        let selected: Vec<ClientId> = (0..=250).filter(|&id| clients.contains(id)).collect();
+        assert_eq!(selected.len(), 103);
+        assert_eq!(selected[..3], [1, 5, 100]);
+        assert_eq!(selected.last(), Some(&200));
+    }
+
+    #[rstest]
+    fn test_select_clients() {
+        let input = "type,client,tx,amount
+deposit,1,1,10
+deposit,2,2,5
+garbled
+withdrawal,1,3,4
+";
+        let lines: Vec<u64> = select_clients(read_records(input.as_bytes()), "1".parse().unwrap())
+            .map(|raw| raw.line)
+            .collect();
+        assert_eq!(lines, [2, 4, 5]);
+    }
+}
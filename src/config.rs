@@ -0,0 +1,412 @@
+//! Engine settings gathered in one serializable struct, so a deployment's
+//! policies can be kept in a TOML or YAML file rather than in code.
+
+use crate::errors::PaymentError;
+use crate::interest::InterestSchedule;
+use crate::limits::Limits;
+use crate::models::ClientId;
+use crate::policy::{
+    AmountPrecision, ClientMatchMode, DuplicateTxPolicy, ExpiredDisputeAction, LockedAccountPolicy,
+    UnderfundedDisputeMode,
+};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Policies of an engine, applied with
+/// [`PaymentEngine::with_config`](crate::engine::PaymentEngine::with_config).
+///
+/// Every field has the engine's default, so a file only lists what it
+/// changes. Enum values are spelled like the CLI flags (`round-half-even`,
+/// `allow-negative`, ...). Stores, logs, listeners, fees and rate providers
+/// aren't policies and are attached separately.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    /// How records reusing a taken tx id are handled.
+    pub duplicate_tx: DuplicateTxPolicy,
+    /// How input amounts with more than four decimal places are handled.
+    pub amount_precision: AmountPrecision,
+    /// How deposits to locked accounts are handled.
+    pub locked_deposits: LockedAccountPolicy,
+    /// What happens to disputes of deposits whose funds were already spent.
+    pub underfunded_disputes: UnderfundedDisputeMode,
+    /// How references naming the wrong client are handled.
+    pub client_match: ClientMatchMode,
+    /// Only allow disputes on credits
+    /// ([`DepositsOnlyPolicy`](crate::policy::DepositsOnlyPolicy)).
+    pub deposits_only_disputes: bool,
+    /// Times a transaction may be disputed.
+    pub max_disputes: u8,
+    /// Days a transaction stays disputable; for ever when `None`.
+    pub dispute_window_days: Option<u64>,
+    /// Days a dispute may stay open before it's settled at the end of a
+    /// run; for ever when `None`.
+    pub dispute_max_age_days: Option<u64>,
+    /// How disputes open longer than `dispute_max_age_days` are settled.
+    pub expired_dispute_action: ExpiredDisputeAction,
+    /// Days idempotency keys are remembered; for the whole run when `None`.
+    pub idempotency_retention_days: Option<u64>,
+    /// Overdraft limit given to new accounts.
+    pub overdraft_limit: Decimal,
+    /// Annual interest rate in percent; no interest when `None`.
+    pub interest_rate: Option<Decimal>,
+    /// Days between interest postings (30 when `None`).
+    pub interest_period_days: Option<u32>,
+    /// Client whose accounts receive chargebacked funds; they leave the
+    /// books when `None`.
+    pub suspense_account: Option<ClientId>,
+    /// Largest amount a single transaction may move.
+    pub max_amount: Option<Decimal>,
+    /// Most a client may withdraw in a day.
+    pub max_daily_withdrawal: Option<Decimal>,
+    /// Transactions a client may make within `velocity_window_seconds`.
+    pub max_transactions: Option<u32>,
+    /// Seconds `max_transactions` counts over (3600 when `None`).
+    pub velocity_window_seconds: Option<u64>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            duplicate_tx: DuplicateTxPolicy::default(),
+            amount_precision: AmountPrecision::default(),
+            locked_deposits: LockedAccountPolicy::default(),
+            underfunded_disputes: UnderfundedDisputeMode::default(),
+            client_match: ClientMatchMode::default(),
+            deposits_only_disputes: false,
+            max_disputes: 1,
+            dispute_window_days: None,
+            dispute_max_age_days: None,
+            expired_dispute_action: ExpiredDisputeAction::default(),
+            idempotency_retention_days: None,
+            overdraft_limit: Decimal::ZERO,
+            interest_rate: None,
+            interest_period_days: None,
+            suspense_account: None,
+            max_amount: None,
+            max_daily_withdrawal: None,
+            max_transactions: None,
+            velocity_window_seconds: None,
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Reads a configuration from the file at `path`, in the format
+    /// [`read_file`] picks for it.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PaymentError> {
+        let config: EngineConfig = read_file(path)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Fails with `InvalidConfig` if a setting is out of range.
+    pub fn validate(&self) -> Result<(), PaymentError> {
+        let invalid = |msg: &str| Err(PaymentError::InvalidConfig(msg.to_string()));
+        if self.overdraft_limit.is_sign_negative() {
+            return invalid("overdraft_limit can't be negative");
+        }
+        if self
+            .interest_rate
+            .is_some_and(|rate| rate.is_sign_negative())
+        {
+            return invalid("interest_rate can't be negative");
+        }
+        if self.interest_period_days == Some(0) {
+            return invalid("interest_period_days must be at least 1");
+        }
+        if self.interest_period_days.is_some() && self.interest_rate.is_none() {
+            return invalid("interest_period_days requires interest_rate");
+        }
+        for (name, amount) in [
+            ("max_amount", self.max_amount),
+            ("max_daily_withdrawal", self.max_daily_withdrawal),
+        ] {
+            if amount.is_some_and(|amount| amount.is_sign_negative()) {
+                return invalid(&format!("{} can't be negative", name));
+            }
+        }
+        if self.max_transactions == Some(0) {
+            return invalid("max_transactions must be at least 1");
+        }
+        if self.velocity_window_seconds == Some(0) {
+            return invalid("velocity_window_seconds must be at least 1");
+        }
+        if self.velocity_window_seconds.is_some() && self.max_transactions.is_none() {
+            return invalid("velocity_window_seconds requires max_transactions");
+        }
+        if self.expired_dispute_action != ExpiredDisputeAction::default()
+            && self.dispute_max_age_days.is_none()
+        {
+            return invalid("expired_dispute_action requires dispute_max_age_days");
+        }
+        for (name, days) in [
+            ("dispute_window_days", self.dispute_window_days),
+            ("dispute_max_age_days", self.dispute_max_age_days),
+            (
+                "idempotency_retention_days",
+                self.idempotency_retention_days,
+            ),
+        ] {
+            if days.is_some_and(|days| days.checked_mul(SECONDS_PER_DAY).is_none()) {
+                return invalid(&format!("{} is too large", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// The interest schedule the rate and period describe, if any.
+    pub fn interest(&self) -> Option<InterestSchedule> {
+        let schedule = InterestSchedule::new(self.interest_rate?);
+        Some(
+            self.interest_period_days
+                .map_or(schedule, |days| schedule.with_period_days(days)),
+        )
+    }
+
+    /// The limits set, if any.
+    pub fn limits(&self) -> Option<Limits> {
+        let mut limits = Limits::new();
+        if let Some(amount) = self.max_amount {
+            limits = limits.with_max_amount(amount);
+        }
+        if let Some(amount) = self.max_daily_withdrawal {
+            limits = limits.with_max_daily_withdrawal(amount);
+        }
+        if let Some(count) = self.max_transactions {
+            let window = self.velocity_window_seconds.unwrap_or(3600);
+            limits = limits.with_max_transactions(count, Duration::from_secs(window));
+        }
+        (limits != Limits::new()).then_some(limits)
+    }
+
+    /// How long transactions stay disputable, if limited.
+    pub fn dispute_window(&self) -> Option<Duration> {
+        self.dispute_window_days.map(days)
+    }
+
+    /// How long disputes may stay open, if limited.
+    pub fn dispute_max_age(&self) -> Option<Duration> {
+        self.dispute_max_age_days.map(days)
+    }
+
+    /// How long idempotency keys are remembered, if limited.
+    pub fn idempotency_retention(&self) -> Option<Duration> {
+        self.idempotency_retention_days.map(days)
+    }
+}
+
+impl std::str::FromStr for EngineConfig {
+    type Err = PaymentError;
+
+    /// Parses and validates a TOML configuration.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let config: EngineConfig =
+            toml::from_str(s).map_err(|e| PaymentError::InvalidConfig(e.message().to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// Deserializes the file at `path` as YAML if its extension is `.yaml` or
+/// `.yml`, and as TOML otherwise. Syntax and type errors are
+/// `InvalidConfig`.
+pub fn read_file<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> Result<T, PaymentError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    match path.extension().and_then(OsStr::to_str) {
+        Some("yaml" | "yml") => {
+            serde_yaml_ng::from_str(&text).map_err(|e| PaymentError::InvalidConfig(e.to_string()))
+        }
+        _ => {
+            toml::from_str(&text).map_err(|e| PaymentError::InvalidConfig(e.message().to_string()))
+        }
+    }
+}
+
+fn days(days: u64) -> Duration {
+    Duration::from_secs(days.saturating_mul(SECONDS_PER_DAY))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::simple;
+    use crate::engine::PaymentEngine;
+    use crate::models::TransactionType;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    #[rstest]
+    fn test_config_parses_toml() {
+        let config: EngineConfig = r#"
+            duplicate_tx = "warn"
+            amount_precision = "round-half-even"
+            locked_deposits = "hold"
+            underfunded_disputes = "allow-negative"
+            deposits_only_disputes = true
+            max_disputes = 3
+            dispute_window_days = 90
+            dispute_max_age_days = 45
+            expired_dispute_action = "chargeback"
+            overdraft_limit = "50.5"
+            interest_rate = 2.5
+            interest_period_days = 7
+            max_amount = "1000"
+            max_transactions = 5
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            config,
+            EngineConfig {
+                duplicate_tx: DuplicateTxPolicy::Warn,
+                amount_precision: AmountPrecision::RoundHalfEven,
+                locked_deposits: LockedAccountPolicy::Hold,
+                underfunded_disputes: UnderfundedDisputeMode::AllowNegative,
+                deposits_only_disputes: true,
+                max_disputes: 3,
+                dispute_window_days: Some(90),
+                dispute_max_age_days: Some(45),
+                expired_dispute_action: ExpiredDisputeAction::Chargeback,
+                overdraft_limit: dec!(50.5),
+                interest_rate: Some(dec!(2.5)),
+                interest_period_days: Some(7),
+                max_amount: Some(dec!(1000)),
+                max_transactions: Some(5),
+                ..EngineConfig::default()
+            }
+        );
+        assert_eq!(
+            config.limits(),
+            Some(
+                Limits::new()
+                    .with_max_amount(dec!(1000))
+                    .with_max_transactions(5, Duration::from_secs(3600))
+            )
+        );
+        assert_eq!(EngineConfig::default().limits(), None);
+        assert_eq!(
+            config.interest(),
+            Some(InterestSchedule::new(dec!(2.5)).with_period_days(7))
+        );
+        assert_eq!(
+            config.dispute_window(),
+            Some(Duration::from_secs(90 * 86_400))
+        );
+        assert_eq!("".parse::<EngineConfig>().unwrap(), EngineConfig::default());
+    }
+
+    #[rstest]
+    fn test_config_round_trips_through_toml() {
+        let config = EngineConfig {
+            client_match: ClientMatchMode::Lenient,
+            idempotency_retention_days: Some(7),
+            ..EngineConfig::default()
+        };
+        let toml = toml::to_string(&config).unwrap();
+        assert_eq!(toml.parse::<EngineConfig>().unwrap(), config);
+    }
+
+    #[rstest]
+    #[case("duplicate_tx = \"skip\"", "unknown variant `skip`")]
+    #[case("shards = 4", "unknown field `shards`")]
+    #[case("overdraft_limit = \"-1\"", "overdraft_limit can't be negative")]
+    #[case("interest_rate = -2", "interest_rate can't be negative")]
+    #[case(
+        "interest_rate = 1\ninterest_period_days = 0",
+        "interest_period_days must be at least 1"
+    )]
+    #[case(
+        "interest_period_days = 7",
+        "interest_period_days requires interest_rate"
+    )]
+    #[case(
+        "dispute_window_days = 999999999999999999",
+        "dispute_window_days is too large"
+    )]
+    #[case(
+        "max_daily_withdrawal = \"-1\"",
+        "max_daily_withdrawal can't be negative"
+    )]
+    #[case("max_transactions = 0", "max_transactions must be at least 1")]
+    #[case(
+        "velocity_window_seconds = 60",
+        "velocity_window_seconds requires max_transactions"
+    )]
+    #[case(
+        "expired_dispute_action = \"chargeback\"",
+        "expired_dispute_action requires dispute_max_age_days"
+    )]
+    fn test_config_errors(#[case] toml: &str, #[case] expected: &str) {
+        let err = toml.parse::<EngineConfig>().unwrap_err();
+        assert!(
+            matches!(&err, PaymentError::InvalidConfig(msg) if msg.contains(expected)),
+            "{}",
+            err
+        );
+    }
+
+    #[rstest]
+    #[case("engine.toml", "duplicate_tx = \"error\"\nmax_disputes = 2\n")]
+    #[case("engine.yaml", "duplicate_tx: error\nmax_disputes: 2\n")]
+    #[case("engine.yml", "{ duplicate_tx: error, max_disputes: 2 }\n")]
+    fn test_config_load(#[case] name: &str, #[case] contents: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+
+        assert_eq!(
+            EngineConfig::load(&path).unwrap(),
+            EngineConfig {
+                duplicate_tx: DuplicateTxPolicy::Error,
+                max_disputes: 2,
+                ..EngineConfig::default()
+            }
+        );
+    }
+
+    #[rstest]
+    fn test_config_load_validates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("engine.yaml");
+        fs::write(&path, "overdraft_limit: -5\n").unwrap();
+        assert!(matches!(
+            EngineConfig::load(&path),
+            Err(PaymentError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            EngineConfig::load(dir.path().join("missing.toml")),
+            Err(PaymentError::Io(_))
+        ));
+    }
+
+    #[rstest]
+    fn test_engine_with_config() {
+        let config = EngineConfig {
+            deposits_only_disputes: true,
+            overdraft_limit: dec!(10.0),
+            ..EngineConfig::default()
+        };
+        let mut engine = PaymentEngine::new().with_config(&config);
+        engine
+            .process(simple(TransactionType::Withdrawal, 1, Some(dec!(5.0))))
+            .unwrap();
+        engine
+            .process(simple(TransactionType::Dispute, 1, None))
+            .unwrap();
+
+        let account = engine.get_account(1, Default::default()).unwrap();
+        assert_eq!(account.available, dec!(-5.0));
+        // Withdrawals can't be disputed under the deposits-only policy.
+        assert_eq!(account.held, dec!(0.0));
+    }
+}
@@ -0,0 +1,194 @@
+//! Rewrites record streams from one input format to another, like CSV
+//! replay archives to MessagePack and back, without processing them.
+
+use crate::csv_handler::COLUMNS;
+use crate::errors::PaymentError;
+use crate::input::{InputFormat, RawRecord};
+use crate::models::InputRecord;
+#[cfg(feature = "msgpack")]
+use crate::msgpack::MsgpackWriter;
+use crate::policy::ErrorPolicy;
+#[cfg(feature = "protobuf")]
+use crate::protobuf::ProtobufWriter;
+use crate::report::{ProcessingReport, SkipKind};
+use std::io::Write;
+
+/// Writes the decoded `records` to `writer` in the `to` format: CSV, JSON
+/// Lines or, with the features of the same names, MessagePack or protobuf.
+/// Records that don't decode are skipped or abort the conversion by
+/// `policy`, as in a run.
+pub fn convert_records<I, W>(
+    records: I,
+    to: InputFormat,
+    policy: ErrorPolicy,
+    writer: W,
+) -> Result<ProcessingReport, PaymentError>
+where
+    I: IntoIterator<Item = RawRecord>,
+    W: Write,
+{
+    let mut writer = RecordWriter::new(to, writer)?;
+    let mut report = ProcessingReport::default();
+    for RawRecord { line, raw, parsed } in records {
+        report.records_read += 1;
+        match parsed {
+            Ok(record) => writer.write(&record)?,
+            Err(e) => report.record_failure(policy, line, raw, SkipKind::Decode, e)?,
+        }
+    }
+    writer.flush()?;
+    Ok(report)
+}
+
+enum RecordWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    JsonLines(W),
+    #[cfg(feature = "msgpack")]
+    MessagePack(MsgpackWriter<W>),
+    #[cfg(feature = "protobuf")]
+    Protobuf(ProtobufWriter<W>),
+}
+
+impl<W: Write> RecordWriter<W> {
+    fn new(format: InputFormat, writer: W) -> Result<Self, PaymentError> {
+        match format {
+            InputFormat::Csv => {
+                let mut wtr = csv::Writer::from_writer(writer);
+                wtr.write_record(COLUMNS)?;
+                Ok(RecordWriter::Csv(Box::new(wtr)))
+            }
+            InputFormat::JsonLines => Ok(RecordWriter::JsonLines(writer)),
+            #[cfg(feature = "msgpack")]
+            InputFormat::MessagePack => Ok(RecordWriter::MessagePack(MsgpackWriter::new(writer))),
+            #[cfg(feature = "protobuf")]
+            InputFormat::Protobuf => Ok(RecordWriter::Protobuf(ProtobufWriter::new(writer))),
+            _ => Err(PaymentError::InvalidConfig(
+                "records can only be converted to csv, jsonl, msgpack or protobuf".to_string(),
+            )),
+        }
+    }
+
+    fn write(&mut self, record: &InputRecord) -> Result<(), PaymentError> {
+        match self {
+            RecordWriter::Csv(wtr) => {
+                let optional = |value: Option<String>| value.unwrap_or_default();
+                wtr.write_record([
+                    record.record_type.as_str().to_string(),
+                    record.client_id.to_string(),
+                    record.tx_id.to_string(),
+                    optional(record.amount.map(|amount| amount.to_string())),
+                    optional(record.counterparty_id.map(|id| id.to_string())),
+                    record.currency.as_str().to_string(),
+                    optional(record.target_currency.map(|c| c.as_str().to_string())),
+                    optional(record.timestamp.map(|ts| ts.to_string())),
+                    optional(record.idempotency_key.clone()),
+                    optional(record.reference.clone()),
+                ])?;
+            }
+            RecordWriter::JsonLines(writer) => {
+                serde_json::to_writer(&mut *writer, record)?;
+                writer.write_all(b"\n")?;
+            }
+            #[cfg(feature = "msgpack")]
+            RecordWriter::MessagePack(wtr) => wtr.write(record)?,
+            #[cfg(feature = "protobuf")]
+            RecordWriter::Protobuf(wtr) => wtr.write(record)?,
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), PaymentError> {
+        match self {
+            RecordWriter::Csv(wtr) => wtr.flush()?,
+            RecordWriter::JsonLines(writer) => writer.flush()?,
+            #[cfg(feature = "msgpack")]
+            RecordWriter::MessagePack(wtr) => wtr.flush()?,
+            #[cfg(feature = "protobuf")]
+            RecordWriter::Protobuf(wtr) => wtr.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_handler::read_records;
+    use crate::json_handler::read_json_lines;
+    use rstest::rstest;
+
+    const CSV: &str =
+        "type,client,tx,amount,counterparty,currency,to_currency,timestamp,idempotency_key
+deposit,1,1,10.5,,EUR,,1700000000,k-1
+transfer,1,2,2.25,2,EUR,,,
+payout,1,3,1.0,,,,,
+dispute,1,1,,,EUR,,,
+";
+
+    fn convert(to: InputFormat) -> (String, ProcessingReport) {
+        let mut output = Vec::new();
+        let report = convert_records(
+            read_records(CSV.as_bytes()),
+            to,
+            ErrorPolicy::Skip,
+            &mut output,
+        )
+        .unwrap();
+        (String::from_utf8(output).unwrap(), report)
+    }
+
+    fn parsed(records: impl Iterator<Item = RawRecord>) -> Vec<InputRecord> {
+        records.filter_map(|record| record.parsed.ok()).collect()
+    }
+
+    #[rstest]
+    fn test_convert_csv_round_trip() {
+        let (output, report) = convert(InputFormat::Csv);
+        assert_eq!((report.records_read, report.skipped.len()), (4, 1));
+        assert_eq!(report.skipped[0].line, 4);
+        assert_eq!(
+            output,
+            "type,client,tx,amount,counterparty,currency,to_currency,timestamp,idempotency_key,reference
+deposit,1,1,10.5,,EUR,,1700000000,k-1,
+transfer,1,2,2.25,2,EUR,,,,
+dispute,1,1,,,EUR,,,,
+"
+        );
+        assert_eq!(
+            parsed(read_records(output.as_bytes())),
+            parsed(read_records(CSV.as_bytes()))
+        );
+    }
+
+    #[rstest]
+    fn test_convert_to_json_lines() {
+        let (output, _) = convert(InputFormat::JsonLines);
+        assert_eq!(output.lines().count(), 3);
+        assert_eq!(
+            parsed(read_json_lines(output.as_bytes())),
+            parsed(read_records(CSV.as_bytes()))
+        );
+    }
+
+    #[rstest]
+    fn test_convert_fails_fast() {
+        let err = convert_records(
+            read_records(CSV.as_bytes()),
+            InputFormat::Csv,
+            ErrorPolicy::FailFast,
+            Vec::new(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, PaymentError::AtLine { line: 4, .. }));
+    }
+
+    #[rstest]
+    fn test_convert_rejects_unwritable_formats() {
+        let err = convert_records(Vec::new(), InputFormat::Ofx, ErrorPolicy::Skip, Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid config: records can only be converted to csv, jsonl, msgpack or protobuf"
+        );
+    }
+}
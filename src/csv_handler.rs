@@ -1,68 +1,533 @@
+use crate::amount_format::AmountFormat;
+use crate::client_registry::{ClientColumns, ClientRegistry};
 use crate::engine::PaymentEngine;
 use crate::errors::PaymentError;
-use crate::models::InputRecord;
+#[cfg(feature = "fast-parse")]
+use crate::fast_parse::Columns;
+use crate::input::{process_records, RawRecord};
+use crate::models::{Account, Currency, InputRecord, OutputRecord, TransactionType};
+use crate::report::ProcessingReport;
+use crate::tx_ids::{TxIdFormat, TxIdMap};
+use csv::{ByteRecord, StringRecord};
+use rayon::prelude::*;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 /// Processes transactions from a CSV file.
 pub fn process_transactions<P: AsRef<Path>>(
     file_path: P,
     engine: &mut PaymentEngine,
-) -> Result<(), PaymentError> {
+) -> Result<ProcessingReport, PaymentError> {
     let file = File::open(file_path)?;
-    let mut rdr = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All) // Handle potential whitespaces
-        .flexible(true) // Allow traiiling commas
-        .from_reader(file);
-
-    for result in rdr.deserialize() {
-        let record: InputRecord = match result {
-            Ok(rec) => rec,
-            Err(e) => {
-                eprintln!("Warning: Skipping bad record: {}", e);
-                continue;
+    process_reader(file, engine)
+}
+
+/// Processes transactions from any CSV source (stdin, sockets, in-memory buffers).
+pub fn process_reader<R: Read>(
+    reader: R,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError> {
+    process_records(read_records(reader), engine)
+}
+
+/// The input columns, as named by the header.
+pub(crate) const COLUMNS: [&str; 10] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "counterparty",
+    "currency",
+    "to_currency",
+    "timestamp",
+    "idempotency_key",
+    "reference",
+];
+
+/// How a partner writes its CSV files: the delimiter, the quoting, the names
+/// its header gives the input columns, if it has one, the names it gives
+/// transaction types, how it writes amounts, and whether to keep the columns
+/// it adds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvDialect {
+    delimiter: u8,
+    quote: Option<u8>,
+    escape: Option<u8>,
+    has_header: bool,
+    /// Header names standing for input columns, keyed in lowercase as they
+    /// match regardless of case.
+    aliases: HashMap<String, String>,
+    /// Type names standing for transaction types, keyed in lowercase.
+    type_aliases: HashMap<String, TransactionType>,
+    strict_types: bool,
+    amount_format: AmountFormat,
+    metadata: bool,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: Some(b'"'),
+            escape: None,
+            has_header: true,
+            aliases: HashMap::new(),
+            type_aliases: HashMap::new(),
+            strict_types: false,
+            amount_format: AmountFormat::Plain,
+            metadata: false,
+        }
+    }
+}
+
+impl CsvDialect {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Separates fields with `delimiter` instead of a comma.
+    pub fn with_delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Quotes fields with `quote` instead of `"`; `None` reads quotes as
+    /// ordinary characters.
+    pub fn with_quote(mut self, quote: Option<u8>) -> Self {
+        self.quote = quote;
+        self
+    }
+
+    /// Escapes quotes inside quoted fields with `escape` instead of doubling
+    /// them.
+    pub fn with_escape(mut self, escape: Option<u8>) -> Self {
+        self.escape = escape;
+        self
+    }
+
+    /// Reads files without a header row, whose columns are the input columns
+    /// in order: `type,client,tx,amount`, then optionally the others as
+    /// listed in `COLUMNS`.
+    pub fn without_header(mut self) -> Self {
+        self.has_header = false;
+        self
+    }
+
+    /// Reads the `name` header as the `column` input column, like `txn_id` as
+    /// `tx`.
+    pub fn with_alias(mut self, name: &str, column: &str) -> Result<Self, PaymentError> {
+        if !COLUMNS.contains(&column) {
+            return Err(PaymentError::InvalidConfig(format!(
+                "unknown column '{}' for header alias '{}' (expected one of {})",
+                column,
+                name,
+                COLUMNS.join(", ")
+            )));
+        }
+        self.aliases
+            .insert(name.trim().to_lowercase(), column.to_string());
+        Ok(self)
+    }
+
+    /// Reads `name` in the `type` column as `record_type`, like `payout` as a
+    /// withdrawal. Names match regardless of case.
+    pub fn with_type_alias(mut self, name: &str, record_type: TransactionType) -> Self {
+        self.type_aliases
+            .insert(name.trim().to_lowercase(), record_type);
+        self
+    }
+
+    /// Reads only the exact type names, like `deposit`, and those given by
+    /// [`with_type_alias`](Self::with_type_alias), instead of also taking
+    /// other spellings (see [`TransactionType::from_alias`]).
+    pub fn with_strict_types(mut self) -> Self {
+        self.strict_types = true;
+        self
+    }
+
+    /// Reads amounts written in `format`, like `1.234,56`.
+    pub fn with_amount_format(mut self, format: AmountFormat) -> Self {
+        self.amount_format = format;
+        self
+    }
+
+    /// Keeps the values of header columns that aren't input columns, like a
+    /// partner's `memo`, in each record's `metadata` instead of ignoring them.
+    pub fn with_metadata(mut self) -> Self {
+        self.metadata = true;
+        self
+    }
+
+    fn reader<R: Read>(&self, reader: R) -> csv::Reader<R> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All) // Handle potential whitespaces
+            .flexible(true) // Allow traiiling commas
+            .delimiter(self.delimiter)
+            .quote(self.quote.unwrap_or_default())
+            .quoting(self.quote.is_some())
+            .escape(self.escape)
+            .double_quote(self.escape.is_none())
+            .has_headers(self.has_header)
+            .from_reader(reader)
+    }
+
+    /// The transaction type named `name` in the `type` column.
+    fn record_type(&self, name: &str) -> Result<TransactionType, PaymentError> {
+        let record_type = match self.type_aliases.get(&name.to_lowercase()) {
+            Some(record_type) => Some(*record_type),
+            None if self.strict_types => name.parse().ok(),
+            None => TransactionType::from_alias(name),
+        };
+        record_type.ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!("unknown transaction type '{}'", name))
+        })
+    }
+
+    /// The columns of the file being read: its header, with aliases replaced
+    /// by the columns they stand for, or `COLUMNS` when it has none.
+    fn columns<R: Read>(&self, rdr: &mut csv::Reader<R>) -> StringRecord {
+        if !self.has_header {
+            return StringRecord::from(COLUMNS.to_vec());
+        }
+        // A broken header surfaces again as an error on the first row.
+        let headers = rdr.headers().cloned().unwrap_or_default();
+        if self.aliases.is_empty() {
+            return headers;
+        }
+        headers
+            .iter()
+            .map(|name| {
+                self.aliases
+                    .get(&name.to_lowercase())
+                    .map_or(name, String::as_str)
+            })
+            .collect()
+    }
+}
+
+/// Lazily decodes CSV rows into records, yielding an error for each bad row.
+pub fn read_records<R: Read>(reader: R) -> impl Iterator<Item = RawRecord> {
+    decode_rows(reader, &CsvDialect::default(), None, None)
+}
+
+/// Like [`read_records`], reading the `tx` column through `tx_ids`.
+pub fn read_records_with_tx_ids<R: Read>(
+    reader: R,
+    tx_ids: TxIdMap,
+) -> impl Iterator<Item = RawRecord> {
+    decode_rows(reader, &CsvDialect::default(), Some(tx_ids), None)
+}
+
+/// Like [`read_records_with_tx_ids`], for files written in `dialect`.
+/// Numeric tx ids are read as they are.
+pub fn read_records_with_dialect<R: Read>(
+    reader: R,
+    dialect: &CsvDialect,
+    tx_ids: TxIdMap,
+) -> impl Iterator<Item = RawRecord> {
+    let tx_ids = Some(tx_ids).filter(|tx_ids| tx_ids.format() != TxIdFormat::Numeric);
+    decode_rows(reader, dialect, tx_ids, None)
+}
+
+/// Like [`read_records_with_dialect`] for numeric tx ids, first passing over
+/// as many rows as `skip` holds without decoding them, and counting `skip`
+/// down by the rows passed. Rows that don't parse count as rows, like the
+/// records they would decode to.
+pub(crate) fn read_records_after<R: Read>(
+    reader: R,
+    dialect: &CsvDialect,
+    skip: &AtomicU64,
+) -> impl Iterator<Item = RawRecord> {
+    decode_rows(reader, dialect, None, Some(skip))
+}
+
+fn decode_rows<R: Read>(
+    reader: R,
+    dialect: &CsvDialect,
+    tx_ids: Option<TxIdMap>,
+    skip: Option<&AtomicU64>,
+) -> impl Iterator<Item = RawRecord> {
+    let mut rdr = dialect.reader(reader);
+    let headers = dialect.columns(&mut rdr);
+    if let Some(skip) = skip {
+        let mut row = ByteRecord::new();
+        while skip.load(Ordering::Relaxed) > 0
+            && !matches!(rdr.read_byte_record(&mut row), Ok(false))
+        {
+            skip.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+    let rewrites = Rewrites::new(dialect, &headers, tx_ids);
+    // Rewritten rows still go through serde.
+    #[cfg(feature = "fast-parse")]
+    let columns = Columns::new(&headers).filter(|_| rewrites.is_none());
+    let positional = !dialect.has_header;
+    let extra: Vec<(usize, String)> = headers
+        .iter()
+        .enumerate()
+        .filter(|&(_, name)| dialect.metadata && !COLUMNS.contains(&name))
+        .map(|(column, name)| (column, name.to_string()))
+        .collect();
+
+    rdr.into_records().map(move |result| match result {
+        Ok(row) => {
+            // Headerless rows only name the columns they have.
+            let short: StringRecord;
+            let headers = if positional && row.len() < headers.len() {
+                short = headers.iter().take(row.len()).collect();
+                &short
+            } else {
+                &headers
+            };
+            #[cfg(feature = "fast-parse")]
+            let parsed = match columns
+                .as_ref()
+                .and_then(|c| c.decode(row.as_byte_record()))
+            {
+                Some(record) => Ok(record),
+                None => decode_row(&row, headers, rewrites.as_ref()),
+            };
+            #[cfg(not(feature = "fast-parse"))]
+            let parsed = decode_row(&row, headers, rewrites.as_ref());
+            RawRecord {
+                line: row.position().map_or(0, |pos| pos.line()),
+                raw: row.iter().collect::<Vec<_>>().join(","),
+                parsed: parsed.map(|mut record| {
+                    record.metadata = extra
+                        .iter()
+                        .filter_map(|(column, name)| {
+                            let value = row.get(*column).filter(|value| !value.is_empty())?;
+                            Some((name.clone(), value.to_string()))
+                        })
+                        .collect();
+                    record
+                }),
             }
+        }
+        Err(e) => RawRecord {
+            line: e.position().map_or(0, |pos| pos.line()),
+            raw: String::new(),
+            parsed: Err(PaymentError::from(e)),
+        },
+    })
+}
+
+/// Fields read before serde, in the columns they're found in: mapped tx
+/// ids, configured type names and localized amounts.
+struct Rewrites {
+    dialect: CsvDialect,
+    tx_ids: Option<(TxIdMap, usize)>,
+    record_type: Option<usize>,
+    amount: Option<usize>,
+}
+
+impl Rewrites {
+    /// The rewrites `dialect` and `tx_ids` need, or `None` if rows can be
+    /// decoded as they are.
+    fn new(dialect: &CsvDialect, headers: &StringRecord, tx_ids: Option<TxIdMap>) -> Option<Self> {
+        let position = |name: &str| headers.iter().position(|column| column == name);
+        let rewrites = Rewrites {
+            dialect: dialect.clone(),
+            tx_ids: tx_ids.zip(position("tx")),
+            record_type: position("type")
+                .filter(|_| dialect.strict_types || !dialect.type_aliases.is_empty()),
+            amount: position("amount").filter(|_| dialect.amount_format != AmountFormat::Plain),
         };
+        (rewrites.tx_ids.is_some() || rewrites.record_type.is_some() || rewrites.amount.is_some())
+            .then_some(rewrites)
+    }
 
-        if let Err(e) = engine.process(record) {
-            eprintln!("Warning: Error processing transaction: {}", e);
+    fn field(&self, column: usize, field: &str) -> Result<String, PaymentError> {
+        match &self.tx_ids {
+            Some((tx_ids, tx_column)) if column == *tx_column => {
+                return tx_ids.map(field).map(|tx_id| tx_id.to_string())
+            }
+            _ => {}
         }
+        if Some(column) == self.record_type {
+            return Ok(self.dialect.record_type(field)?.as_str().to_string());
+        }
+        if Some(column) == self.amount {
+            return self.dialect.amount_format.normalize(field).ok_or_else(|| {
+                PaymentError::InvalidTransaction(format!("invalid amount '{}'", field))
+            });
+        }
+        Ok(field.to_string())
     }
-    Ok(())
 }
 
-/// Writes account states to a CSV format.
+fn decode_row(
+    row: &StringRecord,
+    headers: &StringRecord,
+    rewrites: Option<&Rewrites>,
+) -> Result<InputRecord, PaymentError> {
+    let Some(rewrites) = rewrites else {
+        return Ok(row.deserialize(Some(headers))?);
+    };
+    let row = row
+        .iter()
+        .enumerate()
+        .map(|(column, field)| rewrites.field(column, field))
+        .collect::<Result<StringRecord, _>>()?;
+    Ok(row.deserialize(Some(headers))?)
+}
+
+/// Accounts formatted by one parallel task.
+const FORMAT_CHUNK: usize = 4096;
+
+/// Accounts formatted before their rows are written out, which bounds the
+/// formatted rows held in memory.
+const FORMAT_WINDOW: usize = 64 * FORMAT_CHUNK;
+
+/// Writes account states to a CSV format, followed by the client columns
+/// when the engine has a client registry. The `currency`, `closed` and
+/// `overdraft` columns are only written when some account uses them.
+///
+/// Rows are formatted in parallel, a window of accounts at a time, and
+/// written in order.
 pub fn write_accounts<W: Write>(engine: &PaymentEngine, writer: W) -> Result<(), PaymentError> {
     let mut wtr = csv::Writer::from_writer(writer);
-    let mut accounts = engine.get_accounts();
+    let mut accounts: Vec<&Account> = engine.iter_accounts().collect();
 
-    // Sort by client ID for deterministic output (good for testing)
-    accounts.sort_by_key(|a| a.client_id);
+    // Sort by client ID, then currency, for deterministic output (good for testing)
+    accounts.par_sort_unstable_by_key(|a| (a.client_id, a.currency));
 
-    wtr.write_record(["client", "available", "held", "total", "locked"])?;
+    let registry = Some(engine.client_registry()).filter(|registry| !registry.is_empty());
+    let columns = OptionalColumns::of(&accounts);
+    let mut header = columns.select(ACCOUNT_COLUMNS);
+    if registry.is_some() {
+        header.extend(ClientColumns::NAMES);
+    }
+    wtr.write_record(header)?;
+    let mut writer = wtr
+        .into_inner()
+        .map_err(|e| PaymentError::Io(e.into_error()))?;
 
-    for account_record in accounts {
-        wtr.write_record(&[
-            account_record.client_id.to_string(),
-            format!("{:.4}", account_record.available),
-            format!("{:.4}", account_record.held),
-            format!("{:.4}", account_record.total),
-            account_record.locked.to_string(),
-        ])?;
+    for window in accounts.chunks(FORMAT_WINDOW) {
+        let chunks = window
+            .par_chunks(FORMAT_CHUNK)
+            .map(|chunk| format_rows(chunk, columns, registry))
+            .collect::<Result<Vec<_>, _>>()?;
+        for rows in chunks {
+            writer.write_all(&rows)?;
+        }
     }
 
-    wtr.flush()?;
+    writer.flush()?;
     Ok(())
 }
 
+/// Formats `accounts` as rows of the accounts CSV, without the header,
+/// with the client columns of `registry` if given.
+fn format_rows(
+    accounts: &[&Account],
+    columns: OptionalColumns,
+    registry: Option<&ClientRegistry>,
+) -> Result<Vec<u8>, PaymentError> {
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    for account in accounts {
+        let row = columns.select(account_row(&account.to_output_record()));
+        match registry {
+            Some(registry) => {
+                let client = ClientColumns::of(registry.get(account.client_id));
+                wtr.write_record(row.iter().map(String::as_str).chain(client.fields()))?;
+            }
+            None => wtr.write_record(row)?,
+        }
+    }
+    wtr.into_inner()
+        .map_err(|e| PaymentError::Io(e.into_error()))
+}
+
+/// Every column of the accounts CSV, in the order of [`account_row`].
+const ACCOUNT_COLUMNS: [&str; 8] = [
+    "client",
+    "currency",
+    "available",
+    "held",
+    "total",
+    "locked",
+    "closed",
+    "overdraft",
+];
+
+/// The accounts CSV columns added after `client,available,held,total,locked`,
+/// which are only written when some account uses them, so outputs without
+/// currencies, closures or overdrafts keep the original layout.
+#[derive(Debug, Clone, Copy, Default)]
+struct OptionalColumns {
+    currency: bool,
+    closed: bool,
+    overdraft: bool,
+}
+
+impl OptionalColumns {
+    fn of(accounts: &[&Account]) -> Self {
+        accounts
+            .iter()
+            .fold(Self::default(), |columns, account| OptionalColumns {
+                currency: columns.currency || account.currency != Currency::default(),
+                closed: columns.closed || account.closed,
+                overdraft: columns.overdraft || !account.overdraft_limit.is_zero(),
+            })
+    }
+
+    /// Keeps the fields of a full row, or of [`ACCOUNT_COLUMNS`], that are
+    /// written.
+    fn select<T>(self, row: [T; 8]) -> Vec<T> {
+        let written = [
+            true,
+            self.currency,
+            true,
+            true,
+            true,
+            true,
+            self.closed,
+            self.overdraft,
+        ];
+        row.into_iter()
+            .zip(written)
+            .filter_map(|(field, written)| written.then_some(field))
+            .collect()
+    }
+}
+
+/// Formats an account as a row with every column of the accounts CSV.
+pub(crate) fn account_row(account: &OutputRecord) -> [String; 8] {
+    [
+        account.client_id.to_string(),
+        account.currency.to_string(),
+        format_amount(account.available),
+        format_amount(account.held),
+        format_amount(account.total),
+        account.locked.to_string(),
+        account.closed.to_string(),
+        format_amount(account.overdraft),
+    ]
+}
+
+/// Formats `amount` truncated to 4 decimal places, like `{:.4}`, which panics
+/// on amounts too large to print with 4 places; those get as many as fit.
+pub(crate) fn format_amount(amount: Decimal) -> String {
+    let mut amount = amount.trunc_with_scale(4);
+    amount.rescale(4);
+    amount.to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::engine::PaymentEngine;
     use crate::errors::PaymentError;
-    use crate::models::InputRecord;
+    use crate::models::{ClientId, Currency, InputRecord, TransactionType, TxId};
     use rstest::rstest;
+    use rust_decimal_macros::dec;
     use std::io::Cursor;
 
     /// Helper to run tests with CSV input and capture output.
@@ -144,6 +609,76 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[rstest]
+    #[case::currency(
+        "type,client,tx,amount,counterparty,currency\n\
+        deposit,1,1,1.0,,EUR\n\
+        deposit,2,2,2.0,,",
+        "client,currency,available,held,total,locked\n\
+        1,EUR,1.0000,0.0000,1.0000,false\n\
+        2,,2.0000,0.0000,2.0000,false"
+    )]
+    #[case::closed(
+        "type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        deposit,2,2,2.0\n\
+        withdrawal,2,3,2.0\n\
+        close,2,4,",
+        "client,available,held,total,locked,closed\n\
+        1,1.0000,0.0000,1.0000,false,false\n\
+        2,0.0000,0.0000,0.0000,false,true"
+    )]
+    #[case::overdraft(
+        "type,client,tx,amount\n\
+        deposit,1,1,1.0\n\
+        admin,2,2,5.0",
+        "client,available,held,total,locked,overdraft\n\
+        1,1.0000,0.0000,1.0000,false,0.0000\n\
+        2,0.0000,0.0000,0.0000,false,5.0000"
+    )]
+    fn test_write_accounts_optional_columns(#[case] input: &str, #[case] expected: &str) {
+        let result = super::tests::run_test_csv(input).unwrap();
+        assert_eq!(result, expected);
+    }
+
+    #[rstest]
+    fn test_write_accounts_keeps_order_across_chunks() {
+        let mut engine = PaymentEngine::new();
+        let clients = (2 * FORMAT_CHUNK + 100) as ClientId;
+        for client_id in (1..=clients).rev() {
+            let record = InputRecord {
+                record_type: TransactionType::Deposit,
+                client_id,
+                tx_id: TxId::from(client_id),
+                amount: Some(Decimal::new(client_id as i64, 2)),
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            };
+            engine.process(record).unwrap();
+        }
+
+        let mut accounts = engine.get_accounts();
+        accounts.sort_by_key(|a| a.client_id);
+        let mut expected = "client,available,held,total,locked\n".to_string();
+        for account in &accounts {
+            expected.push_str(
+                &OptionalColumns::default()
+                    .select(account_row(account))
+                    .join(","),
+            );
+            expected.push('\n');
+        }
+
+        let mut output = Vec::new();
+        write_accounts(&engine, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
     #[rstest]
     fn test_process_transactions_error_branch_is_covered() {
         use std::io::Write;
@@ -161,4 +696,251 @@ mod tests {
         // Optionally, check that no accounts were created
         assert!(engine.get_accounts().is_empty());
     }
+
+    #[rstest]
+    #[case(dec!(2), "2.0000")]
+    #[case(dec!(1.23456), "1.2345")]
+    #[case(dec!(-0.5), "-0.5000")]
+    #[case(Decimal::MAX, "79228162514264337593543950335")]
+    fn test_format_amount(#[case] amount: Decimal, #[case] expected: &str) {
+        assert_eq!(format_amount(amount), expected);
+    }
+
+    #[rstest]
+    fn test_process_reader_from_memory() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     withdrawal,1,2,4.0,\n\
+                     this_is_bad_data";
+
+        let mut engine = PaymentEngine::new();
+        let result = process_reader(input.as_bytes(), &mut engine);
+
+        assert!(result.is_ok());
+        let accounts = engine.get_accounts();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].available, rust_decimal_macros::dec!(6.0));
+    }
+
+    #[rstest]
+    fn test_read_records_with_uuid_tx_ids() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,67e55044-10b1-426f-9247-bb680e5fe0c8,10.0\n\
+                     deposit,1,9f1c3a3e-0000-4000-8000-000000000001,5.0\n\
+                     dispute,1,67E55044-10B1-426F-9247-BB680E5FE0C8,\n\
+                     deposit,1,not-a-uuid,1.0";
+
+        let tx_ids = TxIdMap::new(crate::tx_ids::TxIdFormat::Uuid);
+        let records: Vec<_> = read_records_with_tx_ids(input.as_bytes(), tx_ids).collect();
+        let tx_ids: Vec<_> = records
+            .iter()
+            .map(|r| r.parsed.as_ref().map(|record| record.tx_id).ok())
+            .collect();
+        assert_eq!(tx_ids, [Some(1), Some(2), Some(1), None]);
+        assert_eq!(records[3].raw, "deposit,1,not-a-uuid,1.0");
+    }
+
+    #[rstest]
+    #[case(b',', "type,client,tx,amount\ndeposit,1,1,\"1000.5\"")]
+    #[case(b';', "type;client;tx;amount\ndeposit;1;1;1000.5")]
+    #[case(b'\t', "type\tclient\ttx\tamount\ndeposit\t1\t1\t1000.5")]
+    fn test_read_records_with_delimiter(#[case] delimiter: u8, #[case] input: &str) {
+        let dialect = CsvDialect::new().with_delimiter(delimiter);
+        let record = read_records_with_dialect(input.as_bytes(), &dialect, TxIdMap::default())
+            .next()
+            .unwrap();
+        assert_eq!(record.parsed.unwrap().amount, Some(dec!(1000.5)));
+    }
+
+    #[rstest]
+    fn test_read_records_with_quoting_settings() {
+        let input = "type|client|tx|idempotency_key|amount\n\
+                     deposit|1|1|'a\\'b'|1.0\n\
+                     deposit|1|2|\"c\"|1.0";
+        let dialect = CsvDialect::new()
+            .with_delimiter(b'|')
+            .with_quote(Some(b'\''))
+            .with_escape(Some(b'\\'));
+        let keys: Vec<Option<String>> =
+            read_records_with_dialect(input.as_bytes(), &dialect, TxIdMap::default())
+                .map(|record| record.parsed.unwrap().idempotency_key)
+                .collect();
+        assert_eq!(keys, [Some("a'b".to_string()), Some("\"c\"".to_string())]);
+
+        let dialect = CsvDialect::new().with_quote(None);
+        let input = "type,client,tx,amount,idempotency_key\ndeposit,1,1,1.0,\"k\"";
+        let record = read_records_with_dialect(input.as_bytes(), &dialect, TxIdMap::default())
+            .next()
+            .unwrap();
+        assert_eq!(
+            record.parsed.unwrap().idempotency_key.as_deref(),
+            Some("\"k\"")
+        );
+    }
+
+    #[rstest]
+    fn test_read_records_with_header_aliases() {
+        let input = "Kind,Customer,TXN_ID,amount\n\
+                     deposit,1,abc,10.0\n\
+                     withdrawal,1,def,4.0";
+        let dialect = CsvDialect::new()
+            .with_alias("kind", "type")
+            .and_then(|dialect| dialect.with_alias("customer", "client"))
+            .and_then(|dialect| dialect.with_alias("txn_id", "tx"))
+            .unwrap();
+        let tx_ids = TxIdMap::new(TxIdFormat::Text);
+        let mut engine = PaymentEngine::new();
+        let report = process_records(
+            read_records_with_dialect(input.as_bytes(), &dialect, tx_ids),
+            &mut engine,
+        )
+        .unwrap();
+        assert!(report.skipped.is_empty());
+        assert_eq!(engine.get_accounts()[0].available, dec!(6.0));
+
+        let err = CsvDialect::new().with_alias("memo", "note").unwrap_err();
+        assert!(err.to_string().contains("unknown column 'note'"), "{}", err);
+    }
+
+    #[rstest]
+    fn test_read_records_without_header() {
+        let input = "deposit,1,1,10.0\n\
+                     deposit,2,2,5.0,,EUR\n\
+                     dispute,1,1";
+        let dialect = CsvDialect::new().without_header();
+        let records: Vec<RawRecord> =
+            read_records_with_dialect(input.as_bytes(), &dialect, TxIdMap::default()).collect();
+        let decoded: Vec<(u64, TransactionType, ClientId, Option<Decimal>, &str)> = records
+            .iter()
+            .map(|record| {
+                let parsed = record.parsed.as_ref().unwrap();
+                (
+                    record.line,
+                    parsed.record_type,
+                    parsed.client_id,
+                    parsed.amount,
+                    parsed.currency.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            decoded,
+            [
+                (1, TransactionType::Deposit, 1, Some(dec!(10.0)), ""),
+                (2, TransactionType::Deposit, 2, Some(dec!(5.0)), "EUR"),
+                (3, TransactionType::Dispute, 1, None, ""),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case(CsvDialect::new(), vec![vec![], vec![]])]
+    #[case(
+        CsvDialect::new().with_metadata(),
+        vec![vec![("batch", "7"), ("memo", "rent")], vec![("batch", "7")]]
+    )]
+    fn test_read_records_with_extra_columns(
+        #[case] dialect: CsvDialect,
+        #[case] expected: Vec<Vec<(&str, &str)>>,
+    ) {
+        let input = "type,client,tx,amount,memo,batch\n\
+                     deposit,1,1,10.0,rent,7\n\
+                     withdrawal,1,2,4.0,,7";
+        let records: Vec<InputRecord> =
+            read_records_with_dialect(input.as_bytes(), &dialect, TxIdMap::default())
+                .map(|record| record.parsed.unwrap())
+                .collect();
+        assert_eq!(records[1].amount, Some(dec!(4.0)));
+        for (record, expected) in records.iter().zip(expected) {
+            let mut metadata: Vec<(&str, &str)> = record
+                .metadata
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect();
+            metadata.sort();
+            assert_eq!(metadata, expected);
+        }
+    }
+
+    #[rstest]
+    #[case(CsvDialect::new(), Ok(TransactionType::Deposit), Err("'payout'"))]
+    #[case(
+        CsvDialect::new().with_type_alias("PayOut", TransactionType::Withdrawal),
+        Ok(TransactionType::Deposit),
+        Ok(TransactionType::Withdrawal)
+    )]
+    #[case(
+        CsvDialect::new()
+            .with_strict_types()
+            .with_type_alias("payout", TransactionType::Withdrawal),
+        Err("'Deposit'"),
+        Ok(TransactionType::Withdrawal)
+    )]
+    fn test_read_records_with_type_names(
+        #[case] dialect: CsvDialect,
+        #[case] deposit: Result<TransactionType, &str>,
+        #[case] payout: Result<TransactionType, &str>,
+    ) {
+        let input = "type,client,tx,amount\n\
+                     Deposit,1,1,10.0\n\
+                     payout,1,2,4.0";
+        let types: Vec<Result<TransactionType, String>> =
+            read_records_with_dialect(input.as_bytes(), &dialect, TxIdMap::default())
+                .map(|record| {
+                    record
+                        .parsed
+                        .map(|record| record.record_type)
+                        .map_err(|e| e.to_string())
+                })
+                .collect();
+        for (parsed, expected) in types.iter().zip([deposit, payout]) {
+            match (parsed, expected) {
+                (Ok(parsed), Ok(expected)) => assert_eq!(*parsed, expected),
+                (Err(err), Err(name)) => assert!(
+                    err.contains(&format!("unknown transaction type {}", name)),
+                    "{}",
+                    err
+                ),
+                _ => panic!("{:?} for {:?}", parsed, expected),
+            }
+        }
+    }
+
+    #[rstest]
+    fn test_read_records_with_amount_format() {
+        let input = "type;client;tx;amount\n\
+                     deposit;1;1;1.234,56\n\
+                     withdrawal;1;2;(12,00)\n\
+                     withdrawal;1;3;1,2,3\n\
+                     dispute;1;1;";
+        let dialect = CsvDialect::new()
+            .with_delimiter(b';')
+            .with_amount_format(AmountFormat::European);
+        let records: Vec<RawRecord> =
+            read_records_with_dialect(input.as_bytes(), &dialect, TxIdMap::default()).collect();
+        let amount = |record: &RawRecord| record.parsed.as_ref().unwrap().amount;
+        assert_eq!(amount(&records[0]), Some(dec!(1234.56)));
+        assert_eq!(amount(&records[1]), Some(dec!(-12.00)));
+        let err = records[2].parsed.as_ref().unwrap_err().to_string();
+        assert!(err.contains("invalid amount '1,2,3'"), "{}", err);
+        assert_eq!(amount(&records[3]), None);
+    }
+
+    #[rstest]
+    fn test_ids_wider_than_default_need_wide_ids() {
+        let input = "type,client,tx,amount\n\
+                     deposit,70000,5000000000,1.0\n\
+                     dispute,70000,5000000000,";
+
+        let mut engine = PaymentEngine::new();
+        let report = process_reader(input.as_bytes(), &mut engine).unwrap();
+
+        if cfg!(feature = "wide-ids") {
+            assert!(report.skipped.is_empty());
+            assert_eq!(engine.get_accounts()[0].held, dec!(1.0));
+        } else {
+            assert_eq!(report.skipped.len(), 2);
+            assert!(engine.get_accounts().is_empty());
+        }
+    }
 }
@@ -0,0 +1,287 @@
+//! Comparison of two sets of final accounts, such as the output of a new
+//! engine version against golden outputs of the same inputs.
+//!
+//! Amounts are compared as numbers, so `1.5` and `1.5000` are the same
+//! balance.
+
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::models::{ClientId, Currency, OutputRecord};
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// One row of an accounts CSV. The columns added after the first releases
+/// are optional, so older golden outputs can still be read.
+#[derive(Debug, Deserialize)]
+struct AccountRow {
+    client: ClientId,
+    #[serde(default)]
+    currency: Currency,
+    available: Decimal,
+    held: Decimal,
+    total: Option<Decimal>,
+    locked: bool,
+    #[serde(default)]
+    closed: bool,
+    #[serde(default)]
+    overdraft: Decimal,
+}
+
+impl From<AccountRow> for OutputRecord {
+    fn from(row: AccountRow) -> Self {
+        OutputRecord {
+            client_id: row.client,
+            currency: row.currency,
+            available: row.available,
+            held: row.held,
+            total: row
+                .total
+                .unwrap_or_else(|| row.available.saturating_add(row.held)),
+            locked: row.locked,
+            closed: row.closed,
+            overdraft: row.overdraft,
+        }
+    }
+}
+
+/// A balance or flag that differs between the expected and actual accounts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AccountDiff {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub currency: Currency,
+    /// The accounts column that differs, or `account` if only one side has
+    /// the account at all.
+    pub field: &'static str,
+    /// The expected value, or `missing` (`present`) for an `account` row.
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Reads the accounts of an accounts CSV, as written by
+/// [`write_accounts`](crate::csv_handler::write_accounts).
+pub fn read_accounts_csv<R: Read>(reader: R) -> Result<Vec<OutputRecord>, PaymentError> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    rdr.deserialize::<AccountRow>()
+        .map(|row| Ok(row?.into()))
+        .collect()
+}
+
+/// Reads the accounts of the file at `path`: an engine snapshot (see
+/// [`PaymentEngine::snapshot`]) if it starts with `{`, an accounts CSV
+/// otherwise.
+pub fn read_accounts<P: AsRef<Path>>(path: P) -> Result<Vec<OutputRecord>, PaymentError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let snapshot = reader.fill_buf()?.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{');
+    if snapshot {
+        let mut engine = PaymentEngine::new();
+        engine.restore(reader)?;
+        Ok(engine.get_accounts())
+    } else {
+        read_accounts_csv(reader)
+    }
+}
+
+/// Indexes accounts by client and currency, rejecting any listed twice.
+fn by_account(
+    accounts: &[OutputRecord],
+) -> Result<BTreeMap<(ClientId, Currency), &OutputRecord>, PaymentError> {
+    let mut indexed = BTreeMap::new();
+    for account in accounts {
+        if indexed
+            .insert((account.client_id, account.currency), account)
+            .is_some()
+        {
+            return Err(PaymentError::InvalidSnapshot(format!(
+                "account of client {} in currency '{}' listed twice",
+                account.client_id, account.currency
+            )));
+        }
+    }
+    Ok(indexed)
+}
+
+/// Lists every difference between the `expected` and `actual` accounts, by
+/// client, currency and column. No differences means the same accounts with
+/// the same balances, in whatever order they were listed.
+pub fn diff_accounts(
+    expected: &[OutputRecord],
+    actual: &[OutputRecord],
+) -> Result<Vec<AccountDiff>, PaymentError> {
+    let mut expected = by_account(expected)?;
+    let actual = by_account(actual)?;
+    let mut diffs = Vec::new();
+    for (key, account) in actual {
+        let Some(expected) = expected.remove(&key) else {
+            diffs.push(account_diff(key, "missing", "present"));
+            continue;
+        };
+        let fields = [
+            ("available", expected.available != account.available),
+            ("held", expected.held != account.held),
+            ("total", expected.total != account.total),
+            ("locked", expected.locked != account.locked),
+            ("closed", expected.closed != account.closed),
+            ("overdraft", expected.overdraft != account.overdraft),
+        ];
+        for (field, differs) in fields {
+            if differs {
+                diffs.push(AccountDiff {
+                    client_id: key.0,
+                    currency: key.1,
+                    field,
+                    expected: field_value(expected, field),
+                    actual: field_value(account, field),
+                });
+            }
+        }
+    }
+    diffs.extend(
+        expected
+            .into_keys()
+            .map(|key| account_diff(key, "present", "missing")),
+    );
+    diffs.sort_by_key(|diff| (diff.client_id, diff.currency));
+    Ok(diffs)
+}
+
+fn account_diff(key: (ClientId, Currency), expected: &str, actual: &str) -> AccountDiff {
+    AccountDiff {
+        client_id: key.0,
+        currency: key.1,
+        field: "account",
+        expected: expected.to_string(),
+        actual: actual.to_string(),
+    }
+}
+
+/// The value of an accounts column, amounts at full precision.
+fn field_value(account: &OutputRecord, field: &str) -> String {
+    match field {
+        "available" => account.available.normalize().to_string(),
+        "held" => account.held.normalize().to_string(),
+        "total" => account.total.normalize().to_string(),
+        "locked" => account.locked.to_string(),
+        "closed" => account.closed.to_string(),
+        _ => account.overdraft.normalize().to_string(),
+    }
+}
+
+/// Writes the differences as CSV (`client,currency,field,expected,actual`).
+pub fn write_diff_csv<W: Write>(diffs: &[AccountDiff], writer: W) -> Result<(), PaymentError> {
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    wtr.write_record(["client", "currency", "field", "expected", "actual"])?;
+    for diff in diffs {
+        wtr.serialize(diff)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    const EXPECTED: &str = "client,currency,available,held,total,locked,closed,overdraft
+1,,1.5000,0.0000,1.5000,false,false,0.0000
+2,,5.0000,2.0000,7.0000,false,false,0.0000
+3,EUR,0.0000,0.0000,0.0000,true,false,0.0000
+";
+
+    fn accounts(csv: &str) -> Vec<OutputRecord> {
+        read_accounts_csv(csv.as_bytes()).unwrap()
+    }
+
+    #[rstest]
+    fn test_read_accounts_csv_of_older_outputs() {
+        let accounts = accounts("client,available,held,total,locked\n1,1.5,0.5,2.0,true\n");
+        assert_eq!(
+            accounts,
+            [OutputRecord {
+                client_id: 1,
+                currency: Currency::default(),
+                available: dec!(1.5),
+                held: dec!(0.5),
+                total: dec!(2.0),
+                locked: true,
+                closed: false,
+                overdraft: Decimal::ZERO,
+            }]
+        );
+    }
+
+    #[rstest]
+    fn test_diff_of_equal_accounts_in_another_order_and_format() {
+        let reordered = "client,currency,available,held,total,locked,closed,overdraft
+3,EUR,0,0,0,true,false,0
+2,,5,2,7,false,false,0
+1,,1.5,0,1.5,false,false,0
+";
+        let diffs = diff_accounts(&accounts(EXPECTED), &accounts(reordered)).unwrap();
+        assert!(diffs.is_empty());
+    }
+
+    #[rstest]
+    fn test_diff_accounts() {
+        let actual = "client,currency,available,held,total,locked,closed,overdraft
+1,,1.5001,0.0000,1.5001,false,false,0.0000
+2,,5.0000,2.0000,7.0000,true,false,0.0000
+4,,1.0000,0.0000,1.0000,false,false,0.0000
+";
+        let diffs = diff_accounts(&accounts(EXPECTED), &accounts(actual)).unwrap();
+        let mut output = Vec::new();
+        write_diff_csv(&diffs, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,currency,field,expected,actual
+1,,available,1.5,1.5001
+1,,total,1.5,1.5001
+2,,locked,false,true
+3,EUR,account,present,missing
+4,,account,missing,present
+"
+        );
+    }
+
+    #[rstest]
+    fn test_diff_rejects_accounts_listed_twice() {
+        let twice = format!("{}2,,1,0,1,false,false,0\n", EXPECTED);
+        let err = diff_accounts(&accounts(&twice), &accounts(EXPECTED)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid snapshot: account of client 2 in currency '' listed twice"
+        );
+    }
+
+    #[rstest]
+    fn test_read_accounts_of_a_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = PaymentEngine::new();
+        crate::input::process_records(
+            crate::csv_handler::read_records("type,client,tx,amount\ndeposit,1,1,1.5\n".as_bytes()),
+            &mut engine,
+        )
+        .unwrap();
+        let path = dir.path().join("snapshot.json");
+        engine.snapshot(File::create(&path).unwrap()).unwrap();
+        let csv = dir.path().join("accounts.csv");
+        std::fs::write(&csv, &EXPECTED[..EXPECTED.find("\n2").unwrap() + 1]).unwrap();
+
+        let diffs = diff_accounts(
+            &read_accounts(&csv).unwrap(),
+            &read_accounts(&path).unwrap(),
+        )
+        .unwrap();
+        assert!(diffs.is_empty());
+    }
+}
@@ -1,12 +1,220 @@
+use crate::account_store::AccountStore;
+use crate::aging::ExpiredDispute;
+use crate::audit::{AuditEntry, AuditLog, Mutation};
+use crate::blocklist::Blocklist;
+use crate::client_registry::{ClientInfo, ClientRegistry, KycStatus};
+use crate::config::EngineConfig;
 use crate::errors::PaymentError;
-use crate::models::{Account, InputRecord, TransactionInfo, TransactionState, TransactionType};
-use rust_decimal::Decimal;
-use std::collections::HashMap;
+use crate::events::{EngineEvent, EventListener, Listeners};
+use crate::fees::FeeSchedule;
+use crate::idempotency::IdempotencyKeys;
+use crate::interest::{InterestClock, InterestSchedule, SECONDS_PER_DAY};
+use crate::limits::{LimitTracker, LimitUsage, Limits};
+use crate::models::{
+    Account, ClientId, Currency, InputRecord, OpenDispute, TransactionDirection, TransactionInfo,
+    TransactionState, TransactionType, TxId,
+};
+use crate::policy::{
+    AmountPrecision, ClientMatchMode, DefaultDisputePolicy, DepositsOnlyPolicy, DisputePolicy,
+    DuplicateTxPolicy, ExpiredDisputeAction, LockedAccountPolicy, RiskScorer, RiskVerdict,
+    UnderfundedDisputeMode,
+};
+use crate::rates::RateProvider;
+use crate::reconcile::{Flows, Reconciliation, ReconciliationReport};
+use crate::stats::EngineStats;
+use crate::tx_store::{MemoryTxStore, TxStore};
+use crate::wal::{self, WriteAheadLog};
+use rust_decimal::{Decimal, RoundingStrategy};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
 
-#[derive(Debug, Default)]
+/// Version written by `PaymentEngine::snapshot` and accepted by `restore`.
+const SNAPSHOT_VERSION: u32 = 2;
+
+/// Serialized engine state. Policies and store backends are configuration,
+/// not state, so they aren't part of it.
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    accounts: Vec<Account>,
+    transactions: Vec<(TxId, TransactionInfo)>,
+    counter_legs: Vec<(TxId, TransactionInfo)>,
+    #[serde(default)]
+    interest_clock: Option<InterestClock>,
+    #[serde(default)]
+    spent_tx_ids: Vec<TxId>,
+    /// Missing from snapshots written before flows were tracked; the
+    /// restored balances become the opening balances then.
+    #[serde(default)]
+    flows: Vec<(Currency, Flows)>,
+    #[serde(default)]
+    dispute_opened_at: Vec<(Leg, TxId, u64)>,
+    #[serde(default)]
+    latest_timestamp: Option<u64>,
+    #[serde(default)]
+    pending_chargebacks: Vec<(Leg, TxId)>,
+    /// What counts against the limits, restored only into an engine that
+    /// has limits set.
+    #[serde(default)]
+    limit_usage: LimitUsage,
+    /// Records the risk scorer held, without their extra columns.
+    #[serde(default)]
+    held: Vec<InputRecord>,
+}
+
+/// Accounts are held per client and currency.
+pub(crate) type AccountKey = (ClientId, Currency);
+
+/// Whether a transfer's receiving account can take it, checked before the
+/// sending leg is debited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CreditCheck {
+    Open,
+    Closed,
+    /// Crediting the amount would overflow the account's balances.
+    Overflow,
+}
+
+/// Receiving leg of a transfer whose debit went through.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TransferCredit {
+    pub counterparty: AccountKey,
+    pub amount: Decimal,
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Debug)]
 pub struct PaymentEngine {
-    accounts: HashMap<u16, Account>,
-    transactions: HashMap<u32, TransactionInfo>,
+    accounts: FxHashMap<AccountKey, Account>,
+    /// Durable copy of `accounts`, written through on every balance mutation.
+    account_store: Option<Box<dyn AccountStore>>,
+    transactions: Box<dyn TxStore>,
+    /// Receiving legs of transfers, keyed by the same tx id as the sending leg.
+    counter_legs: Box<dyn TxStore>,
+    /// Tx ids taken by applied records that left nothing in `transactions`
+    /// (declined withdrawals, converts, refunded deposits, ...), so they
+    /// can't be reused either.
+    spent_tx_ids: FxHashSet<TxId>,
+    duplicate_tx: DuplicateTxPolicy,
+    amount_precision: AmountPrecision,
+    dispute_policy: Arc<dyn DisputePolicy>,
+    client_match: ClientMatchMode,
+    /// Disputes allowed per transaction; resolved ones can be re-disputed
+    /// until this is reached.
+    max_disputes: u8,
+    /// How long after a transaction it may still be disputed, if limited.
+    dispute_window: Option<Duration>,
+    /// How long disputes may stay open before `expire_disputes` settles
+    /// them, if limited, and how it does.
+    dispute_max_age: Option<Duration>,
+    expired_disputes: ExpiredDisputeAction,
+    underfunded_disputes: UnderfundedDisputeMode,
+    locked_deposits: LockedAccountPolicy,
+    /// Overdraft limit given to new accounts.
+    overdraft_limit: Decimal,
+    fees: Option<FeeSchedule>,
+    /// Client whose accounts receive the funds written off by chargebacks.
+    suspense_account: Option<ClientId>,
+    /// Quotes the rates of `convert` records; conversions fail without one.
+    rates: Option<Arc<dyn RateProvider>>,
+    interest: Option<InterestSchedule>,
+    /// Day interest has been accrued up to, set by the first timestamped record.
+    interest_clock: Option<InterestClock>,
+    /// Keys of applied records that carried an idempotency key.
+    idempotency: IdempotencyKeys,
+    /// Disputes waiting for funds, per account, in the order they were opened.
+    queued_disputes: FxHashMap<AccountKey, Vec<(Leg, TxId)>>,
+    /// Queued disputes charged back already, applied once their funds are
+    /// held.
+    pending_chargebacks: FxHashSet<(Leg, TxId)>,
+    /// When the open disputes were filed, for those whose dispute record
+    /// carried a timestamp.
+    dispute_opened_at: FxHashMap<(Leg, TxId), u64>,
+    /// Newest timestamp of the records seen, the time disputes age against.
+    latest_timestamp: Option<u64>,
+    /// Velocity and amount limits, with what counts against them.
+    limits: Option<LimitTracker>,
+    scorer: Option<Arc<dyn RiskScorer>>,
+    /// Records the scorer held, in the order they arrived.
+    held: Vec<InputRecord>,
+    /// Clients whose records are rejected, and whose accounts are locked.
+    blocklist: Blocklist,
+    /// What's known about the clients: their currency, overdraft limit and
+    /// KYC status, for the policies, and what the outputs echo.
+    clients: ClientRegistry,
+    stats: EngineStats,
+    /// Funds that entered and left the engine, per currency, which the
+    /// account totals are reconciled against.
+    flows: FxHashMap<Currency, Flows>,
+    /// Balance mutations made so far, interest postings aside, which tells
+    /// applied records from ignored ones.
+    mutations: u64,
+    audit_log: Option<AuditLog>,
+    wal: Option<WriteAheadLog>,
+    listeners: Listeners,
+    /// Per-client balance mutations, kept only when statements are enabled.
+    history: Option<FxHashMap<ClientId, Vec<AuditEntry>>>,
+    /// Extra input columns of the record being applied, for its audit
+    /// entries.
+    metadata: HashMap<String, String>,
+}
+
+impl Default for PaymentEngine {
+    fn default() -> Self {
+        Self {
+            accounts: FxHashMap::default(),
+            account_store: None,
+            transactions: Box::new(MemoryTxStore::new()),
+            counter_legs: Box::new(MemoryTxStore::new()),
+            spent_tx_ids: FxHashSet::default(),
+            duplicate_tx: DuplicateTxPolicy::default(),
+            amount_precision: AmountPrecision::default(),
+            dispute_policy: Arc::new(DefaultDisputePolicy),
+            client_match: ClientMatchMode::default(),
+            max_disputes: 1,
+            dispute_window: None,
+            dispute_max_age: None,
+            expired_disputes: ExpiredDisputeAction::default(),
+            underfunded_disputes: UnderfundedDisputeMode::default(),
+            locked_deposits: LockedAccountPolicy::default(),
+            overdraft_limit: Decimal::ZERO,
+            fees: None,
+            suspense_account: None,
+            rates: None,
+            interest: None,
+            interest_clock: None,
+            idempotency: IdempotencyKeys::default(),
+            queued_disputes: FxHashMap::default(),
+            pending_chargebacks: FxHashSet::default(),
+            dispute_opened_at: FxHashMap::default(),
+            latest_timestamp: None,
+            limits: None,
+            scorer: None,
+            held: Vec::new(),
+            blocklist: Blocklist::new(),
+            clients: ClientRegistry::new(),
+            stats: EngineStats::default(),
+            flows: FxHashMap::default(),
+            mutations: 0,
+            audit_log: None,
+            wal: None,
+            listeners: Listeners::default(),
+            history: None,
+            metadata: HashMap::new(),
+        }
+    }
+}
+
+/// Identifies which store holds a referenced transaction leg.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Leg {
+    Primary,
+    Counter,
 }
 
 impl PaymentEngine {
@@ -14,656 +222,6130 @@ impl PaymentEngine {
         Self::default()
     }
 
-    /// Retrieves an account, creating it if it doesn't exist.
-    fn get_or_create_account(&mut self, client_id: u16) -> &mut Account {
-        self.accounts
-            .entry(client_id)
-            .or_insert_with(|| Account::new(client_id))
+    /// Like [`new`](Self::new), with room for `accounts` accounts and `txs`
+    /// stored transactions, so a file of known size is processed without the
+    /// maps rehashing along the way. Only the in-memory transaction store
+    /// takes the hint; `with_tx_store` replaces it.
+    pub fn with_capacity(accounts: usize, txs: usize) -> Self {
+        Self {
+            accounts: FxHashMap::with_capacity_and_hasher(accounts, Default::default()),
+            transactions: Box::new(MemoryTxStore::with_capacity(txs)),
+            ..Self::default()
+        }
     }
 
-    /// Processes a single transaction record.
-    pub fn process(&mut self, record: InputRecord) -> Result<(), PaymentError> {
-        let tx_id = record.tx_id;
+    /// Replaces the policy consulted before opening a dispute.
+    pub fn with_dispute_policy<P: DisputePolicy + 'static>(mut self, policy: P) -> Self {
+        self.dispute_policy = Arc::new(policy);
+        self
+    }
 
-        // Check if the transaction ID is already processed (except for dispute/resolve/chargeback)
-        if matches!(
-            record.record_type,
-            TransactionType::Deposit | TransactionType::Withdrawal
-        ) && self.transactions.contains_key(&tx_id)
-        {
-            // Ignore duplicate deposit/withdrawal transactions silently or log a warning.
-            // For this exercise, we'll ignore them.
-            return Ok(());
+    /// Replaces the store holding disputable transactions (in memory by default).
+    pub fn with_tx_store<S: TxStore + 'static>(mut self, store: S) -> Self {
+        self.transactions = Box::new(store);
+        self
+    }
+
+    /// Keeps accounts in `store` as well as in memory, loading the ones it
+    /// already holds. Every balance mutation is written through before the
+    /// next record is applied; interest accrued since the last mutation and
+    /// the stored transactions aren't persisted.
+    pub fn with_account_store<S: AccountStore + 'static>(
+        mut self,
+        store: S,
+    ) -> Result<Self, PaymentError> {
+        for account in store.accounts()? {
+            let opening = &mut self.flows.entry(account.currency).or_default().opening;
+            *opening = opening.saturating_add(account.total());
+            self.accounts
+                .insert((account.client_id, account.currency), account);
         }
+        self.account_store = Some(Box::new(store));
+        Ok(self)
+    }
 
-        match record.record_type {
-            TransactionType::Deposit => self.handle_deposit(record),
-            TransactionType::Withdrawal => self.handle_withdrawal(record),
-            TransactionType::Dispute => self.handle_dispute(record),
-            TransactionType::Resolve => self.handle_resolve(record),
-            TransactionType::Chargeback => self.handle_chargeback(record),
+    /// Replaces the store holding the receiving legs of transfers.
+    pub fn with_counter_leg_store<S: TxStore + 'static>(mut self, store: S) -> Self {
+        self.counter_legs = Box::new(store);
+        self
+    }
+
+    /// Appends every applied balance mutation to `writer` as CSV (see
+    /// [`AuditEntry`](crate::audit::AuditEntry)). Call `flush_audit_log` once
+    /// processing is done to surface write errors.
+    pub fn with_audit_log<W: Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.audit_log = Some(AuditLog::new(writer));
+        self
+    }
+
+    /// Appends every record to `writer` as a JSON line, once its amount is
+    /// limited to four decimal places, and flushes it before the record is
+    /// applied. `replay` rebuilds the engine from what was written. Duplicates
+    /// and records that fail are logged too, since they can still change the
+    /// state (a blocked client's record locks its account, and any timestamp
+    /// can post interest); only amounts rejected for their precision aren't.
+    pub fn with_write_ahead_log<W: Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.wal = Some(WriteAheadLog::new(writer));
+        self
+    }
+
+    /// Applies the records of a write-ahead log in order, returning how many
+    /// were replayed. Records that fail, as they did when they were logged,
+    /// are passed over. The engine should be fresh and configured like the
+    /// one that wrote the log; an amount it rejects for its precision means
+    /// it isn't, and aborts the replay. Replayed records aren't appended to
+    /// this engine's own log.
+    pub fn replay<R: Read>(&mut self, reader: R) -> Result<u64, PaymentError> {
+        let log = self.wal.take();
+        let result = wal::read_log(reader).try_fold(0, |replayed, entry| {
+            let (line, record) = entry?;
+            let record = self
+                .limit_precision(self.in_client_currency(record))
+                .map_err(|e| PaymentError::AtLine {
+                    line,
+                    source: Box::new(e),
+                })?;
+            let record_type = record.record_type;
+            if let Err(e) = self.observe(record_type, |engine| engine.apply_limited(record)) {
+                tracing::debug!(line, "replayed record failed again: {}", e);
+            }
+            Ok(replayed + 1)
+        });
+        self.wal = log;
+        result
+    }
+
+    /// Registers `listener` to receive every [`EngineEvent`] from now on, after
+    /// the listeners registered before it. Closures and channel senders are
+    /// listeners.
+    pub fn with_event_listener<L: EventListener + 'static>(mut self, listener: L) -> Self {
+        self.listeners.push(Box::new(listener));
+        self
+    }
+
+    /// Keeps every balance mutation per client so `statement` can list them.
+    /// Memory grows with the number of applied transactions.
+    pub fn with_statement_history(mut self) -> Self {
+        self.history = Some(FxHashMap::default());
+        self
+    }
+
+    /// Returns the balance mutations of `client_id` in the order they were
+    /// applied, each with the balances right after it. `None` if statement
+    /// history isn't enabled.
+    pub fn statement(&self, client_id: ClientId) -> Option<&[AuditEntry]> {
+        let history = self.history.as_ref()?;
+        Some(history.get(&client_id).map_or(&[], Vec::as_slice))
+    }
+
+    /// The accounts as an Arrow record batch, one row per account sorted like
+    /// the output (see [`arrow::account_schema`](crate::arrow::account_schema)).
+    #[cfg(feature = "arrow")]
+    pub fn to_record_batch(&self) -> Result<arrow_array::RecordBatch, PaymentError> {
+        crate::arrow::accounts_to_record_batch(self)
+    }
+
+    /// Every client's statement as one Arrow record batch, by client and then
+    /// in the order applied. `None` if statement history isn't enabled.
+    #[cfg(feature = "arrow")]
+    pub fn history_to_record_batch(
+        &self,
+    ) -> Option<Result<arrow_array::RecordBatch, PaymentError>> {
+        let by_client: BTreeMap<_, _> = self.history.as_ref()?.iter().collect();
+        Some(crate::arrow::audit_entries_to_record_batch(
+            by_client.into_values().flatten(),
+        ))
+    }
+
+    /// Flushes buffered audit entries to the audit log writer, if any.
+    pub fn flush_audit_log(&mut self) -> Result<(), PaymentError> {
+        match &mut self.audit_log {
+            Some(log) => log.flush(),
+            None => Ok(()),
         }
     }
 
-    fn handle_deposit(&mut self, record: InputRecord) -> Result<(), PaymentError> {
-        let amount = record.amount.ok_or_else(|| {
-            PaymentError::InvalidTransaction(format!("Deposit {} missing amount", record.tx_id))
-        })?;
-        if amount <= Decimal::ZERO {
+    /// Sets how references naming the wrong client are handled.
+    pub fn with_client_match_mode(mut self, mode: ClientMatchMode) -> Self {
+        self.client_match = mode;
+        self
+    }
+
+    /// Sets how many times a transaction may be disputed (1 by default, so a
+    /// resolved transaction can't be disputed again).
+    pub fn with_max_disputes(mut self, max_disputes: u8) -> Self {
+        self.max_disputes = max_disputes;
+        self
+    }
+
+    /// Rejects disputes filed more than `window` after the disputed
+    /// transaction. Only checked when both records carry a timestamp.
+    pub fn with_dispute_window(mut self, window: Duration) -> Self {
+        self.dispute_window = Some(window);
+        self
+    }
+
+    /// Has [`expire_disputes`](Self::expire_disputes) settle disputes left
+    /// open longer than `max_age` with `action`.
+    pub fn with_dispute_max_age(mut self, max_age: Duration, action: ExpiredDisputeAction) -> Self {
+        self.dispute_max_age = Some(max_age);
+        self.expired_disputes = action;
+        self
+    }
+
+    /// Sets what happens to disputes of deposits whose funds were already spent.
+    pub fn with_underfunded_dispute_mode(mut self, mode: UnderfundedDisputeMode) -> Self {
+        self.underfunded_disputes = mode;
+        self
+    }
+
+    /// Sets how records reusing a taken tx id are handled (dropped silently by
+    /// default).
+    pub fn with_duplicate_tx_policy(mut self, policy: DuplicateTxPolicy) -> Self {
+        self.duplicate_tx = policy;
+        self
+    }
+
+    /// Sets how input amounts with more than four decimal places are handled
+    /// (rejected by default).
+    pub fn with_amount_precision(mut self, precision: AmountPrecision) -> Self {
+        self.amount_precision = precision;
+        self
+    }
+
+    /// Sets how deposits to locked accounts are handled.
+    pub fn with_locked_account_policy(mut self, policy: LockedAccountPolicy) -> Self {
+        self.locked_deposits = policy;
+        self
+    }
+
+    /// Sets the overdraft limit of accounts created from now on. `admin`
+    /// records override it per client.
+    pub fn with_overdraft_limit(mut self, limit: Decimal) -> Self {
+        self.overdraft_limit = limit;
+        self
+    }
+
+    /// Charges fees from `schedule` on every applied transaction.
+    pub fn with_fee_schedule(mut self, schedule: FeeSchedule) -> Self {
+        self.fees = Some(schedule);
+        self
+    }
+
+    /// Credits the funds chargebacks take from clients to `client_id`'s
+    /// account in the same currency, so they stay on the books. Chargebacks
+    /// of withdrawals give the funds back to the client and write nothing
+    /// off.
+    pub fn with_suspense_account(mut self, client_id: ClientId) -> Self {
+        self.suspense_account = Some(client_id);
+        self
+    }
+
+    /// Quotes `convert` records at the rates of `provider`.
+    pub fn with_rate_provider<P: RateProvider + 'static>(mut self, provider: P) -> Self {
+        self.rates = Some(Arc::new(provider));
+        self
+    }
+
+    /// Accrues interest on available balances as timestamped records move the
+    /// clock forward, and posts it every period of `schedule`.
+    pub fn with_interest(mut self, schedule: InterestSchedule) -> Self {
+        self.interest = Some(schedule);
+        self
+    }
+
+    /// Rejects transactions that would break `limits`, timed by their
+    /// `timestamp` (or the newest one seen, for records without one). What
+    /// counts against the limits is part of snapshots.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(LimitTracker::new(limits));
+        self
+    }
+
+    /// Asks `scorer` for a verdict on every record before applying it.
+    /// Rejected records fail with `RiskRejected`; held ones are set aside,
+    /// see [`held`](Self::held).
+    pub fn with_risk_scorer<S: RiskScorer + 'static>(mut self, scorer: S) -> Self {
+        self.scorer = Some(Arc::new(scorer));
+        self
+    }
+
+    /// Rejects every record of the clients on `blocklist`, locking the
+    /// account it names, and transfers to them.
+    pub fn with_blocklist(mut self, blocklist: Blocklist) -> Self {
+        self.blocklist = blocklist;
+        self
+    }
+
+    /// Applies what `clients` says about each client: records that don't
+    /// name a currency are in the client's, new accounts start with the
+    /// client's overdraft limit, and the KYC status restricts what the client
+    /// may do (see [`KycStatus`]).
+    pub fn with_client_registry(mut self, clients: ClientRegistry) -> Self {
+        self.clients = clients;
+        self
+    }
+
+    /// The clients given to `with_client_registry`.
+    pub fn client_registry(&self) -> &ClientRegistry {
+        &self.clients
+    }
+
+    /// What the client registry says about `client_id`, if anything.
+    pub fn client_info(&self, client_id: ClientId) -> Option<&ClientInfo> {
+        self.clients.get(client_id)
+    }
+
+    /// Applies the policies of `config`. The ones it always holds (like the
+    /// duplicate tx policy and overdraft limit) replace those set before;
+    /// the deposits-only dispute policy, the dispute window and maximum age,
+    /// idempotency retention, interest, limits and suspense account only
+    /// replace them when `config` sets them, and are left alone otherwise.
+    pub fn with_config(self, config: &EngineConfig) -> Self {
+        let mut engine = self
+            .with_duplicate_tx_policy(config.duplicate_tx)
+            .with_amount_precision(config.amount_precision)
+            .with_locked_account_policy(config.locked_deposits)
+            .with_underfunded_dispute_mode(config.underfunded_disputes)
+            .with_client_match_mode(config.client_match)
+            .with_max_disputes(config.max_disputes)
+            .with_overdraft_limit(config.overdraft_limit);
+        if config.deposits_only_disputes {
+            engine = engine.with_dispute_policy(DepositsOnlyPolicy);
+        }
+        if let Some(window) = config.dispute_window() {
+            engine = engine.with_dispute_window(window);
+        }
+        if let Some(max_age) = config.dispute_max_age() {
+            engine = engine.with_dispute_max_age(max_age, config.expired_dispute_action);
+        }
+        if let Some(retention) = config.idempotency_retention() {
+            engine = engine.with_idempotency_retention(retention);
+        }
+        if let Some(schedule) = config.interest() {
+            engine = engine.with_interest(schedule);
+        }
+        if let Some(limits) = config.limits() {
+            engine = engine.with_limits(limits);
+        }
+        if let Some(client_id) = config.suspense_account {
+            engine = engine.with_suspense_account(client_id);
+        }
+        engine
+    }
+
+    /// Forgets idempotency keys once records are `retention` newer than the
+    /// one that used them. Keys are kept for the whole run by default.
+    pub fn with_idempotency_retention(mut self, retention: Duration) -> Self {
+        self.idempotency.set_retention(retention);
+        self
+    }
+
+    /// Retrieves an account, creating it if it doesn't exist.
+    fn get_or_create_account(&mut self, key: AccountKey) -> &mut Account {
+        let overdraft_limit = self
+            .client_info(key.0)
+            .and_then(|info| info.overdraft_limit)
+            .unwrap_or(self.overdraft_limit);
+        self.accounts.entry(key).or_insert_with(|| Account {
+            currency: key.1,
+            overdraft_limit,
+            ..Account::new(key.0)
+        })
+    }
+
+    /// Finds the transaction leg referenced by a dispute/resolve/chargeback.
+    /// The receiving leg of a transfer is selected when the record names its client.
+    fn find_leg(
+        &self,
+        tx_id: TxId,
+        client_id: ClientId,
+    ) -> Result<Option<(Leg, TransactionInfo)>, PaymentError> {
+        Ok(match self.counter_legs.get(tx_id)? {
+            Some(info) if info.client_id == client_id => Some((Leg::Counter, info)),
+            _ => self
+                .transactions
+                .get(tx_id)?
+                .map(|info| (Leg::Primary, info)),
+        })
+    }
+
+    /// Rejects a dispute/resolve/chargeback whose client doesn't own the referenced transaction.
+    fn check_client(
+        &self,
+        record: &InputRecord,
+        tx_info: &TransactionInfo,
+    ) -> Result<(), PaymentError> {
+        if self.client_match == ClientMatchMode::Strict && record.client_id != tx_info.client_id {
             return Err(PaymentError::InvalidTransaction(format!(
-                "Deposit amount for tx {} must be positive",
-                record.tx_id
+                "{:?} for tx {} names client {} but the transaction belongs to client {}",
+                record.record_type, record.tx_id, record.client_id, tx_info.client_id
             )));
         }
+        Ok(())
+    }
 
-        let account = self.get_or_create_account(record.client_id);
-        // No locked check needed here, account.deposit will handle it (or allow it).
-        account.deposit(amount);
-
-        // Store deposit info for potential disputes.
-        self.transactions.insert(
-            record.tx_id,
-            TransactionInfo {
-                client_id: record.client_id,
-                amount,
-                state: TransactionState::Normal,
-            },
-        );
+    /// Rejects a record that references a transaction in another currency.
+    fn check_currency(
+        &self,
+        record: &InputRecord,
+        tx_info: &TransactionInfo,
+    ) -> Result<(), PaymentError> {
+        if record.currency != tx_info.currency {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "{:?} for tx {} is in '{}' but the transaction is in '{}'",
+                record.record_type, record.tx_id, record.currency, tx_info.currency
+            )));
+        }
         Ok(())
     }
 
-    fn handle_withdrawal(&mut self, record: InputRecord) -> Result<(), PaymentError> {
-        let amount = record.amount.ok_or_else(|| {
-            PaymentError::InvalidTransaction(format!("Withdrawal {} missing amount", record.tx_id))
-        })?;
-        if amount <= Decimal::ZERO {
+    /// Rejects a dispute filed after the dispute window of its transaction closed.
+    fn check_dispute_window(
+        &self,
+        record: &InputRecord,
+        tx_info: &TransactionInfo,
+    ) -> Result<(), PaymentError> {
+        let (Some(window), Some(filed), Some(happened)) =
+            (self.dispute_window, record.timestamp, tx_info.timestamp)
+        else {
+            return Ok(());
+        };
+        if filed.saturating_sub(happened) > window.as_secs() {
             return Err(PaymentError::InvalidTransaction(format!(
-                "Withdrawal amount for tx {} must be positive",
+                "Dispute for tx {} is past the dispute window",
                 record.tx_id
             )));
         }
-
-        let account = self.get_or_create_account(record.client_id);
-        // account.withdraw will check for locked status.
-        account.withdraw(amount); // We ignore the bool result as per spec.
         Ok(())
     }
 
-    fn handle_dispute(&mut self, record: InputRecord) -> Result<(), PaymentError> {
-        let tx_id = record.tx_id;
-        let tx_info_opt = self.transactions.get(&tx_id).copied(); // Use copied to avoid mutable borrow issues
+    fn leg_store_mut(&mut self, leg: Leg) -> &mut dyn TxStore {
+        match leg {
+            Leg::Primary => self.transactions.as_mut(),
+            Leg::Counter => self.counter_legs.as_mut(),
+        }
+    }
 
-        let tx_info = match tx_info_opt {
-            Some(info) => info,
-            None => return Ok(()), // Ignore if tx doesn't exist.
-        };
+    /// Applies the amount precision policy to the record's amount.
+    fn limit_precision(&self, mut record: InputRecord) -> Result<InputRecord, PaymentError> {
+        if let Some(amount) = record.amount {
+            let limited = self.amount_precision.apply(amount).ok_or_else(|| {
+                PaymentError::InvalidTransaction(format!(
+                    "Amount {} for tx {} has more than 4 decimal places",
+                    amount, record.tx_id
+                ))
+            })?;
+            record.amount = Some(limited);
+        }
+        Ok(record)
+    }
 
-        if tx_info.state != TransactionState::Normal {
-            return Ok(()); // Ignore if not normal.
+    /// Check if the transaction ID is already taken (for records introducing a new one),
+    /// applying the duplicate tx policy. Records with an idempotency key are checked by
+    /// key instead, whatever their type, but can't take over the tx id of an open
+    /// dispute or authorization, whose funds would be left held.
+    fn is_duplicate(&self, record: &InputRecord) -> Result<bool, PaymentError> {
+        if record.idempotency_key.is_some() {
+            if self.idempotency.is_retry(record)? {
+                return Ok(true);
+            }
+            if introduces_tx_id(record.record_type) && self.is_tx_id_open(record.tx_id)? {
+                return Err(PaymentError::InvalidTransaction(format!(
+                    "Tx id {} belongs to an open dispute or authorization",
+                    record.tx_id
+                )));
+            }
+            return Ok(false);
+        }
+        if !(introduces_tx_id(record.record_type) && self.is_tx_id_taken(record.tx_id)?) {
+            return Ok(false);
         }
+        match self.duplicate_tx {
+            DuplicateTxPolicy::Ignore => tracing::debug!("duplicate transaction ignored"),
+            DuplicateTxPolicy::Warn => {
+                tracing::warn!("Duplicate tx id {} ignored", record.tx_id)
+            }
+            DuplicateTxPolicy::Error => {
+                return Err(PaymentError::InvalidTransaction(format!(
+                    "Tx id {} is already taken",
+                    record.tx_id
+                )));
+            }
+        }
+        Ok(true)
+    }
 
-        let account = match self.accounts.get_mut(&tx_info.client_id) {
-            Some(acc) => acc,
-            None => return Ok(()),
-        };
+    fn is_tx_id_taken(&self, tx_id: TxId) -> Result<bool, PaymentError> {
+        Ok(self.spent_tx_ids.contains(&tx_id) || self.transactions.contains(tx_id)?)
+    }
 
-        if account.hold(tx_info.amount) {
-            if let Some(tx_to_update) = self.transactions.get_mut(&tx_id) {
-                tx_to_update.state = TransactionState::Disputed;
+    /// Whether either leg stored under `tx_id` holds or reserves funds.
+    fn is_tx_id_open(&self, tx_id: TxId) -> Result<bool, PaymentError> {
+        for store in [&self.transactions, &self.counter_legs] {
+            let open = store.get(tx_id)?.is_some_and(|info| {
+                matches!(
+                    info.state,
+                    TransactionState::Disputed
+                        | TransactionState::DisputeQueued
+                        | TransactionState::Authorized
+                )
+            });
+            if open {
+                return Ok(true);
             }
         }
+        Ok(false)
+    }
+
+    /// Marks the tx id of an applied record as taken, if its handler didn't
+    /// store it.
+    fn spend_tx_id(&mut self, tx_id: TxId) -> Result<(), PaymentError> {
+        if !self.transactions.contains(tx_id)? {
+            self.spent_tx_ids.insert(tx_id);
+        }
         Ok(())
     }
 
-    fn handle_resolve(&mut self, record: InputRecord) -> Result<(), PaymentError> {
-        let tx_id = record.tx_id;
-        let tx_info_opt = self.transactions.get(&tx_id).copied();
+    /// Processes a single transaction record.
+    pub fn process(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let _span = tracing::debug_span!(
+            "tx",
+            id = record.tx_id,
+            client = record.client_id,
+            kind = ?record.record_type
+        )
+        .entered();
+        self.observe(record.record_type, |engine| engine.apply(record))
+    }
 
-        let tx_info = match tx_info_opt {
-            Some(info) => info,
-            None => return Ok(()),
-        };
+    /// Runs `f` and counts its outcome towards `record_type` in the stats (and
+    /// metrics, when enabled).
+    pub(crate) fn observe<T>(
+        &mut self,
+        record_type: TransactionType,
+        f: impl FnOnce(&mut Self) -> Result<T, PaymentError>,
+    ) -> Result<T, PaymentError> {
+        #[cfg(feature = "metrics")]
+        let started = std::time::Instant::now();
+        let result = f(self);
+        self.stats.record(record_type, result.is_ok());
+        #[cfg(feature = "metrics")]
+        crate::metrics::record(record_type, result.is_ok(), started.elapsed());
+        result
+    }
 
-        if tx_info.state != TransactionState::Disputed {
+    fn apply(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let record = self.limit_precision(self.in_client_currency(record))?;
+        // Logged before anything changes: a record can change the state and
+        // still fail, like a blocked client's, and replaying it has to make
+        // the same changes.
+        if let Some(log) = &mut self.wal {
+            log.append(&record)?;
+        }
+        self.apply_limited(record)
+    }
+
+    /// Applies `record`, its currency and amount already settled by `apply`.
+    fn apply_limited(&mut self, mut record: InputRecord) -> Result<(), PaymentError> {
+        if let Some(timestamp) = record.timestamp {
+            self.latest_timestamp = self.latest_timestamp.max(Some(timestamp));
+            self.advance_clock(timestamp, record.tx_id)?;
+        }
+        self.ensure_not_blocked(&record)?;
+        self.ensure_open((record.client_id, record.currency))?;
+        if self.is_duplicate(&record)? {
             return Ok(());
         }
 
-        let account = match self.accounts.get_mut(&tx_info.client_id) {
-            Some(acc) => acc,
-            None => return Ok(()),
+        // Transfers are screened by `begin_transfer`, which shards call
+        // directly.
+        let screened = record.record_type != TransactionType::Transfer;
+        if screened && !self.screen(&record)? {
+            return Ok(());
+        }
+
+        let key = (record.client_id, record.currency);
+        let (tx_id, record_type) = (record.tx_id, record.record_type);
+        let (amount, now) = (record.amount, self.limit_time(&record));
+        let mutations = self.mutations;
+        let keyed = record.idempotency_key.is_some().then(|| record.clone());
+        // Taken after the clock advanced, so posted interest isn't tagged.
+        self.metadata = std::mem::take(&mut record.metadata);
+        let result = match record.record_type {
+            TransactionType::Deposit => self.handle_deposit(record),
+            TransactionType::Withdrawal => self.handle_withdrawal(record),
+            TransactionType::Dispute => self.handle_dispute(record),
+            TransactionType::Resolve => self.handle_resolve(record),
+            TransactionType::Chargeback => self.handle_chargeback(record),
+            TransactionType::Transfer => self.handle_transfer(record),
+            TransactionType::Refund => self.handle_refund(record),
+            TransactionType::Auth => self.handle_auth(record),
+            TransactionType::Capture => self.handle_capture(record),
+            TransactionType::Void => self.handle_void(record),
+            TransactionType::Close => self.handle_close(record),
+            TransactionType::Admin => self.handle_admin(record),
+            TransactionType::Convert => self.handle_convert(record),
+            TransactionType::CreditAdjustment => self.handle_credit_adjustment(record),
+            TransactionType::DebitAdjustment => self.handle_debit_adjustment(record),
         };
+        self.metadata.clear();
+        result?;
+        if let Some(limits) = self.limits.as_mut().filter(|_| screened) {
+            limits.note(key.0, record_type, amount, now, self.mutations > mutations);
+        }
+        if introduces_tx_id(record_type) {
+            self.spend_tx_id(tx_id)?;
+        }
+        if let Some(record) = keyed {
+            self.idempotency.remember(&record);
+        }
+        self.retry_queued_disputes(key)
+    }
 
-        if account.release(tx_info.amount) {
-            self.transactions.remove(&tx_id);
+    /// Accrues interest up to the day of `timestamp`, posting it at every
+    /// period boundary on the way under `tx_id`, the record that moved the
+    /// clock. Timestamps before the current day are ignored.
+    pub(crate) fn advance_clock(
+        &mut self,
+        timestamp: u64,
+        tx_id: TxId,
+    ) -> Result<(), PaymentError> {
+        let Some(schedule) = self.interest else {
+            return Ok(());
+        };
+        let day = timestamp / SECONDS_PER_DAY;
+        let mut clock = *self.interest_clock.get_or_insert(InterestClock {
+            day,
+            period_start: day,
+        });
+        while clock.day < day {
+            let period_end = clock.period_start + u64::from(schedule.period_days());
+            let until = day.min(period_end);
+            let days = until - clock.day;
+            for account in self.accounts.values_mut() {
+                if account.available > Decimal::ZERO && !account.locked && !account.closed {
+                    account.accrued_interest += schedule.accrue(account.available, days);
+                }
+            }
+            clock.day = until;
+            if until == period_end {
+                clock.period_start = until;
+                self.interest_clock = Some(clock);
+                self.post_interest(tx_id)?;
+            }
         }
+        self.interest_clock = Some(clock);
+        Ok(())
+    }
 
+    /// Credits every open account with its accrued interest, rounded to 4
+    /// decimal places. The rounding remainder carries over to the next period.
+    fn post_interest(&mut self, tx_id: TxId) -> Result<(), PaymentError> {
+        let mut keys: Vec<AccountKey> = self.accounts.keys().copied().collect();
+        // Sorted so the audit log doesn't depend on hash order.
+        keys.sort_unstable();
+        for key in keys {
+            let Some(account) = self.accounts.get_mut(&key) else {
+                continue;
+            };
+            let interest = InterestSchedule::postable(account.accrued_interest);
+            if account.closed || interest <= Decimal::ZERO {
+                continue;
+            }
+            account.deposit(interest)?;
+            account.accrued_interest -= interest;
+            self.stats.interest_paid += interest;
+            self.record_mutation(tx_id, Mutation::Interest, interest, key)?;
+        }
         Ok(())
     }
 
-    fn handle_chargeback(&mut self, record: InputRecord) -> Result<(), PaymentError> {
-        let tx_id = record.tx_id;
-        let tx_info_opt = self.transactions.get(&tx_id).copied();
+    /// Returns true if the account `key` exists in this engine and is closed.
+    pub(crate) fn is_closed(&self, key: AccountKey) -> bool {
+        self.accounts.get(&key).is_some_and(|a| a.closed)
+    }
 
-        let tx_info = match tx_info_opt {
-            Some(info) => info,
-            None => return Ok(()),
-        };
+    /// Returns true if `amount` can be credited to the account `key` without
+    /// overflowing it. Accounts that don't exist yet can take any amount.
+    fn can_credit(&self, key: AccountKey, amount: Decimal) -> bool {
+        self.accounts
+            .get(&key)
+            .is_none_or(|account| account.can_deposit(amount))
+    }
 
-        if tx_info.state != TransactionState::Disputed {
-            return Ok(());
+    /// Checks whether the account `key` in this engine can receive a transfer
+    /// of `amount`.
+    pub(crate) fn check_credit(&self, key: AccountKey, amount: Option<Decimal>) -> CreditCheck {
+        if self.is_closed(key) {
+            CreditCheck::Closed
+        } else if amount.is_some_and(|amount| !self.can_credit(key, amount)) {
+            CreditCheck::Overflow
+        } else {
+            CreditCheck::Open
         }
+    }
 
-        let account = match self.accounts.get_mut(&tx_info.client_id) {
-            Some(acc) => acc,
-            None => return Ok(()),
-        };
-
-        if account.chargeback(tx_info.amount) {
-            self.transactions.remove(&tx_id);
+    fn ensure_open(&self, key: AccountKey) -> Result<(), PaymentError> {
+        if self.is_closed(key) {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Account {} is closed",
+                key.0
+            )));
         }
         Ok(())
     }
 
-    /// Returns a vector of all accounts formatted for output.
-    pub fn get_accounts(&self) -> Vec<crate::models::OutputRecord> {
-        self.accounts
-            .values()
-            .map(|acc| acc.to_output_record())
-            .collect()
+    /// Fails with `Blocked` if the client of `record` is on the blocklist,
+    /// locking the account the record names first.
+    fn ensure_not_blocked(&mut self, record: &InputRecord) -> Result<(), PaymentError> {
+        if !self.blocklist.contains(record.client_id) {
+            return Ok(());
+        }
+        let key = record.account_key();
+        let account = self.get_or_create_account(key);
+        if !account.locked {
+            account.locked = true;
+            self.record_mutation(record.tx_id, Mutation::Block, Decimal::ZERO, key)?;
+        }
+        Err(PaymentError::Blocked(format!(
+            "client {} is on the blocklist",
+            record.client_id
+        )))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::models::{Account, TransactionType};
-    use rstest::rstest;
-    use rust_decimal_macros::dec;
+    /// Puts `record` in its client's currency if it doesn't name one.
+    fn in_client_currency(&self, mut record: InputRecord) -> InputRecord {
+        if record.currency == Currency::default() {
+            if let Some(currency) = self
+                .client_info(record.client_id)
+                .and_then(|info| info.currency)
+            {
+                record.currency = currency;
+            }
+        }
+        record
+    }
 
-    #[rstest]
-    fn test_locked_account_behavior() {
-        let mut acc = Account::new(1);
-        acc.available = dec!(100.0);
-        acc.locked = true;
+    /// Fails with `KycRestricted` if the KYC status of the client of
+    /// `record` doesn't allow it: a client pending KYC may only receive
+    /// funds, and one that failed it may move no funds at all. Disputes and
+    /// their follow-ups and operator records are never restricted.
+    /// Transfers to a client that failed KYC are checked by
+    /// `begin_transfer`.
+    fn check_kyc(&self, record: &InputRecord) -> Result<(), PaymentError> {
+        let Some(status) = self
+            .client_info(record.client_id)
+            .and_then(|info| info.kyc_status)
+        else {
+            return Ok(());
+        };
+        // Whether the record moves funds out of the client's balance, or
+        // into it.
+        let (sends, receives) = match record.record_type {
+            TransactionType::Withdrawal
+            | TransactionType::Transfer
+            | TransactionType::Refund
+            | TransactionType::Auth
+            | TransactionType::Capture
+            | TransactionType::Convert => (true, false),
+            TransactionType::Deposit | TransactionType::Void => (false, true),
+            TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Close
+            | TransactionType::Admin
+            | TransactionType::CreditAdjustment
+            | TransactionType::DebitAdjustment => (false, false),
+        };
+        let allowed = match status {
+            KycStatus::Verified => true,
+            KycStatus::Pending => !sends,
+            KycStatus::Failed => !sends && !receives,
+        };
+        if allowed {
+            return Ok(());
+        }
+        Err(PaymentError::KycRestricted(format!(
+            "tx {} of client {}, whose KYC is {}",
+            record.tx_id,
+            record.client_id,
+            status.as_str()
+        )))
+    }
 
-        acc.deposit(dec!(50.0));
-        assert_eq!(acc.available, dec!(150.0));
+    /// Checks `record` against the KYC status of its client and the limits,
+    /// then asks the risk scorer about it. Returns false if the scorer held
+    /// it, once it's set aside.
+    fn screen(&mut self, record: &InputRecord) -> Result<bool, PaymentError> {
+        self.check_kyc(record)?;
+        if let Some(limits) = &self.limits {
+            limits.check(record, self.limit_time(record))?;
+        }
+        let Some(scorer) = &self.scorer else {
+            return Ok(true);
+        };
+        match scorer.score(record, self.accounts.get(&record.account_key())) {
+            RiskVerdict::Accept => Ok(true),
+            RiskVerdict::Reject(reason) => Err(PaymentError::RiskRejected(format!(
+                "tx {}: {}",
+                record.tx_id, reason
+            ))),
+            RiskVerdict::Hold => {
+                tracing::debug!(tx = record.tx_id, "record held by risk scoring");
+                self.held.push(record.clone());
+                Ok(false)
+            }
+        }
+    }
 
-        assert!(!acc.withdraw(dec!(50.0)));
-        assert_eq!(acc.available, dec!(150.0));
+    /// The records the risk scorer held, in the order they arrived. They're
+    /// part of snapshots.
+    pub fn held(&self) -> &[InputRecord] {
+        &self.held
+    }
 
-        assert!(!acc.hold(dec!(50.0)));
-        assert_eq!(acc.available, dec!(150.0));
-        assert_eq!(acc.held, dec!(0.0));
+    /// Applies the held record with id `tx_id` without scoring it again.
+    /// Returns false if no record with that id is held.
+    pub fn release_held(&mut self, tx_id: TxId) -> Result<bool, PaymentError> {
+        let Some(position) = self.held.iter().position(|record| record.tx_id == tx_id) else {
+            return Ok(false);
+        };
+        let record = self.held.remove(position);
+        let scorer = self.scorer.take();
+        let result = self.process(record);
+        self.scorer = scorer;
+        result.map(|()| true)
+    }
 
-        acc.held = dec!(50.0);
+    /// Drops the held record with id `tx_id` without applying it, returning
+    /// it.
+    pub fn discard_held(&mut self, tx_id: TxId) -> Option<InputRecord> {
+        let position = self.held.iter().position(|record| record.tx_id == tx_id)?;
+        Some(self.held.remove(position))
+    }
+
+    /// When `record` happened as far as the limits are concerned.
+    fn limit_time(&self, record: &InputRecord) -> u64 {
+        record
+            .timestamp
+            .or(self.latest_timestamp)
+            .unwrap_or_default()
+    }
+
+    /// Reports a balance change made by `mutation` to the trace, the audit
+    /// log, the client's statement history, the account store and the event
+    /// listeners.
+    fn record_mutation(
+        &mut self,
+        tx_id: TxId,
+        mutation: Mutation,
+        amount: Decimal,
+        key: AccountKey,
+    ) -> Result<(), PaymentError> {
+        let Some(account) = self.accounts.get_mut(&key) else {
+            return Ok(());
+        };
+        account.touched = true;
+        let account = &*account;
+        if mutation != Mutation::Interest {
+            self.mutations += 1;
+        }
+        self.flows
+            .entry(key.1)
+            .or_default()
+            .record(mutation, amount);
+        let client_id = key.0;
+        tracing::debug!(
+            action = mutation.as_str(),
+            %amount,
+            client = client_id,
+            currency = %key.1,
+            available = %account.available,
+            held = %account.held,
+            locked = account.locked,
+            "account updated"
+        );
+        if self.audit_log.is_some() || self.history.is_some() {
+            let entry =
+                AuditEntry::new(tx_id, mutation, amount, account).with_metadata(&self.metadata);
+            if let Some(log) = &mut self.audit_log {
+                log.record(&entry)?;
+            }
+            if let Some(history) = &mut self.history {
+                history.entry(client_id).or_default().push(entry);
+            }
+        }
+        if let Some(store) = &mut self.account_store {
+            store.upsert(account)?;
+        }
+        if !self.listeners.is_empty() {
+            if let Some(event) = EngineEvent::for_mutation(tx_id, mutation, amount, key) {
+                self.listeners.emit(event);
+            }
+            let change = mutation.total_change(amount);
+            if !change.is_zero() {
+                self.listeners.emit(EngineEvent::BalanceChanged {
+                    tx: tx_id,
+                    client: client_id,
+                    currency: key.1,
+                    change,
+                });
+            }
+        }
+        self.charge_fee(tx_id, mutation, amount, key)
+    }
+
+    /// Moves the fee owed for `mutation` from the client to the house
+    /// account, in the same currency. Fees are charged in full, even if that
+    /// overdraws the client.
+    fn charge_fee(
+        &mut self,
+        tx_id: TxId,
+        mutation: Mutation,
+        amount: Decimal,
+        key: AccountKey,
+    ) -> Result<(), PaymentError> {
+        let Some(record_type) = mutation.fee_type() else {
+            return Ok(());
+        };
+        let Some(schedule) = &self.fees else {
+            return Ok(());
+        };
+        let house_account = (schedule.house_account(), key.1);
+        let fee = match schedule.fee(record_type) {
+            Some(fee) if key.0 != house_account.0 => fee.amount(amount),
+            _ => return Ok(()),
+        };
+        if fee.is_zero() {
+            return Ok(());
+        }
+
+        if let Some(account) = self.accounts.get_mut(&key) {
+            account.charge(fee)?;
+        }
+        self.get_or_create_account(house_account).deposit(fee)?;
+        self.stats.fees_collected += fee;
+        self.record_mutation(tx_id, Mutation::Fee, fee, key)?;
+        self.record_mutation(tx_id, Mutation::FeeIncome, fee, house_account)
+    }
+
+    pub(crate) fn mutations(&self) -> u64 {
+        self.mutations
+    }
+
+    /// Returns the counters accumulated since the engine was created.
+    pub fn stats(&self) -> EngineStats {
+        let mut stats = self.stats;
+        stats.accounts = self.accounts.len() as u64;
+        stats.locked_accounts = self.accounts.values().filter(|a| a.locked).count() as u64;
+        stats
+    }
+
+    fn handle_deposit(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let amount = record.amount.ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!("Deposit {} missing amount", record.tx_id))
+        })?;
+        if amount <= Decimal::ZERO {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Deposit amount for tx {} must be positive",
+                record.tx_id
+            )));
+        }
+
+        let policy = self.locked_deposits;
+        let account = self.get_or_create_account(record.account_key());
+        match policy {
+            _ if !account.locked => account.deposit(amount)?,
+            LockedAccountPolicy::Accept => account.deposit(amount)?,
+            LockedAccountPolicy::Hold => account.deposit_held(amount)?,
+            LockedAccountPolicy::Reject => {
+                return Err(PaymentError::InvalidTransaction(format!(
+                    "Deposit {} rejected: account {} is locked",
+                    record.tx_id, record.client_id
+                )));
+            }
+        }
+        self.record_mutation(
+            record.tx_id,
+            Mutation::Deposit,
+            amount,
+            record.account_key(),
+        )?;
+
+        // Store deposit info for potential disputes.
+        self.transactions.insert(
+            record.tx_id,
+            TransactionInfo {
+                client_id: record.client_id,
+                amount,
+                state: TransactionState::Normal,
+                direction: TransactionDirection::Credit,
+                disputes: 0,
+                currency: record.currency,
+                timestamp: record.timestamp,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn handle_withdrawal(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let amount = record.amount.ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!("Withdrawal {} missing amount", record.tx_id))
+        })?;
+        if amount <= Decimal::ZERO {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Withdrawal amount for tx {} must be positive",
+                record.tx_id
+            )));
+        }
+
+        let account = self.get_or_create_account(record.account_key());
+        // account.withdraw will check for locked status.
+        if !account.withdraw(amount)? {
+            tracing::debug!("withdrawal ignored: insufficient funds or locked account");
+            return Ok(()); // Failed withdrawals are ignored as per spec.
+        }
+        self.record_mutation(
+            record.tx_id,
+            Mutation::Withdrawal,
+            amount,
+            record.account_key(),
+        )?;
+
+        // Store withdrawal info so the client can dispute it.
+        self.transactions.insert(
+            record.tx_id,
+            TransactionInfo {
+                client_id: record.client_id,
+                amount,
+                state: TransactionState::Normal,
+                direction: TransactionDirection::Debit,
+                disputes: 0,
+                currency: record.currency,
+                timestamp: record.timestamp,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn handle_transfer(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let tx_id = record.tx_id;
+        let counterparty = match record.counterparty_id {
+            Some(id) => self.check_credit((id, record.currency), record.amount),
+            None => CreditCheck::Open,
+        };
+        if let Some(credit) = self.begin_transfer(record, counterparty)? {
+            self.complete_transfer(tx_id, credit)?;
+        }
+        Ok(())
+    }
+
+    /// Validates a transfer and applies its sending leg. The counterparty may
+    /// live in another engine, so the caller says whether it can be credited.
+    /// Returns what to credit the counterparty if the debit went through.
+    /// Both legs are in the record's currency.
+    pub(crate) fn begin_transfer(
+        &mut self,
+        record: InputRecord,
+        counterparty: CreditCheck,
+    ) -> Result<Option<TransferCredit>, PaymentError> {
+        let record = self.limit_precision(self.in_client_currency(record))?;
+        self.ensure_not_blocked(&record)?;
+        self.ensure_open(record.account_key())?;
+        if self.is_duplicate(&record)? {
+            return Ok(None);
+        }
+        if !self.screen(&record)? {
+            return Ok(None);
+        }
+
+        let amount = record.amount.ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!("Transfer {} missing amount", record.tx_id))
+        })?;
+        if amount <= Decimal::ZERO {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Transfer amount for tx {} must be positive",
+                record.tx_id
+            )));
+        }
+        let counterparty_id = record.counterparty_id.ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!(
+                "Transfer {} missing counterparty",
+                record.tx_id
+            ))
+        })?;
+        if counterparty_id == record.client_id {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Transfer {} cannot target the sending client",
+                record.tx_id
+            )));
+        }
+        if self.blocklist.contains(counterparty_id) {
+            return Err(PaymentError::Blocked(format!(
+                "transfer {} targets client {}, who is on the blocklist",
+                record.tx_id, counterparty_id
+            )));
+        }
+        let counterparty_kyc = self
+            .client_info(counterparty_id)
+            .and_then(|info| info.kyc_status);
+        if counterparty_kyc == Some(KycStatus::Failed) {
+            return Err(PaymentError::KycRestricted(format!(
+                "transfer {} targets client {}, whose KYC is failed",
+                record.tx_id, counterparty_id
+            )));
+        }
+        match counterparty {
+            CreditCheck::Open => {}
+            CreditCheck::Closed => {
+                return Err(PaymentError::InvalidTransaction(format!(
+                    "Transfer {} targets closed account {}",
+                    record.tx_id, counterparty_id
+                )));
+            }
+            CreditCheck::Overflow => {
+                return Err(PaymentError::Overflow(format!(
+                    "Transfer {} would overflow account {}",
+                    record.tx_id, counterparty_id
+                )));
+            }
+        }
+
+        // Only credit the counterparty once the debit has gone through.
+        let now = self.limit_time(&record);
+        if let Some(limits) = &mut self.limits {
+            limits.note(
+                record.client_id,
+                record.record_type,
+                Some(amount),
+                now,
+                true,
+            );
+        }
+        let sender = self.get_or_create_account(record.account_key());
+        if !sender.withdraw(amount)? {
+            tracing::debug!("transfer ignored: insufficient funds or locked account");
+            self.spend_tx_id(record.tx_id)?;
+            return Ok(None); // Insufficient funds or locked, same as a withdrawal.
+        }
+        self.record_mutation(
+            record.tx_id,
+            Mutation::TransferOut,
+            amount,
+            record.account_key(),
+        )?;
+
+        // Store the sending leg so it can be referenced by a dispute.
+        self.transactions.insert(
+            record.tx_id,
+            TransactionInfo {
+                client_id: record.client_id,
+                amount,
+                state: TransactionState::Normal,
+                direction: TransactionDirection::Debit,
+                disputes: 0,
+                currency: record.currency,
+                timestamp: record.timestamp,
+            },
+        )?;
+        self.idempotency.remember(&record);
+        Ok(Some(TransferCredit {
+            counterparty: (counterparty_id, record.currency),
+            amount,
+            timestamp: record.timestamp,
+        }))
+    }
+
+    /// Applies the receiving leg of a transfer whose debit already succeeded.
+    pub(crate) fn complete_transfer(
+        &mut self,
+        tx_id: TxId,
+        credit: TransferCredit,
+    ) -> Result<(), PaymentError> {
+        let TransferCredit {
+            counterparty,
+            amount,
+            timestamp,
+        } = credit;
+        self.get_or_create_account(counterparty).deposit(amount)?;
+        self.record_mutation(tx_id, Mutation::TransferIn, amount, counterparty)?;
+        self.counter_legs.insert(
+            tx_id,
+            TransactionInfo {
+                client_id: counterparty.0,
+                amount,
+                state: TransactionState::Normal,
+                direction: TransactionDirection::Credit,
+                disputes: 0,
+                currency: counterparty.1,
+                timestamp,
+            },
+        )?;
+        self.retry_queued_disputes(counterparty)
+    }
+
+    /// Returns an undisputed deposit to where it came from. Unlike a chargeback
+    /// this is the merchant's decision, so the account stays unlocked.
+    fn handle_refund(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let tx_id = record.tx_id;
+        let tx_info = match self.transactions.get(tx_id)? {
+            Some(info) => info,
+            None => return Ok(()), // Ignore if tx doesn't exist.
+        };
+        self.check_client(&record, &tx_info)?;
+        self.check_currency(&record, &tx_info)?;
+
+        if tx_info.direction != TransactionDirection::Credit {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Refund {} must reference a deposit",
+                tx_id
+            )));
+        }
+        if !matches!(
+            tx_info.state,
+            TransactionState::Normal | TransactionState::Resolved
+        ) {
+            tracing::debug!("refund ignored: deposit is under dispute");
+            return Ok(());
+        }
+
+        let account = match self.accounts.get_mut(&tx_info.account_key()) {
+            Some(acc) => acc,
+            None => return Ok(()),
+        };
+        // Same checks as a withdrawal: the funds must still be available.
+        if !account.withdraw(tx_info.amount)? {
+            tracing::debug!("refund ignored: insufficient funds or locked account");
+            return Ok(());
+        }
+        self.record_mutation(
+            tx_id,
+            Mutation::Refund,
+            tx_info.amount,
+            tx_info.account_key(),
+        )?;
+        // A refunded deposit can't be refunded or disputed again.
+        self.transactions.remove(tx_id)?;
+        self.spent_tx_ids.insert(tx_id);
+        Ok(())
+    }
+
+    fn handle_auth(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let amount = record.amount.ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!("Auth {} missing amount", record.tx_id))
+        })?;
+        if amount <= Decimal::ZERO {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Auth amount for tx {} must be positive",
+                record.tx_id
+            )));
+        }
+
+        let account = self.get_or_create_account(record.account_key());
+        if !account.authorize(amount)? {
+            tracing::debug!("auth ignored: insufficient funds or locked account");
+            return Ok(()); // Declined, same as a failed withdrawal.
+        }
+        self.record_mutation(record.tx_id, Mutation::Auth, amount, record.account_key())?;
+
+        // Store the authorization until it's captured or voided.
+        self.transactions.insert(
+            record.tx_id,
+            TransactionInfo {
+                client_id: record.client_id,
+                amount,
+                state: TransactionState::Authorized,
+                direction: TransactionDirection::Debit,
+                disputes: 0,
+                currency: record.currency,
+                timestamp: record.timestamp,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Looks up the open authorization referenced by a capture or void.
+    fn find_authorization(
+        &self,
+        record: &InputRecord,
+    ) -> Result<Option<TransactionInfo>, PaymentError> {
+        let tx_info = match self.transactions.get(record.tx_id)? {
+            Some(info) => info,
+            None => return Ok(None), // Ignore if tx doesn't exist.
+        };
+        self.check_client(record, &tx_info)?;
+        self.check_currency(record, &tx_info)?;
+
+        if tx_info.state != TransactionState::Authorized {
+            return Ok(None); // Already settled, or not an authorization.
+        }
+        Ok(Some(tx_info))
+    }
+
+    fn handle_capture(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let Some(tx_info) = self.find_authorization(&record)? else {
+            return Ok(());
+        };
+        let captured = match self.accounts.get_mut(&tx_info.account_key()) {
+            Some(account) => account.capture(tx_info.amount)?,
+            None => false,
+        };
+        if captured {
+            self.record_mutation(
+                record.tx_id,
+                Mutation::Capture,
+                tx_info.amount,
+                tx_info.account_key(),
+            )?;
+            // From here on it's a withdrawal, and can be disputed like one.
+            self.transactions
+                .set_state(record.tx_id, TransactionState::Normal)?;
+        }
+        Ok(())
+    }
+
+    fn handle_void(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let Some(tx_info) = self.find_authorization(&record)? else {
+            return Ok(());
+        };
+        let voided = match self.accounts.get_mut(&tx_info.account_key()) {
+            Some(account) => account.void(tx_info.amount)?,
+            None => false,
+        };
+        if voided {
+            self.record_mutation(
+                record.tx_id,
+                Mutation::Void,
+                tx_info.amount,
+                tx_info.account_key(),
+            )?;
+            self.transactions.remove(record.tx_id)?;
+            self.spent_tx_ids.insert(record.tx_id);
+        }
+        Ok(())
+    }
+
+    /// Closes an account, provided nothing is left in it.
+    fn handle_close(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let account = self
+            .accounts
+            .get_mut(&record.account_key())
+            .ok_or_else(|| {
+                PaymentError::InvalidTransaction(format!(
+                    "Account {} doesn't exist",
+                    record.client_id
+                ))
+            })?;
+        if !account.total().is_zero() {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Account {} can't be closed with a non-zero balance",
+                record.client_id
+            )));
+        }
+        account.closed = true;
+        self.record_mutation(
+            record.tx_id,
+            Mutation::Close,
+            Decimal::ZERO,
+            record.account_key(),
+        )
+    }
+
+    /// Sets the client's overdraft limit. Lowering it below what's already
+    /// overdrawn only blocks further debits.
+    fn handle_admin(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let limit = record.amount.ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!("Admin {} missing amount", record.tx_id))
+        })?;
+        if limit < Decimal::ZERO {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Overdraft limit for tx {} can't be negative",
+                record.tx_id
+            )));
+        }
+        self.get_or_create_account(record.account_key())
+            .overdraft_limit = limit;
+        self.record_mutation(record.tx_id, Mutation::Admin, limit, record.account_key())
+    }
+
+    /// Exchanges funds from one of the client's currency balances into another
+    /// at the rate quoted by the rate provider, rounded to 4 decimal places.
+    fn handle_convert(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let amount = record.amount.ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!("Convert {} missing amount", record.tx_id))
+        })?;
+        if amount <= Decimal::ZERO {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Convert amount for tx {} must be positive",
+                record.tx_id
+            )));
+        }
+        let target = record.target_currency.ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!(
+                "Convert {} missing target currency",
+                record.tx_id
+            ))
+        })?;
+        if target == record.currency {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "Convert {} must target another currency",
+                record.tx_id
+            )));
+        }
+        let rate = self
+            .rates
+            .as_ref()
+            .and_then(|rates| rates.rate(record.currency, target))
+            .ok_or_else(|| {
+                PaymentError::InvalidTransaction(format!(
+                    "No rate from '{}' to '{}' for tx {}",
+                    record.currency, target, record.tx_id
+                ))
+            })?;
+        let converted = amount
+            .checked_mul(rate)
+            .ok_or_else(|| {
+                PaymentError::Overflow(format!("Convert {} amount overflows", record.tx_id))
+            })?
+            .round_dp_with_strategy(4, RoundingStrategy::MidpointAwayFromZero);
+        let target_key = (record.client_id, target);
+        self.ensure_open(target_key)?;
+        // Checked up front so the source is never debited without the credit.
+        if !self.can_credit(target_key, converted) {
+            return Err(PaymentError::Overflow(format!(
+                "Convert {} would overflow account {}",
+                record.tx_id, record.client_id
+            )));
+        }
+
+        let source = self.get_or_create_account(record.account_key());
+        if !source.withdraw(amount)? {
+            tracing::debug!("convert ignored: insufficient funds or locked account");
+            return Ok(()); // Same as a withdrawal.
+        }
+        self.record_mutation(
+            record.tx_id,
+            Mutation::ConvertOut,
+            amount,
+            record.account_key(),
+        )?;
+
+        self.get_or_create_account(target_key).deposit(converted)?;
+        self.record_mutation(record.tx_id, Mutation::ConvertIn, converted, target_key)?;
+        self.retry_queued_disputes(target_key)
+    }
+
+    /// Credits the account outside the deposit flow, on an operator's say so.
+    /// Locked accounts are credited too, and the adjustment can't be disputed.
+    fn handle_credit_adjustment(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let amount = self.adjustment_amount(&record, "Credit")?;
+        self.get_or_create_account(record.account_key())
+            .deposit(amount)?;
+        self.record_mutation(
+            record.tx_id,
+            Mutation::CreditAdjustment,
+            amount,
+            record.account_key(),
+        )
+    }
+
+    /// Debits the account outside the withdrawal flow, on an operator's say
+    /// so. Locked accounts are debited too, even into the red.
+    fn handle_debit_adjustment(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let amount = self.adjustment_amount(&record, "Debit")?;
+        self.get_or_create_account(record.account_key())
+            .charge(amount)?;
+        self.record_mutation(
+            record.tx_id,
+            Mutation::DebitAdjustment,
+            amount,
+            record.account_key(),
+        )
+    }
+
+    /// Checks an adjustment's amount and operator reference. The reference is
+    /// added to the metadata of its audit entry.
+    fn adjustment_amount(
+        &mut self,
+        record: &InputRecord,
+        kind: &str,
+    ) -> Result<Decimal, PaymentError> {
+        let amount = record.amount.ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!(
+                "{} adjustment {} missing amount",
+                kind, record.tx_id
+            ))
+        })?;
+        if amount <= Decimal::ZERO {
+            return Err(PaymentError::InvalidTransaction(format!(
+                "{} adjustment amount for tx {} must be positive",
+                kind, record.tx_id
+            )));
+        }
+        let reference = record
+            .reference
+            .as_deref()
+            .filter(|reference| !reference.trim().is_empty())
+            .ok_or_else(|| {
+                PaymentError::InvalidTransaction(format!(
+                    "{} adjustment {} missing reference",
+                    kind, record.tx_id
+                ))
+            })?;
+        self.metadata
+            .insert("reference".to_string(), reference.to_string());
+        Ok(amount)
+    }
+
+    fn handle_dispute(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let tx_id = record.tx_id;
+        let (leg, tx_info) = match self.find_leg(tx_id, record.client_id)? {
+            Some(found) => found,
+            None => return Ok(()), // Ignore if tx doesn't exist.
+        };
+        self.check_client(&record, &tx_info)?;
+        self.check_currency(&record, &tx_info)?;
+        self.check_dispute_window(&record, &tx_info)?;
+
+        let disputable = match tx_info.state {
+            TransactionState::Normal | TransactionState::Resolved => {
+                tx_info.disputes < self.max_disputes
+            }
+            TransactionState::Disputed
+            | TransactionState::DisputeQueued
+            | TransactionState::Authorized => false,
+        };
+        if !disputable {
+            return Ok(()); // Ignore if already disputed or out of disputes.
+        }
+
+        let account = match self.accounts.get_mut(&tx_info.account_key()) {
+            Some(acc) => acc,
+            None => return Ok(()),
+        };
+
+        if !self.dispute_policy.allows_dispute(&tx_info, account) {
+            tracing::debug!("dispute rejected by policy");
+            return Ok(()); // Ignore if the policy rejects it.
+        }
+
+        let held = match (tx_info.direction, self.underfunded_disputes) {
+            (TransactionDirection::Debit, _) => account.hold_debit(tx_info.amount)?,
+            (TransactionDirection::Credit, UnderfundedDisputeMode::AllowNegative) => {
+                account.hold_overdrawn(tx_info.amount)?
+            }
+            (TransactionDirection::Credit, UnderfundedDisputeMode::Queue) => {
+                if !account.hold(tx_info.amount)? {
+                    if !account.locked {
+                        tracing::debug!("dispute queued: insufficient funds");
+                        self.set_dispute_opened_at(leg, tx_id, record.timestamp);
+                        return self.queue_dispute(leg, tx_id, tx_info);
+                    }
+                    return Ok(());
+                }
+                true
+            }
+            (TransactionDirection::Credit, UnderfundedDisputeMode::Ignore) => {
+                account.hold(tx_info.amount)?
+            }
+        };
+        if held {
+            if tx_info.direction == TransactionDirection::Debit {
+                let reversals = &mut self.flows.entry(tx_info.currency).or_default().reversals;
+                *reversals = reversals.saturating_add(tx_info.amount);
+            }
+            self.record_mutation(
+                tx_id,
+                Mutation::Dispute(tx_info.direction),
+                tx_info.amount,
+                tx_info.account_key(),
+            )?;
+            self.set_dispute_opened_at(leg, tx_id, record.timestamp);
+            let disputed = TransactionInfo {
+                state: TransactionState::Disputed,
+                disputes: tx_info.disputes + 1,
+                ..tx_info
+            };
+            self.leg_store_mut(leg).insert(tx_id, disputed)?;
+        }
+        Ok(())
+    }
+
+    fn set_dispute_opened_at(&mut self, leg: Leg, tx_id: TxId, timestamp: Option<u64>) {
+        match timestamp {
+            Some(timestamp) => self.dispute_opened_at.insert((leg, tx_id), timestamp),
+            None => self.dispute_opened_at.remove(&(leg, tx_id)),
+        };
+    }
+
+    fn queue_dispute(
+        &mut self,
+        leg: Leg,
+        tx_id: TxId,
+        tx_info: TransactionInfo,
+    ) -> Result<(), PaymentError> {
+        let queued = TransactionInfo {
+            state: TransactionState::DisputeQueued,
+            disputes: tx_info.disputes + 1,
+            ..tx_info
+        };
+        self.leg_store_mut(leg).insert(tx_id, queued)?;
+        self.queued_disputes
+            .entry(tx_info.account_key())
+            .or_default()
+            .push((leg, tx_id));
+        Ok(())
+    }
+
+    /// Holds the account's queued disputes, oldest first, for as long as the
+    /// available funds cover them.
+    fn retry_queued_disputes(&mut self, key: AccountKey) -> Result<(), PaymentError> {
+        let Some(mut queue) = self.queued_disputes.remove(&key) else {
+            return Ok(());
+        };
+        let mut done = 0;
+        for &(leg, tx_id) in &queue {
+            let tx_info = match self.leg_store_mut(leg).get(tx_id)? {
+                Some(info) if info.state == TransactionState::DisputeQueued => info,
+                _ => {
+                    done += 1; // Resolved while queued.
+                    continue;
+                }
+            };
+            let Some(account) = self.accounts.get_mut(&key) else {
+                break;
+            };
+            if !account.hold(tx_info.amount)? {
+                break;
+            }
+            self.record_mutation(
+                tx_id,
+                Mutation::Dispute(tx_info.direction),
+                tx_info.amount,
+                key,
+            )?;
+            self.leg_store_mut(leg)
+                .set_state(tx_id, TransactionState::Disputed)?;
+            done += 1;
+            if self.pending_chargebacks.remove(&(leg, tx_id)) {
+                self.charge_back(leg, tx_id, tx_info)?;
+            }
+        }
+        queue.drain(..done);
+        if !queue.is_empty() {
+            self.queued_disputes.insert(key, queue);
+        }
+        Ok(())
+    }
+
+    fn handle_resolve(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let tx_id = record.tx_id;
+        let (leg, tx_info) = match self.find_leg(tx_id, record.client_id)? {
+            Some(found) => found,
+            None => return Ok(()),
+        };
+        self.check_client(&record, &tx_info)?;
+        self.check_currency(&record, &tx_info)?;
+
+        if tx_info.state == TransactionState::DisputeQueued {
+            if self.pending_chargebacks.contains(&(leg, tx_id)) {
+                // Charged back already, which is final.
+                return Ok(());
+            }
+            // Nothing was held yet; the queue entry is dropped on the next retry.
+            self.leg_store_mut(leg)
+                .set_state(tx_id, TransactionState::Resolved)?;
+            self.dispute_opened_at.remove(&(leg, tx_id));
+            return Ok(());
+        }
+
+        if tx_info.state != TransactionState::Disputed {
+            return Ok(());
+        }
+
+        let account = match self.accounts.get_mut(&tx_info.account_key()) {
+            Some(acc) => acc,
+            None => return Ok(()),
+        };
+
+        let released = match tx_info.direction {
+            TransactionDirection::Credit => account.release(tx_info.amount)?,
+            TransactionDirection::Debit => account.release_debit(tx_info.amount)?,
+        };
+        if released {
+            if tx_info.direction == TransactionDirection::Debit {
+                let reversals = &mut self.flows.entry(tx_info.currency).or_default().reversals;
+                *reversals = reversals.saturating_sub(tx_info.amount);
+            }
+            self.record_mutation(
+                tx_id,
+                Mutation::Resolve(tx_info.direction),
+                tx_info.amount,
+                tx_info.account_key(),
+            )?;
+            // Kept so it can be refunded or, if allowed, disputed again.
+            self.leg_store_mut(leg)
+                .set_state(tx_id, TransactionState::Resolved)?;
+            self.dispute_opened_at.remove(&(leg, tx_id));
+        }
+
+        Ok(())
+    }
+
+    fn handle_chargeback(&mut self, record: InputRecord) -> Result<(), PaymentError> {
+        let tx_id = record.tx_id;
+        let (leg, tx_info) = match self.find_leg(tx_id, record.client_id)? {
+            Some(found) => found,
+            None => return Ok(()),
+        };
+        self.check_client(&record, &tx_info)?;
+        self.check_currency(&record, &tx_info)?;
+
+        if tx_info.state == TransactionState::DisputeQueued {
+            // Charged back once the dispute's funds are held, like the funds
+            // of any other chargeback.
+            self.pending_chargebacks.insert((leg, tx_id));
+            return Ok(());
+        }
+        if tx_info.state != TransactionState::Disputed {
+            return Ok(());
+        }
+        self.charge_back(leg, tx_id, tx_info)
+    }
+
+    /// Charges back the disputed transaction leg `tx_id`.
+    fn charge_back(
+        &mut self,
+        leg: Leg,
+        tx_id: TxId,
+        tx_info: TransactionInfo,
+    ) -> Result<(), PaymentError> {
+        let account = match self.accounts.get_mut(&tx_info.account_key()) {
+            Some(acc) => acc,
+            None => return Ok(()),
+        };
+
+        let was_locked = account.locked;
+        let charged_back = match tx_info.direction {
+            TransactionDirection::Credit => account.chargeback(tx_info.amount)?,
+            TransactionDirection::Debit => account.chargeback_debit(tx_info.amount)?,
+        };
+        if charged_back {
+            if tx_info.direction == TransactionDirection::Credit {
+                let chargebacks = &mut self.flows.entry(tx_info.currency).or_default().chargebacks;
+                *chargebacks = chargebacks.saturating_add(tx_info.amount);
+            }
+            self.record_mutation(
+                tx_id,
+                Mutation::Chargeback(tx_info.direction),
+                tx_info.amount,
+                tx_info.account_key(),
+            )?;
+            if tx_info.direction == TransactionDirection::Credit {
+                self.write_off(tx_id, tx_info.amount, tx_info.account_key())?;
+            }
+            self.leg_store_mut(leg).remove(tx_id)?;
+            self.dispute_opened_at.remove(&(leg, tx_id));
+            // The other leg of a transfer may still be stored under this id.
+            self.spent_tx_ids.insert(tx_id);
+            if !was_locked {
+                self.listeners.emit(EngineEvent::AccountLocked {
+                    tx: tx_id,
+                    client: tx_info.client_id,
+                    currency: tx_info.currency,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the funds charged back from the account at `key` to the suspense
+    /// account, if there's one.
+    fn write_off(
+        &mut self,
+        tx_id: TxId,
+        amount: Decimal,
+        key: AccountKey,
+    ) -> Result<(), PaymentError> {
+        let suspense = match self.suspense_account {
+            Some(client_id) if client_id != key.0 => (client_id, key.1),
+            _ => return Ok(()),
+        };
+        self.get_or_create_account(suspense).deposit(amount)?;
+        self.stats.written_off += amount;
+        self.record_mutation(tx_id, Mutation::WriteOff, amount, suspense)
+    }
+
+    /// Combines the state of an independently processed partition into this engine.
+    ///
+    /// Fails without modifying either engine if both saw the same client or the
+    /// same transaction id, since their results can't be reconciled. The fee
+    /// house account and the suspense account are the exception: their
+    /// balances are added up.
+    pub fn merge(&mut self, other: PaymentEngine) -> Result<(), PaymentError> {
+        let shared = self.shared_accounts();
+        let mut clients: Vec<ClientId> = other
+            .accounts
+            .keys()
+            .filter(|key| self.accounts.contains_key(key))
+            .map(|&(client_id, _)| client_id)
+            .filter(|client_id| !shared.contains(client_id))
+            .collect();
+        let mut tx_ids = Vec::new();
+        for (tx_id, _) in other.transactions.entries()? {
+            if self.transactions.contains(tx_id)? {
+                tx_ids.push(tx_id);
+            }
+        }
+        for (tx_id, _) in other.counter_legs.entries()? {
+            if self.counter_legs.contains(tx_id)? {
+                tx_ids.push(tx_id);
+            }
+        }
+        for &tx_id in &other.spent_tx_ids {
+            if self.is_tx_id_taken(tx_id)? {
+                tx_ids.push(tx_id);
+            }
+        }
+        for &tx_id in &self.spent_tx_ids {
+            // Ids spent on both sides are already listed above.
+            if !other.spent_tx_ids.contains(&tx_id) && other.transactions.contains(tx_id)? {
+                tx_ids.push(tx_id);
+            }
+        }
+
+        if !clients.is_empty() || !tx_ids.is_empty() {
+            clients.sort_unstable();
+            clients.dedup();
+            tx_ids.sort_unstable();
+            tx_ids.dedup();
+            return Err(PaymentError::MergeConflict(format!(
+                "overlapping clients {:?}, conflicting tx ids {:?}",
+                clients, tx_ids
+            )));
+        }
+        self.absorb(other)
+    }
+
+    /// The clients every partition may hold an account of: the fee house
+    /// account and the suspense account.
+    fn shared_accounts(&self) -> Vec<ClientId> {
+        let house_account = self.fees.as_ref().map(FeeSchedule::house_account);
+        house_account
+            .into_iter()
+            .chain(self.suspense_account)
+            .collect()
+    }
+
+    /// Moves the accounts and transactions of an engine owning a disjoint set of
+    /// clients into this one. This engine keeps its own policies, blocklist and
+    /// client registry; the other engine's audit log, write-ahead log and event
+    /// listeners aren't carried over.
+    pub(crate) fn absorb(&mut self, other: PaymentEngine) -> Result<(), PaymentError> {
+        self.stats.add(&other.stats);
+        for (currency, flows) in &other.flows {
+            self.flows.entry(*currency).or_default().add(flows);
+        }
+        if self.fees.is_none() {
+            self.fees = other.fees;
+        }
+        if self.suspense_account.is_none() {
+            self.suspense_account = other.suspense_account;
+        }
+        if self.rates.is_none() {
+            self.rates = other.rates;
+        }
+        if self.interest.is_none() {
+            self.interest = other.interest;
+        }
+        // Shards advance their clocks together, so any of them will do.
+        if self.interest_clock.is_none() {
+            self.interest_clock = other.interest_clock;
+        }
+        let shared = self.shared_accounts();
+        for (key, account) in other.accounts {
+            match self.accounts.get_mut(&key) {
+                // Every partition collects fees and write-offs into its own
+                // copy of the house and suspense accounts.
+                Some(house) if shared.contains(&key.0) => house.deposit(account.available)?,
+                _ => {
+                    self.accounts.insert(key, account);
+                }
+            }
+            if let Some(store) = &mut self.account_store {
+                store.upsert(&self.accounts[&key])?;
+            }
+        }
+        self.queued_disputes.extend(other.queued_disputes);
+        self.pending_chargebacks.extend(other.pending_chargebacks);
+        self.dispute_opened_at.extend(other.dispute_opened_at);
+        self.latest_timestamp = self.latest_timestamp.max(other.latest_timestamp);
+        self.held.extend(other.held);
+        match (&mut self.limits, other.limits) {
+            (Some(limits), Some(other)) => limits.absorb(other),
+            (None, other) => self.limits = other,
+            (_, None) => {}
+        }
+        if let Some(other_history) = other.history {
+            let history = self.history.get_or_insert_with(FxHashMap::default);
+            for (client_id, entries) in other_history {
+                history.entry(client_id).or_default().extend(entries);
+            }
+        }
+        for (tx_id, info) in other.transactions.entries()? {
+            self.transactions.insert(tx_id, info)?;
+        }
+        for (tx_id, info) in other.counter_legs.entries()? {
+            self.counter_legs.insert(tx_id, info)?;
+        }
+        self.spent_tx_ids.extend(other.spent_tx_ids);
+        Ok(())
+    }
+
+    /// Writes the accounts and stored transactions as a versioned JSON snapshot.
+    pub fn snapshot<W: Write>(&self, writer: W) -> Result<(), PaymentError> {
+        let mut accounts: Vec<Account> = self.accounts.values().cloned().collect();
+        accounts.sort_by_key(|a| (a.client_id, a.currency));
+        let mut spent_tx_ids: Vec<TxId> = self.spent_tx_ids.iter().copied().collect();
+        spent_tx_ids.sort_unstable();
+        let mut flows: Vec<(Currency, Flows)> = self
+            .flows
+            .iter()
+            .map(|(currency, flows)| (*currency, *flows))
+            .collect();
+        flows.sort_unstable_by_key(|&(currency, _)| currency);
+        let mut dispute_opened_at: Vec<(Leg, TxId, u64)> = self
+            .dispute_opened_at
+            .iter()
+            .map(|(&(leg, tx_id), &opened_at)| (leg, tx_id, opened_at))
+            .collect();
+        dispute_opened_at.sort_unstable_by_key(|&(leg, tx_id, _)| (tx_id, leg == Leg::Counter));
+        let mut pending_chargebacks: Vec<(Leg, TxId)> =
+            self.pending_chargebacks.iter().copied().collect();
+        pending_chargebacks.sort_unstable_by_key(|&(leg, tx_id)| (tx_id, leg == Leg::Counter));
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            accounts,
+            transactions: self.transactions.entries()?,
+            counter_legs: self.counter_legs.entries()?,
+            interest_clock: self.interest_clock,
+            spent_tx_ids,
+            flows,
+            dispute_opened_at,
+            latest_timestamp: self.latest_timestamp,
+            pending_chargebacks,
+            limit_usage: self
+                .limits
+                .as_ref()
+                .map(LimitTracker::usage)
+                .unwrap_or_default(),
+            held: self.held.clone(),
+        };
+        serde_json::to_writer(writer, &snapshot)?;
+        Ok(())
+    }
+
+    /// Replaces the engine state with a snapshot written by `snapshot`,
+    /// keeping the configured policies and stores.
+    pub fn restore<R: Read>(&mut self, reader: R) -> Result<(), PaymentError> {
+        let snapshot: Snapshot = serde_json::from_reader(reader)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(PaymentError::InvalidSnapshot(format!(
+                "unsupported version {} (expected {})",
+                snapshot.version, SNAPSHOT_VERSION
+            )));
+        }
+
+        self.accounts = snapshot
+            .accounts
+            .into_iter()
+            .map(|account| ((account.client_id, account.currency), account))
+            .collect();
+        if let Some(store) = &mut self.account_store {
+            store.clear()?;
+            for account in self.accounts.values() {
+                store.upsert(account)?;
+            }
+        }
+        self.transactions.clear()?;
+        for (tx_id, info) in snapshot.transactions {
+            self.transactions.insert(tx_id, info)?;
+        }
+        self.counter_legs.clear()?;
+        for (tx_id, info) in snapshot.counter_legs {
+            self.counter_legs.insert(tx_id, info)?;
+        }
+        self.interest_clock = snapshot.interest_clock;
+        self.spent_tx_ids = snapshot.spent_tx_ids.into_iter().collect();
+        self.flows = snapshot.flows.into_iter().collect();
+        self.dispute_opened_at = snapshot
+            .dispute_opened_at
+            .into_iter()
+            .map(|(leg, tx_id, opened_at)| ((leg, tx_id), opened_at))
+            .collect();
+        self.latest_timestamp = snapshot.latest_timestamp;
+        self.pending_chargebacks = snapshot.pending_chargebacks.into_iter().collect();
+        if let Some(limits) = &mut self.limits {
+            limits.restore(snapshot.limit_usage);
+        }
+        self.held = snapshot.held;
+        if self.flows.is_empty() {
+            for account in self.accounts.values() {
+                let opening = &mut self.flows.entry(account.currency).or_default().opening;
+                *opening = opening.saturating_add(account.total());
+            }
+        }
+        self.requeue_disputes()
+    }
+
+    /// Rebuilds the dispute queue from the stored states, oldest tx id first.
+    fn requeue_disputes(&mut self) -> Result<(), PaymentError> {
+        self.queued_disputes.clear();
+        let mut queued = Vec::new();
+        for (leg, store) in [
+            (Leg::Primary, &self.transactions),
+            (Leg::Counter, &self.counter_legs),
+        ] {
+            for (tx_id, info) in store.entries()? {
+                if info.state == TransactionState::DisputeQueued {
+                    queued.push((info.account_key(), leg, tx_id));
+                }
+            }
+        }
+        queued.sort_by_key(|&(_, _, tx_id)| tx_id);
+        for (key, leg, tx_id) in queued {
+            self.queued_disputes
+                .entry(key)
+                .or_default()
+                .push((leg, tx_id));
+        }
+        Ok(())
+    }
+
+    /// Drops the accounts of the clients `keep` rejects, so the accounts
+    /// written afterwards cover the others only. Their transactions and the
+    /// account store are left alone, so it's meant for an engine whose
+    /// results are about to be written, not for one that goes on processing.
+    pub fn retain_clients<F: FnMut(ClientId) -> bool>(&mut self, mut keep: F) {
+        self.retain_accounts(|account| keep(account.client_id));
+    }
+
+    /// Like [`retain_clients`](Self::retain_clients), for the accounts `keep`
+    /// rejects.
+    pub fn retain_accounts<F: FnMut(&Account) -> bool>(&mut self, mut keep: F) {
+        self.accounts.retain(|_, account| keep(account));
+    }
+
+    /// Iterates over every account, in no particular order, without copying
+    /// them.
+    pub fn iter_accounts(&self) -> impl ExactSizeIterator<Item = &Account> {
+        self.accounts.values()
+    }
+
+    /// Iterates over every account sorted by client ID, then currency.
+    ///
+    /// Sorting goes through a `BTreeMap` of references, so it costs a pointer
+    /// per account rather than a copy of each.
+    pub fn iter_accounts_sorted(&self) -> impl ExactSizeIterator<Item = &Account> {
+        self.accounts
+            .iter()
+            .map(|(key, account)| (*key, account))
+            .collect::<BTreeMap<_, _>>()
+            .into_values()
+    }
+
+    /// Returns a vector of all accounts formatted for output.
+    ///
+    /// This copies every account; [`Self::iter_accounts`] streams them instead.
+    pub fn get_accounts(&self) -> Vec<crate::models::OutputRecord> {
+        self.accounts
+            .values()
+            .map(|acc| acc.to_output_record())
+            .collect()
+    }
+
+    /// Returns the account of `client_id` in `currency`, if the engine has seen it.
+    pub fn get_account(
+        &self,
+        client_id: ClientId,
+        currency: Currency,
+    ) -> Option<crate::models::OutputRecord> {
+        self.accounts
+            .get(&(client_id, currency))
+            .map(Account::to_output_record)
+    }
+
+    /// Borrows the account of `client_id` in `currency`, if the engine has
+    /// seen it.
+    pub fn account(&self, client_id: ClientId, currency: Currency) -> Option<&Account> {
+        self.accounts.get(&(client_id, currency))
+    }
+
+    /// Whether a chargeback has locked any of `client_id`'s accounts. Walks
+    /// every account, since they're kept per currency.
+    pub fn is_locked(&self, client_id: ClientId) -> bool {
+        self.accounts
+            .values()
+            .any(|account| account.client_id == client_id && account.locked)
+    }
+
+    /// Returns the transactions of `client_id` under an open dispute, queued
+    /// ones included, sorted by tx id. A transfer the client received is
+    /// listed under the transfer's id. Walks every stored transaction.
+    pub fn disputed_transactions(
+        &self,
+        client_id: ClientId,
+    ) -> Result<Vec<(TxId, TransactionInfo)>, PaymentError> {
+        Ok(self
+            .open_dispute_legs(Some(client_id))?
+            .into_iter()
+            .map(|(_, tx_id, info)| (tx_id, info))
+            .collect())
+    }
+
+    /// Returns every transaction under an open dispute, queued ones
+    /// included, sorted by tx id, with when the dispute was filed if its
+    /// record said. Walks every stored transaction.
+    pub fn open_disputes(&self) -> Result<Vec<OpenDispute>, PaymentError> {
+        Ok(self
+            .open_dispute_legs(None)?
+            .into_iter()
+            .map(|(leg, tx_id, info)| OpenDispute {
+                tx_id,
+                client_id: info.client_id,
+                currency: info.currency,
+                amount: info.amount,
+                opened_at: self.dispute_opened_at.get(&(leg, tx_id)).copied(),
+                queued: info.state == TransactionState::DisputeQueued,
+            })
+            .collect())
+    }
+
+    /// Settles the disputes filed more than the maximum dispute age (see
+    /// [`with_dispute_max_age`](Self::with_dispute_max_age)) before the
+    /// newest record timestamp seen, as card schemes do: each is resolved or
+    /// charged back by a record of its tx stamped with that time, which goes
+    /// to the audit log and write-ahead log like any other. Queued disputes
+    /// hold nothing to charge back, so they're always resolved, and disputes
+    /// filed without a timestamp never expire. Meant for the end of a run;
+    /// returns the disputes settled, by tx id.
+    pub fn expire_disputes(&mut self) -> Result<Vec<ExpiredDispute>, PaymentError> {
+        let (Some(max_age), Some(now)) = (self.dispute_max_age, self.latest_timestamp) else {
+            return Ok(Vec::new());
+        };
+        let mut expired = Vec::new();
+        for dispute in self.open_disputes()? {
+            let Some(opened_at) = dispute.opened_at else {
+                continue;
+            };
+            if now.saturating_sub(opened_at) <= max_age.as_secs() {
+                continue;
+            }
+            if self
+                .pending_chargebacks
+                .iter()
+                .any(|&(_, tx_id)| tx_id == dispute.tx_id)
+            {
+                continue; // Settled already, waiting for its funds.
+            }
+            let action = if dispute.queued {
+                ExpiredDisputeAction::Resolve
+            } else {
+                self.expired_disputes
+            };
+            let record_type = match action {
+                ExpiredDisputeAction::Resolve => TransactionType::Resolve,
+                ExpiredDisputeAction::Chargeback => TransactionType::Chargeback,
+            };
+            self.process(InputRecord {
+                record_type,
+                client_id: dispute.client_id,
+                tx_id: dispute.tx_id,
+                amount: None,
+                counterparty_id: None,
+                currency: dispute.currency,
+                target_currency: None,
+                timestamp: Some(now),
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })?;
+            expired.push(ExpiredDispute {
+                tx_id: dispute.tx_id,
+                client_id: dispute.client_id,
+                currency: dispute.currency,
+                amount: dispute.amount,
+                opened_at,
+                action,
+            });
+        }
+        Ok(expired)
+    }
+
+    /// The legs under an open dispute, of `client_id` or of every client,
+    /// sorted by tx id.
+    fn open_dispute_legs(
+        &self,
+        client_id: Option<ClientId>,
+    ) -> Result<Vec<(Leg, TxId, TransactionInfo)>, PaymentError> {
+        let mut disputed = Vec::new();
+        for (leg, store) in [
+            (Leg::Primary, &self.transactions),
+            (Leg::Counter, &self.counter_legs),
+        ] {
+            disputed.extend(
+                store
+                    .entries()?
+                    .into_iter()
+                    .filter(|(_, info)| {
+                        client_id.is_none_or(|id| info.client_id == id)
+                            && matches!(
+                                info.state,
+                                TransactionState::Disputed | TransactionState::DisputeQueued
+                            )
+                    })
+                    .map(|(tx_id, info)| (leg, tx_id, info)),
+            );
+        }
+        disputed.sort_unstable_by_key(|&(_, tx_id, _)| tx_id);
+        Ok(disputed)
+    }
+
+    /// Balances the books of every currency: what the accounts total, house
+    /// and suspense accounts included, against what the deposits, withdrawals,
+    /// chargebacks and other flows applied so far say they should. Transfers
+    /// and fees only move funds between accounts, so they cancel out; a
+    /// partition of a sharded run only balances once merged.
+    pub fn reconcile(&self) -> ReconciliationReport {
+        let mut actual: BTreeMap<Currency, Decimal> = self
+            .flows
+            .keys()
+            .map(|&currency| (currency, Decimal::ZERO))
+            .collect();
+        for account in self.accounts.values() {
+            let total = actual.entry(account.currency).or_default();
+            *total = total.saturating_add(account.total());
+        }
+        let currencies = actual
+            .into_iter()
+            .map(|(currency, actual)| {
+                let flows = self.flows.get(&currency).copied().unwrap_or_default();
+                Reconciliation {
+                    currency,
+                    expected: flows.expected_total(),
+                    actual,
+                    flows,
+                }
+            })
+            .collect();
+        ReconciliationReport { currencies }
+    }
+
+    /// Checks that the engine state is consistent, failing with the first
+    /// broken invariant:
+    ///
+    /// - every account is kept under its own client and currency;
+    /// - held and authorized funds are never negative;
+    /// - an unlocked account holds exactly the amounts of its open disputes
+    ///   (a locked one may also hold deposits frozen by
+    ///   `LockedAccountPolicy::Hold`, so it holds at least that much);
+    /// - an account's authorized funds are exactly the amounts of its open
+    ///   authorizations;
+    /// - every stored transaction has a positive amount and an account;
+    /// - every dispute waiting for funds is queued on its account (the queue
+    ///   may still list disputes resolved while they waited).
+    ///
+    /// It reads every stored transaction, so it's meant for tests and
+    /// debugging rather than for each record.
+    pub fn check_invariants(&self) -> Result<(), PaymentError> {
+        let violation = |msg: String| Err(PaymentError::InvariantViolation(msg));
+        let mut disputed: HashMap<AccountKey, Decimal> = HashMap::new();
+        let mut authorized: HashMap<AccountKey, Decimal> = HashMap::new();
+        for (leg, store) in [
+            (Leg::Primary, &self.transactions),
+            (Leg::Counter, &self.counter_legs),
+        ] {
+            for (tx_id, info) in store.entries()? {
+                let key = info.account_key();
+                if info.amount <= Decimal::ZERO {
+                    return violation(format!("tx {} has amount {}", tx_id, info.amount));
+                }
+                if !self.accounts.contains_key(&key) {
+                    return violation(format!("tx {} belongs to no account", tx_id));
+                }
+                let queued = self
+                    .queued_disputes
+                    .get(&key)
+                    .is_some_and(|queue| queue.contains(&(leg, tx_id)));
+                if info.state == TransactionState::DisputeQueued && !queued {
+                    return violation(format!("dispute of tx {} isn't queued", tx_id));
+                }
+                let open = match info.state {
+                    TransactionState::Disputed => &mut disputed,
+                    TransactionState::Authorized => &mut authorized,
+                    _ => continue,
+                };
+                let sum = open.entry(key).or_default();
+                *sum = sum.saturating_add(info.amount);
+            }
+        }
+
+        for (&key, account) in &self.accounts {
+            let client = key.0;
+            if (account.client_id, account.currency) != key {
+                return violation(format!("account of client {} is misfiled", client));
+            }
+            if account.held.is_sign_negative() || account.authorized.is_sign_negative() {
+                return violation(format!("client {} has negative held funds", client));
+            }
+            let disputed = disputed.get(&key).copied().unwrap_or_default();
+            if account.held != disputed && !(account.locked && account.held > disputed) {
+                return violation(format!(
+                    "client {} holds {} for {} of open disputes",
+                    client, account.held, disputed
+                ));
+            }
+            let authorized = authorized.get(&key).copied().unwrap_or_default();
+            if account.authorized != authorized {
+                return violation(format!(
+                    "client {} has {} authorized for {} of open authorizations",
+                    client, account.authorized, authorized
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether records of this type bring a new tx id, rather than referencing
+/// an earlier transaction.
+fn introduces_tx_id(record_type: TransactionType) -> bool {
+    matches!(
+        record_type,
+        TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Transfer
+            | TransactionType::Auth
+            | TransactionType::Convert
+            | TransactionType::CreditAdjustment
+            | TransactionType::DebitAdjustment
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::account_store::{AccountStore, DiskAccountStore};
+    use crate::fees::Fee;
+    use crate::models::{Account, TransactionType};
+    use crate::tx_store::DiskTxStore;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    #[rstest]
+    fn test_locked_account_behavior() {
+        let mut acc = Account::new(1);
+        acc.available = dec!(100.0);
+        acc.locked = true;
+
+        acc.deposit(dec!(50.0)).unwrap();
+        assert_eq!(acc.available, dec!(150.0));
+
+        assert!(!acc.withdraw(dec!(50.0)).unwrap());
+        assert_eq!(acc.available, dec!(150.0));
+
+        assert!(!acc.hold(dec!(50.0)).unwrap());
+        assert_eq!(acc.available, dec!(150.0));
+        assert_eq!(acc.held, dec!(0.0));
+
+        acc.held = dec!(50.0);
         acc.available = dec!(100.0);
         acc.locked = true;
 
-        assert!(!acc.release(dec!(50.0)));
-        assert_eq!(acc.available, dec!(100.0));
-        assert_eq!(acc.held, dec!(50.0));
+        assert!(!acc.release(dec!(50.0)).unwrap());
+        assert_eq!(acc.available, dec!(100.0));
+        assert_eq!(acc.held, dec!(50.0));
+
+        acc.held = dec!(50.0);
+        assert!(acc.chargeback(dec!(50.0)).unwrap());
+        assert_eq!(acc.held, dec!(0.0));
+        assert!(acc.locked);
+
+        let mut acc2 = Account::new(2);
+        acc2.held = dec!(30.0);
+        acc2.locked = true;
+        assert!(!acc2.chargeback(dec!(50.0)).unwrap());
+        assert_eq!(acc2.held, dec!(30.0));
+    }
+
+    #[rstest]
+    #[case(LockedAccountPolicy::Accept, dec!(50.0), dec!(0.0), true)]
+    #[case(LockedAccountPolicy::Hold, dec!(0.0), dec!(50.0), true)]
+    #[case(LockedAccountPolicy::Reject, dec!(0.0), dec!(0.0), false)]
+    fn test_engine_locked_account_policy(
+        #[case] policy: LockedAccountPolicy,
+        #[case] expected_available: Decimal,
+        #[case] expected_held: Decimal,
+        #[case] accepted: bool,
+    ) {
+        let mut engine = PaymentEngine::new().with_locked_account_policy(policy);
+        for record_type in [
+            TransactionType::Deposit,
+            TransactionType::Dispute,
+            TransactionType::Chargeback,
+        ] {
+            engine
+                .process(simple(record_type, 1, Some(dec!(100.0))))
+                .unwrap();
+        }
+
+        let result = engine.process(simple(TransactionType::Deposit, 2, Some(dec!(50.0))));
+
+        match result {
+            Ok(()) => assert!(accepted),
+            Err(PaymentError::InvalidTransaction(msg)) => {
+                assert!(!accepted);
+                assert_eq!(msg, "Deposit 2 rejected: account 1 is locked");
+            }
+            Err(e) => panic!("Unexpected error {}", e),
+        }
+        let acc = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(acc.available, expected_available);
+        assert_eq!(acc.held, expected_held);
+        assert!(acc.locked);
+    }
+
+    #[rstest]
+    // Within the global limit.
+    #[case(None, dec!(50.0), dec!(-40.0))]
+    // Past it: declined.
+    #[case(None, dec!(70.0), dec!(10.0))]
+    // An admin record raises the limit for this client.
+    #[case(Some(dec!(100.0)), dec!(70.0), dec!(-60.0))]
+    // Or removes it.
+    #[case(Some(dec!(0.0)), dec!(50.0), dec!(10.0))]
+    fn test_engine_overdraft(
+        #[case] admin_limit: Option<Decimal>,
+        #[case] withdrawal: Decimal,
+        #[case] expected_available: Decimal,
+    ) {
+        let mut engine = PaymentEngine::new().with_overdraft_limit(dec!(50.0));
+        let mut records = vec![(TransactionType::Deposit, 1, dec!(10.0))];
+        if let Some(limit) = admin_limit {
+            records.push((TransactionType::Admin, 2, limit));
+        }
+        records.push((TransactionType::Withdrawal, 3, withdrawal));
+        for (record_type, tx_id, amount) in records {
+            engine
+                .process(simple(record_type, tx_id, Some(amount)))
+                .unwrap();
+        }
+
+        let output = engine
+            .accounts
+            .get(&(1, Currency::default()))
+            .unwrap()
+            .to_output_record();
+        assert_eq!(output.available, expected_available);
+        assert_eq!(output.overdraft, admin_limit.unwrap_or(dec!(50.0)));
+    }
+
+    #[rstest]
+    #[case(None, "Admin 1 missing amount")]
+    #[case(Some(dec!(-1.0)), "Overdraft limit for tx 1 can't be negative")]
+    fn test_engine_admin_invalid_limit(
+        #[case] amount: Option<Decimal>,
+        #[case] expected_msg: &str,
+    ) {
+        let mut engine = PaymentEngine::new();
+        let result = engine.process(simple(TransactionType::Admin, 1, amount));
+
+        match result {
+            Err(PaymentError::InvalidTransaction(msg)) => assert_eq!(msg, expected_msg),
+            other => panic!("Expected InvalidTransaction, got {:?}", other),
+        }
+        assert!(engine.accounts.is_empty());
+    }
+
+    #[rstest]
+    fn test_engine_adjustments() {
+        let mut engine = engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(10.0)),
+            (TransactionType::Dispute, 1, 1, dec!(0)),
+            (TransactionType::Chargeback, 1, 1, dec!(0)),
+        ])
+        .with_statement_history();
+        let adjustment = |record_type, tx_id, amount| InputRecord {
+            reference: Some("OPS-42".to_string()),
+            ..simple(record_type, tx_id, Some(amount))
+        };
+        // Locked accounts are adjusted, and debits may overdraw them.
+        engine
+            .process(adjustment(TransactionType::CreditAdjustment, 2, dec!(3.0)))
+            .unwrap();
+        engine
+            .process(adjustment(TransactionType::DebitAdjustment, 3, dec!(5.0)))
+            .unwrap();
+
+        let account = engine.get_account(1, Currency::default()).unwrap();
+        assert_eq!(account.available, dec!(-2.0));
+        assert!(account.locked);
+        let entries: Vec<(&str, &str)> = engine
+            .statement(1)
+            .unwrap()
+            .iter()
+            .map(|entry| (entry.action.as_str(), entry.metadata.as_str()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("credit_adjustment", r#"{"reference":"OPS-42"}"#),
+                ("debit_adjustment", r#"{"reference":"OPS-42"}"#),
+            ]
+        );
+        assert!(engine.reconcile().is_balanced());
+        assert_eq!(engine.stats().adjustments, 2);
+        // Adjustments can't be disputed.
+        engine
+            .process(simple(TransactionType::Dispute, 2, None))
+            .unwrap();
+        assert_eq!(
+            engine.get_account(1, Currency::default()).unwrap().held,
+            dec!(0)
+        );
+    }
+
+    #[rstest]
+    #[case(None, Some("OPS-1"), "Credit adjustment 1 missing amount")]
+    #[case(
+        Some(dec!(-1.0)),
+        Some("OPS-1"),
+        "Credit adjustment amount for tx 1 must be positive"
+    )]
+    #[case(Some(dec!(1.0)), None, "Credit adjustment 1 missing reference")]
+    #[case(Some(dec!(1.0)), Some(" "), "Credit adjustment 1 missing reference")]
+    fn test_engine_adjustment_invalid(
+        #[case] amount: Option<Decimal>,
+        #[case] reference: Option<&str>,
+        #[case] expected_msg: &str,
+    ) {
+        let mut engine = PaymentEngine::new();
+        let result = engine.process(InputRecord {
+            reference: reference.map(str::to_string),
+            ..simple(TransactionType::CreditAdjustment, 1, amount)
+        });
+
+        match result {
+            Err(PaymentError::InvalidTransaction(msg)) => assert_eq!(msg, expected_msg),
+            other => panic!("Expected InvalidTransaction, got {:?}", other),
+        }
+        assert!(engine.accounts.is_empty());
+    }
+
+    #[rstest]
+    fn test_engine_multi_currency_balances() {
+        let usd: Currency = "USD".parse().unwrap();
+        let eur: Currency = "EUR".parse().unwrap();
+        let mut engine = PaymentEngine::new();
+        for (record_type, tx_id, amount, currency) in [
+            (TransactionType::Deposit, 1, dec!(10.0), usd),
+            (TransactionType::Deposit, 2, dec!(5.0), eur),
+            (TransactionType::Deposit, 3, dec!(1.0), Currency::default()),
+            // Only 5.0 EUR available; the USD balance doesn't cover it.
+            (TransactionType::Withdrawal, 4, dec!(7.0), eur),
+            (TransactionType::Withdrawal, 5, dec!(4.0), usd),
+        ] {
+            engine
+                .process(InputRecord {
+                    currency,
+                    ..simple(record_type, tx_id, Some(amount))
+                })
+                .unwrap();
+        }
+
+        let mut accounts = engine.get_accounts();
+        accounts.sort_by_key(|a| a.currency);
+        let balances: Vec<_> = accounts
+            .iter()
+            .map(|a| (a.client_id, a.currency, a.available))
+            .collect();
+        assert_eq!(
+            balances,
+            [
+                (1, Currency::default(), dec!(1.0)),
+                (1, eur, dec!(5.0)),
+                (1, usd, dec!(6.0)),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case(Some(0), Some(90 * SECONDS_PER_DAY), None)]
+    #[case(Some(0), Some(90 * SECONDS_PER_DAY + 1), Some("Dispute for tx 1 is past the dispute window"))]
+    // Timestamps in the wrong order count as no time passed.
+    #[case(Some(SECONDS_PER_DAY), Some(0), None)]
+    // Without both timestamps the window can't be checked.
+    #[case(None, Some(365 * SECONDS_PER_DAY), None)]
+    #[case(Some(0), None, None)]
+    fn test_engine_dispute_window(
+        #[case] deposited_at: Option<u64>,
+        #[case] disputed_at: Option<u64>,
+        #[case] expected_err: Option<&str>,
+    ) {
+        let mut engine =
+            PaymentEngine::new().with_dispute_window(Duration::from_secs(90 * SECONDS_PER_DAY));
+        let record = |record_type, amount, timestamp| InputRecord {
+            record_type,
+            client_id: 1,
+            tx_id: 1,
+            amount,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+        engine
+            .process(record(
+                TransactionType::Deposit,
+                Some(dec!(10.0)),
+                deposited_at,
+            ))
+            .unwrap();
+
+        let result = engine.process(record(TransactionType::Dispute, None, disputed_at));
+        let held = engine.accounts.get(&(1, Currency::default())).unwrap().held;
+        match expected_err {
+            None => {
+                result.unwrap();
+                assert_eq!(held, dec!(10.0));
+            }
+            Some(expected) => {
+                match result {
+                    Err(PaymentError::InvalidTransaction(msg)) => assert_eq!(msg, expected),
+                    other => panic!("Expected InvalidTransaction, got {:?}", other),
+                }
+                assert_eq!(held, Decimal::ZERO);
+            }
+        }
+    }
+
+    fn keyed(tx_id: TxId, amount: Decimal, key: &str, timestamp: Option<u64>) -> InputRecord {
+        InputRecord {
+            record_type: TransactionType::Deposit,
+            client_id: 1,
+            tx_id,
+            amount: Some(amount),
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp,
+            idempotency_key: Some(key.to_string()),
+            reference: None,
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[rstest]
+    fn test_engine_idempotency_keys() {
+        let mut engine = PaymentEngine::new();
+        engine.process(keyed(1, dec!(10.0), "a", None)).unwrap();
+        // A retry of the same request is dropped, even under a new tx id.
+        engine.process(keyed(1, dec!(10.0), "a", None)).unwrap();
+        engine.process(keyed(3, dec!(10.0), "a", None)).unwrap();
+        // A new key is applied even though the tx id was already used.
+        engine.process(keyed(1, dec!(5.0), "b", None)).unwrap();
+        assert_eq!(
+            engine
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .available,
+            dec!(15.0)
+        );
+
+        // Reusing a key for a different request is a conflict.
+        match engine.process(keyed(2, dec!(1.0), "a", None)) {
+            Err(PaymentError::IdempotencyConflict(msg)) => {
+                assert_eq!(msg, "key 'a' was already used by tx 1")
+            }
+            other => panic!("Expected IdempotencyConflict, got {:?}", other),
+        }
+    }
+
+    #[rstest]
+    #[case::dispute(
+        vec![
+            simple(TransactionType::Deposit, 2, Some(dec!(4.0))),
+            simple(TransactionType::Dispute, 2, None),
+        ],
+        TransactionType::Resolve,
+        dec!(19.0)
+    )]
+    #[case::authorization(
+        vec![simple(TransactionType::Auth, 2, Some(dec!(4.0)))],
+        TransactionType::Void,
+        dec!(15.0)
+    )]
+    fn test_engine_idempotency_key_cant_reuse_open_tx_id(
+        #[case] opening: Vec<InputRecord>,
+        #[case] settle: TransactionType,
+        #[case] available: Decimal,
+    ) {
+        let mut engine = PaymentEngine::new();
+        engine.process(keyed(1, dec!(10.0), "a", None)).unwrap();
+        for record in opening {
+            engine.process(record).unwrap();
+        }
+
+        let err = engine.process(keyed(2, dec!(5.0), "b", None)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid transaction: Tx id 2 belongs to an open dispute or authorization"
+        );
+        engine.check_invariants().unwrap();
+
+        // Once settled, the tx id can be reused again.
+        engine.process(simple(settle, 2, None)).unwrap();
+        engine.process(keyed(2, dec!(5.0), "b", None)).unwrap();
+        let account = &engine.accounts[&(1, Currency::default())];
+        assert_eq!((account.available, account.held), (available, dec!(0.0)));
+        engine.check_invariants().unwrap();
+    }
+
+    #[rstest]
+    #[case::within_retention(3, dec!(10.0))]
+    #[case::after_retention(8, dec!(20.0))]
+    fn test_engine_idempotency_retention(#[case] retry_day: u64, #[case] expected: Decimal) {
+        let mut engine = PaymentEngine::new()
+            .with_idempotency_retention(Duration::from_secs(7 * SECONDS_PER_DAY));
+        engine.process(keyed(1, dec!(10.0), "a", Some(0))).unwrap();
+        engine
+            .process(keyed(1, dec!(10.0), "a", Some(retry_day * SECONDS_PER_DAY)))
+            .unwrap();
+        assert_eq!(
+            engine
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .available,
+            expected
+        );
+    }
+
+    #[rstest]
+    fn test_engine_dispute_currency_mismatch() {
+        let usd: Currency = "USD".parse().unwrap();
+        let mut engine = PaymentEngine::new();
+        let record = |record_type, amount, currency| InputRecord {
+            record_type,
+            client_id: 1,
+            tx_id: 1,
+            amount,
+            counterparty_id: None,
+            currency,
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+        engine
+            .process(record(TransactionType::Deposit, Some(dec!(10.0)), usd))
+            .unwrap();
+
+        let result = engine.process(record(
+            TransactionType::Dispute,
+            None,
+            "EUR".parse().unwrap(),
+        ));
+        match result {
+            Err(PaymentError::InvalidTransaction(msg)) => assert_eq!(
+                msg,
+                "Dispute for tx 1 is in 'EUR' but the transaction is in 'USD'"
+            ),
+            other => panic!("Expected InvalidTransaction, got {:?}", other),
+        }
+        assert_eq!(engine.accounts.get(&(1, usd)).unwrap().held, Decimal::ZERO);
+
+        engine
+            .process(record(TransactionType::Dispute, None, usd))
+            .unwrap();
+        assert_eq!(engine.accounts.get(&(1, usd)).unwrap().held, dec!(10.0));
+    }
+
+    /// Quotes every pair at the same rate.
+    #[derive(Debug)]
+    struct FlatRate(Decimal);
+
+    impl RateProvider for FlatRate {
+        fn rate(&self, _from: Currency, _to: Currency) -> Option<Decimal> {
+            Some(self.0)
+        }
+    }
+
+    #[rstest]
+    #[case(dec!(40.0), dec!(60.0), Some(dec!(30.0)))]
+    // Rounded to 4 decimal places.
+    #[case(dec!(0.0001), dec!(99.9999), Some(dec!(0.0001)))]
+    // Insufficient funds: ignored like a withdrawal.
+    #[case(dec!(150.0), dec!(100.0), None)]
+    fn test_engine_convert(
+        #[case] amount: Decimal,
+        #[case] expected_usd: Decimal,
+        #[case] expected_eur: Option<Decimal>,
+    ) {
+        let usd: Currency = "USD".parse().unwrap();
+        let eur: Currency = "EUR".parse().unwrap();
+        let mut engine = PaymentEngine::new().with_rate_provider(FlatRate(dec!(0.75)));
+        for (record_type, tx_id, amount, target_currency) in [
+            (TransactionType::Deposit, 1, dec!(100.0), None),
+            (TransactionType::Convert, 2, amount, Some(eur)),
+        ] {
+            engine
+                .process(InputRecord {
+                    record_type,
+                    client_id: 1,
+                    tx_id,
+                    amount: Some(amount),
+                    counterparty_id: None,
+                    currency: usd,
+                    target_currency,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+
+        assert_eq!(
+            engine.accounts.get(&(1, usd)).unwrap().available,
+            expected_usd
+        );
+        assert_eq!(
+            engine.accounts.get(&(1, eur)).map(|a| a.available),
+            expected_eur
+        );
+    }
+
+    #[rstest]
+    #[case(Some(dec!(1.0)), Some("EUR"), "No rate from 'USD' to 'EUR' for tx 2")]
+    #[case(Some(dec!(1.0)), None, "Convert 2 missing target currency")]
+    #[case(Some(dec!(1.0)), Some("usd"), "Convert 2 must target another currency")]
+    #[case(None, Some("EUR"), "Convert 2 missing amount")]
+    #[case(Some(dec!(0)), Some("EUR"), "Convert amount for tx 2 must be positive")]
+    fn test_engine_convert_invalid(
+        #[case] amount: Option<Decimal>,
+        #[case] target: Option<&str>,
+        #[case] expected_msg: &str,
+    ) {
+        let usd: Currency = "USD".parse().unwrap();
+        // No rate provider, so no pair is quoted.
+        let mut engine = PaymentEngine::new();
+        let record = |record_type, tx_id, amount, target_currency| InputRecord {
+            record_type,
+            client_id: 1,
+            tx_id,
+            amount,
+            counterparty_id: None,
+            currency: usd,
+            target_currency,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+        engine
+            .process(record(TransactionType::Deposit, 1, Some(dec!(10.0)), None))
+            .unwrap();
+
+        let target = target.map(|code| code.parse().unwrap());
+        match engine.process(record(TransactionType::Convert, 2, amount, target)) {
+            Err(PaymentError::InvalidTransaction(msg)) => assert_eq!(msg, expected_msg),
+            other => panic!("Expected InvalidTransaction, got {:?}", other),
+        }
+        assert_eq!(engine.accounts.len(), 1);
+        assert_eq!(
+            engine.accounts.get(&(1, usd)).unwrap().available,
+            dec!(10.0)
+        );
+    }
+
+    #[rstest]
+    fn test_engine_overflow_is_rejected() {
+        let eur: Currency = "EUR".parse().unwrap();
+        let mut engine = PaymentEngine::new().with_rate_provider(FlatRate(dec!(1)));
+        let record = |record_type, client_id, tx_id, amount, currency| InputRecord {
+            client_id,
+            counterparty_id: Some(2),
+            currency,
+            target_currency: Some(eur),
+            ..simple(record_type, tx_id, Some(amount))
+        };
+        let default = Currency::default();
+        for deposit in [
+            record(TransactionType::Deposit, 1, 1, dec!(10.0), default),
+            record(TransactionType::Deposit, 2, 2, Decimal::MAX, default),
+            record(TransactionType::Deposit, 1, 3, Decimal::MAX, eur),
+        ] {
+            engine.process(deposit).unwrap();
+        }
+
+        for (rejected, expected_msg) in [
+            (
+                record(TransactionType::Deposit, 2, 4, dec!(1.0), default),
+                "balance of client 2 would overflow",
+            ),
+            (
+                record(TransactionType::Transfer, 1, 5, dec!(5.0), default),
+                "Transfer 5 would overflow account 2",
+            ),
+            (
+                record(TransactionType::Convert, 1, 6, dec!(5.0), default),
+                "Convert 6 would overflow account 1",
+            ),
+        ] {
+            match engine.process(rejected) {
+                Err(PaymentError::Overflow(msg)) => assert_eq!(msg, expected_msg),
+                other => panic!("Expected Overflow, got {:?}", other),
+            }
+        }
+        let available = |key| engine.accounts.get(&key).unwrap().available;
+        assert_eq!(available((1, default)), dec!(10.0));
+        assert_eq!(available((2, default)), Decimal::MAX);
+        assert_eq!(available((1, eur)), Decimal::MAX);
+    }
+
+    fn timestamped(tx_id: TxId, amount: Decimal, day: u64) -> InputRecord {
+        InputRecord {
+            record_type: TransactionType::Deposit,
+            client_id: 1,
+            tx_id,
+            amount: Some(amount),
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: Some(day * SECONDS_PER_DAY),
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[rstest]
+    fn test_engine_interest() {
+        // 0.1% a day, posted every 10 days.
+        let mut engine = PaymentEngine::new()
+            .with_interest(InterestSchedule::new(dec!(36.5)).with_period_days(10))
+            .with_statement_history();
+        for record in [
+            timestamped(1, dec!(1000.0), 0),
+            timestamped(2, dec!(1.0), 12),
+            // Timestamps before the clock don't move it back.
+            timestamped(3, dec!(0.5), 3),
+            timestamped(4, dec!(1.0), 20),
+        ] {
+            engine.process(record).unwrap();
+        }
+
+        let entries: Vec<(TxId, &str, Decimal, Decimal)> = engine
+            .statement(1)
+            .unwrap()
+            .iter()
+            .map(|e| (e.tx, e.action.as_str(), e.amount, e.available))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (1, "deposit", dec!(1000), dec!(1000)),
+                // Days 0-10 on 1000.0.
+                (2, "interest", dec!(10), dec!(1010)),
+                (2, "deposit", dec!(1), dec!(1011)),
+                (3, "deposit", dec!(0.5), dec!(1011.5)),
+                // Days 10-12 on 1010.0, then days 12-20 on 1011.5.
+                (4, "interest", dec!(10.112), dec!(1021.612)),
+                (4, "deposit", dec!(1), dec!(1022.612)),
+            ]
+        );
+        assert_eq!(engine.stats().interest_paid, dec!(20.112));
+    }
+
+    #[rstest]
+    fn test_engine_restore_keeps_interest_clock() {
+        let make_engine = || {
+            PaymentEngine::new()
+                .with_interest(InterestSchedule::new(dec!(36.5)).with_period_days(10))
+        };
+        let mut engine = make_engine();
+        engine.process(timestamped(1, dec!(1000.0), 0)).unwrap();
+        engine.process(timestamped(2, dec!(1.0), 5)).unwrap();
+        let mut snapshot = Vec::new();
+        engine.snapshot(&mut snapshot).unwrap();
+
+        let mut restored = make_engine();
+        restored.restore(snapshot.as_slice()).unwrap();
+        restored.process(timestamped(3, dec!(1.0), 10)).unwrap();
+
+        // 5 days on 1000.0 before the snapshot, 5 days on 1001.0 after it.
+        assert_eq!(
+            restored
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .available,
+            dec!(1012.005)
+        );
+    }
+
+    #[rstest]
+    fn test_engine_fees() {
+        let schedule = FeeSchedule::new(99)
+            .with_fee(TransactionType::Deposit, Fee::percent(dec!(1)))
+            .with_fee(TransactionType::Withdrawal, Fee::flat(dec!(1.0)));
+        let mut engine = PaymentEngine::new()
+            .with_fee_schedule(schedule)
+            .with_statement_history();
+        let records = [
+            (TransactionType::Deposit, 1, dec!(100.0)),
+            (TransactionType::Withdrawal, 2, dec!(50.0)),
+            // Declined, so no fee.
+            (TransactionType::Withdrawal, 3, dec!(500.0)),
+        ];
+        for (record_type, tx_id, amount) in records {
+            engine
+                .process(InputRecord {
+                    record_type,
+                    client_id: 1,
+                    tx_id,
+                    amount: Some(amount),
+                    counterparty_id: None,
+                    currency: Currency::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+
+        assert_eq!(
+            engine
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .available,
+            dec!(48.0)
+        );
+        assert_eq!(
+            engine
+                .accounts
+                .get(&(99, Currency::default()))
+                .unwrap()
+                .available,
+            dec!(2.0)
+        );
+        assert_eq!(engine.stats().fees_collected, dec!(2.0));
+        let actions: Vec<&str> = engine
+            .statement(1)
+            .unwrap()
+            .iter()
+            .map(|e| e.action.as_str())
+            .collect();
+        assert_eq!(actions, ["deposit", "fee", "withdrawal", "fee"]);
+    }
+
+    fn suspense_engine_with(
+        records: &[(TransactionType, ClientId, TxId, Decimal)],
+    ) -> PaymentEngine {
+        applied(
+            PaymentEngine::new()
+                .with_suspense_account(99)
+                .with_statement_history(),
+            records,
+        )
+    }
+
+    #[rstest]
+    fn test_engine_suspense_account() {
+        let engine = suspense_engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(10.0)),
+            (TransactionType::Deposit, 1, 2, dec!(5.0)),
+            (TransactionType::Dispute, 1, 1, dec!(0.0)),
+            (TransactionType::Chargeback, 1, 1, dec!(0.0)),
+            // A withdrawal charged back is returned to its client instead.
+            (TransactionType::Deposit, 2, 3, dec!(8.0)),
+            (TransactionType::Withdrawal, 2, 4, dec!(3.0)),
+            (TransactionType::Dispute, 2, 4, dec!(0.0)),
+            (TransactionType::Chargeback, 2, 4, dec!(0.0)),
+        ]);
+
+        let suspense = engine.get_account(99, Currency::default()).unwrap();
+        assert_eq!(
+            (suspense.available, suspense.total),
+            (dec!(10.0), dec!(10.0))
+        );
+        assert!(!suspense.locked);
+        let client = engine.get_account(1, Currency::default()).unwrap();
+        assert_eq!((client.total, client.locked), (dec!(5.0), true));
+        // Everything deposited is still on the books, the charged back
+        // withdrawal having been returned.
+        let total: Decimal = engine.get_accounts().iter().map(|a| a.total).sum();
+        assert_eq!(total, dec!(23.0));
+        assert_eq!(engine.stats().written_off, dec!(10.0));
+        let entries = engine.statement(99).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            (entries[0].tx, entries[0].action.as_str()),
+            (1, "write_off")
+        );
+    }
+
+    #[rstest]
+    fn test_engine_merge_adds_up_suspense_accounts() {
+        let chargeback = |client_id: ClientId, tx_id: TxId| {
+            suspense_engine_with(&[
+                (TransactionType::Deposit, client_id, tx_id, dec!(4.0)),
+                (TransactionType::Dispute, client_id, tx_id, dec!(0.0)),
+                (TransactionType::Chargeback, client_id, tx_id, dec!(0.0)),
+            ])
+        };
+        let mut left = chargeback(1, 1);
+        left.merge(chargeback(2, 2)).unwrap();
+        let suspense = left.get_account(99, Currency::default()).unwrap();
+        assert_eq!(suspense.available, dec!(8.0));
+        assert_eq!(left.stats().written_off, dec!(8.0));
+    }
+
+    #[rstest]
+    fn test_engine_reconcile() {
+        let mut engine = suspense_engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(10.0)),
+            (TransactionType::Deposit, 1, 2, dec!(5.0)),
+            (TransactionType::Dispute, 1, 1, dec!(0.0)),
+            (TransactionType::Chargeback, 1, 1, dec!(0.0)),
+            (TransactionType::Deposit, 2, 3, dec!(8.0)),
+            (TransactionType::Withdrawal, 2, 4, dec!(3.0)),
+            (TransactionType::Dispute, 2, 4, dec!(0.0)),
+            (TransactionType::Chargeback, 2, 4, dec!(0.0)),
+        ]);
+        let report = engine.reconcile();
+        assert!(report.is_balanced());
+        let books = &report.currencies[0];
+        assert_eq!(
+            books.flows,
+            Flows {
+                deposits: dec!(23.0),
+                withdrawals: dec!(3.0),
+                chargebacks: dec!(10.0),
+                reversals: dec!(3.0),
+                written_off: dec!(10.0),
+                ..Flows::default()
+            }
+        );
+        assert_eq!((books.expected, books.actual), (dec!(23.0), dec!(23.0)));
+
+        // Funds that appear without a record are caught.
+        engine
+            .accounts
+            .get_mut(&(2, Currency::default()))
+            .unwrap()
+            .available += dec!(1.5);
+        let report = engine.reconcile();
+        assert!(!report.is_balanced());
+        assert_eq!(report.currencies[0].difference(), dec!(1.5));
+    }
+
+    #[rstest]
+    fn test_engine_reconcile_after_restore() {
+        let engine = engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(10.0)),
+            (TransactionType::Withdrawal, 1, 2, dec!(4.0)),
+        ]);
+        let mut snapshot = Vec::new();
+        engine.snapshot(&mut snapshot).unwrap();
+        let mut restored = PaymentEngine::new();
+        restored.restore(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.reconcile(), engine.reconcile());
+
+        // Snapshots from before flows were tracked open with their balances.
+        let mut old: serde_json::Value = serde_json::from_slice(&snapshot).unwrap();
+        old.as_object_mut().unwrap().remove("flows");
+        let mut restored = PaymentEngine::new();
+        restored.restore(old.to_string().as_bytes()).unwrap();
+        let report = restored.reconcile();
+        assert!(report.is_balanced());
+        assert_eq!(report.currencies[0].flows.opening, dec!(6.0));
+    }
+
+    #[rstest]
+    #[case(1, dec!(10.0), dec!(10.0), dec!(0.0), false)]
+    fn test_account_deposit(
+        #[case] client_id: ClientId,
+        #[case] deposit_amount: Decimal,
+        #[case] expected_available: Decimal,
+        #[case] expected_held: Decimal,
+        #[case] expected_locked: bool,
+    ) {
+        let mut acc = Account::new(client_id);
+        acc.deposit(deposit_amount).unwrap();
+        assert_eq!(acc.available, expected_available);
+        assert_eq!(acc.held, expected_held);
+        assert_eq!(acc.locked, expected_locked);
+        assert_eq!(acc.total(), expected_available + expected_held);
+    }
+
+    #[rstest]
+    #[case(dec!(100.0), dec!(50.0), true, dec!(50.0))]
+    #[case(dec!(100.0), dec!(100.0), true, dec!(0.0))]
+    #[case(dec!(100.0), dec!(150.0), false, dec!(100.0))]
+    fn test_account_withdraw(
+        #[case] initial_available: Decimal,
+        #[case] withdraw_amount: Decimal,
+        #[case] expected_success: bool,
+        #[case] expected_final_available: Decimal,
+    ) {
+        let mut acc = Account::new(1);
+        acc.available = initial_available;
+        let success = acc.withdraw(withdraw_amount).unwrap();
+        assert_eq!(success, expected_success);
+        assert_eq!(acc.available, expected_final_available);
+        assert_eq!(acc.held, dec!(0.0));
+    }
+
+    #[rstest]
+    #[case(dec!(100.0), dec!(50.0), true, dec!(50.0), dec!(50.0))]
+    #[case(dec!(100.0), dec!(150.0), false, dec!(100.0), dec!(0.0))]
+    fn test_account_hold(
+        #[case] initial_available: Decimal,
+        #[case] hold_amount: Decimal,
+        #[case] expected_success: bool,
+        #[case] expected_available: Decimal,
+        #[case] expected_held: Decimal,
+    ) {
+        let mut acc = Account::new(1);
+        acc.available = initial_available;
+        let success = acc.hold(hold_amount).unwrap();
+        assert_eq!(success, expected_success);
+        assert_eq!(acc.available, expected_available);
+        assert_eq!(acc.held, expected_held);
+    }
+
+    #[rstest]
+    #[case(dec!(50.0), dec!(50.0), true, dec!(50.0), dec!(0.0))]
+    #[case(dec!(50.0), dec!(100.0), false, dec!(0.0), dec!(50.0))]
+    fn test_account_release(
+        #[case] initial_held: Decimal,
+        #[case] release_amount: Decimal,
+        #[case] expected_success: bool,
+        #[case] expected_available: Decimal,
+        #[case] expected_held: Decimal,
+    ) {
+        let mut acc = Account::new(1);
+        acc.held = initial_held;
+        let success = acc.release(release_amount).unwrap();
+        assert_eq!(success, expected_success);
+        assert_eq!(acc.available, expected_available);
+        assert_eq!(acc.held, expected_held);
+    }
+
+    #[rstest]
+    #[case(dec!(50.0), dec!(50.0), true, dec!(0.0), dec!(0.0), true)]
+    #[case(dec!(50.0), dec!(100.0), false, dec!(0.0), dec!(50.0), false)]
+    fn test_account_chargeback(
+        #[case] initial_held: Decimal,
+        #[case] chargeback_amount: Decimal,
+        #[case] expected_success: bool,
+        #[case] expected_available: Decimal,
+        #[case] expected_held: Decimal,
+        #[case] expected_locked: bool,
+    ) {
+        let mut acc = Account::new(1);
+        acc.held = initial_held;
+        let success = acc.chargeback(chargeback_amount).unwrap();
+        assert_eq!(success, expected_success);
+        assert_eq!(acc.available, expected_available);
+        assert_eq!(acc.held, expected_held);
+        assert_eq!(acc.locked, expected_locked);
+    }
+
+    #[rstest]
+    fn test_engine_deposit_and_withdraw() {
+        let mut engine = PaymentEngine::new();
+        let rec1 = InputRecord {
+            record_type: TransactionType::Deposit,
+            client_id: 1,
+            tx_id: 1,
+            amount: Some(dec!(100.0)),
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+        let rec2 = InputRecord {
+            record_type: TransactionType::Withdrawal,
+            client_id: 1,
+            tx_id: 2,
+            amount: Some(dec!(30.0)),
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+        let rec3 = InputRecord {
+            record_type: TransactionType::Withdrawal,
+            client_id: 1,
+            tx_id: 3,
+            amount: Some(dec!(80.0)),
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        }; // Should fail
+
+        assert!(engine.process(rec1).is_ok());
+        assert!(engine.process(rec2).is_ok());
+        assert!(engine.process(rec3).is_ok());
+
+        let acc = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(acc.available, dec!(70.0));
+        assert_eq!(acc.held, dec!(0.0));
+        assert!(!acc.locked);
+        // The deposit and the successful withdrawal are stored, the failed one isn't.
+        assert_eq!(engine.transactions.len(), 2);
+    }
+
+    #[rstest]
+    fn test_engine_full_dispute_cycle() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(dec!(100.0)),
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Dispute,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+        let acc1 = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(acc1.available, dec!(0.0));
+        assert_eq!(acc1.held, dec!(100.0));
+        assert_eq!(
+            engine.transactions.get(1).unwrap().unwrap().state,
+            TransactionState::Disputed
+        );
+
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Resolve,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+        let acc2 = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(acc2.available, dec!(100.0));
+        assert_eq!(acc2.held, dec!(0.0));
+        assert!(!acc2.locked);
+        // the transaction is kept as resolved, having used up its dispute
+        let tx = engine.transactions.get(1).unwrap().unwrap();
+        assert_eq!(tx.state, TransactionState::Resolved);
+        assert_eq!(tx.disputes, 1);
+    }
+
+    #[rstest]
+    // Default: one dispute per transaction.
+    #[case(1, dec!(100.0), TransactionState::Resolved)]
+    // Re-dispute allowed once more.
+    #[case(2, dec!(0.0), TransactionState::Disputed)]
+    fn test_engine_redispute_after_resolve(
+        #[case] max_disputes: u8,
+        #[case] expected_available: Decimal,
+        #[case] expected_state: TransactionState,
+    ) {
+        let mut engine = PaymentEngine::new().with_max_disputes(max_disputes);
+        for record_type in [
+            TransactionType::Deposit,
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Dispute,
+        ] {
+            engine
+                .process(InputRecord {
+                    record_type,
+                    client_id: 1,
+                    tx_id: 1,
+                    amount: Some(dec!(100.0)),
+                    counterparty_id: None,
+                    currency: Currency::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+
+        assert_eq!(
+            engine
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .available,
+            expected_available
+        );
+        let tx = engine.transactions.get(1).unwrap().unwrap();
+        assert_eq!(tx.state, expected_state);
+        assert_eq!(tx.disputes, max_disputes);
+    }
+
+    #[rstest]
+    #[case(UnderfundedDisputeMode::Ignore, dec!(20.0), dec!(0.0), TransactionState::Normal)]
+    #[case(
+        UnderfundedDisputeMode::AllowNegative,
+        dec!(-80.0),
+        dec!(100.0),
+        TransactionState::Disputed
+    )]
+    #[case(
+        UnderfundedDisputeMode::Queue,
+        dec!(20.0),
+        dec!(0.0),
+        TransactionState::DisputeQueued
+    )]
+    fn test_engine_underfunded_dispute(
+        #[case] mode: UnderfundedDisputeMode,
+        #[case] expected_available: Decimal,
+        #[case] expected_held: Decimal,
+        #[case] expected_state: TransactionState,
+    ) {
+        let mut engine = PaymentEngine::new().with_underfunded_dispute_mode(mode);
+        for (record_type, tx_id, amount) in [
+            (TransactionType::Deposit, 1, Some(dec!(100.0))),
+            (TransactionType::Withdrawal, 2, Some(dec!(80.0))),
+            (TransactionType::Dispute, 1, None),
+        ] {
+            engine
+                .process(InputRecord {
+                    record_type,
+                    client_id: 1,
+                    tx_id,
+                    amount,
+                    counterparty_id: None,
+                    currency: Currency::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+
+        let acc = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(acc.available, expected_available);
+        assert_eq!(acc.held, expected_held);
+        assert_eq!(
+            engine.transactions.get(1).unwrap().unwrap().state,
+            expected_state
+        );
+    }
+
+    #[rstest]
+    // Funds arrive: the oldest queued dispute is held.
+    #[case(TransactionType::Deposit, dec!(10.0), dec!(100.0), TransactionState::Disputed)]
+    // Resolved before funds arrive: nothing is ever held.
+    #[case(TransactionType::Resolve, dec!(110.0), dec!(0.0), TransactionState::Resolved)]
+    fn test_engine_queued_dispute(
+        #[case] next: TransactionType,
+        #[case] expected_available: Decimal,
+        #[case] expected_held: Decimal,
+        #[case] expected_state: TransactionState,
+    ) {
+        let mut engine =
+            PaymentEngine::new().with_underfunded_dispute_mode(UnderfundedDisputeMode::Queue);
+        for (record_type, tx_id, amount) in [
+            (TransactionType::Deposit, 1, Some(dec!(100.0))),
+            (TransactionType::Withdrawal, 2, Some(dec!(80.0))),
+            (TransactionType::Dispute, 1, None),
+            (next, 1, None),
+            (TransactionType::Deposit, 3, Some(dec!(90.0))),
+        ] {
+            engine
+                .process(InputRecord {
+                    record_type,
+                    client_id: 1,
+                    tx_id,
+                    amount,
+                    counterparty_id: None,
+                    currency: Currency::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+
+        let acc = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(acc.available, expected_available);
+        assert_eq!(acc.held, expected_held);
+        assert_eq!(
+            engine.transactions.get(1).unwrap().unwrap().state,
+            expected_state
+        );
+        assert!(engine.queued_disputes.is_empty());
+    }
+
+    #[rstest]
+    fn test_engine_queued_dispute_chargeback() {
+        let mut engine =
+            PaymentEngine::new().with_underfunded_dispute_mode(UnderfundedDisputeMode::Queue);
+        for record in [
+            simple(TransactionType::Deposit, 1, Some(dec!(100.0))),
+            simple(TransactionType::Withdrawal, 2, Some(dec!(80.0))),
+            simple(TransactionType::Dispute, 1, None),
+            // Waits for the dispute's funds.
+            simple(TransactionType::Chargeback, 1, None),
+            // Too late, it's charged back.
+            simple(TransactionType::Resolve, 1, None),
+        ] {
+            engine.process(record).unwrap();
+        }
+        let account = engine.account(1, Currency::default()).unwrap();
+        assert_eq!((account.available, account.held), (dec!(20.0), dec!(0)));
+        assert!(!account.locked);
+
+        // The pending chargeback survives a snapshot.
+        let mut snapshot = Vec::new();
+        engine.snapshot(&mut snapshot).unwrap();
+        let mut restored =
+            PaymentEngine::new().with_underfunded_dispute_mode(UnderfundedDisputeMode::Queue);
+        restored.restore(snapshot.as_slice()).unwrap();
+        restored
+            .process(simple(TransactionType::Deposit, 3, Some(dec!(90.0))))
+            .unwrap();
+
+        let account = restored.account(1, Currency::default()).unwrap();
+        assert_eq!(
+            (account.available, account.held, account.locked),
+            (dec!(10.0), dec!(0), true)
+        );
+        assert!(restored.transactions.get(1).unwrap().is_none());
+        assert!(restored.queued_disputes.is_empty());
+        assert!(restored.pending_chargebacks.is_empty());
+    }
+
+    #[rstest]
+    fn test_engine_restore_requeues_disputes() {
+        let mut engine =
+            PaymentEngine::new().with_underfunded_dispute_mode(UnderfundedDisputeMode::Queue);
+        for (record_type, tx_id, amount) in [
+            (TransactionType::Deposit, 1, Some(dec!(100.0))),
+            (TransactionType::Withdrawal, 2, Some(dec!(80.0))),
+            (TransactionType::Dispute, 1, None),
+        ] {
+            engine
+                .process(InputRecord {
+                    record_type,
+                    client_id: 1,
+                    tx_id,
+                    amount,
+                    counterparty_id: None,
+                    currency: Currency::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+        let mut snapshot = Vec::new();
+        engine.snapshot(&mut snapshot).unwrap();
+
+        let mut restored =
+            PaymentEngine::new().with_underfunded_dispute_mode(UnderfundedDisputeMode::Queue);
+        restored.restore(snapshot.as_slice()).unwrap();
+        restored
+            .process(InputRecord {
+                record_type: TransactionType::Deposit,
+                client_id: 1,
+                tx_id: 3,
+                amount: Some(dec!(80.0)),
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+
+        assert_eq!(
+            restored
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .held,
+            dec!(100.0)
+        );
+    }
+
+    #[rstest]
+    fn test_engine_dispute_chargeback() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(dec!(100.0)),
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Dispute,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+        let acc1 = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(acc1.available, dec!(0.0));
+        assert_eq!(acc1.held, dec!(100.0));
+
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Chargeback,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+        let acc2 = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(acc2.available, dec!(0.0));
+        assert_eq!(acc2.held, dec!(0.0));
+        assert!(acc2.locked); // Account is now locked
+                              // the transaction is *gone* after being resolved
+        assert!(!engine.transactions.contains(1).unwrap());
+    }
+
+    #[rstest]
+    #[case(TransactionType::Dispute)]
+    #[case(TransactionType::Resolve)]
+    #[case(TransactionType::Chargeback)]
+    fn test_engine_ignore_non_existent_tx(#[case] tx_type: TransactionType) {
+        let mut engine = PaymentEngine::new();
+        let record = InputRecord {
+            record_type: tx_type,
+            client_id: 1,
+            tx_id: 99,
+            amount: None,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+
+        assert!(engine.process(record).is_ok());
+        assert!(engine.accounts.is_empty());
+        assert!(engine.transactions.is_empty());
+    }
+
+    #[rstest]
+    #[case(TransactionType::Resolve)]
+    #[case(TransactionType::Chargeback)]
+    fn test_engine_ignore_invalid_state(#[case] tx_type: TransactionType) {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(dec!(100.0)),
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+
+        let record = InputRecord {
+            record_type: tx_type,
+            client_id: 1,
+            tx_id: 1,
+            amount: None,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+        assert!(engine.process(record).is_ok());
+
+        let acc = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(acc.available, dec!(100.0));
+        assert_eq!(acc.held, dec!(0.0));
+        assert_eq!(
+            engine.transactions.get(1).unwrap().unwrap().state,
+            TransactionState::Normal
+        );
+    }
+
+    #[rstest]
+    fn test_engine_ignore_dispute_already_disputed() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(dec!(100.0)),
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Dispute,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+
+        let acc_before = engine
+            .accounts
+            .get(&(1, Currency::default()))
+            .unwrap()
+            .clone();
+        let tx_state_before = engine.transactions.get(1).unwrap().unwrap().state;
+
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Dispute,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+
+        let acc_after = engine.accounts.get(&(1, Currency::default())).unwrap();
+        let tx_state_after = engine.transactions.get(1).unwrap().unwrap().state;
+
+        assert_eq!(&acc_before, acc_after);
+        assert_eq!(tx_state_before, tx_state_after);
+    }
+
+    #[rstest]
+    fn test_engine_deposit_missing_amount() {
+        let mut engine = PaymentEngine::new();
+        let record = InputRecord {
+            record_type: TransactionType::Deposit,
+            client_id: 1,
+            tx_id: 99,
+            amount: None,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+
+        let result = engine.process(record);
+
+        assert!(result.is_err());
+
+        match result.err().unwrap() {
+            PaymentError::InvalidTransaction(msg) => {
+                assert!(msg.contains("Deposit 99 missing amount"));
+            }
+            _ => panic!("Expected InvalidTransaction error"),
+        }
+        assert!(engine.accounts.is_empty());
+    }
+
+    #[rstest]
+    #[case(dec!(-10.0))]
+    #[case(dec!(0.0))]
+    fn test_engine_deposit_invalid_amount(#[case] invalid_amount: Decimal) {
+        let mut engine = PaymentEngine::new();
+        let record = InputRecord {
+            record_type: TransactionType::Deposit,
+            client_id: 1,
+            tx_id: 100,
+            amount: Some(invalid_amount),
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+
+        let result = engine.process(record);
+
+        assert!(result.is_err());
+
+        match result.err().unwrap() {
+            PaymentError::InvalidTransaction(msg) => {
+                assert!(msg.contains("Deposit amount for tx 100 must be positive"));
+            }
+            _ => panic!("Expected InvalidTransaction error"),
+        }
+        assert!(engine.accounts.is_empty());
+    }
+
+    #[rstest]
+    fn test_engine_withdrawal_missing_amount() {
+        let mut engine = PaymentEngine::new();
+        let record = InputRecord {
+            record_type: TransactionType::Withdrawal,
+            client_id: 1,
+            tx_id: 201,
+            amount: None,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+
+        let result = engine.process(record);
+
+        assert!(result.is_err());
+
+        match result.err().unwrap() {
+            PaymentError::InvalidTransaction(msg) => {
+                assert!(msg.contains("Withdrawal 201 missing amount"));
+            }
+            _ => panic!("Expected InvalidTransaction error"),
+        }
+        assert!(engine.accounts.is_empty());
+    }
+
+    #[rstest]
+    fn test_engine_duplicate_deposit_is_ignored() {
+        let mut engine = PaymentEngine::new();
+        let record = InputRecord {
+            record_type: TransactionType::Deposit,
+            client_id: 1,
+            tx_id: 1,
+            amount: Some(rust_decimal_macros::dec!(100.0)),
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+
+        // First deposit should be processed
+        assert!(engine.process(record.clone()).is_ok());
+
+        // Second deposit with same tx_id should be ignored (covered line)
+        assert!(engine.process(record.clone()).is_ok());
+
+        // Only one deposit should be reflected in the account
+        let acc = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(acc.available, rust_decimal_macros::dec!(100.0));
+        assert_eq!(engine.transactions.len(), 1);
+    }
+
+    /// A record of client 1 in the default currency, without the optional
+    /// columns. Tests of other modules build on it too.
+    pub(crate) fn simple(
+        record_type: TransactionType,
+        tx_id: TxId,
+        amount: Option<Decimal>,
+    ) -> InputRecord {
+        InputRecord {
+            record_type,
+            client_id: 1,
+            tx_id,
+            amount,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[rstest]
+    // Declined for insufficient funds, so nothing was stored under tx 2.
+    #[case::declined_withdrawal(&[(TransactionType::Withdrawal, 2, Some(dec!(50.0)))])]
+    #[case::refunded_deposit(&[
+        (TransactionType::Deposit, 2, Some(dec!(5.0))),
+        (TransactionType::Refund, 2, None),
+    ])]
+    #[case::voided_auth(&[
+        (TransactionType::Auth, 2, Some(dec!(5.0))),
+        (TransactionType::Void, 2, None),
+    ])]
+    fn test_engine_tx_id_stays_taken(#[case] before: &[(TransactionType, TxId, Option<Decimal>)]) {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(simple(TransactionType::Deposit, 1, Some(dec!(10.0))))
+            .unwrap();
+        for &(record_type, tx_id, amount) in before {
+            engine.process(simple(record_type, tx_id, amount)).unwrap();
+        }
+
+        engine
+            .process(simple(TransactionType::Deposit, 2, Some(dec!(1.0))))
+            .unwrap();
+        let account = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(account.available, dec!(10.0));
+        assert!(!engine.transactions.contains(2).unwrap());
+    }
+
+    #[rstest]
+    #[case::ignore(DuplicateTxPolicy::Ignore, None)]
+    #[case::warn(DuplicateTxPolicy::Warn, None)]
+    #[case::error(DuplicateTxPolicy::Error, Some("Tx id 1 is already taken"))]
+    fn test_engine_duplicate_tx_policy(
+        #[case] policy: DuplicateTxPolicy,
+        #[case] expected_err: Option<&str>,
+    ) {
+        let mut engine = PaymentEngine::new().with_duplicate_tx_policy(policy);
+        engine
+            .process(simple(TransactionType::Deposit, 1, Some(dec!(10.0))))
+            .unwrap();
+
+        let result = engine.process(simple(TransactionType::Withdrawal, 1, Some(dec!(4.0))));
+        match expected_err {
+            None => result.unwrap(),
+            Some(expected) => match result {
+                Err(PaymentError::InvalidTransaction(msg)) => assert_eq!(msg, expected),
+                other => panic!("Expected InvalidTransaction, got {:?}", other),
+            },
+        }
+        let account = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(account.available, dec!(10.0));
+    }
+
+    #[rstest]
+    #[case::reject(AmountPrecision::Reject, None)]
+    #[case::truncate(AmountPrecision::Truncate, Some(dec!(1.2345)))]
+    #[case::round_half_even(AmountPrecision::RoundHalfEven, Some(dec!(1.2346)))]
+    fn test_engine_amount_precision(
+        #[case] precision: AmountPrecision,
+        #[case] expected: Option<Decimal>,
+    ) {
+        let mut engine = PaymentEngine::new().with_amount_precision(precision);
+        let result = engine.process(simple(TransactionType::Deposit, 1, Some(dec!(1.23456))));
+
+        match expected {
+            Some(available) => {
+                result.unwrap();
+                let account = engine.accounts.get(&(1, Currency::default())).unwrap();
+                assert_eq!(account.available, available);
+            }
+            None => {
+                match result {
+                    Err(PaymentError::InvalidTransaction(msg)) => assert_eq!(
+                        msg,
+                        "Amount 1.23456 for tx 1 has more than 4 decimal places"
+                    ),
+                    other => panic!("Expected InvalidTransaction, got {:?}", other),
+                }
+                assert!(engine.accounts.is_empty());
+            }
+        }
+    }
+
+    #[rstest]
+    fn test_engine_restore_keeps_spent_tx_ids() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(simple(TransactionType::Withdrawal, 1, Some(dec!(5.0))))
+            .unwrap();
+        let mut snapshot = Vec::new();
+        engine.snapshot(&mut snapshot).unwrap();
+
+        let mut restored = PaymentEngine::new();
+        restored.restore(snapshot.as_slice()).unwrap();
+        restored
+            .process(simple(TransactionType::Deposit, 1, Some(dec!(5.0))))
+            .unwrap();
+        let account = restored.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(account.available, Decimal::ZERO);
+    }
+
+    #[rstest]
+    #[case(TransactionType::Dispute, 42, 99, TransactionState::Normal, dec!(10.0))]
+    #[case(TransactionType::Resolve, 55, 77, TransactionState::Disputed, dec!(25.0))]
+    #[case(TransactionType::Chargeback, 88, 123, TransactionState::Disputed, dec!(40.0))]
+    fn test_missing_account_is_ignored(
+        #[case] tx_type: TransactionType,
+        #[case] tx_id: TxId,
+        #[case] client_id: ClientId,
+        #[case] state: TransactionState,
+        #[case] amount: Decimal,
+    ) {
+        let mut engine = PaymentEngine::new();
+
+        // Insert a transaction for a client that does not exist in accounts
+        engine
+            .transactions
+            .insert(
+                tx_id,
+                TransactionInfo {
+                    client_id,
+                    amount,
+                    state,
+                    direction: TransactionDirection::Credit,
+                    disputes: 0,
+                    currency: Currency::default(),
+                    timestamp: None,
+                },
+            )
+            .unwrap();
+
+        // Now process the record (dispute/resolve/chargeback)
+        let record = InputRecord {
+            record_type: tx_type,
+            client_id,
+            tx_id,
+            amount: None,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+
+        // This should hit the `None => return Ok(())` branch
+        assert!(engine.process(record).is_ok());
+        // Still no account created
+        assert!(!engine
+            .accounts
+            .contains_key(&(client_id, Currency::default())));
+    }
+
+    #[rstest]
+    #[case(dec!(-10.0))]
+    #[case(dec!(0.0))]
+    fn test_engine_withdrawal_invalid_amount(#[case] invalid_amount: Decimal) {
+        let mut engine = PaymentEngine::new();
+        let record = InputRecord {
+            record_type: TransactionType::Withdrawal,
+            client_id: 1,
+            tx_id: 202,
+            amount: Some(invalid_amount),
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+
+        let result = engine.process(record);
+
+        assert!(result.is_err());
+
+        match result.err().unwrap() {
+            PaymentError::InvalidTransaction(msg) => {
+                assert!(msg.contains("Withdrawal amount for tx 202 must be positive"));
+            }
+            _ => panic!("Expected InvalidTransaction error"),
+        }
+        assert!(engine.accounts.is_empty());
+    }
+
+    #[rstest]
+    fn test_engine_audit_log() {
+        use crate::audit::AuditEntry;
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut engine = PaymentEngine::new().with_audit_log(file.reopen().unwrap());
+        let record = |record_type, tx_id, amount| InputRecord {
+            record_type,
+            client_id: 1,
+            tx_id,
+            amount,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+        let records = [
+            record(TransactionType::Deposit, 1, Some(dec!(10.0))),
+            record(TransactionType::Withdrawal, 2, Some(dec!(50.0))), // Ignored, not audited.
+            record(TransactionType::Withdrawal, 3, Some(dec!(4.0))),
+            record(TransactionType::Dispute, 3, None),
+            record(TransactionType::Chargeback, 3, None),
+        ];
+        for rec in records {
+            engine.process(rec).unwrap();
+        }
+        engine.flush_audit_log().unwrap();
+
+        let entries: Vec<AuditEntry> = csv::Reader::from_path(file.path())
+            .unwrap()
+            .deserialize()
+            .map(Result::unwrap)
+            .collect();
+        let summary: Vec<(TxId, &str, Decimal, Decimal, bool)> = entries
+            .iter()
+            .map(|e| (e.tx, e.action.as_str(), e.available, e.held, e.locked))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (1, "deposit", dec!(10), dec!(0), false),
+                (3, "withdrawal", dec!(6), dec!(0), false),
+                (3, "dispute", dec!(6), dec!(4), false),
+                (3, "chargeback", dec!(10), dec!(0), true),
+            ]
+        );
+    }
+
+    #[rstest]
+    fn test_engine_audits_record_metadata() {
+        let mut engine = PaymentEngine::new().with_statement_history();
+        let record = |record_type, tx_id, metadata: &[(&str, &str)]| InputRecord {
+            metadata: metadata
+                .iter()
+                .map(|&(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            ..simple(record_type, tx_id, Some(dec!(2.0)))
+        };
+        let metadata = [("memo", "rent"), ("batch", "7")];
+        engine
+            .process(record(TransactionType::Deposit, 1, &metadata))
+            .unwrap();
+        engine
+            .process(record(TransactionType::Withdrawal, 2, &[]))
+            .unwrap();
+
+        let metadata: Vec<&str> = engine
+            .statement(1)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.metadata.as_str())
+            .collect();
+        assert_eq!(metadata, [r#"{"batch":"7","memo":"rent"}"#, ""]);
+    }
+
+    #[rstest]
+    fn test_engine_statement_history() {
+        let mut engine = PaymentEngine::new().with_statement_history();
+        assert_eq!(PaymentEngine::new().statement(1), None);
+
+        let records = [
+            (TransactionType::Deposit, 1, 1, Some(dec!(10.0)), None),
+            (TransactionType::Deposit, 2, 2, Some(dec!(3.0)), None),
+            (TransactionType::Transfer, 1, 3, Some(dec!(4.0)), Some(2)),
+            (TransactionType::Dispute, 2, 2, None, None),
+        ];
+        for (record_type, client_id, tx_id, amount, counterparty_id) in records {
+            engine
+                .process(InputRecord {
+                    record_type,
+                    client_id,
+                    tx_id,
+                    amount,
+                    counterparty_id,
+                    currency: Currency::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+
+        let running = |client_id| -> Vec<(TxId, String, Decimal, Decimal)> {
+            engine
+                .statement(client_id)
+                .unwrap()
+                .iter()
+                .map(|e| (e.tx, e.action.clone(), e.available, e.held))
+                .collect()
+        };
+        assert_eq!(
+            running(1),
+            [
+                (1, "deposit".to_string(), dec!(10), dec!(0)),
+                (3, "transfer_out".to_string(), dec!(6), dec!(0)),
+            ]
+        );
+        assert_eq!(
+            running(2),
+            [
+                (2, "deposit".to_string(), dec!(3), dec!(0)),
+                (3, "transfer_in".to_string(), dec!(7), dec!(0)),
+                (2, "dispute".to_string(), dec!(4), dec!(3)),
+            ]
+        );
+        assert!(running(9).is_empty());
+    }
+
+    #[rstest]
+    // Full refund of an undisputed deposit, without locking.
+    #[case(&[], dec!(0.0), false)]
+    // Deposit under dispute: refund is ignored.
+    #[case(&[TransactionType::Dispute], dec!(0.0), true)]
+    // Funds already withdrawn: refund is ignored like a failed withdrawal.
+    #[case(&[TransactionType::Withdrawal], dec!(0.0), true)]
+    // Second refund of the same deposit is ignored.
+    #[case(&[TransactionType::Refund], dec!(0.0), false)]
+    // Resolved deposit can still be refunded.
+    #[case(&[TransactionType::Dispute, TransactionType::Resolve], dec!(0.0), false)]
+    fn test_engine_refund(
+        #[case] before: &[TransactionType],
+        #[case] expected_available: Decimal,
+        #[case] deposit_kept: bool,
+    ) {
+        let mut engine = PaymentEngine::new();
+        let record = |record_type, tx_id, amount| InputRecord {
+            record_type,
+            client_id: 1,
+            tx_id,
+            amount,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+        engine
+            .process(record(TransactionType::Deposit, 1, Some(dec!(10.0))))
+            .unwrap();
+        for record_type in before {
+            let rec = match record_type {
+                TransactionType::Withdrawal => record(*record_type, 2, Some(dec!(10.0))),
+                _ => record(*record_type, 1, None),
+            };
+            engine.process(rec).unwrap();
+        }
+
+        engine
+            .process(record(TransactionType::Refund, 1, None))
+            .unwrap();
+
+        let account = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(account.available, expected_available);
+        assert!(!account.locked);
+        assert_eq!(engine.transactions.contains(1).unwrap(), deposit_kept);
+    }
+
+    #[rstest]
+    fn test_engine_refund_rejects_withdrawal_reference() {
+        let mut engine = PaymentEngine::new();
+        for (record_type, tx_id, amount) in [
+            (TransactionType::Deposit, 1, Some(dec!(10.0))),
+            (TransactionType::Withdrawal, 2, Some(dec!(4.0))),
+        ] {
+            engine
+                .process(InputRecord {
+                    record_type,
+                    client_id: 1,
+                    tx_id,
+                    amount,
+                    counterparty_id: None,
+                    currency: Currency::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+
+        let result = engine.process(InputRecord {
+            record_type: TransactionType::Refund,
+            client_id: 1,
+            tx_id: 2,
+            amount: None,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        });
+
+        match result {
+            Err(PaymentError::InvalidTransaction(msg)) => {
+                assert_eq!(msg, "Refund 2 must reference a deposit")
+            }
+            other => panic!("Expected InvalidTransaction, got {:?}", other),
+        }
+        assert_eq!(
+            engine
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .available,
+            dec!(6.0)
+        );
+    }
+
+    #[rstest]
+    // Open authorization: funds reserved, reported as held.
+    #[case(&[], dec!(6.0), dec!(4.0), true)]
+    // Capture settles the hold; the tx stays for disputes.
+    #[case(&[TransactionType::Capture], dec!(6.0), dec!(0.0), true)]
+    // Void releases the hold and forgets the tx.
+    #[case(&[TransactionType::Void], dec!(10.0), dec!(0.0), false)]
+    // Once captured, a void is ignored.
+    #[case(&[TransactionType::Capture, TransactionType::Void], dec!(6.0), dec!(0.0), true)]
+    // Once voided, a capture is ignored.
+    #[case(&[TransactionType::Void, TransactionType::Capture], dec!(10.0), dec!(0.0), false)]
+    // Authorizations can't be disputed.
+    #[case(&[TransactionType::Dispute], dec!(6.0), dec!(4.0), true)]
+    fn test_engine_auth_flow(
+        #[case] after: &[TransactionType],
+        #[case] expected_available: Decimal,
+        #[case] expected_held: Decimal,
+        #[case] auth_kept: bool,
+    ) {
+        let mut engine = engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(10.0)),
+            (TransactionType::Auth, 1, 2, dec!(4.0)),
+        ]);
+        for &record_type in after {
+            engine
+                .process(InputRecord {
+                    record_type,
+                    client_id: 1,
+                    tx_id: 2,
+                    amount: None,
+                    counterparty_id: None,
+                    currency: Currency::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+
+        let account = engine.accounts.get(&(1, Currency::default())).unwrap();
+        let output = account.to_output_record();
+        assert_eq!(output.available, expected_available);
+        assert_eq!(output.held, expected_held);
+        assert_eq!(output.total, expected_available + expected_held);
+        assert_eq!(engine.transactions.contains(2).unwrap(), auth_kept);
+    }
+
+    #[rstest]
+    fn test_engine_auth_insufficient_funds_is_declined() {
+        let engine = engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(3.0)),
+            (TransactionType::Auth, 1, 2, dec!(4.0)),
+        ]);
+
+        let account = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(account.available, dec!(3.0));
+        assert_eq!(account.authorized, dec!(0.0));
+        assert!(!engine.transactions.contains(2).unwrap());
+    }
+
+    #[rstest]
+    fn test_engine_captured_auth_can_be_disputed() {
+        let mut engine = engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(10.0)),
+            (TransactionType::Auth, 1, 2, dec!(4.0)),
+            (TransactionType::Capture, 1, 2, dec!(0.0)),
+        ]);
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Dispute,
+                client_id: 1,
+                tx_id: 2,
+                amount: None,
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+
+        assert_eq!(
+            engine.transactions.get(2).unwrap().unwrap().state,
+            TransactionState::Disputed
+        );
+    }
+
+    #[rstest]
+    // Emptied account: closed.
+    #[case(dec!(10.0), None)]
+    // Money left: stays open.
+    #[case(dec!(4.0), Some("Account 1 can't be closed with a non-zero balance"))]
+    fn test_engine_close(#[case] withdrawn: Decimal, #[case] expected_err: Option<&str>) {
+        let mut engine = engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(10.0)),
+            (TransactionType::Withdrawal, 1, 2, withdrawn),
+        ]);
+
+        let result = engine.process(InputRecord {
+            record_type: TransactionType::Close,
+            client_id: 1,
+            tx_id: 3,
+            amount: None,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        });
+
+        match (result, expected_err) {
+            (Ok(()), None) => {}
+            (Err(PaymentError::InvalidTransaction(msg)), Some(expected)) => {
+                assert_eq!(msg, expected)
+            }
+            (other, _) => panic!("Unexpected result {:?}", other),
+        }
+        assert_eq!(
+            engine.is_closed((1, Currency::default())),
+            expected_err.is_none()
+        );
+    }
+
+    #[rstest]
+    fn test_engine_close_unknown_account() {
+        let mut engine = PaymentEngine::new();
+        let result = engine.process(InputRecord {
+            record_type: TransactionType::Close,
+            client_id: 1,
+            tx_id: 1,
+            amount: None,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        });
+
+        match result {
+            Err(PaymentError::InvalidTransaction(msg)) => {
+                assert_eq!(msg, "Account 1 doesn't exist")
+            }
+            other => panic!("Expected InvalidTransaction, got {:?}", other),
+        }
+        assert!(engine.accounts.is_empty());
+    }
+
+    #[rstest]
+    #[case(TransactionType::Deposit, 1, None, "Account 1 is closed")]
+    #[case(TransactionType::Dispute, 1, None, "Account 1 is closed")]
+    #[case(
+        TransactionType::Transfer,
+        2,
+        Some(1),
+        "Transfer 9 targets closed account 1"
+    )]
+    fn test_engine_closed_account_rejects_transactions(
+        #[case] record_type: TransactionType,
+        #[case] client_id: ClientId,
+        #[case] counterparty_id: Option<ClientId>,
+        #[case] expected_msg: &str,
+    ) {
+        let mut engine = engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(10.0)),
+            (TransactionType::Withdrawal, 1, 2, dec!(10.0)),
+            (TransactionType::Close, 1, 3, dec!(0.0)),
+            (TransactionType::Deposit, 2, 4, dec!(5.0)),
+        ]);
+
+        let result = engine.process(InputRecord {
+            record_type,
+            client_id,
+            tx_id: 9,
+            amount: Some(dec!(1.0)),
+            counterparty_id,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        });
+
+        match result {
+            Err(PaymentError::InvalidTransaction(msg)) => assert_eq!(msg, expected_msg),
+            other => panic!("Expected InvalidTransaction, got {:?}", other),
+        }
+        assert_eq!(
+            engine
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .total(),
+            dec!(0.0)
+        );
+        assert_eq!(
+            engine
+                .accounts
+                .get(&(2, Currency::default()))
+                .unwrap()
+                .available,
+            dec!(5.0)
+        );
+    }
+
+    #[rstest]
+    fn test_engine_stats() {
+        let mut engine = PaymentEngine::new();
+        let record = |record_type, tx_id, amount| InputRecord {
+            record_type,
+            client_id: 1,
+            tx_id,
+            amount,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        };
+        let records = [
+            record(TransactionType::Deposit, 1, Some(dec!(10.0))),
+            record(TransactionType::Deposit, 1, Some(dec!(10.0))),
+            record(TransactionType::Withdrawal, 2, Some(dec!(-1.0))),
+            record(TransactionType::Dispute, 1, None),
+            record(TransactionType::Chargeback, 1, None),
+        ];
+        for rec in records {
+            let _ = engine.process(rec);
+        }
+
+        assert_eq!(
+            engine.stats(),
+            EngineStats {
+                deposits: 2,
+                disputes: 1,
+                chargebacks: 1,
+                failed: 1,
+                accounts: 1,
+                locked_accounts: 1,
+                ..EngineStats::default()
+            }
+        );
+        assert_eq!(engine.stats().processed(), 5);
+    }
+
+    #[rstest]
+    fn test_engine_transfer_moves_funds() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(dec!(100.0)),
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Transfer,
+                client_id: 1,
+                tx_id: 2,
+                amount: Some(dec!(40.0)),
+                counterparty_id: Some(2),
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+
+        assert_eq!(
+            engine
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .available,
+            dec!(60.0)
+        );
+        assert_eq!(
+            engine
+                .accounts
+                .get(&(2, Currency::default()))
+                .unwrap()
+                .available,
+            dec!(40.0)
+        );
+        assert_eq!(
+            engine.transactions.get(2).unwrap().unwrap().direction,
+            TransactionDirection::Debit
+        );
+        assert_eq!(engine.counter_legs.get(2).unwrap().unwrap().client_id, 2);
+    }
+
+    #[rstest]
+    fn test_engine_transfer_insufficient_funds_is_noop() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Transfer,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(dec!(40.0)),
+                counterparty_id: Some(2),
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+
+        assert_eq!(
+            engine
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .available,
+            dec!(0.0)
+        );
+        assert!(!engine.accounts.contains_key(&(2, Currency::default())));
+        assert!(engine.transactions.is_empty());
+        assert!(engine.counter_legs.is_empty());
+    }
+
+    #[rstest]
+    #[case(None, "Transfer 7 missing counterparty")]
+    #[case(Some(1), "Transfer 7 cannot target the sending client")]
+    fn test_engine_transfer_invalid_counterparty(
+        #[case] counterparty_id: Option<ClientId>,
+        #[case] expected_msg: &str,
+    ) {
+        let mut engine = PaymentEngine::new();
+        let result = engine.process(InputRecord {
+            record_type: TransactionType::Transfer,
+            client_id: 1,
+            tx_id: 7,
+            amount: Some(dec!(10.0)),
+            counterparty_id,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        });
+
+        match result.err().unwrap() {
+            PaymentError::InvalidTransaction(msg) => assert!(msg.contains(expected_msg)),
+            _ => panic!("Expected InvalidTransaction error"),
+        }
+        assert!(engine.accounts.is_empty());
+    }
+
+    #[rstest]
+    fn test_engine_dispute_received_transfer() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(dec!(100.0)),
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Transfer,
+                client_id: 1,
+                tx_id: 2,
+                amount: Some(dec!(40.0)),
+                counterparty_id: Some(2),
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Dispute,
+                client_id: 2,
+                tx_id: 2,
+                amount: None,
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+
+        let receiver = engine.accounts.get(&(2, Currency::default())).unwrap();
+        assert_eq!(receiver.available, dec!(0.0));
+        assert_eq!(receiver.held, dec!(40.0));
+        assert_eq!(
+            engine.counter_legs.get(2).unwrap().unwrap().state,
+            TransactionState::Disputed
+        );
+        assert_eq!(
+            engine.transactions.get(2).unwrap().unwrap().state,
+            TransactionState::Normal
+        );
+
+        engine
+            .process(InputRecord {
+                record_type: TransactionType::Chargeback,
+                client_id: 2,
+                tx_id: 2,
+                amount: None,
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+        assert!(
+            engine
+                .accounts
+                .get(&(2, Currency::default()))
+                .unwrap()
+                .locked
+        );
+        assert!(!engine.counter_legs.contains(2).unwrap());
+        assert!(engine.transactions.contains(2).unwrap());
+    }
+
+    #[rstest]
+    fn test_engine_charged_back_transfer_leg_keeps_tx_id_spent() {
+        let mut engine = PaymentEngine::new();
+        let transfer = |client_id, tx_id, amount, counterparty_id| InputRecord {
+            client_id,
+            counterparty_id: Some(counterparty_id),
+            ..simple(TransactionType::Transfer, tx_id, Some(amount))
+        };
+        let of_client = |client_id, record_type, tx_id, amount| InputRecord {
+            client_id,
+            ..simple(record_type, tx_id, amount)
+        };
+        for record in [
+            simple(TransactionType::Deposit, 1, Some(dec!(100.0))),
+            transfer(1, 2, dec!(40.0), 2),
+            of_client(2, TransactionType::Dispute, 2, None),
+            of_client(1, TransactionType::Dispute, 2, None),
+            of_client(1, TransactionType::Chargeback, 2, None),
+            of_client(2, TransactionType::Deposit, 3, Some(dec!(10.0))),
+        ] {
+            engine.process(record).unwrap();
+        }
+
+        // The receiver's leg is still disputed, so its id can't be reused:
+        // the transfer is dropped as a duplicate.
+        engine.process(transfer(2, 2, dec!(1.0), 3)).unwrap();
+        let account = engine.account(2, Currency::default()).unwrap();
+        assert_eq!((account.available, account.held), (dec!(10.0), dec!(40.0)));
+        assert!(engine.account(3, Currency::default()).is_none());
+        assert_eq!(
+            engine.counter_legs.get(2).unwrap().unwrap().state,
+            TransactionState::Disputed
+        );
+        engine.check_invariants().unwrap();
+    }
+
+    #[rstest]
+    fn test_account_debit_dispute_cycle() {
+        let mut acc = Account::new(1);
+        acc.available = dec!(50.0);
 
-        acc.held = dec!(50.0);
-        assert!(acc.chargeback(dec!(50.0)));
+        assert!(acc.hold_debit(dec!(20.0)).unwrap());
+        assert_eq!(acc.available, dec!(50.0));
+        assert_eq!(acc.held, dec!(20.0));
+
+        assert!(!acc.release_debit(dec!(30.0)).unwrap());
+        assert!(acc.release_debit(dec!(20.0)).unwrap());
+        assert_eq!(acc.held, dec!(0.0));
+        assert_eq!(acc.total(), dec!(50.0));
+
+        assert!(acc.hold_debit(dec!(20.0)).unwrap());
+        assert!(acc.chargeback_debit(dec!(20.0)).unwrap());
+        assert_eq!(acc.available, dec!(70.0));
         assert_eq!(acc.held, dec!(0.0));
         assert!(acc.locked);
 
-        let mut acc2 = Account::new(2);
-        acc2.held = dec!(30.0);
-        acc2.locked = true;
-        assert!(!acc2.chargeback(dec!(50.0)));
-        assert_eq!(acc2.held, dec!(30.0));
+        assert!(!acc.hold_debit(dec!(5.0)).unwrap());
+        assert!(!acc.chargeback_debit(dec!(5.0)).unwrap());
     }
 
     #[rstest]
-    #[case(1, dec!(10.0), dec!(10.0), dec!(0.0), false)]
-    fn test_account_deposit(
-        #[case] client_id: u16,
-        #[case] deposit_amount: Decimal,
+    fn test_account_overflow() {
+        let mut acc = Account::new(1);
+        acc.deposit(Decimal::MAX).unwrap();
+        assert!(!acc.can_deposit(dec!(1.0)));
+        assert!(matches!(
+            acc.deposit(dec!(1.0)),
+            Err(PaymentError::Overflow(_))
+        ));
+        // Available alone fits, but the total wouldn't.
+        assert!(matches!(
+            acc.hold_debit(dec!(1.0)),
+            Err(PaymentError::Overflow(_))
+        ));
+        assert_eq!(acc.available, Decimal::MAX);
+        assert_eq!(acc.held, Decimal::ZERO);
+
+        // Overdrafts can't overflow what's spendable either.
+        acc.overdraft_limit = dec!(10.0);
+        assert!(acc.withdraw(dec!(1.0)).unwrap());
+    }
+
+    #[rstest]
+    #[case(TransactionType::Resolve, dec!(60.0), false, Some(TransactionState::Resolved))]
+    #[case(TransactionType::Chargeback, dec!(100.0), true, None)]
+    fn test_engine_withdrawal_dispute(
+        #[case] outcome: TransactionType,
         #[case] expected_available: Decimal,
-        #[case] expected_held: Decimal,
         #[case] expected_locked: bool,
+        #[case] expected_state: Option<TransactionState>,
     ) {
-        let mut acc = Account::new(client_id);
-        acc.deposit(deposit_amount);
+        let mut engine = PaymentEngine::new();
+        for (record_type, tx_id, amount) in [
+            (TransactionType::Deposit, 1, Some(dec!(100.0))),
+            (TransactionType::Withdrawal, 2, Some(dec!(40.0))),
+            (TransactionType::Dispute, 2, None),
+        ] {
+            engine
+                .process(InputRecord {
+                    record_type,
+                    client_id: 1,
+                    tx_id,
+                    amount,
+                    counterparty_id: None,
+                    currency: Currency::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+
+        let acc = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(acc.available, dec!(60.0));
+        assert_eq!(acc.held, dec!(40.0));
+        assert_eq!(
+            engine.transactions.get(2).unwrap().unwrap().state,
+            TransactionState::Disputed
+        );
+
+        engine
+            .process(InputRecord {
+                record_type: outcome,
+                client_id: 1,
+                tx_id: 2,
+                amount: None,
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            })
+            .unwrap();
+
+        let acc = engine.accounts.get(&(1, Currency::default())).unwrap();
         assert_eq!(acc.available, expected_available);
-        assert_eq!(acc.held, expected_held);
+        assert_eq!(acc.held, dec!(0.0));
         assert_eq!(acc.locked, expected_locked);
-        assert_eq!(acc.total(), expected_available + expected_held);
+        assert_eq!(
+            engine.transactions.get(2).unwrap().map(|tx| tx.state),
+            expected_state
+        );
     }
 
-    #[rstest]
-    #[case(dec!(100.0), dec!(50.0), true, dec!(50.0))]
-    #[case(dec!(100.0), dec!(100.0), true, dec!(0.0))]
-    #[case(dec!(100.0), dec!(150.0), false, dec!(100.0))]
-    fn test_account_withdraw(
-        #[case] initial_available: Decimal,
-        #[case] withdraw_amount: Decimal,
-        #[case] expected_success: bool,
-        #[case] expected_final_available: Decimal,
-    ) {
-        let mut acc = Account::new(1);
-        acc.available = initial_available;
-        let success = acc.withdraw(withdraw_amount);
-        assert_eq!(success, expected_success);
-        assert_eq!(acc.available, expected_final_available);
-        assert_eq!(acc.held, dec!(0.0));
+    #[derive(Debug)]
+    struct MaxAmountPolicy(Decimal);
+
+    impl DisputePolicy for MaxAmountPolicy {
+        fn allows_dispute(&self, tx: &TransactionInfo, _account: &Account) -> bool {
+            tx.amount <= self.0
+        }
     }
 
     #[rstest]
-    #[case(dec!(100.0), dec!(50.0), true, dec!(50.0), dec!(50.0))]
-    #[case(dec!(100.0), dec!(150.0), false, dec!(100.0), dec!(0.0))]
-    fn test_account_hold(
-        #[case] initial_available: Decimal,
-        #[case] hold_amount: Decimal,
-        #[case] expected_success: bool,
+    #[case(dec!(100.0), dec!(0.0), TransactionState::Disputed)]
+    #[case(dec!(50.0), dec!(100.0), TransactionState::Normal)]
+    fn test_engine_consults_dispute_policy(
+        #[case] max_amount: Decimal,
         #[case] expected_available: Decimal,
-        #[case] expected_held: Decimal,
+        #[case] expected_state: TransactionState,
     ) {
-        let mut acc = Account::new(1);
-        acc.available = initial_available;
-        let success = acc.hold(hold_amount);
-        assert_eq!(success, expected_success);
-        assert_eq!(acc.available, expected_available);
-        assert_eq!(acc.held, expected_held);
+        let mut engine = PaymentEngine::new().with_dispute_policy(MaxAmountPolicy(max_amount));
+        for record_type in [TransactionType::Deposit, TransactionType::Dispute] {
+            engine
+                .process(InputRecord {
+                    record_type,
+                    client_id: 1,
+                    tx_id: 1,
+                    amount: Some(dec!(100.0)),
+                    counterparty_id: None,
+                    currency: Currency::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+
+        assert_eq!(
+            engine
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .available,
+            expected_available
+        );
+        assert_eq!(
+            engine.transactions.get(1).unwrap().unwrap().state,
+            expected_state
+        );
     }
 
-    #[rstest]
-    #[case(dec!(50.0), dec!(50.0), true, dec!(50.0), dec!(0.0))]
-    #[case(dec!(50.0), dec!(100.0), false, dec!(0.0), dec!(50.0))]
-    fn test_account_release(
-        #[case] initial_held: Decimal,
-        #[case] release_amount: Decimal,
-        #[case] expected_success: bool,
-        #[case] expected_available: Decimal,
-        #[case] expected_held: Decimal,
-    ) {
-        let mut acc = Account::new(1);
-        acc.held = initial_held;
-        let success = acc.release(release_amount);
-        assert_eq!(success, expected_success);
-        assert_eq!(acc.available, expected_available);
-        assert_eq!(acc.held, expected_held);
+    /// Rejects large amounts and holds withdrawals of more than half of what's
+    /// available.
+    #[derive(Debug)]
+    struct TestScorer;
+
+    impl RiskScorer for TestScorer {
+        fn score(&self, record: &InputRecord, account: Option<&Account>) -> RiskVerdict {
+            let amount = record.amount.unwrap_or_default();
+            if amount > dec!(1000) {
+                return RiskVerdict::Reject("amount too large".to_string());
+            }
+            let available = account.map_or(Decimal::ZERO, |account| account.available);
+            if record.record_type == TransactionType::Withdrawal && amount > available / dec!(2) {
+                return RiskVerdict::Hold;
+            }
+            RiskVerdict::Accept
+        }
     }
 
     #[rstest]
-    #[case(dec!(50.0), dec!(50.0), true, dec!(0.0), dec!(0.0), true)]
-    #[case(dec!(50.0), dec!(100.0), false, dec!(0.0), dec!(50.0), false)]
-    fn test_account_chargeback(
-        #[case] initial_held: Decimal,
-        #[case] chargeback_amount: Decimal,
-        #[case] expected_success: bool,
-        #[case] expected_available: Decimal,
-        #[case] expected_held: Decimal,
-        #[case] expected_locked: bool,
-    ) {
-        let mut acc = Account::new(1);
-        acc.held = initial_held;
-        let success = acc.chargeback(chargeback_amount);
-        assert_eq!(success, expected_success);
-        assert_eq!(acc.available, expected_available);
-        assert_eq!(acc.held, expected_held);
-        assert_eq!(acc.locked, expected_locked);
+    fn test_engine_consults_risk_scorer() {
+        let mut engine = PaymentEngine::new().with_risk_scorer(TestScorer);
+        engine
+            .process(simple(TransactionType::Deposit, 1, Some(dec!(100))))
+            .unwrap();
+        let err = engine
+            .process(simple(TransactionType::Deposit, 2, Some(dec!(5000))))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Rejected by risk scoring: tx 2: amount too large"
+        );
+        for (tx_id, amount) in [(3, dec!(80)), (4, dec!(20)), (5, dec!(50))] {
+            engine
+                .process(simple(TransactionType::Withdrawal, tx_id, Some(amount)))
+                .unwrap();
+        }
+        let held: Vec<TxId> = engine.held().iter().map(|record| record.tx_id).collect();
+        assert_eq!(held, [3, 5]);
+        assert_eq!(
+            engine.account(1, Currency::default()).unwrap().available,
+            dec!(80)
+        );
+
+        // Held records survive a snapshot.
+        let mut snapshot = Vec::new();
+        engine.snapshot(&mut snapshot).unwrap();
+        let mut engine = PaymentEngine::new().with_risk_scorer(TestScorer);
+        engine.restore(snapshot.as_slice()).unwrap();
+        let held: Vec<TxId> = engine.held().iter().map(|record| record.tx_id).collect();
+        assert_eq!(held, [3, 5]);
+
+        // Released records aren't scored again.
+        assert!(engine.release_held(3).unwrap());
+        assert!(!engine.release_held(3).unwrap());
+        assert_eq!(
+            engine.account(1, Currency::default()).unwrap().available,
+            dec!(0)
+        );
+        assert_eq!(engine.discard_held(5).unwrap().amount, Some(dec!(50)));
+        assert!(engine.held().is_empty());
+        engine
+            .process(simple(TransactionType::Deposit, 6, Some(dec!(10))))
+            .unwrap();
+        assert_eq!(
+            engine.account(1, Currency::default()).unwrap().available,
+            dec!(10)
+        );
     }
 
     #[rstest]
-    fn test_engine_deposit_and_withdraw() {
+    #[case(TransactionType::Dispute, TransactionState::Normal)]
+    #[case(TransactionType::Resolve, TransactionState::Disputed)]
+    #[case(TransactionType::Chargeback, TransactionState::Disputed)]
+    fn test_engine_strict_client_mismatch_is_rejected(
+        #[case] tx_type: TransactionType,
+        #[case] state: TransactionState,
+    ) {
         let mut engine = PaymentEngine::new();
-        let rec1 = InputRecord {
-            record_type: TransactionType::Deposit,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(dec!(100.0)),
-        };
-        let rec2 = InputRecord {
-            record_type: TransactionType::Withdrawal,
-            client_id: 1,
-            tx_id: 2,
-            amount: Some(dec!(30.0)),
-        };
-        let rec3 = InputRecord {
-            record_type: TransactionType::Withdrawal,
-            client_id: 1,
-            tx_id: 3,
-            amount: Some(dec!(80.0)),
-        }; // Should fail
+        let mut account = Account::new(1);
+        account.held = dec!(10.0);
+        engine
+            .accounts
+            .insert((1, Currency::default()), account.clone());
+        engine
+            .transactions
+            .insert(
+                1,
+                TransactionInfo {
+                    client_id: 1,
+                    amount: dec!(10.0),
+                    state,
+                    direction: TransactionDirection::Credit,
+                    disputes: 0,
+                    currency: Currency::default(),
+                    timestamp: None,
+                },
+            )
+            .unwrap();
 
-        assert!(engine.process(rec1).is_ok());
-        assert!(engine.process(rec2).is_ok());
-        assert!(engine.process(rec3).is_ok());
+        let result = engine.process(InputRecord {
+            record_type: tx_type,
+            client_id: 2,
+            tx_id: 1,
+            amount: None,
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        });
 
-        let acc = engine.accounts.get(&1).unwrap();
-        assert_eq!(acc.available, dec!(70.0));
-        assert_eq!(acc.held, dec!(0.0));
-        assert!(!acc.locked);
-        assert_eq!(engine.transactions.len(), 1);
+        match result.err().unwrap() {
+            PaymentError::InvalidTransaction(msg) => {
+                assert!(msg.contains("names client 2 but the transaction belongs to client 1"));
+            }
+            _ => panic!("Expected InvalidTransaction error"),
+        }
+        assert_eq!(
+            engine.accounts.get(&(1, Currency::default())).unwrap(),
+            &account
+        );
+        assert_eq!(engine.transactions.get(1).unwrap().unwrap().state, state);
     }
 
     #[rstest]
-    fn test_engine_full_dispute_cycle() {
-        let mut engine = PaymentEngine::new();
+    fn test_engine_lenient_client_mismatch_is_applied() {
+        let mut engine = PaymentEngine::new().with_client_match_mode(ClientMatchMode::Lenient);
         engine
             .process(InputRecord {
                 record_type: TransactionType::Deposit,
                 client_id: 1,
                 tx_id: 1,
                 amount: Some(dec!(100.0)),
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
             })
             .unwrap();
-
         engine
             .process(InputRecord {
                 record_type: TransactionType::Dispute,
-                client_id: 1,
+                client_id: 2,
                 tx_id: 1,
                 amount: None,
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
             })
             .unwrap();
-        let acc1 = engine.accounts.get(&1).unwrap();
-        assert_eq!(acc1.available, dec!(0.0));
-        assert_eq!(acc1.held, dec!(100.0));
+
         assert_eq!(
-            engine.transactions.get(&1).unwrap().state,
-            TransactionState::Disputed
+            engine.accounts.get(&(1, Currency::default())).unwrap().held,
+            dec!(100.0)
         );
+        assert!(!engine.accounts.contains_key(&(2, Currency::default())));
+    }
 
-        engine
+    #[rstest]
+    fn test_engine_with_disk_tx_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = PaymentEngine::new()
+            .with_tx_store(DiskTxStore::create(dir.path().join("tx.idx")).unwrap());
+        for (record_type, tx_id) in [
+            (TransactionType::Deposit, 1),
+            (TransactionType::Deposit, 2),
+            (TransactionType::Dispute, 1),
+            (TransactionType::Chargeback, 1),
+        ] {
+            engine
+                .process(InputRecord {
+                    record_type,
+                    client_id: 1,
+                    tx_id,
+                    amount: Some(dec!(10.0)),
+                    counterparty_id: None,
+                    currency: Currency::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+
+        let acc = engine.accounts.get(&(1, Currency::default())).unwrap();
+        assert_eq!(acc.available, dec!(10.0));
+        assert!(acc.locked);
+        assert_eq!(engine.transactions.len(), 1);
+        assert!(engine.transactions.contains(2).unwrap());
+    }
+
+    #[rstest]
+    fn test_engine_event_listeners() {
+        use crate::events::EngineEvent;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::mpsc;
+
+        let (sender, receiver) = mpsc::channel();
+        let deposits = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&deposits);
+        let mut engine = PaymentEngine::new()
+            .with_event_listener(sender)
+            .with_event_listener(move |event: &EngineEvent| {
+                if let EngineEvent::Deposited { .. } = event {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        for record in [
+            simple(TransactionType::Deposit, 1, Some(dec!(10.0))),
+            simple(TransactionType::Deposit, 2, Some(dec!(5.0))),
+            simple(TransactionType::Withdrawal, 3, Some(dec!(2.0))),
+            // Declined, so nothing happens.
+            simple(TransactionType::Withdrawal, 4, Some(dec!(50.0))),
+            simple(TransactionType::Dispute, 1, None),
+            simple(TransactionType::Dispute, 3, None),
+            simple(TransactionType::Chargeback, 1, None),
+            // The account is already locked by now.
+            simple(TransactionType::Chargeback, 3, None),
+        ] {
+            engine.process(record).unwrap();
+        }
+        drop(engine);
+
+        assert_eq!(deposits.load(Ordering::Relaxed), 2);
+        let (client, currency) = (1, Currency::default());
+        let (mut changes, mut events) = (Vec::new(), Vec::new());
+        for event in receiver.iter() {
+            match event {
+                EngineEvent::BalanceChanged { tx, change, .. } => changes.push((tx, change)),
+                event => events.push(event),
+            }
+        }
+        // Disputing the withdrawal holds it on top of the balance, and its
+        // chargeback only releases it.
+        assert_eq!(
+            changes,
+            [
+                (1, dec!(10.0)),
+                (2, dec!(5.0)),
+                (3, dec!(-2.0)),
+                (3, dec!(2.0)),
+                (1, dec!(-10.0)),
+            ]
+        );
+        assert_eq!(
+            events,
+            [
+                EngineEvent::Deposited {
+                    tx: 1,
+                    client,
+                    currency,
+                    amount: dec!(10.0),
+                },
+                EngineEvent::Deposited {
+                    tx: 2,
+                    client,
+                    currency,
+                    amount: dec!(5.0),
+                },
+                EngineEvent::Withdrawn {
+                    tx: 3,
+                    client,
+                    currency,
+                    amount: dec!(2.0),
+                },
+                EngineEvent::DisputeOpened {
+                    tx: 1,
+                    client,
+                    currency,
+                    amount: dec!(10.0),
+                },
+                EngineEvent::DisputeOpened {
+                    tx: 3,
+                    client,
+                    currency,
+                    amount: dec!(2.0),
+                },
+                EngineEvent::ChargebackApplied {
+                    tx: 1,
+                    client,
+                    currency,
+                    amount: dec!(10.0),
+                },
+                EngineEvent::AccountLocked {
+                    tx: 1,
+                    client,
+                    currency,
+                },
+                EngineEvent::ChargebackApplied {
+                    tx: 3,
+                    client,
+                    currency,
+                    amount: dec!(2.0),
+                },
+            ]
+        );
+    }
+
+    #[rstest]
+    fn test_engine_write_ahead_log_replay() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut engine = PaymentEngine::new()
+            .with_amount_precision(AmountPrecision::Truncate)
+            .with_write_ahead_log(file.reopen().unwrap());
+        let records = [
+            simple(TransactionType::Deposit, 1, Some(dec!(10.00009))),
+            simple(TransactionType::Deposit, 2, Some(dec!(5.0))),
+            // Declined, but it takes tx 3.
+            simple(TransactionType::Withdrawal, 3, Some(dec!(100.0))),
+            // Dropped as a duplicate.
+            simple(TransactionType::Deposit, 2, Some(dec!(5.0))),
+            // Rejected.
+            simple(TransactionType::Deposit, 5, Some(dec!(-1.0))),
+            simple(TransactionType::Dispute, 2, None),
+            simple(TransactionType::Withdrawal, 4, Some(dec!(1.0))),
+        ];
+        let results: Vec<bool> = records
+            .into_iter()
+            .map(|record| engine.process(record).is_ok())
+            .collect();
+        assert_eq!(results, [true, true, true, true, false, true, true]);
+
+        // Every record is logged, whatever became of it.
+        let log = std::fs::read_to_string(file.path()).unwrap();
+        assert_eq!(log.lines().count(), 7);
+        assert!(log.starts_with(r#"{"type":"deposit","client":1,"tx":1,"amount":"10.0000""#));
+
+        let replay_log = tempfile::NamedTempFile::new().unwrap();
+        let mut replayed = PaymentEngine::new()
+            .with_amount_precision(AmountPrecision::Truncate)
+            .with_write_ahead_log(replay_log.reopen().unwrap());
+        assert_eq!(replayed.replay(log.as_bytes()).unwrap(), 7);
+        assert_eq!(replayed.get_accounts(), engine.get_accounts());
+        // The tx ids the log used are taken again.
+        assert!(replayed
+            .process(simple(TransactionType::Deposit, 4, Some(dec!(1.0))))
+            .is_ok());
+        assert_eq!(replayed.get_accounts(), engine.get_accounts());
+        // Only the record given after the replay is logged.
+        assert_eq!(
+            std::fs::read_to_string(replay_log.path())
+                .unwrap()
+                .lines()
+                .count(),
+            1
+        );
+    }
+
+    #[rstest]
+    fn test_engine_replay_repeats_failed_records() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let blocklist = Blocklist::new().with_client(2);
+        let mut engine = engine_with(&[(TransactionType::Deposit, 2, 1, dec!(10))])
+            .with_blocklist(blocklist.clone())
+            .with_write_ahead_log(file.reopen().unwrap());
+        // Locks the account, then fails.
+        let err = engine
             .process(InputRecord {
-                record_type: TransactionType::Resolve,
-                client_id: 1,
-                tx_id: 1,
-                amount: None,
+                client_id: 2,
+                ..simple(TransactionType::Withdrawal, 2, Some(dec!(5)))
             })
-            .unwrap();
-        let acc2 = engine.accounts.get(&1).unwrap();
-        assert_eq!(acc2.available, dec!(100.0));
-        assert_eq!(acc2.held, dec!(0.0));
-        assert!(!acc2.locked);
-        // the transaction is *gone* after being resolved
-        assert!(!engine.transactions.contains_key(&1));
+            .unwrap_err();
+        assert!(matches!(err, PaymentError::Blocked(_)));
+
+        let log = std::fs::read_to_string(file.path()).unwrap();
+        let mut replayed =
+            engine_with(&[(TransactionType::Deposit, 2, 1, dec!(10))]).with_blocklist(blocklist);
+        assert_eq!(replayed.replay(log.as_bytes()).unwrap(), 1);
+        assert_eq!(replayed.get_accounts(), engine.get_accounts());
+        assert!(replayed.account(2, Currency::default()).unwrap().locked);
     }
 
     #[rstest]
-    fn test_engine_dispute_chargeback() {
-        let mut engine = PaymentEngine::new();
+    fn test_engine_replay_rejects_inconsistent_log() {
+        // Written by an engine that truncated amounts instead of rejecting them.
+        let log =
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"5.00001\",\"currency\":\"\"}\n";
+        let err = PaymentEngine::new().replay(log.as_bytes()).unwrap_err();
+        assert!(matches!(err, PaymentError::AtLine { line: 1, .. }));
+    }
+
+    #[rstest]
+    fn test_engine_with_account_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.db");
+        let mut engine = PaymentEngine::new()
+            .with_account_store(DiskAccountStore::open(&path).unwrap())
+            .unwrap();
         engine
-            .process(InputRecord {
-                record_type: TransactionType::Deposit,
-                client_id: 1,
-                tx_id: 1,
-                amount: Some(dec!(100.0)),
-            })
+            .process(simple(TransactionType::Deposit, 1, Some(dec!(10.0))))
+            .unwrap();
+        engine
+            .process(simple(TransactionType::Withdrawal, 2, Some(dec!(4.0))))
             .unwrap();
+        drop(engine);
 
+        // A new engine over the same store picks up where the last one stopped.
+        let mut engine = PaymentEngine::new()
+            .with_account_store(DiskAccountStore::open(&path).unwrap())
+            .unwrap();
+        assert_eq!(
+            engine
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .available,
+            dec!(6.0)
+        );
         engine
+            .process(simple(TransactionType::Deposit, 3, Some(dec!(1.0))))
+            .unwrap();
+        let store = DiskAccountStore::open(&path).unwrap();
+        let stored = store.get((1, Currency::default())).unwrap().unwrap();
+        assert_eq!(stored.available, dec!(7.0));
+        // What the store held is the opening balance of the books.
+        let books = &engine.reconcile().currencies[0];
+        assert_eq!(
+            (books.flows.opening, books.flows.deposits),
+            (dec!(6.0), dec!(1.0))
+        );
+        assert!(books.is_balanced());
+    }
+
+    #[rstest]
+    fn test_engine_snapshot_and_restore() {
+        let mut engine = PaymentEngine::new();
+        for (record_type, tx_id, amount, counterparty_id) in [
+            (TransactionType::Deposit, 1, Some(dec!(10.0)), None),
+            (TransactionType::Deposit, 3, Some(dec!(5.0)), None),
+            (TransactionType::Transfer, 2, Some(dec!(5.0)), Some(2)),
+            (TransactionType::Dispute, 1, None, None),
+        ] {
+            engine
+                .process(InputRecord {
+                    record_type,
+                    client_id: 1,
+                    tx_id,
+                    amount,
+                    counterparty_id,
+                    currency: Currency::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+        assert_eq!(
+            engine.accounts.get(&(1, Currency::default())).unwrap().held,
+            dec!(10.0)
+        );
+
+        let mut buf = Vec::new();
+        engine.snapshot(&mut buf).unwrap();
+
+        let mut restored = PaymentEngine::new();
+        restored
             .process(InputRecord {
-                record_type: TransactionType::Dispute,
-                client_id: 1,
-                tx_id: 1,
-                amount: None,
+                record_type: TransactionType::Deposit,
+                client_id: 9,
+                tx_id: 9,
+                amount: Some(dec!(1.0)),
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
             })
             .unwrap();
-        let acc1 = engine.accounts.get(&1).unwrap();
-        assert_eq!(acc1.available, dec!(0.0));
-        assert_eq!(acc1.held, dec!(100.0));
+        restored.restore(buf.as_slice()).unwrap();
+
+        assert_eq!(restored.accounts, engine.accounts);
+        assert_eq!(
+            restored.transactions.entries().unwrap().len(),
+            engine.transactions.len()
+        );
+        assert!(!restored.transactions.contains(9).unwrap());
+        assert_eq!(
+            restored.counter_legs.get(2).unwrap(),
+            engine.counter_legs.get(2).unwrap()
+        );
 
-        engine
+        // Processing resumes where the snapshot left off.
+        restored
             .process(InputRecord {
-                record_type: TransactionType::Chargeback,
+                record_type: TransactionType::Resolve,
                 client_id: 1,
                 tx_id: 1,
                 amount: None,
+                counterparty_id: None,
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
             })
             .unwrap();
-        let acc2 = engine.accounts.get(&1).unwrap();
-        assert_eq!(acc2.available, dec!(0.0));
-        assert_eq!(acc2.held, dec!(0.0));
-        assert!(acc2.locked); // Account is now locked
-                              // the transaction is *gone* after being resolved
-        assert!(!engine.transactions.contains_key(&1));
+        assert_eq!(
+            restored
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .available,
+            dec!(10.0)
+        );
+        assert_eq!(
+            restored
+                .accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .held,
+            dec!(0.0)
+        );
     }
 
     #[rstest]
-    #[case(TransactionType::Dispute)]
-    #[case(TransactionType::Resolve)]
-    #[case(TransactionType::Chargeback)]
-    fn test_engine_ignore_non_existent_tx(#[case] tx_type: TransactionType) {
+    #[case("{\"version\":99,\"accounts\":[],\"transactions\":[],\"counter_legs\":[]}")]
+    #[case("not a snapshot")]
+    fn test_engine_restore_rejects_invalid_snapshot(#[case] input: &str) {
         let mut engine = PaymentEngine::new();
-        let record = InputRecord {
-            record_type: tx_type,
-            client_id: 1,
-            tx_id: 99,
-            amount: None,
-        };
+        let result = engine.restore(input.as_bytes());
 
-        assert!(engine.process(record).is_ok());
-        assert!(engine.accounts.is_empty());
-        assert!(engine.transactions.is_empty());
+        assert!(matches!(
+            result,
+            Err(PaymentError::InvalidSnapshot(_)) | Err(PaymentError::Json(_))
+        ));
     }
 
     #[rstest]
-    #[case(TransactionType::Resolve)]
-    #[case(TransactionType::Chargeback)]
-    fn test_engine_ignore_invalid_state(#[case] tx_type: TransactionType) {
-        let mut engine = PaymentEngine::new();
+    fn test_engine_check_invariants() {
+        let mut engine = engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(10.0)),
+            (TransactionType::Deposit, 1, 2, dec!(5.0)),
+            (TransactionType::Auth, 1, 3, dec!(2.0)),
+        ]);
         engine
-            .process(InputRecord {
-                record_type: TransactionType::Deposit,
-                client_id: 1,
-                tx_id: 1,
-                amount: Some(dec!(100.0)),
-            })
+            .process(simple(TransactionType::Dispute, 2, None))
             .unwrap();
+        engine.check_invariants().unwrap();
 
-        let record = InputRecord {
-            record_type: tx_type,
-            client_id: 1,
-            tx_id: 1,
-            amount: None,
-        };
-        assert!(engine.process(record).is_ok());
+        let key = (1, Currency::default());
+        engine.accounts.get_mut(&key).unwrap().held = dec!(4.0);
+        assert_eq!(
+            engine.check_invariants().unwrap_err().to_string(),
+            "Invariant violated: client 1 holds 4.0 for 5.0 of open disputes"
+        );
 
-        let acc = engine.accounts.get(&1).unwrap();
-        assert_eq!(acc.available, dec!(100.0));
-        assert_eq!(acc.held, dec!(0.0));
+        // Locked accounts may hold more than their disputes.
+        let account = engine.accounts.get_mut(&key).unwrap();
+        account.held = dec!(6.0);
+        account.locked = true;
+        engine.check_invariants().unwrap();
+
+        engine.accounts.get_mut(&key).unwrap().authorized = dec!(0.0);
         assert_eq!(
-            engine.transactions.get(&1).unwrap().state,
-            TransactionState::Normal
+            engine.check_invariants().unwrap_err().to_string(),
+            "Invariant violated: client 1 has 0.0 authorized for 2.0 of open authorizations"
         );
     }
 
     #[rstest]
-    fn test_engine_ignore_dispute_already_disputed() {
-        let mut engine = PaymentEngine::new();
+    fn test_engine_with_capacity() {
+        let mut engine = PaymentEngine::with_capacity(100, 1_000);
+        assert!(engine.accounts.capacity() >= 100);
+
+        for tx_id in 1..=3 {
+            engine
+                .process(simple(TransactionType::Deposit, tx_id, Some(dec!(2.0))))
+                .unwrap();
+        }
         engine
-            .process(InputRecord {
-                record_type: TransactionType::Deposit,
-                client_id: 1,
-                tx_id: 1,
-                amount: Some(dec!(100.0)),
-            })
+            .process(simple(TransactionType::Dispute, 2, None))
+            .unwrap();
+        let account = engine.get_account(1, Currency::default()).unwrap();
+        assert_eq!(account.available, dec!(4.0));
+        assert_eq!(account.held, dec!(2.0));
+        engine.check_invariants().unwrap();
+    }
+
+    #[rstest]
+    fn test_engine_iter_accounts() {
+        let engine = engine_with(&[
+            (TransactionType::Deposit, 3, 1, dec!(3.0)),
+            (TransactionType::Deposit, 1, 2, dec!(1.0)),
+            (TransactionType::Deposit, 2, 3, dec!(2.0)),
+        ]);
+
+        let mut accounts: Vec<_> = engine
+            .iter_accounts()
+            .map(Account::to_output_record)
+            .collect();
+        accounts.sort_by_key(|a| a.client_id);
+        let mut expected = engine.get_accounts();
+        expected.sort_by_key(|a| a.client_id);
+        assert_eq!(accounts, expected);
+
+        let sorted = engine.iter_accounts_sorted();
+        assert_eq!(sorted.len(), 3);
+        assert_eq!(
+            sorted
+                .map(|a| (a.client_id, a.available))
+                .collect::<Vec<_>>(),
+            [(1, dec!(1.0)), (2, dec!(2.0)), (3, dec!(3.0))]
+        );
+    }
+
+    #[rstest]
+    fn test_engine_retain_clients() {
+        let mut engine = engine_with(&[
+            (TransactionType::Deposit, 3, 1, dec!(3.0)),
+            (TransactionType::Deposit, 1, 2, dec!(1.0)),
+            (TransactionType::Deposit, 2, 3, dec!(2.0)),
+        ]);
+        engine.retain_clients(|client_id| client_id != 2);
+        let clients: Vec<ClientId> = engine.iter_accounts_sorted().map(|a| a.client_id).collect();
+        assert_eq!(clients, [1, 3]);
+    }
+
+    #[rstest]
+    fn test_engine_empty_accounts() {
+        let mut engine = engine_with(&[
+            // Ignored for lack of funds, but the account is created anyway.
+            (TransactionType::Withdrawal, 1, 1, dec!(5.0)),
+            (TransactionType::Deposit, 2, 2, dec!(3.0)),
+            (TransactionType::Withdrawal, 2, 3, dec!(3.0)),
+            (TransactionType::Deposit, 3, 4, dec!(1.0)),
+        ]);
+        let empty: Vec<ClientId> = engine
+            .iter_accounts_sorted()
+            .filter(|account| account.is_empty())
+            .map(|account| account.client_id)
+            .collect();
+        assert_eq!(empty, [1]);
+
+        engine.retain_accounts(|account| !account.is_empty());
+        let clients: Vec<ClientId> = engine.iter_accounts_sorted().map(|a| a.client_id).collect();
+        assert_eq!(clients, [2, 3]);
+    }
+
+    #[rstest]
+    fn test_engine_account_queries() {
+        let mut engine = engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(5.0)),
+            (TransactionType::Deposit, 1, 2, dec!(3.0)),
+            (TransactionType::Deposit, 1, 3, dec!(1.0)),
+            (TransactionType::Deposit, 2, 4, dec!(2.0)),
+        ]);
+        for tx_id in [3, 2] {
+            engine
+                .process(simple(TransactionType::Dispute, tx_id, None))
+                .unwrap();
+        }
+        engine
+            .process(simple(TransactionType::Chargeback, 3, None))
             .unwrap();
         engine
             .process(InputRecord {
-                record_type: TransactionType::Dispute,
-                client_id: 1,
-                tx_id: 1,
-                amount: None,
+                client_id: 2,
+                ..simple(TransactionType::Dispute, 4, None)
             })
             .unwrap();
 
-        let acc_before = engine.accounts.get(&1).unwrap().clone();
-        let tx_state_before = engine.transactions.get(&1).unwrap().state;
+        let account = engine.account(1, Currency::default()).unwrap();
+        assert_eq!(account.available, dec!(5.0));
+        assert_eq!(account.held, dec!(3.0));
+        assert!(engine.account(9, Currency::default()).is_none());
+
+        assert!(engine.is_locked(1));
+        assert!(!engine.is_locked(2));
+        assert!(!engine.is_locked(9));
+
+        let disputed = |client_id| -> Vec<TxId> {
+            engine
+                .disputed_transactions(client_id)
+                .unwrap()
+                .into_iter()
+                .map(|(tx_id, _)| tx_id)
+                .collect()
+        };
+        assert_eq!(disputed(1), [2]);
+        assert_eq!(disputed(2), [4]);
+        assert!(disputed(9).is_empty());
+    }
 
+    #[rstest]
+    fn test_engine_open_disputes() {
+        let mut engine = engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(5.0)),
+            (TransactionType::Deposit, 1, 2, dec!(3.0)),
+            (TransactionType::Deposit, 2, 3, dec!(1.0)),
+        ]);
+        let filed = |tx_id, client_id, timestamp| InputRecord {
+            client_id,
+            timestamp,
+            ..simple(TransactionType::Dispute, tx_id, None)
+        };
+        engine.process(filed(1, 1, Some(100))).unwrap();
+        engine.process(filed(2, 1, None)).unwrap();
+        engine.process(filed(3, 2, Some(200))).unwrap();
         engine
             .process(InputRecord {
-                record_type: TransactionType::Dispute,
-                client_id: 1,
-                tx_id: 1,
-                amount: None,
+                client_id: 2,
+                ..simple(TransactionType::Resolve, 3, None)
             })
             .unwrap();
 
-        let acc_after = engine.accounts.get(&1).unwrap();
-        let tx_state_after = engine.transactions.get(&1).unwrap().state;
+        let open = engine.open_disputes().unwrap();
+        assert_eq!(
+            open,
+            [
+                OpenDispute {
+                    tx_id: 1,
+                    client_id: 1,
+                    currency: Currency::default(),
+                    amount: dec!(5.0),
+                    opened_at: Some(100),
+                    queued: false,
+                },
+                OpenDispute {
+                    tx_id: 2,
+                    client_id: 1,
+                    currency: Currency::default(),
+                    amount: dec!(3.0),
+                    opened_at: None,
+                    queued: false,
+                },
+            ]
+        );
 
-        assert_eq!(&acc_before, acc_after);
-        assert_eq!(tx_state_before, tx_state_after);
+        let mut snapshot = Vec::new();
+        engine.snapshot(&mut snapshot).unwrap();
+        let mut restored = PaymentEngine::new();
+        restored.restore(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.open_disputes().unwrap(), open);
     }
 
     #[rstest]
-    fn test_engine_deposit_missing_amount() {
-        let mut engine = PaymentEngine::new();
-        let record = InputRecord {
-            record_type: TransactionType::Deposit,
-            client_id: 1,
-            tx_id: 99,
+    #[case(ExpiredDisputeAction::Resolve, dec!(10.0), false)]
+    #[case(ExpiredDisputeAction::Chargeback, dec!(5.0), true)]
+    fn test_engine_expire_disputes(
+        #[case] action: ExpiredDisputeAction,
+        #[case] total: Decimal,
+        #[case] locked: bool,
+    ) {
+        let mut engine = PaymentEngine::new()
+            .with_dispute_max_age(Duration::from_secs(30 * SECONDS_PER_DAY), action);
+        let dispute = |tx_id, day| InputRecord {
+            record_type: TransactionType::Dispute,
             amount: None,
+            ..timestamped(tx_id, Decimal::ZERO, day)
         };
-
-        let result = engine.process(record);
-
-        assert!(result.is_err());
-
-        match result.err().unwrap() {
-            PaymentError::InvalidTransaction(msg) => {
-                assert!(msg.contains("Deposit 99 missing amount"));
-            }
-            _ => panic!("Expected InvalidTransaction error"),
+        for record in [
+            timestamped(1, dec!(5.0), 0),
+            timestamped(2, dec!(3.0), 0),
+            timestamped(3, dec!(1.0), 0),
+            dispute(1, 1),
+            dispute(2, 20),
+            simple(TransactionType::Dispute, 3, None),
+            timestamped(4, dec!(1.0), 40),
+        ] {
+            engine.process(record).unwrap();
         }
-        assert!(engine.accounts.is_empty());
+
+        let expired = engine.expire_disputes().unwrap();
+        assert_eq!(
+            expired,
+            [ExpiredDispute {
+                tx_id: 1,
+                client_id: 1,
+                currency: Currency::default(),
+                amount: dec!(5.0),
+                opened_at: SECONDS_PER_DAY,
+                action,
+            }]
+        );
+        let open: Vec<TxId> = engine
+            .open_disputes()
+            .unwrap()
+            .into_iter()
+            .map(|dispute| dispute.tx_id)
+            .collect();
+        assert_eq!(open, [2, 3]);
+        let account = engine.account(1, Currency::default()).unwrap();
+        assert_eq!((account.total(), account.locked), (total, locked));
+        assert!(engine.expire_disputes().unwrap().is_empty());
+        engine.check_invariants().unwrap();
     }
 
     #[rstest]
-    #[case(dec!(-10.0))]
-    #[case(dec!(0.0))]
-    fn test_engine_deposit_invalid_amount(#[case] invalid_amount: Decimal) {
-        let mut engine = PaymentEngine::new();
-        let record = InputRecord {
-            record_type: TransactionType::Deposit,
-            client_id: 1,
-            tx_id: 100,
-            amount: Some(invalid_amount),
+    fn test_engine_limits() {
+        let mut engine = PaymentEngine::new().with_limits(
+            Limits::new()
+                .with_max_amount(dec!(100))
+                .with_max_daily_withdrawal(dec!(50))
+                .with_max_transactions(4, Duration::from_secs(SECONDS_PER_DAY)),
+        );
+        let record = |record_type, tx_id, amount, day| InputRecord {
+            record_type,
+            counterparty_id: (record_type == TransactionType::Transfer).then_some(2),
+            ..timestamped(tx_id, amount, day)
         };
+        let results: Vec<bool> = [
+            record(TransactionType::Deposit, 1, dec!(100), 0),
+            record(TransactionType::Deposit, 2, dec!(150), 0),
+            record(TransactionType::Withdrawal, 3, dec!(30), 0),
+            // Declined for the funds, but counted as a transaction.
+            record(TransactionType::Transfer, 4, dec!(100), 0),
+            record(TransactionType::Withdrawal, 5, dec!(30), 0),
+            // Not a transaction.
+            simple(TransactionType::Dispute, 3, None),
+            record(TransactionType::Withdrawal, 6, dec!(20), 0),
+            record(TransactionType::Deposit, 7, dec!(10), 0),
+            record(TransactionType::Withdrawal, 8, dec!(30), 1),
+        ]
+        .into_iter()
+        .map(|record| match engine.process(record) {
+            Ok(()) => true,
+            Err(PaymentError::LimitExceeded(_)) => false,
+            Err(e) => panic!("Expected LimitExceeded, got {:?}", e),
+        })
+        .collect();
 
-        let result = engine.process(record);
-
-        assert!(result.is_err());
-
-        match result.err().unwrap() {
-            PaymentError::InvalidTransaction(msg) => {
-                assert!(msg.contains("Deposit amount for tx 100 must be positive"));
-            }
-            _ => panic!("Expected InvalidTransaction error"),
-        }
-        assert!(engine.accounts.is_empty());
+        assert_eq!(
+            results,
+            [true, false, true, true, false, true, true, false, true]
+        );
+        let account = engine.account(1, Currency::default()).unwrap();
+        assert_eq!((account.available, account.held), (dec!(20), dec!(30)));
     }
 
     #[rstest]
-    fn test_engine_withdrawal_missing_amount() {
-        let mut engine = PaymentEngine::new();
-        let record = InputRecord {
+    fn test_engine_limits_survive_snapshot() {
+        let limits = Limits::new()
+            .with_max_daily_withdrawal(dec!(10))
+            .with_max_transactions(3, Duration::from_secs(SECONDS_PER_DAY));
+        let withdrawal = |tx_id, amount| InputRecord {
             record_type: TransactionType::Withdrawal,
-            client_id: 1,
-            tx_id: 201,
-            amount: None,
+            ..timestamped(tx_id, amount, 0)
         };
+        let mut engine = PaymentEngine::new().with_limits(limits);
+        engine.process(timestamped(1, dec!(100), 0)).unwrap();
+        engine.process(withdrawal(2, dec!(8))).unwrap();
+        let mut snapshot = Vec::new();
+        engine.snapshot(&mut snapshot).unwrap();
 
-        let result = engine.process(record);
+        let mut restored = PaymentEngine::new().with_limits(limits);
+        restored.restore(snapshot.as_slice()).unwrap();
+        assert!(matches!(
+            restored.process(withdrawal(3, dec!(5))),
+            Err(PaymentError::LimitExceeded(_))
+        ));
+        // Up to the daily limit, and the third transaction of the window.
+        restored.process(withdrawal(4, dec!(2))).unwrap();
+        assert!(matches!(
+            restored.process(timestamped(5, dec!(1), 0)),
+            Err(PaymentError::LimitExceeded(_))
+        ));
+        restored.process(timestamped(6, dec!(1), 1)).unwrap();
+    }
 
-        assert!(result.is_err());
+    #[rstest]
+    fn test_engine_blocklist() {
+        let mut engine = engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(10)),
+            (TransactionType::Deposit, 2, 2, dec!(10)),
+        ])
+        .with_blocklist(Blocklist::new().with_client(2).with_client(3));
+        let transfer = |tx_id, client_id, counterparty_id| InputRecord {
+            record_type: TransactionType::Transfer,
+            client_id,
+            counterparty_id: Some(counterparty_id),
+            ..simple(TransactionType::Transfer, tx_id, Some(dec!(5)))
+        };
+        for record in [
+            InputRecord {
+                client_id: 2,
+                ..simple(TransactionType::Withdrawal, 3, Some(dec!(5)))
+            },
+            InputRecord {
+                client_id: 3,
+                ..simple(TransactionType::Deposit, 4, Some(dec!(5)))
+            },
+            transfer(5, 1, 2),
+            transfer(6, 2, 1),
+        ] {
+            let err = engine.process(record).unwrap_err();
+            assert!(matches!(err, PaymentError::Blocked(_)), "{:?}", err);
+        }
 
-        match result.err().unwrap() {
-            PaymentError::InvalidTransaction(msg) => {
-                assert!(msg.contains("Withdrawal 201 missing amount"));
-            }
-            _ => panic!("Expected InvalidTransaction error"),
+        for (client_id, total, locked) in [
+            (1, dec!(10), false),
+            (2, dec!(10), true),
+            (3, dec!(0), true),
+        ] {
+            let account = engine.account(client_id, Currency::default()).unwrap();
+            assert_eq!((account.total(), account.locked), (total, locked));
         }
-        assert!(engine.accounts.is_empty());
     }
 
     #[rstest]
-    fn test_engine_duplicate_deposit_is_ignored() {
-        let mut engine = PaymentEngine::new();
-        let record = InputRecord {
-            record_type: TransactionType::Deposit,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(rust_decimal_macros::dec!(100.0)),
+    fn test_engine_client_registry() {
+        let eur: Currency = "EUR".parse().unwrap();
+        let kyc = |status| ClientInfo {
+            kyc_status: Some(status),
+            ..ClientInfo::default()
         };
+        let mut engine = PaymentEngine::new().with_client_registry(
+            ClientRegistry::new()
+                .with_client(
+                    1,
+                    ClientInfo {
+                        currency: Some(eur),
+                        overdraft_limit: Some(dec!(20)),
+                        ..ClientInfo::default()
+                    },
+                )
+                .with_client(2, kyc(KycStatus::Pending))
+                .with_client(3, kyc(KycStatus::Failed)),
+        );
+        let record = |record_type, client_id, tx_id| InputRecord {
+            client_id,
+            ..simple(record_type, tx_id, Some(dec!(10)))
+        };
+        let results: Vec<bool> = [
+            // In EUR, overdrawn within the client's limit.
+            record(TransactionType::Withdrawal, 1, 1),
+            record(TransactionType::Deposit, 2, 2),
+            record(TransactionType::Withdrawal, 2, 3),
+            record(TransactionType::Deposit, 3, 4),
+            InputRecord {
+                counterparty_id: Some(3),
+                ..record(TransactionType::Transfer, 1, 5)
+            },
+        ]
+        .into_iter()
+        .map(|record| match engine.process(record) {
+            Ok(()) => true,
+            Err(PaymentError::KycRestricted(_)) => false,
+            Err(e) => panic!("Expected KycRestricted, got {:?}", e),
+        })
+        .collect();
 
-        // First deposit should be processed
-        assert!(engine.process(record.clone()).is_ok());
-
-        // Second deposit with same tx_id should be ignored (covered line)
-        assert!(engine.process(record.clone()).is_ok());
-
-        // Only one deposit should be reflected in the account
-        let acc = engine.accounts.get(&1).unwrap();
-        assert_eq!(acc.available, rust_decimal_macros::dec!(100.0));
-        assert_eq!(engine.transactions.len(), 1);
+        assert_eq!(results, [true, true, false, false, false]);
+        let account = engine.account(1, eur).unwrap();
+        assert_eq!(
+            (account.available, account.overdraft_limit),
+            (dec!(-10), dec!(20))
+        );
+        assert_eq!(
+            engine.account(2, Currency::default()).unwrap().available,
+            dec!(10)
+        );
+        assert!(engine.account(1, Currency::default()).is_none());
     }
 
     #[rstest]
-    #[case(TransactionType::Dispute, 42, 99, TransactionState::Normal, dec!(10.0))]
-    #[case(TransactionType::Resolve, 55, 77, TransactionState::Disputed, dec!(25.0))]
-    #[case(TransactionType::Chargeback, 88, 123, TransactionState::Disputed, dec!(40.0))]
-    fn test_missing_account_is_ignored(
-        #[case] tx_type: TransactionType,
-        #[case] tx_id: u32,
-        #[case] client_id: u16,
-        #[case] state: TransactionState,
-        #[case] amount: Decimal,
+    #[case::deposit(TransactionType::Deposit, false)]
+    #[case::withdrawal(TransactionType::Withdrawal, true)]
+    #[case::transfer(TransactionType::Transfer, true)]
+    #[case::refund(TransactionType::Refund, true)]
+    #[case::auth(TransactionType::Auth, true)]
+    #[case::capture(TransactionType::Capture, true)]
+    #[case::void(TransactionType::Void, false)]
+    #[case::convert(TransactionType::Convert, true)]
+    fn test_engine_kyc_restricts_moving_funds(
+        #[case] record_type: TransactionType,
+        #[case] restricted_when_pending: bool,
     ) {
-        let mut engine = PaymentEngine::new();
-
-        // Insert a transaction for a client that does not exist in accounts
-        engine.transactions.insert(
-            tx_id,
-            TransactionInfo {
-                client_id,
-                amount,
-                state,
-            },
+        let kyc = |status| ClientInfo {
+            kyc_status: Some(status),
+            ..ClientInfo::default()
+        };
+        let mut engine = PaymentEngine::new().with_client_registry(
+            ClientRegistry::new()
+                .with_client(2, kyc(KycStatus::Pending))
+                .with_client(3, kyc(KycStatus::Failed)),
         );
-
-        // Now process the record (dispute/resolve/chargeback)
-        let record = InputRecord {
-            record_type: tx_type,
+        let record = |client_id| InputRecord {
             client_id,
-            tx_id,
-            amount: None,
+            counterparty_id: Some(1),
+            target_currency: Some("EUR".parse().unwrap()),
+            ..simple(record_type, 9, Some(dec!(5)))
         };
+        let is_restricted = |result| matches!(result, Err(PaymentError::KycRestricted(_)));
 
-        // This should hit the `None => return Ok(())` branch
-        assert!(engine.process(record).is_ok());
-        // Still no account created
-        assert!(!engine.accounts.contains_key(&client_id));
+        assert!(is_restricted(engine.process(record(3))));
+        assert_eq!(
+            is_restricted(engine.process(record(2))),
+            restricted_when_pending
+        );
+    }
+
+    fn engine_with(records: &[(TransactionType, ClientId, TxId, Decimal)]) -> PaymentEngine {
+        applied(PaymentEngine::new(), records)
+    }
+
+    /// `engine`, configured by the caller, once `records` are applied.
+    fn applied(
+        mut engine: PaymentEngine,
+        records: &[(TransactionType, ClientId, TxId, Decimal)],
+    ) -> PaymentEngine {
+        for &(record_type, client_id, tx_id, amount) in records {
+            engine
+                .process(InputRecord {
+                    client_id,
+                    ..simple(record_type, tx_id, Some(amount))
+                })
+                .unwrap();
+        }
+        engine
     }
 
     #[rstest]
-    #[case(dec!(-10.0))]
-    #[case(dec!(0.0))]
-    fn test_engine_withdrawal_invalid_amount(#[case] invalid_amount: Decimal) {
-        let mut engine = PaymentEngine::new();
-        let record = InputRecord {
-            record_type: TransactionType::Withdrawal,
-            client_id: 1,
-            tx_id: 202,
-            amount: Some(invalid_amount),
-        };
+    fn test_engine_merge_disjoint_partitions() {
+        let mut left = engine_with(&[(TransactionType::Deposit, 1, 1, dec!(10.0))]);
+        let right = engine_with(&[
+            (TransactionType::Deposit, 2, 2, dec!(5.0)),
+            (TransactionType::Dispute, 2, 2, dec!(0.0)),
+        ]);
 
-        let result = engine.process(record);
+        left.merge(right).unwrap();
 
-        assert!(result.is_err());
+        assert_eq!(left.accounts.len(), 2);
+        assert_eq!(
+            left.accounts.get(&(2, Currency::default())).unwrap().held,
+            dec!(5.0)
+        );
+        assert_eq!(
+            left.transactions.get(2).unwrap().unwrap().state,
+            TransactionState::Disputed
+        );
+    }
 
-        match result.err().unwrap() {
-            PaymentError::InvalidTransaction(msg) => {
-                assert!(msg.contains("Withdrawal amount for tx 202 must be positive"));
+    #[rstest]
+    #[case(2, 1, "overlapping clients [], conflicting tx ids [1]")]
+    #[case(1, 2, "overlapping clients [1], conflicting tx ids []")]
+    #[case(1, 1, "overlapping clients [1], conflicting tx ids [1]")]
+    fn test_engine_merge_conflicts(
+        #[case] client_id: ClientId,
+        #[case] tx_id: TxId,
+        #[case] expected_msg: &str,
+    ) {
+        let mut left = engine_with(&[(TransactionType::Deposit, 1, 1, dec!(10.0))]);
+        let right = engine_with(&[(TransactionType::Deposit, client_id, tx_id, dec!(5.0))]);
+
+        match left.merge(right).err().unwrap() {
+            PaymentError::MergeConflict(msg) => assert_eq!(msg, expected_msg),
+            _ => panic!("Expected MergeConflict error"),
+        }
+        assert_eq!(left.accounts.len(), 1);
+        assert_eq!(
+            left.accounts
+                .get(&(1, Currency::default()))
+                .unwrap()
+                .available,
+            dec!(10.0)
+        );
+    }
+
+    #[rstest]
+    fn test_engine_merge_conflicts_on_spent_tx_ids() {
+        // Tx 2 was declined on the left, so it never reached the store.
+        let mut left = engine_with(&[
+            (TransactionType::Deposit, 1, 1, dec!(10.0)),
+            (TransactionType::Withdrawal, 1, 2, dec!(50.0)),
+        ]);
+        let right = engine_with(&[(TransactionType::Deposit, 2, 2, dec!(5.0))]);
+
+        match left.merge(right).err().unwrap() {
+            PaymentError::MergeConflict(msg) => {
+                assert_eq!(msg, "overlapping clients [], conflicting tx ids [2]")
             }
-            _ => panic!("Expected InvalidTransaction error"),
+            _ => panic!("Expected MergeConflict error"),
         }
-        assert!(engine.accounts.is_empty());
     }
 }
@@ -7,6 +7,9 @@ pub enum PaymentError {
     #[error("CSV processing error: {0}")]
     Csv(#[from] csv::Error),
 
+    #[error("JSON processing error: {0}")]
+    Json(#[from] serde_json::Error),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -15,4 +18,73 @@ pub enum PaymentError {
 
     #[error("Invalid transaction: {0}")]
     InvalidTransaction(String),
+
+    #[error("line {line}: {source}")]
+    AtLine {
+        line: u64,
+        #[source]
+        source: Box<PaymentError>,
+    },
+
+    #[error("Merge conflict: {0}")]
+    MergeConflict(String),
+
+    #[error("Invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+
+    #[error("Invalid rates: {0}")]
+    InvalidRates(String),
+
+    #[error("Idempotency conflict: {0}")]
+    IdempotencyConflict(String),
+
+    #[error("Late record: {0}")]
+    LateRecord(String),
+
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(String),
+
+    #[error("Rejected by risk scoring: {0}")]
+    RiskRejected(String),
+
+    #[error("Blocked: {0}")]
+    Blocked(String),
+
+    #[error("Not allowed by KYC status: {0}")]
+    KycRestricted(String),
+
+    #[error("Arithmetic overflow: {0}")]
+    Overflow(String),
+
+    #[error("Invalid generator settings: {0}")]
+    InvalidGenerator(String),
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Invariant violated: {0}")]
+    InvariantViolation(String),
+
+    #[error("XML error: {0}")]
+    Xml(#[from] roxmltree::Error),
+
+    #[cfg(feature = "arrow")]
+    #[error("Arrow error: {0}")]
+    Arrow(#[from] arrow_schema::ArrowError),
+
+    #[cfg(feature = "parquet")]
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+
+    #[cfg(feature = "avro")]
+    #[error("Avro error: {0}")]
+    Avro(String),
+
+    #[cfg(feature = "msgpack")]
+    #[error("MessagePack error: {0}")]
+    MessagePack(String),
+
+    #[cfg(feature = "protobuf")]
+    #[error("Protobuf error: {0}")]
+    Protobuf(String),
 }
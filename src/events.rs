@@ -0,0 +1,145 @@
+use crate::audit::Mutation;
+use crate::models::{ClientId, Currency, TxId};
+use rust_decimal::Decimal;
+use std::fmt;
+use std::sync::mpsc::{Sender, SyncSender};
+
+/// A domain event emitted by the engine as it applies records.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineEvent {
+    Deposited {
+        tx: TxId,
+        client: ClientId,
+        currency: Currency,
+        amount: Decimal,
+    },
+    Withdrawn {
+        tx: TxId,
+        client: ClientId,
+        currency: Currency,
+        amount: Decimal,
+    },
+    /// Funds of transaction `tx` were held for a dispute.
+    DisputeOpened {
+        tx: TxId,
+        client: ClientId,
+        currency: Currency,
+        amount: Decimal,
+    },
+    /// The disputed transaction `tx` was reversed.
+    ChargebackApplied {
+        tx: TxId,
+        client: ClientId,
+        currency: Currency,
+        amount: Decimal,
+    },
+    /// The record of `tx` changed the account's total by `change`, negative
+    /// if it took funds out. Follows the event describing the change, if
+    /// there's one, and covers every change: transfers, fees, interest,
+    /// refunds and adjustments too.
+    BalanceChanged {
+        tx: TxId,
+        client: ClientId,
+        currency: Currency,
+        change: Decimal,
+    },
+    /// The account was locked by the chargeback of `tx`. Follows the
+    /// `ChargebackApplied` event that caused it.
+    AccountLocked {
+        tx: TxId,
+        client: ClientId,
+        currency: Currency,
+    },
+}
+
+impl EngineEvent {
+    /// The event announcing a balance change made by `mutation`, if any.
+    pub(crate) fn for_mutation(
+        tx: TxId,
+        mutation: Mutation,
+        amount: Decimal,
+        (client, currency): (ClientId, Currency),
+    ) -> Option<Self> {
+        Some(match mutation {
+            Mutation::Deposit => EngineEvent::Deposited {
+                tx,
+                client,
+                currency,
+                amount,
+            },
+            Mutation::Withdrawal => EngineEvent::Withdrawn {
+                tx,
+                client,
+                currency,
+                amount,
+            },
+            Mutation::Dispute(_) => EngineEvent::DisputeOpened {
+                tx,
+                client,
+                currency,
+                amount,
+            },
+            Mutation::Chargeback(_) => EngineEvent::ChargebackApplied {
+                tx,
+                client,
+                currency,
+                amount,
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Receives the events of an engine, synchronously and in the order they
+/// happen. Listeners run inside `process`, so slow ones slow the engine down;
+/// channels hand events off to another thread instead.
+pub trait EventListener: Send {
+    fn on_event(&mut self, event: &EngineEvent);
+}
+
+impl<F: FnMut(&EngineEvent) + Send> EventListener for F {
+    fn on_event(&mut self, event: &EngineEvent) {
+        self(event)
+    }
+}
+
+// Events sent after the receiver hung up are dropped; the engine keeps going.
+impl EventListener for Sender<EngineEvent> {
+    fn on_event(&mut self, event: &EngineEvent) {
+        let _ = self.send(event.clone());
+    }
+}
+
+impl EventListener for SyncSender<EngineEvent> {
+    fn on_event(&mut self, event: &EngineEvent) {
+        let _ = self.send(event.clone());
+    }
+}
+
+/// The listeners registered with an engine.
+#[derive(Default)]
+pub(crate) struct Listeners(Vec<Box<dyn EventListener>>);
+
+impl Listeners {
+    pub(crate) fn push(&mut self, listener: Box<dyn EventListener>) {
+        self.0.push(listener);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn emit(&mut self, event: EngineEvent) {
+        for listener in &mut self.0 {
+            listener.on_event(&event);
+        }
+    }
+}
+
+impl fmt::Debug for Listeners {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Listeners")
+            .field("len", &self.0.len())
+            .finish()
+    }
+}
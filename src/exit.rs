@@ -0,0 +1,88 @@
+//! Exit statuses of the binary, one per class of failure so scripts and
+//! orchestrators can branch on what went wrong.
+
+use payment_engine::PaymentError;
+use std::io;
+use std::process;
+
+/// Why the binary exits with a failure status; the discriminant is the status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Any failure without a class of its own, like an address that can't be
+    /// listened on.
+    Other = 1,
+    /// Bad arguments or `--config` file (clap's status for usage errors).
+    Usage = 2,
+    /// An input file (or another file to read, like `--rates`) doesn't exist.
+    InputNotFound = 3,
+    /// A bad record stopped a `--strict` run, or a validation run found
+    /// records to skip.
+    InvalidRecord = 4,
+    /// The accounts, statement, rejects or generated input couldn't be
+    /// written, or an output like `--audit-log` couldn't be opened.
+    Write = 5,
+    /// The engine broke one of its invariants while processing, or its books
+    /// didn't balance under `--reconcile`.
+    Invariant = 6,
+    /// Accounts differ from the expected ones (`diff`, `--verify`).
+    Mismatch = 7,
+}
+
+impl Failure {
+    /// The class of an error met while reading and processing the inputs. A
+    /// missing file is taken for a missing input: outputs are opened with
+    /// their failures classed on the spot.
+    pub fn of(err: &PaymentError) -> Self {
+        match err {
+            PaymentError::Io(e) if e.kind() == io::ErrorKind::NotFound => Failure::InputNotFound,
+            PaymentError::AtLine { .. } => Failure::InvalidRecord,
+            PaymentError::InvariantViolation(_) => Failure::Invariant,
+            _ => Failure::Other,
+        }
+    }
+
+    /// Exits the process with this failure's status.
+    pub fn exit(self) -> ! {
+        process::exit(self as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(io::Error::from(io::ErrorKind::NotFound).into(), Failure::InputNotFound)]
+    #[case(io::Error::from(io::ErrorKind::PermissionDenied).into(), Failure::Other)]
+    #[case(
+        PaymentError::AtLine {
+            line: 3,
+            source: Box::new(PaymentError::InvalidTransaction("bad".to_string())),
+        },
+        Failure::InvalidRecord
+    )]
+    #[case(
+        PaymentError::InvariantViolation("negative held".to_string()),
+        Failure::Invariant
+    )]
+    #[case(PaymentError::InvalidRates("bad".to_string()), Failure::Other)]
+    fn test_failure_of(#[case] err: PaymentError, #[case] expected: Failure) {
+        assert_eq!(Failure::of(&err), expected);
+    }
+
+    #[rstest]
+    fn test_failure_statuses_are_distinct() {
+        let statuses = [
+            Failure::Other,
+            Failure::Usage,
+            Failure::InputNotFound,
+            Failure::InvalidRecord,
+            Failure::Write,
+            Failure::Invariant,
+            Failure::Mismatch,
+        ]
+        .map(|failure| failure as i32);
+        assert_eq!(statuses, [1, 2, 3, 4, 5, 6, 7]);
+    }
+}
@@ -0,0 +1,248 @@
+//! Hand-rolled decoding of CSV rows into records, reading the type, ids and
+//! amount straight from the bytes of the row instead of going through serde.
+//!
+//! It only takes the plain spellings (lowercase type names, unsigned decimal
+//! ids and amounts of up to 15 digits) and gives up on anything else, leaving
+//! the row to serde so results and error messages stay exactly the same.
+
+use crate::models::{ClientId, Currency, InputRecord, TransactionType};
+use csv::{ByteRecord, StringRecord};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::str::{self, FromStr};
+
+/// Amounts longer than this go to serde: it reads them through an `f64`, which
+/// only gives back the digits it was given up to 15 of them.
+const MAX_AMOUNT_DIGITS: usize = 15;
+
+/// Positions of the record's columns in the header.
+#[derive(Debug, Clone)]
+pub(crate) struct Columns {
+    record_type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    counterparty: Option<usize>,
+    currency: Option<usize>,
+    target_currency: Option<usize>,
+    timestamp: Option<usize>,
+    idempotency_key: Option<usize>,
+    reference: Option<usize>,
+    len: usize,
+}
+
+impl Columns {
+    /// Finds the columns in `headers`, or `None` if the header lacks a required
+    /// column or repeats one, which only serde reports properly.
+    pub(crate) fn new(headers: &StringRecord) -> Option<Self> {
+        let find = |name: &str| {
+            let mut positions = headers.iter().enumerate().filter(|&(_, h)| h == name);
+            match (positions.next(), positions.next()) {
+                (Some((position, _)), None) => Ok(Some(position)),
+                (None, _) => Ok(None),
+                (Some(_), Some(_)) => Err(()),
+            }
+        };
+        Some(Columns {
+            record_type: find("type").ok()??,
+            client: find("client").ok()??,
+            tx: find("tx").ok()??,
+            amount: find("amount").ok()?,
+            counterparty: find("counterparty").ok()?,
+            currency: find("currency").ok()?,
+            target_currency: find("to_currency").ok()?,
+            timestamp: find("timestamp").ok()?,
+            idempotency_key: find("idempotency_key").ok()?,
+            reference: find("reference").ok()?,
+            len: headers.len(),
+        })
+    }
+
+    /// Decodes `row`, or returns `None` to leave it to serde.
+    pub(crate) fn decode(&self, row: &ByteRecord) -> Option<InputRecord> {
+        if row.len() > self.len {
+            return None;
+        }
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|column| row.get(column))
+                .filter(|field| !field.is_empty())
+        };
+        Some(InputRecord {
+            record_type: parse_type(row.get(self.record_type)?)?,
+            client_id: parse_id(row.get(self.client)?)?,
+            tx_id: parse_id(row.get(self.tx)?)?,
+            amount: optional(field(self.amount), parse_amount)?,
+            counterparty_id: optional(field(self.counterparty), parse_id::<ClientId>)?,
+            currency: optional(field(self.currency), parse_currency)?.unwrap_or_default(),
+            target_currency: optional(field(self.target_currency), parse_currency)?,
+            timestamp: optional(field(self.timestamp), parse_id::<u64>)?,
+            idempotency_key: optional(field(self.idempotency_key), |field| {
+                str::from_utf8(field).ok().map(str::to_string)
+            })?,
+            reference: optional(field(self.reference), |field| {
+                str::from_utf8(field).ok().map(str::to_string)
+            })?,
+            metadata: HashMap::new(),
+        })
+    }
+}
+
+/// `Some(None)` for a missing or empty field, `None` if it doesn't parse.
+fn optional<T>(field: Option<&[u8]>, parse: impl FnOnce(&[u8]) -> Option<T>) -> Option<Option<T>> {
+    match field {
+        None => Some(None),
+        Some(field) => parse(field).map(Some),
+    }
+}
+
+fn parse_type(field: &[u8]) -> Option<TransactionType> {
+    Some(match field {
+        b"deposit" => TransactionType::Deposit,
+        b"withdrawal" => TransactionType::Withdrawal,
+        b"dispute" => TransactionType::Dispute,
+        b"resolve" => TransactionType::Resolve,
+        b"chargeback" => TransactionType::Chargeback,
+        b"transfer" => TransactionType::Transfer,
+        b"refund" => TransactionType::Refund,
+        b"auth" => TransactionType::Auth,
+        b"capture" => TransactionType::Capture,
+        b"void" => TransactionType::Void,
+        b"close" => TransactionType::Close,
+        b"admin" => TransactionType::Admin,
+        b"convert" => TransactionType::Convert,
+        _ => return None,
+    })
+}
+
+/// Parses an unsigned id made of ASCII digits only.
+fn parse_id<T: TryFrom<u64>>(field: &[u8]) -> Option<T> {
+    if field.is_empty() {
+        return None;
+    }
+    let mut value: u64 = 0;
+    for &b in field {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        value = value.checked_mul(10)?.checked_add(u64::from(b - b'0'))?;
+    }
+    T::try_from(value).ok()
+}
+
+/// Parses `digits[.digits]` into the same value serde gives, which carries
+/// no trailing zeros (`10.50` is `10.5`).
+fn parse_amount(field: &[u8]) -> Option<Decimal> {
+    let (whole, fraction) = match field.iter().position(|&b| b == b'.') {
+        Some(dot) => (&field[..dot], &field[dot + 1..]),
+        None => (field, &[][..]),
+    };
+    let digits = whole.len() + fraction.len();
+    if whole.is_empty()
+        || (field.len() > whole.len() && fraction.is_empty())
+        || digits > MAX_AMOUNT_DIGITS
+    {
+        return None;
+    }
+    let mut mantissa: i64 = 0;
+    for &b in whole.iter().chain(fraction) {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        mantissa = mantissa * 10 + i64::from(b - b'0');
+    }
+    Some(Decimal::new(mantissa, fraction.len() as u32).normalize())
+}
+
+fn parse_currency(field: &[u8]) -> Option<Currency> {
+    Currency::from_str(str::from_utf8(field).ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::GeneratorConfig;
+    use rstest::rstest;
+
+    fn decode(header: &str, row: &str) -> Option<InputRecord> {
+        let headers = StringRecord::from(header.split(',').collect::<Vec<_>>());
+        let row = ByteRecord::from(row.split(',').collect::<Vec<_>>());
+        Columns::new(&headers)?.decode(&row)
+    }
+
+    /// What serde makes of the same row.
+    fn deserialize(header: &str, row: &str) -> InputRecord {
+        let headers = StringRecord::from(header.split(',').collect::<Vec<_>>());
+        let row = StringRecord::from(row.split(',').collect::<Vec<_>>());
+        row.deserialize(Some(&headers)).unwrap()
+    }
+
+    #[rstest]
+    #[case("type,client,tx,amount", "deposit,1,2,10.5")]
+    #[case("type,client,tx,amount", "deposit,1,2,10.0000")]
+    #[case("type,client,tx,amount", "withdrawal,65535,4294967295,0.0001")]
+    #[case("type,client,tx,amount", "deposit,1,2,007")]
+    #[case("type,client,tx,amount", "deposit,1,2,123456789012.345")]
+    #[case("type,client,tx,amount", "dispute,1,2,")]
+    #[case("type,client,tx,amount", "resolve,1,2")]
+    #[case(
+        "tx,type,client,amount,counterparty,currency,to_currency,timestamp,idempotency_key",
+        "7,transfer,1,2.5,3,usd,EUR,1700000000,req-1"
+    )]
+    #[case("type,client,tx,amount,currency,note", "deposit,1,2,1.0,,whatever")]
+    fn test_decode_matches_serde(#[case] header: &str, #[case] row: &str) {
+        let decoded = decode(header, row).expect("fast path should take the row");
+        let expected = deserialize(header, row);
+        assert_eq!(decoded, expected);
+        // Same scale too, not just the same value.
+        assert_eq!(
+            decoded.amount.map(|a| a.to_string()),
+            expected.amount.map(|a| a.to_string())
+        );
+    }
+
+    #[rstest]
+    #[case("type,client,tx,amount", "Deposit,1,2,1.0")]
+    #[case("type,client,tx,amount", "deposit,+1,2,1.0")]
+    #[case("type,client,tx,amount", "deposit,1,2,-1.0")]
+    #[case("type,client,tx,amount", "deposit,1,2,.5")]
+    #[case("type,client,tx,amount", "deposit,1,2,5.")]
+    #[case("type,client,tx,amount", "deposit,1,2,1e3")]
+    #[case("type,client,tx,amount", "deposit,1,2,1234567890.1234567")]
+    #[case("type,client,tx,amount", "deposit,99999999999999999999,2,1.0")]
+    #[case("type,client,tx,amount", "deposit,1,2,1.0,extra")]
+    #[case("type,client,tx,amount", "deposit,1")]
+    #[case("type,client,amount", "deposit,1,1.0")]
+    #[case("type,client,tx,tx", "deposit,1,2,3")]
+    fn test_decode_leaves_the_rest_to_serde(#[case] header: &str, #[case] row: &str) {
+        assert_eq!(decode(header, row), None);
+    }
+
+    #[rstest]
+    fn test_decode_matches_serde_on_generated_input() {
+        let config = GeneratorConfig {
+            invalid_ratio: 0.2,
+            ..GeneratorConfig::default()
+        };
+        let mut data = Vec::new();
+        config.generate(&mut data).unwrap();
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(data.as_slice());
+        let headers = rdr.headers().unwrap().clone();
+        let columns = Columns::new(&headers).unwrap();
+
+        let mut decoded = 0;
+        for row in rdr.records() {
+            let row = row.unwrap();
+            let expected = row.deserialize::<InputRecord>(Some(&headers));
+            if let Some(record) = columns.decode(row.as_byte_record()) {
+                let expected = expected.unwrap();
+                assert_eq!(format!("{:?}", record), format!("{:?}", expected));
+                decoded += 1;
+            }
+        }
+        assert!(decoded > 7_000, "only {} rows took the fast path", decoded);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::models::{ClientId, TransactionType};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::HashMap;
+
+/// Fee charged for one transaction type: a flat amount plus a percentage of
+/// the amount moved.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Fee {
+    pub flat: Decimal,
+    /// Percentage of the transaction amount, e.g. `1.5` for 1.5%.
+    pub percent: Decimal,
+}
+
+impl Fee {
+    pub fn flat(flat: Decimal) -> Self {
+        Fee {
+            flat,
+            percent: Decimal::ZERO,
+        }
+    }
+
+    pub fn percent(percent: Decimal) -> Self {
+        Fee {
+            flat: Decimal::ZERO,
+            percent,
+        }
+    }
+
+    /// Fee owed for moving `amount`, rounded half-up to 4 decimal places.
+    pub fn amount(&self, amount: Decimal) -> Decimal {
+        (self.flat + amount * self.percent / Decimal::ONE_HUNDRED)
+            .round_dp_with_strategy(4, RoundingStrategy::MidpointAwayFromZero)
+    }
+}
+
+/// Fees per transaction type, and the house account that collects them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeSchedule {
+    house_account: ClientId,
+    fees: HashMap<TransactionType, Fee>,
+}
+
+impl FeeSchedule {
+    /// An empty schedule paying into `house_account`.
+    pub fn new(house_account: ClientId) -> Self {
+        FeeSchedule {
+            house_account,
+            fees: HashMap::new(),
+        }
+    }
+
+    /// Charges `fee` for every applied transaction of `record_type`.
+    pub fn with_fee(mut self, record_type: TransactionType, fee: Fee) -> Self {
+        self.fees.insert(record_type, fee);
+        self
+    }
+
+    pub fn house_account(&self) -> ClientId {
+        self.house_account
+    }
+
+    pub fn fee(&self, record_type: TransactionType) -> Option<&Fee> {
+        self.fees.get(&record_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    #[rstest]
+    #[case(Fee::flat(dec!(0.25)), dec!(100.0), dec!(0.25))]
+    #[case(Fee::percent(dec!(1.5)), dec!(100.0), dec!(1.5))]
+    #[case(Fee { flat: dec!(0.30), percent: dec!(2.9) }, dec!(10.0), dec!(0.59))]
+    // Rounded to 4 decimal places.
+    #[case(Fee::percent(dec!(1)), dec!(0.12345), dec!(0.0012))]
+    #[case(Fee::percent(dec!(50)), dec!(0.00015), dec!(0.0001))]
+    fn test_fee_amount(#[case] fee: Fee, #[case] amount: Decimal, #[case] expected: Decimal) {
+        assert_eq!(fee.amount(amount), expected);
+    }
+
+    #[rstest]
+    fn test_fee_schedule_lookup() {
+        let schedule =
+            FeeSchedule::new(9).with_fee(TransactionType::Withdrawal, Fee::flat(dec!(1.0)));
+
+        assert_eq!(schedule.house_account(), 9);
+        assert_eq!(
+            schedule.fee(TransactionType::Withdrawal),
+            Some(&Fee::flat(dec!(1.0)))
+        );
+        assert_eq!(schedule.fee(TransactionType::Deposit), None);
+    }
+}
@@ -0,0 +1,301 @@
+//! Fixed-width records, as legacy and mainframe upstreams still write
+//! settlement files.
+//!
+//! A layout file (TOML, or YAML by extension) gives the span of each input
+//! column, named like the CSV header, so a record's fields are cut out and
+//! decoded exactly like a CSV row:
+//!
+//! ```toml
+//! skip_lines = 1          # a file header
+//! record_prefix = "D"     # only detail records; trailers are skipped
+//! implied_decimals = 2    # amounts like 0000012345 are 123.45
+//!
+//! [fields]
+//! type = { start = 2, length = 10 }
+//! client = { start = 12, length = 5 }
+//! tx = { start = 17, length = 10 }
+//! amount = { start = 27, length = 10 }
+//! ```
+
+use crate::config;
+use crate::csv_handler::COLUMNS;
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::input::{process_records, RawRecord};
+use crate::models::InputRecord;
+use crate::report::ProcessingReport;
+use crate::tx_ids::{TxIdFormat, TxIdMap};
+use csv::StringRecord;
+use rust_decimal::Decimal;
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// Where a field lies in each record: its starting column, counted from 1 as
+/// record layouts and copybooks do, and its width in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpan {
+    pub start: usize,
+    pub length: usize,
+}
+
+/// The layout of fixed-width records, read from a `--layout` file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FixedWidthLayout {
+    /// Lines before the first record, like a file header.
+    pub skip_lines: usize,
+    /// When set, only lines starting with it are records; headers, batch
+    /// lines and trailers are skipped.
+    pub record_prefix: Option<String>,
+    /// Decimal places of amounts written without a decimal point, like
+    /// COBOL's `V99`.
+    pub implied_decimals: u32,
+    /// The span of each column, by its CSV header name.
+    pub fields: BTreeMap<String, FieldSpan>,
+}
+
+impl FixedWidthLayout {
+    /// Reads and validates the layout file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PaymentError> {
+        let layout: Self = config::read_file(path)?;
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    /// Checks that the layout places the required columns, and only known
+    /// ones, at valid spans.
+    pub fn validate(&self) -> Result<(), PaymentError> {
+        let invalid = |message: String| Err(PaymentError::InvalidConfig(message));
+        for (name, span) in &self.fields {
+            if !COLUMNS.contains(&name.as_str()) {
+                return invalid(format!(
+                    "unknown layout field '{}' (expected one of {})",
+                    name,
+                    COLUMNS.join(", ")
+                ));
+            }
+            if span.start == 0 || span.length == 0 {
+                return invalid(format!(
+                    "layout field '{}' needs a start from 1 and a length",
+                    name
+                ));
+            }
+        }
+        if let Some(missing) = ["type", "client", "tx"]
+            .into_iter()
+            .find(|name| !self.fields.contains_key(*name))
+        {
+            return invalid(format!("layout has no '{}' field", missing));
+        }
+        if self.implied_decimals > 28 {
+            return invalid(format!(
+                "invalid implied_decimals {} (at most 28)",
+                self.implied_decimals
+            ));
+        }
+        Ok(())
+    }
+
+    /// Cuts the fields out of `line` and decodes them as a CSV row would be.
+    fn decode(&self, line: &str, tx_ids: &TxIdMap) -> Result<InputRecord, PaymentError> {
+        let mut headers = StringRecord::new();
+        let mut row = StringRecord::new();
+        for (name, span) in &self.fields {
+            let end = (span.start - 1 + span.length).min(line.len());
+            let field = line
+                .get((span.start - 1).min(end)..end)
+                .ok_or_else(|| {
+                    PaymentError::InvalidTransaction(format!(
+                        "field '{}' splits a character of '{}'",
+                        name, line
+                    ))
+                })?
+                .trim();
+            let field = match name.as_str() {
+                "tx" if tx_ids.format() != TxIdFormat::Numeric => tx_ids.map(field)?.to_string(),
+                "amount" => self.amount(field),
+                _ => field.to_string(),
+            };
+            headers.push_field(name);
+            row.push_field(&field);
+        }
+        Ok(row.deserialize(Some(&headers))?)
+    }
+
+    /// Places the implied decimal point of an amount written without one.
+    /// Anything else is left for decoding to accept or reject.
+    fn amount(&self, field: &str) -> String {
+        if self.implied_decimals == 0 || field.contains('.') {
+            return field.to_string();
+        }
+        match field.parse::<i128>() {
+            Ok(units) => Decimal::try_from_i128_with_scale(units, self.implied_decimals)
+                .map_or_else(|_| field.to_string(), |amount| amount.to_string()),
+            Err(_) => field.to_string(),
+        }
+    }
+}
+
+/// Processes fixed-width records laid out by `layout`.
+pub fn process_fixed_width<R: Read>(
+    reader: R,
+    layout: &FixedWidthLayout,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError> {
+    process_records(read_fixed_width(reader, layout, TxIdMap::default()), engine)
+}
+
+/// Lazily decodes fixed-width records laid out by `layout`, reading the `tx`
+/// field through `tx_ids`. Blank lines are skipped.
+pub fn read_fixed_width<R: Read>(
+    reader: R,
+    layout: &FixedWidthLayout,
+    tx_ids: TxIdMap,
+) -> impl Iterator<Item = RawRecord> {
+    let layout = layout.clone();
+    BufReader::new(reader)
+        .split(b'\n')
+        .zip(1..)
+        .skip(layout.skip_lines)
+        .filter_map(move |(line, number)| {
+            let line = match line {
+                // Legacy encodings only garble free text; the fields that
+                // are decoded are ASCII.
+                Ok(line) => String::from_utf8_lossy(&line)
+                    .trim_end_matches(['\r', '\n'])
+                    .to_string(),
+                Err(e) => {
+                    return Some(RawRecord {
+                        line: number,
+                        raw: String::new(),
+                        parsed: Err(e.into()),
+                    })
+                }
+            };
+            let is_record = match &layout.record_prefix {
+                Some(prefix) => line.starts_with(prefix.as_str()),
+                None => !line.trim().is_empty(),
+            };
+            is_record.then(|| RawRecord {
+                line: number,
+                parsed: layout.decode(&line, &tx_ids),
+                raw: line,
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TransactionType;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    const LAYOUT: &str = r#"
+skip_lines = 1
+record_prefix = "D"
+implied_decimals = 2
+
+[fields]
+type = { start = 2, length = 10 }
+client = { start = 12, length = 5 }
+tx = { start = 17, length = 10 }
+amount = { start = 27, length = 10 }
+currency = { start = 37, length = 3 }
+"#;
+
+    const RECORDS: &str = "HSETTLEMENT 20240301
+Ddeposit   0000100000000010000012345USD
+Dwithdrawal0000100000000020000002500USD
+Ddispute   000010000000001          USD
+T0000000003
+";
+
+    fn layout() -> FixedWidthLayout {
+        let layout: FixedWidthLayout = toml::from_str(LAYOUT).unwrap();
+        layout.validate().unwrap();
+        layout
+    }
+
+    #[rstest]
+    fn test_read_fixed_width() {
+        let records: Vec<RawRecord> =
+            read_fixed_width(RECORDS.as_bytes(), &layout(), TxIdMap::default()).collect();
+        let decoded: Vec<(u64, TransactionType, u64, Option<Decimal>)> = records
+            .iter()
+            .map(|record| {
+                let parsed = record.parsed.as_ref().unwrap();
+                (
+                    record.line,
+                    parsed.record_type,
+                    u64::from(parsed.tx_id),
+                    parsed.amount,
+                )
+            })
+            .collect();
+        assert_eq!(
+            decoded,
+            [
+                (2, TransactionType::Deposit, 1, Some(dec!(123.45))),
+                (3, TransactionType::Withdrawal, 2, Some(dec!(25.00))),
+                (4, TransactionType::Dispute, 1, None),
+            ]
+        );
+        let first = records[0].parsed.as_ref().unwrap();
+        assert_eq!((first.client_id, first.currency.as_str()), (1, "USD"));
+        assert_eq!(records[0].raw, "Ddeposit   0000100000000010000012345USD");
+    }
+
+    #[rstest]
+    fn test_read_fixed_width_maps_tx_references() {
+        let mut layout = layout();
+        layout.implied_decimals = 0;
+        let records = "Hheader\nDdeposit   00002TXN-A     12.5\n";
+        let tx_ids = TxIdMap::new(TxIdFormat::Text);
+        let parsed: Vec<InputRecord> = read_fixed_width(records.as_bytes(), &layout, tx_ids)
+            .map(|record| record.parsed.unwrap())
+            .collect();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!((parsed[0].client_id, parsed[0].tx_id), (2, 1));
+        assert_eq!(parsed[0].amount, Some(dec!(12.5)));
+    }
+
+    #[rstest]
+    fn test_read_fixed_width_reports_bad_fields() {
+        let records = "H\nDpayout    0000100000000010000012345\n";
+        let parsed: Vec<RawRecord> =
+            read_fixed_width(records.as_bytes(), &layout(), TxIdMap::default()).collect();
+        assert_eq!(parsed[0].line, 2);
+        assert!(parsed[0].parsed.is_err());
+    }
+
+    #[rstest]
+    #[case(
+        "[fields]\nclient = { start = 1, length = 1 }\ntx = { start = 2, length = 1 }",
+        "no 'type' field"
+    )]
+    #[case("[fields]\ntype = { start = 1, length = 1 }\nclient = { start = 2, length = 1 }\ntx = { start = 0, length = 1 }", "'tx' needs a start from 1")]
+    #[case(
+        "[fields]\nkind = { start = 1, length = 1 }",
+        "unknown layout field 'kind'"
+    )]
+    fn test_layout_validation(#[case] layout: &str, #[case] message: &str) {
+        let layout: FixedWidthLayout = toml::from_str(layout).unwrap();
+        let err = layout.validate().unwrap_err().to_string();
+        assert!(err.contains(message), "{}", err);
+    }
+
+    #[rstest]
+    fn test_process_fixed_width() {
+        let mut engine = PaymentEngine::new();
+        let report = process_fixed_width(RECORDS.as_bytes(), &layout(), &mut engine).unwrap();
+        assert_eq!(report.records_read, 3);
+        let account = engine.get_account(1, "USD".parse().unwrap()).unwrap();
+        // The dispute can't hold more than the available 98.45.
+        assert_eq!(account.available, dec!(98.45));
+        assert_eq!(account.held, dec!(0));
+    }
+}
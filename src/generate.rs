@@ -0,0 +1,192 @@
+use crate::errors::PaymentError;
+use crate::models::{ClientId, TxId};
+use rust_decimal::Decimal;
+use std::io::Write;
+
+/// Settings of a synthetic input file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeneratorConfig {
+    /// Clients the records are spread over (ids `1..=clients`).
+    pub clients: u64,
+    /// Records written, invalid ones included.
+    pub transactions: u64,
+    /// Share of records that are disputes, resolves or chargebacks.
+    pub dispute_ratio: f64,
+    /// Share of records that are malformed or rejected by the engine.
+    pub invalid_ratio: f64,
+    /// The same seed (and settings) always gives the same file.
+    pub seed: u64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            clients: 100,
+            transactions: 10_000,
+            dispute_ratio: 0.05,
+            invalid_ratio: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+/// Malformed or rejected records mixed in by `invalid_ratio`.
+const INVALID_ROWS: [&str; 4] = [
+    "this_is_bad_data",
+    "deposit,{client},{tx},not_a_number",
+    "deposit,{client},{tx},-1.0",
+    "teleport,{client},{tx},1.0",
+];
+
+impl GeneratorConfig {
+    /// Checks that the ratios are between 0 and 1 and the ids fit their types.
+    pub fn validate(&self) -> Result<(), PaymentError> {
+        let invalid = |msg: String| Err(PaymentError::InvalidGenerator(msg));
+        if self.clients == 0 || self.clients > u64::from(ClientId::MAX) {
+            return invalid(format!(
+                "client count must be between 1 and {}",
+                ClientId::MAX
+            ));
+        }
+        if self.transactions > u64::from(TxId::MAX) {
+            return invalid(format!("transaction count can't exceed {}", TxId::MAX));
+        }
+        for (name, ratio) in [
+            ("dispute", self.dispute_ratio),
+            ("invalid", self.invalid_ratio),
+        ] {
+            if !(0.0..=1.0).contains(&ratio) {
+                return invalid(format!("{} ratio must be between 0 and 1", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a CSV input (`type,client,tx,amount`) of `transactions`
+    /// records: deposits and withdrawals of random clients and amounts, with
+    /// disputes of earlier deposits (later resolved or charged back) and
+    /// invalid records mixed in at the configured ratios.
+    pub fn generate<W: Write>(&self, writer: W) -> Result<(), PaymentError> {
+        self.validate()?;
+        let mut rng = fastrand::Rng::with_seed(self.seed);
+        let mut wtr = csv::WriterBuilder::new().flexible(true).from_writer(writer);
+        wtr.write_record(["type", "client", "tx", "amount"])?;
+
+        // Deposits that can still be disputed, and open disputes.
+        let mut deposits: Vec<(ClientId, TxId)> = Vec::new();
+        let mut disputed: Vec<(ClientId, TxId)> = Vec::new();
+        let mut next_tx: TxId = 0;
+        for _ in 0..self.transactions {
+            next_tx += 1;
+            let client = ClientId::try_from(rng.u64(1..=self.clients))
+                .expect("client count was checked to fit a client id");
+
+            if rng.f64() < self.invalid_ratio {
+                let row = INVALID_ROWS[rng.usize(..INVALID_ROWS.len())]
+                    .replace("{client}", &client.to_string())
+                    .replace("{tx}", &next_tx.to_string());
+                wtr.write_record(row.split(','))?;
+                continue;
+            }
+
+            if rng.f64() < self.dispute_ratio {
+                let settle = !disputed.is_empty() && (deposits.is_empty() || rng.bool());
+                if settle {
+                    let (client, tx) = disputed.swap_remove(rng.usize(..disputed.len()));
+                    let kind = if rng.bool() { "resolve" } else { "chargeback" };
+                    wtr.write_record([kind, &client.to_string(), &tx.to_string(), ""])?;
+                    continue;
+                }
+                if !deposits.is_empty() {
+                    let (client, tx) = deposits.swap_remove(rng.usize(..deposits.len()));
+                    disputed.push((client, tx));
+                    wtr.write_record(["dispute", &client.to_string(), &tx.to_string(), ""])?;
+                    continue;
+                }
+            }
+
+            let amount = Decimal::new(rng.i64(1..=10_000_000), 4);
+            let kind = if rng.u8(..10) < 6 {
+                deposits.push((client, next_tx));
+                "deposit"
+            } else {
+                "withdrawal"
+            };
+            wtr.write_record([
+                kind,
+                &client.to_string(),
+                &next_tx.to_string(),
+                &amount.to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_handler::read_records;
+    use crate::engine::PaymentEngine;
+    use crate::input::process_records_with_policy;
+    use crate::policy::ErrorPolicy;
+    use rstest::rstest;
+
+    fn generate(config: GeneratorConfig) -> String {
+        let mut output = Vec::new();
+        config.generate(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[rstest]
+    fn test_same_seed_same_file() {
+        let config = GeneratorConfig {
+            transactions: 200,
+            invalid_ratio: 0.1,
+            ..GeneratorConfig::default()
+        };
+
+        assert_eq!(generate(config), generate(config));
+        assert_ne!(
+            generate(config),
+            generate(GeneratorConfig { seed: 1, ..config })
+        );
+    }
+
+    #[rstest]
+    fn test_generated_file_processes() {
+        let config = GeneratorConfig {
+            clients: 5,
+            transactions: 1_000,
+            dispute_ratio: 0.2,
+            invalid_ratio: 0.05,
+            seed: 7,
+        };
+        let output = generate(config);
+        assert_eq!(output.lines().count(), 1_001);
+        assert!(output.contains("\ndispute,"));
+        assert!(output.contains("\nresolve,") || output.contains("\nchargeback,"));
+
+        let mut engine = PaymentEngine::new();
+        let report = process_records_with_policy(
+            read_records(output.as_bytes()),
+            &mut engine,
+            ErrorPolicy::Skip,
+        )
+        .unwrap();
+        assert_eq!(report.records_read, 1_000);
+        assert!(!report.skipped.is_empty());
+        assert!(report.skipped.len() < 150);
+        assert!(engine.get_accounts().len() <= 5);
+    }
+
+    #[rstest]
+    #[case(GeneratorConfig { clients: 0, ..GeneratorConfig::default() })]
+    #[case(GeneratorConfig { dispute_ratio: 1.5, ..GeneratorConfig::default() })]
+    #[case(GeneratorConfig { invalid_ratio: -0.1, ..GeneratorConfig::default() })]
+    #[case(GeneratorConfig { invalid_ratio: f64::NAN, ..GeneratorConfig::default() })]
+    fn test_rejects_invalid_config(#[case] config: GeneratorConfig) {
+        assert!(config.generate(Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,324 @@
+//! gRPC front end for the engine, defined by `proto/payment_engine.proto`.
+//!
+//! The service plumbing below is written out by hand from the schema, as
+//! `tonic-build` would generate it, over the messages of `protobuf`, so
+//! building the crate doesn't need `protoc`. Keep it in sync when the schema
+//! changes.
+
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::models::{ClientId, Currency, InputRecord};
+pub use crate::protobuf::pb;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio_stream::{Stream, StreamExt};
+use tonic::codegen::{http, Body, BoxFuture, Context, Poll, Service, StdError};
+use tonic::{Request, Response, Status, Streaming};
+use tonic_prost::ProstCodec;
+
+/// Full name of the service, as routed by gRPC servers.
+pub const SERVICE_NAME: &str = "payment_engine.v1.PaymentEngine";
+
+/// Serves an engine over gRPC. Every stream and query shares the engine, and
+/// concurrent streams are applied record by record in arrival order.
+#[derive(Debug, Clone)]
+pub struct GrpcService {
+    engine: Arc<Mutex<PaymentEngine>>,
+}
+
+impl GrpcService {
+    pub fn new(engine: PaymentEngine) -> Self {
+        Self::from_shared(Arc::new(Mutex::new(engine)))
+    }
+
+    /// Serves an engine the embedding service also holds, e.g. to write the
+    /// accounts out on shutdown.
+    pub fn from_shared(engine: Arc<Mutex<PaymentEngine>>) -> Self {
+        Self { engine }
+    }
+
+    /// The engine behind the service.
+    pub fn engine(&self) -> Arc<Mutex<PaymentEngine>> {
+        Arc::clone(&self.engine)
+    }
+
+    /// Listens on `addr` until the server fails.
+    pub async fn serve(self, addr: SocketAddr) -> Result<(), tonic::transport::Error> {
+        tonic::transport::Server::builder()
+            .add_service(self)
+            .serve(addr)
+            .await
+    }
+
+    /// Applies every transaction of `stream` in order. Invalid and rejected
+    /// ones are listed in the summary; a broken stream aborts with its status,
+    /// keeping what was already applied.
+    pub async fn stream_transactions<S>(&self, stream: S) -> Result<pb::StreamSummary, Status>
+    where
+        S: Stream<Item = Result<pb::Transaction, Status>>,
+    {
+        tokio::pin!(stream);
+        let mut summary = pb::StreamSummary::default();
+        while let Some(tx) = stream.next().await {
+            let tx = tx?;
+            summary.received += 1;
+            let tx_id = tx.tx;
+            let result = match InputRecord::try_from(tx) {
+                Ok(record) => self.engine.lock().await.process(record),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {}
+                Err(PaymentError::Io(e)) => return Err(Status::internal(e.to_string())),
+                Err(e) => summary.rejected.push(pb::Rejection {
+                    position: summary.received,
+                    tx: tx_id,
+                    reason: e.to_string(),
+                }),
+            }
+        }
+        Ok(summary)
+    }
+
+    pub async fn get_account(&self, request: pb::GetAccountRequest) -> Result<pb::Account, Status> {
+        let client = ClientId::try_from(request.client).map_err(|_| {
+            Status::invalid_argument(format!("invalid client id {}", request.client))
+        })?;
+        let currency = request
+            .currency
+            .parse::<Currency>()
+            .map_err(Status::invalid_argument)?;
+        let engine = self.engine.lock().await;
+        engine
+            .get_account(client, currency)
+            .map(pb::Account::from)
+            .ok_or_else(|| Status::not_found(format!("no account for client {}", client)))
+    }
+
+    pub async fn list_accounts(&self) -> pb::ListAccountsResponse {
+        let engine = self.engine.lock().await;
+        pb::ListAccountsResponse {
+            accounts: engine
+                .iter_accounts_sorted()
+                .map(|account| pb::Account::from(account.to_output_record()))
+                .collect(),
+        }
+    }
+}
+
+impl tonic::server::NamedService for GrpcService {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+struct StreamTransactionsSvc(GrpcService);
+
+impl tonic::server::ClientStreamingService<pb::Transaction> for StreamTransactionsSvc {
+    type Response = pb::StreamSummary;
+    type Future = BoxFuture<Response<Self::Response>, Status>;
+
+    fn call(&mut self, request: Request<Streaming<pb::Transaction>>) -> Self::Future {
+        let service = self.0.clone();
+        Box::pin(async move {
+            service
+                .stream_transactions(request.into_inner())
+                .await
+                .map(Response::new)
+        })
+    }
+}
+
+struct GetAccountSvc(GrpcService);
+
+impl tonic::server::UnaryService<pb::GetAccountRequest> for GetAccountSvc {
+    type Response = pb::Account;
+    type Future = BoxFuture<Response<Self::Response>, Status>;
+
+    fn call(&mut self, request: Request<pb::GetAccountRequest>) -> Self::Future {
+        let service = self.0.clone();
+        Box::pin(async move {
+            service
+                .get_account(request.into_inner())
+                .await
+                .map(Response::new)
+        })
+    }
+}
+
+struct ListAccountsSvc(GrpcService);
+
+impl tonic::server::UnaryService<pb::ListAccountsRequest> for ListAccountsSvc {
+    type Response = pb::ListAccountsResponse;
+    type Future = BoxFuture<Response<Self::Response>, Status>;
+
+    fn call(&mut self, _request: Request<pb::ListAccountsRequest>) -> Self::Future {
+        let service = self.0.clone();
+        Box::pin(async move { Ok(Response::new(service.list_accounts().await)) })
+    }
+}
+
+impl<B> Service<http::Request<B>> for GrpcService
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::Body>;
+    type Error = std::convert::Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let service = self.clone();
+        match request.uri().path() {
+            "/payment_engine.v1.PaymentEngine/StreamTransactions" => Box::pin(async move {
+                let mut grpc = tonic::server::Grpc::new(ProstCodec::default());
+                Ok(grpc
+                    .client_streaming(StreamTransactionsSvc(service), request)
+                    .await)
+            }),
+            "/payment_engine.v1.PaymentEngine/GetAccount" => Box::pin(async move {
+                let mut grpc = tonic::server::Grpc::new(ProstCodec::default());
+                Ok(grpc.unary(GetAccountSvc(service), request).await)
+            }),
+            "/payment_engine.v1.PaymentEngine/ListAccounts" => Box::pin(async move {
+                let mut grpc = tonic::server::Grpc::new(ProstCodec::default());
+                Ok(grpc.unary(ListAccountsSvc(service), request).await)
+            }),
+            _ => Box::pin(async move { Ok(Status::unimplemented("unknown method").into_http()) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::codegen::http::uri::PathAndQuery;
+    use tonic::transport::{Channel, Server};
+
+    fn transaction(
+        r#type: pb::TransactionType,
+        client: u64,
+        tx: u64,
+        amount: &str,
+    ) -> pb::Transaction {
+        pb::Transaction {
+            r#type: r#type as i32,
+            client,
+            tx,
+            amount: (!amount.is_empty()).then(|| amount.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_stream_transactions() {
+        let service = GrpcService::new(PaymentEngine::new());
+        let stream = tokio_stream::iter([
+            Ok(transaction(pb::TransactionType::Deposit, 1, 1, "10")),
+            Ok(transaction(pb::TransactionType::Deposit, 1, 2, "-1")),
+            Ok(transaction(pb::TransactionType::Unspecified, 1, 3, "")),
+            Ok(transaction(pb::TransactionType::Withdrawal, 1, 4, "2.5")),
+        ]);
+
+        let summary = service.stream_transactions(stream).await.unwrap();
+        assert_eq!(summary.received, 4);
+        let rejected: Vec<(u64, u64)> = summary
+            .rejected
+            .iter()
+            .map(|r| (r.position, r.tx))
+            .collect();
+        assert_eq!(rejected, [(2, 2), (3, 3)]);
+
+        let account = service
+            .get_account(pb::GetAccountRequest {
+                client: 1,
+                currency: String::new(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(account.available, "7.5000");
+        assert_eq!(account.total, "7.5000");
+        let missing = service
+            .get_account(pb::GetAccountRequest {
+                client: 2,
+                currency: String::new(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_stream_transactions_stops_on_broken_stream() {
+        let service = GrpcService::new(PaymentEngine::new());
+        let stream = tokio_stream::iter([
+            Ok(transaction(pb::TransactionType::Deposit, 1, 1, "10")),
+            Err(Status::cancelled("client went away")),
+            Ok(transaction(pb::TransactionType::Deposit, 1, 2, "10")),
+        ]);
+
+        let status = service.stream_transactions(stream).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Cancelled);
+        assert_eq!(
+            service.list_accounts().await.accounts[0].available,
+            "10.0000"
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_grpc_server_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = GrpcService::new(PaymentEngine::new());
+        tokio::spawn(
+            Server::builder()
+                .add_service(service)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let channel = Channel::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = tonic::client::Grpc::new(channel);
+        client.ready().await.unwrap();
+        let transactions = tokio_stream::iter([
+            transaction(pb::TransactionType::Deposit, 2, 1, "5"),
+            transaction(pb::TransactionType::Deposit, 1, 2, "3"),
+        ]);
+        let summary: pb::StreamSummary = client
+            .client_streaming(
+                Request::new(transactions),
+                PathAndQuery::from_static("/payment_engine.v1.PaymentEngine/StreamTransactions"),
+                ProstCodec::default(),
+            )
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(summary.received, 2);
+        assert!(summary.rejected.is_empty());
+
+        client.ready().await.unwrap();
+        let response: pb::ListAccountsResponse = client
+            .unary(
+                Request::new(pb::ListAccountsRequest {}),
+                PathAndQuery::from_static("/payment_engine.v1.PaymentEngine/ListAccounts"),
+                ProstCodec::default(),
+            )
+            .await
+            .unwrap()
+            .into_inner();
+        let clients: Vec<u64> = response.accounts.iter().map(|a| a.client).collect();
+        assert_eq!(clients, [1, 2]);
+    }
+}
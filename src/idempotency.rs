@@ -0,0 +1,97 @@
+use crate::errors::PaymentError;
+use crate::models::{InputRecord, TxId};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// Idempotency keys of applied records, so that retries sent with the same
+/// key are dropped even if their tx id was reused.
+#[derive(Debug, Default)]
+pub(crate) struct IdempotencyKeys {
+    /// How long a key is remembered, going by record timestamps; forever
+    /// when `None`.
+    retention: Option<Duration>,
+    /// The record each key was first applied with (minus its tx id and
+    /// timestamp), its tx id and when.
+    seen: HashMap<String, (InputRecord, TxId, Option<u64>)>,
+    /// Timestamped keys, oldest first, for expiry.
+    expiry: VecDeque<(u64, String)>,
+}
+
+impl IdempotencyKeys {
+    pub(crate) fn set_retention(&mut self, retention: Duration) {
+        self.retention = Some(retention);
+    }
+
+    /// Returns true if `record` repeats one already applied under its key.
+    /// Reusing a key for a different record is a conflict.
+    pub(crate) fn is_retry(&self, record: &InputRecord) -> Result<bool, PaymentError> {
+        let Some(key) = &record.idempotency_key else {
+            return Ok(false);
+        };
+        let Some((first, first_tx_id, seen_at)) = self.seen.get(key) else {
+            return Ok(false);
+        };
+        if self.expired(*seen_at, record.timestamp) {
+            return Ok(false);
+        }
+        if *first != fingerprint(record) {
+            return Err(PaymentError::IdempotencyConflict(format!(
+                "key '{}' was already used by tx {}",
+                key, first_tx_id
+            )));
+        }
+        Ok(true)
+    }
+
+    /// Remembers the key of an applied record, forgetting keys that have
+    /// outlived the retention window.
+    pub(crate) fn remember(&mut self, record: &InputRecord) {
+        let Some(key) = &record.idempotency_key else {
+            return;
+        };
+        self.seen.insert(
+            key.clone(),
+            (fingerprint(record), record.tx_id, record.timestamp),
+        );
+        let (Some(retention), Some(now)) = (self.retention, record.timestamp) else {
+            return;
+        };
+        self.expiry.push_back((now, key.clone()));
+        let cutoff = now.saturating_sub(retention.as_secs());
+        while let Some((seen_at, _)) = self.expiry.front() {
+            if *seen_at >= cutoff {
+                break;
+            }
+            let Some((seen_at, key)) = self.expiry.pop_front() else {
+                break;
+            };
+            // Only forget the key if it wasn't used again since.
+            if self
+                .seen
+                .get(&key)
+                .is_some_and(|(_, _, at)| *at == Some(seen_at))
+            {
+                self.seen.remove(&key);
+            }
+        }
+    }
+
+    fn expired(&self, seen_at: Option<u64>, now: Option<u64>) -> bool {
+        match (self.retention, seen_at, now) {
+            (Some(retention), Some(seen_at), Some(now)) => {
+                now.saturating_sub(seen_at) > retention.as_secs()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// What a retry must match: the record without its tx id, which producers
+/// may assign anew when retrying, and its timestamp.
+fn fingerprint(record: &InputRecord) -> InputRecord {
+    InputRecord {
+        tx_id: 0,
+        timestamp: None,
+        ..record.clone()
+    }
+}
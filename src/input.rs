@@ -0,0 +1,484 @@
+use crate::account_map::AccountMap;
+#[cfg(feature = "avro")]
+use crate::avro_handler;
+use crate::csv_handler::{self, CsvDialect};
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::fixed_width::{self, FixedWidthLayout};
+use crate::iso20022;
+use crate::json_handler;
+use crate::models::InputRecord;
+#[cfg(feature = "msgpack")]
+use crate::msgpack;
+use crate::mt940;
+use crate::nacha;
+use crate::ofx;
+use crate::policy::ErrorPolicy;
+#[cfg(feature = "protobuf")]
+use crate::protobuf;
+use crate::qif;
+use crate::report::{ProcessingReport, SkipKind};
+use crate::results::{RecordStatus, ResultWriter};
+use crate::tx_ids::{TxIdFormat, TxIdMap};
+use std::io::{self, Read, Write};
+use std::iter;
+use std::str::FromStr;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+/// Supported transaction input encodings.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    /// Newline-delimited JSON, one `InputRecord` object per line.
+    JsonLines,
+    /// An Avro object container file (see `avro_handler`).
+    #[cfg(feature = "avro")]
+    Avro,
+    /// A MessagePack record stream (see `msgpack`).
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    /// Length-delimited protobuf `Transaction` messages (see `protobuf`).
+    #[cfg(feature = "protobuf")]
+    Protobuf,
+    /// An ISO 20022 pain.001 credit transfer initiation (see `iso20022`).
+    Pain001,
+    /// An ISO 20022 camt.053 bank statement (see `iso20022`).
+    Camt053,
+    /// A SWIFT MT940 bank statement (see `mt940`).
+    Mt940,
+    /// A NACHA ACH file (see `nacha`).
+    Nacha,
+    /// An OFX or QFX bank or credit card statement (see `ofx`).
+    Ofx,
+    /// A Quicken Interchange Format export (see `qif`).
+    Qif,
+    /// Fixed-width records cut up by a layout (see `fixed_width`).
+    FixedWidth,
+}
+
+impl InputFormat {
+    /// Whether records are lines of text, which connections can send one at a
+    /// time.
+    pub fn is_lines(self) -> bool {
+        matches!(self, InputFormat::Csv | InputFormat::JsonLines)
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" | "ndjson" => Ok(InputFormat::JsonLines),
+            #[cfg(feature = "avro")]
+            "avro" => Ok(InputFormat::Avro),
+            #[cfg(not(feature = "avro"))]
+            "avro" => Err("avro input requires the avro feature".to_string()),
+            #[cfg(feature = "msgpack")]
+            "msgpack" | "messagepack" => Ok(InputFormat::MessagePack),
+            #[cfg(not(feature = "msgpack"))]
+            "msgpack" | "messagepack" => {
+                Err("msgpack input requires the msgpack feature".to_string())
+            }
+            #[cfg(feature = "protobuf")]
+            "protobuf" | "proto" => Ok(InputFormat::Protobuf),
+            #[cfg(not(feature = "protobuf"))]
+            "protobuf" | "proto" => Err("protobuf input requires the protobuf feature".to_string()),
+            "pain001" | "pain.001" => Ok(InputFormat::Pain001),
+            "camt053" | "camt.053" => Ok(InputFormat::Camt053),
+            "mt940" => Ok(InputFormat::Mt940),
+            "nacha" | "ach" => Ok(InputFormat::Nacha),
+            "ofx" | "qfx" => Ok(InputFormat::Ofx),
+            "qif" => Ok(InputFormat::Qif),
+            "fixed" | "fixed-width" => Ok(InputFormat::FixedWidth),
+            other => Err(format!("unknown input format '{}'", other)),
+        }
+    }
+}
+
+/// Processes transactions from `reader`, decoding them according to `format`.
+pub fn process_input<R: Read>(
+    reader: R,
+    format: InputFormat,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError> {
+    match format {
+        InputFormat::Csv => csv_handler::process_reader(reader, engine),
+        InputFormat::JsonLines => json_handler::process_json_lines(reader, engine),
+        #[cfg(feature = "avro")]
+        InputFormat::Avro => avro_handler::process_avro(reader, engine),
+        #[cfg(feature = "msgpack")]
+        InputFormat::MessagePack => msgpack::process_msgpack(reader, engine),
+        #[cfg(feature = "protobuf")]
+        InputFormat::Protobuf => protobuf::process_protobuf(reader, engine),
+        InputFormat::Pain001 => iso20022::process_pain001(reader, engine),
+        InputFormat::Camt053 => iso20022::process_camt053(reader, engine),
+        InputFormat::Mt940 => mt940::process_mt940(reader, engine),
+        InputFormat::Nacha => nacha::process_nacha(reader, engine),
+        InputFormat::Ofx => ofx::process_ofx(reader, engine),
+        InputFormat::Qif => qif::process_qif(reader, engine),
+        InputFormat::FixedWidth => Err(no_layout()),
+    }
+}
+
+/// A decoded (or undecodable) record tagged with its position in the input.
+#[derive(Debug)]
+pub struct RawRecord {
+    /// 1-based line number in the input (the CSV header is line 1), or record
+    /// number in a binary file.
+    pub line: u64,
+    /// The record as it appeared in the input, for diagnostics.
+    pub raw: String,
+    pub parsed: Result<InputRecord, PaymentError>,
+}
+
+/// Lazily decodes records from `reader` according to `format`.
+pub fn read_records<'a, R: Read + 'a>(
+    reader: R,
+    format: InputFormat,
+) -> Box<dyn Iterator<Item = RawRecord> + 'a> {
+    match format {
+        InputFormat::Csv => Box::new(csv_handler::read_records(reader)),
+        InputFormat::JsonLines => Box::new(json_handler::read_json_lines(reader)),
+        #[cfg(feature = "avro")]
+        InputFormat::Avro => avro_handler::read_avro(reader),
+        #[cfg(feature = "msgpack")]
+        InputFormat::MessagePack => Box::new(msgpack::read_msgpack(reader)),
+        #[cfg(feature = "protobuf")]
+        InputFormat::Protobuf => Box::new(protobuf::read_protobuf(reader)),
+        InputFormat::Pain001
+        | InputFormat::Camt053
+        | InputFormat::Mt940
+        | InputFormat::Nacha
+        | InputFormat::Ofx
+        | InputFormat::Qif
+        | InputFormat::FixedWidth => {
+            read_records_with_options(reader, format, &InputOptions::default())
+        }
+    }
+}
+
+/// Like [`read_records`], reading transaction references through `tx_ids`
+/// (e.g. UUIDs mapped to compact tx ids).
+pub fn read_records_with_tx_ids<'a, R: Read + 'a>(
+    reader: R,
+    format: InputFormat,
+    tx_ids: TxIdMap,
+) -> Box<dyn Iterator<Item = RawRecord> + 'a> {
+    read_records_with_accounts(reader, format, tx_ids, &AccountMap::default())
+}
+
+/// Like [`read_records_with_tx_ids`], resolving the accounts named by bank
+/// files to clients through `accounts`.
+pub fn read_records_with_accounts<'a, R: Read + 'a>(
+    reader: R,
+    format: InputFormat,
+    tx_ids: TxIdMap,
+    accounts: &AccountMap,
+) -> Box<dyn Iterator<Item = RawRecord> + 'a> {
+    let options = InputOptions {
+        tx_ids,
+        accounts: accounts.clone(),
+        ..InputOptions::default()
+    };
+    read_records_with_options(reader, format, &options)
+}
+
+/// What decoding needs besides the format: how tx references map to tx ids,
+/// which clients own the accounts named by bank files, the dialect of CSV
+/// files and the layout of fixed-width records. Clones share the tx id
+/// assignments and the rows left to skip.
+#[derive(Debug, Clone, Default)]
+pub struct InputOptions {
+    pub tx_ids: TxIdMap,
+    pub accounts: AccountMap,
+    pub csv: CsvDialect,
+    pub layout: Option<FixedWidthLayout>,
+    /// CSV rows to pass over without decoding them, like those a checkpoint
+    /// covers, counted down across the inputs read. Only numeric tx ids can
+    /// be skipped: mapped ones have to be decoded to keep their numbering.
+    pub skip_rows: Arc<AtomicU64>,
+}
+
+/// Lazily decodes records from `reader` according to `format` and `options`.
+/// Fixed-width input without a layout yields a single error.
+pub fn read_records_with_options<'a, R: Read + 'a>(
+    reader: R,
+    format: InputFormat,
+    options: &InputOptions,
+) -> Box<dyn Iterator<Item = RawRecord> + 'a> {
+    let (tx_ids, accounts) = (options.tx_ids.clone(), &options.accounts);
+    let numeric = tx_ids.format() == TxIdFormat::Numeric;
+    match format {
+        InputFormat::Csv if numeric => Box::new(csv_handler::read_records_after(
+            reader,
+            &options.csv,
+            &options.skip_rows,
+        )),
+        InputFormat::Csv => Box::new(csv_handler::read_records_with_dialect(
+            reader,
+            &options.csv,
+            tx_ids,
+        )),
+        InputFormat::JsonLines if numeric => Box::new(json_handler::read_json_lines(reader)),
+        InputFormat::JsonLines => {
+            Box::new(json_handler::read_json_lines_with_tx_ids(reader, tx_ids))
+        }
+        #[cfg(feature = "avro")]
+        InputFormat::Avro if numeric => avro_handler::read_avro(reader),
+        #[cfg(feature = "avro")]
+        InputFormat::Avro => avro_handler::read_avro_with_tx_ids(reader, tx_ids),
+        // Binary streams hold tx ids, mapped when they were written.
+        #[cfg(feature = "msgpack")]
+        InputFormat::MessagePack => Box::new(msgpack::read_msgpack(reader)),
+        #[cfg(feature = "protobuf")]
+        InputFormat::Protobuf => Box::new(protobuf::read_protobuf(reader)),
+        InputFormat::Pain001 => Box::new(iso20022::read_pain001(reader, tx_ids, accounts)),
+        InputFormat::Camt053 => Box::new(iso20022::read_camt053(reader, tx_ids, accounts)),
+        InputFormat::Mt940 => Box::new(mt940::read_mt940(reader, tx_ids, accounts)),
+        InputFormat::Nacha => Box::new(nacha::read_nacha(reader, tx_ids, accounts)),
+        InputFormat::Ofx => Box::new(ofx::read_ofx(reader, tx_ids, accounts)),
+        InputFormat::Qif => Box::new(qif::read_qif(reader, tx_ids, accounts)),
+        InputFormat::FixedWidth => match &options.layout {
+            Some(layout) => Box::new(fixed_width::read_fixed_width(reader, layout, tx_ids)),
+            None => Box::new(iter::once(RawRecord {
+                line: 0,
+                raw: String::new(),
+                parsed: Err(no_layout()),
+            })),
+        },
+    }
+}
+
+fn no_layout() -> PaymentError {
+    PaymentError::InvalidConfig("fixed-width input needs a layout".to_string())
+}
+
+/// Applies decoded records to the engine.
+///
+/// Undecodable records and rejected transactions are logged, skipped and
+/// listed in the returned report; only I/O failures (reading the input or
+/// writing a transaction store or audit log) abort processing.
+pub fn process_records<I>(
+    records: I,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError>
+where
+    I: IntoIterator<Item = RawRecord>,
+{
+    process_records_with_policy(records, engine, ErrorPolicy::Skip)
+}
+
+/// Applies decoded records to the engine, handling invalid ones per `policy`.
+pub fn process_records_with_policy<I>(
+    records: I,
+    engine: &mut PaymentEngine,
+    policy: ErrorPolicy,
+) -> Result<ProcessingReport, PaymentError>
+where
+    I: IntoIterator<Item = RawRecord>,
+{
+    process_all(records, engine, policy, None::<&mut ResultWriter<io::Sink>>)
+}
+
+/// Like [`process_records_with_policy`], also writing the result of every
+/// record to `results` as it's processed.
+pub fn process_records_with_results<I, W>(
+    records: I,
+    engine: &mut PaymentEngine,
+    policy: ErrorPolicy,
+    results: &mut ResultWriter<W>,
+) -> Result<ProcessingReport, PaymentError>
+where
+    I: IntoIterator<Item = RawRecord>,
+    W: Write,
+{
+    process_all(records, engine, policy, Some(results))
+}
+
+fn process_all<I, W>(
+    records: I,
+    engine: &mut PaymentEngine,
+    policy: ErrorPolicy,
+    mut results: Option<&mut ResultWriter<W>>,
+) -> Result<ProcessingReport, PaymentError>
+where
+    I: IntoIterator<Item = RawRecord>,
+    W: Write,
+{
+    let mut report = ProcessingReport::default();
+    for RawRecord { line, raw, parsed } in records {
+        report.records_read += 1;
+        let record = match parsed {
+            Ok(rec) => rec,
+            Err(e) => {
+                if let Some(results) = results.as_deref_mut() {
+                    results.write(engine, line, None, RecordStatus::Invalid, Some(&e))?;
+                }
+                report.record_failure(policy, line, raw, SkipKind::Decode, e)?;
+                continue;
+            }
+        };
+
+        // The engine takes the record, so keep a copy for its result line.
+        let copy = results.is_some().then(|| record.clone());
+        let mutations = engine.mutations();
+        let result = engine.process(record);
+        if let (Some(results), Some(record)) = (results.as_deref_mut(), &copy) {
+            let status = match &result {
+                Ok(()) if engine.mutations() == mutations => RecordStatus::Ignored,
+                Ok(()) => RecordStatus::Applied,
+                Err(_) => RecordStatus::Rejected,
+            };
+            results.write(engine, line, Some(record), status, result.as_ref().err())?;
+        }
+        if let Err(e) = result {
+            report.record_failure(policy, line, raw, SkipKind::Rejected, e)?;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("csv", Ok(InputFormat::Csv))]
+    #[case("JSONL", Ok(InputFormat::JsonLines))]
+    #[case("pain.001", Ok(InputFormat::Pain001))]
+    #[case("camt053", Ok(InputFormat::Camt053))]
+    #[case("MT940", Ok(InputFormat::Mt940))]
+    #[case("ach", Ok(InputFormat::Nacha))]
+    #[case("QFX", Ok(InputFormat::Ofx))]
+    #[case("qif", Ok(InputFormat::Qif))]
+    #[case("fixed-width", Ok(InputFormat::FixedWidth))]
+    #[case("ndjson", Ok(InputFormat::JsonLines))]
+    #[case("xml", Err("unknown input format 'xml'".to_string()))]
+    fn test_input_format_from_str(
+        #[case] input: &str,
+        #[case] expected: Result<InputFormat, String>,
+    ) {
+        assert_eq!(input.parse::<InputFormat>(), expected);
+    }
+
+    #[rstest]
+    #[case(InputFormat::Csv, "type,client,tx,amount\ndeposit,1,1,2.0\n")]
+    #[case(
+        InputFormat::JsonLines,
+        "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"2.0\"}\n"
+    )]
+    fn test_process_input_dispatches_on_format(#[case] format: InputFormat, #[case] input: &str) {
+        let mut engine = PaymentEngine::new();
+        assert!(process_input(input.as_bytes(), format, &mut engine).is_ok());
+        assert_eq!(engine.get_accounts().len(), 1);
+    }
+
+    #[rstest]
+    #[case("type,client,tx,amount\ndeposit,1,1,2.0\nbogus\n", 3, "CSV")]
+    #[case(
+        "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,0\n",
+        3,
+        "must be positive"
+    )]
+    fn test_fail_fast_reports_line(
+        #[case] input: &str,
+        #[case] expected_line: u64,
+        #[case] expected_msg: &str,
+    ) {
+        let mut engine = PaymentEngine::new();
+        let result = process_records_with_policy(
+            read_records(input.as_bytes(), InputFormat::Csv),
+            &mut engine,
+            ErrorPolicy::FailFast,
+        );
+
+        match result.err().unwrap() {
+            PaymentError::AtLine { line, source } => {
+                assert_eq!(line, expected_line);
+                assert!(source.to_string().contains(expected_msg));
+            }
+            other => panic!("Expected AtLine error, got {}", other),
+        }
+        // Records before the failure were applied.
+        assert_eq!(engine.get_accounts().len(), 1);
+    }
+
+    #[rstest]
+    fn test_skip_rows_across_inputs() {
+        let options = InputOptions::default();
+        options
+            .skip_rows
+            .store(3, std::sync::atomic::Ordering::Relaxed);
+        let first = "type,client,tx,amount\ndeposit,1,1,1\n";
+        // Passed over unread, so the row that doesn't parse isn't reported.
+        let second = "type,client,tx,amount\ndeposit,1,2,1,extra\ngarbled\ndeposit,1,3,1\n";
+        let records: Vec<(u64, crate::models::TxId)> = [first, second]
+            .into_iter()
+            .flat_map(|input| {
+                read_records_with_options(input.as_bytes(), InputFormat::Csv, &options)
+            })
+            .map(|record| (record.line, record.parsed.unwrap().tx_id))
+            .collect();
+        assert_eq!(records, [(4, 3)]);
+        assert_eq!(
+            options.skip_rows.load(std::sync::atomic::Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[rstest]
+    fn test_skip_policy_continues_past_bad_records() {
+        let input = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"2.0\"}\n\
+                     garbage\n\
+                     {\"type\":\"deposit\",\"client\":2,\"tx\":2,\"amount\":\"2.0\"}\n";
+        let mut engine = PaymentEngine::new();
+        let result = process_records_with_policy(
+            read_records(input.as_bytes(), InputFormat::JsonLines),
+            &mut engine,
+            ErrorPolicy::Skip,
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(engine.get_accounts().len(), 2);
+    }
+
+    #[rstest]
+    #[case(
+        InputFormat::Csv,
+        "type,client,tx,amount\ndeposit,1,1,2.0\nbogus\nwithdrawal,1,2,-1.0\n",
+        "withdrawal,1,2,-1.0"
+    )]
+    #[case(
+        InputFormat::JsonLines,
+        "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"2.0\"}\n\nbogus\n\
+         {\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":\"-1.0\"}\n",
+        "{\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":\"-1.0\"}"
+    )]
+    fn test_report_lists_skipped_records(
+        #[case] format: InputFormat,
+        #[case] input: &str,
+        #[case] withdrawal: &str,
+    ) {
+        let mut engine = PaymentEngine::new();
+        let report = process_records(read_records(input.as_bytes(), format), &mut engine).unwrap();
+
+        assert_eq!(report.records_read, 3);
+        let skipped: Vec<(u64, &str, SkipKind)> = report
+            .skipped
+            .iter()
+            .map(|s| (s.line, s.raw.as_str(), s.kind))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                (3, "bogus", SkipKind::Decode),
+                (4, withdrawal, SkipKind::Rejected)
+            ]
+        );
+    }
+}
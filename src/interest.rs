@@ -0,0 +1,101 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde_derive::{Deserialize, Serialize};
+
+pub(crate) const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Interest paid on available balances: accrued daily at an annual rate and
+/// posted to the accounts every `period_days` days.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterestSchedule {
+    /// Annual rate as a percentage, e.g. `2.5` for 2.5%.
+    annual_rate: Decimal,
+    period_days: u32,
+}
+
+impl InterestSchedule {
+    /// Pays `annual_rate` percent a year (1/365th of it a day), posted every
+    /// 30 days.
+    pub fn new(annual_rate: Decimal) -> Self {
+        InterestSchedule {
+            annual_rate,
+            period_days: 30,
+        }
+    }
+
+    /// Posts accrued interest every `period_days` days instead. Zero is
+    /// treated as one.
+    pub fn with_period_days(mut self, period_days: u32) -> Self {
+        self.period_days = period_days.max(1);
+        self
+    }
+
+    pub fn annual_rate(&self) -> Decimal {
+        self.annual_rate
+    }
+
+    pub fn period_days(&self) -> u32 {
+        self.period_days
+    }
+
+    /// Interest earned by `balance` over `days` days, unrounded.
+    pub(crate) fn accrue(&self, balance: Decimal, days: u64) -> Decimal {
+        balance * self.annual_rate * Decimal::from(days) / Decimal::from(365 * 100)
+    }
+
+    /// The part of `accrued` that can be posted, rounded half-up to 4
+    /// decimal places.
+    pub(crate) fn postable(accrued: Decimal) -> Decimal {
+        accrued.round_dp_with_strategy(4, RoundingStrategy::MidpointAwayFromZero)
+    }
+}
+
+/// How far the engine's interest clock has advanced, in days since the Unix
+/// epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct InterestClock {
+    /// Last day interest was accrued up to.
+    pub day: u64,
+    /// First day of the current posting period.
+    pub period_start: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    #[rstest]
+    #[case(dec!(1000), 1, dec!(3.65), dec!(0.1))]
+    #[case(dec!(1000), 30, dec!(3.65), dec!(3.0))]
+    #[case(dec!(0), 30, dec!(3.65), dec!(0))]
+    fn test_interest_accrue(
+        #[case] balance: Decimal,
+        #[case] days: u64,
+        #[case] annual_rate: Decimal,
+        #[case] expected: Decimal,
+    ) {
+        assert_eq!(
+            InterestSchedule::new(annual_rate).accrue(balance, days),
+            expected
+        );
+    }
+
+    #[rstest]
+    fn test_interest_schedule_period() {
+        assert_eq!(InterestSchedule::new(dec!(1)).period_days(), 30);
+        assert_eq!(
+            InterestSchedule::new(dec!(1))
+                .with_period_days(0)
+                .period_days(),
+            1
+        );
+    }
+
+    #[rstest]
+    #[case(dec!(0.123456), dec!(0.1235))]
+    #[case(dec!(0.00004), dec!(0.0000))]
+    fn test_interest_postable(#[case] accrued: Decimal, #[case] expected: Decimal) {
+        assert_eq!(InterestSchedule::postable(accrued), expected);
+    }
+}
@@ -0,0 +1,635 @@
+//! ISO 20022 payment files, so bank batch files and statements can be run
+//! through the engine without a pre-processor.
+//!
+//! A pain.001 customer credit transfer initiation moves funds from the debtor
+//! account of each payment information block to the creditor account of each
+//! of its transactions. Accounts are resolved to clients through an
+//! [`AccountMap`]; a transfer becomes a withdrawal when only the debtor is a
+//! client, a deposit when only the creditor is, and a `transfer` when both
+//! are.
+//!
+//! A camt.053 bank to customer statement lists the entries booked to one
+//! account per statement: credits become deposits and debits withdrawals of
+//! the account's client, so the engine's balances can be reconciled with the
+//! bank's.
+//!
+//! Elements are matched by local name, so every version of a message reads
+//! the same.
+
+use crate::account_map::AccountMap;
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::input::{process_records, RawRecord};
+use crate::interest::SECONDS_PER_DAY;
+use crate::models::{ClientId, Currency, InputRecord, TransactionType};
+use crate::report::ProcessingReport;
+use crate::tx_ids::TxIdMap;
+use roxmltree::{Document, Node};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::io::Read;
+use std::vec;
+
+/// The placeholder for an end-to-end id the debtor didn't assign.
+const NOT_PROVIDED: &str = "NOTPROVIDED";
+/// The status of entries that moved the balance, as opposed to pending or
+/// expected ones.
+const BOOKED: &str = "BOOK";
+
+/// Processes the transfers of a pain.001 file, reading account numbers as
+/// client ids.
+pub fn process_pain001<R: Read>(
+    reader: R,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError> {
+    process_records(
+        read_pain001(reader, TxIdMap::default(), &AccountMap::default()),
+        engine,
+    )
+}
+
+/// Decodes the transfers of a pain.001 file, one record per
+/// `CdtTrfTxInf`. Their `line` is where the element starts and their tx id
+/// the end-to-end id (or the instruction id or UETR when it wasn't provided),
+/// read through `tx_ids`.
+///
+/// The document is parsed whole, so one that isn't well-formed XML yields a
+/// single error at the line of the fault.
+pub fn read_pain001<R: Read>(
+    reader: R,
+    tx_ids: TxIdMap,
+    accounts: &AccountMap,
+) -> vec::IntoIter<RawRecord> {
+    read_document(reader, "CstmrCdtTrfInitn", "pain.001", |initiation| {
+        let mut records = Vec::new();
+        for payment in children(initiation, "PmtInf") {
+            let debtor = account(payment, "DbtrAcct");
+            for transfer in children(payment, "CdtTrfTxInf") {
+                let parsed = date(payment, "ReqdExctnDt").and_then(|timestamp| {
+                    decode_transfer(transfer, debtor, timestamp, &tx_ids, accounts)
+                });
+                records.push(record_of(transfer, parsed));
+            }
+        }
+        records
+    })
+}
+
+/// Processes the booked entries of a camt.053 file, reading account numbers
+/// as client ids.
+pub fn process_camt053<R: Read>(
+    reader: R,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError> {
+    process_records(
+        read_camt053(reader, TxIdMap::default(), &AccountMap::default()),
+        engine,
+    )
+}
+
+/// Decodes the booked entries of a camt.053 file, one record per `Ntry`;
+/// pending and expected entries haven't moved the balance yet and are left
+/// out. Their `line` is where the element starts and their tx id the
+/// servicer's reference (or the entry reference or end-to-end id when it has
+/// none, or else the statement id and the entry's position), read through
+/// `tx_ids`. Their timestamp is the booking date.
+pub fn read_camt053<R: Read>(
+    reader: R,
+    tx_ids: TxIdMap,
+    accounts: &AccountMap,
+) -> vec::IntoIter<RawRecord> {
+    read_document(reader, "BkToCstmrStmt", "camt.053", |message| {
+        let mut records = Vec::new();
+        for statement in children(message, "Stmt") {
+            let owner = account(statement, "Acct");
+            for (entry, position) in children(statement, "Ntry").zip(1..) {
+                let status = text(entry, &["Sts", "Cd"]).or_else(|| text(entry, &["Sts"]));
+                if status.is_some_and(|status| status != BOOKED) {
+                    continue;
+                }
+                let parsed = decode_entry(statement, entry, position, owner, &tx_ids, accounts);
+                records.push(record_of(entry, parsed));
+            }
+        }
+        records
+    })
+}
+
+/// Parses a whole document and hands its `message` element (the root's only
+/// child) to `decode`. A document that isn't well-formed XML, or not the
+/// message `name`, yields a single error at the line of the fault.
+fn read_document<R: Read>(
+    mut reader: R,
+    message: &str,
+    name: &str,
+    decode: impl FnOnce(Node) -> Vec<RawRecord>,
+) -> vec::IntoIter<RawRecord> {
+    let mut text = String::new();
+    if let Err(e) = reader.read_to_string(&mut text) {
+        return vec![error_at(0, e.into())].into_iter();
+    }
+    let document = match Document::parse(&text) {
+        Ok(document) => document,
+        Err(e) => return vec![error_at(u64::from(e.pos().row), e.into())].into_iter(),
+    };
+    let root = document.root_element();
+    match child(root, message) {
+        Some(message) => decode(message).into_iter(),
+        None => {
+            let err = invalid(format!("not a {} document", name));
+            vec![error_at(line_of(root), err)].into_iter()
+        }
+    }
+}
+
+fn decode_transfer(
+    transfer: Node,
+    debtor: Option<&str>,
+    timestamp: Option<u64>,
+    tx_ids: &TxIdMap,
+    accounts: &AccountMap,
+) -> Result<InputRecord, PaymentError> {
+    let reference = ["EndToEndId", "InstrId", "UETR"]
+        .into_iter()
+        .filter_map(|id| text(transfer, &["PmtId", id]))
+        .find(|&reference| reference != NOT_PROVIDED)
+        .ok_or_else(|| missing("PmtId"))?;
+    let (value, currency) = amount(transfer, &["Amt", "InstdAmt"], reference)?;
+
+    let client = |account: Option<&str>| account.and_then(|account| accounts.client(account));
+    let creditor = account(transfer, "CdtrAcct");
+    let (record_type, client_id, counterparty_id): (_, ClientId, _) =
+        match (client(debtor), client(creditor)) {
+            (Some(debtor), Some(creditor)) => (TransactionType::Transfer, debtor, Some(creditor)),
+            (Some(debtor), None) => (TransactionType::Withdrawal, debtor, None),
+            (None, Some(creditor)) => (TransactionType::Deposit, creditor, None),
+            (None, None) => {
+                return Err(invalid(format!(
+                    "neither account of {} belongs to a client",
+                    reference
+                )))
+            }
+        };
+
+    Ok(InputRecord {
+        record_type,
+        client_id,
+        tx_id: tx_ids.map(reference)?,
+        amount: Some(value),
+        counterparty_id,
+        currency,
+        target_currency: None,
+        timestamp,
+        idempotency_key: None,
+        reference: None,
+        metadata: HashMap::new(),
+    })
+}
+
+fn decode_entry(
+    statement: Node,
+    entry: Node,
+    position: usize,
+    owner: Option<&str>,
+    tx_ids: &TxIdMap,
+    accounts: &AccountMap,
+) -> Result<InputRecord, PaymentError> {
+    let details = ["NtryDtls", "TxDtls", "Refs", "EndToEndId"];
+    let reference = match text(entry, &["AcctSvcrRef"])
+        .or_else(|| text(entry, &["NtryRef"]))
+        .or_else(|| text(entry, &details).filter(|&reference| reference != NOT_PROVIDED))
+    {
+        Some(reference) => reference.to_string(),
+        None => {
+            let statement = text(statement, &["Id"]).ok_or_else(|| missing("AcctSvcrRef"))?;
+            format!("{}/{}", statement, position)
+        }
+    };
+    let (value, currency) = amount(entry, &["Amt"], &reference)?;
+    let record_type = match text(entry, &["CdtDbtInd"]) {
+        Some("CRDT") => TransactionType::Deposit,
+        Some("DBIT") => TransactionType::Withdrawal,
+        _ => return Err(invalid(format!("invalid CdtDbtInd in {}", reference))),
+    };
+    let owner = owner.ok_or_else(|| missing("Acct"))?;
+    let client_id = accounts
+        .client(owner)
+        .ok_or_else(|| invalid(format!("account {} belongs to no client", owner)))?;
+
+    Ok(InputRecord {
+        record_type,
+        client_id,
+        tx_id: tx_ids.map(&reference)?,
+        amount: Some(value),
+        counterparty_id: None,
+        currency,
+        target_currency: None,
+        timestamp: date(entry, "BookgDt")?,
+        idempotency_key: None,
+        reference: None,
+        metadata: HashMap::new(),
+    })
+}
+
+/// The amount at `path` and the currency of its `Ccy` attribute.
+fn amount(node: Node, path: &[&str], reference: &str) -> Result<(Decimal, Currency), PaymentError> {
+    let name = path.last().copied().unwrap_or_default();
+    let amount = element(node, path).ok_or_else(|| missing(name))?;
+    let currency = amount
+        .attribute("Ccy")
+        .ok_or_else(|| missing("Ccy"))?
+        .parse()
+        .map_err(PaymentError::InvalidTransaction)?;
+    let value = amount
+        .text()
+        .unwrap_or_default()
+        .trim()
+        .parse()
+        .map_err(|_| invalid(format!("invalid amount in {}", reference)))?;
+    Ok((value, currency))
+}
+
+/// The date element `name` of `node`, at midnight UTC. Dates are plain in
+/// older versions of the messages and a choice of `Dt` or `DtTm` in newer
+/// ones.
+fn date(node: Node, name: &str) -> Result<Option<u64>, PaymentError> {
+    let Some(date) = child(node, name) else {
+        return Ok(None);
+    };
+    let value = ["Dt", "DtTm"]
+        .into_iter()
+        .find_map(|choice| text(date, &[choice]))
+        .or_else(|| date.text().map(str::trim))
+        .unwrap_or_default();
+    value
+        .get(..10)
+        .and_then(parse_date)
+        .map(Some)
+        .ok_or_else(|| invalid(format!("invalid {} '{}'", name, value)))
+}
+
+/// Seconds since the Unix epoch at the start of a `YYYY-MM-DD` date.
+fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-').map(|part| part.parse::<u32>().ok());
+    date_timestamp(parts.next()??, parts.next()??, parts.next()??)
+}
+
+/// Seconds since the Unix epoch at the start of a date, if it's a valid one
+/// since then.
+pub(crate) fn date_timestamp(year: u32, month: u32, day: u32) -> Option<u64> {
+    let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    let month_days = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if !(1970..=9999).contains(&year)
+        || !(1..=12).contains(&month)
+        || !(1..=month_days).contains(&day)
+    {
+        return None;
+    }
+    // Days from civil, with years starting in March so leap days come last.
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let days = 365 * year + year / 4 - year / 100 + year / 400 + day_of_year - 719_468;
+    Some(u64::from(days) * SECONDS_PER_DAY)
+}
+
+/// The IBAN or other identification of an account element of `node`.
+fn account<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    text(node, &[name, "Id", "IBAN"]).or_else(|| text(node, &[name, "Id", "Othr", "Id"]))
+}
+
+fn children<'a, 'i>(node: Node<'a, 'i>, name: &str) -> impl Iterator<Item = Node<'a, 'i>> {
+    let name = name.to_string();
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Option<Node<'a, 'i>> {
+    children(node, name).next()
+}
+
+fn element<'a, 'i>(node: Node<'a, 'i>, path: &[&str]) -> Option<Node<'a, 'i>> {
+    path.iter().try_fold(node, |node, name| child(node, name))
+}
+
+fn text<'a>(node: Node<'a, '_>, path: &[&str]) -> Option<&'a str> {
+    element(node, path)?
+        .text()
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+}
+
+/// The record of `node`, with its source on one line for the rejects file.
+fn record_of(node: Node, parsed: Result<InputRecord, PaymentError>) -> RawRecord {
+    RawRecord {
+        line: line_of(node),
+        raw: node.document().input_text()[node.range()]
+            .lines()
+            .map(str::trim)
+            .collect(),
+        parsed,
+    }
+}
+
+fn line_of(node: Node) -> u64 {
+    u64::from(node.document().text_pos_at(node.range().start).row)
+}
+
+fn error_at(line: u64, err: PaymentError) -> RawRecord {
+    RawRecord {
+        line,
+        raw: String::new(),
+        parsed: Err(err),
+    }
+}
+
+fn missing(element: &str) -> PaymentError {
+    invalid(format!("missing {}", element))
+}
+
+fn invalid(message: String) -> PaymentError {
+    PaymentError::InvalidTransaction(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TxId;
+    use crate::tx_ids::TxIdFormat;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    const PAIN001: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.09">
+  <CstmrCdtTrfInitn>
+    <GrpHdr>
+      <MsgId>MSG-1</MsgId>
+      <NbOfTxs>3</NbOfTxs>
+    </GrpHdr>
+    <PmtInf>
+      <PmtInfId>PMT-1</PmtInfId>
+      <ReqdExctnDt><Dt>2024-03-01</Dt></ReqdExctnDt>
+      <DbtrAcct><Id><IBAN>DE89370400440532013000</IBAN></Id></DbtrAcct>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>E2E-1</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">10.50</InstdAmt></Amt>
+        <CdtrAcct><Id><IBAN>GB29NWBK60161331926819</IBAN></Id></CdtrAcct>
+      </CdtTrfTxInf>
+      <CdtTrfTxInf>
+        <PmtId><InstrId>I-2</InstrId><EndToEndId>NOTPROVIDED</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">2</InstdAmt></Amt>
+        <CdtrAcct><Id><Othr><Id>2</Id></Othr></Id></CdtrAcct>
+      </CdtTrfTxInf>
+    </PmtInf>
+    <PmtInf>
+      <PmtInfId>PMT-2</PmtInfId>
+      <ReqdExctnDt>2024-03-02</ReqdExctnDt>
+      <DbtrAcct><Id><IBAN>FR1420041010050500013M02606</IBAN></Id></DbtrAcct>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>E2E-3</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">7</InstdAmt></Amt>
+        <CdtrAcct><Id><IBAN>DE89 3704 0044 0532 0130 00</IBAN></Id></CdtrAcct>
+      </CdtTrfTxInf>
+    </PmtInf>
+  </CstmrCdtTrfInitn>
+</Document>
+"#;
+
+    fn accounts() -> AccountMap {
+        AccountMap::new()
+            .with_account("DE89370400440532013000", 1)
+            .with_account("2", 2)
+    }
+
+    fn read(xml: &str) -> Vec<RawRecord> {
+        read_pain001(xml.as_bytes(), TxIdMap::new(TxIdFormat::Text), &accounts()).collect()
+    }
+
+    #[rstest]
+    fn test_read_pain001() {
+        let records = read(PAIN001);
+        let decoded: Vec<(
+            u64,
+            TransactionType,
+            ClientId,
+            TxId,
+            Option<ClientId>,
+            Decimal,
+        )> = records
+            .iter()
+            .map(|record| {
+                let parsed = record.parsed.as_ref().unwrap();
+                (
+                    record.line,
+                    parsed.record_type,
+                    parsed.client_id,
+                    parsed.tx_id,
+                    parsed.counterparty_id,
+                    parsed.amount.unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            decoded,
+            [
+                (12, TransactionType::Withdrawal, 1, 1, None, dec!(10.50)),
+                (17, TransactionType::Transfer, 1, 2, Some(2), dec!(2)),
+                (27, TransactionType::Deposit, 1, 3, None, dec!(7)),
+            ]
+        );
+
+        let first = records[0].parsed.as_ref().unwrap();
+        assert_eq!(first.currency.as_str(), "EUR");
+        assert_eq!(first.timestamp, Some(1_709_251_200));
+        assert_eq!(
+            records[2].parsed.as_ref().unwrap().timestamp,
+            Some(1_709_251_200 + SECONDS_PER_DAY)
+        );
+        assert!(records[0].raw.starts_with("<CdtTrfTxInf><PmtId>"));
+    }
+
+    #[rstest]
+    fn test_process_pain001_reads_client_ids() {
+        let xml = PAIN001
+            .replace("DE89370400440532013000", "1")
+            .replace("DE89 3704 0044 0532 0130 00", "1")
+            .replace("E2E-1", "11")
+            .replace("I-2", "12")
+            .replace("E2E-3", "13");
+        let mut engine = PaymentEngine::new();
+        let report = process_pain001(xml.as_bytes(), &mut engine).unwrap();
+
+        // Client 1 is only paid after both of its transfers out, which are
+        // declined.
+        assert_eq!(report.records_read, 3);
+        assert!(report.skipped.is_empty());
+        let currency = "EUR".parse().unwrap();
+        assert_eq!(engine.get_account(1, currency).unwrap().available, dec!(7));
+    }
+
+    #[rstest]
+    #[case("<Document><Other/></Document>", 1, "not a pain.001 document")]
+    #[case("<Document>\n<CstmrCdtTrfInitn>\n</Document>", 3, "")]
+    fn test_read_pain001_rejects_documents(
+        #[case] xml: &str,
+        #[case] line: u64,
+        #[case] message: &str,
+    ) {
+        let records = read(xml);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].line, line);
+        let err = records[0].parsed.as_ref().unwrap_err().to_string();
+        assert!(err.contains(message), "{}", err);
+    }
+
+    #[rstest]
+    #[case(
+        "<Amt><InstdAmt Ccy=\"EUR\">10.50</InstdAmt></Amt>",
+        "<Amt/>",
+        "missing InstdAmt"
+    )]
+    #[case("Ccy=\"EUR\">10.50", "Ccy=\"EUR\">ten", "invalid amount in E2E-1")]
+    #[case("<Dt>2024-03-01</Dt>", "<Dt>2024-13-01</Dt>", "invalid ReqdExctnDt")]
+    #[case(
+        "<IBAN>DE89370400440532013000</IBAN>",
+        "<IBAN>NL91ABNA0417164300</IBAN>",
+        "neither account of E2E-1 belongs to a client"
+    )]
+    fn test_read_pain001_rejects_transfers(
+        #[case] from: &str,
+        #[case] to: &str,
+        #[case] message: &str,
+    ) {
+        let records = read(&PAIN001.replacen(from, to, 1));
+        let err = records[0].parsed.as_ref().unwrap_err().to_string();
+        assert!(err.contains(message), "{}", err);
+        // The other transfers still decode.
+        assert!(records[2].parsed.is_ok());
+    }
+
+    const CAMT053: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.08">
+  <BkToCstmrStmt>
+    <Stmt>
+      <Id>STMT-1</Id>
+      <Acct><Id><IBAN>DE89370400440532013000</IBAN></Id></Acct>
+      <Ntry>
+        <Amt Ccy="EUR">100.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts><Cd>BOOK</Cd></Sts>
+        <BookgDt><Dt>2024-03-01</Dt></BookgDt>
+        <AcctSvcrRef>BANK-1</AcctSvcrRef>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">40.00</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts><Cd>PDNG</Cd></Sts>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">25.50</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <NtryDtls><TxDtls><Refs><EndToEndId>E2E-1</EndToEndId></Refs></TxDtls></NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">1.00</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+      </Ntry>
+    </Stmt>
+    <Stmt>
+      <Id>STMT-2</Id>
+      <Acct><Id><IBAN>GB29NWBK60161331926819</IBAN></Id></Acct>
+      <Ntry>
+        <Amt Ccy="GBP">5</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>
+"#;
+
+    #[rstest]
+    fn test_read_camt053() {
+        let records: Vec<RawRecord> = read_camt053(
+            CAMT053.as_bytes(),
+            TxIdMap::new(TxIdFormat::Text),
+            &accounts(),
+        )
+        .collect();
+        assert_eq!(records.len(), 4);
+        let decoded: Vec<(u64, TransactionType, TxId, Decimal, Option<u64>)> = records[..3]
+            .iter()
+            .map(|record| {
+                let parsed = record.parsed.as_ref().unwrap();
+                assert_eq!(parsed.client_id, 1);
+                (
+                    record.line,
+                    parsed.record_type,
+                    parsed.tx_id,
+                    parsed.amount.unwrap(),
+                    parsed.timestamp,
+                )
+            })
+            .collect();
+        // The pending entry is left out; the last has no reference of its own.
+        assert_eq!(
+            decoded,
+            [
+                (
+                    7,
+                    TransactionType::Deposit,
+                    1,
+                    dec!(100.00),
+                    Some(1_709_251_200)
+                ),
+                (19, TransactionType::Withdrawal, 2, dec!(25.50), None),
+                (25, TransactionType::Withdrawal, 3, dec!(1.00), None),
+            ]
+        );
+        let err = records[3].parsed.as_ref().unwrap_err().to_string();
+        assert!(
+            err.contains("account GB29NWBK60161331926819 belongs to no client"),
+            "{}",
+            err
+        );
+    }
+
+    #[rstest]
+    fn test_process_camt053_reads_client_ids() {
+        let xml = CAMT053
+            .replace("DE89370400440532013000", "1")
+            .replace("BANK-1", "11")
+            .replace("E2E-1", "12");
+        let mut engine = PaymentEngine::new();
+        let report = process_camt053(xml.as_bytes(), &mut engine).unwrap();
+
+        // The references made of statement ids aren't numeric, and the last
+        // statement's account isn't a client id.
+        assert_eq!(report.records_read, 4);
+        assert_eq!(report.skipped.len(), 2);
+        let currency = "EUR".parse().unwrap();
+        assert_eq!(
+            engine.get_account(1, currency).unwrap().available,
+            dec!(74.50)
+        );
+    }
+
+    #[rstest]
+    #[case("1970-01-01", Some(0))]
+    #[case("2000-02-29", Some(951_782_400))]
+    #[case("2024-12-31", Some(1_735_603_200))]
+    #[case("2024-00-01", None)]
+    #[case("2023-02-29", None)]
+    #[case("yesterday", None)]
+    fn test_parse_date(#[case] date: &str, #[case] expected: Option<u64>) {
+        assert_eq!(parse_date(date), expected);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::client_registry::ClientColumns;
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::input::{process_records, RawRecord};
+use crate::models::{InputRecord, OutputRecord};
+use crate::report::ProcessingReport;
+use crate::tx_ids::TxIdMap;
+use serde_derive::Serialize;
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// Processes transactions from newline-delimited JSON (one record per line).
+pub fn process_json_lines<R: Read>(
+    reader: R,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError> {
+    process_records(read_json_lines(reader), engine)
+}
+
+/// Lazily decodes JSON lines into records, skipping blank lines.
+pub fn read_json_lines<R: Read>(reader: R) -> impl Iterator<Item = RawRecord> {
+    decode_lines(reader, None)
+}
+
+/// Like [`read_json_lines`], reading the `tx` field through `tx_ids`. String
+/// and numeric values are both accepted as references.
+pub fn read_json_lines_with_tx_ids<R: Read>(
+    reader: R,
+    tx_ids: TxIdMap,
+) -> impl Iterator<Item = RawRecord> {
+    decode_lines(reader, Some(tx_ids))
+}
+
+fn decode_lines<R: Read>(reader: R, tx_ids: Option<TxIdMap>) -> impl Iterator<Item = RawRecord> {
+    BufReader::new(reader)
+        .lines()
+        .zip(1..)
+        .filter(|(line, _)| !matches!(line, Ok(l) if l.trim().is_empty()))
+        .map(move |(line, number)| match line {
+            Ok(raw) => RawRecord {
+                line: number,
+                parsed: decode_line(&raw, tx_ids.as_ref()),
+                raw,
+            },
+            Err(e) => RawRecord {
+                line: number,
+                raw: String::new(),
+                parsed: Err(PaymentError::from(e)),
+            },
+        })
+}
+
+fn decode_line(raw: &str, tx_ids: Option<&TxIdMap>) -> Result<InputRecord, PaymentError> {
+    let Some(tx_ids) = tx_ids else {
+        return Ok(serde_json::from_str(raw)?);
+    };
+    let mut record: serde_json::Value = serde_json::from_str(raw)?;
+    if let Some(tx) = record.get_mut("tx") {
+        let tx_id = match &*tx {
+            serde_json::Value::String(reference) => tx_ids.map(reference)?,
+            other => tx_ids.map(&other.to_string())?,
+        };
+        *tx = tx_id.into();
+    }
+    Ok(serde_json::from_value(record)?)
+}
+
+/// An account as written, followed by the client columns when the engine
+/// has a client registry.
+#[derive(Serialize)]
+struct AccountRow<'a> {
+    #[serde(flatten)]
+    account: OutputRecord,
+    #[serde(flatten)]
+    client: Option<ClientColumns<'a>>,
+}
+
+/// Returns the accounts sorted by client ID and currency, with amounts at the
+/// output precision.
+fn output_records(engine: &PaymentEngine) -> Vec<AccountRow<'_>> {
+    let registry = engine.client_registry();
+    engine
+        .iter_accounts_sorted()
+        .map(|account| AccountRow {
+            account: at_output_precision(account.to_output_record()),
+            client: (!registry.is_empty())
+                .then(|| ClientColumns::of(registry.get(account.client_id))),
+        })
+        .collect()
+}
+
+/// Rescales the amounts of `account` to the output precision.
+pub(crate) fn at_output_precision(mut account: OutputRecord) -> OutputRecord {
+    account.available.rescale(4);
+    account.held.rescale(4);
+    account.total.rescale(4);
+    account.overdraft.rescale(4);
+    account
+}
+
+/// Writes account states as a single JSON array.
+pub fn write_accounts_json<W: Write>(
+    engine: &PaymentEngine,
+    mut writer: W,
+) -> Result<(), PaymentError> {
+    serde_json::to_writer(&mut writer, &output_records(engine))?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes account states as newline-delimited JSON, one account per line.
+pub fn write_accounts_json_lines<W: Write>(
+    engine: &PaymentEngine,
+    mut writer: W,
+) -> Result<(), PaymentError> {
+    for account in output_records(engine) {
+        serde_json::to_writer(&mut writer, &account)?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    #[rstest]
+    fn test_process_json_lines() {
+        let input = r#"{"type":"deposit","client":1,"tx":1,"amount":"10.5"}
+
+{"type":"withdrawal","client":1,"tx":2,"amount":2.5}
+not json at all
+{"type":"transfer","client":1,"tx":3,"amount":"3.0","counterparty":2}
+{"type":"dispute","client":1,"tx":2}
+"#;
+
+        let mut engine = PaymentEngine::new();
+        assert!(process_json_lines(input.as_bytes(), &mut engine).is_ok());
+
+        let mut accounts = engine.get_accounts();
+        accounts.sort_by_key(|a| a.client_id);
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].available, dec!(5.0));
+        assert_eq!(accounts[0].held, dec!(2.5));
+        assert_eq!(accounts[1].available, dec!(3.0));
+    }
+
+    #[rstest]
+    fn test_read_json_lines_with_text_tx_ids() {
+        let input = r#"{"type":"deposit","client":1,"tx":"pay_a","amount":"10"}
+{"type":"deposit","client":1,"tx":7,"amount":"1"}
+{"type":"dispute","client":1,"tx":"pay_a"}
+{"type":"deposit","client":1,"tx":"","amount":"1"}
+"#;
+
+        let tx_ids = TxIdMap::new(crate::tx_ids::TxIdFormat::Text);
+        let tx_ids: Vec<_> = read_json_lines_with_tx_ids(input.as_bytes(), tx_ids)
+            .map(|r| r.parsed.map(|record| record.tx_id).ok())
+            .collect();
+        assert_eq!(tx_ids, [Some(1), Some(2), Some(1), None]);
+    }
+
+    fn sample_engine() -> PaymentEngine {
+        let mut engine = PaymentEngine::new();
+        let input = "{\"type\":\"deposit\",\"client\":2,\"tx\":1,\"amount\":\"1.5\"}\n\
+                     {\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":\"2\"}";
+        process_json_lines(input.as_bytes(), &mut engine).unwrap();
+        engine
+    }
+
+    #[rstest]
+    fn test_write_accounts_json() {
+        let mut output = Vec::new();
+        write_accounts_json(&sample_engine(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[{\"client\":1,\"currency\":\"\",\"available\":\"2.0000\",\"held\":\"0.0000\",\"total\":\"2.0000\",\"locked\":false,\"closed\":false,\"overdraft\":\"0.0000\"},\
+             {\"client\":2,\"currency\":\"\",\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false,\"closed\":false,\"overdraft\":\"0.0000\"}]\n"
+        );
+    }
+
+    #[rstest]
+    fn test_write_accounts_json_lines() {
+        let mut output = Vec::new();
+        write_accounts_json_lines(&sample_engine(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"client\":1,\"currency\":\"\",\"available\":\"2.0000\",\"held\":\"0.0000\",\"total\":\"2.0000\",\"locked\":false,\"closed\":false,\"overdraft\":\"0.0000\"}\n\
+             {\"client\":2,\"currency\":\"\",\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false,\"closed\":false,\"overdraft\":\"0.0000\"}\n"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! A streaming payments engine that processes deposits, withdrawals and the
+//! dispute lifecycle, producing final client account states.
+
+// Ids are widened to `u64` for disk slots and the wire, which is a no-op once
+// `wide-ids` makes them `u64` already.
+#![cfg_attr(feature = "wide-ids", allow(clippy::useless_conversion))]
+
+pub mod account_map;
+pub mod account_store;
+pub mod aging;
+pub mod amount_format;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod audit;
+#[cfg(feature = "avro")]
+pub mod avro_handler;
+pub mod blocklist;
+pub mod checkpoint;
+pub mod client_registry;
+pub mod clients;
+pub mod config;
+pub mod convert;
+pub mod csv_handler;
+pub mod diff;
+pub mod engine;
+pub mod errors;
+pub mod events;
+#[cfg(feature = "fast-parse")]
+mod fast_parse;
+pub mod fees;
+pub mod fixed_width;
+pub mod generate;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+mod idempotency;
+pub mod input;
+pub mod interest;
+pub mod iso20022;
+pub mod json_handler;
+pub mod limits;
+pub mod line_protocol;
+pub mod merge;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod models;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+pub mod mt940;
+pub mod nacha;
+pub mod ofx;
+pub mod output;
+#[cfg(feature = "parquet")]
+pub mod parquet_handler;
+pub mod pipeline;
+pub mod policy;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod qif;
+pub mod rates;
+pub mod reconcile;
+pub mod reorder;
+pub mod report;
+pub mod results;
+pub mod risk_report;
+pub mod settlement;
+pub mod sharded;
+pub mod stats;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod table;
+pub mod tx_ids;
+pub mod tx_store;
+mod wal;
+
+pub use account_map::AccountMap;
+pub use account_store::{AccountStore, DiskAccountStore, MemoryAccountStore};
+pub use amount_format::AmountFormat;
+pub use blocklist::Blocklist;
+pub use client_registry::{ClientInfo, ClientRegistry, KycStatus};
+pub use clients::ClientSet;
+pub use config::EngineConfig;
+pub use csv_handler::{process_reader, process_transactions, write_accounts, CsvDialect};
+pub use engine::PaymentEngine;
+pub use errors::PaymentError;
+pub use events::{EngineEvent, EventListener};
+pub use fees::{Fee, FeeSchedule};
+pub use fixed_width::FixedWidthLayout;
+pub use generate::GeneratorConfig;
+pub use input::{process_input, InputFormat, InputOptions};
+pub use interest::InterestSchedule;
+pub use limits::Limits;
+pub use models::{ClientId, InputRecord, OpenDispute, OutputRecord, TransactionType, TxId};
+pub use output::{write_output, write_output_file, OutputFormat};
+pub use policy::{
+    AmountPrecision, ClientMatchMode, DefaultDisputePolicy, DepositsOnlyPolicy, DisputePolicy,
+    DuplicateTxPolicy, ErrorPolicy, ExpiredDisputeAction, LockedAccountPolicy, RiskScorer,
+    RiskVerdict, UnderfundedDisputeMode,
+};
+pub use rates::{RateProvider, StaticRates};
+pub use reconcile::{Flows, Reconciliation, ReconciliationReport};
+pub use report::{ProcessingReport, SkipKind, SkippedRecord};
+pub use results::{RecordStatus, ResultWriter};
+pub use settlement::{Settlement, SettlementCollector};
+pub use sharded::process_sharded;
+pub use stats::EngineStats;
+pub use tx_ids::{TxIdFormat, TxIdMap};
+pub use tx_store::{CompactTxStore, DiskTxStore, MemoryTxStore, TxStore};
@@ -0,0 +1,330 @@
+//! Velocity and limit rules: basic risk controls checked as records are
+//! ingested, rejecting the transactions that would break them.
+
+use crate::errors::PaymentError;
+use crate::interest::SECONDS_PER_DAY;
+use crate::models::{ClientId, InputRecord, TransactionType};
+use rust_decimal::Decimal;
+use rustc_hash::FxHashMap;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Limits on what a client may do. Each one is off until set.
+///
+/// They apply to the records that start a transaction: deposits,
+/// withdrawals, transfers, authorizations and conversions. Disputes and
+/// other records referencing a transaction, closures, admin records and
+/// adjustments are exempt.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    max_amount: Option<Decimal>,
+    max_daily_withdrawal: Option<Decimal>,
+    max_transactions: Option<(u32, Duration)>,
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rejects transactions moving more than `amount`.
+    pub fn with_max_amount(mut self, amount: Decimal) -> Self {
+        self.max_amount = Some(amount);
+        self
+    }
+
+    /// Rejects withdrawals that would take what a client withdrew on the
+    /// day (UTC, by record timestamp) past `amount`. Declined withdrawals
+    /// don't count.
+    pub fn with_max_daily_withdrawal(mut self, amount: Decimal) -> Self {
+        self.max_daily_withdrawal = Some(amount);
+        self
+    }
+
+    /// Rejects a client's transactions once `count` of them were accepted
+    /// within `window` before it.
+    pub fn with_max_transactions(mut self, count: u32, window: Duration) -> Self {
+        self.max_transactions = Some((count, window));
+        self
+    }
+
+    pub fn max_amount(&self) -> Option<Decimal> {
+        self.max_amount
+    }
+
+    pub fn max_daily_withdrawal(&self) -> Option<Decimal> {
+        self.max_daily_withdrawal
+    }
+
+    pub fn max_transactions(&self) -> Option<(u32, Duration)> {
+        self.max_transactions
+    }
+}
+
+/// The limits of an engine, with what its clients did that counts against
+/// them. Times are seconds since the Unix epoch.
+#[derive(Debug, Default)]
+pub(crate) struct LimitTracker {
+    limits: Limits,
+    /// Day and amount withdrawn that day, per client.
+    withdrawn: FxHashMap<ClientId, (u64, Decimal)>,
+    /// Times of the accepted transactions still in the velocity window, per
+    /// client, oldest first.
+    recent: FxHashMap<ClientId, VecDeque<u64>>,
+}
+
+impl LimitTracker {
+    pub(crate) fn new(limits: Limits) -> Self {
+        LimitTracker {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Fails with `LimitExceeded` if applying `record` at `now` would break
+    /// a limit.
+    pub(crate) fn check(&self, record: &InputRecord, now: u64) -> Result<(), PaymentError> {
+        if !is_limited(record.record_type) {
+            return Ok(());
+        }
+        let amount = record.amount.unwrap_or_default();
+        if let Some(max) = self.limits.max_amount.filter(|max| amount > *max) {
+            return Err(PaymentError::LimitExceeded(format!(
+                "tx {} moves {}, over the {} transaction limit",
+                record.tx_id, amount, max
+            )));
+        }
+        if let Some(max) = self.limits.max_daily_withdrawal {
+            let today = self.withdrawn_on(record.client_id, now / SECONDS_PER_DAY);
+            if record.record_type == TransactionType::Withdrawal
+                && today.saturating_add(amount) > max
+            {
+                return Err(PaymentError::LimitExceeded(format!(
+                    "withdrawal {} would take client {}'s withdrawals today to {}, over the {} daily limit",
+                    record.tx_id,
+                    record.client_id,
+                    today.saturating_add(amount),
+                    max
+                )));
+            }
+        }
+        if let Some((max, window)) = self.limits.max_transactions {
+            let count = self.recent.get(&record.client_id).map_or(0, |times| {
+                times
+                    .iter()
+                    .filter(|&&time| within(time, now, window))
+                    .count()
+            });
+            if count >= max as usize {
+                return Err(PaymentError::LimitExceeded(format!(
+                    "client {} already made {} transactions in the last {} seconds",
+                    record.client_id,
+                    count,
+                    window.as_secs()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts a record the engine accepted at `now` against the limits;
+    /// `applied` tells whether it moved funds.
+    pub(crate) fn note(
+        &mut self,
+        client_id: ClientId,
+        record_type: TransactionType,
+        amount: Option<Decimal>,
+        now: u64,
+        applied: bool,
+    ) {
+        if !is_limited(record_type) {
+            return;
+        }
+        if self.limits.max_daily_withdrawal.is_some()
+            && record_type == TransactionType::Withdrawal
+            && applied
+        {
+            let day = now / SECONDS_PER_DAY;
+            let today = self.withdrawn_on(client_id, day);
+            let amount = amount.unwrap_or_default();
+            self.withdrawn
+                .insert(client_id, (day, today.saturating_add(amount)));
+        }
+        if let Some((_, window)) = self.limits.max_transactions {
+            let times = self.recent.entry(client_id).or_default();
+            while times
+                .front()
+                .is_some_and(|&time| !within(time, now, window))
+            {
+                times.pop_front();
+            }
+            times.push_back(now);
+        }
+    }
+
+    /// Takes in what another partition of the same engine tracked, for
+    /// clients this one hasn't seen.
+    pub(crate) fn absorb(&mut self, other: LimitTracker) {
+        for (client_id, withdrawn) in other.withdrawn {
+            self.withdrawn.entry(client_id).or_insert(withdrawn);
+        }
+        for (client_id, times) in other.recent {
+            self.recent.entry(client_id).or_insert(times);
+        }
+    }
+
+    /// What counts against the limits, to keep in a snapshot.
+    pub(crate) fn usage(&self) -> LimitUsage {
+        let mut withdrawn: Vec<(ClientId, u64, Decimal)> = self
+            .withdrawn
+            .iter()
+            .map(|(&client_id, &(day, amount))| (client_id, day, amount))
+            .collect();
+        withdrawn.sort_unstable_by_key(|&(client_id, _, _)| client_id);
+        let mut recent: Vec<(ClientId, Vec<u64>)> = self
+            .recent
+            .iter()
+            .map(|(&client_id, times)| (client_id, times.iter().copied().collect()))
+            .collect();
+        recent.sort_unstable_by_key(|&(client_id, _)| client_id);
+        LimitUsage { withdrawn, recent }
+    }
+
+    /// Replaces what counts against the limits with `usage` from a snapshot.
+    pub(crate) fn restore(&mut self, usage: LimitUsage) {
+        self.withdrawn = usage
+            .withdrawn
+            .into_iter()
+            .map(|(client_id, day, amount)| (client_id, (day, amount)))
+            .collect();
+        self.recent = usage
+            .recent
+            .into_iter()
+            .map(|(client_id, times)| (client_id, times.into()))
+            .collect();
+    }
+
+    fn withdrawn_on(&self, client_id: ClientId, day: u64) -> Decimal {
+        match self.withdrawn.get(&client_id) {
+            Some(&(withdrawn_day, amount)) if withdrawn_day == day => amount,
+            _ => Decimal::ZERO,
+        }
+    }
+}
+
+/// The part of a `LimitTracker` kept in snapshots, by client.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct LimitUsage {
+    /// Day and amount withdrawn that day.
+    withdrawn: Vec<(ClientId, u64, Decimal)>,
+    /// Times of the transactions in the velocity window, oldest first.
+    recent: Vec<(ClientId, Vec<u64>)>,
+}
+
+/// Whether `time` is less than `window` before `now`. Records out of order
+/// may be later than `now`.
+fn within(time: u64, now: u64, window: Duration) -> bool {
+    now.saturating_sub(time) < window.as_secs()
+}
+
+fn is_limited(record_type: TransactionType) -> bool {
+    matches!(
+        record_type,
+        TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Transfer
+            | TransactionType::Auth
+            | TransactionType::Convert
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::tests::simple;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    fn rejected(
+        tracker: &LimitTracker,
+        record_type: TransactionType,
+        amount: Decimal,
+        now: u64,
+    ) -> bool {
+        match tracker.check(&simple(record_type, 1, Some(amount)), now) {
+            Ok(()) => false,
+            Err(PaymentError::LimitExceeded(_)) => true,
+            Err(e) => panic!("Expected LimitExceeded, got {:?}", e),
+        }
+    }
+
+    #[rstest]
+    #[case(TransactionType::Deposit, dec!(100), false)]
+    #[case(TransactionType::Deposit, dec!(100.01), true)]
+    #[case(TransactionType::Withdrawal, dec!(500), true)]
+    // Exempt.
+    #[case(TransactionType::CreditAdjustment, dec!(500), false)]
+    #[case(TransactionType::Admin, dec!(500), false)]
+    fn test_limits_max_amount(
+        #[case] record_type: TransactionType,
+        #[case] amount: Decimal,
+        #[case] expected: bool,
+    ) {
+        let tracker = LimitTracker::new(Limits::new().with_max_amount(dec!(100)));
+        assert_eq!(rejected(&tracker, record_type, amount, 0), expected);
+    }
+
+    #[rstest]
+    fn test_limits_max_daily_withdrawal() {
+        let mut tracker = LimitTracker::new(Limits::new().with_max_daily_withdrawal(dec!(100)));
+        tracker.note(1, TransactionType::Withdrawal, Some(dec!(60)), 10, true);
+        // Declined withdrawals and deposits don't count.
+        tracker.note(1, TransactionType::Withdrawal, Some(dec!(30)), 20, false);
+        tracker.note(1, TransactionType::Deposit, Some(dec!(30)), 30, true);
+        assert!(!rejected(
+            &tracker,
+            TransactionType::Withdrawal,
+            dec!(40),
+            40
+        ));
+        assert!(rejected(
+            &tracker,
+            TransactionType::Withdrawal,
+            dec!(40.5),
+            40
+        ));
+        assert!(!rejected(
+            &tracker,
+            TransactionType::Deposit,
+            dec!(1000),
+            40
+        ));
+        // The next day starts over.
+        assert!(!rejected(
+            &tracker,
+            TransactionType::Withdrawal,
+            dec!(100),
+            SECONDS_PER_DAY
+        ));
+    }
+
+    #[rstest]
+    fn test_limits_max_transactions() {
+        let mut tracker =
+            LimitTracker::new(Limits::new().with_max_transactions(2, Duration::from_secs(60)));
+        tracker.note(1, TransactionType::Deposit, Some(dec!(1)), 100, true);
+        // Only transactions count.
+        tracker.note(1, TransactionType::Dispute, None, 110, true);
+        assert!(!rejected(
+            &tracker,
+            TransactionType::Withdrawal,
+            dec!(1),
+            120
+        ));
+        tracker.note(1, TransactionType::Withdrawal, Some(dec!(1)), 120, false);
+        assert!(rejected(&tracker, TransactionType::Deposit, dec!(1), 150));
+        // The first one has left the window.
+        assert!(!rejected(&tracker, TransactionType::Deposit, dec!(1), 160));
+    }
+}
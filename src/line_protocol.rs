@@ -0,0 +1,234 @@
+//! Newline-delimited protocol for long-running ingestion over a socket.
+//!
+//! Each line a client sends is a transaction in the input format, a CSV
+//! header (which sets the columns of the CSV lines after it on that
+//! connection), or a query:
+//!
+//! - `balances` lists every account;
+//! - `balance <client>` lists the accounts of one client.
+//!
+//! Transactions and headers are answered with `ok` or `error: <reason>`.
+//! Queries are answered with one account per line in the output format (JSON
+//! formats give one object per line), followed by an empty line.
+
+use crate::csv_handler;
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::input::InputFormat;
+use crate::json_handler;
+use crate::models::{Account, ClientId, InputRecord};
+use crate::output::OutputFormat;
+use crate::table;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Columns of CSV lines sent before any header.
+pub const DEFAULT_CSV_HEADER: &str = "type,client,tx,amount";
+
+/// Serves one connection until the client closes it, applying its
+/// transactions to the shared `engine`. Only I/O failures (on the connection,
+/// a store or a log) end it early.
+pub fn serve_connection<R: Read, W: Write>(
+    engine: &Mutex<PaymentEngine>,
+    input: InputFormat,
+    output: OutputFormat,
+    reader: R,
+    mut writer: W,
+) -> Result<(), PaymentError> {
+    let mut header = DEFAULT_CSV_HEADER.to_string();
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(query) = parse_query(line) {
+            match query {
+                Ok(client_id) => write_accounts(&lock(engine), client_id, output, &mut writer)?,
+                Err(e) => writeln!(writer, "error: {}", e)?,
+            }
+        } else if input == InputFormat::Csv && is_csv_header(line) {
+            header = line.to_string();
+            writeln!(writer, "ok")?;
+        } else {
+            match decode(line, input, &header).and_then(|record| lock(engine).process(record)) {
+                Ok(()) => writeln!(writer, "ok")?,
+                Err(e @ PaymentError::Io(_)) => return Err(e),
+                Err(e) => writeln!(writer, "error: {}", e)?,
+            }
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Locks an engine shared between threads, like the connections and a
+/// watched directory. A thread that panicked holding it can't have left it
+/// half-updated (records are applied whole), so the others keep using it.
+pub fn lock(engine: &Mutex<PaymentEngine>) -> MutexGuard<'_, PaymentEngine> {
+    engine.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Parses a query line into the client it asks for (`None` for all of them).
+/// Returns `None` for lines that aren't queries.
+fn parse_query(line: &str) -> Option<Result<Option<ClientId>, String>> {
+    let mut words = line.split_whitespace();
+    let command = words.next()?.to_ascii_lowercase();
+    let query = match (command.as_str(), words.next(), words.next()) {
+        ("balances", None, _) => Ok(None),
+        ("balance", Some(client), None) => client
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid client id '{}'", client)),
+        ("balance", None, _) => Err("balance requires a client id".to_string()),
+        ("balance" | "balances", _, _) => Err(format!("invalid query '{}'", line)),
+        _ => return None,
+    };
+    Some(query)
+}
+
+fn is_csv_header(line: &str) -> bool {
+    line.split(',')
+        .next()
+        .is_some_and(|column| column.trim().eq_ignore_ascii_case("type"))
+}
+
+fn decode(line: &str, format: InputFormat, header: &str) -> Result<InputRecord, PaymentError> {
+    match format {
+        InputFormat::Csv => {
+            let document = format!("{}\n{}", header, line);
+            let record = csv_handler::read_records(document.as_bytes()).next();
+            match record {
+                Some(record) => record.parsed,
+                None => Err(PaymentError::InvalidTransaction(format!(
+                    "empty record '{}'",
+                    line
+                ))),
+            }
+        }
+        InputFormat::JsonLines => Ok(serde_json::from_str(line)?),
+        // Refused when the server starts; the records of files aren't lines.
+        _ => Err(PaymentError::InvalidConfig(format!(
+            "requests can't be read as {:?}",
+            format
+        ))),
+    }
+}
+
+fn write_accounts<W: Write>(
+    engine: &PaymentEngine,
+    client_id: Option<ClientId>,
+    format: OutputFormat,
+    mut writer: W,
+) -> Result<(), PaymentError> {
+    let accounts: Vec<_> = engine
+        .iter_accounts_sorted()
+        .filter(|a| client_id.is_none_or(|id| a.client_id == id))
+        .map(Account::to_output_record)
+        .collect();
+    match format {
+        OutputFormat::Csv => {
+            let mut wtr = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut writer);
+            for account in &accounts {
+                wtr.write_record(csv_handler::account_row(account))?;
+            }
+            wtr.flush()?;
+        }
+        OutputFormat::Json | OutputFormat::JsonLines => {
+            for account in accounts {
+                serde_json::to_writer(&mut writer, &json_handler::at_output_precision(account))?;
+                writeln!(writer)?;
+            }
+        }
+        OutputFormat::Table { color } => {
+            table::write_accounts_table(&accounts, color, &mut writer)?
+        }
+        // Answers are lines, which binary files can't be written as.
+        #[cfg(any(feature = "arrow", feature = "avro"))]
+        _ => {
+            return Err(PaymentError::InvalidConfig(
+                "balances can't be answered in a binary format".to_string(),
+            ))
+        }
+    }
+    writeln!(writer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn serve(input: InputFormat, output: OutputFormat, session: &str) -> String {
+        let engine = Mutex::new(PaymentEngine::new());
+        let mut responses = Vec::new();
+        serve_connection(&engine, input, output, session.as_bytes(), &mut responses).unwrap();
+        String::from_utf8(responses).unwrap()
+    }
+
+    #[rstest]
+    fn test_serve_csv_connection() {
+        let session = "deposit,1,1,10.0\n\
+                       \n\
+                       withdrawal,1,2,20.0\n\
+                       type,client,tx,amount,currency\n\
+                       deposit,2,3,1.5,EUR\n\
+                       deposit,x,4,1.0,EUR\n\
+                       balance 1\n\
+                       balances\n\
+                       balance two\n";
+
+        assert_eq!(
+            serve(InputFormat::Csv, OutputFormat::Csv, session),
+            "ok\n\
+             ok\n\
+             ok\n\
+             ok\n\
+             error: CSV processing error: CSV deserialize error: record 1 (line: 2, byte: 31): field 1: invalid digit found in string\n\
+             1,,10.0000,0.0000,10.0000,false,false,0.0000\n\
+             \n\
+             1,,10.0000,0.0000,10.0000,false,false,0.0000\n\
+             2,EUR,1.5000,0.0000,1.5000,false,false,0.0000\n\
+             \n\
+             error: invalid client id 'two'\n"
+        );
+    }
+
+    #[rstest]
+    fn test_serve_json_connection() {
+        let session = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"2\"}\n\
+                       {\"type\":\"deposit\"}\n\
+                       BALANCES\n";
+
+        let responses = serve(InputFormat::JsonLines, OutputFormat::Json, session);
+        let lines: Vec<&str> = responses.lines().collect();
+        assert_eq!(lines[0], "ok");
+        assert!(lines[1].starts_with("error: JSON processing error: missing field"));
+        assert_eq!(
+            lines[2..],
+            [
+                r#"{"client":1,"currency":"","available":"2.0000","held":"0.0000","total":"2.0000","locked":false,"closed":false,"overdraft":"0.0000"}"#,
+                ""
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("balances", Some(Ok(None)))]
+    #[case("balance 7", Some(Ok(Some(7))))]
+    #[case("Balance  7 ", Some(Ok(Some(7))))]
+    #[case("balance", Some(Err("balance requires a client id".to_string())))]
+    #[case("balance 1 2", Some(Err("invalid query 'balance 1 2'".to_string())))]
+    #[case("balances 1", Some(Err("invalid query 'balances 1'".to_string())))]
+    #[case("deposit,1,1,1.0", None)]
+    fn test_parse_query(
+        #[case] line: &str,
+        #[case] expected: Option<Result<Option<ClientId>, String>>,
+    ) {
+        assert_eq!(parse_query(line), expected);
+    }
+}
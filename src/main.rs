@@ -1,31 +1,943 @@
 use std::env;
-use std::io;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::num::NonZeroUsize;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-mod csv_handler;
-mod engine;
-mod errors;
-mod models;
+use payment_engine::convert::convert_records;
+use payment_engine::input::RawRecord;
+use payment_engine::{
+    checkpoint, clients, diff, input, line_protocol, merge, output, pipeline, reorder, risk_report,
+    sharded, AccountMap, AccountStore, Blocklist, ClientRegistry, ClientSet, CompactTxStore,
+    DiskAccountStore, DiskTxStore, FixedWidthLayout, InputOptions, MemoryAccountStore,
+    OutputRecord, PaymentEngine, PaymentError, ProcessingReport, ResultWriter, SettlementCollector,
+    SkipKind, StaticRates, TxIdFormat, TxIdMap,
+};
+
+use exit::Failure;
+
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{EventKind, RecursiveMode, Watcher};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+
+mod cli;
+mod exit;
+
+/// Records the parse thread may decode ahead of the engine under `--threads 2`.
+const READ_AHEAD_CAPACITY: NonZeroUsize = NonZeroUsize::new(16_384).unwrap();
+
+/// Records held to be put in order under `--reorder-window`.
+const REORDER_CAPACITY: NonZeroUsize = NonZeroUsize::new(1_000_000).unwrap();
 
 fn main() {
-    // 1. Get the input file path from command-line arguments.
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <input_csv_file>", args[0]);
-        process::exit(1);
+    // 1. Parse the command-line arguments; help, version and usage errors exit here.
+    let args = cli::parse_args(env::args().skip(1)).unwrap_or_else(|e| {
+        // Printing can only fail if stdout or stderr is closed.
+        let _ = e.print();
+        if e.use_stderr() {
+            Failure::Usage.exit();
+        }
+        process::exit(0)
+    });
+
+    init_logging(args.verbosity);
+
+    // With `generate`, write a synthetic input instead of processing any.
+    if let Some(config) = &args.generate {
+        let result = match &args.output {
+            Some(path) => File::create(path)
+                .map_err(PaymentError::from)
+                .and_then(|file| config.generate(BufWriter::new(file))),
+            None => config.generate(io::stdout().lock()),
+        };
+        if let Err(e) = result {
+            eprintln!("Error generating transactions: {}", e);
+            Failure::Write.exit();
+        }
+        return;
     }
-    let input_path = &args[1];
 
-    // 2. Process the transactions.
-    let mut engine = engine::PaymentEngine::new();
-    if let Err(e) = csv_handler::process_transactions(input_path, &mut engine) {
-        eprintln!("Error processing transactions: {}", e);
-        process::exit(1);
+    // With `diff`, compare two account files instead of processing any.
+    if let Some((expected, actual)) = &args.diff {
+        diff_accounts(expected, actual, args.output.as_deref());
+        return;
     }
 
-    // 3. Write the final account states to stdout.
-    if let Err(e) = csv_handler::write_accounts(&engine, io::stdout()) {
+    // 2. Process the transactions of every input in order ("-" reads from stdin).
+    let started = Instant::now();
+    let accounts = args
+        .accounts
+        .as_ref()
+        .map(AccountMap::load)
+        .transpose()
+        .unwrap_or_else(|e| {
+            eprintln!("Error reading accounts: {}", e);
+            Failure::of(&e).exit();
+        })
+        .unwrap_or_default();
+    let layout = args
+        .layout
+        .as_ref()
+        .map(FixedWidthLayout::load)
+        .transpose()
+        .unwrap_or_else(|e| {
+            eprintln!("Error reading layout: {}", e);
+            Failure::of(&e).exit();
+        });
+    let expected = args
+        .verify
+        .as_ref()
+        .map(diff::read_accounts)
+        .transpose()
+        .unwrap_or_else(|e| {
+            eprintln!("Error reading expected accounts: {}", e);
+            Failure::of(&e).exit();
+        });
+    let options = InputOptions {
+        tx_ids: TxIdMap::new(args.tx_id_format),
+        accounts,
+        csv: args.csv.clone(),
+        layout,
+        skip_rows: Default::default(),
+    };
+
+    // With `convert`, rewrite the decoded records instead of processing them.
+    if let Some(to) = args.convert {
+        let result = open_inputs(&args.inputs).and_then(|readers| {
+            let records = decode_inputs(readers, InputOrdering::of(&args), options);
+            match &args.output {
+                Some(path) => {
+                    let file = BufWriter::new(create_output(path, "output"));
+                    convert_records(records, to, args.error_policy, file)
+                }
+                None => convert_records(
+                    records,
+                    to,
+                    args.error_policy,
+                    BufWriter::new(io::stdout().lock()),
+                ),
+            }
+        });
+        match result {
+            Ok(report) => write_rejects(&report, &args),
+            Err(e) => {
+                eprintln!("Error converting records: {}", e);
+                Failure::of(&e).exit();
+            }
+        }
+        return;
+    }
+
+    let settlement = args.settlement.as_ref().map(|_| SettlementCollector::new());
+    let result = open_inputs(&args.inputs)
+        .and_then(|readers| run(readers, &args, &options, settlement.as_ref()))
+        .and_then(|(mut engine, report)| {
+            let expired = engine.expire_disputes()?;
+            engine.flush_audit_log()?;
+            // One pass over the final state, so a bug surfaces as its own
+            // failure rather than as wrong balances.
+            if args.check_invariants {
+                engine.check_invariants()?;
+            }
+            if args.reconcile {
+                reconcile(&engine)?;
+            }
+            Ok((engine, report, expired))
+        });
+    let (mut engine, report) = match result {
+        Ok((engine, report, expired)) => {
+            write_rejects(&report, &args);
+            if let Some(path) = &args.expired_disputes {
+                if let Err(e) = output::write_report_file(&expired, path) {
+                    eprintln!("Error writing expired disputes: {}", e);
+                    Failure::Write.exit();
+                }
+            }
+            if let (Some(path), Some(settlement)) = (&args.settlement, &settlement) {
+                if let Err(e) = output::write_report_file(&settlement.report(), path) {
+                    eprintln!("Error writing settlement report: {}", e);
+                    Failure::Write.exit();
+                }
+            }
+            if let Some(path) = &args.risk_report {
+                if let Err(e) = risk_report::risk_report(&engine)
+                    .and_then(|items| output::write_report_file(&items, path))
+                {
+                    eprintln!("Error writing risk report: {}", e);
+                    Failure::Write.exit();
+                }
+            }
+            if args.stats {
+                print_stats(&engine, &report, started.elapsed());
+            }
+            if args.validate {
+                eprintln!(
+                    "Validated {} records: {} invalid, {} rejected, {} conflicting",
+                    report.records_read,
+                    report.count(SkipKind::Decode),
+                    report.count(SkipKind::Rejected)
+                        + report.count(SkipKind::Limit)
+                        + report.count(SkipKind::Blocked),
+                    report.count(SkipKind::Conflict)
+                );
+            }
+            (engine, report)
+        }
+        Err(e) => {
+            eprintln!("Error processing transactions: {}", e);
+            Failure::of(&e).exit();
+        }
+    };
+
+    // 3. With --watch or --listen, keep the engine live until the process is
+    //    killed: new files in the watched directory are processed as they
+    //    appear, and connections are served alongside.
+    if args.watch.is_some() || args.listen.is_some() {
+        let engine = &Arc::new(Mutex::new(engine));
+        let (args, options) = (&args, &options);
+        thread::scope(|scope| {
+            if let Some(dir) = &args.watch {
+                scope.spawn(move || {
+                    if let Err(e) = watch(engine, Path::new(dir), args, options) {
+                        eprintln!("Error watching {}: {}", dir, e);
+                        Failure::Other.exit();
+                    }
+                });
+            }
+            if let Some(addr) = &args.listen {
+                if let Err(e) = listen(engine, addr, args) {
+                    eprintln!("Error listening: {}", e);
+                    Failure::Other.exit();
+                }
+            }
+        });
+        return;
+    }
+
+    // 4. Write the final account states (or the requested client statement) to
+    //    the output file, or stdout by default.
+    if let Some(clients) = &args.clients {
+        // Transfers to other clients and fees leave accounts of theirs behind.
+        engine.retain_clients(|client_id| clients.contains(client_id));
+    }
+    if args.skip_empty {
+        engine.retain_accounts(|account| !account.is_empty());
+    }
+    if let Err(e) = write_accounts(&engine, &args) {
         eprintln!("Error writing accounts: {}", e);
-        process::exit(1);
+        Failure::Write.exit();
+    }
+    // The accounts are out, so a rerun starts over rather than resuming.
+    if let Some(path) = &args.checkpoint {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                eprintln!("Error removing checkpoint: {}", e);
+                Failure::Write.exit();
+            }
+            _ => {}
+        }
+    }
+
+    // 5. With --verify, the run fails unless the accounts are the expected ones.
+    if let (Some(path), Some(expected)) = (&args.verify, &expected) {
+        verify_accounts(path, expected, &engine);
+    }
+
+    // 6. A validation run fails if any record would be skipped.
+    if args.validate && !report.skipped.is_empty() {
+        Failure::InvalidRecord.exit();
+    }
+}
+
+/// Writes the skipped records to `--rejects`, if given.
+fn write_rejects(report: &ProcessingReport, args: &cli::Args) {
+    if let Some(path) = &args.rejects {
+        if let Err(e) = File::create(path)
+            .map_err(PaymentError::from)
+            .and_then(|file| report.write_rejects(BufWriter::new(file)))
+        {
+            eprintln!("Error writing rejects: {}", e);
+            Failure::Write.exit();
+        }
+    }
+}
+
+/// Writes the accounts (or the requested client statement) to `--output`, or
+/// stdout by default.
+fn write_accounts(engine: &PaymentEngine, args: &cli::Args) -> Result<(), PaymentError> {
+    match (args.statement, &args.output) {
+        (Some(client_id), output) => {
+            let entries = engine.statement(client_id).unwrap_or_default();
+            match output {
+                Some(path) => output::write_statement_file(entries, args.output_format, path),
+                None => output::write_statement(entries, args.output_format, io::stdout()),
+            }
+        }
+        (None, Some(path)) => output::write_output_file(engine, args.output_format, path),
+        (None, None) => output::write_output(engine, args.output_format, io::stdout()),
+    }
+}
+
+/// Sends log events to stderr. `-v`/`-q` pick the default level; `RUST_LOG`
+/// can still refine it per module (e.g.
+/// `RUST_LOG=payment_engine::engine=trace`).
+fn init_logging(verbosity: i8) {
+    let level = match verbosity {
+        i8::MIN..=-1 => LevelFilter::ERROR,
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .without_time()
+        .with_target(false)
+        .init();
+}
+
+/// Fails with an invariant violation naming every currency whose books don't
+/// balance (`--reconcile`).
+fn reconcile(engine: &PaymentEngine) -> Result<(), PaymentError> {
+    let differences: Vec<String> = engine
+        .reconcile()
+        .differences()
+        .map(ToString::to_string)
+        .collect();
+    if differences.is_empty() {
+        return Ok(());
+    }
+    Err(PaymentError::InvariantViolation(format!(
+        "books don't balance: {}",
+        differences.join("; ")
+    )))
+}
+
+/// Writes the differences between the expected and final accounts to stderr,
+/// exiting with `Failure::Mismatch` if there are any.
+fn verify_accounts(path: &str, expected: &[OutputRecord], engine: &PaymentEngine) {
+    let diffs = diff::diff_accounts(expected, &engine.get_accounts()).unwrap_or_else(|e| {
+        eprintln!("Error verifying accounts: {}", e);
+        Failure::of(&e).exit();
+    });
+    if diffs.is_empty() {
+        return;
+    }
+    eprintln!("{} differences from {}:", diffs.len(), path);
+    if let Err(e) = diff::write_diff_csv(&diffs, io::stderr().lock()) {
+        eprintln!("Error writing differences: {}", e);
+        Failure::Write.exit();
+    }
+    Failure::Mismatch.exit();
+}
+
+/// Writes the differences between the expected and actual account files to
+/// `output` (or stdout), exiting with `Failure::Mismatch` if there are any.
+fn diff_accounts(expected: &str, actual: &str, output: Option<&str>) {
+    let diffs = diff::read_accounts(expected)
+        .and_then(|expected| diff::diff_accounts(&expected, &diff::read_accounts(actual)?))
+        .unwrap_or_else(|e| {
+            eprintln!("Error reading accounts: {}", e);
+            Failure::of(&e).exit();
+        });
+    let result = match output {
+        Some(path) => File::create(path)
+            .map_err(PaymentError::from)
+            .and_then(|file| diff::write_diff_csv(&diffs, BufWriter::new(file))),
+        None => diff::write_diff_csv(&diffs, io::stdout().lock()),
+    };
+    if let Err(e) = result {
+        eprintln!("Error writing differences: {}", e);
+        Failure::Write.exit();
+    }
+    if !diffs.is_empty() {
+        eprintln!(
+            "{} differences between {} and {}",
+            diffs.len(),
+            expected,
+            actual
+        );
+        Failure::Mismatch.exit();
+    }
+}
+
+/// Prints a summary of the run to stderr, keeping stdout for the accounts.
+fn print_stats(engine: &PaymentEngine, report: &ProcessingReport, elapsed: Duration) {
+    let stats = engine.stats();
+    let secs = elapsed.as_secs_f64();
+    let throughput = if secs > 0.0 {
+        report.records_read as f64 / secs
+    } else {
+        0.0
+    };
+    eprintln!(
+        "Records read: {} ({} skipped)",
+        report.records_read,
+        report.skipped.len()
+    );
+    eprintln!(
+        "Deposits: {}, withdrawals: {}, disputes: {}, resolves: {}, chargebacks: {}, transfers: {}, refunds: {}, auths: {}, captures: {}, voids: {}, closes: {}, admins: {}, converts: {}, adjustments: {}",
+        stats.deposits,
+        stats.withdrawals,
+        stats.disputes,
+        stats.resolves,
+        stats.chargebacks,
+        stats.transfers,
+        stats.refunds,
+        stats.auths,
+        stats.captures,
+        stats.voids,
+        stats.closes,
+        stats.admins,
+        stats.converts,
+        stats.adjustments
+    );
+    eprintln!(
+        "Accounts: {} ({} locked)",
+        stats.accounts, stats.locked_accounts
+    );
+    eprintln!("Elapsed: {:.3}s ({:.0} records/s)", secs, throughput);
+}
+
+/// Opens every input up front so a missing file fails before any processing.
+fn open_inputs(paths: &[String]) -> Result<Vec<Box<dyn Read + Send>>, PaymentError> {
+    paths
+        .iter()
+        .map(|path| -> Result<Box<dyn Read + Send>, PaymentError> {
+            if path == "-" {
+                Ok(Box::new(io::stdin()))
+            } else {
+                open_file(path)
+            }
+        })
+        .collect()
+}
+
+/// Memory-maps regular files; pipes and devices are read as usual.
+#[cfg(feature = "mmap")]
+fn open_file(path: &str) -> Result<Box<dyn Read + Send>, PaymentError> {
+    if fs::metadata(path)?.is_file() {
+        Ok(Box::new(payment_engine::mmap::map_file(path)?))
+    } else {
+        Ok(Box::new(File::open(path)?))
+    }
+}
+
+#[cfg(not(feature = "mmap"))]
+fn open_file(path: &str) -> Result<Box<dyn Read + Send>, PaymentError> {
+    Ok(Box::new(File::open(path)?))
+}
+
+/// Feeds the inputs through a single engine, or through client shards when
+/// requested, decoding them according to `options`. With `--threads 2` they're
+/// decoded on a parse thread running ahead of the engine. With `--checkpoint`
+/// the engine is checkpointed as it goes, and resumed from the checkpoint
+/// left by an interrupted run. The movements of the records are summed up by
+/// `settlement`, if given.
+fn run(
+    readers: Vec<Box<dyn Read + Send>>,
+    args: &cli::Args,
+    options: &InputOptions,
+    settlement: Option<&SettlementCollector>,
+) -> Result<(PaymentEngine, ProcessingReport), PaymentError> {
+    let blocklist = args
+        .blocklist
+        .as_ref()
+        .map(|path| Blocklist::load(path, &options.accounts))
+        .transpose()?;
+    let clients = args
+        .clients_file
+        .as_ref()
+        .map(ClientRegistry::load)
+        .transpose()?;
+    let (ordering, options) = (InputOrdering::of(args), options.clone());
+    // CSV rows a checkpoint covers are passed over unread, unless they have
+    // to be decoded to map their tx ids, or to be put in order or selected.
+    let covered = match &args.checkpoint {
+        Some(path)
+            if args.input_format == input::InputFormat::Csv
+                && args.tx_id_format == TxIdFormat::Numeric
+                && !args.merge_by_timestamp
+                && args.reorder_window.is_none()
+                && args.clients.is_none() =>
+        {
+            checkpoint::records_covered(Path::new(path))?
+        }
+        _ => 0,
+    };
+    options.skip_rows.store(covered, Ordering::Relaxed);
+    let skip_rows = Arc::clone(&options.skip_rows);
+    let decode = move || decode_inputs(readers, ordering, options);
+    let records: Box<dyn Iterator<Item = RawRecord>> = if args.threads.get() > 1 {
+        Box::new(pipeline::read_ahead(decode, READ_AHEAD_CAPACITY))
+    } else {
+        Box::new(decode())
+    };
+    let rates = args.rates.as_ref().map(StaticRates::load).transpose()?;
+    if args.shards.get() > 1 {
+        // Called once per shard, then once more for the merged engine.
+        let shard_ids = AtomicUsize::new(0);
+        return sharded::process_sharded(records, args.shards, args.error_policy, || {
+            let shard = shard_ids.fetch_add(1, Ordering::Relaxed);
+            let engine = build_engine(
+                args,
+                rates.as_ref(),
+                blocklist.as_ref(),
+                clients.as_ref(),
+                &format!("shard{}-", shard),
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("Error creating transaction store: {}", e);
+                Failure::Other.exit();
+            });
+            match settlement {
+                Some(settlement) => engine.with_event_listener(settlement.clone()),
+                None => engine,
+            }
+        });
+    }
+
+    let mut engine = build_engine(
+        args,
+        rates.as_ref(),
+        blocklist.as_ref(),
+        clients.as_ref(),
+        "",
+    )?;
+    if args.validate {
+        engine = load_state(engine, args)?;
+    } else {
+        if let Some(path) = &args.audit_log {
+            engine = engine.with_audit_log(BufWriter::new(create_output(path, "audit log")));
+        }
+        if let Some(path) = &args.account_store {
+            engine = engine.with_account_store(DiskAccountStore::open(path)?)?;
+        }
+        if let Some(path) = &args.wal {
+            engine = attach_wal(engine, path)?;
+        }
+    }
+    // Records replayed from the log were settled by the run that applied them.
+    if let Some(settlement) = settlement {
+        engine = engine.with_event_listener(settlement.clone());
     }
+    let report = match (&args.results, &args.checkpoint) {
+        (Some(path), _) => {
+            let mut results = ResultWriter::new(BufWriter::new(create_output(path, "results")));
+            let report = input::process_records_with_results(
+                records,
+                &mut engine,
+                args.error_policy,
+                &mut results,
+            )?;
+            results.flush()?;
+            report
+        }
+        (None, Some(path)) if covered > 0 => {
+            let report = checkpoint::process_records_after_checkpoint(
+                records,
+                &mut engine,
+                args.error_policy,
+                Path::new(path),
+                args.checkpoint_every,
+            )?;
+            let missing = skip_rows.load(Ordering::Relaxed);
+            if missing > 0 {
+                return Err(PaymentError::InvalidSnapshot(format!(
+                    "the checkpoint covers {} records but the inputs have {}",
+                    covered,
+                    covered - missing
+                )));
+            }
+            report
+        }
+        (None, Some(path)) => checkpoint::process_records_with_checkpoints(
+            records,
+            &mut engine,
+            args.error_policy,
+            Path::new(path),
+            args.checkpoint_every,
+        )?,
+        (None, None) => {
+            input::process_records_with_policy(records, &mut engine, args.error_policy)?
+        }
+    };
+    engine.flush_audit_log()?;
+    Ok((engine, report))
+}
+
+/// How the records of the inputs are decoded, put in order and selected.
+#[derive(Debug, Clone)]
+struct InputOrdering {
+    format: input::InputFormat,
+    merge_by_timestamp: bool,
+    reorder_window: Option<u64>,
+    clients: Option<ClientSet>,
+}
+
+impl InputOrdering {
+    fn of(args: &cli::Args) -> Self {
+        Self {
+            format: args.input_format,
+            merge_by_timestamp: args.merge_by_timestamp,
+            reorder_window: args.reorder_window,
+            clients: args.clients.clone(),
+        }
+    }
+}
+
+/// Decodes the inputs one after the other, or merged by timestamp with
+/// `--merge-by-timestamp`, then reordered with `--reorder-window`, keeping
+/// the records of the `--clients` only.
+fn decode_inputs(
+    readers: Vec<Box<dyn Read + Send>>,
+    ordering: InputOrdering,
+    options: InputOptions,
+) -> Box<dyn Iterator<Item = RawRecord>> {
+    let format = ordering.format;
+    let sources = readers
+        .into_iter()
+        .map(move |reader| input::read_records_with_options(reader, format, &options));
+    let records: Box<dyn Iterator<Item = RawRecord>> = if ordering.merge_by_timestamp {
+        Box::new(merge::merge_by_timestamp(sources))
+    } else {
+        Box::new(sources.flatten())
+    };
+    let records: Box<dyn Iterator<Item = RawRecord>> = match ordering.reorder_window {
+        Some(window) => Box::new(reorder::reorder_by_timestamp(
+            records,
+            window,
+            REORDER_CAPACITY,
+        )),
+        None => records,
+    };
+    match ordering.clients {
+        Some(clients) => Box::new(clients::select_clients(records, clients)),
+        None => records,
+    }
+}
+
+/// Serves the line protocol on `addr` (`unix:<path>` for a Unix socket, a TCP
+/// `host:port` otherwise), one thread per connection sharing `engine`.
+fn listen(
+    engine: &Arc<Mutex<PaymentEngine>>,
+    addr: &str,
+    args: &cli::Args,
+) -> Result<(), PaymentError> {
+    #[cfg(unix)]
+    if let Some(path) = addr.strip_prefix("unix:") {
+        let listener = UnixListener::bind(path)?;
+        eprintln!("Listening on unix:{}", path);
+        accept(engine, args, listener.incoming(), UnixStream::try_clone);
+        return Ok(());
+    }
+
+    let listener = TcpListener::bind(addr)?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+    accept(engine, args, listener.incoming(), TcpStream::try_clone);
+    Ok(())
+}
+
+/// Serves every connection `incoming` accepts, reading from a clone of its
+/// stream made with `try_clone` and answering on the stream itself.
+fn accept<S>(
+    engine: &Arc<Mutex<PaymentEngine>>,
+    args: &cli::Args,
+    incoming: impl Iterator<Item = io::Result<S>>,
+    try_clone: fn(&S) -> io::Result<S>,
+) where
+    S: Read + Write + Send + 'static,
+{
+    for stream in incoming {
+        match stream.and_then(|stream| Ok((try_clone(&stream)?, stream))) {
+            Ok((reader, writer)) => spawn_connection(engine, args, reader, writer),
+            Err(e) => tracing::warn!("Failed to accept connection: {}", e),
+        }
+    }
+}
+
+/// Serves one connection on its own thread, flushing the audit log once it
+/// closes so its mutations don't wait in the buffer for the next connection.
+fn spawn_connection<R, W>(
+    engine: &Arc<Mutex<PaymentEngine>>,
+    args: &cli::Args,
+    reader: R,
+    writer: W,
+) where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let engine = Arc::clone(engine);
+    let (input_format, output_format) = (args.input_format, args.output_format);
+    thread::spawn(move || {
+        let result = line_protocol::serve_connection(
+            &engine,
+            input_format,
+            output_format,
+            reader,
+            BufWriter::new(writer),
+        )
+        .and_then(|()| line_protocol::lock(&engine).flush_audit_log());
+        if let Err(e) = result {
+            tracing::warn!("Connection closed: {}", e);
+        }
+    });
+}
+
+/// Processes the files in `dir`, then every file that appears in it, through
+/// the shared engine, writing the accounts after each one and archiving it to
+/// the `processed/` subdirectory, or to `rejected/` if it fails. Files are
+/// picked up once they're closed after writing or moved into the directory;
+/// hidden files are ignored.
+fn watch(
+    engine: &Mutex<PaymentEngine>,
+    dir: &Path,
+    args: &cli::Args,
+    options: &InputOptions,
+) -> Result<(), PaymentError> {
+    let (archive, rejected) = (dir.join("processed"), dir.join("rejected"));
+    fs::create_dir_all(&archive)?;
+    fs::create_dir_all(&rejected)?;
+    let (events, received) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(events).map_err(io::Error::other)?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(io::Error::other)?;
+    eprintln!("Watching {}", dir.display());
+
+    // Files dropped before the watch started. Any event they raise later finds
+    // them already archived.
+    let mut existing = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    existing.sort();
+    for path in existing {
+        process_dropped_file(engine, &path, &archive, &rejected, args, options);
+    }
+
+    for event in received {
+        match event {
+            Ok(event) if is_file_complete(event.kind) => {
+                for path in event.paths {
+                    process_dropped_file(engine, &path, &archive, &rejected, args, options);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Error watching {}: {}", dir.display(), e),
+        }
+    }
+    Ok(())
+}
+
+/// Whether `kind` means a file is fully written: closed after writing, or
+/// renamed into place.
+fn is_file_complete(kind: EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Access(AccessKind::Close(AccessMode::Write))
+            | EventKind::Modify(ModifyKind::Name(
+                RenameMode::To | RenameMode::Both | RenameMode::Any
+            ))
+    )
+}
+
+/// Processes a file dropped into the watched directory. A file that fails (an
+/// unreadable one, or a bad record with `--strict`) is moved to `rejected`
+/// instead of `archive`, so it isn't applied again, next to a `.error` file
+/// saying how far it got: the records before the failing one were applied.
+fn process_dropped_file(
+    engine: &Mutex<PaymentEngine>,
+    path: &Path,
+    archive: &Path,
+    rejected: &Path,
+    args: &cli::Args,
+    options: &InputOptions,
+) {
+    let hidden = path
+        .file_name()
+        .is_none_or(|name| name.to_string_lossy().starts_with('.'));
+    if hidden || !path.is_file() {
+        return;
+    }
+
+    let mut engine = line_protocol::lock(engine);
+    let mut read = 0;
+    let processed = File::open(path)
+        .map_err(PaymentError::from)
+        .and_then(|file| {
+            let records = input::read_records_with_options(file, args.input_format, options)
+                .inspect(|_| read += 1);
+            input::process_records_with_policy(records, &mut engine, args.error_policy)
+        });
+    let report = match processed {
+        Ok(report) => report,
+        Err(e) => {
+            tracing::error!("Error processing {}: {}", path.display(), e);
+            let progress = if read == 0 {
+                format!("not processed: {}\n", e)
+            } else {
+                format!(
+                    "stopped at record {}, the records before it were applied: {}\n",
+                    read, e
+                )
+            };
+            match archive_file(path, rejected).and_then(|target| {
+                fs::write(format!("{}.error", target.display()), progress)?;
+                Ok(target)
+            }) {
+                Ok(target) => tracing::error!("Moved {} to {}", path.display(), target.display()),
+                Err(e) => tracing::error!("Error rejecting {}: {}", path.display(), e),
+            }
+            return;
+        }
+    };
+
+    // The records are applied: archive the file even if the accounts can't be
+    // written, so it isn't applied twice.
+    if let Err(e) = engine
+        .flush_audit_log()
+        .and_then(|()| write_accounts(&engine, args))
+    {
+        tracing::error!("Error writing accounts after {}: {}", path.display(), e);
+    }
+    match archive_file(path, archive) {
+        Ok(archived) => tracing::info!(
+            "Processed {} ({} records, {} skipped), archived to {}",
+            path.display(),
+            report.records_read,
+            report.skipped.len(),
+            archived.display()
+        ),
+        Err(e) => tracing::error!("Error archiving {}: {}", path.display(), e),
+    }
+}
+
+/// Moves `path` into `archive`, numbering it if a file of the same name was
+/// archived before.
+fn archive_file(path: &Path, archive: &Path) -> io::Result<PathBuf> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut target = archive.join(name.as_ref());
+    let mut n = 1;
+    while target.exists() {
+        target = archive.join(format!("{}.{}", name, n));
+        n += 1;
+    }
+    fs::rename(path, &target)?;
+    Ok(target)
+}
+
+/// Creates the output file at `path`, named `name` in the error.
+fn create_output(path: &str, name: &str) -> File {
+    open_output(
+        path,
+        OpenOptions::new().write(true).create(true).truncate(true),
+        name,
+    )
+}
+
+/// Opens the output file at `path` with `options`, exiting with
+/// `Failure::Write` if it can't be: a missing directory on the way to an
+/// output isn't a missing input.
+fn open_output(path: &str, options: &OpenOptions, name: &str) -> File {
+    options.open(path).unwrap_or_else(|e| {
+        eprintln!("Error opening {} {}: {}", name, path, e);
+        Failure::Write.exit();
+    })
+}
+
+/// Rebuilds `engine` from the write-ahead log at `path` (created if missing)
+/// and has it append every record it applies from now on. A torn last entry is
+/// cut off first so the next one starts on its own line.
+fn attach_wal(mut engine: PaymentEngine, path: &str) -> Result<PaymentEngine, PaymentError> {
+    let mut file = open_output(
+        path,
+        OpenOptions::new().read(true).append(true).create(true),
+        "write-ahead log",
+    );
+    let mut log = Vec::new();
+    file.read_to_end(&mut log)?;
+    let replayed = engine.replay(log.as_slice())?;
+    tracing::info!("Replayed {} records from {}", replayed, path);
+    let complete = log.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    file.set_len(complete as u64)?;
+    Ok(engine.with_write_ahead_log(file))
+}
+
+/// Starts a `validate` run from the state the real run would start from,
+/// without attaching anything that would write it: the accounts of
+/// `--account-store` are copied into memory and `--wal` is only replayed.
+fn load_state(mut engine: PaymentEngine, args: &cli::Args) -> Result<PaymentEngine, PaymentError> {
+    if let Some(path) = args
+        .account_store
+        .as_ref()
+        .filter(|p| Path::new(p).exists())
+    {
+        let mut accounts = MemoryAccountStore::new();
+        for account in DiskAccountStore::open(path)?.accounts()? {
+            accounts.upsert(&account)?;
+        }
+        engine = engine.with_account_store(accounts)?;
+    }
+    if let Some(path) = args.wal.as_ref().filter(|p| Path::new(p).exists()) {
+        let replayed = engine.replay(BufReader::new(File::open(path)?))?;
+        tracing::info!("Replayed {} records from {}", replayed, path);
+    }
+    Ok(engine)
+}
+
+/// Creates an engine configured from `args` (and the `--rates` table,
+/// `--blocklist` and `--clients-file`, loaded once), backed by on-disk
+/// transaction stores when `--tx-store-dir` is set (outside `validate` runs)
+/// or compact in-memory ones with `--compact-tx-store`, and keeping statement
+/// history for the `statement` subcommand.
+fn build_engine(
+    args: &cli::Args,
+    rates: Option<&StaticRates>,
+    blocklist: Option<&Blocklist>,
+    clients: Option<&ClientRegistry>,
+    file_prefix: &str,
+) -> Result<PaymentEngine, PaymentError> {
+    let mut engine = PaymentEngine::new().with_config(&args.engine);
+    if let Some(rates) = rates {
+        engine = engine.with_rate_provider(rates.clone());
+    }
+    if let Some(blocklist) = blocklist {
+        engine = engine.with_blocklist(blocklist.clone());
+    }
+    if let Some(clients) = clients {
+        engine = engine.with_client_registry(clients.clone());
+    }
+    if args.statement.is_some() {
+        engine = engine.with_statement_history();
+    }
+    if args.compact_tx_store {
+        engine = engine
+            .with_tx_store(CompactTxStore::new())
+            .with_counter_leg_store(CompactTxStore::new());
+    }
+    let Some(dir) = args.tx_store_dir.as_ref().filter(|_| !args.validate) else {
+        return Ok(engine);
+    };
+
+    let dir = Path::new(dir);
+    fs::create_dir_all(dir)?;
+    Ok(engine
+        .with_tx_store(DiskTxStore::create(
+            dir.join(format!("{}transactions.idx", file_prefix)),
+        )?)
+        .with_counter_leg_store(DiskTxStore::create(
+            dir.join(format!("{}counter_legs.idx", file_prefix)),
+        )?))
 }
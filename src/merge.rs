@@ -0,0 +1,146 @@
+//! Chronological merging of several inputs, so records from files or
+//! partitions written in parallel are applied in the order they happened
+//! rather than input by input, which decides dispute outcomes.
+
+use crate::input::RawRecord;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Merges `sources` by record timestamp, each assumed to be in order already.
+/// Records with the same timestamp come in source order, and those without
+/// one (or that don't decode) keep their place behind the record before them
+/// in their source.
+pub fn merge_by_timestamp<I>(sources: impl IntoIterator<Item = I>) -> MergeByTimestamp<I>
+where
+    I: Iterator<Item = RawRecord>,
+{
+    let mut merge = MergeByTimestamp {
+        sources: Vec::new(),
+        heads: BinaryHeap::new(),
+    };
+    for records in sources {
+        merge.sources.push(Source {
+            records,
+            head: None,
+            timestamp: 0,
+        });
+        merge.advance(merge.sources.len() - 1);
+    }
+    merge
+}
+
+/// The records of several sources in timestamp order (see
+/// [`merge_by_timestamp`]).
+pub struct MergeByTimestamp<I> {
+    sources: Vec<Source<I>>,
+    /// The timestamp of the next record of each source that has one left.
+    heads: BinaryHeap<Reverse<(u64, usize)>>,
+}
+
+struct Source<I> {
+    records: I,
+    head: Option<RawRecord>,
+    /// The timestamp the head is ordered by.
+    timestamp: u64,
+}
+
+impl<I: Iterator<Item = RawRecord>> MergeByTimestamp<I> {
+    /// Reads the next record of source `index` into its head.
+    fn advance(&mut self, index: usize) {
+        let source = &mut self.sources[index];
+        source.head = source.records.next();
+        if let Some(head) = &source.head {
+            if let Some(timestamp) = head.parsed.as_ref().ok().and_then(|r| r.timestamp) {
+                source.timestamp = timestamp;
+            }
+            self.heads.push(Reverse((source.timestamp, index)));
+        }
+    }
+}
+
+impl<I: Iterator<Item = RawRecord>> Iterator for MergeByTimestamp<I> {
+    type Item = RawRecord;
+
+    fn next(&mut self) -> Option<RawRecord> {
+        let Reverse((_, index)) = self.heads.pop()?;
+        let record = self.sources[index].head.take();
+        self.advance(index);
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_handler::read_records;
+    use crate::engine::PaymentEngine;
+    use crate::input::process_records;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    fn source(csv: &'static str) -> impl Iterator<Item = RawRecord> {
+        read_records(csv.as_bytes())
+    }
+
+    fn tx_ids(records: impl Iterator<Item = RawRecord>) -> Vec<u64> {
+        records
+            .map(|record| record.parsed.map_or(0, |r| u64::from(r.tx_id)))
+            .collect()
+    }
+
+    #[rstest]
+    fn test_merge_by_timestamp() {
+        let first = source(
+            "type,client,tx,amount,timestamp\n\
+             deposit,1,1,5,100\n\
+             deposit,1,2,5,300\n\
+             withdrawal,1,3,1,\n\
+             deposit,1,4,5,500\n",
+        );
+        let second = source(
+            "type,client,tx,amount,timestamp\n\
+             deposit,2,10,5,50\n\
+             deposit,2,11,5,300\n\
+             payout,2,12,1,\n\
+             deposit,2,13,5,400\n",
+        );
+        // Ties go to the first input; undated and undecodable records follow
+        // the record before them.
+        assert_eq!(
+            tx_ids(merge_by_timestamp([first, second])),
+            [10, 1, 2, 3, 11, 0, 13, 4]
+        );
+    }
+
+    #[rstest]
+    fn test_merge_keeps_lines() {
+        let merged: Vec<u64> = merge_by_timestamp([
+            source("type,client,tx,amount,timestamp\ndeposit,1,1,5,20\n"),
+            source("type,client,tx,amount,timestamp\ndeposit,1,2,5,10\n"),
+        ])
+        .map(|record| record.line)
+        .collect();
+        assert_eq!(merged, [2, 2]);
+        assert_eq!(
+            merge_by_timestamp(Vec::<std::vec::IntoIter<RawRecord>>::new()).count(),
+            0
+        );
+    }
+
+    #[rstest]
+    fn test_merge_decides_disputes() {
+        // The chargeback arrives in another file, but happened after the
+        // deposit was disputed.
+        let deposits = source(
+            "type,client,tx,amount,timestamp\n\
+             deposit,1,1,10,100\n\
+             dispute,1,1,,200\n",
+        );
+        let chargebacks = source("type,client,tx,amount,timestamp\nchargeback,1,1,,300\n");
+        let mut engine = PaymentEngine::new();
+        process_records(merge_by_timestamp([chargebacks, deposits]), &mut engine).unwrap();
+        let account = engine.get_account(1, Default::default()).unwrap();
+        assert!(account.locked);
+        assert_eq!(account.total, dec!(0));
+    }
+}
@@ -0,0 +1,99 @@
+//! Instrumentation through the `metrics` facade, enabled by the `metrics`
+//! feature. Install any `metrics` recorder (e.g. a Prometheus exporter) in
+//! the embedding service to collect these.
+
+use crate::models::TransactionType;
+use std::time::Duration;
+
+/// Records handed to the engine, labelled by `type`.
+pub const TRANSACTIONS_PROCESSED: &str = "payment_engine_transactions_processed_total";
+/// Records the engine rejected with an error, labelled by `type`.
+pub const TRANSACTIONS_FAILED: &str = "payment_engine_transactions_failed_total";
+/// Time spent applying a single record, in seconds.
+pub const PROCESSING_LATENCY: &str = "payment_engine_processing_latency_seconds";
+
+fn type_label(record_type: TransactionType) -> &'static str {
+    match record_type {
+        TransactionType::Deposit => "deposit",
+        TransactionType::Withdrawal => "withdrawal",
+        TransactionType::Dispute => "dispute",
+        TransactionType::Resolve => "resolve",
+        TransactionType::Chargeback => "chargeback",
+        TransactionType::Transfer => "transfer",
+        TransactionType::Refund => "refund",
+        TransactionType::Auth => "auth",
+        TransactionType::Capture => "capture",
+        TransactionType::Void => "void",
+        TransactionType::Close => "close",
+        TransactionType::Admin => "admin",
+        TransactionType::Convert => "convert",
+        TransactionType::CreditAdjustment => "credit_adjustment",
+        TransactionType::DebitAdjustment => "debit_adjustment",
+    }
+}
+
+pub(crate) fn record(record_type: TransactionType, ok: bool, latency: Duration) {
+    let label = type_label(record_type);
+    metrics::counter!(TRANSACTIONS_PROCESSED, "type" => label).increment(1);
+    if !ok {
+        metrics::counter!(TRANSACTIONS_FAILED, "type" => label).increment(1);
+    }
+    metrics::histogram!(PROCESSING_LATENCY).record(latency.as_secs_f64());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentEngine;
+    use crate::models::{Currency, InputRecord};
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use metrics_util::MetricKind;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+    use std::collections::HashMap;
+
+    #[rstest]
+    fn test_engine_emits_metrics() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            let mut engine = PaymentEngine::new();
+            for (tx_id, amount) in [(1, dec!(5.0)), (2, dec!(-1.0))] {
+                let _ = engine.process(InputRecord {
+                    record_type: TransactionType::Deposit,
+                    client_id: 1,
+                    tx_id,
+                    amount: Some(amount),
+                    counterparty_id: None,
+                    currency: Currency::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                });
+            }
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        let value = |kind, name: &str| {
+            snapshot
+                .iter()
+                .find(|(key, ..)| key.kind() == kind && key.key().name() == name)
+                .map(|(.., value)| value)
+        };
+        assert_eq!(
+            value(MetricKind::Counter, TRANSACTIONS_PROCESSED),
+            Some(&DebugValue::Counter(2))
+        );
+        assert_eq!(
+            value(MetricKind::Counter, TRANSACTIONS_FAILED),
+            Some(&DebugValue::Counter(1))
+        );
+        assert!(matches!(
+            value(MetricKind::Histogram, PROCESSING_LATENCY),
+            Some(DebugValue::Histogram(samples)) if samples.len() == 2
+        ));
+    }
+}
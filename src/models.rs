@@ -1,7 +1,84 @@
+use crate::errors::PaymentError;
 use rust_decimal::Decimal;
+use serde::{de, Deserializer, Serializer};
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy)]
+/// Identifies a client. `u16` by default; the `wide-ids` feature widens it to
+/// `u64` for larger id spaces.
+#[cfg(not(feature = "wide-ids"))]
+pub type ClientId = u16;
+#[cfg(feature = "wide-ids")]
+pub type ClientId = u64;
+
+/// Identifies a transaction. `u32` by default; `u64` with the `wide-ids`
+/// feature.
+#[cfg(not(feature = "wide-ids"))]
+pub type TxId = u32;
+#[cfg(feature = "wide-ids")]
+pub type TxId = u64;
+
+/// Currency code of up to 8 ASCII letters or digits, kept uppercase. The empty
+/// code is the default currency of records that don't name one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 8]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        let len = self.0.iter().position(|&b| b == 0).unwrap_or(self.0.len());
+        // Only ASCII is ever stored.
+        std::str::from_utf8(&self.0[..len]).unwrap_or_default()
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; 8] {
+        self.0
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; 8]) -> Self {
+        Currency(bytes)
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.trim();
+        if code.len() > 8 || !code.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(format!("invalid currency '{}'", s));
+        }
+        let mut bytes = [0u8; 8];
+        for (slot, b) in bytes.iter_mut().zip(code.bytes()) {
+            *slot = b.to_ascii_uppercase();
+        }
+        Ok(Currency(bytes))
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl serde::Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        code.parse().map_err(de::Error::custom)
+    }
+}
+
+/// A record's `type`. Deserialization is lenient (see
+/// [`TransactionType::from_alias`]); `FromStr` takes the exact names only.
+#[derive(Debug, Serialize, PartialEq, Eq, Hash, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -9,120 +86,589 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Transfer,
+    /// Merchant-initiated return of an earlier deposit, referenced by its tx id.
+    Refund,
+    /// Reserves funds for a later capture or void.
+    Auth,
+    /// Turns an authorization into a withdrawal.
+    Capture,
+    /// Cancels an authorization, releasing the reserved funds.
+    Void,
+    /// Closes an account with a zero balance.
+    Close,
+    /// Sets the client's overdraft limit to `amount`.
+    Admin,
+    /// Exchanges `amount` of the client's `currency` balance into `to_currency`.
+    Convert,
+    /// Operator correction crediting `amount` outside the deposit flow; needs
+    /// a `reference`.
+    #[serde(rename = "credit_adjustment")]
+    CreditAdjustment,
+    /// Operator correction debiting `amount` outside the withdrawal flow;
+    /// needs a `reference`.
+    #[serde(rename = "debit_adjustment")]
+    DebitAdjustment,
+}
+
+impl TransactionType {
+    const ALL: [TransactionType; 15] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Transfer,
+        TransactionType::Refund,
+        TransactionType::Auth,
+        TransactionType::Capture,
+        TransactionType::Void,
+        TransactionType::Close,
+        TransactionType::Admin,
+        TransactionType::Convert,
+        TransactionType::CreditAdjustment,
+        TransactionType::DebitAdjustment,
+    ];
+
+    /// The name the input and output use for the type.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Refund => "refund",
+            TransactionType::Auth => "auth",
+            TransactionType::Capture => "capture",
+            TransactionType::Void => "void",
+            TransactionType::Close => "close",
+            TransactionType::Admin => "admin",
+            TransactionType::Convert => "convert",
+            TransactionType::CreditAdjustment => "credit_adjustment",
+            TransactionType::DebitAdjustment => "debit_adjustment",
+        }
+    }
+
+    /// The type's position among the types, as binary encodings store it.
+    #[cfg(feature = "msgpack")]
+    pub(crate) fn index(self) -> u8 {
+        Self::ALL
+            .iter()
+            .position(|record_type| *record_type == self)
+            .unwrap_or_default() as u8
+    }
+
+    /// The type at `index`, as [`index`](Self::index) gives it.
+    #[cfg(feature = "msgpack")]
+    pub(crate) fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(index)).copied()
+    }
+
+    /// Reads a type name as upstreams spell it: in any case, ignoring `_`,
+    /// `-` and spaces (so `Charge_Back` is a chargeback), and taking
+    /// `withdraw` for `withdrawal` and `authorization` or `authorize` for
+    /// `auth`.
+    pub fn from_alias(name: &str) -> Option<Self> {
+        let name: String = name
+            .chars()
+            .filter(|c| !matches!(c, '_' | '-' | ' '))
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        match name.as_str() {
+            "withdraw" => Some(TransactionType::Withdrawal),
+            "authorization" | "authorize" => Some(TransactionType::Auth),
+            "creditadjustment" => Some(TransactionType::CreditAdjustment),
+            "debitadjustment" => Some(TransactionType::DebitAdjustment),
+            name => name.parse().ok(),
+        }
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|record_type| record_type.as_str() == s)
+            .ok_or_else(|| format!("unknown transaction type '{}'", s))
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TransactionType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Self::from_alias(&name)
+            .ok_or_else(|| de::Error::custom(format!("unknown transaction type '{}'", name)))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct InputRecord {
     #[serde(rename = "type")]
     pub record_type: TransactionType,
     #[serde(rename = "client")]
-    pub client_id: u16,
+    pub client_id: ClientId,
     #[serde(rename = "tx")]
-    pub tx_id: u32,
+    pub tx_id: TxId,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub amount: Option<Decimal>,
+    /// Receiving client of a `transfer`; unused by every other record type.
+    #[serde(
+        rename = "counterparty",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub counterparty_id: Option<ClientId>,
+    /// Balance the record applies to; the default currency when omitted.
+    #[serde(default)]
+    pub currency: Currency,
+    /// Currency a `convert` buys; unused by every other record type.
+    #[serde(
+        rename = "to_currency",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub target_currency: Option<Currency>,
+    /// Seconds since the Unix epoch. Drives interest accrual when present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Upstream key identifying the request. When present, retries are
+    /// detected by this key instead of the tx id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    /// Operator reference of an adjustment, e.g. a ticket id; unused by
+    /// every other record type.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// Extra columns of the input row, by header name, when the reader
+    /// captures them (see `CsvDialect::with_metadata`). Recorded in the
+    /// audit log.
+    #[serde(skip)]
+    pub metadata: HashMap<String, String>,
+}
+
+impl InputRecord {
+    /// The (client, currency) balance this record applies to.
+    pub fn account_key(&self) -> (ClientId, Currency) {
+        (self.client_id, self.currency)
+    }
 }
 
 #[derive(Debug, Serialize, PartialEq, Clone)]
 pub struct OutputRecord {
     #[serde(rename = "client")]
-    pub client_id: u16,
+    pub client_id: ClientId,
+    pub currency: Currency,
     pub available: Decimal,
     pub held: Decimal,
     pub total: Decimal,
     pub locked: bool,
+    pub closed: bool,
+    pub overdraft: Decimal,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct Account {
-    pub client_id: u16,
+    pub client_id: ClientId,
+    /// Each client holds a separate account per currency.
+    #[serde(default)]
+    pub currency: Currency,
     pub available: Decimal,
     pub held: Decimal,
+    /// Funds reserved by open authorizations, tracked apart from dispute holds.
+    #[serde(default)]
+    pub authorized: Decimal,
     pub locked: bool,
+    /// Closed accounts reject every further transaction.
+    #[serde(default)]
+    pub closed: bool,
+    /// How far below zero debits may take available funds.
+    #[serde(default)]
+    pub overdraft_limit: Decimal,
+    /// Interest accrued since it was last posted, before rounding.
+    #[serde(default)]
+    pub accrued_interest: Decimal,
+    /// Whether any balance mutation was applied to the account. Accounts
+    /// created for records that were then ignored, like a withdrawal from a
+    /// new client, aren't.
+    #[serde(default)]
+    pub touched: bool,
 }
 
 impl Account {
-    pub fn new(client_id: u16) -> Self {
+    pub fn new(client_id: ClientId) -> Self {
         Account {
             client_id,
+            currency: Currency::default(),
             available: Decimal::new(0, 4),
             held: Decimal::new(0, 4),
+            authorized: Decimal::new(0, 4),
             locked: false,
+            closed: false,
+            overdraft_limit: Decimal::new(0, 4),
+            accrued_interest: Decimal::ZERO,
+            touched: false,
         }
     }
 
+    /// Whether the account holds nothing and nothing was ever applied to it,
+    /// so it says nothing about its client (`--skip-empty`).
+    pub fn is_empty(&self) -> bool {
+        !self.touched && self.total().is_zero() && !self.locked && !self.closed
+    }
+
     pub fn total(&self) -> Decimal {
-        self.available + self.held
+        self.available + self.held + self.authorized
     }
 
     /// Processes a deposit into the account.
-    pub fn deposit(&mut self, amount: Decimal) {
-        self.available += amount;
+    pub fn deposit(&mut self, amount: Decimal) -> Result<(), PaymentError> {
+        self.update(
+            self.available.checked_add(amount),
+            Some(self.held),
+            Some(self.authorized),
+        )
+    }
+
+    /// Returns true if `deposit(amount)` wouldn't overflow.
+    pub fn can_deposit(&self, amount: Decimal) -> bool {
+        self.available
+            .checked_add(amount)
+            .is_some_and(|available| balances_fit(available, self.held, self.authorized))
+    }
+
+    /// Credits a deposit straight to held funds.
+    pub fn deposit_held(&mut self, amount: Decimal) -> Result<(), PaymentError> {
+        self.update(
+            Some(self.available),
+            self.held.checked_add(amount),
+            Some(self.authorized),
+        )
+    }
+
+    /// Funds available to debits, including the overdraft.
+    pub fn spendable(&self) -> Decimal {
+        self.available.saturating_add(self.overdraft_limit)
+    }
+
+    /// Debits the account unconditionally, e.g. for fees.
+    pub fn charge(&mut self, amount: Decimal) -> Result<(), PaymentError> {
+        self.update(
+            self.available.checked_sub(amount),
+            Some(self.held),
+            Some(self.authorized),
+        )
     }
 
     /// Processes a withdrawal from the account.
     /// Returns true if successful, false otherwise (insufficient funds or locked).
-    pub fn withdraw(&mut self, amount: Decimal) -> bool {
-        if !self.locked && self.available >= amount {
-            self.available -= amount;
-            true
-        } else {
-            false
+    pub fn withdraw(&mut self, amount: Decimal) -> Result<bool, PaymentError> {
+        if self.locked || self.spendable() < amount {
+            return Ok(false);
         }
+        self.charge(amount)?;
+        Ok(true)
     }
 
     /// Puts funds on hold due to a dispute.
-    pub fn hold(&mut self, amount: Decimal) -> bool {
-        if !self.locked && self.available >= amount {
-            self.available -= amount;
-            self.held += amount;
-            true
-        } else {
-            false
+    pub fn hold(&mut self, amount: Decimal) -> Result<bool, PaymentError> {
+        if self.locked || self.available < amount {
+            return Ok(false);
+        }
+        self.hold_overdrawn(amount)
+    }
+
+    /// Holds disputed funds even if that takes available below zero.
+    pub fn hold_overdrawn(&mut self, amount: Decimal) -> Result<bool, PaymentError> {
+        if self.locked {
+            return Ok(false);
         }
+        self.update(
+            self.available.checked_sub(amount),
+            self.held.checked_add(amount),
+            Some(self.authorized),
+        )?;
+        Ok(true)
     }
 
     /// Releases held funds after a dispute resolution.
-    pub fn release(&mut self, amount: Decimal) -> bool {
-        if !self.locked && self.held >= amount {
-            self.held -= amount;
-            self.available += amount;
-            true
-        } else {
-            false
+    pub fn release(&mut self, amount: Decimal) -> Result<bool, PaymentError> {
+        if self.locked || self.held < amount {
+            return Ok(false);
         }
+        self.update(
+            self.available.checked_add(amount),
+            self.held.checked_sub(amount),
+            Some(self.authorized),
+        )?;
+        Ok(true)
     }
 
     /// Processes a chargeback, removing held funds and locking the account.
-    pub fn chargeback(&mut self, amount: Decimal) -> bool {
-        if self.held >= amount {
-            self.held -= amount;
-            self.locked = true;
-            true
-        } else {
-            false
+    pub fn chargeback(&mut self, amount: Decimal) -> Result<bool, PaymentError> {
+        if self.held < amount {
+            return Ok(false);
+        }
+        self.update(
+            Some(self.available),
+            self.held.checked_sub(amount),
+            Some(self.authorized),
+        )?;
+        self.locked = true;
+        Ok(true)
+    }
+
+    /// Holds the amount of a disputed withdrawal while the dispute is open.
+    /// The funds were already debited, so only `held` (and the total) grows.
+    pub fn hold_debit(&mut self, amount: Decimal) -> Result<bool, PaymentError> {
+        if self.locked {
+            return Ok(false);
+        }
+        self.deposit_held(amount)?;
+        Ok(true)
+    }
+
+    /// Drops the hold on a disputed withdrawal, leaving the withdrawal in place.
+    pub fn release_debit(&mut self, amount: Decimal) -> Result<bool, PaymentError> {
+        if self.locked || self.held < amount {
+            return Ok(false);
+        }
+        self.update(
+            Some(self.available),
+            self.held.checked_sub(amount),
+            Some(self.authorized),
+        )?;
+        Ok(true)
+    }
+
+    /// Reverses a disputed withdrawal, re-crediting the client and locking the account.
+    pub fn chargeback_debit(&mut self, amount: Decimal) -> Result<bool, PaymentError> {
+        if self.held < amount {
+            return Ok(false);
+        }
+        self.update(
+            self.available.checked_add(amount),
+            self.held.checked_sub(amount),
+            Some(self.authorized),
+        )?;
+        self.locked = true;
+        Ok(true)
+    }
+
+    /// Reserves funds for an authorization.
+    pub fn authorize(&mut self, amount: Decimal) -> Result<bool, PaymentError> {
+        if self.locked || self.spendable() < amount {
+            return Ok(false);
+        }
+        self.update(
+            self.available.checked_sub(amount),
+            Some(self.held),
+            self.authorized.checked_add(amount),
+        )?;
+        Ok(true)
+    }
+
+    /// Settles an authorization: the reserved funds leave the account.
+    pub fn capture(&mut self, amount: Decimal) -> Result<bool, PaymentError> {
+        if self.authorized < amount {
+            return Ok(false);
+        }
+        self.update(
+            Some(self.available),
+            Some(self.held),
+            self.authorized.checked_sub(amount),
+        )?;
+        Ok(true)
+    }
+
+    /// Cancels an authorization, making the reserved funds available again.
+    pub fn void(&mut self, amount: Decimal) -> Result<bool, PaymentError> {
+        if self.authorized < amount {
+            return Ok(false);
         }
+        self.update(
+            self.available.checked_add(amount),
+            Some(self.held),
+            self.authorized.checked_sub(amount),
+        )?;
+        Ok(true)
     }
 
+    /// Stores new balances, or fails without touching any of them if one
+    /// overflowed or they no longer add up to a representable total.
+    fn update(
+        &mut self,
+        available: Option<Decimal>,
+        held: Option<Decimal>,
+        authorized: Option<Decimal>,
+    ) -> Result<(), PaymentError> {
+        match (available, held, authorized) {
+            (Some(available), Some(held), Some(authorized))
+                if balances_fit(available, held, authorized) =>
+            {
+                self.available = available;
+                self.held = held;
+                self.authorized = authorized;
+                Ok(())
+            }
+            _ => Err(PaymentError::Overflow(format!(
+                "balance of client {} would overflow",
+                self.client_id
+            ))),
+        }
+    }
+
+    /// Output `held` covers both dispute holds and authorizations.
     pub fn to_output_record(&self) -> OutputRecord {
         OutputRecord {
             client_id: self.client_id,
+            currency: self.currency,
             available: self.available,
-            held: self.held,
+            held: self.held + self.authorized,
             total: self.total(),
             locked: self.locked,
+            closed: self.closed,
+            overdraft: self.overdraft_limit,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+/// Whether the balances can be summed for `total` and the output `held`
+/// without overflowing.
+fn balances_fit(available: Decimal, held: Decimal, authorized: Decimal) -> bool {
+    let total = available
+        .checked_add(held)
+        .and_then(|sum| sum.checked_add(authorized));
+    total.is_some() && held.checked_add(authorized).is_some()
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
 pub enum TransactionState {
     Normal,
     Disputed,
+    /// An authorization waiting to be captured or voided.
+    Authorized,
+    /// Disputed, but waiting for enough available funds to be held.
+    DisputeQueued,
+    /// A dispute was resolved; the transaction may be disputed again if the
+    /// engine allows more than one dispute per transaction.
+    Resolved,
+}
+
+/// Whether a stored transaction moved funds into or out of the client's account.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum TransactionDirection {
+    Credit,
+    Debit,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// A transaction under an open dispute, from
+/// [`PaymentEngine::open_disputes`](crate::engine::PaymentEngine::open_disputes).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct OpenDispute {
+    #[serde(rename = "tx")]
+    pub tx_id: TxId,
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub currency: Currency,
+    /// The disputed amount.
+    pub amount: Decimal,
+    /// When the dispute was filed, if its record carried a timestamp.
+    pub opened_at: Option<u64>,
+    /// Whether the dispute is waiting for enough available funds to hold
+    /// the amount (see `UnderfundedDisputeMode::Queue`).
+    pub queued: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
 pub struct TransactionInfo {
-    pub client_id: u16,
+    pub client_id: ClientId,
     pub amount: Decimal,
     pub state: TransactionState,
+    pub direction: TransactionDirection,
+    /// Times the transaction has been disputed.
+    #[serde(default)]
+    pub disputes: u8,
+    /// Currency the amount was moved in.
+    #[serde(default)]
+    pub currency: Currency,
+    /// When the transaction happened, if the record said.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+}
+
+impl TransactionInfo {
+    /// The (client, currency) balance the transaction was applied to.
+    pub fn account_key(&self) -> (ClientId, Currency) {
+        (self.client_id, self.currency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("usd", Ok("USD"))]
+    #[case(" EUR ", Ok("EUR"))]
+    #[case("", Ok(""))]
+    #[case("BTC2", Ok("BTC2"))]
+    #[case("TOOLONGCODE", Err("invalid currency 'TOOLONGCODE'"))]
+    #[case("U$D", Err("invalid currency 'U$D'"))]
+    fn test_currency_parse(#[case] input: &str, #[case] expected: Result<&str, &str>) {
+        let parsed = input.parse::<Currency>();
+        assert_eq!(
+            parsed
+                .as_ref()
+                .map(Currency::as_str)
+                .map_err(String::as_str),
+            expected
+        );
+    }
+
+    #[rstest]
+    fn test_currency_default_is_empty() {
+        assert_eq!(Currency::default().to_string(), "");
+        assert_eq!("".parse::<Currency>().unwrap(), Currency::default());
+    }
+
+    #[rstest]
+    #[case("deposit", Some(TransactionType::Deposit))]
+    #[case("DEPOSIT", Some(TransactionType::Deposit))]
+    #[case("Withdraw", Some(TransactionType::Withdrawal))]
+    #[case("charge_back", Some(TransactionType::Chargeback))]
+    #[case("Charge-Back", Some(TransactionType::Chargeback))]
+    #[case("authorization", Some(TransactionType::Auth))]
+    #[case("to_currency", None)]
+    #[case("payout", None)]
+    fn test_transaction_type_from_alias(
+        #[case] name: &str,
+        #[case] expected: Option<TransactionType>,
+    ) {
+        assert_eq!(TransactionType::from_alias(name), expected);
+    }
+
+    #[rstest]
+    fn test_transaction_type_parse_is_exact() {
+        for record_type in TransactionType::ALL {
+            assert_eq!(record_type.as_str().parse(), Ok(record_type));
+        }
+        assert_eq!(
+            "Deposit".parse::<TransactionType>(),
+            Err("unknown transaction type 'Deposit'".to_string())
+        );
+        let record: InputRecord =
+            serde_json::from_str(r#"{"type":"DEPOSIT","client":1,"tx":1}"#).unwrap();
+        assert_eq!(record.record_type, TransactionType::Deposit);
+    }
 }
@@ -0,0 +1,233 @@
+//! MessagePack record streams, a compact binary encoding of `InputRecord`s
+//! for replay archives that decode faster and take less space than CSV.
+//!
+//! A stream is a sequence of MessagePack arrays, one per record, holding the
+//! input columns in order, with the record type as its index in
+//! [`TransactionType`] and amounts as decimal strings. `convert` writes them.
+
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::input::{process_records, RawRecord};
+use crate::models::{ClientId, Currency, InputRecord, TransactionType, TxId};
+use crate::report::ProcessingReport;
+use rust_decimal::Decimal;
+use serde::Deserialize as _;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::iter;
+
+/// One record on the wire: type index, client, tx, amount, counterparty,
+/// currency, target currency, timestamp, idempotency key and operator
+/// reference. The reference was added last and may be missing.
+#[derive(Debug, Serialize, Deserialize)]
+struct Packed(
+    u8,
+    ClientId,
+    TxId,
+    Option<Decimal>,
+    Option<ClientId>,
+    Currency,
+    Option<Currency>,
+    Option<u64>,
+    Option<String>,
+    #[serde(default)] Option<String>,
+);
+
+impl Packed {
+    fn new(record: &InputRecord) -> Self {
+        Packed(
+            record.record_type.index(),
+            record.client_id,
+            record.tx_id,
+            record.amount,
+            record.counterparty_id,
+            record.currency,
+            record.target_currency,
+            record.timestamp,
+            record.idempotency_key.clone(),
+            record.reference.clone(),
+        )
+    }
+
+    fn into_record(self) -> Result<InputRecord, PaymentError> {
+        let Packed(
+            index,
+            client_id,
+            tx_id,
+            amount,
+            counterparty_id,
+            currency,
+            target,
+            timestamp,
+            key,
+            reference,
+        ) = self;
+        let record_type = TransactionType::from_index(index).ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!("unknown transaction type {}", index))
+        })?;
+        Ok(InputRecord {
+            record_type,
+            client_id,
+            tx_id,
+            amount,
+            counterparty_id,
+            currency,
+            target_currency: target,
+            timestamp,
+            idempotency_key: key,
+            reference,
+            metadata: HashMap::new(),
+        })
+    }
+}
+
+/// Processes a MessagePack record stream.
+pub fn process_msgpack<R: Read>(
+    reader: R,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError> {
+    process_records(read_msgpack(reader), engine)
+}
+
+/// Lazily decodes a MessagePack record stream. Records are numbered from 1
+/// and have no raw text. A record that doesn't decode ends the stream, since
+/// the next one can't be located; one of an unknown type doesn't.
+pub fn read_msgpack<R: Read>(reader: R) -> impl Iterator<Item = RawRecord> {
+    let mut de = rmp_serde::Deserializer::new(BufReader::new(reader));
+    let mut line = 0;
+    let mut done = false;
+    iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        line += 1;
+        let parsed = match de.get_mut().fill_buf() {
+            Ok([]) => return None,
+            Ok(_) => Packed::deserialize(&mut de).map_err(|e| {
+                done = true;
+                PaymentError::MessagePack(e.to_string())
+            }),
+            Err(e) => {
+                done = true;
+                Err(e.into())
+            }
+        };
+        let parsed = parsed.and_then(Packed::into_record);
+        Some(RawRecord {
+            line,
+            raw: String::new(),
+            parsed,
+        })
+    })
+}
+
+/// Writes records as a MessagePack stream.
+pub struct MsgpackWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> MsgpackWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn write(&mut self, record: &InputRecord) -> Result<(), PaymentError> {
+        rmp_serde::encode::write(&mut self.writer, &Packed::new(record))
+            .map_err(|e| PaymentError::MessagePack(e.to_string()))
+    }
+
+    pub fn flush(&mut self) -> Result<(), PaymentError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_handler;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    const CSV: &str =
+        "type,client,tx,amount,counterparty,currency,to_currency,timestamp,idempotency_key
+deposit,1,1,10.5,,EUR,,1700000000,k-1
+transfer,1,2,2.25,2,EUR,,,
+convert,1,3,1.0,,EUR,USD,,
+dispute,1,1,,,EUR,,,
+";
+
+    fn records() -> Vec<InputRecord> {
+        csv_handler::read_records(CSV.as_bytes())
+            .map(|record| record.parsed.unwrap())
+            .collect()
+    }
+
+    fn encode(records: &[InputRecord]) -> Vec<u8> {
+        let mut writer = MsgpackWriter::new(Vec::new());
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        writer.flush().unwrap();
+        writer.writer
+    }
+
+    #[rstest]
+    fn test_msgpack_round_trip() {
+        let records = records();
+        let encoded = encode(&records);
+        assert!(encoded.len() < CSV.len());
+        let decoded: Vec<RawRecord> = read_msgpack(encoded.as_slice()).collect();
+        assert_eq!(
+            decoded.iter().map(|record| record.line).collect::<Vec<_>>(),
+            [1, 2, 3, 4]
+        );
+        let decoded: Vec<InputRecord> = decoded
+            .into_iter()
+            .map(|record| record.parsed.unwrap())
+            .collect();
+        assert_eq!(decoded, records);
+    }
+
+    #[rstest]
+    fn test_read_msgpack_reports_bad_records() {
+        let mut encoded = encode(&records()[..1]);
+        // A record with an unknown type index, then a truncated one.
+        rmp_serde::encode::write(
+            &mut encoded,
+            &(
+                99u8,
+                1,
+                2,
+                Some(dec!(1)),
+                None::<u16>,
+                "",
+                None::<&str>,
+                None::<u64>,
+                None::<&str>,
+            ),
+        )
+        .unwrap();
+        encoded.extend_from_slice(&[0x99, 0x00]);
+        let decoded: Vec<RawRecord> = read_msgpack(encoded.as_slice()).collect();
+        assert_eq!(decoded.len(), 3);
+        assert!(decoded[0].parsed.is_ok());
+        let err = decoded[1].parsed.as_ref().unwrap_err().to_string();
+        assert!(err.contains("unknown transaction type 99"), "{}", err);
+        assert!(matches!(
+            decoded[2].parsed,
+            Err(PaymentError::MessagePack(_))
+        ));
+    }
+
+    #[rstest]
+    fn test_process_msgpack() {
+        let mut engine = PaymentEngine::new();
+        let report = process_msgpack(encode(&records()).as_slice(), &mut engine).unwrap();
+        assert_eq!(report.records_read, 4);
+        let eur = engine.get_account(1, "EUR".parse().unwrap()).unwrap();
+        // The convert has no rates to go by, and the dispute can't hold 10.5.
+        assert_eq!(eur.available, dec!(8.25));
+    }
+}
@@ -0,0 +1,313 @@
+//! SWIFT MT940 customer statements, so the engine's balances can be
+//! reconciled with a bank's.
+//!
+//! Each `:61:` statement line becomes a deposit (credits and reversed debits)
+//! or a withdrawal (debits and reversed credits) of the client owning the
+//! statement's `:25:` account, in the currency of its `:60F:` opening
+//! balance. Messages may come bare or in their SWIFT blocks; fields other
+//! than those are ignored.
+
+use crate::account_map::AccountMap;
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::input::{process_records, RawRecord};
+use crate::iso20022::date_timestamp;
+use crate::models::{Currency, InputRecord, TransactionType};
+use crate::report::ProcessingReport;
+use crate::tx_ids::TxIdMap;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Split};
+use std::iter::Zip;
+use std::ops::RangeFrom;
+
+/// The customer reference of lines the account owner didn't reference.
+const NO_REFERENCE: &str = "NONREF";
+
+/// Processes the statement lines of an MT940 file, reading account numbers
+/// as client ids.
+pub fn process_mt940<R: Read>(
+    reader: R,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError> {
+    process_records(
+        read_mt940(reader, TxIdMap::default(), &AccountMap::default()),
+        engine,
+    )
+}
+
+/// Lazily decodes the statement lines of an MT940 file. Their tx id is the
+/// bank's reference (or the customer's, unless it's `NONREF`, or else the
+/// statement's `:20:` reference and the line's position), read through
+/// `tx_ids`, and their timestamp the value date.
+pub fn read_mt940<R: Read>(
+    reader: R,
+    tx_ids: TxIdMap,
+    accounts: &AccountMap,
+) -> impl Iterator<Item = RawRecord> {
+    Mt940Records {
+        // Free text fields are often in a legacy encoding; the decoded
+        // fields are ASCII either way.
+        lines: BufReader::new(reader).split(b'\n').zip(1..),
+        statement: Statement::default(),
+        tx_ids,
+        accounts: accounts.clone(),
+    }
+}
+
+/// The fields of the statement being read that its lines depend on.
+#[derive(Debug, Default)]
+struct Statement {
+    reference: String,
+    account: Option<String>,
+    currency: Option<Currency>,
+    entries: usize,
+}
+
+struct Mt940Records<R> {
+    lines: Zip<Split<BufReader<R>>, RangeFrom<u64>>,
+    statement: Statement,
+    tx_ids: TxIdMap,
+    accounts: AccountMap,
+}
+
+impl<R: Read> Iterator for Mt940Records<R> {
+    type Item = RawRecord;
+
+    fn next(&mut self) -> Option<RawRecord> {
+        for (line, number) in self.lines.by_ref() {
+            let line = match line {
+                Ok(line) => String::from_utf8_lossy(&line).trim_end().to_string(),
+                Err(e) => {
+                    return Some(RawRecord {
+                        line: number,
+                        raw: String::new(),
+                        parsed: Err(e.into()),
+                    })
+                }
+            };
+            let Some((tag, value)) = field(&line) else {
+                continue;
+            };
+            match tag {
+                "20" => {
+                    self.statement = Statement {
+                        reference: value.to_string(),
+                        ..Statement::default()
+                    }
+                }
+                "25" => self.statement.account = Some(value.to_string()),
+                // The currency follows the mark and the date.
+                "60F" | "60M" => {
+                    self.statement.currency = value.get(7..10).and_then(|code| code.parse().ok())
+                }
+                "61" => {
+                    self.statement.entries += 1;
+                    return Some(RawRecord {
+                        line: number,
+                        parsed: self.decode(value),
+                        raw: line,
+                    });
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+impl<R> Mt940Records<R> {
+    fn decode(&self, line: &str) -> Result<InputRecord, PaymentError> {
+        let invalid =
+            || PaymentError::InvalidTransaction(format!("invalid statement line '{}'", line));
+        let (timestamp, rest) = value_date(line).ok_or_else(invalid)?;
+        // An optional MMDD entry date.
+        let rest = match rest.get(..4) {
+            Some(date) if date.bytes().all(|b| b.is_ascii_digit()) => &rest[4..],
+            _ => rest,
+        };
+        let (record_type, rest) = [
+            ("RC", TransactionType::Withdrawal),
+            ("RD", TransactionType::Deposit),
+            ("C", TransactionType::Deposit),
+            ("D", TransactionType::Withdrawal),
+        ]
+        .into_iter()
+        .find_map(|(mark, record_type)| rest.strip_prefix(mark).map(|rest| (record_type, rest)))
+        .ok_or_else(invalid)?;
+        // An optional funds code, the third letter of the currency.
+        let rest = rest
+            .strip_prefix(|c: char| c.is_ascii_alphabetic())
+            .unwrap_or(rest);
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != ',')
+            .unwrap_or(rest.len());
+        let amount: Decimal = rest[..end]
+            .trim_end_matches(',')
+            .replace(',', ".")
+            .parse()
+            .map_err(|_| invalid())?;
+        // The transaction type, then the references.
+        let references = rest[end..].get(4..).ok_or_else(invalid)?;
+        let (customer, bank) = references.split_once("//").unwrap_or((references, ""));
+        let reference = [bank.trim(), customer.trim()]
+            .into_iter()
+            .find(|reference| !reference.is_empty() && *reference != NO_REFERENCE)
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{}/{}", self.statement.reference, self.statement.entries));
+
+        let account = self.statement.account.as_deref().ok_or_else(|| {
+            PaymentError::InvalidTransaction("statement line before the :25: account".to_string())
+        })?;
+        let client_id = self.accounts.client(account).ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!("account {} belongs to no client", account))
+        })?;
+        let currency = self.statement.currency.ok_or_else(|| {
+            PaymentError::InvalidTransaction(
+                "statement line before the :60F: opening balance".to_string(),
+            )
+        })?;
+
+        Ok(InputRecord {
+            record_type,
+            client_id,
+            tx_id: self.tx_ids.map(&reference)?,
+            amount: Some(amount),
+            counterparty_id: None,
+            currency,
+            target_currency: None,
+            timestamp: Some(timestamp),
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        })
+    }
+}
+
+/// The tag and value of a line starting a field, like `:61:...`.
+fn field(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix(':')?;
+    let (tag, value) = rest.split_once(':')?;
+    (tag.len() <= 3 && tag.bytes().all(|b| b.is_ascii_alphanumeric())).then_some((tag, value))
+}
+
+/// The timestamp of a leading `YYMMDD` value date, and the rest of the line.
+/// Two-digit years before 70 are this century's.
+pub(crate) fn value_date(line: &str) -> Option<(u64, &str)> {
+    let digits = |range| {
+        line.get(range)
+            .and_then(|digits: &str| digits.parse::<u32>().ok())
+    };
+    let (year, month, day) = (digits(0..2)?, digits(2..4)?, digits(4..6)?);
+    let year = if year < 70 { 2000 + year } else { 1900 + year };
+    Some((date_timestamp(year, month, day)?, &line[6..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ClientId, TxId};
+    use crate::tx_ids::TxIdFormat;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    const MT940: &str = "{1:F01BANKDEFFAXXX0000000000}{2:O940BANKDEFFXXXX}{4:
+:20:STMT-1
+:25:10020030/1234567
+:28C:00001/001
+:60F:C240301EUR1000,00
+:61:2403010301C100,00NTRFNONREF//BANK-1
+:86:Incoming transfer
+:61:240302DR25,5NTRFINV-42
+:61:240303RD1,NCHGNONREF
+:62F:C240303EUR1076,50
+-}
+:20:STMT-2
+:25:DE89370400440532013000
+:60F:C240301GBP0,
+:61:240304C5,NTRFNONREF
+";
+
+    fn read(mt940: &str, accounts: AccountMap) -> Vec<RawRecord> {
+        read_mt940(mt940.as_bytes(), TxIdMap::new(TxIdFormat::Text), &accounts).collect()
+    }
+
+    #[rstest]
+    fn test_read_mt940() {
+        let accounts = AccountMap::new().with_account("10020030/1234567", 1);
+        let records = read(MT940, accounts);
+        assert_eq!(records.len(), 4);
+
+        let decoded: Vec<(u64, TransactionType, ClientId, TxId, Decimal, Option<u64>)> = records
+            [..3]
+            .iter()
+            .map(|record| {
+                let parsed = record.parsed.as_ref().unwrap();
+                assert_eq!(parsed.currency.as_str(), "EUR");
+                (
+                    record.line,
+                    parsed.record_type,
+                    parsed.client_id,
+                    parsed.tx_id,
+                    parsed.amount.unwrap(),
+                    parsed.timestamp,
+                )
+            })
+            .collect();
+        let day = |n: u64| Some(1_709_251_200 + (n - 1) * 86_400);
+        assert_eq!(
+            decoded,
+            [
+                (6, TransactionType::Deposit, 1, 1, dec!(100.00), day(1)),
+                (8, TransactionType::Withdrawal, 1, 2, dec!(25.5), day(2)),
+                (9, TransactionType::Deposit, 1, 3, dec!(1), day(3)),
+            ]
+        );
+        assert_eq!(records[0].raw, ":61:2403010301C100,00NTRFNONREF//BANK-1");
+
+        let err = records[3].parsed.as_ref().unwrap_err().to_string();
+        assert!(
+            err.contains("account DE89370400440532013000 belongs to no client"),
+            "{}",
+            err
+        );
+    }
+
+    #[rstest]
+    fn test_read_mt940_falls_back_to_statement_references() {
+        let accounts = AccountMap::new()
+            .with_account("10020030/1234567", 1)
+            .with_account("DE89370400440532013000", 2);
+        let tx_ids = TxIdMap::new(TxIdFormat::Text);
+        let records: Vec<RawRecord> =
+            read_mt940(MT940.as_bytes(), tx_ids.clone(), &accounts).collect();
+        let last = records[3].parsed.as_ref().unwrap();
+        assert_eq!((last.client_id, last.currency.as_str()), (2, "GBP"));
+        // BANK-1, INV-42, STMT-1/3 and STMT-2/1.
+        assert_eq!(tx_ids.map("STMT-1/3").unwrap(), 3);
+        assert_eq!(last.tx_id, 4);
+    }
+
+    #[rstest]
+    #[case(":61:2403X1C1,NTRF", "invalid statement line")]
+    #[case(":61:240301X1,NTRF", "invalid statement line")]
+    #[case(":61:240301C1,", "invalid statement line")]
+    #[case(":61:240301CEUR,NTRF", "invalid statement line")]
+    fn test_read_mt940_rejects_lines(#[case] line: &str, #[case] message: &str) {
+        let mt940 = format!(":20:S\n:25:1\n:60F:C240301EUR0,\n{}\n", line);
+        let records = read(&mt940, AccountMap::new());
+        let err = records[0].parsed.as_ref().unwrap_err().to_string();
+        assert!(err.contains(message), "{}", err);
+    }
+
+    #[rstest]
+    fn test_process_mt940_reads_client_ids() {
+        let mt940 = ":20:S\n:25:7\n:60F:C240301EUR0,\n\
+                     :61:240301C10,NTRF11\n:61:240302D4,NTRF12\n";
+        let mut engine = PaymentEngine::new();
+        let report = process_mt940(mt940.as_bytes(), &mut engine).unwrap();
+        assert_eq!(report.records_read, 2);
+        let currency = "EUR".parse().unwrap();
+        assert_eq!(engine.get_account(7, currency).unwrap().available, dec!(6));
+    }
+}
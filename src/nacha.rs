@@ -0,0 +1,404 @@
+//! NACHA ACH files, so the entries a US processor receives can be applied
+//! without a pre-processor.
+//!
+//! Entry detail records credit or debit the client owning the receiver's
+//! account: credits become deposits and debits withdrawals, referenced by
+//! their trace number. A return reverses the entry named by the original
+//! trace number of its addenda, which maps onto the dispute lifecycle: it
+//! becomes a dispute of that entry immediately followed by its chargeback.
+//! Prenotes, notifications of change and zero-dollar entries move no money
+//! and are left out.
+
+use crate::account_map::AccountMap;
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::input::{process_records, RawRecord};
+use crate::models::{Currency, InputRecord, TransactionType};
+use crate::mt940::value_date;
+use crate::report::ProcessingReport;
+use crate::tx_ids::TxIdMap;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Every record is this long; files may omit the line breaks between them.
+const RECORD_LEN: usize = 94;
+/// ACH only moves US dollars.
+const CURRENCY: &str = "USD";
+
+/// Processes the entries of a NACHA file, reading account numbers as client
+/// ids.
+pub fn process_nacha<R: Read>(
+    reader: R,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError> {
+    process_records(
+        read_nacha(reader, TxIdMap::default(), &AccountMap::default()),
+        engine,
+    )
+}
+
+/// Lazily decodes the entries of a NACHA file. Their `line` is the position
+/// of their entry detail record, and their timestamp the effective entry
+/// date of their batch. Trace numbers have 15 digits, more than numeric tx
+/// ids hold without `wide-ids`, so `tx_ids` usually maps them as strings.
+pub fn read_nacha<R: Read>(
+    reader: R,
+    tx_ids: TxIdMap,
+    accounts: &AccountMap,
+) -> impl Iterator<Item = RawRecord> {
+    NachaRecords {
+        records: records(reader).zip(1..),
+        tx_ids,
+        accounts: accounts.clone(),
+        currency: CURRENCY.parse().unwrap_or_default(),
+        effective: None,
+        entry: None,
+        ready: VecDeque::new(),
+    }
+}
+
+/// The records of a file, split at line breaks or every `RECORD_LEN`
+/// characters when there are none.
+fn records<R: Read>(reader: R) -> impl Iterator<Item = io::Result<String>> {
+    BufReader::new(reader)
+        .split(b'\n')
+        .flat_map(|line| match line {
+            Ok(line) => {
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim_end_matches('\r');
+                if line.len() > RECORD_LEN
+                    && line.len().is_multiple_of(RECORD_LEN)
+                    && line.is_ascii()
+                {
+                    line.as_bytes()
+                        .chunks(RECORD_LEN)
+                        .map(|record| Ok(String::from_utf8_lossy(record).into_owned()))
+                        .collect()
+                } else {
+                    vec![Ok(line.to_string())]
+                }
+            }
+            Err(e) => vec![Err(e)],
+        })
+        .filter(|record| !matches!(record, Ok(record) if record.trim().is_empty()))
+}
+
+/// An entry detail record waiting for its addenda.
+struct Entry {
+    line: u64,
+    record: String,
+    addenda: Option<String>,
+}
+
+struct NachaRecords<I> {
+    records: I,
+    tx_ids: TxIdMap,
+    accounts: AccountMap,
+    currency: Currency,
+    /// The effective entry date of the current batch.
+    effective: Option<u64>,
+    entry: Option<Entry>,
+    /// Decoded records not yet returned; a return yields two.
+    ready: VecDeque<RawRecord>,
+}
+
+impl<I: Iterator<Item = (io::Result<String>, u64)>> Iterator for NachaRecords<I> {
+    type Item = RawRecord;
+
+    fn next(&mut self) -> Option<RawRecord> {
+        loop {
+            if let Some(record) = self.ready.pop_front() {
+                return Some(record);
+            }
+            let Some((record, line)) = self.records.next() else {
+                let entry = self.entry.take()?;
+                self.push(entry);
+                continue;
+            };
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    self.ready.push_back(RawRecord {
+                        line,
+                        raw: String::new(),
+                        parsed: Err(e.into()),
+                    });
+                    continue;
+                }
+            };
+            if record.starts_with('7') {
+                if let Some(entry) = self.entry.as_mut().filter(|entry| entry.addenda.is_none()) {
+                    entry.addenda = Some(record);
+                }
+                continue;
+            }
+            if let Some(entry) = self.entry.take() {
+                self.push(entry);
+            }
+            match record.get(..1) {
+                Some("5") => {
+                    self.effective = record
+                        .get(69..75)
+                        .and_then(value_date)
+                        .map(|(date, _)| date)
+                }
+                Some("6") => {
+                    self.entry = Some(Entry {
+                        line,
+                        record,
+                        addenda: None,
+                    })
+                }
+                // File and batch headers and controls, and the padding.
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<I> NachaRecords<I> {
+    /// Queues the records an entry becomes.
+    fn push(&mut self, entry: Entry) {
+        let records = match self.decode(&entry) {
+            Ok(records) => records.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        };
+        for parsed in records {
+            self.ready.push_back(RawRecord {
+                line: entry.line,
+                raw: entry.record.clone(),
+                parsed,
+            });
+        }
+    }
+
+    fn decode(&self, entry: &Entry) -> Result<Vec<InputRecord>, PaymentError> {
+        let record = &entry.record;
+        let invalid = |what: &str| {
+            PaymentError::InvalidTransaction(format!("invalid {} in entry '{}'", what, record))
+        };
+        if record.len() != RECORD_LEN || !record.is_ascii() {
+            return Err(invalid("length"));
+        }
+        let digits = |range: std::ops::Range<usize>| record[range].trim().parse::<u64>().ok();
+        let account = record[12..29].trim();
+        let cents = digits(29..39).ok_or_else(|| invalid("amount"))?;
+        let trace = record[79..94].trim();
+
+        // The second digit of the transaction code tells live credits and
+        // debits from returns and from entries moving no money.
+        let record_type = match record.as_bytes()[2] {
+            b'2' => TransactionType::Deposit,
+            b'5' | b'7' => TransactionType::Withdrawal,
+            b'1' | b'6' => return self.decode_return(entry, account),
+            b'3' | b'4' | b'8' | b'9' => return Ok(Vec::new()),
+            _ => return Err(invalid("transaction code")),
+        };
+        if cents == 0 {
+            return Ok(Vec::new());
+        }
+        Ok(vec![InputRecord {
+            amount: Some(Decimal::new(cents as i64, 2)),
+            ..self.record(record_type, account, trace)?
+        }])
+    }
+
+    /// A return (addenda type 99) becomes a dispute and a chargeback of the
+    /// original entry; a notification of change (98) moves nothing.
+    fn decode_return(
+        &self,
+        entry: &Entry,
+        account: &str,
+    ) -> Result<Vec<InputRecord>, PaymentError> {
+        let addenda = entry.addenda.as_deref().unwrap_or_default();
+        match addenda.get(1..3) {
+            Some("98") => Ok(Vec::new()),
+            Some("99") => {
+                let original = addenda.get(6..21).map(str::trim).ok_or_else(|| {
+                    PaymentError::InvalidTransaction(format!(
+                        "invalid return addenda '{}'",
+                        addenda
+                    ))
+                })?;
+                Ok(vec![
+                    self.record(TransactionType::Dispute, account, original)?,
+                    self.record(TransactionType::Chargeback, account, original)?,
+                ])
+            }
+            _ => Err(PaymentError::InvalidTransaction(format!(
+                "return entry '{}' without its return addenda",
+                entry.record
+            ))),
+        }
+    }
+
+    fn record(
+        &self,
+        record_type: TransactionType,
+        account: &str,
+        trace: &str,
+    ) -> Result<InputRecord, PaymentError> {
+        let client_id = self.accounts.client(account).ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!("account {} belongs to no client", account))
+        })?;
+        Ok(InputRecord {
+            record_type,
+            client_id,
+            tx_id: self.tx_ids.map(trace)?,
+            amount: None,
+            counterparty_id: None,
+            currency: self.currency,
+            target_currency: None,
+            timestamp: self.effective,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ClientId, TxId};
+    use crate::tx_ids::TxIdFormat;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    fn pad(record: &str) -> String {
+        format!("{:<94}", record)
+    }
+
+    /// An entry detail record.
+    fn entry(code: &str, account: &str, cents: u64, addenda: bool, trace: &str) -> String {
+        pad(&format!(
+            "6{}12345678{:<1}{:<17}{:010}{:<15}{:<22}  {}{:>15}",
+            code,
+            "9",
+            account,
+            cents,
+            "ID",
+            "NAME",
+            u8::from(addenda),
+            trace
+        ))
+    }
+
+    fn batch_header(date: &str) -> String {
+        pad(&format!("5200{:<65}{}", "COMPANY", date))
+    }
+
+    fn return_addenda(original: &str) -> String {
+        pad(&format!("799R01{:>15}", original))
+    }
+
+    fn file(records: &[String]) -> String {
+        let mut lines = vec![pad("101 123456789 1234567892403010000A094101")];
+        lines.extend_from_slice(records);
+        lines.push(pad("8200"));
+        lines.push(pad("9000001"));
+        lines.push("9".repeat(94));
+        lines.join("\n")
+    }
+
+    fn decode(file: &str) -> Vec<(u64, TransactionType, ClientId, TxId, Option<Decimal>)> {
+        let accounts = AccountMap::new().with_account("000111", 1);
+        read_nacha(file.as_bytes(), TxIdMap::new(TxIdFormat::Text), &accounts)
+            .map(|record| {
+                let parsed = record.parsed.unwrap();
+                (
+                    record.line,
+                    parsed.record_type,
+                    parsed.client_id,
+                    parsed.tx_id,
+                    parsed.amount,
+                )
+            })
+            .collect()
+    }
+
+    #[rstest]
+    fn test_read_nacha() {
+        let file = file(&[
+            batch_header("240301"),
+            entry("22", "000111", 10_050, false, "123456780000001"),
+            entry("27", "000111", 2_500, true, "123456780000002"),
+            pad("705payment details"),
+            entry("23", "000111", 0, false, "123456780000003"),
+            entry("26", "000111", 10_050, true, "123456780000004"),
+            return_addenda("123456780000001"),
+        ]);
+        assert_eq!(
+            decode(&file),
+            [
+                (3, TransactionType::Deposit, 1, 1, Some(dec!(100.50))),
+                (4, TransactionType::Withdrawal, 1, 2, Some(dec!(25.00))),
+                (7, TransactionType::Dispute, 1, 1, None),
+                (7, TransactionType::Chargeback, 1, 1, None),
+            ]
+        );
+
+        let record = read_nacha(
+            file.as_bytes(),
+            TxIdMap::new(TxIdFormat::Text),
+            &AccountMap::new().with_account("000111", 1),
+        )
+        .next()
+        .unwrap();
+        let parsed = record.parsed.unwrap();
+        assert_eq!(parsed.currency.as_str(), "USD");
+        assert_eq!(parsed.timestamp, Some(1_709_251_200));
+        assert_eq!(record.raw.len(), RECORD_LEN);
+    }
+
+    #[rstest]
+    fn test_read_nacha_without_line_breaks() {
+        let file = file(&[
+            batch_header("240301"),
+            entry("32", "000111", 1, false, "123456780000001"),
+        ])
+        .replace('\n', "");
+        assert_eq!(
+            decode(&file),
+            [(3, TransactionType::Deposit, 1, 1, Some(dec!(0.01)))]
+        );
+    }
+
+    #[rstest]
+    #[case(
+        entry("22", "999", 100, false, "1"),
+        "account 999 belongs to no client"
+    )]
+    #[case(entry("20", "000111", 100, false, "1"), "invalid transaction code")]
+    #[case(entry("21", "000111", 100, false, "1"), "without its return addenda")]
+    #[case("6221234".to_string(), "invalid length")]
+    fn test_read_nacha_rejects_entries(#[case] record: String, #[case] message: &str) {
+        let file = file(&[batch_header("240301"), record]);
+        let accounts = AccountMap::new().with_account("000111", 1);
+        let records: Vec<RawRecord> =
+            read_nacha(file.as_bytes(), TxIdMap::new(TxIdFormat::Text), &accounts).collect();
+        assert_eq!(records.len(), 1);
+        let err = records[0].parsed.as_ref().unwrap_err().to_string();
+        assert!(err.contains(message), "{}", err);
+    }
+
+    #[rstest]
+    fn test_process_nacha_applies_returns() {
+        let file = file(&[
+            batch_header("240301"),
+            entry("22", "7", 10_000, false, "11"),
+            entry("22", "7", 5_000, false, "12"),
+            entry("21", "7", 10_000, true, "13"),
+            return_addenda("11"),
+        ]);
+        let mut engine = PaymentEngine::new();
+        let report = process_nacha(file.as_bytes(), &mut engine).unwrap();
+
+        assert_eq!(report.records_read, 4);
+        assert!(report.skipped.is_empty());
+        let account = engine.get_account(7, "USD".parse().unwrap()).unwrap();
+        assert_eq!(account.available, dec!(50));
+        assert!(account.locked);
+    }
+}
@@ -0,0 +1,373 @@
+//! OFX (and Quicken's QFX) bank and credit card statements, so
+//! personal-finance exports can be replayed through the engine.
+//!
+//! Both the SGML flavour of OFX 1.x, whose elements aren't closed, and the
+//! XML of OFX 2.x are read by the same tolerant tag scanner. Each `STMTTRN`
+//! becomes a deposit (positive amounts) or a withdrawal (negative ones) of the
+//! client owning the statement's `ACCTID`, in the statement's `CURDEF`
+//! currency.
+
+use crate::account_map::AccountMap;
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::input::{process_records, RawRecord};
+use crate::iso20022::date_timestamp;
+use crate::models::{Currency, InputRecord, TransactionType};
+use crate::report::ProcessingReport;
+use crate::tx_ids::TxIdMap;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::io::Read;
+use std::vec;
+
+const TRANSACTION: &str = "STMTTRN";
+
+/// Processes the transactions of an OFX file, reading account numbers as
+/// client ids.
+pub fn process_ofx<R: Read>(
+    reader: R,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError> {
+    process_records(
+        read_ofx(reader, TxIdMap::default(), &AccountMap::default()),
+        engine,
+    )
+}
+
+/// Decodes the transactions of an OFX file. Their tx id is the `FITID` the
+/// bank gave them, read through `tx_ids`, and their timestamp `DTPOSTED`.
+pub fn read_ofx<R: Read>(
+    mut reader: R,
+    tx_ids: TxIdMap,
+    accounts: &AccountMap,
+) -> vec::IntoIter<RawRecord> {
+    let mut bytes = Vec::new();
+    if let Err(e) = reader.read_to_end(&mut bytes) {
+        return vec![RawRecord {
+            line: 0,
+            raw: String::new(),
+            parsed: Err(e.into()),
+        }]
+        .into_iter();
+    }
+    // OFX 1.x files are often in a legacy encoding; the decoded elements are
+    // ASCII either way.
+    let text = String::from_utf8_lossy(&bytes);
+
+    let mut records = Vec::new();
+    let mut account: Option<&str> = None;
+    let mut currency: Option<Currency> = None;
+    // The start and line of the transaction being read, and its elements.
+    let mut transaction: Option<(usize, u64, HashMap<&str, &str>)> = None;
+    let (mut line, mut counted) = (1, 0);
+    for tag in tags(&text) {
+        match tag {
+            Tag::Start { name, value, start } => {
+                if let Some((_, _, elements)) = transaction.as_mut() {
+                    elements.entry(name).or_insert(value);
+                } else if name == TRANSACTION {
+                    line += text[counted..start].matches('\n').count() as u64;
+                    counted = start;
+                    transaction = Some((start, line, HashMap::new()));
+                } else if name == "ACCTID" {
+                    account = Some(value);
+                } else if name == "CURDEF" {
+                    currency = value.parse().ok();
+                }
+            }
+            Tag::End { name, end } if name == TRANSACTION => {
+                let Some((start, line, elements)) = transaction.take() else {
+                    continue;
+                };
+                records.push(RawRecord {
+                    line,
+                    raw: text[start..end].lines().map(str::trim).collect(),
+                    parsed: decode(&elements, account, currency, &tx_ids, accounts),
+                });
+            }
+            Tag::End { .. } => {}
+        }
+    }
+    records.into_iter()
+}
+
+fn decode(
+    elements: &HashMap<&str, &str>,
+    account: Option<&str>,
+    currency: Option<Currency>,
+    tx_ids: &TxIdMap,
+    accounts: &AccountMap,
+) -> Result<InputRecord, PaymentError> {
+    let element = |name: &str| {
+        elements
+            .get(name)
+            .copied()
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| PaymentError::InvalidTransaction(format!("missing {}", name)))
+    };
+    let reference = element("FITID")?;
+    let invalid = |name: &str, value: &str| {
+        PaymentError::InvalidTransaction(format!(
+            "invalid {} '{}' in transaction {}",
+            name, value, reference
+        ))
+    };
+    let amount = element("TRNAMT")?;
+    let value: Decimal = amount
+        .replace(',', ".")
+        .parse()
+        .map_err(|_| invalid("TRNAMT", amount))?;
+    let posted = element("DTPOSTED")?;
+    let timestamp = date_time(posted).ok_or_else(|| invalid("DTPOSTED", posted))?;
+
+    let account = account.ok_or_else(|| {
+        PaymentError::InvalidTransaction("transaction before the ACCTID account".to_string())
+    })?;
+    let client_id = accounts.client(account).ok_or_else(|| {
+        PaymentError::InvalidTransaction(format!("account {} belongs to no client", account))
+    })?;
+    let currency = currency.ok_or_else(|| {
+        PaymentError::InvalidTransaction("transaction before the CURDEF currency".to_string())
+    })?;
+    let record_type = if value.is_sign_negative() {
+        TransactionType::Withdrawal
+    } else {
+        TransactionType::Deposit
+    };
+
+    Ok(InputRecord {
+        record_type,
+        client_id,
+        tx_id: tx_ids.map(reference)?,
+        amount: Some(value.abs()),
+        counterparty_id: None,
+        currency,
+        target_currency: None,
+        timestamp: Some(timestamp),
+        idempotency_key: None,
+        reference: None,
+        metadata: HashMap::new(),
+    })
+}
+
+/// A start tag with the text following it, which is the value of elements
+/// (closed or not), or an end tag.
+enum Tag<'a> {
+    Start {
+        name: &'a str,
+        value: &'a str,
+        start: usize,
+    },
+    End {
+        name: &'a str,
+        end: usize,
+    },
+}
+
+fn tags(text: &str) -> impl Iterator<Item = Tag<'_>> {
+    text.match_indices('<').filter_map(move |(start, _)| {
+        let rest = &text[start + 1..];
+        let close = rest.find('>')?;
+        let name = rest[..close].trim();
+        let after = &rest[close + 1..];
+        if let Some(name) = name.strip_prefix('/') {
+            return Some(Tag::End {
+                name,
+                end: start + close + 2,
+            });
+        }
+        // Processing instructions and comments.
+        if name.starts_with(['?', '!']) {
+            return None;
+        }
+        let value = after[..after.find('<').unwrap_or(after.len())].trim();
+        Some(Tag::Start { name, value, start })
+    })
+}
+
+/// The timestamp of a `YYYYMMDD[HHMMSS[.XXX]][[offset:TZ]]` date time, in
+/// GMT unless it gives an offset in hours.
+fn date_time(value: &str) -> Option<u64> {
+    let (local, zone) = match value.split_once('[') {
+        Some((local, zone)) => (local, Some(zone.trim_end_matches(']'))),
+        None => (value, None),
+    };
+    let local = local.split('.').next()?.trim();
+    let number = |range| {
+        local
+            .get(range)
+            .and_then(|digits: &str| digits.parse::<u32>().ok())
+    };
+    let date = date_timestamp(number(0..4)?, number(4..6)?, number(6..8)?)?;
+    let time = match local.len() {
+        8 => 0,
+        12 => number(8..10)? * 3_600 + number(10..12)? * 60,
+        14 => number(8..10)? * 3_600 + number(10..12)? * 60 + number(12..14)?,
+        _ => return None,
+    };
+    let offset = match zone {
+        Some(zone) => {
+            let hours: Decimal = zone.split(':').next()?.trim().parse().ok()?;
+            i64::try_from((hours * Decimal::from(3_600)).trunc()).ok()?
+        }
+        None => 0,
+    };
+    u64::try_from((date + u64::from(time)) as i64 - offset).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ClientId, TxId};
+    use crate::tx_ids::TxIdFormat;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    const OFX_SGML: &str = "OFXHEADER:100
+DATA:OFXSGML
+VERSION:102
+
+<OFX>
+<BANKMSGSRSV1><STMTTRNRS><STMTRS>
+<CURDEF>USD
+<BANKACCTFROM><BANKID>121000248<ACCTID>0001234567<ACCTTYPE>CHECKING</BANKACCTFROM>
+<BANKTRANLIST><DTSTART>20240301<DTEND>20240331
+<STMTTRN>
+<TRNTYPE>CREDIT
+<DTPOSTED>20240301120000.000[-5:EST]
+<TRNAMT>1500.00
+<FITID>2024030101
+<NAME>PAYROLL
+</STMTTRN>
+<STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20240302<TRNAMT>-42.10<FITID>2024030201</STMTTRN>
+</BANKTRANLIST>
+</STMTRS></STMTTRNRS></BANKMSGSRSV1>
+</OFX>
+";
+
+    const OFX_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<?OFX OFXHEADER="200" VERSION="220"?>
+<OFX>
+  <CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS>
+    <CURDEF>EUR</CURDEF>
+    <CCACCTFROM><ACCTID>4111 1111</ACCTID></CCACCTFROM>
+    <BANKTRANLIST>
+      <STMTTRN>
+        <TRNTYPE>PAYMENT</TRNTYPE>
+        <DTPOSTED>20240305</DTPOSTED>
+        <TRNAMT>-9.99</TRNAMT>
+        <FITID>CC-1</FITID>
+      </STMTTRN>
+    </BANKTRANLIST>
+  </CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1>
+</OFX>
+"#;
+
+    fn decode(ofx: &str, accounts: AccountMap) -> Vec<RawRecord> {
+        read_ofx(ofx.as_bytes(), TxIdMap::new(TxIdFormat::Text), &accounts).collect()
+    }
+
+    #[rstest]
+    fn test_read_ofx_sgml() {
+        let records = decode(OFX_SGML, AccountMap::new().with_account("0001234567", 1));
+        let decoded: Vec<(u64, TransactionType, ClientId, TxId, Decimal, Option<u64>)> = records
+            .iter()
+            .map(|record| {
+                let parsed = record.parsed.as_ref().unwrap();
+                assert_eq!(parsed.currency.as_str(), "USD");
+                (
+                    record.line,
+                    parsed.record_type,
+                    parsed.client_id,
+                    parsed.tx_id,
+                    parsed.amount.unwrap(),
+                    parsed.timestamp,
+                )
+            })
+            .collect();
+        assert_eq!(
+            decoded,
+            [
+                // Noon EST is 17:00 GMT.
+                (
+                    10,
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    dec!(1500.00),
+                    Some(1_709_312_400)
+                ),
+                (
+                    17,
+                    TransactionType::Withdrawal,
+                    1,
+                    2,
+                    dec!(42.10),
+                    Some(1_709_337_600)
+                ),
+            ]
+        );
+        assert_eq!(
+            records[1].raw,
+            "<STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20240302<TRNAMT>-42.10<FITID>2024030201</STMTTRN>"
+        );
+    }
+
+    #[rstest]
+    fn test_read_ofx_xml() {
+        let records = decode(OFX_XML, AccountMap::new().with_account("41111111", 2));
+        assert_eq!(records.len(), 1);
+        let parsed = records[0].parsed.as_ref().unwrap();
+        assert_eq!(records[0].line, 8);
+        assert_eq!(parsed.record_type, TransactionType::Withdrawal);
+        assert_eq!((parsed.client_id, parsed.currency.as_str()), (2, "EUR"));
+        assert_eq!(parsed.amount, Some(dec!(9.99)));
+        assert_eq!(parsed.timestamp, Some(1_709_596_800));
+    }
+
+    #[rstest]
+    #[case("<TRNAMT>-42.10", "<TRNAMT>lots", "invalid TRNAMT 'lots'")]
+    #[case(
+        "<DTPOSTED>20240302",
+        "<DTPOSTED>20240230",
+        "invalid DTPOSTED '20240230'"
+    )]
+    #[case("<FITID>2024030201", "", "missing FITID")]
+    #[case("<ACCTID>0001234567", "<ACCTID>99", "account 99 belongs to no client")]
+    fn test_read_ofx_rejects_transactions(
+        #[case] from: &str,
+        #[case] to: &str,
+        #[case] message: &str,
+    ) {
+        let ofx = OFX_SGML.replace(from, to);
+        let records = decode(&ofx, AccountMap::new().with_account("0001234567", 1));
+        let err = records[1].parsed.as_ref().unwrap_err().to_string();
+        assert!(err.contains(message), "{}", err);
+    }
+
+    #[rstest]
+    #[case("20240301", Some(1_709_251_200))]
+    #[case("202403011230", Some(1_709_296_200))]
+    #[case("20240301000000[+5.5:IST]", Some(1_709_231_400))]
+    #[case("20240301[0:GMT]", Some(1_709_251_200))]
+    #[case("2024030", None)]
+    #[case("19691231", None)]
+    fn test_date_time(#[case] value: &str, #[case] expected: Option<u64>) {
+        assert_eq!(date_time(value), expected);
+    }
+
+    #[rstest]
+    fn test_process_ofx_reads_client_ids() {
+        let ofx = OFX_SGML
+            .replace("0001234567", "7")
+            .replace("1500.00", "100");
+        let mut engine = PaymentEngine::new();
+        let report = process_ofx(ofx.as_bytes(), &mut engine).unwrap();
+        assert_eq!(report.records_read, 2);
+        let currency = "USD".parse().unwrap();
+        assert_eq!(
+            engine.get_account(7, currency).unwrap().available,
+            dec!(57.90)
+        );
+    }
+}
@@ -0,0 +1,386 @@
+#[cfg(feature = "arrow")]
+use crate::arrow;
+use crate::audit::AuditEntry;
+#[cfg(feature = "avro")]
+use crate::avro_handler;
+use crate::csv_handler;
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::json_handler;
+#[cfg(feature = "parquet")]
+use crate::parquet_handler;
+use crate::table;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+
+/// Supported account snapshot encodings.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// A single JSON array of accounts.
+    Json,
+    /// Newline-delimited JSON, one account per line.
+    JsonLines,
+    /// An aligned table for people to read (see [`table`](crate::table)),
+    /// with locked accounts in red if `color` is set.
+    Table { color: bool },
+    /// An Arrow IPC file of the [`arrow`](crate::arrow) batches.
+    #[cfg(feature = "arrow")]
+    Arrow,
+    /// A Parquet file of the same columns (see `parquet_handler`).
+    #[cfg(feature = "parquet")]
+    Parquet,
+    /// An Avro object container file (see `avro_handler`).
+    #[cfg(feature = "avro")]
+    Avro,
+}
+
+impl OutputFormat {
+    /// Whether the format is a binary file rather than text lines.
+    pub fn is_binary(self) -> bool {
+        match self {
+            OutputFormat::Csv
+            | OutputFormat::Json
+            | OutputFormat::JsonLines
+            | OutputFormat::Table { .. } => false,
+            #[cfg(any(feature = "arrow", feature = "avro"))]
+            _ => true,
+        }
+    }
+}
+
+/// Decimal places of amounts in every output format and report.
+pub(crate) const OUTPUT_SCALE: u32 = 4;
+
+/// The mantissa of `amount` at `OUTPUT_SCALE` decimal places, rounded like the
+/// text formats if it has more. Amounts too large to rescale keep fewer
+/// places, which the `i128` makes up for.
+#[cfg(any(feature = "arrow", feature = "avro"))]
+pub(crate) fn output_mantissa(mut amount: Decimal) -> i128 {
+    amount.rescale(OUTPUT_SCALE);
+    amount.mantissa() * 10i128.pow(OUTPUT_SCALE - amount.scale())
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" => Ok(OutputFormat::JsonLines),
+            "table" => Ok(OutputFormat::Table { color: false }),
+            #[cfg(feature = "arrow")]
+            "arrow" => Ok(OutputFormat::Arrow),
+            #[cfg(not(feature = "arrow"))]
+            "arrow" => Err("arrow output requires the arrow feature".to_string()),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(OutputFormat::Parquet),
+            #[cfg(not(feature = "parquet"))]
+            "parquet" => Err("parquet output requires the parquet feature".to_string()),
+            #[cfg(feature = "avro")]
+            "avro" => Ok(OutputFormat::Avro),
+            #[cfg(not(feature = "avro"))]
+            "avro" => Err("avro output requires the avro feature".to_string()),
+            other => Err(format!("unknown output format '{}'", other)),
+        }
+    }
+}
+
+/// Writes the final account states to `writer` in the requested `format`.
+pub fn write_output<W: Write + Send>(
+    engine: &PaymentEngine,
+    format: OutputFormat,
+    writer: W,
+) -> Result<(), PaymentError> {
+    match format {
+        OutputFormat::Csv => csv_handler::write_accounts(engine, writer),
+        OutputFormat::Json => json_handler::write_accounts_json(engine, writer),
+        OutputFormat::JsonLines => json_handler::write_accounts_json_lines(engine, writer),
+        OutputFormat::Table { color } => {
+            let accounts: Vec<_> = engine
+                .iter_accounts_sorted()
+                .map(|account| account.to_output_record())
+                .collect();
+            table::write_accounts_table(&accounts, color, writer)
+        }
+        #[cfg(feature = "arrow")]
+        OutputFormat::Arrow => arrow::write_ipc(&arrow::accounts_to_record_batch(engine)?, writer),
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => parquet_handler::write_accounts_parquet(engine, writer),
+        #[cfg(feature = "avro")]
+        OutputFormat::Avro => avro_handler::write_accounts_avro(engine, writer),
+    }
+}
+
+/// Writes the final account states to the file at `path` atomically.
+///
+/// The snapshot is written to a temporary file next to `path` and renamed over
+/// it once complete, so readers never observe a partially written file.
+pub fn write_output_file<P: AsRef<Path>>(
+    engine: &PaymentEngine,
+    format: OutputFormat,
+    path: P,
+) -> Result<(), PaymentError> {
+    write_file_atomically(path.as_ref(), |writer| write_output(engine, format, writer))
+}
+
+/// Writes a client statement (see `PaymentEngine::statement`) to `writer`.
+/// CSV uses the audit log columns.
+pub fn write_statement<W: Write + Send>(
+    entries: &[AuditEntry],
+    format: OutputFormat,
+    mut writer: W,
+) -> Result<(), PaymentError> {
+    match format {
+        OutputFormat::Csv => {
+            let mut wtr = csv::Writer::from_writer(writer);
+            for entry in entries {
+                wtr.serialize(entry)?;
+            }
+            wtr.flush()?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut writer, entries)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::JsonLines => {
+            for entry in entries {
+                serde_json::to_writer(&mut writer, entry)?;
+                writeln!(writer)?;
+            }
+        }
+        OutputFormat::Table { color } => table::write_statement_table(entries, color, writer)?,
+        #[cfg(feature = "arrow")]
+        OutputFormat::Arrow => {
+            arrow::write_ipc(&arrow::audit_entries_to_record_batch(entries)?, writer)?
+        }
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => parquet_handler::write_statement_parquet(entries, writer)?,
+        #[cfg(feature = "avro")]
+        OutputFormat::Avro => avro_handler::write_statement_avro(entries, writer)?,
+    }
+    Ok(())
+}
+
+/// Writes a client statement to the file at `path` atomically.
+pub fn write_statement_file<P: AsRef<Path>>(
+    entries: &[AuditEntry],
+    format: OutputFormat,
+    path: P,
+) -> Result<(), PaymentError> {
+    write_file_atomically(path.as_ref(), |writer| {
+        write_statement(entries, format, writer)
+    })
+}
+
+/// A row of an end-of-run report, like the settlement or the risk report.
+/// Reports are written as CSV or a JSON array of the serialized rows, with
+/// amounts at the output precision.
+pub trait ReportRow: Serialize + Clone {
+    /// The CSV header of a report without rows; otherwise it's taken from
+    /// the field names.
+    const COLUMNS: &'static [&'static str];
+
+    /// The amounts of the row.
+    fn amounts_mut(&mut self) -> Vec<&mut Decimal>;
+
+    /// Fills in the optional columns some of `rows` leave out, as every CSV
+    /// row needs the same columns. Nothing is optional by default.
+    fn fill_columns(_rows: &mut [Self]) {}
+}
+
+/// `rows` with their amounts at the output precision.
+fn at_output_scale<R: ReportRow>(rows: &[R]) -> Vec<R> {
+    rows.iter()
+        .cloned()
+        .map(|mut row| {
+            for amount in row.amounts_mut() {
+                amount.rescale(OUTPUT_SCALE);
+            }
+            row
+        })
+        .collect()
+}
+
+/// Writes a report as CSV.
+pub fn write_report_csv<R: ReportRow, W: Write>(rows: &[R], writer: W) -> Result<(), PaymentError> {
+    let mut rows = at_output_scale(rows);
+    R::fill_columns(&mut rows);
+    let mut wtr = csv::Writer::from_writer(writer);
+    if rows.is_empty() {
+        wtr.write_record(R::COLUMNS)?;
+    }
+    for row in &rows {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes a report as a single JSON array.
+pub fn write_report_json<R: ReportRow, W: Write>(
+    rows: &[R],
+    mut writer: W,
+) -> Result<(), PaymentError> {
+    serde_json::to_writer(&mut writer, &at_output_scale(rows))?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a report to the file at `path` atomically: as JSON if it ends in
+/// `.json`, as CSV otherwise.
+pub fn write_report_file<R: ReportRow, P: AsRef<Path>>(
+    rows: &[R],
+    path: P,
+) -> Result<(), PaymentError> {
+    let path = path.as_ref();
+    let json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    write_file_atomically(path, |writer| {
+        if json {
+            write_report_json(rows, writer)
+        } else {
+            write_report_csv(rows, writer)
+        }
+    })
+}
+
+/// Writes `path` through a temporary sibling renamed over it, so it's never
+/// seen half written.
+pub(crate) fn write_file_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut (dyn Write + Send)) -> Result<(), PaymentError>,
+) -> Result<(), PaymentError> {
+    let tmp_path = temp_path_for(path);
+
+    let result = write_synced(&tmp_path, write)
+        .and_then(|_| fs::rename(&tmp_path, path).map_err(PaymentError::from));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+fn write_synced(
+    path: &Path,
+    write: impl FnOnce(&mut (dyn Write + Send)) -> Result<(), PaymentError>,
+) -> Result<(), PaymentError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write(&mut writer)?;
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Returns a hidden, process-unique sibling path used while writing `path`.
+fn temp_path_for(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp-{}", file_name, process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("csv", Ok(OutputFormat::Csv))]
+    #[case("JSON", Ok(OutputFormat::Json))]
+    #[case("ndjson", Ok(OutputFormat::JsonLines))]
+    #[case("xml", Err("unknown output format 'xml'".to_string()))]
+    fn test_output_format_from_str(
+        #[case] input: &str,
+        #[case] expected: Result<OutputFormat, String>,
+    ) {
+        assert_eq!(input.parse::<OutputFormat>(), expected);
+    }
+
+    #[cfg(any(feature = "arrow", feature = "avro"))]
+    #[rstest]
+    #[case(Decimal::TEN, 100_000)]
+    #[case(Decimal::new(-5, 1), -5_000)]
+    #[case(Decimal::new(5, 5), 1)]
+    #[case(Decimal::new(15, 5), 2)]
+    #[case(Decimal::MAX, 792_281_625_142_643_375_935_439_503_350_000)]
+    fn test_output_mantissa(#[case] amount: Decimal, #[case] expected: i128) {
+        assert_eq!(output_mantissa(amount), expected);
+    }
+
+    #[rstest]
+    #[case(OutputFormat::Csv, "client,available,held,total,locked\n")]
+    #[case(OutputFormat::Json, "[]\n")]
+    #[case(OutputFormat::JsonLines, "")]
+    fn test_write_output_dispatches_on_format(
+        #[case] format: OutputFormat,
+        #[case] expected: &str,
+    ) {
+        let mut output = Vec::new();
+        write_output(&PaymentEngine::new(), format, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[rstest]
+    fn test_write_output_file_replaces_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.csv");
+        fs::write(&path, "stale").unwrap();
+
+        write_output_file(&PaymentEngine::new(), OutputFormat::Csv, &path).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "client,available,held,total,locked\n"
+        );
+        // Only the target remains, no temporary file is left behind.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[rstest]
+    fn test_write_output_file_missing_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing").join("accounts.csv");
+
+        let result = write_output_file(&PaymentEngine::new(), OutputFormat::Csv, &path);
+
+        assert!(matches!(result, Err(PaymentError::Io(_))));
+        assert!(!path.exists());
+    }
+
+    #[rstest]
+    #[case(
+        OutputFormat::Csv,
+        "tx,client,currency,action,amount,available,held,locked,metadata\n\
+         1,7,,deposit,2.5000,2.5000,0.0000,false,\n"
+    )]
+    #[case(
+        OutputFormat::JsonLines,
+        "{\"tx\":1,\"client\":7,\"currency\":\"\",\"action\":\"deposit\",\"amount\":\"2.5000\",\
+         \"available\":\"2.5000\",\"held\":\"0.0000\",\"locked\":false,\"metadata\":\"\"}\n"
+    )]
+    fn test_write_statement(#[case] format: OutputFormat, #[case] expected: &str) {
+        let mut account = crate::models::Account::new(7);
+        account.deposit(rust_decimal_macros::dec!(2.5)).unwrap();
+        let entries = [AuditEntry::new(
+            1,
+            crate::audit::Mutation::Deposit,
+            rust_decimal_macros::dec!(2.5),
+            &account,
+        )];
+
+        let mut output = Vec::new();
+        write_statement(&entries, format, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+}
@@ -0,0 +1,93 @@
+//! Account snapshots and statements written as Parquet, so they can be loaded
+//! into a data lake without converting the CSV first. The columns are those
+//! of the [`arrow`](crate::arrow) batches.
+
+use crate::arrow;
+use crate::audit::AuditEntry;
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use arrow_array::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use std::io::Write;
+
+/// Writes account states as a Parquet file with a single row group, sorted
+/// like the other formats.
+pub fn write_accounts_parquet<W: Write + Send>(
+    engine: &PaymentEngine,
+    writer: W,
+) -> Result<(), PaymentError> {
+    write_parquet(&arrow::accounts_to_record_batch(engine)?, writer)
+}
+
+/// Writes a client statement as a Parquet file with a single row group.
+pub fn write_statement_parquet<W: Write + Send>(
+    entries: &[AuditEntry],
+    writer: W,
+) -> Result<(), PaymentError> {
+    write_parquet(&arrow::audit_entries_to_record_batch(entries)?, writer)
+}
+
+/// Streams `batch` to `writer`, closing the file with its metadata, which
+/// Parquet puts after the rows.
+fn write_parquet<W: Write + Send>(batch: &RecordBatch, writer: W) -> Result<(), PaymentError> {
+    let mut file = ArrowWriter::try_new(writer, batch.schema(), None)?;
+    file.write(batch)?;
+    file.into_inner()?.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{InputRecord, TransactionType};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+    use std::collections::HashMap;
+
+    fn read_batches(file: std::fs::File) -> Vec<RecordBatch> {
+        ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[rstest]
+    fn test_write_accounts_parquet() {
+        let mut engine = PaymentEngine::new().with_statement_history();
+        for (client_id, tx_id) in [(2, 1), (1, 2)] {
+            engine
+                .process(InputRecord {
+                    record_type: TransactionType::Deposit,
+                    client_id,
+                    tx_id,
+                    amount: Some(dec!(1.5)),
+                    counterparty_id: None,
+                    currency: Default::default(),
+                    target_currency: None,
+                    timestamp: None,
+                    idempotency_key: None,
+                    reference: None,
+                    metadata: HashMap::new(),
+                })
+                .unwrap();
+        }
+
+        let mut file = tempfile::tempfile().unwrap();
+        write_accounts_parquet(&engine, &mut file).unwrap();
+        assert_eq!(
+            read_batches(file),
+            [arrow::accounts_to_record_batch(&engine).unwrap()]
+        );
+
+        let statement = engine.statement(1).unwrap();
+        let mut file = tempfile::tempfile().unwrap();
+        write_statement_parquet(statement, &mut file).unwrap();
+        assert_eq!(
+            read_batches(file),
+            [arrow::audit_entries_to_record_batch(statement).unwrap()]
+        );
+    }
+}
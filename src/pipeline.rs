@@ -0,0 +1,153 @@
+//! Decoding records on a thread of their own, so parsing the next rows
+//! overlaps with applying the previous ones.
+
+use crate::input::RawRecord;
+use std::num::NonZeroUsize;
+use std::panic;
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::vec;
+
+/// Records handed over at a time, so the channel isn't paid for per record.
+const BATCH_SIZE: usize = 256;
+
+/// Records decoded ahead on a parse thread, yielded in input order.
+///
+/// Dropping it early stops the parse thread at its next hand-over.
+pub struct ReadAhead {
+    batches: Receiver<Vec<RawRecord>>,
+    batch: vec::IntoIter<RawRecord>,
+    parser: Option<JoinHandle<()>>,
+}
+
+/// Decodes the records of `make_records` on a new thread, staying at most
+/// about `capacity` records ahead: the parser blocks once that many are
+/// waiting, so memory stays bounded however far behind the consumer falls.
+///
+/// `make_records` runs on the parse thread, so the readers it decodes don't
+/// need to be `Send`-able iterators themselves. A panic while parsing is
+/// raised again on the consumer once it has taken the records before it.
+pub fn read_ahead<F, I>(make_records: F, capacity: NonZeroUsize) -> ReadAhead
+where
+    F: FnOnce() -> I + Send + 'static,
+    I: IntoIterator<Item = RawRecord>,
+{
+    let (sender, batches) = mpsc::sync_channel(capacity.get().div_ceil(BATCH_SIZE));
+    let parser = thread::spawn(move || {
+        let mut records = make_records().into_iter();
+        loop {
+            let batch: Vec<_> = records.by_ref().take(BATCH_SIZE).collect();
+            if batch.is_empty() || sender.send(batch).is_err() {
+                break;
+            }
+        }
+    });
+    ReadAhead {
+        batches,
+        batch: Vec::new().into_iter(),
+        parser: Some(parser),
+    }
+}
+
+impl Iterator for ReadAhead {
+    type Item = RawRecord;
+
+    fn next(&mut self) -> Option<RawRecord> {
+        loop {
+            if let Some(record) = self.batch.next() {
+                return Some(record);
+            }
+            match self.batches.recv() {
+                Ok(batch) => self.batch = batch.into_iter(),
+                Err(_) => {
+                    if let Some(Err(panic)) = self.parser.take().map(JoinHandle::join) {
+                        panic::resume_unwind(panic);
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_handler::read_records;
+    use crate::generate::GeneratorConfig;
+    use rstest::rstest;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn record(line: u64) -> RawRecord {
+        RawRecord {
+            line,
+            raw: String::new(),
+            parsed: Err(crate::errors::PaymentError::InvalidTransaction(
+                "test".to_string(),
+            )),
+        }
+    }
+
+    #[rstest]
+    fn test_read_ahead_keeps_input_order() {
+        let config = GeneratorConfig {
+            transactions: 1_000,
+            invalid_ratio: 0.1,
+            ..GeneratorConfig::default()
+        };
+        let mut data = Vec::new();
+        config.generate(&mut data).unwrap();
+
+        let expected: Vec<_> = read_records(data.as_slice())
+            .map(|r| (r.line, r.raw))
+            .collect();
+        let records: Vec<_> = read_ahead(
+            move || read_records(std::io::Cursor::new(data)),
+            NonZeroUsize::new(100).unwrap(),
+        )
+        .map(|r| (r.line, r.raw))
+        .collect();
+        assert_eq!(records, expected);
+    }
+
+    #[rstest]
+    fn test_read_ahead_applies_backpressure() {
+        let produced = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&produced);
+        let mut records = read_ahead(
+            move || {
+                (1..).map(move |line| {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    record(line)
+                })
+            },
+            NonZeroUsize::MIN,
+        );
+
+        assert_eq!(records.next().unwrap().line, 1);
+        thread::sleep(Duration::from_millis(50));
+        // The batch being read, the one waiting in the channel and the one
+        // the parser is blocked on.
+        assert!(produced.load(Ordering::Relaxed) <= 3 * BATCH_SIZE);
+    }
+
+    #[rstest]
+    #[should_panic(expected = "parser failed")]
+    fn test_read_ahead_resumes_parser_panics() {
+        let records = read_ahead(
+            || {
+                (1..=3).map(|line| {
+                    if line < 3 {
+                        record(line)
+                    } else {
+                        panic!("parser failed")
+                    }
+                })
+            },
+            NonZeroUsize::MIN,
+        );
+        records.for_each(drop);
+    }
+}
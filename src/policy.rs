@@ -0,0 +1,296 @@
+use crate::models::{Account, InputRecord, TransactionDirection, TransactionInfo};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::str::FromStr;
+
+/// Decides whether a stored transaction may be disputed.
+///
+/// The engine consults the policy in `handle_dispute` before putting any funds
+/// on hold, so partners can plug in their own rules.
+pub trait DisputePolicy: Debug + Send + Sync {
+    /// Returns true if `tx` may be disputed given the current state of `account`.
+    fn allows_dispute(&self, tx: &TransactionInfo, account: &Account) -> bool;
+}
+
+/// Allows disputes on deposits and withdrawals of unlocked accounts.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultDisputePolicy;
+
+impl DisputePolicy for DefaultDisputePolicy {
+    fn allows_dispute(&self, _tx: &TransactionInfo, account: &Account) -> bool {
+        !account.locked
+    }
+}
+
+/// Only allows disputes on credits (deposits and received transfers).
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DepositsOnlyPolicy;
+
+impl DisputePolicy for DepositsOnlyPolicy {
+    fn allows_dispute(&self, tx: &TransactionInfo, account: &Account) -> bool {
+        !account.locked && tx.direction == TransactionDirection::Credit
+    }
+}
+
+/// What a [`RiskScorer`] decided about a record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RiskVerdict {
+    /// Apply the record as usual.
+    Accept,
+    /// Reject the record, for the reason given.
+    Reject(String),
+    /// Set the record aside unapplied until it's released or discarded (see
+    /// [`PaymentEngine::held`](crate::engine::PaymentEngine::held)).
+    Hold,
+}
+
+/// Scores records before they're applied, e.g. with a fraud model.
+///
+/// The engine asks for a verdict on every record it's about to apply, once
+/// duplicates and limits are ruled out, so in-house models can be plugged in
+/// without forking it.
+pub trait RiskScorer: Debug + Send + Sync {
+    /// Scores `record` given the current state of the client's account in
+    /// its currency, `None` if there isn't one yet.
+    fn score(&self, record: &InputRecord, account: Option<&Account>) -> RiskVerdict;
+}
+
+/// How dispute/resolve/chargeback rows naming a different client than the
+/// referenced transaction are handled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientMatchMode {
+    /// Reject the row with an error.
+    #[default]
+    Strict,
+    /// Apply the row to the transaction's owner regardless of the client field.
+    Lenient,
+}
+
+/// How deposits to accounts locked by a chargeback are handled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LockedAccountPolicy {
+    /// Credit the deposit to available funds as usual.
+    #[default]
+    Accept,
+    /// Credit the deposit to held funds, frozen with the account.
+    Hold,
+    /// Reject the deposit with an error.
+    Reject,
+}
+
+/// What to do when a deposit is disputed after its funds were spent, leaving
+/// too little available to hold.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnderfundedDisputeMode {
+    /// Drop the dispute.
+    #[default]
+    Ignore,
+    /// Hold the full amount anyway, letting available go negative.
+    AllowNegative,
+    /// Keep the dispute pending until enough funds arrive to hold it.
+    Queue,
+}
+
+/// What happens to disputes left open past the maximum dispute age (see
+/// [`PaymentEngine::expire_disputes`](crate::engine::PaymentEngine::expire_disputes)).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExpiredDisputeAction {
+    /// Release the held funds to the client.
+    #[default]
+    Resolve,
+    /// Escalate to a chargeback, locking the account.
+    Chargeback,
+}
+
+impl FromStr for ExpiredDisputeAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "resolve" => Ok(ExpiredDisputeAction::Resolve),
+            "chargeback" => Ok(ExpiredDisputeAction::Chargeback),
+            other => Err(format!("unknown expired dispute action '{}'", other)),
+        }
+    }
+}
+
+impl ExpiredDisputeAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ExpiredDisputeAction::Resolve => "resolve",
+            ExpiredDisputeAction::Chargeback => "chargeback",
+        }
+    }
+}
+
+/// What to do when a deposit, withdrawal, transfer, auth or convert reuses a
+/// tx id already taken by an earlier record.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateTxPolicy {
+    /// Drop the record.
+    #[default]
+    Ignore,
+    /// Drop the record and log a warning.
+    Warn,
+    /// Reject the record with an error.
+    Error,
+}
+
+impl FromStr for DuplicateTxPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ignore" => Ok(DuplicateTxPolicy::Ignore),
+            "warn" => Ok(DuplicateTxPolicy::Warn),
+            "error" => Ok(DuplicateTxPolicy::Error),
+            other => Err(format!("unknown duplicate tx policy '{}'", other)),
+        }
+    }
+}
+
+/// Decimal places kept in balances; input amounts with more are handled by
+/// an [`AmountPrecision`].
+pub const AMOUNT_DECIMAL_PLACES: u32 = 4;
+
+/// What to do with input amounts that have more than four decimal places.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AmountPrecision {
+    /// Reject the record with an error.
+    #[default]
+    Reject,
+    /// Drop the extra digits.
+    Truncate,
+    /// Round to the nearest value, ties to even (banker's rounding).
+    RoundHalfEven,
+}
+
+impl AmountPrecision {
+    /// Brings `amount` down to four decimal places, or `None` if it has more
+    /// and the policy is to reject it.
+    pub fn apply(self, amount: Decimal) -> Option<Decimal> {
+        if amount.normalize().scale() <= AMOUNT_DECIMAL_PLACES {
+            return Some(amount);
+        }
+        match self {
+            AmountPrecision::Reject => None,
+            AmountPrecision::Truncate => Some(amount.trunc_with_scale(AMOUNT_DECIMAL_PLACES)),
+            AmountPrecision::RoundHalfEven => Some(amount.round_dp_with_strategy(
+                AMOUNT_DECIMAL_PLACES,
+                RoundingStrategy::MidpointNearestEven,
+            )),
+        }
+    }
+}
+
+impl FromStr for AmountPrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "reject" => Ok(AmountPrecision::Reject),
+            "truncate" => Ok(AmountPrecision::Truncate),
+            "round-half-even" => Ok(AmountPrecision::RoundHalfEven),
+            other => Err(format!("unknown amount precision '{}'", other)),
+        }
+    }
+}
+
+/// What to do when a record can't be decoded or is rejected by the engine.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Log a warning and continue with the next record.
+    #[default]
+    Skip,
+    /// Abort processing on the first invalid record.
+    FailFast,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Currency, TransactionState};
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    #[rstest]
+    #[case(TransactionDirection::Credit, false, true, true)]
+    #[case(TransactionDirection::Debit, false, true, false)]
+    #[case(TransactionDirection::Credit, true, false, false)]
+    #[case(TransactionDirection::Debit, true, false, false)]
+    fn test_builtin_policies(
+        #[case] direction: TransactionDirection,
+        #[case] locked: bool,
+        #[case] expected_default: bool,
+        #[case] expected_deposits_only: bool,
+    ) {
+        let tx = TransactionInfo {
+            client_id: 1,
+            amount: dec!(10.0),
+            state: TransactionState::Normal,
+            direction,
+            disputes: 0,
+            currency: Currency::default(),
+            timestamp: None,
+        };
+        let mut account = Account::new(1);
+        account.locked = locked;
+
+        assert_eq!(
+            DefaultDisputePolicy.allows_dispute(&tx, &account),
+            expected_default
+        );
+        assert_eq!(
+            DepositsOnlyPolicy.allows_dispute(&tx, &account),
+            expected_deposits_only
+        );
+    }
+
+    #[rstest]
+    #[case(AmountPrecision::Reject, dec!(1.2345), Some(dec!(1.2345)))]
+    #[case(AmountPrecision::Reject, dec!(1.500000), Some(dec!(1.500000)))]
+    #[case(AmountPrecision::Reject, dec!(1.23456), None)]
+    #[case(AmountPrecision::Truncate, dec!(1.23459), Some(dec!(1.2345)))]
+    #[case(AmountPrecision::Truncate, dec!(-1.23459), Some(dec!(-1.2345)))]
+    #[case(AmountPrecision::RoundHalfEven, dec!(1.23445), Some(dec!(1.2344)))]
+    #[case(AmountPrecision::RoundHalfEven, dec!(1.23455), Some(dec!(1.2346)))]
+    #[case(AmountPrecision::RoundHalfEven, dec!(1.234501), Some(dec!(1.2345)))]
+    fn test_amount_precision(
+        #[case] precision: AmountPrecision,
+        #[case] amount: Decimal,
+        #[case] expected: Option<Decimal>,
+    ) {
+        assert_eq!(precision.apply(amount), expected);
+    }
+
+    #[rstest]
+    #[case("reject", Ok(AmountPrecision::Reject))]
+    #[case("Truncate", Ok(AmountPrecision::Truncate))]
+    #[case("round-half-even", Ok(AmountPrecision::RoundHalfEven))]
+    #[case("round", Err("unknown amount precision 'round'".to_string()))]
+    fn test_amount_precision_from_str(
+        #[case] input: &str,
+        #[case] expected: Result<AmountPrecision, String>,
+    ) {
+        assert_eq!(input.parse::<AmountPrecision>(), expected);
+    }
+
+    #[rstest]
+    #[case("ignore", Ok(DuplicateTxPolicy::Ignore))]
+    #[case("WARN", Ok(DuplicateTxPolicy::Warn))]
+    #[case("error", Ok(DuplicateTxPolicy::Error))]
+    #[case("skip", Err("unknown duplicate tx policy 'skip'".to_string()))]
+    fn test_duplicate_tx_policy_from_str(
+        #[case] input: &str,
+        #[case] expected: Result<DuplicateTxPolicy, String>,
+    ) {
+        assert_eq!(input.parse::<DuplicateTxPolicy>(), expected);
+    }
+}
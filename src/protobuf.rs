@@ -0,0 +1,559 @@
+//! Protobuf messages for transactions and account snapshots, defined by
+//! `proto/payment_engine.proto`, so producers in any language can write data
+//! the engine reads.
+//!
+//! The message types below are written out by hand from the schema, as
+//! `prost-build` would generate them, so building the crate doesn't need
+//! `protoc`. Keep them in sync when the schema changes.
+//!
+//! Transaction files are streams of length-delimited `Transaction` messages,
+//! as `writeDelimitedTo` and its counterparts in other languages write them.
+
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::input::{process_records, RawRecord};
+use crate::models::{ClientId, Currency, InputRecord, OutputRecord, TransactionType, TxId};
+use crate::report::ProcessingReport;
+use prost::Message;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::iter;
+
+/// Messages of the `payment_engine.v1` protobuf package.
+pub mod pb {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum TransactionType {
+        Unspecified = 0,
+        Deposit = 1,
+        Withdrawal = 2,
+        Dispute = 3,
+        Resolve = 4,
+        Chargeback = 5,
+        Transfer = 6,
+        Refund = 7,
+        Auth = 8,
+        Capture = 9,
+        Void = 10,
+        Close = 11,
+        Admin = 12,
+        Convert = 13,
+        CreditAdjustment = 14,
+        DebitAdjustment = 15,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+    pub struct Transaction {
+        #[prost(enumeration = "TransactionType", tag = "1")]
+        pub r#type: i32,
+        #[prost(uint64, tag = "2")]
+        pub client: u64,
+        #[prost(uint64, tag = "3")]
+        pub tx: u64,
+        #[prost(string, optional, tag = "4")]
+        pub amount: Option<String>,
+        #[prost(uint64, optional, tag = "5")]
+        pub counterparty: Option<u64>,
+        #[prost(string, tag = "6")]
+        pub currency: String,
+        #[prost(string, optional, tag = "7")]
+        pub to_currency: Option<String>,
+        #[prost(uint64, optional, tag = "8")]
+        pub timestamp: Option<u64>,
+        #[prost(string, optional, tag = "9")]
+        pub idempotency_key: Option<String>,
+        #[prost(string, optional, tag = "10")]
+        pub reference: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+    pub struct Rejection {
+        #[prost(uint64, tag = "1")]
+        pub position: u64,
+        #[prost(uint64, tag = "2")]
+        pub tx: u64,
+        #[prost(string, tag = "3")]
+        pub reason: String,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+    pub struct StreamSummary {
+        #[prost(uint64, tag = "1")]
+        pub received: u64,
+        #[prost(message, repeated, tag = "2")]
+        pub rejected: Vec<Rejection>,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+    pub struct GetAccountRequest {
+        #[prost(uint64, tag = "1")]
+        pub client: u64,
+        #[prost(string, tag = "2")]
+        pub currency: String,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+    pub struct Account {
+        #[prost(uint64, tag = "1")]
+        pub client: u64,
+        #[prost(string, tag = "2")]
+        pub currency: String,
+        #[prost(string, tag = "3")]
+        pub available: String,
+        #[prost(string, tag = "4")]
+        pub held: String,
+        #[prost(string, tag = "5")]
+        pub total: String,
+        #[prost(bool, tag = "6")]
+        pub locked: bool,
+        #[prost(bool, tag = "7")]
+        pub closed: bool,
+        #[prost(string, tag = "8")]
+        pub overdraft: String,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+    pub struct AccountSnapshot {
+        #[prost(message, repeated, tag = "1")]
+        pub accounts: Vec<Account>,
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, prost::Message)]
+    pub struct ListAccountsRequest {}
+
+    #[derive(Clone, PartialEq, Eq, Hash, prost::Message)]
+    pub struct ListAccountsResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub accounts: Vec<Account>,
+    }
+}
+
+impl From<TransactionType> for pb::TransactionType {
+    fn from(record_type: TransactionType) -> Self {
+        match record_type {
+            TransactionType::Deposit => pb::TransactionType::Deposit,
+            TransactionType::Withdrawal => pb::TransactionType::Withdrawal,
+            TransactionType::Dispute => pb::TransactionType::Dispute,
+            TransactionType::Resolve => pb::TransactionType::Resolve,
+            TransactionType::Chargeback => pb::TransactionType::Chargeback,
+            TransactionType::Transfer => pb::TransactionType::Transfer,
+            TransactionType::Refund => pb::TransactionType::Refund,
+            TransactionType::Auth => pb::TransactionType::Auth,
+            TransactionType::Capture => pb::TransactionType::Capture,
+            TransactionType::Void => pb::TransactionType::Void,
+            TransactionType::Close => pb::TransactionType::Close,
+            TransactionType::Admin => pb::TransactionType::Admin,
+            TransactionType::Convert => pb::TransactionType::Convert,
+            TransactionType::CreditAdjustment => pb::TransactionType::CreditAdjustment,
+            TransactionType::DebitAdjustment => pb::TransactionType::DebitAdjustment,
+        }
+    }
+}
+
+impl From<&InputRecord> for pb::Transaction {
+    fn from(record: &InputRecord) -> Self {
+        pb::Transaction {
+            r#type: pb::TransactionType::from(record.record_type) as i32,
+            client: u64::from(record.client_id),
+            tx: u64::from(record.tx_id),
+            amount: record.amount.map(|amount| amount.to_string()),
+            counterparty: record.counterparty_id.map(u64::from),
+            currency: record.currency.to_string(),
+            to_currency: record.target_currency.map(|currency| currency.to_string()),
+            timestamp: record.timestamp,
+            idempotency_key: record.idempotency_key.clone(),
+            reference: record.reference.clone(),
+        }
+    }
+}
+
+impl TryFrom<pb::Transaction> for InputRecord {
+    type Error = PaymentError;
+
+    fn try_from(tx: pb::Transaction) -> Result<Self, Self::Error> {
+        let invalid = |what: String| PaymentError::InvalidTransaction(what);
+        let record_type = match pb::TransactionType::try_from(tx.r#type) {
+            Ok(pb::TransactionType::Deposit) => TransactionType::Deposit,
+            Ok(pb::TransactionType::Withdrawal) => TransactionType::Withdrawal,
+            Ok(pb::TransactionType::Dispute) => TransactionType::Dispute,
+            Ok(pb::TransactionType::Resolve) => TransactionType::Resolve,
+            Ok(pb::TransactionType::Chargeback) => TransactionType::Chargeback,
+            Ok(pb::TransactionType::Transfer) => TransactionType::Transfer,
+            Ok(pb::TransactionType::Refund) => TransactionType::Refund,
+            Ok(pb::TransactionType::Auth) => TransactionType::Auth,
+            Ok(pb::TransactionType::Capture) => TransactionType::Capture,
+            Ok(pb::TransactionType::Void) => TransactionType::Void,
+            Ok(pb::TransactionType::Close) => TransactionType::Close,
+            Ok(pb::TransactionType::Admin) => TransactionType::Admin,
+            Ok(pb::TransactionType::Convert) => TransactionType::Convert,
+            Ok(pb::TransactionType::CreditAdjustment) => TransactionType::CreditAdjustment,
+            Ok(pb::TransactionType::DebitAdjustment) => TransactionType::DebitAdjustment,
+            Ok(pb::TransactionType::Unspecified) | Err(_) => {
+                return Err(invalid(format!("unknown transaction type {}", tx.r#type)))
+            }
+        };
+        let client = |id: u64| {
+            ClientId::try_from(id).map_err(|_| invalid(format!("invalid client id {}", id)))
+        };
+        let currency = |code: &str| code.parse::<Currency>().map_err(invalid);
+        Ok(InputRecord {
+            record_type,
+            client_id: client(tx.client)?,
+            tx_id: TxId::try_from(tx.tx)
+                .map_err(|_| invalid(format!("invalid tx id {}", tx.tx)))?,
+            amount: tx
+                .amount
+                .map(|amount| {
+                    amount
+                        .trim()
+                        .parse()
+                        .map_err(|_| invalid(format!("invalid amount '{}'", amount)))
+                })
+                .transpose()?,
+            counterparty_id: tx.counterparty.map(client).transpose()?,
+            currency: currency(&tx.currency)?,
+            target_currency: tx.to_currency.as_deref().map(currency).transpose()?,
+            timestamp: tx.timestamp,
+            idempotency_key: tx.idempotency_key,
+            reference: tx.reference,
+            metadata: HashMap::new(),
+        })
+    }
+}
+
+impl From<OutputRecord> for pb::Account {
+    fn from(mut account: OutputRecord) -> Self {
+        for amount in [
+            &mut account.available,
+            &mut account.held,
+            &mut account.total,
+            &mut account.overdraft,
+        ] {
+            amount.rescale(4);
+        }
+        pb::Account {
+            client: u64::from(account.client_id),
+            currency: account.currency.to_string(),
+            available: account.available.to_string(),
+            held: account.held.to_string(),
+            total: account.total.to_string(),
+            locked: account.locked,
+            closed: account.closed,
+            overdraft: account.overdraft.to_string(),
+        }
+    }
+}
+
+impl TryFrom<pb::Account> for OutputRecord {
+    type Error = PaymentError;
+
+    fn try_from(account: pb::Account) -> Result<Self, Self::Error> {
+        let invalid = |what: String| PaymentError::Protobuf(what);
+        let amount = |amount: &str| {
+            amount
+                .trim()
+                .parse::<Decimal>()
+                .map_err(|_| invalid(format!("invalid amount '{}'", amount)))
+        };
+        Ok(OutputRecord {
+            client_id: ClientId::try_from(account.client)
+                .map_err(|_| invalid(format!("invalid client id {}", account.client)))?,
+            currency: account.currency.parse().map_err(invalid)?,
+            available: amount(&account.available)?,
+            held: amount(&account.held)?,
+            total: amount(&account.total)?,
+            locked: account.locked,
+            closed: account.closed,
+            overdraft: amount(&account.overdraft)?,
+        })
+    }
+}
+
+/// Processes a stream of length-delimited `Transaction` messages.
+pub fn process_protobuf<R: Read>(
+    reader: R,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError> {
+    process_records(read_protobuf(reader), engine)
+}
+
+/// Lazily decodes a stream of length-delimited `Transaction` messages.
+/// Records are numbered from 1 and have no raw text. A message that doesn't
+/// decode is skipped, but a length cut short ends the stream, since the next
+/// message can't be located.
+pub fn read_protobuf<R: Read>(reader: R) -> impl Iterator<Item = RawRecord> {
+    let mut reader = BufReader::new(reader);
+    let mut line = 0;
+    let mut done = false;
+    iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        line += 1;
+        let parsed = match read_delimited(&mut reader) {
+            Ok(None) => return None,
+            Ok(Some(message)) => pb::Transaction::decode(message.as_slice())
+                .map_err(|e| PaymentError::Protobuf(e.to_string()))
+                .and_then(InputRecord::try_from),
+            Err(e) => {
+                done = true;
+                Err(e)
+            }
+        };
+        Some(RawRecord {
+            line,
+            raw: String::new(),
+            parsed,
+        })
+    })
+}
+
+/// Reads the next length-delimited message, or `None` at the end of the
+/// stream.
+fn read_delimited<R: BufRead>(reader: &mut R) -> Result<Option<Vec<u8>>, PaymentError> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let truncated = |e: io::Error| match e.kind() {
+        io::ErrorKind::UnexpectedEof => PaymentError::Protobuf("truncated message".to_string()),
+        _ => e.into(),
+    };
+    let mut length = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte).map_err(truncated)?;
+        length |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            let mut message = Vec::new();
+            reader.take(length).read_to_end(&mut message)?;
+            if (message.len() as u64) < length {
+                return Err(PaymentError::Protobuf("truncated message".to_string()));
+            }
+            return Ok(Some(message));
+        }
+    }
+    Err(PaymentError::Protobuf("invalid message length".to_string()))
+}
+
+/// Writes records as a stream of length-delimited `Transaction` messages.
+pub struct ProtobufWriter<W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> ProtobufWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn write(&mut self, record: &InputRecord) -> Result<(), PaymentError> {
+        self.buffer.clear();
+        pb::Transaction::from(record)
+            .encode_length_delimited(&mut self.buffer)
+            .map_err(|e| PaymentError::Protobuf(e.to_string()))?;
+        self.writer.write_all(&self.buffer)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), PaymentError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes the accounts, sorted by client and currency, as an
+/// `AccountSnapshot` message.
+pub fn write_account_snapshot<W: Write>(
+    engine: &PaymentEngine,
+    mut writer: W,
+) -> Result<(), PaymentError> {
+    let snapshot = pb::AccountSnapshot {
+        accounts: engine
+            .iter_accounts_sorted()
+            .map(|account| pb::Account::from(account.to_output_record()))
+            .collect(),
+    };
+    writer.write_all(&snapshot.encode_to_vec())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads the accounts of an `AccountSnapshot` message.
+pub fn read_account_snapshot<R: Read>(mut reader: R) -> Result<Vec<OutputRecord>, PaymentError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    pb::AccountSnapshot::decode(bytes.as_slice())
+        .map_err(|e| PaymentError::Protobuf(e.to_string()))?
+        .accounts
+        .into_iter()
+        .map(OutputRecord::try_from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_handler;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    fn transaction(
+        r#type: pb::TransactionType,
+        client: u64,
+        tx: u64,
+        amount: &str,
+    ) -> pb::Transaction {
+        pb::Transaction {
+            r#type: r#type as i32,
+            client,
+            tx,
+            amount: (!amount.is_empty()).then(|| amount.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    fn test_transaction_to_input_record() {
+        let record = InputRecord::try_from(pb::Transaction {
+            counterparty: Some(2),
+            currency: "eur".to_string(),
+            timestamp: Some(60),
+            ..transaction(pb::TransactionType::Transfer, 1, 7, " 1.25 ")
+        })
+        .unwrap();
+        assert_eq!(record.record_type, TransactionType::Transfer);
+        assert_eq!(record.client_id, 1);
+        assert_eq!(record.tx_id, 7);
+        assert_eq!(record.amount, Some(dec!(1.25)));
+        assert_eq!(record.counterparty_id, Some(2));
+        assert_eq!(record.currency.as_str(), "EUR");
+        assert_eq!(record.timestamp, Some(60));
+    }
+
+    #[rstest]
+    #[case(
+        transaction(pb::TransactionType::Unspecified, 1, 1, "1"),
+        "unknown transaction type 0"
+    )]
+    #[case(pb::Transaction { r#type: 99, ..Default::default() }, "unknown transaction type 99")]
+    #[case(
+        transaction(pb::TransactionType::Deposit, 1, 1, "lots"),
+        "invalid amount 'lots'"
+    )]
+    #[case(
+        pb::Transaction { currency: "EU-R".to_string(), ..transaction(pb::TransactionType::Deposit, 1, 1, "1") },
+        "invalid currency 'EU-R'"
+    )]
+    fn test_invalid_transaction(#[case] tx: pb::Transaction, #[case] reason: &str) {
+        let err = InputRecord::try_from(tx).unwrap_err();
+        assert_eq!(err.to_string(), format!("Invalid transaction: {}", reason));
+    }
+
+    #[cfg(not(feature = "wide-ids"))]
+    #[rstest]
+    #[case(
+        transaction(pb::TransactionType::Deposit, 70_000, 1, "1"),
+        "invalid client id 70000"
+    )]
+    #[case(
+        transaction(pb::TransactionType::Deposit, 1, 1 << 32, "1"),
+        "invalid tx id 4294967296"
+    )]
+    fn test_rejects_ids_past_their_width(#[case] tx: pb::Transaction, #[case] reason: &str) {
+        let err = InputRecord::try_from(tx).unwrap_err();
+        assert_eq!(err.to_string(), format!("Invalid transaction: {}", reason));
+    }
+
+    const CSV: &str =
+        "type,client,tx,amount,counterparty,currency,to_currency,timestamp,idempotency_key
+deposit,1,1,10.5,,EUR,,1700000000,k-1
+transfer,1,2,2.25,2,EUR,,,
+convert,1,3,1.0,,EUR,USD,,
+chargeback,1,1,,,,,,
+";
+
+    fn records() -> Vec<InputRecord> {
+        csv_handler::read_records(CSV.as_bytes())
+            .map(|record| record.parsed.unwrap())
+            .collect()
+    }
+
+    fn encode(records: &[InputRecord]) -> Vec<u8> {
+        let mut writer = ProtobufWriter::new(Vec::new());
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        writer.flush().unwrap();
+        writer.writer
+    }
+
+    #[rstest]
+    fn test_protobuf_round_trip() {
+        let records = records();
+        let decoded: Vec<InputRecord> = read_protobuf(encode(&records).as_slice())
+            .map(|record| record.parsed.unwrap())
+            .collect();
+        assert_eq!(decoded, records);
+    }
+
+    #[rstest]
+    fn test_read_protobuf_reports_bad_messages() {
+        let mut encoded = encode(&records()[..1]);
+        transaction(pb::TransactionType::Unspecified, 1, 2, "1")
+            .encode_length_delimited(&mut encoded)
+            .unwrap();
+        // A message that isn't a transaction, then one cut short.
+        encoded.extend_from_slice(&[2, 0xff, 0xff]);
+        encoded.extend_from_slice(&[9, 0x08]);
+        let decoded: Vec<RawRecord> = read_protobuf(encoded.as_slice()).collect();
+        assert_eq!(
+            decoded.iter().map(|record| record.line).collect::<Vec<_>>(),
+            [1, 2, 3, 4]
+        );
+        assert!(decoded[0].parsed.is_ok());
+        let err = decoded[1].parsed.as_ref().unwrap_err().to_string();
+        assert!(err.contains("unknown transaction type 0"), "{}", err);
+        assert!(matches!(decoded[2].parsed, Err(PaymentError::Protobuf(_))));
+        let err = decoded[3].parsed.as_ref().unwrap_err().to_string();
+        assert_eq!(err, "Protobuf error: truncated message");
+    }
+
+    #[rstest]
+    fn test_process_protobuf() {
+        let mut engine = PaymentEngine::new();
+        let report = process_protobuf(encode(&records()).as_slice(), &mut engine).unwrap();
+        assert_eq!(report.records_read, 4);
+        let eur = engine.get_account(1, "EUR".parse().unwrap()).unwrap();
+        assert_eq!(eur.available, dec!(8.25));
+    }
+
+    #[rstest]
+    fn test_account_snapshot_round_trip() {
+        let mut engine = PaymentEngine::new();
+        process_protobuf(encode(&records()).as_slice(), &mut engine).unwrap();
+        let mut snapshot = Vec::new();
+        write_account_snapshot(&engine, &mut snapshot).unwrap();
+        let accounts = read_account_snapshot(snapshot.as_slice()).unwrap();
+        let expected: Vec<OutputRecord> = engine
+            .iter_accounts_sorted()
+            .map(|account| account.to_output_record())
+            .collect();
+        assert_eq!(accounts, expected);
+        assert_eq!(accounts[0].available, dec!(8.25));
+    }
+
+    #[rstest]
+    fn test_invalid_account() {
+        let account = pb::Account {
+            client: 1,
+            available: "lots".to_string(),
+            ..Default::default()
+        };
+        let err = OutputRecord::try_from(account).unwrap_err();
+        assert_eq!(err.to_string(), "Protobuf error: invalid amount 'lots'");
+    }
+}
@@ -0,0 +1,369 @@
+//! Quicken Interchange Format exports, so personal-finance users can replay
+//! their bank history through the engine.
+//!
+//! QIF names accounts but neither transactions nor currencies: entries belong
+//! to the client owning the account of the latest `!Account` block, in the
+//! default currency, and are referenced by that account and their position.
+//! Entries of bank, cash, credit card and other asset or liability sections
+//! are read; investment, category and memorized lists are ignored.
+
+use crate::account_map::AccountMap;
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::input::{process_records, RawRecord};
+use crate::iso20022::date_timestamp;
+use crate::models::{Currency, InputRecord, TransactionType};
+use crate::report::ProcessingReport;
+use crate::tx_ids::{TxIdFormat, TxIdMap};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Split};
+use std::iter::Zip;
+use std::ops::RangeFrom;
+
+/// The `!Type:` sections whose entries move money.
+const CASH_SECTIONS: [&str; 5] = ["bank", "cash", "ccard", "oth a", "oth l"];
+
+/// Processes the entries of a QIF file, reading account names as client ids.
+/// References are never numeric, so they're mapped as strings.
+pub fn process_qif<R: Read>(
+    reader: R,
+    engine: &mut PaymentEngine,
+) -> Result<ProcessingReport, PaymentError> {
+    process_records(
+        read_qif(
+            reader,
+            TxIdMap::new(TxIdFormat::Text),
+            &AccountMap::default(),
+        ),
+        engine,
+    )
+}
+
+/// Lazily decodes the entries of a QIF file. Their tx id is the account name
+/// and the entry's position in that account, like `Checking/3`, read through
+/// `tx_ids`, and their timestamp the `D` date, read as month, day and year.
+pub fn read_qif<R: Read>(
+    reader: R,
+    tx_ids: TxIdMap,
+    accounts: &AccountMap,
+) -> impl Iterator<Item = RawRecord> {
+    QifRecords {
+        lines: BufReader::new(reader).split(b'\n').zip(1..),
+        section: Section::Other,
+        account: None,
+        entries: HashMap::new(),
+        entry: Vec::new(),
+        start: 0,
+        tx_ids,
+        accounts: accounts.clone(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Account,
+    Cash,
+    Other,
+}
+
+struct QifRecords<R> {
+    lines: Zip<Split<BufReader<R>>, RangeFrom<u64>>,
+    section: Section,
+    account: Option<String>,
+    /// How many entries each account had so far.
+    entries: HashMap<String, usize>,
+    /// The lines of the entry being read, and the line it started on.
+    entry: Vec<String>,
+    start: u64,
+    tx_ids: TxIdMap,
+    accounts: AccountMap,
+}
+
+impl<R: Read> Iterator for QifRecords<R> {
+    type Item = RawRecord;
+
+    fn next(&mut self) -> Option<RawRecord> {
+        for (line, number) in self.lines.by_ref() {
+            let line = match line {
+                Ok(line) => String::from_utf8_lossy(&line).trim().to_string(),
+                Err(e) => {
+                    return Some(RawRecord {
+                        line: number,
+                        raw: String::new(),
+                        parsed: Err(e.into()),
+                    })
+                }
+            };
+            if let Some(header) = line.strip_prefix('!') {
+                let header = header.to_ascii_lowercase();
+                if header == "account" {
+                    self.section = Section::Account;
+                } else if let Some(kind) = header.strip_prefix("type:") {
+                    self.section = if CASH_SECTIONS.contains(&kind.trim()) {
+                        Section::Cash
+                    } else {
+                        Section::Other
+                    };
+                }
+                // `!Option:` and `!Clear:` switches don't start sections.
+                self.entry.clear();
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            if !line.starts_with('^') {
+                if self.entry.is_empty() {
+                    self.start = number;
+                }
+                self.entry.push(line);
+                continue;
+            }
+            let entry = std::mem::take(&mut self.entry);
+            match self.section {
+                Section::Account => {
+                    self.account = field(&entry, 'N').map(str::to_string);
+                }
+                Section::Cash if !entry.is_empty() => {
+                    return Some(RawRecord {
+                        line: self.start,
+                        parsed: self.decode(&entry),
+                        raw: entry.join("|"),
+                    });
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+impl<R> QifRecords<R> {
+    fn decode(&mut self, entry: &[String]) -> Result<InputRecord, PaymentError> {
+        let account = self.account.as_deref().ok_or_else(|| {
+            PaymentError::InvalidTransaction("entry outside an !Account block".to_string())
+        })?;
+        let position = self.entries.entry(account.to_string()).or_default();
+        *position += 1;
+        let reference = format!("{}/{}", account, position);
+        let invalid = |name: &str, value: &str| {
+            PaymentError::InvalidTransaction(format!(
+                "invalid {} '{}' in entry {}",
+                name, value, reference
+            ))
+        };
+        let missing = |name: &str| {
+            PaymentError::InvalidTransaction(format!("missing {} in entry {}", name, reference))
+        };
+
+        let amount = field(entry, 'T')
+            .or_else(|| field(entry, 'U'))
+            .ok_or_else(|| missing("amount"))?;
+        let value: Decimal = amount
+            .replace(',', "")
+            .parse()
+            .map_err(|_| invalid("amount", amount))?;
+        let date = field(entry, 'D').ok_or_else(|| missing("date"))?;
+        let timestamp = date_of(date).ok_or_else(|| invalid("date", date))?;
+        let client_id = self.accounts.client(account).ok_or_else(|| {
+            PaymentError::InvalidTransaction(format!("account {} belongs to no client", account))
+        })?;
+        let record_type = if value.is_sign_negative() {
+            TransactionType::Withdrawal
+        } else {
+            TransactionType::Deposit
+        };
+
+        Ok(InputRecord {
+            record_type,
+            client_id,
+            tx_id: self.tx_ids.map(&reference)?,
+            amount: Some(value.abs()),
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: Some(timestamp),
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        })
+    }
+}
+
+/// The value of the first line of `entry` starting with `code`.
+fn field(entry: &[String], code: char) -> Option<&str> {
+    entry
+        .iter()
+        .find_map(|line| line.strip_prefix(code))
+        .map(str::trim)
+}
+
+/// The timestamp of a Quicken date: month, day and year in any separators,
+/// like `3/1/2024`, ` 3/ 1'24` or `03-01-24`, or a `2024-03-01` ISO date.
+/// Two-digit years before 70 are this century's.
+fn date_of(date: &str) -> Option<u64> {
+    let parts: Vec<u32> = date
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let [first, second, third] = parts[..] else {
+        return None;
+    };
+    let (year, month, day) = if first > 31 {
+        (first, second, third)
+    } else {
+        (third, first, second)
+    };
+    let year = match year {
+        0..=69 => 2000 + year,
+        70..=99 => 1900 + year,
+        _ => year,
+    };
+    date_timestamp(year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ClientId, TxId};
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    const QIF: &str = "!Option:AutoSwitch
+!Account
+NChecking
+TBank
+^
+!Clear:AutoSwitch
+!Type:Bank
+D03/01/2024
+T1,500.00
+PPayroll
+^
+D 3/ 2'24
+T-42.10
+N1001
+PGrocer
+LFood
+^
+!Type:Cat
+NFood
+E
+^
+!Account
+NVisa
+TCCard
+^
+!Type:CCard
+D2024-03-05
+U-9.99
+^
+";
+
+    fn decode(qif: &str, accounts: AccountMap) -> Vec<RawRecord> {
+        read_qif(qif.as_bytes(), TxIdMap::new(TxIdFormat::Text), &accounts).collect()
+    }
+
+    #[rstest]
+    fn test_read_qif() {
+        let accounts = AccountMap::new()
+            .with_account("Checking", 1)
+            .with_account("Visa", 2);
+        let records = decode(QIF, accounts);
+        let decoded: Vec<(u64, TransactionType, ClientId, TxId, Decimal, Option<u64>)> = records
+            .iter()
+            .map(|record| {
+                let parsed = record.parsed.as_ref().unwrap();
+                (
+                    record.line,
+                    parsed.record_type,
+                    parsed.client_id,
+                    parsed.tx_id,
+                    parsed.amount.unwrap(),
+                    parsed.timestamp,
+                )
+            })
+            .collect();
+        let day = |n: u64| Some(1_709_251_200 + (n - 1) * 86_400);
+        assert_eq!(
+            decoded,
+            [
+                (8, TransactionType::Deposit, 1, 1, dec!(1500.00), day(1)),
+                (12, TransactionType::Withdrawal, 1, 2, dec!(42.10), day(2)),
+                (27, TransactionType::Withdrawal, 2, 3, dec!(9.99), day(5)),
+            ]
+        );
+        assert_eq!(records[0].raw, "D03/01/2024|T1,500.00|PPayroll");
+    }
+
+    #[rstest]
+    fn test_read_qif_references_entries_by_account() {
+        let accounts = AccountMap::new()
+            .with_account("Checking", 1)
+            .with_account("Visa", 2);
+        let tx_ids = TxIdMap::new(TxIdFormat::Text);
+        let records: Vec<RawRecord> = read_qif(QIF.as_bytes(), tx_ids.clone(), &accounts).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(tx_ids.map("Checking/2").unwrap(), 2);
+        assert_eq!(tx_ids.map("Visa/1").unwrap(), 3);
+    }
+
+    #[rstest]
+    #[case("!Type:Bank\nD3/1/24\nT1\n^\n", "entry outside an !Account block")]
+    #[case(
+        "!Account\nNSavings\n^\n!Type:Bank\nD3/1/24\nT1\n^\n",
+        "account Savings belongs to no client"
+    )]
+    #[case(
+        "!Account\nN1\n^\n!Type:Bank\nD3/1/24\n^\n",
+        "missing amount in entry 1/1"
+    )]
+    #[case("!Account\nN1\n^\n!Type:Bank\nT1\n^\n", "missing date in entry 1/1")]
+    #[case(
+        "!Account\nN1\n^\n!Type:Bank\nD2/30/24\nT1\n^\n",
+        "invalid date '2/30/24'"
+    )]
+    #[case(
+        "!Account\nN1\n^\n!Type:Bank\nD3/1/24\nTten\n^\n",
+        "invalid amount 'ten'"
+    )]
+    fn test_read_qif_rejects_entries(#[case] qif: &str, #[case] message: &str) {
+        let records = decode(qif, AccountMap::new());
+        let err = records[0].parsed.as_ref().unwrap_err().to_string();
+        assert!(err.contains(message), "{}", err);
+    }
+
+    #[rstest]
+    fn test_read_qif_ignores_other_sections() {
+        let qif = "!Account\nN1\n^\n!Type:Invst\nD3/1/24\nT100\nNBuy\n^\n!Type:Memorized\nT5\n^\n";
+        assert!(decode(qif, AccountMap::new()).is_empty());
+    }
+
+    #[rstest]
+    #[case("03/01/2024", Some(1_709_251_200))]
+    #[case(" 3/ 1'24", Some(1_709_251_200))]
+    #[case("3-1-99", Some(920_246_400))]
+    #[case("2024-03-01", Some(1_709_251_200))]
+    #[case("13/01/2024", None)]
+    #[case("3/1", None)]
+    fn test_date_of(#[case] date: &str, #[case] expected: Option<u64>) {
+        assert_eq!(date_of(date), expected);
+    }
+
+    #[rstest]
+    fn test_process_qif_reads_client_ids() {
+        let qif = "!Account\nN7\n^\n!Type:Bank\nD3/1/24\nT10\n^\nD3/2/24\nT-4\n^\n";
+        let mut engine = PaymentEngine::new();
+        let report = process_qif(qif.as_bytes(), &mut engine).unwrap();
+        assert_eq!(report.records_read, 2);
+        assert_eq!(
+            engine
+                .get_account(7, Currency::default())
+                .unwrap()
+                .available,
+            dec!(6)
+        );
+    }
+}
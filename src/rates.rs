@@ -0,0 +1,128 @@
+use crate::errors::PaymentError;
+use crate::models::Currency;
+use rust_decimal::Decimal;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Source of the exchange rates used by `convert` records.
+///
+/// The engine asks for a rate each time it applies a conversion, so
+/// implementations can serve live rates.
+pub trait RateProvider: Debug + Send + Sync {
+    /// Units of `to` bought by one unit of `from`, or `None` if the pair isn't
+    /// quoted.
+    fn rate(&self, from: Currency, to: Currency) -> Option<Decimal>;
+}
+
+/// Fixed table of exchange rates. A pair quoted one way is also used the
+/// other way at the inverse rate.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StaticRates {
+    rates: HashMap<(Currency, Currency), Decimal>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RateRow {
+    from: Currency,
+    to: Currency,
+    rate: Decimal,
+}
+
+impl StaticRates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Quotes one unit of `from` at `rate` units of `to`.
+    pub fn with_rate(mut self, from: Currency, to: Currency, rate: Decimal) -> Self {
+        self.rates.insert((from, to), rate);
+        self
+    }
+
+    /// Reads a `from,to,rate` CSV table. Rates must be positive.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, PaymentError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let mut rates = Self::new();
+        for row in rdr.deserialize() {
+            let RateRow { from, to, rate } = row?;
+            if rate <= Decimal::ZERO {
+                return Err(PaymentError::InvalidRates(format!(
+                    "rate from '{}' to '{}' must be positive",
+                    from, to
+                )));
+            }
+            rates = rates.with_rate(from, to, rate);
+        }
+        Ok(rates)
+    }
+
+    /// Reads a `from,to,rate` CSV table from the file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PaymentError> {
+        Self::from_reader(File::open(path)?)
+    }
+}
+
+impl RateProvider for StaticRates {
+    fn rate(&self, from: Currency, to: Currency) -> Option<Decimal> {
+        if from == to {
+            return Some(Decimal::ONE);
+        }
+        match self.rates.get(&(from, to)) {
+            Some(rate) => Some(*rate),
+            None => self
+                .rates
+                .get(&(to, from))
+                .and_then(|rate| Decimal::ONE.checked_div(*rate)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    fn currency(code: &str) -> Currency {
+        code.parse().unwrap()
+    }
+
+    #[rstest]
+    #[case("USD", "EUR", Some(dec!(0.8)))]
+    #[case("EUR", "USD", Some(dec!(1.25)))]
+    #[case("USD", "USD", Some(dec!(1)))]
+    #[case("USD", "GBP", None)]
+    fn test_static_rates(#[case] from: &str, #[case] to: &str, #[case] expected: Option<Decimal>) {
+        let rates = StaticRates::new().with_rate(currency("USD"), currency("EUR"), dec!(0.8));
+        assert_eq!(rates.rate(currency(from), currency(to)), expected);
+    }
+
+    #[rstest]
+    fn test_static_rates_from_reader() {
+        let input = "from,to,rate\nusd,eur,0.8\nGBP, USD, 1.3\n";
+        let rates = StaticRates::from_reader(input.as_bytes()).unwrap();
+
+        assert_eq!(
+            rates.rate(currency("USD"), currency("EUR")),
+            Some(dec!(0.8))
+        );
+        assert_eq!(
+            rates.rate(currency("GBP"), currency("USD")),
+            Some(dec!(1.3))
+        );
+    }
+
+    #[rstest]
+    #[case("from,to,rate\nUSD,EUR,0\n")]
+    #[case("from,to,rate\nUSD,EUR,-1.5\n")]
+    fn test_static_rates_rejects_non_positive(#[case] input: &str) {
+        let result = StaticRates::from_reader(input.as_bytes());
+        assert!(matches!(result, Err(PaymentError::InvalidRates(_))));
+    }
+}
@@ -0,0 +1,221 @@
+//! Reconciliation of the books: the funds applied records moved into and out
+//! of the engine, summed per currency, against the balances it holds.
+
+use crate::audit::Mutation;
+use crate::models::Currency;
+use rust_decimal::Decimal;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+/// Funds that entered and left the engine in one currency, as the records
+/// applied say. Transfers and fees move funds between accounts and aren't
+/// flows.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Flows {
+    /// Balances the engine started from: accounts loaded from an account
+    /// store, or restored from a snapshot that didn't carry flows.
+    pub opening: Decimal,
+    pub deposits: Decimal,
+    /// Withdrawals, refunds and captures.
+    pub withdrawals: Decimal,
+    /// Deposits taken back by chargebacks.
+    pub chargebacks: Decimal,
+    /// Disputed withdrawals held for (or returned to) their client, less
+    /// those resolved.
+    pub reversals: Decimal,
+    /// Interest posted.
+    pub interest: Decimal,
+    /// Funds converted into the currency, less those converted out of it.
+    pub conversions: Decimal,
+    /// Chargebacked funds credited to the suspense account.
+    pub written_off: Decimal,
+    /// Credit adjustments, less debit adjustments.
+    pub adjustments: Decimal,
+}
+
+impl Flows {
+    /// What the accounts in the currency should total.
+    pub fn expected_total(&self) -> Decimal {
+        self.opening
+            .saturating_add(self.deposits)
+            .saturating_sub(self.withdrawals)
+            .saturating_sub(self.chargebacks)
+            .saturating_add(self.reversals)
+            .saturating_add(self.interest)
+            .saturating_add(self.conversions)
+            .saturating_add(self.written_off)
+            .saturating_add(self.adjustments)
+    }
+
+    /// Counts a balance change made by `mutation` whose effect on the total
+    /// doesn't depend on the direction of the transaction: disputes, resolves
+    /// and chargebacks are counted by the engine itself.
+    pub(crate) fn record(&mut self, mutation: Mutation, amount: Decimal) {
+        let flow = match mutation {
+            Mutation::Deposit => &mut self.deposits,
+            Mutation::Withdrawal | Mutation::Refund | Mutation::Capture => &mut self.withdrawals,
+            Mutation::Interest => &mut self.interest,
+            Mutation::ConvertIn => &mut self.conversions,
+            Mutation::ConvertOut => {
+                self.conversions = self.conversions.saturating_sub(amount);
+                return;
+            }
+            Mutation::WriteOff => &mut self.written_off,
+            Mutation::CreditAdjustment => &mut self.adjustments,
+            Mutation::DebitAdjustment => {
+                self.adjustments = self.adjustments.saturating_sub(amount);
+                return;
+            }
+            Mutation::TransferOut
+            | Mutation::TransferIn
+            | Mutation::Auth
+            | Mutation::Void
+            | Mutation::Dispute(_)
+            | Mutation::Resolve(_)
+            | Mutation::Chargeback(_)
+            | Mutation::Close
+            | Mutation::Admin
+            | Mutation::Block
+            | Mutation::Fee
+            | Mutation::FeeIncome => return,
+        };
+        *flow = flow.saturating_add(amount);
+    }
+
+    /// Adds the flows of another partition of the same engine.
+    pub(crate) fn add(&mut self, other: &Flows) {
+        for (flow, other) in [
+            (&mut self.opening, other.opening),
+            (&mut self.deposits, other.deposits),
+            (&mut self.withdrawals, other.withdrawals),
+            (&mut self.chargebacks, other.chargebacks),
+            (&mut self.reversals, other.reversals),
+            (&mut self.interest, other.interest),
+            (&mut self.conversions, other.conversions),
+            (&mut self.written_off, other.written_off),
+            (&mut self.adjustments, other.adjustments),
+        ] {
+            *flow = flow.saturating_add(other);
+        }
+    }
+}
+
+/// The books of one currency.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Reconciliation {
+    pub currency: Currency,
+    /// What the flows say the accounts should total.
+    pub expected: Decimal,
+    /// What the accounts total, house and suspense accounts included.
+    pub actual: Decimal,
+    pub flows: Flows,
+}
+
+impl Reconciliation {
+    /// How much more the accounts hold than the flows account for.
+    pub fn difference(&self) -> Decimal {
+        self.actual.saturating_sub(self.expected)
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.actual == self.expected
+    }
+}
+
+impl fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "currency '{}': accounts total {}, records account for {} (difference {})",
+            self.currency,
+            self.actual,
+            self.expected,
+            self.difference()
+        )
+    }
+}
+
+/// The books of every currency an engine has seen, from
+/// [`PaymentEngine::reconcile`](crate::engine::PaymentEngine::reconcile).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReconciliationReport {
+    /// One entry per currency, in currency order.
+    pub currencies: Vec<Reconciliation>,
+}
+
+impl ReconciliationReport {
+    /// Whether the books of every currency balance.
+    pub fn is_balanced(&self) -> bool {
+        self.currencies.iter().all(Reconciliation::is_balanced)
+    }
+
+    /// The currencies whose books don't balance.
+    pub fn differences(&self) -> impl Iterator<Item = &Reconciliation> {
+        self.currencies.iter().filter(|r| !r.is_balanced())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    #[rstest]
+    fn test_flows_expected_total() {
+        let mut flows = Flows {
+            opening: dec!(100),
+            ..Flows::default()
+        };
+        for (mutation, amount) in [
+            (Mutation::Deposit, dec!(50)),
+            (Mutation::Withdrawal, dec!(20)),
+            (Mutation::Refund, dec!(5)),
+            (Mutation::Capture, dec!(5)),
+            (Mutation::Interest, dec!(0.25)),
+            (Mutation::ConvertOut, dec!(10)),
+            (Mutation::ConvertIn, dec!(3)),
+            (Mutation::TransferOut, dec!(7)),
+            (Mutation::Fee, dec!(1)),
+            (Mutation::WriteOff, dec!(4)),
+            (Mutation::CreditAdjustment, dec!(2)),
+            (Mutation::DebitAdjustment, dec!(0.5)),
+        ] {
+            flows.record(mutation, amount);
+        }
+        flows.chargebacks = dec!(4);
+        assert_eq!(flows.conversions, dec!(-7));
+        assert_eq!(flows.adjustments, dec!(1.5));
+        assert_eq!(flows.expected_total(), dec!(114.75));
+    }
+
+    #[rstest]
+    fn test_reconciliation_report() {
+        let balanced = Reconciliation {
+            currency: Currency::default(),
+            expected: dec!(10),
+            actual: dec!(10),
+            flows: Flows::default(),
+        };
+        let short = Reconciliation {
+            currency: "EUR".parse().unwrap(),
+            expected: dec!(10),
+            actual: dec!(7.5),
+            flows: Flows::default(),
+        };
+        let report = ReconciliationReport {
+            currencies: vec![balanced.clone(), short.clone()],
+        };
+        assert!(!report.is_balanced());
+        assert_eq!(report.differences().collect::<Vec<_>>(), [&short]);
+        assert_eq!(
+            short.to_string(),
+            "currency 'EUR': accounts total 7.5, records account for 10 (difference -2.5)"
+        );
+        assert!(ReconciliationReport {
+            currencies: vec![balanced]
+        }
+        .is_balanced());
+    }
+}
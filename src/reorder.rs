@@ -0,0 +1,156 @@
+//! Reordering of slightly out-of-order feeds, as distributed producers write
+//! them, so records are applied in timestamp order.
+//!
+//! Records wait in a buffer until one at least `window` seconds newer has
+//! arrived, then leave it earliest first. A record older than one already
+//! released is too late to be put in order and is dropped, and reported as
+//! [`SkipKind::Late`](crate::report::SkipKind::Late).
+
+use crate::errors::PaymentError;
+use crate::input::RawRecord;
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+
+/// Reorders `records` by timestamp, tolerating records up to `window` seconds
+/// late. At most `capacity` records are held; when it's full, the earliest
+/// leaves early. Records with the same timestamp keep their order, and those
+/// without one (or that don't decode) keep their place behind the record that
+/// arrived before them.
+pub fn reorder_by_timestamp<I>(
+    records: I,
+    window: u64,
+    capacity: NonZeroUsize,
+) -> ReorderByTimestamp<I::IntoIter>
+where
+    I: IntoIterator<Item = RawRecord>,
+{
+    ReorderByTimestamp {
+        records: records.into_iter(),
+        window,
+        capacity: capacity.get(),
+        pending: BTreeMap::new(),
+        arrivals: 0,
+        last: 0,
+        newest: 0,
+        released: None,
+        exhausted: false,
+    }
+}
+
+/// The records of a feed in timestamp order (see [`reorder_by_timestamp`]).
+pub struct ReorderByTimestamp<I> {
+    records: I,
+    window: u64,
+    capacity: usize,
+    /// Buffered records by timestamp and arrival.
+    pending: BTreeMap<(u64, u64), RawRecord>,
+    arrivals: u64,
+    /// The timestamp of the last record that arrived with one.
+    last: u64,
+    /// The newest timestamp seen.
+    newest: u64,
+    /// The timestamp of the last record released.
+    released: Option<u64>,
+    exhausted: bool,
+}
+
+impl<I: Iterator<Item = RawRecord>> Iterator for ReorderByTimestamp<I> {
+    type Item = RawRecord;
+
+    fn next(&mut self) -> Option<RawRecord> {
+        loop {
+            if let Some(&(timestamp, _)) = self.pending.keys().next() {
+                if self.exhausted
+                    || self.pending.len() >= self.capacity
+                    || timestamp.saturating_add(self.window) <= self.newest
+                {
+                    self.released = Some(timestamp);
+                    return self.pending.pop_first().map(|(_, record)| record);
+                }
+            }
+            if self.exhausted {
+                return None;
+            }
+            let Some(mut record) = self.records.next() else {
+                self.exhausted = true;
+                continue;
+            };
+            if let Some(timestamp) = record.parsed.as_ref().ok().and_then(|r| r.timestamp) {
+                if let Some(released) = self.released.filter(|&released| timestamp < released) {
+                    record.parsed = Err(PaymentError::LateRecord(format!(
+                        "timestamp {} arrived after records up to {} were applied",
+                        timestamp, released
+                    )));
+                    return Some(record);
+                }
+                self.last = timestamp;
+                self.newest = self.newest.max(timestamp);
+            }
+            self.arrivals += 1;
+            self.pending.insert((self.last, self.arrivals), record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_handler::read_records;
+    use crate::engine::PaymentEngine;
+    use crate::input::process_records;
+    use crate::report::SkipKind;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    const FEED: &str = "type,client,tx,amount,timestamp
+deposit,1,1,5,100
+deposit,1,2,5,130
+deposit,1,3,5,110
+withdrawal,1,4,1,
+deposit,1,5,5,200
+deposit,1,6,5,120
+deposit,1,7,5,200
+";
+
+    fn reorder(window: u64, capacity: usize) -> Vec<(u64, Option<u64>)> {
+        let capacity = NonZeroUsize::new(capacity).unwrap();
+        reorder_by_timestamp(read_records(FEED.as_bytes()), window, capacity)
+            .map(|record| (record.line, record.parsed.ok().map(|r| u64::from(r.tx_id))))
+            .collect()
+    }
+
+    #[rstest]
+    // Undated tx 4 stays behind tx 3, and tx 6, 80s behind tx 5, is in time.
+    #[case(100, 100, &[(2, Some(1)), (4, Some(3)), (5, Some(4)), (7, Some(6)), (3, Some(2)), (6, Some(5)), (8, Some(7))])]
+    // With 20s, tx 2 at 130 was released before tx 6 at 120 came.
+    #[case(20, 100, &[(2, Some(1)), (4, Some(3)), (5, Some(4)), (3, Some(2)), (7, None), (6, Some(5)), (8, Some(7))])]
+    // A buffer of two records releases tx 2 before tx 6 comes as well.
+    #[case(100, 2, &[(2, Some(1)), (4, Some(3)), (5, Some(4)), (3, Some(2)), (7, None), (6, Some(5)), (8, Some(7))])]
+    fn test_reorder_by_timestamp(
+        #[case] window: u64,
+        #[case] capacity: usize,
+        #[case] expected: &[(u64, Option<u64>)],
+    ) {
+        assert_eq!(reorder(window, capacity), expected);
+    }
+
+    #[rstest]
+    fn test_reorder_reports_late_records() {
+        let mut engine = PaymentEngine::new();
+        let capacity = NonZeroUsize::new(100).unwrap();
+        let report = process_records(
+            reorder_by_timestamp(read_records(FEED.as_bytes()), 20, capacity),
+            &mut engine,
+        )
+        .unwrap();
+        assert_eq!(report.records_read, 7);
+        assert_eq!(report.count(SkipKind::Late), 1);
+        assert_eq!(report.skipped[0].line, 7);
+        assert_eq!(
+            report.skipped[0].reason,
+            "Late record: timestamp 120 arrived after records up to 130 were applied"
+        );
+        let account = engine.get_account(1, Default::default()).unwrap();
+        assert_eq!(account.available, dec!(24));
+    }
+}
@@ -0,0 +1,291 @@
+use crate::errors::PaymentError;
+use crate::policy::ErrorPolicy;
+use serde_derive::{Deserialize, Serialize};
+use std::io::Write;
+
+/// Why a record wasn't applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SkipKind {
+    /// The record couldn't be decoded.
+    Decode,
+    /// The engine rejected the transaction.
+    Rejected,
+    /// The record reused the idempotency key of a different record.
+    Conflict,
+    /// The record arrived too late to be applied in timestamp order (see
+    /// `reorder`).
+    Late,
+    /// The transaction would have broken a limit (see `limits`).
+    Limit,
+    /// The client, or the counterparty, is on the blocklist (see
+    /// `blocklist`).
+    Blocked,
+}
+
+/// A record that was read but not applied to the engine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedRecord {
+    /// 1-based line number in the input.
+    pub line: u64,
+    /// The record as it appeared in the input.
+    pub raw: String,
+    pub kind: SkipKind,
+    /// Human-readable reason, as it was logged.
+    pub reason: String,
+}
+
+impl SkipKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            SkipKind::Decode => "decode",
+            SkipKind::Rejected => "rejected",
+            SkipKind::Conflict => "conflict",
+            SkipKind::Late => "late",
+            SkipKind::Limit => "limit",
+            SkipKind::Blocked => "blocked",
+        }
+    }
+}
+
+/// Outcome of a processing run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProcessingReport {
+    /// Records read from the input, including skipped ones.
+    pub records_read: u64,
+    /// Records that weren't applied, in input order.
+    pub skipped: Vec<SkippedRecord>,
+}
+
+impl ProcessingReport {
+    /// Logs and records a skipped record, or turns it into an error under
+    /// `ErrorPolicy::FailFast`. I/O errors are never skipped: they mean the
+    /// input, a transaction store or the audit log is unusable.
+    pub(crate) fn record_failure(
+        &mut self,
+        policy: ErrorPolicy,
+        line: u64,
+        raw: String,
+        kind: SkipKind,
+        error: PaymentError,
+    ) -> Result<(), PaymentError> {
+        if let PaymentError::Io(_) = error {
+            return Err(error);
+        }
+        if policy == ErrorPolicy::FailFast {
+            return Err(PaymentError::AtLine {
+                line,
+                source: Box::new(error),
+            });
+        }
+
+        let kind = match error {
+            PaymentError::IdempotencyConflict(_) => SkipKind::Conflict,
+            PaymentError::LateRecord(_) => SkipKind::Late,
+            PaymentError::LimitExceeded(_) => SkipKind::Limit,
+            PaymentError::Blocked(_) => SkipKind::Blocked,
+            _ => kind,
+        };
+        let reason = error.to_string();
+        match kind {
+            SkipKind::Decode => {
+                tracing::warn!("Skipping bad record: line {}: {}: {}", line, raw, reason)
+            }
+            SkipKind::Late => {
+                tracing::warn!("Dropping late record: line {}: {}: {}", line, raw, reason)
+            }
+            SkipKind::Rejected | SkipKind::Conflict | SkipKind::Limit | SkipKind::Blocked => {
+                tracing::warn!(
+                    "Error processing transaction: line {}: {}: {}",
+                    line,
+                    raw,
+                    reason
+                )
+            }
+        }
+        self.skipped.push(SkippedRecord {
+            line,
+            raw,
+            kind,
+            reason,
+        });
+        Ok(())
+    }
+
+    /// Number of skipped records of the given kind.
+    pub fn count(&self, kind: SkipKind) -> usize {
+        self.skipped.iter().filter(|s| s.kind == kind).count()
+    }
+
+    /// Folds in the report of another worker over the same input.
+    pub(crate) fn absorb(&mut self, other: ProcessingReport) {
+        self.records_read += other.records_read;
+        self.skipped.extend(other.skipped);
+        self.skipped.sort_by_key(|s| s.line);
+    }
+
+    /// Writes the skipped records as CSV (`line,kind,reason,record`), keeping
+    /// each raw record intact so it can be corrected and fed back in.
+    pub fn write_rejects<W: Write>(&self, writer: W) -> Result<(), PaymentError> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record(["line", "kind", "reason", "record"])?;
+        for skipped in &self.skipped {
+            wtr.write_record([
+                skipped.line.to_string().as_str(),
+                skipped.kind.as_str(),
+                &skipped.reason,
+                &skipped.raw,
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn invalid(msg: &str) -> PaymentError {
+        PaymentError::InvalidTransaction(msg.to_string())
+    }
+
+    #[rstest]
+    fn test_record_failure_skip_collects_record() {
+        let mut report = ProcessingReport::default();
+        report
+            .record_failure(
+                ErrorPolicy::Skip,
+                7,
+                "withdrawal,1,2,-1".to_string(),
+                SkipKind::Rejected,
+                invalid("negative"),
+            )
+            .unwrap();
+
+        assert_eq!(
+            report.skipped,
+            vec![SkippedRecord {
+                line: 7,
+                raw: "withdrawal,1,2,-1".to_string(),
+                kind: SkipKind::Rejected,
+                reason: "Invalid transaction: negative".to_string(),
+            }]
+        );
+    }
+
+    #[rstest]
+    fn test_record_failure_idempotency_conflict() {
+        let mut report = ProcessingReport::default();
+        report
+            .record_failure(
+                ErrorPolicy::Skip,
+                4,
+                "deposit,1,2,1.0,,,,,a".to_string(),
+                SkipKind::Rejected,
+                PaymentError::IdempotencyConflict("key 'a' was already used by tx 1".to_string()),
+            )
+            .unwrap();
+
+        assert_eq!(report.skipped[0].kind, SkipKind::Conflict);
+    }
+
+    #[rstest]
+    fn test_record_failure_fail_fast_returns_error() {
+        let mut report = ProcessingReport::default();
+        let result = report.record_failure(
+            ErrorPolicy::FailFast,
+            3,
+            "bogus".to_string(),
+            SkipKind::Decode,
+            invalid("bad"),
+        );
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "line 3: Invalid transaction: bad"
+        );
+        assert!(report.skipped.is_empty());
+    }
+
+    #[rstest]
+    fn test_count_by_kind() {
+        let skipped = |kind| SkippedRecord {
+            line: 1,
+            raw: String::new(),
+            kind,
+            reason: String::new(),
+        };
+        let report = ProcessingReport {
+            records_read: 4,
+            skipped: vec![
+                skipped(SkipKind::Decode),
+                skipped(SkipKind::Rejected),
+                skipped(SkipKind::Decode),
+            ],
+        };
+
+        assert_eq!(report.count(SkipKind::Decode), 2);
+        assert_eq!(report.count(SkipKind::Rejected), 1);
+        assert_eq!(report.count(SkipKind::Conflict), 0);
+    }
+
+    #[rstest]
+    fn test_absorb_keeps_input_order() {
+        let skipped = |line| SkippedRecord {
+            line,
+            raw: String::new(),
+            kind: SkipKind::Decode,
+            reason: String::new(),
+        };
+        let mut report = ProcessingReport {
+            records_read: 5,
+            skipped: vec![skipped(2), skipped(9)],
+        };
+        report.absorb(ProcessingReport {
+            records_read: 0,
+            skipped: vec![skipped(4)],
+        });
+
+        assert_eq!(report.records_read, 5);
+        let lines: Vec<u64> = report.skipped.iter().map(|s| s.line).collect();
+        assert_eq!(lines, [2, 4, 9]);
+    }
+
+    #[rstest]
+    fn test_write_rejects() {
+        let report = ProcessingReport {
+            records_read: 3,
+            skipped: vec![SkippedRecord {
+                line: 3,
+                raw: "withdrawal,1,2,-1".to_string(),
+                kind: SkipKind::Rejected,
+                reason: "Invalid transaction: negative".to_string(),
+            }],
+        };
+        let mut buf = Vec::new();
+        report.write_rejects(&mut buf).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "line,kind,reason,record\n\
+             3,rejected,Invalid transaction: negative,\"withdrawal,1,2,-1\"\n"
+        );
+    }
+
+    #[rstest]
+    fn test_record_failure_io_error_is_fatal() {
+        let mut report = ProcessingReport::default();
+        let result = report.record_failure(
+            ErrorPolicy::Skip,
+            2,
+            String::new(),
+            SkipKind::Rejected,
+            PaymentError::Io(std::io::Error::other("disk full")),
+        );
+
+        assert!(matches!(result, Err(PaymentError::Io(_))));
+        assert!(report.skipped.is_empty());
+    }
+}
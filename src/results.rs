@@ -0,0 +1,138 @@
+use crate::csv_handler::format_amount;
+use crate::engine::PaymentEngine;
+use crate::errors::PaymentError;
+use crate::models::{ClientId, Currency, InputRecord, TransactionType, TxId};
+use serde_derive::Serialize;
+use std::io::Write;
+
+/// What became of an input record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordStatus {
+    /// The engine applied it and balances moved (or the account changed).
+    Applied,
+    /// The engine accepted it without changing anything, as it does for
+    /// declined withdrawals, duplicates or disputes of unknown transactions.
+    Ignored,
+    /// The engine rejected it with an error.
+    Rejected,
+    /// It couldn't be decoded.
+    Invalid,
+}
+
+#[derive(Serialize)]
+struct ResultRow {
+    line: u64,
+    #[serde(rename = "type")]
+    record_type: Option<TransactionType>,
+    client: Option<ClientId>,
+    currency: Option<Currency>,
+    tx: Option<TxId>,
+    status: RecordStatus,
+    reason: String,
+    available: Option<String>,
+    held: Option<String>,
+    total: Option<String>,
+    locked: Option<bool>,
+}
+
+/// Writes one CSV line per input record with its status, the reason it
+/// wasn't applied, and the balances of its account right after it
+/// (`line,type,client,currency,tx,status,reason,available,held,total,locked`).
+/// Columns an invalid record can't fill, or balances of an account that
+/// doesn't exist, are left empty.
+pub struct ResultWriter<W: Write> {
+    wtr: csv::Writer<W>,
+}
+
+impl<W: Write> ResultWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            wtr: csv::Writer::from_writer(writer),
+        }
+    }
+
+    /// Writes the result of the record at `line`. `record` is `None` when it
+    /// couldn't be decoded.
+    pub(crate) fn write(
+        &mut self,
+        engine: &PaymentEngine,
+        line: u64,
+        record: Option<&InputRecord>,
+        status: RecordStatus,
+        reason: Option<&PaymentError>,
+    ) -> Result<(), PaymentError> {
+        let account =
+            record.and_then(|record| engine.get_account(record.client_id, record.currency));
+        self.wtr.serialize(ResultRow {
+            line,
+            record_type: record.map(|r| r.record_type),
+            client: record.map(|r| r.client_id),
+            currency: record.map(|r| r.currency),
+            tx: record.map(|r| r.tx_id),
+            status,
+            reason: reason.map(ToString::to_string).unwrap_or_default(),
+            available: account.as_ref().map(|a| format_amount(a.available)),
+            held: account.as_ref().map(|a| format_amount(a.held)),
+            total: account.as_ref().map(|a| format_amount(a.total)),
+            locked: account.as_ref().map(|a| a.locked),
+        })?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), PaymentError> {
+        self.wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_handler::read_records;
+    use crate::input::process_records_with_results;
+    use crate::policy::ErrorPolicy;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_result_per_record() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     withdrawal,1,2,50.0\n\
+                     deposit,2,3,-1.0\n\
+                     this_is_bad_data\n\
+                     dispute,1,1,";
+
+        let mut engine = PaymentEngine::new();
+        let mut output = Vec::new();
+        let mut results = ResultWriter::new(&mut output);
+        let report = process_records_with_results(
+            read_records(input.as_bytes()),
+            &mut engine,
+            ErrorPolicy::Skip,
+            &mut results,
+        )
+        .unwrap();
+        results.flush().unwrap();
+        drop(results);
+
+        assert_eq!(report.skipped.len(), 2);
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[..3],
+            [
+                "line,type,client,currency,tx,status,reason,available,held,total,locked",
+                "2,deposit,1,,1,applied,,10.0000,0.0000,10.0000,false",
+                "3,withdrawal,1,,2,ignored,,10.0000,0.0000,10.0000,false",
+            ]
+        );
+        assert!(lines[3].starts_with("4,deposit,2,,3,rejected,Invalid transaction: "));
+        assert!(lines[3].ends_with(",,,,"));
+        assert!(lines[4].starts_with("5,,,,,invalid,"));
+        assert_eq!(
+            lines[5],
+            "6,dispute,1,,1,applied,,0.0000,10.0000,10.0000,false"
+        );
+    }
+}
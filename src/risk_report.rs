@@ -0,0 +1,187 @@
+//! End-of-run risk report: the accounts left locked and the disputes still
+//! open, with the funds they hold, for risk and operations teams to follow
+//! up on.
+
+use crate::engine::PaymentEngine;
+use crate::models::{ClientId, Currency, TxId};
+use crate::output::ReportRow;
+use crate::PaymentError;
+use rust_decimal::Decimal;
+use serde_derive::Serialize;
+
+/// What a [`RiskItem`] reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskKind {
+    /// An account locked by a chargeback (or an admin record).
+    LockedAccount,
+    /// A transaction under dispute, its amount held.
+    Dispute,
+    /// A transaction under dispute, waiting for enough available funds to
+    /// be held.
+    QueuedDispute,
+}
+
+/// A locked account or an open dispute.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RiskItem {
+    pub kind: RiskKind,
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub currency: Currency,
+    /// The disputed transaction; `None` for a locked account.
+    pub tx: Option<TxId>,
+    /// The account's total, or the disputed amount.
+    pub amount: Decimal,
+    /// The funds held: all of the account's, or those held by the dispute.
+    pub held: Decimal,
+    /// The client's name and external id, from the engine's client registry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+}
+
+/// Lists the locked accounts and open disputes of `engine`, by client and
+/// currency, each account before its disputes (by tx id). A transfer the
+/// client received is listed under the transfer's id. Walks every stored
+/// transaction.
+pub fn risk_report(engine: &PaymentEngine) -> Result<Vec<RiskItem>, PaymentError> {
+    let mut items: Vec<RiskItem> = engine
+        .iter_accounts()
+        .filter(|account| account.locked)
+        .map(|account| RiskItem {
+            kind: RiskKind::LockedAccount,
+            client_id: account.client_id,
+            currency: account.currency,
+            tx: None,
+            amount: account.total(),
+            held: account.held,
+            name: None,
+            external_id: None,
+        })
+        .collect();
+    items.extend(engine.open_disputes()?.into_iter().map(|dispute| RiskItem {
+        kind: if dispute.queued {
+            RiskKind::QueuedDispute
+        } else {
+            RiskKind::Dispute
+        },
+        client_id: dispute.client_id,
+        currency: dispute.currency,
+        tx: Some(dispute.tx_id),
+        amount: dispute.amount,
+        held: if dispute.queued {
+            Decimal::ZERO
+        } else {
+            dispute.amount
+        },
+        name: None,
+        external_id: None,
+    }));
+    for item in &mut items {
+        if let Some(info) = engine.client_info(item.client_id) {
+            item.name.clone_from(&info.name);
+            item.external_id.clone_from(&info.external_id);
+        }
+    }
+    items.sort_by_key(|item| (item.client_id, item.currency, item.tx));
+    Ok(items)
+}
+
+impl ReportRow for RiskItem {
+    const COLUMNS: &'static [&'static str] =
+        &["kind", "client", "currency", "tx", "amount", "held"];
+
+    fn amounts_mut(&mut self) -> Vec<&mut Decimal> {
+        vec![&mut self.amount, &mut self.held]
+    }
+
+    /// `name` and `external_id` columns follow if any item has them.
+    fn fill_columns(items: &mut [Self]) {
+        let named = items
+            .iter()
+            .any(|item| item.name.is_some() || item.external_id.is_some());
+        if named {
+            for item in items {
+                item.name.get_or_insert_with(String::new);
+                item.external_id.get_or_insert_with(String::new);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_handler::read_records;
+    use crate::input::process_records;
+    use crate::output::{write_report_csv, write_report_json};
+    use crate::policy::UnderfundedDisputeMode;
+    use rstest::rstest;
+
+    const INPUT: &str = "type,client,tx,amount
+deposit,1,1,100
+deposit,1,2,50
+dispute,1,2,
+deposit,2,3,20
+dispute,2,3,
+chargeback,2,3,
+deposit,2,4,5
+deposit,3,5,10
+withdrawal,3,6,10
+dispute,3,5,
+deposit,4,7,1
+";
+
+    fn report() -> Vec<RiskItem> {
+        let mut engine =
+            PaymentEngine::new().with_underfunded_dispute_mode(UnderfundedDisputeMode::Queue);
+        process_records(read_records(INPUT.as_bytes()), &mut engine).unwrap();
+        risk_report(&engine).unwrap()
+    }
+
+    #[rstest]
+    fn test_write_risk_report_csv() {
+        let mut output = Vec::new();
+        write_report_csv(&report(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "kind,client,currency,tx,amount,held
+dispute,1,,2,50.0000,50.0000
+locked_account,2,,,5.0000,0.0000
+queued_dispute,3,,5,10.0000,0.0000
+"
+        );
+    }
+
+    #[rstest]
+    fn test_write_risk_report_json() {
+        let mut output = Vec::new();
+        write_report_json(&report()[1..2], &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[{\"kind\":\"locked_account\",\"client\":2,\"currency\":\"\",\"tx\":null,\
+             \"amount\":\"5.0000\",\"held\":\"0.0000\"}]\n"
+        );
+    }
+    #[rstest]
+    #[case::empty(0, "kind,client,currency,tx,amount,held\n")]
+    #[case::partly_named(
+        2,
+        "kind,client,currency,tx,amount,held,name,external_id
+dispute,1,,2,50.0000,50.0000,Ada,
+locked_account,2,,,5.0000,0.0000,,
+"
+    )]
+    fn test_write_risk_report_csv_columns(#[case] len: usize, #[case] expected: &str) {
+        let mut items = report();
+        items.truncate(len);
+        if let Some(item) = items.first_mut() {
+            item.name = Some("Ada".to_string());
+        }
+        let mut output = Vec::new();
+        write_report_csv(&items, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+}
@@ -0,0 +1,269 @@
+//! End-of-day settlement: what moved through each client account over a run,
+//! for netting against the bank, reported alongside the account snapshot.
+
+use crate::events::{EngineEvent, EventListener};
+use crate::models::{ClientId, Currency};
+use crate::output::ReportRow;
+use rust_decimal::Decimal;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// The movements of one client account over the processed period.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Settlement {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub currency: Currency,
+    /// Funds deposited.
+    pub gross_deposits: Decimal,
+    /// Funds withdrawn.
+    pub gross_withdrawals: Decimal,
+    /// Change of the account's total: every credit less every debit,
+    /// transfers, fees, interest, refunds and adjustments included.
+    pub net_movement: Decimal,
+    /// Funds held by disputes, whether or not they were resolved since.
+    pub disputed: Decimal,
+    /// Funds reversed by chargebacks.
+    pub chargebacks: Decimal,
+}
+
+/// Sums the movements of every account from the events of the engines it
+/// listens to. Clones share their totals, so one can be registered with
+/// each engine of a sharded run.
+#[derive(Debug, Clone, Default)]
+pub struct SettlementCollector {
+    totals: Arc<Mutex<BTreeMap<(ClientId, Currency), Settlement>>>,
+}
+
+impl SettlementCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The settlement of every account with a movement so far, by client and
+    /// currency.
+    pub fn report(&self) -> Vec<Settlement> {
+        let totals = self.totals.lock().unwrap_or_else(PoisonError::into_inner);
+        totals.values().cloned().collect()
+    }
+}
+
+impl EventListener for SettlementCollector {
+    fn on_event(&mut self, event: &EngineEvent) {
+        let (client, currency, amount) = match *event {
+            EngineEvent::Deposited {
+                client,
+                currency,
+                amount,
+                ..
+            }
+            | EngineEvent::Withdrawn {
+                client,
+                currency,
+                amount,
+                ..
+            }
+            | EngineEvent::DisputeOpened {
+                client,
+                currency,
+                amount,
+                ..
+            }
+            | EngineEvent::ChargebackApplied {
+                client,
+                currency,
+                amount,
+                ..
+            }
+            | EngineEvent::BalanceChanged {
+                client,
+                currency,
+                change: amount,
+                ..
+            } => (client, currency, amount),
+            EngineEvent::AccountLocked { .. } => return,
+        };
+        let mut totals = self.totals.lock().unwrap_or_else(PoisonError::into_inner);
+        let settlement = totals
+            .entry((client, currency))
+            .or_insert_with(|| Settlement {
+                client_id: client,
+                currency,
+                ..Settlement::default()
+            });
+        match event {
+            EngineEvent::Deposited { .. } => settlement.gross_deposits += amount,
+            EngineEvent::Withdrawn { .. } => settlement.gross_withdrawals += amount,
+            EngineEvent::DisputeOpened { .. } => settlement.disputed += amount,
+            EngineEvent::ChargebackApplied { .. } => settlement.chargebacks += amount,
+            EngineEvent::BalanceChanged { .. } => settlement.net_movement += amount,
+            EngineEvent::AccountLocked { .. } => {}
+        }
+    }
+}
+
+impl ReportRow for Settlement {
+    const COLUMNS: &'static [&'static str] = &[
+        "client",
+        "currency",
+        "gross_deposits",
+        "gross_withdrawals",
+        "net_movement",
+        "disputed",
+        "chargebacks",
+    ];
+
+    fn amounts_mut(&mut self) -> Vec<&mut Decimal> {
+        vec![
+            &mut self.gross_deposits,
+            &mut self.gross_withdrawals,
+            &mut self.net_movement,
+            &mut self.disputed,
+            &mut self.chargebacks,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_handler::read_records;
+    use crate::engine::PaymentEngine;
+    use crate::input::process_records;
+    use crate::output::{write_report_csv, write_report_file};
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    const INPUT: &str = "type,client,tx,amount,currency
+deposit,1,1,100,
+deposit,1,2,50,
+withdrawal,1,3,30,
+dispute,1,2,,
+chargeback,1,2,,
+deposit,2,4,20,
+dispute,2,4,,
+resolve,2,4,,
+withdrawal,2,5,5,
+deposit,2,6,7.5,EUR
+withdrawal,3,7,10,
+";
+
+    fn settle(input: &str) -> Vec<Settlement> {
+        let collector = SettlementCollector::new();
+        let mut engine = PaymentEngine::new().with_event_listener(collector.clone());
+        process_records(read_records(input.as_bytes()), &mut engine).unwrap();
+        collector.report()
+    }
+
+    #[rstest]
+    fn test_settlement_per_account() {
+        let report = settle(INPUT);
+        let totals: Vec<_> = report
+            .iter()
+            .map(|s| {
+                (
+                    s.client_id,
+                    s.currency.to_string(),
+                    s.gross_deposits,
+                    s.gross_withdrawals,
+                    s.net_movement,
+                    s.disputed,
+                    s.chargebacks,
+                )
+            })
+            .collect();
+        // Client 3 had nothing to withdraw, so it moved nothing.
+        assert_eq!(
+            totals,
+            [
+                (
+                    1,
+                    String::new(),
+                    dec!(150),
+                    dec!(30),
+                    dec!(70),
+                    dec!(50),
+                    dec!(50)
+                ),
+                (
+                    2,
+                    String::new(),
+                    dec!(20),
+                    dec!(5),
+                    dec!(15),
+                    dec!(20),
+                    dec!(0)
+                ),
+                (
+                    2,
+                    "EUR".to_string(),
+                    dec!(7.5),
+                    dec!(0),
+                    dec!(7.5),
+                    dec!(0),
+                    dec!(0)
+                ),
+            ]
+        );
+    }
+
+    #[rstest]
+    // Charging back a withdrawal gives its funds back.
+    #[case(
+        "deposit,1,1,10,\nwithdrawal,1,2,3,\ndispute,1,2,,\nchargeback,1,2,,\n",
+        &[(1, dec!(10))]
+    )]
+    #[case("deposit,1,1,10,\ntransfer,1,2,4,2\n", &[(1, dec!(6)), (2, dec!(4))])]
+    fn test_settlement_net_movement(#[case] records: &str, #[case] net: &[(ClientId, Decimal)]) {
+        let report = settle(&format!("type,client,tx,amount,counterparty\n{}", records));
+        let movements: Vec<_> = report
+            .iter()
+            .map(|s| (s.client_id, s.net_movement))
+            .collect();
+        assert_eq!(movements, net);
+    }
+
+    #[rstest]
+    fn test_settlement_of_sharded_engines() {
+        let collector = SettlementCollector::new();
+        let mut first = PaymentEngine::new().with_event_listener(collector.clone());
+        let mut second = PaymentEngine::new().with_event_listener(collector.clone());
+        process_records(
+            read_records("type,client,tx,amount\ndeposit,2,1,5\n".as_bytes()),
+            &mut first,
+        )
+        .unwrap();
+        process_records(
+            read_records("type,client,tx,amount\ndeposit,1,2,3\n".as_bytes()),
+            &mut second,
+        )
+        .unwrap();
+        let clients: Vec<_> = collector.report().iter().map(|s| s.client_id).collect();
+        assert_eq!(clients, [1, 2]);
+    }
+
+    #[rstest]
+    fn test_write_settlement_csv() {
+        let mut output = Vec::new();
+        write_report_csv(&settle(INPUT)[..2], &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,currency,gross_deposits,gross_withdrawals,net_movement,disputed,chargebacks
+1,,150.0000,30.0000,70.0000,50.0000,50.0000
+2,,20.0000,5.0000,15.0000,20.0000,0.0000
+"
+        );
+    }
+
+    #[rstest]
+    fn test_write_settlement_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settlement.json");
+        write_report_file(&settle(INPUT)[2..], &path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "[{\"client\":2,\"currency\":\"EUR\",\"gross_deposits\":\"7.5000\",\"gross_withdrawals\":\"0.0000\",\"net_movement\":\"7.5000\",\"disputed\":\"0.0000\",\"chargebacks\":\"0.0000\"}]\n"
+        );
+    }
+}
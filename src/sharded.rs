@@ -0,0 +1,489 @@
+use crate::engine::{AccountKey, CreditCheck, PaymentEngine, TransferCredit};
+use crate::errors::PaymentError;
+use crate::input::RawRecord;
+use crate::interest::SECONDS_PER_DAY;
+use crate::models::{ClientId, InputRecord, TransactionType, TxId};
+use crate::policy::ErrorPolicy;
+use crate::report::{ProcessingReport, SkipKind};
+use rust_decimal::Decimal;
+use std::num::NonZeroUsize;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+
+/// Number of records buffered per shard before the router blocks.
+const SHARD_QUEUE_CAPACITY: usize = 1024;
+
+/// Where a record came from, kept for warnings and the processing report.
+struct Origin {
+    line: u64,
+    raw: String,
+}
+
+/// Work sent from the router to a shard worker.
+enum ShardMessage {
+    Record(Origin, InputRecord),
+    /// Moves the interest clock to a new day, sent to every shard so they post
+    /// interest at the same boundaries.
+    Tick {
+        timestamp: u64,
+        tx_id: TxId,
+    },
+    /// Asks whether a client's account in a currency can be credited an amount.
+    CheckCredit(AccountKey, Option<Decimal>, SyncSender<CreditCheck>),
+    /// Sending leg of a transfer whose counterparty lives on another shard,
+    /// with whether the counterparty can be credited. The outcome is reported
+    /// back so the router can credit the other side.
+    TransferDebit {
+        origin: Origin,
+        record: InputRecord,
+        counterparty: CreditCheck,
+        reply: SyncSender<Option<TransferCredit>>,
+    },
+    /// Receiving leg of a cross-shard transfer whose debit already succeeded.
+    TransferCredit {
+        origin: Origin,
+        tx_id: TxId,
+        credit: TransferCredit,
+    },
+}
+
+/// Processes records on `shards` worker threads, each owning the clients with
+/// `client_id % shards == index`, and merges the results into a single engine.
+/// The merged engine is made by `make_engine` too, so it keeps the policies
+/// the shards ran under, e.g. for `expire_disputes` afterwards.
+///
+/// Records for the same client are applied in input order. Transfers between
+/// clients on different shards are applied in two steps: the router checks the
+/// receiving account isn't closed and can take the amount, then waits for the sending shard's debit
+/// before crediting the receiving shard. Whenever a record's timestamp starts a
+/// new day, every shard's interest clock is advanced before it's routed. Duplicate
+/// transaction ids are only detected within a shard.
+///
+/// Under `ErrorPolicy::FailFast` the first failing shard stops, the router
+/// stops feeding work once it notices, and the failure with the lowest line
+/// number is returned.
+pub fn process_sharded<I, F>(
+    records: I,
+    shards: NonZeroUsize,
+    policy: ErrorPolicy,
+    make_engine: F,
+) -> Result<(PaymentEngine, ProcessingReport), PaymentError>
+where
+    I: IntoIterator<Item = RawRecord>,
+    F: Fn() -> PaymentEngine + Sync,
+{
+    let shard_count = shards.get();
+    let shard_of = |client_id: ClientId| (u64::from(client_id) % shard_count as u64) as usize;
+
+    thread::scope(|scope| {
+        let mut senders = Vec::with_capacity(shard_count);
+        let mut workers = Vec::with_capacity(shard_count);
+        for _ in 0..shard_count {
+            let (tx, rx) = mpsc::sync_channel(SHARD_QUEUE_CAPACITY);
+            senders.push(tx);
+            let make_engine = &make_engine;
+            workers.push(scope.spawn(move || run_shard(make_engine(), rx, policy)));
+        }
+
+        let routed = route_records(records, &senders, shard_of, policy);
+        // Closing the channels lets the workers drain their queues and exit.
+        drop(senders);
+
+        let mut merged = make_engine();
+        let mut report = ProcessingReport::default();
+        let mut failures = Vec::new();
+        for worker in workers {
+            match worker.join().expect("shard worker panicked") {
+                Ok((engine, shard_report)) => {
+                    merged.absorb(engine)?;
+                    report.absorb(shard_report);
+                }
+                Err(e) => failures.push(e),
+            }
+        }
+        match routed {
+            Err(PaymentError::Io(e)) => return Err(PaymentError::Io(e)),
+            Err(e) => failures.push(e),
+            Ok(router_report) => report.absorb(router_report),
+        }
+        match failures.into_iter().min_by_key(failure_line) {
+            Some(e) => Err(e),
+            None => Ok((merged, report)),
+        }
+    })
+}
+
+/// Orders worker failures for reporting; I/O errors carry no line and win.
+fn failure_line(error: &PaymentError) -> u64 {
+    match error {
+        PaymentError::AtLine { line, .. } => *line,
+        _ => 0,
+    }
+}
+
+/// Dispatches records to their shards until the input ends or a shard hangs up.
+fn route_records<I>(
+    records: I,
+    senders: &[SyncSender<ShardMessage>],
+    shard_of: impl Fn(ClientId) -> usize,
+    policy: ErrorPolicy,
+) -> Result<ProcessingReport, PaymentError>
+where
+    I: IntoIterator<Item = RawRecord>,
+{
+    let mut report = ProcessingReport::default();
+    let mut last_day = None;
+    for RawRecord { line, raw, parsed } in records {
+        report.records_read += 1;
+        let record = match parsed {
+            Ok(rec) => rec,
+            Err(e) => {
+                report.record_failure(policy, line, raw, SkipKind::Decode, e)?;
+                continue;
+            }
+        };
+        let origin = Origin { line, raw };
+
+        if let Some(timestamp) = record.timestamp {
+            let day = timestamp / SECONDS_PER_DAY;
+            if last_day.is_none_or(|last| day > last) {
+                last_day = Some(day);
+                let tx_id = record.tx_id;
+                if !senders
+                    .iter()
+                    .all(|sender| send(sender, ShardMessage::Tick { timestamp, tx_id }))
+                {
+                    break;
+                }
+            }
+        }
+
+        let shard = shard_of(record.client_id);
+        let cross_shard_counterparty = match (record.record_type, record.counterparty_id) {
+            (TransactionType::Transfer, Some(counterparty_id))
+                if shard_of(counterparty_id) != shard =>
+            {
+                Some(counterparty_id)
+            }
+            _ => None,
+        };
+
+        let Some(counterparty_id) = cross_shard_counterparty else {
+            if !send(&senders[shard], ShardMessage::Record(origin, record)) {
+                break;
+            }
+            continue;
+        };
+
+        let tx_id = record.tx_id;
+        let credit_origin = Origin {
+            line,
+            raw: origin.raw.clone(),
+        };
+        let (check_tx, check_rx) = mpsc::sync_channel(1);
+        if !send(
+            &senders[shard_of(counterparty_id)],
+            ShardMessage::CheckCredit((counterparty_id, record.currency), record.amount, check_tx),
+        ) {
+            break;
+        }
+        let Ok(counterparty) = check_rx.recv() else {
+            break;
+        };
+        let (reply_tx, reply_rx) = mpsc::sync_channel(1);
+        let debit = ShardMessage::TransferDebit {
+            origin,
+            record,
+            counterparty,
+            reply: reply_tx,
+        };
+        if !send(&senders[shard], debit) {
+            break;
+        }
+        if let Ok(Some(credit)) = reply_rx.recv() {
+            let counterparty_shard = shard_of(credit.counterparty.0);
+            let credit = ShardMessage::TransferCredit {
+                origin: credit_origin,
+                tx_id,
+                credit,
+            };
+            if !send(&senders[counterparty_shard], credit) {
+                break;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Returns false once the worker has hung up, which it only does after
+/// failing fast (or panicking, which surfaces when it is joined).
+fn send(sender: &SyncSender<ShardMessage>, message: ShardMessage) -> bool {
+    sender.send(message).is_ok()
+}
+
+fn run_shard(
+    mut engine: PaymentEngine,
+    rx: Receiver<ShardMessage>,
+    policy: ErrorPolicy,
+) -> Result<(PaymentEngine, ProcessingReport), PaymentError> {
+    let mut report = ProcessingReport::default();
+    let mut reject = |origin: Origin, e| {
+        report.record_failure(policy, origin.line, origin.raw, SkipKind::Rejected, e)
+    };
+    for message in rx {
+        match message {
+            ShardMessage::Record(origin, record) => {
+                if let Err(e) = engine.process(record) {
+                    reject(origin, e)?;
+                }
+            }
+            ShardMessage::Tick { timestamp, tx_id } => {
+                // Only fails if the audit log can't be written, which isn't
+                // enabled with shards.
+                engine.advance_clock(timestamp, tx_id)?;
+            }
+            ShardMessage::CheckCredit(key, amount, reply) => {
+                let _ = reply.send(engine.check_credit(key, amount));
+            }
+            ShardMessage::TransferDebit {
+                origin,
+                record,
+                counterparty,
+                reply,
+            } => {
+                let result = engine.observe(TransactionType::Transfer, |engine| {
+                    engine.begin_transfer(record, counterparty)
+                });
+                let outcome = match result {
+                    Ok(outcome) => outcome,
+                    Err(e) => {
+                        reject(origin, e)?;
+                        None
+                    }
+                };
+                let _ = reply.send(outcome);
+            }
+            ShardMessage::TransferCredit {
+                origin,
+                tx_id,
+                credit,
+            } => {
+                if let Err(e) = engine.complete_transfer(tx_id, credit) {
+                    reject(origin, e)?;
+                }
+            }
+        }
+    }
+    Ok((engine, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_handler;
+    use crate::fees::{Fee, FeeSchedule};
+    use crate::interest::InterestSchedule;
+    use crate::policy::ExpiredDisputeAction;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+    use std::time::Duration;
+
+    fn run(input: &str, shards: usize) -> Vec<crate::models::OutputRecord> {
+        let (engine, _) = process_sharded(
+            csv_handler::read_records(input.as_bytes()),
+            NonZeroUsize::new(shards).unwrap(),
+            ErrorPolicy::Skip,
+            PaymentEngine::new,
+        )
+        .unwrap();
+        let mut accounts = engine.get_accounts();
+        accounts.sort_by_key(|a| a.client_id);
+        accounts
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(2)]
+    #[case(3)]
+    #[case(8)]
+    fn test_sharded_matches_sequential(#[case] shards: usize) {
+        let input = "type,client,tx,amount,counterparty\n\
+                     deposit,1,1,100.0,\n\
+                     deposit,2,2,50.0,\n\
+                     deposit,3,3,10.0,\n\
+                     withdrawal,1,4,20.0,\n\
+                     transfer,1,5,30.0,2\n\
+                     transfer,3,6,99.0,1\n\
+                     dispute,2,5,\n\
+                     transfer,2,7,50.0,3\n\
+                     deposit,4,8,5.0,\n\
+                     dispute,4,8,\n\
+                     chargeback,4,8,\n\
+                     deposit,5,9,5.0,\n\
+                     withdrawal,5,10,5.0,\n\
+                     close,5,11,,\n\
+                     transfer,1,12,1.0,5\n\
+                     deposit,5,13,1.0,\n\
+                     bad,row\n\
+                     dispute,1,4,\n\
+                     resolve,1,4,";
+
+        let mut sequential = PaymentEngine::new();
+        csv_handler::process_reader(input.as_bytes(), &mut sequential).unwrap();
+        let mut expected = sequential.get_accounts();
+        expected.sort_by_key(|a| a.client_id);
+
+        assert_eq!(run(input, shards), expected);
+
+        let (engine, _) = process_sharded(
+            csv_handler::read_records(input.as_bytes()),
+            NonZeroUsize::new(shards).unwrap(),
+            ErrorPolicy::Skip,
+            PaymentEngine::new,
+        )
+        .unwrap();
+        assert_eq!(engine.stats(), sequential.stats());
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(2)]
+    fn test_sharded_transfer_overflow_is_rejected(#[case] shards: usize) {
+        let input = "type,client,tx,amount,counterparty\n\
+                     deposit,1,1,1000000000000000000000000000.0,\n\
+                     deposit,2,2,79000000000000000000000000000.0,\n\
+                     transfer,1,3,1000000000000000000000000000.0,2";
+
+        let accounts = run(input, shards);
+        assert_eq!(accounts[0].available, dec!(1000000000000000000000000000));
+        assert_eq!(accounts[1].available, dec!(79000000000000000000000000000));
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(3)]
+    fn test_sharded_fees_match_sequential(#[case] shards: usize) {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,100.0\n\
+                     deposit,2,2,50.0\n\
+                     withdrawal,1,3,20.0\n\
+                     withdrawal,2,4,10.0\n\
+                     withdrawal,3,5,10.0\n";
+        let make_engine = || {
+            PaymentEngine::new().with_fee_schedule(
+                FeeSchedule::new(9).with_fee(TransactionType::Withdrawal, Fee::flat(dec!(0.5))),
+            )
+        };
+
+        let mut sequential = make_engine();
+        csv_handler::process_reader(input.as_bytes(), &mut sequential).unwrap();
+        let mut expected = sequential.get_accounts();
+        expected.sort_by_key(|a| a.client_id);
+
+        let (engine, _) = process_sharded(
+            csv_handler::read_records(input.as_bytes()),
+            NonZeroUsize::new(shards).unwrap(),
+            ErrorPolicy::Skip,
+            make_engine,
+        )
+        .unwrap();
+        let mut accounts = engine.get_accounts();
+        accounts.sort_by_key(|a| a.client_id);
+
+        assert_eq!(accounts, expected);
+        assert_eq!(engine.stats().fees_collected, dec!(1.0));
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(3)]
+    fn test_sharded_interest_matches_sequential(#[case] shards: usize) {
+        // Only client 1 has records after day 0; client 2 still earns interest.
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,1000.0,0\n\
+                     deposit,2,2,100.0,3600\n\
+                     deposit,1,3,1.0,2160000\n";
+        let make_engine = || {
+            PaymentEngine::new()
+                .with_interest(InterestSchedule::new(dec!(36.5)).with_period_days(10))
+        };
+
+        let mut sequential = make_engine();
+        csv_handler::process_reader(input.as_bytes(), &mut sequential).unwrap();
+        let mut expected = sequential.get_accounts();
+        expected.sort_by_key(|a| a.client_id);
+
+        let (engine, _) = process_sharded(
+            csv_handler::read_records(input.as_bytes()),
+            NonZeroUsize::new(shards).unwrap(),
+            ErrorPolicy::Skip,
+            make_engine,
+        )
+        .unwrap();
+        let mut accounts = engine.get_accounts();
+        accounts.sort_by_key(|a| a.client_id);
+
+        assert_eq!(accounts, expected);
+        assert_eq!(accounts[1].available, dec!(102.01));
+        assert_eq!(engine.stats().interest_paid, dec!(22.11));
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(2)]
+    fn test_sharded_expires_disputes_like_sequential(#[case] shards: usize) {
+        // Client 2's shard holds the newest timestamp the dispute ages against.
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,10.0,0\n\
+                     dispute,1,1,,86400\n\
+                     deposit,2,2,5.0,864000\n";
+        let make_engine = || {
+            PaymentEngine::new().with_dispute_max_age(
+                Duration::from_secs(2 * 86_400),
+                ExpiredDisputeAction::Resolve,
+            )
+        };
+
+        let (mut engine, _) = process_sharded(
+            csv_handler::read_records(input.as_bytes()),
+            NonZeroUsize::new(shards).unwrap(),
+            ErrorPolicy::Skip,
+            make_engine,
+        )
+        .unwrap();
+        let expired = engine.expire_disputes().unwrap();
+        let mut accounts = engine.get_accounts();
+        accounts.sort_by_key(|a| a.client_id);
+
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].tx_id, 1);
+        assert_eq!(
+            (accounts[0].available, accounts[0].held),
+            (dec!(10.0), dec!(0.0))
+        );
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(4)]
+    fn test_sharded_fail_fast_reports_first_line(#[case] shards: usize) {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,2,2,10.0\n\
+                     withdrawal,3,3,-1.0\n\
+                     withdrawal,2,4,-1.0\n\
+                     deposit,1,5,10.0\n";
+
+        let result = process_sharded(
+            csv_handler::read_records(input.as_bytes()),
+            NonZeroUsize::new(shards).unwrap(),
+            ErrorPolicy::FailFast,
+            PaymentEngine::new,
+        );
+
+        match result.err().unwrap() {
+            PaymentError::AtLine { line, .. } => assert_eq!(line, 4),
+            other => panic!("Expected AtLine error, got {}", other),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::models::TransactionType;
+use rust_decimal::Decimal;
+
+/// Counters describing what an engine has processed.
+///
+/// Per-type counts include records the engine accepted but ignored (e.g.
+/// duplicates or withdrawals with insufficient funds); records it rejected
+/// with an error are only counted in `failed`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EngineStats {
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub disputes: u64,
+    pub resolves: u64,
+    pub chargebacks: u64,
+    pub transfers: u64,
+    pub refunds: u64,
+    pub auths: u64,
+    pub captures: u64,
+    pub voids: u64,
+    pub closes: u64,
+    pub admins: u64,
+    pub converts: u64,
+    /// Credit and debit adjustments.
+    pub adjustments: u64,
+    /// Records rejected with an error.
+    pub failed: u64,
+    /// Fees paid into the house account.
+    pub fees_collected: Decimal,
+    /// Interest posted to accounts.
+    pub interest_paid: Decimal,
+    /// Chargebacked funds credited to the suspense account.
+    pub written_off: Decimal,
+    /// Accounts held by the engine.
+    pub accounts: u64,
+    /// Accounts locked by a chargeback.
+    pub locked_accounts: u64,
+}
+
+impl EngineStats {
+    /// Total records handed to the engine, accepted or not.
+    pub fn processed(&self) -> u64 {
+        self.deposits
+            + self.withdrawals
+            + self.disputes
+            + self.resolves
+            + self.chargebacks
+            + self.transfers
+            + self.refunds
+            + self.auths
+            + self.captures
+            + self.voids
+            + self.closes
+            + self.admins
+            + self.converts
+            + self.adjustments
+            + self.failed
+    }
+
+    pub(crate) fn record(&mut self, record_type: TransactionType, ok: bool) {
+        if !ok {
+            self.failed += 1;
+            return;
+        }
+        let counter = match record_type {
+            TransactionType::Deposit => &mut self.deposits,
+            TransactionType::Withdrawal => &mut self.withdrawals,
+            TransactionType::Dispute => &mut self.disputes,
+            TransactionType::Resolve => &mut self.resolves,
+            TransactionType::Chargeback => &mut self.chargebacks,
+            TransactionType::Transfer => &mut self.transfers,
+            TransactionType::Refund => &mut self.refunds,
+            TransactionType::Auth => &mut self.auths,
+            TransactionType::Capture => &mut self.captures,
+            TransactionType::Void => &mut self.voids,
+            TransactionType::Close => &mut self.closes,
+            TransactionType::Admin => &mut self.admins,
+            TransactionType::Convert => &mut self.converts,
+            TransactionType::CreditAdjustment | TransactionType::DebitAdjustment => {
+                &mut self.adjustments
+            }
+        };
+        *counter += 1;
+    }
+
+    /// Adds the record counters of `other`; account counts are derived by the
+    /// engine and left untouched.
+    pub(crate) fn add(&mut self, other: &EngineStats) {
+        self.deposits += other.deposits;
+        self.withdrawals += other.withdrawals;
+        self.disputes += other.disputes;
+        self.resolves += other.resolves;
+        self.chargebacks += other.chargebacks;
+        self.transfers += other.transfers;
+        self.refunds += other.refunds;
+        self.auths += other.auths;
+        self.captures += other.captures;
+        self.voids += other.voids;
+        self.closes += other.closes;
+        self.admins += other.admins;
+        self.converts += other.converts;
+        self.adjustments += other.adjustments;
+        self.failed += other.failed;
+        self.fees_collected += other.fees_collected;
+        self.interest_paid += other.interest_paid;
+        self.written_off += other.written_off;
+    }
+}
@@ -0,0 +1,86 @@
+use crate::engine::PaymentEngine;
+use crate::models::InputRecord;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+
+impl PaymentEngine {
+    /// Processes records from an async stream until it ends.
+    ///
+    /// Records are pulled one at a time, so a producer feeding the stream
+    /// through [`bounded_channel`] is slowed down to the engine's pace.
+    pub async fn process_stream<S>(&mut self, stream: S)
+    where
+        S: Stream<Item = InputRecord>,
+    {
+        tokio::pin!(stream);
+        while let Some(record) = stream.next().await {
+            if let Err(e) = self.process(record) {
+                tracing::warn!("Error processing transaction: {}", e);
+            }
+        }
+    }
+}
+
+/// Creates a bounded channel whose receiving half can be handed to
+/// [`PaymentEngine::process_stream`]. Senders wait once `capacity` records
+/// are buffered, giving network producers natural backpressure.
+pub fn bounded_channel(
+    capacity: usize,
+) -> (mpsc::Sender<InputRecord>, ReceiverStream<InputRecord>) {
+    let (tx, rx) = mpsc::channel(capacity);
+    (tx, ReceiverStream::new(rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Currency, TransactionType, TxId};
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+    use std::collections::HashMap;
+
+    fn deposit(tx_id: TxId) -> InputRecord {
+        InputRecord {
+            record_type: TransactionType::Deposit,
+            client_id: 1,
+            tx_id,
+            amount: Some(dec!(1.0)),
+            counterparty_id: None,
+            currency: Currency::default(),
+            target_currency: None,
+            timestamp: None,
+            idempotency_key: None,
+            reference: None,
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_process_stream_with_backpressure() {
+        let (tx, stream) = bounded_channel(2);
+        let producer = tokio::spawn(async move {
+            for tx_id in 1..=10 {
+                tx.send(deposit(tx_id)).await.unwrap();
+            }
+        });
+
+        let mut engine = PaymentEngine::new();
+        engine.process_stream(stream).await;
+        producer.await.unwrap();
+
+        assert_eq!(engine.get_accounts()[0].available, dec!(10.0));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_process_stream_from_iterator() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process_stream(tokio_stream::iter(vec![deposit(1), deposit(1), deposit(2)]))
+            .await;
+
+        assert_eq!(engine.get_accounts()[0].available, dec!(2.0));
+    }
+}
@@ -0,0 +1,222 @@
+//! Aligned tables of accounts and statements, for people reading small
+//! outputs in a terminal rather than programs parsing them.
+
+use crate::audit::AuditEntry;
+use crate::csv_handler::{account_row, format_amount};
+use crate::errors::PaymentError;
+use crate::models::OutputRecord;
+use rust_decimal::Decimal;
+use std::io::Write;
+
+/// ANSI escape codes wrapped around the rows of locked accounts.
+const LOCKED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+/// Writes `accounts` as a table with every CSV column, in the order given.
+/// With `color`, the rows of locked accounts are red.
+pub fn write_accounts_table<W: Write>(
+    accounts: &[OutputRecord],
+    color: bool,
+    writer: W,
+) -> Result<(), PaymentError> {
+    let rows = accounts
+        .iter()
+        .map(|account| (account_row(account).to_vec(), account.locked))
+        .collect::<Vec<_>>();
+    write_table(
+        &[
+            "client",
+            "currency",
+            "available",
+            "held",
+            "total",
+            "locked",
+            "closed",
+            "overdraft",
+        ],
+        &rows,
+        color,
+        writer,
+    )
+}
+
+/// Writes a client statement as a table with the audit log columns. With
+/// `color`, the mutations that left the account locked are red.
+pub fn write_statement_table<W: Write>(
+    entries: &[AuditEntry],
+    color: bool,
+    writer: W,
+) -> Result<(), PaymentError> {
+    let rows = entries
+        .iter()
+        .map(|entry| {
+            let row = vec![
+                entry.tx.to_string(),
+                entry.client.to_string(),
+                entry.currency.to_string(),
+                entry.action.clone(),
+                format_amount(entry.amount),
+                format_amount(entry.available),
+                format_amount(entry.held),
+                entry.locked.to_string(),
+                entry.metadata.clone(),
+            ];
+            (row, entry.locked)
+        })
+        .collect::<Vec<_>>();
+    write_table(
+        &[
+            "tx",
+            "client",
+            "currency",
+            "action",
+            "amount",
+            "available",
+            "held",
+            "locked",
+            "metadata",
+        ],
+        &rows,
+        color,
+        writer,
+    )
+}
+
+/// Writes `rows` under `header`, each column padded to its widest cell and
+/// separated by two spaces. Columns of numbers are right aligned, the others
+/// left aligned. Rows flagged `true` are colored when `color` is set.
+fn write_table<W: Write>(
+    header: &[&str],
+    rows: &[(Vec<String>, bool)],
+    color: bool,
+    mut writer: W,
+) -> Result<(), PaymentError> {
+    let columns = 0..header.len();
+    let widths: Vec<usize> = columns
+        .clone()
+        .map(|i| {
+            rows.iter()
+                .map(|(row, _)| row[i].chars().count())
+                .fold(header[i].len(), usize::max)
+        })
+        .collect();
+    let numeric: Vec<bool> = columns
+        .clone()
+        .map(|i| {
+            rows.iter()
+                .map(|(row, _)| &row[i])
+                .filter(|cell| !cell.is_empty())
+                .all(|cell| cell.parse::<Decimal>().is_ok())
+        })
+        .collect();
+
+    let line = |cells: &mut dyn Iterator<Item = &str>| {
+        let mut line = String::new();
+        for (i, cell) in cells.enumerate() {
+            if i > 0 {
+                line.push_str("  ");
+            }
+            let padding = " ".repeat(widths[i] - cell.chars().count());
+            if numeric[i] {
+                line.push_str(&padding);
+                line.push_str(cell);
+            } else {
+                line.push_str(cell);
+                line.push_str(&padding);
+            }
+        }
+        line.truncate(line.trim_end().len());
+        line
+    };
+
+    writeln!(writer, "{}", line(&mut header.iter().copied()))?;
+    let rule: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+    writeln!(writer, "{}", line(&mut rule.iter().map(String::as_str)))?;
+    for (row, highlighted) in rows {
+        let text = line(&mut row.iter().map(String::as_str));
+        if color && *highlighted {
+            writeln!(writer, "{}{}{}", LOCKED, text, RESET)?;
+        } else {
+            writeln!(writer, "{}", text)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentEngine;
+    use crate::input::process_records;
+    use rstest::rstest;
+
+    fn engine() -> PaymentEngine {
+        let input = "type,client,tx,amount
+deposit,1,1,1.5
+deposit,12,2,100
+dispute,12,2,
+chargeback,12,2,
+deposit,3,3,7.25
+";
+        let mut engine = PaymentEngine::new().with_statement_history();
+        process_records(
+            crate::csv_handler::read_records(input.as_bytes()),
+            &mut engine,
+        )
+        .unwrap();
+        engine
+    }
+
+    fn accounts_table(color: bool) -> String {
+        let accounts: Vec<OutputRecord> = engine()
+            .iter_accounts_sorted()
+            .map(|account| account.to_output_record())
+            .collect();
+        let mut output = Vec::new();
+        write_accounts_table(&accounts, color, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[rstest]
+    fn test_write_accounts_table() {
+        assert_eq!(
+            accounts_table(false),
+            "\
+client  currency  available    held   total  locked  closed  overdraft
+------  --------  ---------  ------  ------  ------  ------  ---------
+     1               1.5000  0.0000  1.5000  false   false      0.0000
+     3               7.2500  0.0000  7.2500  false   false      0.0000
+    12               0.0000  0.0000  0.0000  true    false      0.0000
+"
+        );
+    }
+
+    #[rstest]
+    fn test_write_accounts_table_colors_locked_accounts() {
+        let table = accounts_table(true);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines[2], accounts_table(false).lines().nth(2).unwrap());
+        assert_eq!(
+            lines[4],
+            "\x1b[31m    12               0.0000  0.0000  0.0000  true    false      0.0000\x1b[0m"
+        );
+    }
+
+    #[rstest]
+    fn test_write_statement_table() {
+        let engine = engine();
+        let mut output = Vec::new();
+        write_statement_table(engine.statement(12).unwrap(), false, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+tx  client  currency  action        amount  available      held  locked  metadata
+--  ------  --------  ----------  --------  ---------  --------  ------  --------
+ 2      12            deposit     100.0000   100.0000    0.0000  false
+ 2      12            dispute     100.0000     0.0000  100.0000  false
+ 2      12            chargeback  100.0000     0.0000    0.0000  true
+"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use crate::errors::PaymentError;
+use crate::models::TxId;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use uuid::Uuid;
+
+/// How the `tx` column of the input identifies transactions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TxIdFormat {
+    /// Numeric ids, used as they are.
+    #[default]
+    Numeric,
+    /// UUIDs, in any of the usual spellings (hyphenated or not, any case).
+    Uuid,
+    /// Arbitrary non-empty strings, compared exactly.
+    Text,
+}
+
+impl FromStr for TxIdFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "numeric" => Ok(TxIdFormat::Numeric),
+            "uuid" => Ok(TxIdFormat::Uuid),
+            "string" | "text" => Ok(TxIdFormat::Text),
+            other => Err(format!("unknown tx id format '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Reference {
+    Uuid(u128),
+    Text(Box<str>),
+}
+
+#[derive(Debug, Default)]
+struct Assigned {
+    ids: HashMap<Reference, TxId>,
+    next: TxId,
+}
+
+/// Maps the transaction references of an input to compact tx ids, assigned
+/// from 1 in order of first appearance, so the engine can key transactions
+/// from gateways that use UUIDs or other strings.
+///
+/// Clones share their assignments, so every reader of one run (and the
+/// threads feeding it) must use clones of the same map. Every distinct
+/// reference stays in memory for the map's lifetime.
+#[derive(Debug, Clone, Default)]
+pub struct TxIdMap {
+    format: TxIdFormat,
+    assigned: Arc<Mutex<Assigned>>,
+}
+
+impl TxIdMap {
+    pub fn new(format: TxIdFormat) -> Self {
+        Self {
+            format,
+            assigned: Arc::default(),
+        }
+    }
+
+    pub fn format(&self) -> TxIdFormat {
+        self.format
+    }
+
+    /// Number of distinct references mapped so far.
+    pub fn len(&self) -> usize {
+        self.lock().ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The tx id of `reference`, assigning the next free one if it's new.
+    /// Numeric references are parsed as they are.
+    pub fn map(&self, reference: &str) -> Result<TxId, PaymentError> {
+        let reference = reference.trim();
+        let invalid = |what: &str| {
+            PaymentError::InvalidTransaction(format!("invalid tx {} '{}'", what, reference))
+        };
+        let key = match self.format {
+            TxIdFormat::Numeric => return reference.parse().map_err(|_| invalid("id")),
+            TxIdFormat::Uuid => Reference::Uuid(
+                Uuid::try_parse(reference)
+                    .map_err(|_| invalid("UUID"))?
+                    .as_u128(),
+            ),
+            TxIdFormat::Text if reference.is_empty() => return Err(invalid("reference")),
+            TxIdFormat::Text => Reference::Text(reference.into()),
+        };
+
+        let mut assigned = self.lock();
+        if let Some(&tx_id) = assigned.ids.get(&key) {
+            return Ok(tx_id);
+        }
+        let tx_id = assigned.next.checked_add(1).ok_or_else(|| {
+            PaymentError::Overflow(format!("more than {} distinct tx references", TxId::MAX))
+        })?;
+        assigned.next = tx_id;
+        assigned.ids.insert(key, tx_id);
+        Ok(tx_id)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Assigned> {
+        self.assigned.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("numeric", Ok(TxIdFormat::Numeric))]
+    #[case("UUID", Ok(TxIdFormat::Uuid))]
+    #[case("string", Ok(TxIdFormat::Text))]
+    #[case("text", Ok(TxIdFormat::Text))]
+    #[case("hex", Err("unknown tx id format 'hex'".to_string()))]
+    fn test_tx_id_format_from_str(
+        #[case] input: &str,
+        #[case] expected: Result<TxIdFormat, String>,
+    ) {
+        assert_eq!(input.parse::<TxIdFormat>(), expected);
+    }
+
+    #[rstest]
+    fn test_uuids_map_to_compact_ids() {
+        let map = TxIdMap::new(TxIdFormat::Uuid);
+        let shared = map.clone();
+
+        assert_eq!(map.map("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap(), 1);
+        assert_eq!(map.map("9f1c3a3e-0000-4000-8000-000000000001").unwrap(), 2);
+        // Same UUID, different spelling.
+        assert_eq!(shared.map(" 67E5504410B1426F9247BB680E5FE0C8").unwrap(), 1);
+        assert_eq!(map.len(), 2);
+
+        let err = map.map("67e55044-10b1").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid transaction: invalid tx UUID '67e55044-10b1'"
+        );
+    }
+
+    #[rstest]
+    fn test_text_references_map_to_compact_ids() {
+        let map = TxIdMap::new(TxIdFormat::Text);
+        assert_eq!(map.map("pay_123").unwrap(), 1);
+        assert_eq!(map.map("PAY_123").unwrap(), 2);
+        assert_eq!(map.map("pay_123").unwrap(), 1);
+        assert!(map.map(" ").is_err());
+    }
+
+    #[rstest]
+    fn test_numeric_references_are_parsed() {
+        let map = TxIdMap::new(TxIdFormat::Numeric);
+        assert_eq!(map.map("42").unwrap(), 42);
+        assert!(map.map("pay_123").is_err());
+        assert!(map.is_empty());
+    }
+}
@@ -0,0 +1,646 @@
+use crate::models::{
+    ClientId, Currency, TransactionDirection, TransactionInfo, TransactionState, TxId,
+};
+use rust_decimal::Decimal;
+use rustc_hash::FxHashMap;
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Storage for transactions that may still be referenced by a dispute.
+///
+/// The engine only ever looks transactions up by id, so implementations are
+/// free to keep them in memory, on disk, or anywhere else.
+pub trait TxStore: Debug + Send {
+    fn get(&self, tx_id: TxId) -> io::Result<Option<TransactionInfo>>;
+
+    fn insert(&mut self, tx_id: TxId, info: TransactionInfo) -> io::Result<()>;
+
+    fn remove(&mut self, tx_id: TxId) -> io::Result<Option<TransactionInfo>>;
+
+    /// Returns every stored transaction, in no particular order.
+    fn entries(&self) -> io::Result<Vec<(TxId, TransactionInfo)>>;
+
+    fn len(&self) -> usize;
+
+    fn contains(&self, tx_id: TxId) -> io::Result<bool> {
+        Ok(self.get(tx_id)?.is_some())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every stored transaction.
+    fn clear(&mut self) -> io::Result<()> {
+        for (tx_id, _) in self.entries()? {
+            self.remove(tx_id)?;
+        }
+        Ok(())
+    }
+
+    /// Updates the state of a stored transaction, returning false if it's unknown.
+    fn set_state(&mut self, tx_id: TxId, state: TransactionState) -> io::Result<bool> {
+        match self.get(tx_id)? {
+            Some(mut info) => {
+                info.state = state;
+                self.insert(tx_id, info)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Keeps transactions in a `HashMap`. This is the default store.
+#[derive(Debug, Default)]
+pub struct MemoryTxStore {
+    transactions: FxHashMap<TxId, TransactionInfo>,
+}
+
+impl MemoryTxStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a store with room for `capacity` transactions.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            transactions: FxHashMap::with_capacity_and_hasher(capacity, Default::default()),
+        }
+    }
+}
+
+impl TxStore for MemoryTxStore {
+    fn get(&self, tx_id: TxId) -> io::Result<Option<TransactionInfo>> {
+        Ok(self.transactions.get(&tx_id).copied())
+    }
+
+    fn insert(&mut self, tx_id: TxId, info: TransactionInfo) -> io::Result<()> {
+        self.transactions.insert(tx_id, info);
+        Ok(())
+    }
+
+    fn remove(&mut self, tx_id: TxId) -> io::Result<Option<TransactionInfo>> {
+        Ok(self.transactions.remove(&tx_id))
+    }
+
+    fn entries(&self) -> io::Result<Vec<(TxId, TransactionInfo)>> {
+        Ok(self
+            .transactions
+            .iter()
+            .map(|(id, info)| (*id, *info))
+            .collect())
+    }
+
+    fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.transactions.clear();
+        Ok(())
+    }
+}
+
+/// State code of an empty slot in [`CompactTxStore`]'s dense vector.
+const VACANT: u8 = 7;
+
+/// Dense slots appended past the end of the vector may leave it at most this
+/// many slots longer than twice its occupied ones.
+const DENSE_SLACK: usize = 64;
+
+/// A transaction packed into 18 bytes with the default id widths: the amount
+/// as an `i64` mantissa and its scale, and the currency as an index into the
+/// store's currency table.
+#[derive(Debug, Clone, Copy)]
+struct CompactTx {
+    mantissa: [u8; 8],
+    timestamp: [u8; 4],
+    client_id: ClientId,
+    /// Bits 0-2: state (or `VACANT`), bit 3: debit, bits 4-6: scale of the
+    /// amount, bit 7: has a timestamp.
+    flags: u8,
+    disputes: u8,
+    currency: u8,
+}
+
+impl CompactTx {
+    const VACANT: Self = CompactTx {
+        mantissa: [0; 8],
+        timestamp: [0; 4],
+        client_id: 0,
+        flags: VACANT,
+        disputes: 0,
+        currency: 0,
+    };
+
+    fn is_vacant(&self) -> bool {
+        self.flags & 0b111 == VACANT
+    }
+}
+
+/// Keeps transactions in memory in a packed encoding, taking under half the
+/// memory of [`MemoryTxStore`], and a quarter or less when tx ids count up
+/// from zero without large gaps: those are kept in a vector indexed by tx id
+/// instead of a map. Ids far past the others go to a map, as do the few
+/// transactions the encoding can't hold exactly (amounts with more than 18
+/// digits or 7 decimal places, timestamps past 2106, more than 256
+/// currencies), which are stored unpacked.
+#[derive(Debug, Default)]
+pub struct CompactTxStore {
+    dense: Vec<CompactTx>,
+    /// Occupied slots of `dense`.
+    dense_len: usize,
+    sparse: FxHashMap<TxId, CompactTx>,
+    unpacked: FxHashMap<TxId, TransactionInfo>,
+    currencies: Vec<Currency>,
+}
+
+impl CompactTxStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn pack(&mut self, info: &TransactionInfo) -> Option<CompactTx> {
+        let amount = info.amount;
+        let mantissa = i64::try_from(amount.mantissa()).ok()?;
+        if amount.scale() > 7 || (amount.is_zero() && amount.is_sign_negative()) {
+            return None;
+        }
+        let timestamp = match info.timestamp {
+            Some(timestamp) => Some(u32::try_from(timestamp).ok()?),
+            None => None,
+        };
+        let currency = match self.currencies.iter().position(|&c| c == info.currency) {
+            Some(index) => index,
+            None if self.currencies.len() <= usize::from(u8::MAX) => {
+                self.currencies.push(info.currency);
+                self.currencies.len() - 1
+            }
+            None => return None,
+        };
+        let state = match info.state {
+            TransactionState::Normal => 0,
+            TransactionState::Disputed => 1,
+            TransactionState::Authorized => 2,
+            TransactionState::Resolved => 3,
+            TransactionState::DisputeQueued => 4,
+        };
+        let debit = match info.direction {
+            TransactionDirection::Credit => 0,
+            TransactionDirection::Debit => 1,
+        };
+        Some(CompactTx {
+            mantissa: mantissa.to_le_bytes(),
+            timestamp: timestamp.unwrap_or(0).to_le_bytes(),
+            client_id: info.client_id,
+            flags: state
+                | debit << 3
+                | (amount.scale() as u8) << 4
+                | u8::from(timestamp.is_some()) << 7,
+            disputes: info.disputes,
+            currency: currency as u8,
+        })
+    }
+
+    fn unpack(&self, tx: &CompactTx) -> TransactionInfo {
+        let state = match tx.flags & 0b111 {
+            0 => TransactionState::Normal,
+            1 => TransactionState::Disputed,
+            2 => TransactionState::Authorized,
+            3 => TransactionState::Resolved,
+            _ => TransactionState::DisputeQueued,
+        };
+        let direction = if tx.flags & 1 << 3 == 0 {
+            TransactionDirection::Credit
+        } else {
+            TransactionDirection::Debit
+        };
+        TransactionInfo {
+            client_id: tx.client_id,
+            amount: Decimal::new(
+                i64::from_le_bytes(tx.mantissa),
+                u32::from(tx.flags >> 4 & 0b111),
+            ),
+            state,
+            direction,
+            disputes: tx.disputes,
+            currency: self.currencies[usize::from(tx.currency)],
+            timestamp: (tx.flags & 1 << 7 != 0)
+                .then(|| u64::from(u32::from_le_bytes(tx.timestamp))),
+        }
+    }
+
+    /// The slot of `tx_id` in the dense vector, if it has one.
+    fn dense_slot(&self, tx_id: TxId) -> Option<usize> {
+        usize::try_from(tx_id)
+            .ok()
+            .filter(|&index| index < self.dense.len())
+    }
+
+    /// Whether `tx_id` may get a slot by growing the dense vector.
+    fn fits_dense(&self, tx_id: TxId) -> bool {
+        usize::try_from(tx_id).is_ok_and(|index| {
+            index
+                .checked_add(1)
+                .is_some_and(|len| len <= 2 * (self.dense_len + 1) + DENSE_SLACK)
+        })
+    }
+}
+
+impl TxStore for CompactTxStore {
+    fn get(&self, tx_id: TxId) -> io::Result<Option<TransactionInfo>> {
+        if let Some(tx) = self.dense_slot(tx_id).map(|index| &self.dense[index]) {
+            if !tx.is_vacant() {
+                return Ok(Some(self.unpack(tx)));
+            }
+        }
+        if let Some(tx) = self.sparse.get(&tx_id) {
+            return Ok(Some(self.unpack(tx)));
+        }
+        Ok(self.unpacked.get(&tx_id).copied())
+    }
+
+    fn insert(&mut self, tx_id: TxId, info: TransactionInfo) -> io::Result<()> {
+        self.remove(tx_id)?;
+        let Some(tx) = self.pack(&info) else {
+            self.unpacked.insert(tx_id, info);
+            return Ok(());
+        };
+        if self.dense_slot(tx_id).is_none() && self.fits_dense(tx_id) {
+            self.dense.resize(tx_id as usize + 1, CompactTx::VACANT);
+        }
+        match self.dense_slot(tx_id) {
+            Some(index) => {
+                self.dense[index] = tx;
+                self.dense_len += 1;
+            }
+            None => {
+                self.sparse.insert(tx_id, tx);
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, tx_id: TxId) -> io::Result<Option<TransactionInfo>> {
+        if let Some(index) = self.dense_slot(tx_id) {
+            let tx = std::mem::replace(&mut self.dense[index], CompactTx::VACANT);
+            if !tx.is_vacant() {
+                self.dense_len -= 1;
+                return Ok(Some(self.unpack(&tx)));
+            }
+        }
+        if let Some(tx) = self.sparse.remove(&tx_id) {
+            return Ok(Some(self.unpack(&tx)));
+        }
+        Ok(self.unpacked.remove(&tx_id))
+    }
+
+    fn entries(&self) -> io::Result<Vec<(TxId, TransactionInfo)>> {
+        let dense = self
+            .dense
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| !tx.is_vacant())
+            .map(|(index, tx)| (index as TxId, self.unpack(tx)));
+        let sparse = self.sparse.iter().map(|(&id, tx)| (id, self.unpack(tx)));
+        let unpacked = self.unpacked.iter().map(|(&id, info)| (id, *info));
+        Ok(dense.chain(sparse).chain(unpacked).collect())
+    }
+
+    fn len(&self) -> usize {
+        self.dense_len + self.sparse.len() + self.unpacked.len()
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        *self = Self {
+            currencies: std::mem::take(&mut self.currencies),
+            ..Self::default()
+        };
+        Ok(())
+    }
+}
+
+/// Size of one slot in the on-disk index.
+const SLOT_SIZE: u64 = 48;
+
+/// Keeps transactions in a file indexed directly by tx id.
+///
+/// Each tx id owns a fixed-size slot at `tx_id * SLOT_SIZE`, so lookups are a
+/// single seek and memory use stays constant however many transactions are
+/// stored. Unused slots are never written, which keeps the file sparse on
+/// filesystems that support it.
+#[derive(Debug)]
+pub struct DiskTxStore {
+    file: File,
+    len: usize,
+}
+
+impl DiskTxStore {
+    /// Creates an empty store at `path`, truncating any existing file.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self { file, len: 0 })
+    }
+
+    fn read_slot(&self, tx_id: TxId) -> io::Result<Option<TransactionInfo>> {
+        let mut file = &self.file;
+        let offset = slot_offset(tx_id)?;
+        if offset >= file.metadata()?.len() {
+            return Ok(None);
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let mut slot = [0u8; SLOT_SIZE as usize];
+        file.read_exact(&mut slot)?;
+        decode_slot(&slot)
+    }
+
+    fn write_slot(&mut self, tx_id: TxId, slot: &[u8; SLOT_SIZE as usize]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(slot_offset(tx_id)?))?;
+        self.file.write_all(slot)
+    }
+}
+
+impl TxStore for DiskTxStore {
+    fn get(&self, tx_id: TxId) -> io::Result<Option<TransactionInfo>> {
+        self.read_slot(tx_id)
+    }
+
+    fn insert(&mut self, tx_id: TxId, info: TransactionInfo) -> io::Result<()> {
+        if self.read_slot(tx_id)?.is_none() {
+            self.len += 1;
+        }
+        self.write_slot(tx_id, &encode_slot(&info))
+    }
+
+    fn remove(&mut self, tx_id: TxId) -> io::Result<Option<TransactionInfo>> {
+        let previous = self.read_slot(tx_id)?;
+        if previous.is_some() {
+            self.write_slot(tx_id, &[0u8; SLOT_SIZE as usize])?;
+            self.len -= 1;
+        }
+        Ok(previous)
+    }
+
+    fn entries(&self) -> io::Result<Vec<(TxId, TransactionInfo)>> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(file);
+        let mut slot = [0u8; SLOT_SIZE as usize];
+        let mut entries = Vec::with_capacity(self.len);
+        let mut tx_id: TxId = 0;
+        while entries.len() < self.len {
+            reader.read_exact(&mut slot)?;
+            if let Some(info) = decode_slot(&slot)? {
+                entries.push((tx_id, info));
+            }
+            tx_id = tx_id.wrapping_add(1);
+        }
+        Ok(entries)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// Where the slot of `tx_id` starts. With `wide-ids`, ids past the largest
+/// file size are rejected.
+fn slot_offset(tx_id: TxId) -> io::Result<u64> {
+    u64::from(tx_id).checked_mul(SLOT_SIZE).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("tx id {} is too large for the disk store", tx_id),
+        )
+    })
+}
+
+// Slot layout: [present, state, direction, client (8, LE), amount (16), disputes,
+// currency (8), has timestamp, timestamp (8, LE), padding].
+fn encode_slot(info: &TransactionInfo) -> [u8; SLOT_SIZE as usize] {
+    let mut slot = [0u8; SLOT_SIZE as usize];
+    slot[0] = 1;
+    slot[1] = match info.state {
+        TransactionState::Normal => 0,
+        TransactionState::Disputed => 1,
+        TransactionState::Authorized => 2,
+        TransactionState::Resolved => 3,
+        TransactionState::DisputeQueued => 4,
+    };
+    slot[2] = match info.direction {
+        TransactionDirection::Credit => 0,
+        TransactionDirection::Debit => 1,
+    };
+    slot[3..11].copy_from_slice(&u64::from(info.client_id).to_le_bytes());
+    slot[11..27].copy_from_slice(&info.amount.serialize());
+    slot[27] = info.disputes;
+    slot[28..36].copy_from_slice(&info.currency.to_bytes());
+    if let Some(timestamp) = info.timestamp {
+        slot[36] = 1;
+        slot[37..45].copy_from_slice(&timestamp.to_le_bytes());
+    }
+    slot
+}
+
+fn decode_slot(slot: &[u8; SLOT_SIZE as usize]) -> io::Result<Option<TransactionInfo>> {
+    if slot[0] == 0 {
+        return Ok(None);
+    }
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "corrupt transaction slot");
+    let state = match slot[1] {
+        0 => TransactionState::Normal,
+        1 => TransactionState::Disputed,
+        2 => TransactionState::Authorized,
+        3 => TransactionState::Resolved,
+        4 => TransactionState::DisputeQueued,
+        _ => return Err(corrupt()),
+    };
+    let direction = match slot[2] {
+        0 => TransactionDirection::Credit,
+        1 => TransactionDirection::Debit,
+        _ => return Err(corrupt()),
+    };
+    let mut client_id = [0u8; 8];
+    client_id.copy_from_slice(&slot[3..11]);
+    let client_id = ClientId::try_from(u64::from_le_bytes(client_id)).map_err(|_| corrupt())?;
+    let mut amount = [0u8; 16];
+    amount.copy_from_slice(&slot[11..27]);
+    let mut currency = [0u8; 8];
+    currency.copy_from_slice(&slot[28..36]);
+    let timestamp = match slot[36] {
+        0 => None,
+        1 => {
+            let mut timestamp = [0u8; 8];
+            timestamp.copy_from_slice(&slot[37..45]);
+            Some(u64::from_le_bytes(timestamp))
+        }
+        _ => return Err(corrupt()),
+    };
+    Ok(Some(TransactionInfo {
+        client_id,
+        amount: Decimal::deserialize(amount),
+        state,
+        direction,
+        disputes: slot[27],
+        currency: Currency::from_bytes(currency),
+        timestamp,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    fn info(client_id: ClientId, amount: Decimal) -> TransactionInfo {
+        TransactionInfo {
+            client_id,
+            amount,
+            state: TransactionState::Normal,
+            direction: TransactionDirection::Credit,
+            disputes: 0,
+            currency: Currency::default(),
+            timestamp: None,
+        }
+    }
+
+    fn exercise_store(store: &mut dyn TxStore) {
+        assert!(store.is_empty());
+        assert_eq!(store.get(7).unwrap(), None);
+
+        store.insert(7, info(1, dec!(1.2345))).unwrap();
+        store.insert(3, info(2, dec!(10))).unwrap();
+        store.insert(7, info(1, dec!(5.5))).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(7).unwrap(), Some(info(1, dec!(5.5))));
+        assert!(store.contains(3).unwrap());
+
+        assert!(store.set_state(3, TransactionState::Disputed).unwrap());
+        assert!(!store.set_state(99, TransactionState::Disputed).unwrap());
+        assert_eq!(
+            store.get(3).unwrap().unwrap().state,
+            TransactionState::Disputed
+        );
+
+        let resolved = TransactionInfo {
+            state: TransactionState::Resolved,
+            disputes: 2,
+            currency: "EUR".parse().unwrap(),
+            timestamp: Some(u64::MAX),
+            ..info(2, dec!(10))
+        };
+        store.insert(3, resolved).unwrap();
+        assert_eq!(store.get(3).unwrap(), Some(resolved));
+
+        let mut entries = store.entries().unwrap();
+        entries.sort_by_key(|(id, _)| *id);
+        assert_eq!(
+            entries.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            [3, 7]
+        );
+
+        assert_eq!(store.remove(7).unwrap(), Some(info(1, dec!(5.5))));
+        assert_eq!(store.remove(7).unwrap(), None);
+        assert_eq!(store.len(), 1);
+        assert!(!store.contains(7).unwrap());
+
+        store.clear().unwrap();
+        assert!(store.is_empty());
+        assert!(!store.contains(3).unwrap());
+    }
+
+    #[rstest]
+    fn test_memory_tx_store() {
+        exercise_store(&mut MemoryTxStore::new());
+    }
+
+    #[rstest]
+    fn test_compact_tx_store() {
+        exercise_store(&mut CompactTxStore::new());
+    }
+
+    #[rstest]
+    fn test_compact_tx_store_layouts() {
+        let mut store = CompactTxStore::new();
+        let far = TxId::from(u32::MAX);
+        let unpackable = [
+            info(1, Decimal::MAX),
+            info(1, dec!(1.00000001)),
+            TransactionInfo {
+                timestamp: Some(u64::MAX),
+                ..info(1, dec!(1))
+            },
+        ];
+        for tx_id in 1..=100 {
+            store.insert(tx_id, info(1, dec!(0.5))).unwrap();
+        }
+        store.insert(far, info(2, dec!(10.1000))).unwrap();
+        for (tx_id, info) in (200..).zip(unpackable) {
+            store.insert(far - tx_id, info).unwrap();
+        }
+
+        assert_eq!(store.dense_len, 100);
+        assert_eq!(store.sparse.len(), 1);
+        assert_eq!(store.unpacked.len(), 3);
+        assert_eq!(store.len(), 104);
+        // Amounts come back with their scale, not just their value.
+        assert_eq!(
+            store.get(far).unwrap().unwrap().amount.to_string(),
+            "10.1000"
+        );
+        for (tx_id, info) in (200..).zip(unpackable) {
+            assert_eq!(store.get(far - tx_id).unwrap(), Some(info));
+        }
+
+        // Moving between layouts leaves a single copy behind.
+        store.insert(50, unpackable[0]).unwrap();
+        store.insert(far, info(2, dec!(1.00000001))).unwrap();
+        assert_eq!(store.len(), 104);
+        assert_eq!(store.get(50).unwrap(), Some(unpackable[0]));
+        assert_eq!(store.remove(50).unwrap(), Some(unpackable[0]));
+        assert_eq!(store.get(50).unwrap(), None);
+        assert_eq!(store.entries().unwrap().len(), 103);
+    }
+
+    #[cfg(not(feature = "wide-ids"))]
+    #[rstest]
+    fn test_compact_tx_size() {
+        assert_eq!(std::mem::size_of::<CompactTx>(), 18);
+        assert_eq!(std::mem::size_of::<TransactionInfo>(), 48);
+    }
+
+    #[rstest]
+    fn test_disk_tx_store() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise_store(&mut DiskTxStore::create(dir.path().join("tx.idx")).unwrap());
+    }
+
+    #[rstest]
+    fn test_disk_tx_store_large_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = DiskTxStore::create(dir.path().join("tx.idx")).unwrap();
+
+        let tx_id = TxId::from(u32::MAX);
+        store.insert(tx_id, info(9, dec!(1))).unwrap();
+        assert_eq!(store.get(tx_id).unwrap(), Some(info(9, dec!(1))));
+        assert_eq!(store.get(tx_id - 1).unwrap(), None);
+    }
+
+    #[cfg(feature = "wide-ids")]
+    #[rstest]
+    fn test_disk_tx_store_rejects_ids_past_file_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = DiskTxStore::create(dir.path().join("tx.idx")).unwrap();
+
+        let err = store.insert(TxId::MAX, info(9, dec!(1))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -31,21 +31,592 @@ fn test_cli_success() {
         .stderr(predicate::str::is_empty());
 }
 
+#[rstest]
+fn test_cli_reads_stdin_with_dash() {
+    let input_content = "type,client,tx,amount\n\
+                         deposit,1,1,10.0\n\
+                         withdrawal,1,2,5.0";
+
+    let expected_output = "client,available,held,total,locked\n\
+                           1,5.0000,0.0000,5.0000,false";
+
+    let mut cmd = assert_cmd::Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("-").write_stdin(input_content);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_json_lines_input() {
+    let input_content = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"10.0\"}\n\
+                         {\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":\"5.0\"}";
+
+    let expected_output = "client,available,held,total,locked\n\
+                           1,5.0000,0.0000,5.0000,false";
+
+    let mut cmd = assert_cmd::Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["--input-format", "jsonl", "-"])
+        .write_stdin(input_content);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_pain001_input() {
+    let input_file = create_temp_csv(
+        r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.03">
+  <CstmrCdtTrfInitn>
+    <PmtInf>
+      <ReqdExctnDt>2024-03-01</ReqdExctnDt>
+      <DbtrAcct><Id><IBAN>FR1420041010050500013M02606</IBAN></Id></DbtrAcct>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>E2E-1</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">100.00</InstdAmt></Amt>
+        <CdtrAcct><Id><IBAN>DE89 3704 0044 0532 0130 00</IBAN></Id></CdtrAcct>
+      </CdtTrfTxInf>
+    </PmtInf>
+    <PmtInf>
+      <DbtrAcct><Id><IBAN>DE89370400440532013000</IBAN></Id></DbtrAcct>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>E2E-2</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">30.00</InstdAmt></Amt>
+        <CdtrAcct><Id><Othr><Id>555</Id></Othr></Id></CdtrAcct>
+      </CdtTrfTxInf>
+    </PmtInf>
+  </CstmrCdtTrfInitn>
+</Document>"#,
+    );
+    let accounts_file = create_temp_csv("account,client\nDE89370400440532013000,1\n555,2");
+
+    let expected_output = "client,currency,available,held,total,locked\n\
+                           1,EUR,70.0000,0.0000,70.0000,false\n\
+                           2,EUR,30.0000,0.0000,30.0000,false";
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["--input-format", "pain001", "--tx-id-format", "string", "--accounts"])
+        .arg(accounts_file.path())
+        .arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_mt940_input() {
+    let input_content = ":20:STMT-1\n\
+                         :25:10020030/1234567\n\
+                         :60F:C240301EUR0,00\n\
+                         :61:240301C100,00NTRFNONREF//BANK-1\n\
+                         :86:Incoming transfer\n\
+                         :61:240302D40,NTRFNONREF//BANK-2\n\
+                         :62F:C240302EUR60,00";
+    let accounts_file = create_temp_csv("account,client\n10020030/1234567,1");
+
+    let expected_output = "client,currency,available,held,total,locked\n\
+                           1,EUR,60.0000,0.0000,60.0000,false";
+
+    let mut cmd = assert_cmd::Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["--input-format", "mt940", "--tx-id-format", "string", "--accounts"])
+        .arg(accounts_file.path())
+        .arg("-")
+        .write_stdin(input_content);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_nacha_input() {
+    let entry = |code: &str, cents: u64, addenda: char, trace: &str| {
+        format!(
+            "6{}123456789{:<17}{:010}{:<15}{:<22}  {}{}",
+            code, "000111", cents, "ID", "NAME", addenda, trace
+        )
+    };
+    let input_content = [
+        format!("{:<94}", "101 123456789 1234567892403010000A094101"),
+        format!("{:<94}", format!("5200{:<65}240301", "COMPANY")),
+        entry("22", 10_000, '0', "123456780000001"),
+        entry("22", 5_000, '0', "123456780000002"),
+        entry("27", 2_500, '0', "123456780000003"),
+        entry("26", 5_000, '1', "123456780000004"),
+        format!("{:<94}", "799R10123456780000002"),
+        format!("{:<94}", "8200"),
+        format!("{:<94}", "9000001"),
+    ]
+    .join("\n");
+    let accounts_file = create_temp_csv("account,client\n000111,1");
+
+    let expected_output = "client,currency,available,held,total,locked\n\
+                           1,USD,75.0000,0.0000,75.0000,true";
+
+    let mut cmd = assert_cmd::Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["--input-format", "nacha", "--tx-id-format", "string", "--accounts"])
+        .arg(accounts_file.path())
+        .arg("-")
+        .write_stdin(input_content);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_ofx_input() {
+    let input_content = "OFXHEADER:100\n\
+                         DATA:OFXSGML\n\
+                         \n\
+                         <OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS>\n\
+                         <CURDEF>USD\n\
+                         <BANKACCTFROM><BANKID>121000248<ACCTID>0001234567</BANKACCTFROM>\n\
+                         <BANKTRANLIST>\n\
+                         <STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20240301<TRNAMT>100.00<FITID>F-1</STMTTRN>\n\
+                         <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20240302<TRNAMT>-40<FITID>F-2</STMTTRN>\n\
+                         </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>";
+    let accounts_file = create_temp_csv("account,client\n0001234567,1");
+
+    let expected_output = "client,currency,available,held,total,locked\n\
+                           1,USD,60.0000,0.0000,60.0000,false";
+
+    let mut cmd = assert_cmd::Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["--input-format", "ofx", "--tx-id-format", "string", "--accounts"])
+        .arg(accounts_file.path())
+        .arg("-")
+        .write_stdin(input_content);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_fixed_width_input() {
+    let layout_file = create_temp_csv(
+        "skip_lines = 1\n\
+         implied_decimals = 2\n\
+         [fields]\n\
+         type = { start = 1, length = 10 }\n\
+         client = { start = 11, length = 4 }\n\
+         tx = { start = 15, length = 6 }\n\
+         amount = { start = 21, length = 9 }",
+    );
+    let input_content = "HEADER 20240301\n\
+                         deposit   0001000001000010000\n\
+                         withdrawal0001000002000002550\n";
+
+    let expected_output = "client,available,held,total,locked\n\
+                           1,74.5000,0.0000,74.5000,false";
+
+    let mut cmd = assert_cmd::Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["--input-format", "fixed", "--layout"])
+        .arg(layout_file.path())
+        .arg("-")
+        .write_stdin(input_content);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_csv_dialect() {
+    let input_file = create_temp_csv(
+        "type;Customer;txn_id;amount\n\
+         deposit;1;A-1;'10.5'\n\
+         withdrawal;1;A-2;2.5",
+    );
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.args([
+        "--delimiter",
+        ";",
+        "--quote",
+        "'",
+        "--tx-id-format",
+        "string",
+        "--header-alias",
+        "customer=client",
+        "--header-alias",
+        "txn_id=tx",
+    ])
+    .arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "client,available,held,total,locked\n\
+             1,8.0000,0.0000,8.0000,false\n",
+        ))
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_no_header() {
+    let input_file = create_temp_csv("deposit,1,1,10.0\nwithdrawal,1,2,4.0");
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--no-header").arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "client,available,held,total,locked\n\
+             1,6.0000,0.0000,6.0000,false\n",
+        ))
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_type_aliases() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         Deposit,1,1,10.0\n\
+         WITHDRAW,1,2,1.0\n\
+         payout,1,3,4.0",
+    );
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--type-alias")
+        .arg("payout=withdrawal")
+        .arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "client,available,held,total,locked\n\
+             1,5.0000,0.0000,5.0000,false\n",
+        ))
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_amount_format() {
+    let input_file = create_temp_csv(
+        "type;client;tx;amount\n\
+         deposit;1;1;1.234,56\n\
+         withdrawal;1;2;34,56",
+    );
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["--delimiter", ";", "--amount-format", "eu"])
+        .arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "client,available,held,total,locked\n\
+             1,1200.0000,0.0000,1200.0000,false\n",
+        ))
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_json_output() {
+    let input_file = create_temp_csv("type,client,tx,amount\ndeposit,1,1,10.0");
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["--output-format", "json"]).arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "[{\"client\":1,\"currency\":\"\",\"available\":\"10.0000\",\"held\":\"0.0000\",\"total\":\"10.0000\",\"locked\":false,\"closed\":false,\"overdraft\":\"0.0000\"}]\n",
+        ))
+        .stderr(predicate::str::is_empty());
+}
+
+#[cfg(feature = "parquet")]
+#[rstest]
+fn test_cli_parquet_output() {
+    let input_file = create_temp_csv("type,client,tx,amount\ndeposit,1,1,10.0");
+    let output_dir = tempfile::tempdir().unwrap();
+    let output_path = output_dir.path().join("accounts.parquet");
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["--output-format", "parquet", "--output"])
+        .arg(&output_path)
+        .arg(input_file.path());
+    cmd.assert().success().stdout(predicate::str::is_empty());
+
+    // Parquet files start and end with the magic bytes.
+    let bytes = std::fs::read(&output_path).unwrap();
+    assert!(bytes.starts_with(b"PAR1") && bytes.ends_with(b"PAR1"));
+}
+
+#[cfg(feature = "avro")]
+#[rstest]
+fn test_cli_avro_output() {
+    use avro_schema::read::fallible_streaming_iterator::FallibleStreamingIterator;
+    use avro_schema::read::{block_iterator, read_metadata};
+
+    let input_file = create_temp_csv("type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,1.0");
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["--output-format", "avro"]).arg(input_file.path());
+    let output = cmd.assert().success().get_output().stdout.clone();
+
+    let mut reader = output.as_slice();
+    let metadata = read_metadata(&mut reader).unwrap();
+    assert_eq!(metadata.record.name, "Account");
+    let mut blocks = block_iterator(reader, metadata.compression, metadata.marker);
+    assert_eq!(blocks.next().unwrap().unwrap().number_of_rows, 2);
+    assert!(blocks.next().unwrap().is_none());
+}
+
+#[rstest]
+fn test_cli_output_file() {
+    let input_file = create_temp_csv("type,client,tx,amount\ndeposit,1,1,10.0");
+    let output_dir = tempfile::tempdir().unwrap();
+    let output_path = output_dir.path().join("accounts.csv");
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--output").arg(&output_path).arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::is_empty());
+    assert_eq!(
+        std::fs::read_to_string(&output_path).unwrap(),
+        "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\n"
+    );
+}
+
+#[rstest]
+fn test_cli_sharded_processing() {
+    let input_content = "type,client,tx,amount,counterparty\n\
+                         deposit,1,1,10.0,\n\
+                         deposit,2,2,3.0,\n\
+                         transfer,1,3,4.0,2\n\
+                         withdrawal,2,4,1.0,";
+    let input_file = create_temp_csv(input_content);
+
+    let expected_output = "client,available,held,total,locked\n\
+                           1,6.0000,0.0000,6.0000,false\n\
+                           2,6.0000,0.0000,6.0000,false";
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["--shards", "2"]).arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_disk_tx_store() {
+    let input_content = "type,client,tx,amount\n\
+                         deposit,1,1,10.0\n\
+                         deposit,1,2,5.0\n\
+                         dispute,1,2,";
+    let input_file = create_temp_csv(input_content);
+    let store_dir = tempfile::tempdir().unwrap();
+
+    let expected_output = "client,available,held,total,locked\n\
+                           1,10.0000,5.0000,15.0000,false";
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--tx-store-dir")
+        .arg(store_dir.path())
+        .arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+    assert!(store_dir.path().join("transactions.idx").exists());
+}
+
+#[rstest]
+fn test_cli_compact_tx_store() {
+    let input_content = "type,client,tx,amount\n\
+                         deposit,1,1,10.0\n\
+                         deposit,1,2,5.0\n\
+                         deposit,2,4000000000,7.5\n\
+                         dispute,1,2,\n\
+                         dispute,2,4000000000,\n\
+                         chargeback,2,4000000000,";
+    let input_file = create_temp_csv(input_content);
+
+    let expected_output = "client,available,held,total,locked\n\
+                           1,10.0000,5.0000,15.0000,false\n\
+                           2,0.0000,0.0000,0.0000,true";
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--compact-tx-store").arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_parse_thread() {
+    let day1 = create_temp_csv("type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,3.0");
+    let day2 = create_temp_csv("type,client,tx,amount\nwithdrawal,1,3,4.0\nbogus,1,4,1.0\ndispute,2,2,");
+
+    let expected_output = "client,available,held,total,locked\n\
+                           1,6.0000,0.0000,6.0000,false\n\
+                           2,0.0000,3.0000,3.0000,false";
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["--threads", "2", "--stats"])
+        .arg(day1.path())
+        .arg(day2.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::contains("Records read: 5 (1 skipped)"));
+}
+
+#[rstest]
+fn test_cli_config() {
+    let input_content = "type,client,tx,amount\n\
+                         deposit,1,1,10.0\n\
+                         withdrawal,1,2,15.0\n\
+                         dispute,1,2,";
+    let input_file = create_temp_csv(input_content);
+    let config = create_temp_csv("overdraft_limit = \"10\"\ndeposits_only_disputes = true");
+
+    // The withdrawal overdraws the account, and can't be disputed.
+    let expected_output = "client,available,held,total,locked,overdraft\n\
+                           1,-5.0000,0.0000,-5.0000,false,10.0000";
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--config")
+        .arg(config.path())
+        .arg(input_file.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+
+    // Flags override the file.
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--config")
+        .arg(config.path())
+        .args(["--overdraft-limit", "2"])
+        .arg(input_file.path());
+    cmd.assert().success().stdout(predicate::str::contains(
+        "1,10.0000,0.0000,10.0000,false,2.0000",
+    ));
+
+    let bad_config = create_temp_csv("overdraft = 10");
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--config")
+        .arg(bad_config.path())
+        .arg(input_file.path());
+    cmd.assert().failure().code(2).stderr(predicate::str::contains(
+        "Invalid config: unknown field `overdraft`",
+    ));
+}
+
+#[rstest]
+fn test_cli_config_yaml() {
+    let input_file = create_temp_csv(
+        "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"10.0\"}\n\
+         {\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":\"15.0\"}",
+    );
+    let mut config = tempfile::Builder::new().suffix(".yaml").tempfile().unwrap();
+    writeln!(
+        config,
+        "overdraft_limit: 10\nio:\n  input_format: jsonl\n  output_format: jsonl"
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--config")
+        .arg(config.path())
+        .arg(input_file.path());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "{\"client\":1,\"currency\":\"\",\"available\":\"-5.0000\"",
+        ))
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_multiple_input_files() {
+    let day1 = create_temp_csv("type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,3.0");
+    let day2 = create_temp_csv("type,client,tx,amount\nwithdrawal,1,3,4.0\ndispute,2,2,");
+
+    let expected_output = "client,available,held,total,locked\n\
+                           1,6.0000,0.0000,6.0000,false\n\
+                           2,0.0000,3.0000,3.0000,false";
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg(day1.path()).arg(day2.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+}
+
 #[rstest]
 fn test_cli_no_args() {
     let mut cmd = Command::cargo_bin("payment_engine").unwrap();
     cmd.assert()
         .failure()
+        .code(2)
         .stdout(predicate::str::is_empty())
         .stderr(predicate::str::contains("Usage:"));
 }
 
+#[rstest]
+fn test_cli_help() {
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("statement"))
+        .stderr(predicate::str::is_empty());
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["serve", "--help"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("--listen <ADDR>"));
+}
+
+#[rstest]
+#[case("--audit-log")]
+#[case("--wal")]
+#[case("--results")]
+fn test_cli_output_in_missing_directory(#[case] flag: &str) {
+    let input_file = create_temp_csv("type,client,tx,amount\ndeposit,1,1,1.0");
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args([flag, "/nonexistent/dir/out.csv"])
+        .arg(input_file.path())
+        .assert()
+        .code(5)
+        .stderr(predicate::str::contains("/nonexistent/dir/out.csv"));
+}
+
 #[rstest]
 fn test_cli_file_not_found() {
     let mut cmd = Command::cargo_bin("payment_engine").unwrap();
     cmd.arg("non_existent_file_12345.csv");
     cmd.assert()
         .failure()
+        .code(3)
         .stdout(predicate::str::is_empty())
         .stderr(predicate::str::contains(
             "Error processing transactions: IO error:",
@@ -69,7 +640,1474 @@ fn test_cli_bad_csv_skips_and_warns() {
     cmd.assert()
         .success()
         .stdout(predicate::str::diff(expected_output).trim())
-        .stderr(predicate::str::contains("Warning: Skipping bad record:"));
+        .stderr(predicate::str::contains(
+            "WARN Skipping bad record: line 3: this_is_bad_data:",
+        ));
+}
+
+#[rstest]
+#[case(&[])]
+#[case(&["--shards", "3"])]
+fn test_cli_strict_fails_on_bad_record(#[case] extra_args: &[&str]) {
+    let input_content = "type,client,tx,amount\n\
+                         deposit,1,1,10.0\n\
+                         this_is_bad_data\n\
+                         withdrawal,1,2,5.0";
+    let input_file = create_temp_csv(input_content);
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--strict").args(extra_args).arg(input_file.path());
+
+    cmd.assert()
+        .failure()
+        .code(4)
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains(
+            "Error processing transactions: line 3:",
+        ));
+}
+
+#[rstest]
+#[case(&[])]
+#[case(&["--shards", "2"])]
+fn test_cli_rejects_file(#[case] extra_args: &[&str]) {
+    let input_content = "type,client,tx,amount\n\
+                         deposit,1,1,10.0\n\
+                         this_is_bad_data\n\
+                         withdrawal,2,2,-5.0";
+    let input_file = create_temp_csv(input_content);
+    let rejects = NamedTempFile::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(extra_args)
+        .arg("--rejects")
+        .arg(rejects.path())
+        .arg(input_file.path());
+
+    cmd.assert().success();
+
+    let rejected = std::fs::read_to_string(rejects.path()).unwrap();
+    let lines: Vec<&str> = rejected.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "line,kind,reason,record");
+    assert!(lines[1].starts_with("3,decode,"));
+    assert!(lines[1].ends_with(",this_is_bad_data"));
+    assert!(lines[2].starts_with("4,rejected,"));
+    assert!(lines[2].ends_with(",\"withdrawal,2,2,-5.0\""));
+}
+
+#[rstest]
+fn test_cli_stats_summary() {
+    let input_content = "type,client,tx,amount\n\
+                         deposit,1,1,10.0\n\
+                         deposit,2,2,10.0\n\
+                         this_is_bad_data\n\
+                         withdrawal,1,3,5.0\n\
+                         dispute,2,2,\n\
+                         chargeback,2,2,";
+    let input_file = create_temp_csv(input_content);
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--stats").arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("Records read: 6 (1 skipped)"))
+        .stderr(predicate::str::contains(
+            "Deposits: 2, withdrawals: 1, disputes: 1, resolves: 0, chargebacks: 1, transfers: 0, refunds: 0, auths: 0, captures: 0, voids: 0, closes: 0, admins: 0, converts: 0",
+        ))
+        .stderr(predicate::str::contains("Accounts: 2 (1 locked)"))
+        .stderr(predicate::str::contains("records/s"));
+}
+
+#[rstest]
+#[case("-q", predicate::str::is_empty().boxed())]
+#[case("-vv", predicate::str::contains("DEBUG tx{id=1 client=1 kind=Deposit}: account updated").boxed())]
+fn test_cli_verbosity(#[case] flag: &str, #[case] expected_stderr: predicates::BoxPredicate<str>) {
+    let input_content = "type,client,tx,amount\n\
+                         deposit,1,1,10.0\n\
+                         this_is_bad_data";
+    let input_file = create_temp_csv(input_content);
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg(flag).arg(input_file.path()).env_remove("RUST_LOG");
+
+    cmd.assert().success().stderr(expected_stderr);
+}
+
+#[rstest]
+fn test_cli_audit_log() {
+    let input_content = "type,client,tx,amount\n\
+                         deposit,1,1,10.0\n\
+                         withdrawal,1,2,50.0\n\
+                         withdrawal,1,3,2.5";
+    let input_file = create_temp_csv(input_content);
+    let audit = NamedTempFile::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--audit-log").arg(audit.path()).arg(input_file.path());
+
+    cmd.assert().success();
+
+    let expected_audit = "tx,client,currency,action,amount,available,held,locked,metadata\n\
+                          1,1,,deposit,10.0000,10.0000,0.0000,false,\n\
+                          3,1,,withdrawal,2.5000,7.5000,0.0000,false,\n";
+    assert_eq!(std::fs::read_to_string(audit.path()).unwrap(), expected_audit);
+}
+
+#[rstest]
+fn test_cli_adjustments() {
+    let input_content = "type,client,tx,amount,reference\n\
+                         deposit,1,1,10.0,\n\
+                         credit_adjustment,1,2,1.5,OPS-7\n\
+                         debit_adjustment,1,3,20.0,OPS-8\n\
+                         debit_adjustment,1,4,1.0,";
+    let input_file = create_temp_csv(input_content);
+    let audit = NamedTempFile::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--audit-log").arg(audit.path()).arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("1,-8.5000,0.0000,-8.5000,false"));
+
+    let expected_audit = "tx,client,currency,action,amount,available,held,locked,metadata\n\
+                          1,1,,deposit,10.0000,10.0000,0.0000,false,\n\
+                          2,1,,credit_adjustment,1.5000,11.5000,0.0000,false,\"{\"\"reference\"\":\"\"OPS-7\"\"}\"\n\
+                          3,1,,debit_adjustment,20.0000,-8.5000,0.0000,false,\"{\"\"reference\"\":\"\"OPS-8\"\"}\"\n";
+    assert_eq!(std::fs::read_to_string(audit.path()).unwrap(), expected_audit);
+}
+
+#[rstest]
+fn test_cli_capture_metadata() {
+    let input_content = "type,client,tx,amount,memo\n\
+                         deposit,1,1,10.0,rent\n\
+                         withdrawal,1,2,2.5,";
+    let input_file = create_temp_csv(input_content);
+    let audit = NamedTempFile::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--capture-metadata")
+        .arg("--audit-log")
+        .arg(audit.path())
+        .arg(input_file.path());
+
+    cmd.assert().success();
+
+    let expected_audit = "tx,client,currency,action,amount,available,held,locked,metadata\n\
+                          1,1,,deposit,10.0000,10.0000,0.0000,false,\"{\"\"memo\"\":\"\"rent\"\"}\"\n\
+                          2,1,,withdrawal,2.5000,7.5000,0.0000,false,\n";
+    assert_eq!(std::fs::read_to_string(audit.path()).unwrap(), expected_audit);
+}
+
+#[rstest]
+#[case(&[])]
+#[case(&["--shards", "2"])]
+fn test_cli_statement(#[case] extra_args: &[&str]) {
+    let input_content = "type,client,tx,amount,counterparty\n\
+                         deposit,1,1,10.0,\n\
+                         deposit,2,2,5.0,\n\
+                         transfer,2,3,1.5,1\n\
+                         withdrawal,1,4,2.0,";
+    let input_file = create_temp_csv(input_content);
+
+    let expected_output = "tx,client,currency,action,amount,available,held,locked,metadata\n\
+                           1,1,,deposit,10.0000,10.0000,0.0000,false,\n\
+                           3,1,,transfer_in,1.5000,11.5000,0.0000,false,\n\
+                           4,1,,withdrawal,2.0000,9.5000,0.0000,false,";
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["statement", "1"])
+        .args(extra_args)
+        .arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+#[case(&[])]
+#[case(&["--shards", "2"])]
+fn test_cli_rates(#[case] extra_args: &[&str]) {
+    let input_content = "type,client,tx,amount,currency,to_currency\n\
+                         deposit,1,1,100.0,USD,\n\
+                         convert,1,2,40.0,USD,EUR\n\
+                         deposit,2,3,10.0,EUR,\n\
+                         convert,2,4,10.0,EUR,USD";
+    let input_file = create_temp_csv(input_content);
+    let rates_file = create_temp_csv("from,to,rate\nUSD,EUR,0.8");
+
+    let expected_output = "client,currency,available,held,total,locked\n\
+                           1,EUR,32.0000,0.0000,32.0000,false\n\
+                           1,USD,60.0000,0.0000,60.0000,false\n\
+                           2,EUR,0.0000,0.0000,0.0000,false\n\
+                           2,USD,12.5000,0.0000,12.5000,false";
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--rates")
+        .arg(rates_file.path())
+        .args(extra_args)
+        .arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(expected_output).trim())
+        .stderr(predicate::str::is_empty());
+}
+
+#[rstest]
+fn test_cli_interest() {
+    // 36.5% a year is 0.1% a day; interest is posted after 10 days.
+    let input_content = "type,client,tx,amount,timestamp\n\
+                         deposit,1,1,1000.0,0\n\
+                         withdrawal,1,2,10.0,864000";
+    let input_file = create_temp_csv(input_content);
+    let audit = NamedTempFile::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(["--interest-rate", "36.5", "--interest-period", "10"])
+        .arg("--audit-log")
+        .arg(audit.path())
+        .arg(input_file.path());
+
+    cmd.assert().success().stdout(predicate::str::contains(
+        "1,1000.0000,0.0000,1000.0000,false",
+    ));
+
+    let expected_audit = "tx,client,currency,action,amount,available,held,locked,metadata\n\
+                          1,1,,deposit,1000.0000,1000.0000,0.0000,false,\n\
+                          2,1,,interest,10.0000,1010.0000,0.0000,false,\n\
+                          2,1,,withdrawal,10.0000,1000.0000,0.0000,false,\n";
+    assert_eq!(std::fs::read_to_string(audit.path()).unwrap(), expected_audit);
+}
+
+#[rstest]
+#[case(&[], "1,1.0000,0.0000,1.0000,false")]
+#[case(&["--amount-precision", "truncate"], "1,2.2345,0.0000,2.2345,false")]
+#[case(&["--amount-precision", "round-half-even"], "1,2.2346,0.0000,2.2346,false")]
+fn test_cli_amount_precision(#[case] extra_args: &[&str], #[case] expected: &str) {
+    let input_content = "type,client,tx,amount\n\
+                         deposit,1,1,1.0\n\
+                         deposit,1,2,1.23456";
+    let input_file = create_temp_csv(input_content);
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.args(extra_args).arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(expected));
+}
+
+#[rstest]
+fn test_cli_idempotency_keys() {
+    let input_content = "type,client,tx,amount,idempotency_key\n\
+                         deposit,1,1,10.0,a\n\
+                         deposit,1,2,10.0,a\n\
+                         deposit,1,3,7.0,a\n\
+                         deposit,1,1,5.0,b";
+    let input_file = create_temp_csv(input_content);
+    let rejects = NamedTempFile::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--rejects")
+        .arg(rejects.path())
+        .arg(input_file.path());
+
+    cmd.assert().success().stdout(predicate::str::contains(
+        "1,15.0000,0.0000,15.0000,false",
+    ));
+
+    let rejected = std::fs::read_to_string(rejects.path()).unwrap();
+    let lines: Vec<&str> = rejected.lines().collect();
+    assert_eq!(lines.len(), 2);
+    // The retry under tx 2 is dropped; tx 3 reuses the key for another amount.
+    assert!(lines[1].starts_with("4,conflict,"));
+}
+
+#[rstest]
+fn test_cli_account_store() {
+    let store_dir = tempfile::tempdir().unwrap();
+    let store = store_dir.path().join("accounts.db");
+    let first = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,2,2,3.0",
+    );
+    let second = create_temp_csv(
+        "type,client,tx,amount\n\
+         withdrawal,1,3,4.0",
+    );
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("--account-store")
+        .arg(&store)
+        .arg(first.path())
+        .assert()
+        .success();
+
+    // The second run starts from the balances the first one left behind.
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("--account-store")
+        .arg(&store)
+        .arg(second.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1,6.0000,0.0000,6.0000,false",
+        ))
+        .stdout(predicate::str::contains(
+            "2,3.0000,0.0000,3.0000,false",
+        ));
+}
+
+#[rstest]
+fn test_cli_validate_leaves_state_untouched() {
+    let store_dir = tempfile::tempdir().unwrap();
+    let store = store_dir.path().join("accounts.db");
+    let first = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0",
+    );
+    let second = create_temp_csv(
+        "type,client,tx,amount\n\
+         withdrawal,1,2,4.0\n\
+         this_is_bad_data\n\
+         deposit,2,3,-1.0",
+    );
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("--account-store")
+        .arg(&store)
+        .arg(first.path())
+        .assert()
+        .success();
+    let stored = std::fs::read(&store).unwrap();
+
+    // The simulation starts from the stored balances but never writes them.
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("validate")
+        .arg("--account-store")
+        .arg(&store)
+        .arg(second.path())
+        .assert()
+        .failure()
+        .code(4)
+        .stdout(predicate::str::diff(
+            "client,available,held,total,locked\n\
+             1,6.0000,0.0000,6.0000,false\n",
+        ))
+        .stderr(predicate::str::contains(
+            "Validated 3 records: 1 invalid, 1 rejected, 0 conflicting",
+        ));
+    assert_eq!(std::fs::read(&store).unwrap(), stored);
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("validate")
+        .arg(first.path())
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Validated 1 records: 0 invalid, 0 rejected, 0 conflicting",
+        ));
+}
+
+#[rstest]
+fn test_cli_write_ahead_log() {
+    let wal_dir = tempfile::tempdir().unwrap();
+    let wal = wal_dir.path().join("engine.wal");
+    let first = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         withdrawal,1,2,3.0",
+    );
+    let second = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,3,1.0",
+    );
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("--wal")
+        .arg(&wal)
+        .arg(first.path())
+        .assert()
+        .success();
+    // A crash in the middle of an append leaves a torn last entry behind.
+    let mut file = std::fs::OpenOptions::new().append(true).open(&wal).unwrap();
+    file.write_all(b"{\"type\":\"dep").unwrap();
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("--wal")
+        .arg(&wal)
+        .arg(second.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1,8.0000,0.0000,8.0000,false",
+        ));
+
+    let log = std::fs::read_to_string(&wal).unwrap();
+    assert_eq!(log.lines().count(), 3);
+    assert!(log.ends_with("\n"));
+}
+
+#[rstest]
+fn test_cli_uuid_tx_ids() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,67e55044-10b1-426f-9247-bb680e5fe0c8,10.0\n\
+         deposit,1,9f1c3a3e-0000-4000-8000-000000000001,5.0\n\
+         dispute,1,67e55044-10b1-426f-9247-bb680e5fe0c8,\n\
+         chargeback,1,67e55044-10b1-426f-9247-bb680e5fe0c8,\n\
+         deposit,1,42,1.0",
+    );
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--tx-id-format")
+        .arg("uuid")
+        .arg(input_file.path());
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "client,available,held,total,locked\n\
+             1,5.0000,0.0000,5.0000,true\n",
+        ))
+        .stderr(predicate::str::contains("invalid tx UUID '42'"));
+}
+
+#[rstest]
+fn test_cli_results() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         withdrawal,1,2,50.0\n\
+         dispute,1,1,",
+    );
+    let results = NamedTempFile::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+    cmd.arg("--results")
+        .arg(results.path())
+        .arg(input_file.path());
+
+    cmd.assert().success();
+    assert_eq!(
+        std::fs::read_to_string(results.path()).unwrap(),
+        "line,type,client,currency,tx,status,reason,available,held,total,locked\n\
+         2,deposit,1,,1,applied,,10.0000,0.0000,10.0000,false\n\
+         3,withdrawal,1,,2,ignored,,10.0000,0.0000,10.0000,false\n\
+         4,dispute,1,,1,applied,,0.0000,10.0000,10.0000,false\n"
+    );
+}
+
+#[rstest]
+fn test_cli_generate() {
+    let generate = |seed: &str| {
+        let output = Command::cargo_bin("payment_engine")
+            .unwrap()
+            .args(["generate", "--clients", "3", "--transactions", "50"])
+            .args(["--invalid-ratio", "0.1", "--seed", seed])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let generated = generate("9");
+    assert!(generated.starts_with("type,client,tx,amount\n"));
+    assert_eq!(generated.lines().count(), 51);
+    assert_eq!(generate("9"), generated);
+
+    let input_file = create_temp_csv(&generated);
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "client,available,held,total,locked\n",
+        ));
+}
+
+#[rstest]
+fn test_cli_convert() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         payout,1,2,1.0\n\
+         withdrawal,1,3,2.5",
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let rejects = dir.path().join("rejects.csv");
+    let converted = dir.path().join("records.jsonl");
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["convert", "--to", "jsonl", "--rejects"])
+        .arg(&rejects)
+        .arg("-o")
+        .arg(&converted)
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+    assert_eq!(
+        std::fs::read_to_string(&converted).unwrap(),
+        "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"10\",\"currency\":\"\"}\n\
+         {\"type\":\"withdrawal\",\"client\":1,\"tx\":3,\"amount\":\"2.5\",\"currency\":\"\"}\n"
+    );
+    let rejects = std::fs::read_to_string(&rejects).unwrap();
+    assert!(rejects.contains("3,decode,"), "{}", rejects);
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--input-format", "jsonl"])
+        .arg(&converted)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,7.5000,0.0000,7.5000"));
+}
+
+#[rstest]
+fn test_cli_diff() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,2,2,5.0\n\
+         dispute,2,2,",
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let actual = dir.path().join("accounts.csv");
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("-o")
+        .arg(&actual)
+        .arg(input_file.path())
+        .assert()
+        .success();
+
+    // The same balances written differently are no difference.
+    let golden = create_temp_csv(
+        "client,currency,available,held,total,locked,closed,overdraft\n\
+         2,,0,5,5,false,false,0\n\
+         1,,10,0,10,false,false,0",
+    );
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("diff")
+        .arg(golden.path())
+        .arg(&actual)
+        .assert()
+        .success()
+        .stdout("client,currency,field,expected,actual\n");
+
+    let golden = create_temp_csv(
+        "client,available,held,total,locked\n\
+         1,10.0,0.0,10.0,false\n\
+         2,5.0,0.0,5.0,false\n\
+         3,1.0,0.0,1.0,true",
+    );
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("diff")
+        .arg(golden.path())
+        .arg(&actual)
+        .assert()
+        .code(7)
+        .stdout(
+            "client,currency,field,expected,actual\n\
+             2,,available,5,0\n\
+             2,,held,0,5\n\
+             3,,account,present,missing\n",
+        )
+        .stderr(predicate::str::contains("3 differences between"));
+}
+
+#[rstest]
+fn test_cli_clients() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount,counterparty\n\
+         deposit,1,1,10.0,\n\
+         deposit,2,2,5.0,\n\
+         deposit,150,3,7.0,\n\
+         transfer,1,4,2.5,3\n\
+         withdrawal,2,5,1.0,\n\
+         garbled",
+    );
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--clients", "1,100-200"])
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(
+            "client,available,held,total,locked\n\
+             1,7.5000,0.0000,7.5000,false\n\
+             150,7.0000,0.0000,7.0000,false\n",
+        )
+        .stderr(predicate::str::contains("Skipping bad record: line 7"));
+}
+
+#[rstest]
+fn test_cli_skip_empty() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         withdrawal,1,1,5.0\n\
+         deposit,2,2,3.0\n\
+         withdrawal,2,3,3.0",
+    );
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("--skip-empty")
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(
+            "client,available,held,total,locked\n\
+             2,0.0000,0.0000,0.0000,false\n",
+        );
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1,0.0000,0.0000,0.0000,false",
+        ));
+}
+
+#[rstest]
+fn test_cli_table_output() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,1.5\n\
+         deposit,12,2,100.0\n\
+         dispute,12,2,\n\
+         chargeback,12,2,",
+    );
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--output-format", "table"])
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(
+            "client  currency  available    held   total  locked  closed  overdraft\n\
+             ------  --------  ---------  ------  ------  ------  ------  ---------\n     \
+             1               1.5000  0.0000  1.5000  false   false      0.0000\n    \
+             12               0.0000  0.0000  0.0000  true    false      0.0000\n",
+        );
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--output-format", "table", "--color"])
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[31m    12  "));
+}
+
+#[rstest]
+fn test_cli_verify() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         withdrawal,1,2,2.5",
+    );
+    let golden = create_temp_csv(
+        "client,available,held,total,locked\n\
+         1,7.5,0,7.5,false",
+    );
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("--verify")
+        .arg(golden.path())
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,7.5000,0.0000,7.5000"));
+
+    // The accounts are still written, and the differences listed after them.
+    let golden = create_temp_csv(
+        "client,available,held,total,locked\n\
+         1,7.4999,0,7.4999,false",
+    );
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("--verify")
+        .arg(golden.path())
+        .arg(input_file.path())
+        .assert()
+        .code(7)
+        .stdout(predicate::str::contains("1,7.5000,0.0000,7.5000"))
+        .stderr(predicate::str::contains("2 differences from "))
+        .stderr(predicate::str::contains(
+            "client,currency,field,expected,actual\n\
+             1,,available,7.4999,7.5\n\
+             1,,total,7.4999,7.5\n",
+        ));
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--verify", "missing.csv"])
+        .arg(input_file.path())
+        .assert()
+        .code(3)
+        .stdout(predicate::str::is_empty());
+}
+
+#[cfg(feature = "msgpack")]
+#[rstest]
+fn test_cli_convert_msgpack() {
+    let input = "type,client,tx,amount,counterparty,currency,to_currency,timestamp,idempotency_key,reference\n\
+                 deposit,1,1,10.5,,EUR,,1700000000,k-1,\n\
+                 transfer,1,2,2.5,2,EUR,,,,\n";
+    let input_file = create_temp_csv(input.trim_end());
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("records.msgpack");
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["convert", "--to", "msgpack", "-o"])
+        .arg(&archive)
+        .arg(input_file.path())
+        .assert()
+        .success();
+    assert!(std::fs::metadata(&archive).unwrap().len() < input.len() as u64);
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["convert", "--input-format", "msgpack", "--to", "csv"])
+        .arg(&archive)
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(input));
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--input-format", "msgpack"])
+        .arg(&archive)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2,EUR,2.5000"));
+}
+
+#[cfg(feature = "protobuf")]
+#[rstest]
+fn test_cli_convert_protobuf() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount,counterparty\n\
+         deposit,1,1,10.5,\n\
+         transfer,1,2,2.5,2",
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let stream = dir.path().join("records.pb");
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["convert", "--to", "protobuf", "-o"])
+        .arg(&stream)
+        .arg(input_file.path())
+        .assert()
+        .success();
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--input-format", "protobuf"])
+        .arg(&stream)
+        .assert()
+        .success()
+        .stdout(predicate::str::diff(
+            "client,available,held,total,locked\n\
+             1,8.0000,0.0000,8.0000,false\n\
+             2,2.5000,0.0000,2.5000,false\n",
+        ));
+}
+
+#[rstest]
+fn test_cli_merge_by_timestamp() {
+    let disputes = create_temp_csv(
+        "type,client,tx,amount,timestamp\n\
+         deposit,1,1,10.0,100\n\
+         dispute,1,1,,200",
+    );
+    let chargebacks = create_temp_csv("type,client,tx,amount,timestamp\nchargeback,1,1,,300");
+    let run = |merge: bool| {
+        let mut cmd = Command::cargo_bin("payment_engine").unwrap();
+        if merge {
+            cmd.arg("--merge-by-timestamp");
+        }
+        cmd.arg(chargebacks.path()).arg(disputes.path()).assert().success()
+    };
+
+    // In input order the chargeback comes before the dispute it settles.
+    run(false).stdout(predicate::str::contains(
+        "1,0.0000,10.0000,10.0000,false",
+    ));
+    run(true).stdout(predicate::str::contains(
+        "1,0.0000,0.0000,0.0000,true",
+    ));
+}
+
+#[rstest]
+fn test_cli_reorder_window() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount,timestamp\n\
+         deposit,1,1,10.0,100\n\
+         chargeback,1,1,,130\n\
+         dispute,1,1,,120\n\
+         deposit,1,2,5.0,200\n\
+         withdrawal,1,3,1.0,110",
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let rejects = dir.path().join("rejects.csv");
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--reorder-window", "30", "--rejects"])
+        .arg(&rejects)
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1,5.0000,0.0000,5.0000,true",
+        ))
+        .stderr(predicate::str::contains("Dropping late record: line 6"));
+    assert_eq!(
+        std::fs::read_to_string(&rejects).unwrap(),
+        "line,kind,reason,record\n\
+         6,late,Late record: timestamp 110 arrived after records up to 130 were applied,\"withdrawal,1,3,1.0,110\"\n"
+    );
+}
+
+#[rstest]
+fn test_cli_suspense_account() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,2,2,5.0\n\
+         dispute,1,1,\n\
+         chargeback,1,1,",
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let audit_log = dir.path().join("audit.csv");
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--suspense-account", "9999", "--audit-log"])
+        .arg(&audit_log)
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1,0.0000,0.0000,0.0000,true",
+        ))
+        .stdout(predicate::str::contains(
+            "9999,10.0000,0.0000,10.0000,false",
+        ));
+    let audit = std::fs::read_to_string(&audit_log).unwrap();
+    assert!(
+        audit.lines().last().unwrap().starts_with("1,9999,,write_off,10"),
+        "{}",
+        audit
+    );
+
+    // Each shard credits its own copy, added up once they're merged.
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--shards", "2", "--suspense-account", "9999"])
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "9999,10.0000,0.0000,10.0000,false",
+        ));
+}
+
+#[rstest]
+#[case("--shards", "1")]
+#[case("--shards", "2")]
+fn test_cli_settlement(#[case] flag: &str, #[case] value: &str) {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,100.0\n\
+         deposit,2,2,50.0\n\
+         withdrawal,1,3,30.0\n\
+         dispute,2,2,\n\
+         chargeback,2,2,",
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let settlement = dir.path().join("settlement.csv");
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args([flag, value, "--settlement"])
+        .arg(&settlement)
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1,70.0000,0.0000,70.0000,false",
+        ));
+    assert_eq!(
+        std::fs::read_to_string(&settlement).unwrap(),
+        "client,currency,gross_deposits,gross_withdrawals,net_movement,disputed,chargebacks\n\
+         1,,100.0000,30.0000,70.0000,0.0000,0.0000\n\
+         2,,50.0000,0.0000,0.0000,50.0000,50.0000\n"
+    );
+}
+
+#[rstest]
+fn test_cli_risk_report() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,100.0\n\
+         deposit,2,2,50.0\n\
+         dispute,1,1,\n\
+         dispute,2,2,\n\
+         chargeback,2,2,",
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let risk_report = dir.path().join("risk.csv");
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("--risk-report")
+        .arg(&risk_report)
+        .arg(input_file.path())
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(&risk_report).unwrap(),
+        "kind,client,currency,tx,amount,held\n\
+         dispute,1,,1,100.0000,100.0000\n\
+         locked_account,2,,,0.0000,0.0000\n"
+    );
+}
+
+#[rstest]
+fn test_cli_dispute_max_age() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount,timestamp\n\
+         deposit,1,1,100.0,0\n\
+         deposit,2,2,50.0,0\n\
+         dispute,1,1,,86400\n\
+         dispute,2,2,,2592000\n\
+         deposit,3,3,1.0,3456000",
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let expired = dir.path().join("expired.csv");
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--dispute-max-age", "30", "--expired-dispute-action", "chargeback"])
+        .arg("--expired-disputes")
+        .arg(&expired)
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1,0.0000,0.0000,0.0000,true",
+        ))
+        .stdout(predicate::str::contains(
+            "2,0.0000,50.0000,50.0000,false",
+        ));
+    assert_eq!(
+        std::fs::read_to_string(&expired).unwrap(),
+        "tx,client,currency,amount,opened_at,action\n\
+         1,1,,100.0000,86400,chargeback\n"
+    );
+}
+
+#[rstest]
+#[case(&[])]
+#[case(&["--shards", "2"])]
+fn test_cli_limits(#[case] extra_args: &[&str]) {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount,counterparty\n\
+         deposit,1,1,500.0,\n\
+         deposit,2,2,2000.0,\n\
+         withdrawal,1,3,150.0,\n\
+         withdrawal,1,4,100.0,\n\
+         transfer,1,5,50.0,2\n\
+         deposit,1,6,1.0,",
+    );
+    let rejects = NamedTempFile::new().unwrap();
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(extra_args)
+        .args(["--max-amount", "1000", "--max-daily-withdrawal", "200"])
+        .args(["--max-transactions", "3", "--rejects"])
+        .arg(rejects.path())
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1,300.0000,0.0000,300.0000,false",
+        ))
+        .stdout(predicate::str::contains(
+            "2,50.0000,0.0000,50.0000,false",
+        ));
+    let rejected = std::fs::read_to_string(rejects.path()).unwrap();
+    let lines: Vec<&str> = rejected.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[1].starts_with(
+        "3,limit,\"Limit exceeded: tx 2 moves 2000, over the 1000 transaction limit\""
+    ));
+    assert!(lines[2].starts_with("5,limit,"));
+    assert!(lines[3].starts_with("7,limit,"));
+}
+
+#[rstest]
+#[case(&[])]
+#[case(&["--shards", "2"])]
+fn test_cli_blocklist(#[case] extra_args: &[&str]) {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount,counterparty\n\
+         deposit,1,1,10.0,\n\
+         deposit,2,2,5.0,\n\
+         transfer,1,3,4.0,2\n\
+         deposit,3,4,1.0,",
+    );
+    let blocklist = create_temp_csv("# sanctioned clients\n2\n");
+    let rejects = NamedTempFile::new().unwrap();
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(extra_args)
+        .arg("--blocklist")
+        .arg(blocklist.path())
+        .arg("--rejects")
+        .arg(rejects.path())
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1,10.0000,0.0000,10.0000,false",
+        ))
+        .stdout(predicate::str::contains(
+            "2,0.0000,0.0000,0.0000,true",
+        ))
+        .stdout(predicate::str::contains(
+            "3,1.0000,0.0000,1.0000,false",
+        ));
+    let rejected = std::fs::read_to_string(rejects.path()).unwrap();
+    let lines: Vec<&str> = rejected.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("3,blocked,Blocked: client 2 is on the blocklist,"));
+    assert!(lines[2].starts_with(
+        "4,blocked,\"Blocked: transfer 3 targets client 2, who is on the blocklist\""
+    ));
+}
+
+#[rstest]
+#[case::single("1")]
+#[case::sharded("2")]
+fn test_cli_clients_file(#[case] shards: &str) {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,2,2,5.0\n\
+         withdrawal,2,3,1.0\n\
+         deposit,3,4,1.0\n\
+         dispute,1,1,",
+    );
+    let clients = create_temp_csv(
+        "client,name,external_id,kyc_status\n\
+         1,Ada,CRM-1,verified\n\
+         2,Grace,CRM-2,pending\n",
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let (rejects, risk) = (dir.path().join("rejects.csv"), dir.path().join("risk.csv"));
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--shards", shards])
+        .arg("--clients-file")
+        .arg(clients.path())
+        .arg("--rejects")
+        .arg(&rejects)
+        .arg("--risk-report")
+        .arg(&risk)
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(
+            "client,available,held,total,locked,name,external_id,kyc_status\n\
+             1,0.0000,10.0000,10.0000,false,Ada,CRM-1,verified\n\
+             2,5.0000,0.0000,5.0000,false,Grace,CRM-2,pending\n\
+             3,1.0000,0.0000,1.0000,false,,,\n",
+        );
+    let rejected = std::fs::read_to_string(&rejects).unwrap();
+    let lines: Vec<&str> = rejected.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].starts_with(
+        "4,rejected,\"Not allowed by KYC status: tx 3 of client 2, whose KYC is pending\""
+    ));
+    assert_eq!(
+        std::fs::read_to_string(&risk).unwrap(),
+        "kind,client,currency,tx,amount,held,name,external_id\n\
+         dispute,1,,1,10.0000,10.0000,Ada,CRM-1\n"
+    );
+
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--shards", shards])
+        .arg("--clients-file")
+        .arg(clients.path())
+        .args(["--output-format", "jsonl"])
+        .arg(input_file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "{\"client\":3,\"currency\":\"\",\"available\":\"1.0000\",\"held\":\"0.0000\",\
+             \"total\":\"1.0000\",\"locked\":false,\"closed\":false,\"overdraft\":\"0.0000\",\
+             \"name\":null,\"external_id\":null,\"kyc_status\":null}",
+        ))
+        .stdout(predicate::str::contains(
+            "\"name\":\"Grace\",\"external_id\":\"CRM-2\",\"kyc_status\":\"pending\"}",
+        ));
+}
+
+#[rstest]
+fn test_cli_checkpoint() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10.0\n\
+                 deposit,2,2,5.0\n\
+                 withdrawal,1,3,x\n\
+                 deposit,1,4,1.0\n\
+                 dispute,1,1,";
+    let input_file = create_temp_csv(input);
+    let dir = tempfile::tempdir().unwrap();
+    let checkpoint = dir.path().join("run.checkpoint");
+
+    // A strict run stops at the bad withdrawal, leaving a checkpoint of the
+    // first two records.
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--strict", "--checkpoint-every", "2", "--checkpoint"])
+        .arg(&checkpoint)
+        .arg(input_file.path())
+        .assert()
+        .failure();
+    assert!(checkpoint.exists());
+
+    // Inputs shorter than the checkpoint can't be resumed.
+    let short = create_temp_csv("type,client,tx,amount\ndeposit,1,1,10.0");
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--checkpoint-every", "2", "--checkpoint"])
+        .arg(&checkpoint)
+        .arg(short.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "the checkpoint covers 2 records but the inputs have 1",
+        ));
+
+    // Resuming doesn't apply the first records again, even though they've
+    // been garbled since, and removes the checkpoint once done.
+    let resumed = create_temp_csv(&input.replace("deposit,1,1,10.0", "garbled"));
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--checkpoint-every", "2", "--checkpoint"])
+        .arg(&checkpoint)
+        .arg(resumed.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1,1.0000,10.0000,11.0000,false",
+        ))
+        .stdout(predicate::str::contains(
+            "2,5.0000,0.0000,5.0000,false",
+        ))
+        .stderr(predicate::str::contains("Skipping bad record: line 4"));
+    assert!(!checkpoint.exists());
+}
+
+#[rstest]
+fn test_cli_checkpoint_keeps_limits() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,100.0\n\
+                 withdrawal,1,2,8.0\n\
+                 withdrawal,1,3,x\n\
+                 withdrawal,1,4,5.0";
+    let input_file = create_temp_csv(input);
+    let dir = tempfile::tempdir().unwrap();
+    let checkpoint = dir.path().join("run.checkpoint");
+    let run = |strict: bool| {
+        let mut command = Command::cargo_bin("payment_engine").unwrap();
+        if strict {
+            command.arg("--strict");
+        }
+        command
+            .args(["--max-daily-withdrawal", "10", "--checkpoint-every", "2"])
+            .arg("--checkpoint")
+            .arg(&checkpoint)
+            .arg(input_file.path())
+            .assert()
+    };
+    run(true).failure();
+    assert!(checkpoint.exists());
+
+    // The withdrawal before the checkpoint still counts once resumed.
+    run(false)
+        .success()
+        .stdout(predicate::str::contains(
+            "1,92.0000,0.0000,92.0000,false",
+        ));
+}
+
+#[rstest]
+fn test_cli_reconcile() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10.0\n\
+                 deposit,2,2,5.0\n\
+                 withdrawal,1,3,x\n\
+                 deposit,1,4,1.0\n\
+                 dispute,1,1,";
+    let input_file = create_temp_csv(input);
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("--reconcile")
+        .arg(input_file.path())
+        .assert()
+        .success();
+
+    // A checkpoint whose books were tampered with no longer balances once
+    // resumed.
+    let dir = tempfile::tempdir().unwrap();
+    let checkpoint = dir.path().join("run.checkpoint");
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--strict", "--checkpoint-every", "2", "--checkpoint"])
+        .arg(&checkpoint)
+        .arg(input_file.path())
+        .assert()
+        .failure();
+    let contents = std::fs::read_to_string(&checkpoint).unwrap();
+    assert!(contents.contains("\"deposits\":\"15\""));
+    std::fs::write(
+        &checkpoint,
+        contents.replace("\"deposits\":\"15\"", "\"deposits\":\"14\""),
+    )
+    .unwrap();
+    Command::cargo_bin("payment_engine")
+        .unwrap()
+        .args(["--reconcile", "--checkpoint"])
+        .arg(&checkpoint)
+        .arg(input_file.path())
+        .assert()
+        .code(6)
+        .stderr(predicate::str::contains(
+            "books don't balance: currency '': accounts total 16, records account for 15 (difference 1)",
+        ));
+}
+
+#[rstest]
+fn test_cli_check_invariants() {
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,2,2,5.0\n\
+         withdrawal,1,3,x",
+    );
+    let dir = tempfile::tempdir().unwrap();
+    let checkpoint = dir.path().join("run.checkpoint");
+    let run = |flags: &[&str]| {
+        Command::cargo_bin("payment_engine")
+            .unwrap()
+            .args(flags)
+            .args(["--checkpoint-every", "2", "--checkpoint"])
+            .arg(&checkpoint)
+            .arg(input_file.path())
+            .assert()
+    };
+    let tamper = || {
+        let contents = std::fs::read_to_string(&checkpoint).unwrap();
+        std::fs::write(
+            &checkpoint,
+            contents.replacen("\"held\":\"0.0000\"", "\"held\":\"-1\"", 1),
+        )
+        .unwrap();
+    };
+
+    // A checkpoint holding negative funds is only caught when asked.
+    run(&["--strict"]).failure();
+    tamper();
+    run(&[]).success();
+    run(&["--strict"]).failure();
+    tamper();
+    run(&["--check-invariants"])
+        .code(6)
+        .stderr(predicate::str::contains("client 1 has negative held funds"));
+}
+
+#[rstest]
+fn test_cli_listen() {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpStream;
+    use std::process::Stdio;
+
+    let input_file = create_temp_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0",
+    );
+    let mut server = Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("serve")
+        .arg("--listen")
+        .arg("127.0.0.1:0")
+        .arg(input_file.path())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(server.stderr.take().unwrap());
+    let mut banner = String::new();
+    stderr.read_line(&mut banner).unwrap();
+    let addr = banner.trim().strip_prefix("Listening on ").unwrap();
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"withdrawal,1,2,4.0\ndeposit,1,x,1.0\nbalance 1\n")
+        .unwrap();
+    let mut responses = BufReader::new(stream.try_clone().unwrap());
+    let mut lines = Vec::new();
+    for _ in 0..4 {
+        let mut line = String::new();
+        responses.read_line(&mut line).unwrap();
+        lines.push(line);
+    }
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    assert_eq!(lines[0], "ok\n");
+    assert!(lines[1].starts_with("error: CSV processing error"));
+    assert_eq!(lines[2], "1,,6.0000,0.0000,6.0000,false,false,0.0000\n");
+    assert_eq!(lines[3], "\n");
+}
+
+#[rstest]
+fn test_cli_watch() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use std::time::{Duration, Instant};
+
+    let dir = tempfile::tempdir().unwrap();
+    let incoming = dir.path().join("incoming");
+    std::fs::create_dir(&incoming).unwrap();
+    let output = dir.path().join("accounts.csv");
+    // Dropped before the watch starts.
+    std::fs::write(
+        incoming.join("day1.csv"),
+        "type,client,tx,amount\ndeposit,1,1,10.0\n",
+    )
+    .unwrap();
+
+    let mut watcher = Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("serve")
+        .arg("--watch")
+        .arg(&incoming)
+        .arg("--output")
+        .arg(&output)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(watcher.stderr.take().unwrap());
+    let mut banner = String::new();
+    stderr.read_line(&mut banner).unwrap();
+    assert!(banner.starts_with("Watching "));
+
+    // Written elsewhere and moved in, as a drop would.
+    let staged = dir.path().join("day2.csv");
+    std::fs::write(&staged, "type,client,tx,amount\nwithdrawal,1,2,4.0\n").unwrap();
+    std::fs::rename(&staged, incoming.join("day2.csv")).unwrap();
+
+    let archived = incoming.join("processed").join("day2.csv");
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !archived.exists() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    watcher.kill().unwrap();
+    watcher.wait().unwrap();
+
+    assert!(incoming.join("processed").join("day1.csv").exists());
+    assert!(archived.exists());
+    assert!(!incoming.join("day2.csv").exists());
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        "client,available,held,total,locked\n\
+         1,6.0000,0.0000,6.0000,false\n"
+    );
+}
+
+#[rstest]
+fn test_cli_watch_rejects_failed_file() {
+    use std::process::Stdio;
+    use std::time::{Duration, Instant};
+
+    let dir = tempfile::tempdir().unwrap();
+    let incoming = dir.path().join("incoming");
+    std::fs::create_dir(&incoming).unwrap();
+    let output = dir.path().join("accounts.csv");
+    std::fs::write(
+        incoming.join("a.csv"),
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         refill,1,2,50.0\n\
+         deposit,1,3,1.0\n",
+    )
+    .unwrap();
+    std::fs::write(
+        incoming.join("b.csv"),
+        "type,client,tx,amount\ndeposit,1,4,5.0\n",
+    )
+    .unwrap();
+
+    let mut watcher = Command::cargo_bin("payment_engine")
+        .unwrap()
+        .arg("serve")
+        .arg("--strict")
+        .arg("--watch")
+        .arg(&incoming)
+        .arg("--output")
+        .arg(&output)
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let archived = incoming.join("processed").join("b.csv");
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !archived.exists() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    watcher.kill().unwrap();
+    watcher.wait().unwrap();
+
+    // The failed file isn't applied again, and says how far it got.
+    assert!(!incoming.join("a.csv").exists());
+    assert!(incoming.join("rejected").join("a.csv").exists());
+    let progress =
+        std::fs::read_to_string(incoming.join("rejected").join("a.csv.error")).unwrap();
+    assert!(
+        progress.starts_with("stopped at record 2, the records before it were applied: "),
+        "{}",
+        progress
+    );
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        "client,available,held,total,locked\n\
+         1,15.0000,0.0000,15.0000,false\n"
+    );
 }
 
 #[rstest]
@@ -91,7 +2129,7 @@ fn test_cli_write_error() {
 
     let output = child.wait_with_output().expect("Failed to wait for process");
 
-    assert!(!output.status.success());
+    assert_eq!(output.status.code(), Some(5));
 
     let stderr = String::from_utf8_lossy(&output.stderr);
     assert!(stderr.contains("Error writing accounts:"));
@@ -0,0 +1,132 @@
+//! Throws random record sequences at the engine and checks its invariants
+//! after every record.
+
+use payment_engine::models::Currency;
+use payment_engine::{
+    ClientId, CompactTxStore, InputRecord, LockedAccountPolicy, PaymentEngine, TransactionType,
+    TxId, UnderfundedDisputeMode,
+};
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+const RECORD_TYPES: [TransactionType; 10] = [
+    TransactionType::Deposit,
+    TransactionType::Withdrawal,
+    TransactionType::Dispute,
+    TransactionType::Resolve,
+    TransactionType::Chargeback,
+    TransactionType::Transfer,
+    TransactionType::Refund,
+    TransactionType::Auth,
+    TransactionType::Capture,
+    TransactionType::Void,
+];
+
+/// Few clients and tx ids, so records keep referring to each other.
+fn record() -> impl Strategy<Value = InputRecord> {
+    (
+        prop::sample::select(RECORD_TYPES.as_slice()),
+        1..=3u16,
+        1..=30u32,
+        prop::option::weighted(0.9, -10_000i64..=1_000_000),
+        1..=3u16,
+    )
+        .prop_map(|(record_type, client, tx, amount, counterparty)| {
+            let needs_amount = !matches!(
+                record_type,
+                TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+            );
+            InputRecord {
+                record_type,
+                client_id: ClientId::from(client),
+                tx_id: TxId::from(tx),
+                amount: amount
+                    .filter(|_| needs_amount)
+                    .map(|amount| Decimal::new(amount, 4)),
+                counterparty_id: (record_type == TransactionType::Transfer)
+                    .then(|| ClientId::from(counterparty)),
+                currency: Currency::default(),
+                target_currency: None,
+                timestamp: None,
+                idempotency_key: None,
+                reference: None,
+                metadata: HashMap::new(),
+            }
+        })
+}
+
+/// The default engine and the policies that change how funds are held, on
+/// either in-memory transaction store.
+fn engine() -> impl Strategy<Value = PaymentEngine> {
+    (
+        prop::sample::select(vec![
+            UnderfundedDisputeMode::Ignore,
+            UnderfundedDisputeMode::AllowNegative,
+            UnderfundedDisputeMode::Queue,
+        ]),
+        prop::sample::select(vec![
+            LockedAccountPolicy::Accept,
+            LockedAccountPolicy::Hold,
+            LockedAccountPolicy::Reject,
+        ]),
+        1..=3u8,
+        any::<bool>(),
+    )
+        .prop_map(|(underfunded, locked, max_disputes, compact)| {
+            let engine = PaymentEngine::new()
+                .with_underfunded_dispute_mode(underfunded)
+                .with_locked_account_policy(locked)
+                .with_max_disputes(max_disputes);
+            if compact {
+                engine
+                    .with_tx_store(CompactTxStore::new())
+                    .with_counter_leg_store(CompactTxStore::new())
+            } else {
+                engine
+            }
+        })
+}
+
+proptest! {
+    #[test]
+    fn invariants_hold_after_every_record(
+        mut engine in engine(),
+        records in prop::collection::vec(record(), 1..200),
+    ) {
+        for record in records {
+            let _ = engine.process(record.clone());
+            if let Err(e) = engine.check_invariants() {
+                panic!("{} after {:?}", e, record);
+            }
+            if let Some(books) = engine.reconcile().differences().next() {
+                panic!("books don't balance after {:?}: {}", record, books);
+            }
+        }
+    }
+
+    #[test]
+    fn locked_accounts_stay_locked_and_funds_only_come_in(
+        mut engine in engine(),
+        records in prop::collection::vec(record(), 1..200),
+    ) {
+        let mut locked: HashMap<ClientId, Decimal> = HashMap::new();
+        for record in records {
+            let _ = engine.process(record.clone());
+            for account in engine.get_accounts() {
+                if let Some(&available) = locked.get(&account.client_id) {
+                    prop_assert!(account.locked, "client {} was unlocked by {:?}", account.client_id, record);
+                    prop_assert!(
+                        account.available >= available,
+                        "funds left locked client {} with {:?}",
+                        account.client_id,
+                        record
+                    );
+                }
+                if account.locked {
+                    locked.insert(account.client_id, account.available);
+                }
+            }
+        }
+    }
+}